@@ -0,0 +1,83 @@
+//! Times finding bins in an index of many short references: querying reads from the first and
+//! last references, which should take as long as each other now that seed hits find their bins by
+//! binary search rather than by walking every bin before them, and extracting a taxid's
+//! references, against scanning every bin for them.
+//!
+//! Run with `cargo bench --bench bin_lookup`.
+
+extern crate mtsv;
+extern crate rand;
+
+use mtsv::index::{BinMode, Database, Gi, MGIndex, QueryParams, QueryStats, TaxId};
+use rand::{Rng, XorShiftRng};
+use std::time::{Duration, Instant};
+
+const NUM_REFERENCES: u32 = 50_000;
+const REFERENCE_LEN: usize = 200;
+const NUM_READS: usize = 20_000;
+const READ_LEN: usize = 100;
+
+fn random_seq<R: Rng>(rng: &mut R, len: usize) -> Vec<u8> {
+    (0..len).map(|_| *rng.choose(b"ACGT").unwrap()).collect()
+}
+
+fn seconds(d: Duration) -> f64 {
+    d.as_secs() as f64 + d.subsec_nanos() as f64 / 1e9
+}
+
+fn main() {
+    let mut rng = XorShiftRng::new_unseeded();
+
+    let mut db = Database::new();
+    let mut references = Vec::new();
+    for gi in 0..NUM_REFERENCES {
+        let reference = random_seq(&mut rng, REFERENCE_LEN);
+        db.insert(TaxId(gi as u64), vec![(Gi(gi), reference.clone())]);
+        references.push(reference);
+    }
+
+    let index = MGIndex::new(db, 64, 32, BinMode::PerGi);
+    let fmindex = index.fm_index();
+    let params = QueryParams::default();
+
+    let last = references.len() - 100;
+    for &(label, from) in &[("first", 0), ("last ", last)] {
+        let reads = (0..NUM_READS)
+            .map(|_| {
+                let reference = &references[from + rng.gen_range(0, 100)];
+                let start = rng.gen_range(0, REFERENCE_LEN - READ_LEN);
+                reference[start..start + READ_LEN].to_vec()
+            })
+            .collect::<Vec<_>>();
+
+        let mut stats = QueryStats::default();
+        let mut num_hits = 0;
+        let start = Instant::now();
+        for read in &reads {
+            num_hits += index.matching_tax_ids(&fmindex, read, &params, &mut stats).len();
+        }
+        let elapsed = seconds(start.elapsed());
+        println!("reads from the {} 100 references: {:.3}s ({:.1} reads/s, {} hits)",
+                 label,
+                 elapsed,
+                 NUM_READS as f64 / elapsed,
+                 num_hits);
+    }
+
+    let taxids = (0..NUM_REFERENCES as u64).step_by(50).collect::<Vec<_>>();
+    let start = Instant::now();
+    let found = taxids.iter().map(|&t| index.get_references(t).len()).sum::<usize>();
+    println!("references of {} taxids: {:.3}s ({} found)",
+             taxids.len(),
+             seconds(start.elapsed()),
+             found);
+
+    let start = Instant::now();
+    let scanned = taxids.iter()
+        .map(|&t| index.bins().filter(|bin| bin.tax_id == TaxId(t)).count())
+        .sum::<usize>();
+    println!("scanning every bin for {} taxids: {:.3}s ({} found)",
+             taxids.len(),
+             seconds(start.elapsed()),
+             scanned);
+}
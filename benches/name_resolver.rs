@@ -0,0 +1,91 @@
+//! Times writing v2 results with many hits per read: without the accession field, with it filled
+//! in by a `NameResolver`, and looking the same names up in the index's accession map, as the
+//! writer did before, for comparison.
+//!
+//! Run with `cargo bench --bench name_resolver`.
+
+extern crate mtsv;
+extern crate rand;
+
+use mtsv::index::{BinInfo, Gi, Hit, TaxId};
+use mtsv::io::{FindingsField, FindingsWriter, NameResolver, ResultsHeader, V2_FIELDS};
+use rand::{Rng, XorShiftRng};
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const NUM_REFERENCES: u32 = 200_000;
+const NUM_READS: usize = 50_000;
+const HITS_PER_READ: usize = 50;
+
+fn seconds(d: Duration) -> f64 {
+    d.as_secs() as f64 + d.subsec_nanos() as f64 / 1e9
+}
+
+fn main() {
+    let mut rng = XorShiftRng::new_unseeded();
+
+    let bins = (1..NUM_REFERENCES + 1)
+        .map(|gi| BinInfo { gi: Gi(gi), tax_id: TaxId(gi as u64 / 4), start: 0, end: 0 })
+        .collect::<Vec<_>>();
+    // half of the references have accessions, and the rest get made-up names
+    let accessions = bins.iter()
+        .filter(|bin| bin.gi.0 % 2 == 0)
+        .map(|bin| (bin.gi, format!("NZ_CP{:06}.1", bin.gi.0)))
+        .collect::<BTreeMap<_, _>>();
+
+    let start = Instant::now();
+    let names = Arc::new(NameResolver::new(bins.iter().cloned(), &accessions));
+    println!("resolver for {} references: {:.3}s",
+             NUM_REFERENCES,
+             seconds(start.elapsed()));
+
+    let reads = (0..NUM_READS)
+        .map(|i| {
+            let hits = (0..HITS_PER_READ)
+                .map(|_| {
+                    let bin = bins[rng.gen_range(0, bins.len())];
+                    Hit {
+                        tax_id: bin.tax_id,
+                        edit: rng.gen_range(0, 5),
+                        seeds: 10,
+                        seeds_searched: 20,
+                        clipped_start: 0,
+                        clipped_end: 0,
+                        gi: bin.gi,
+                    }
+                })
+                .collect::<Vec<_>>();
+            (format!("read_{}", i), hits)
+        })
+        .collect::<Vec<_>>();
+
+    let mut with_accessions = V2_FIELDS.to_vec();
+    with_accessions.push(FindingsField::Accession);
+    for &(label, fields) in &[("without accessions", &V2_FIELDS[..]),
+                              ("with accessions   ", &with_accessions[..])] {
+        let mut writer = FindingsWriter::new(io::sink()).with_names(names.clone());
+        writer.write_header(&ResultsHeader::v2(fields)).unwrap();
+        let start = Instant::now();
+        for &(ref read_id, ref hits) in &reads {
+            writer.write_hits(read_id, hits).unwrap();
+        }
+        let elapsed = seconds(start.elapsed());
+        println!("{} {:.3}s ({:.1} reads/s)", label, elapsed, NUM_READS as f64 / elapsed);
+    }
+
+    let hits = reads.iter().flat_map(|&(_, ref hits)| hits.iter()).collect::<Vec<_>>();
+    let start = Instant::now();
+    let resolved = hits.iter().filter_map(|hit| names.name(hit.gi)).map(str::len).sum::<usize>();
+    println!("resolver lookups: {:.3}s ({} bytes of names)",
+             seconds(start.elapsed()),
+             resolved);
+    let start = Instant::now();
+    let mapped = hits.iter()
+        .map(|hit| accessions.get(&hit.gi).map_or(String::new(), |a| a.clone()).len())
+        .sum::<usize>();
+    println!("accession map lookups: {:.3}s ({} bytes of accessions)",
+             seconds(start.elapsed()),
+             mapped);
+}
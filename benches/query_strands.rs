@@ -0,0 +1,75 @@
+//! Compares read throughput when every strand's alignment profile is built up front with building
+//! it only once a candidate needs scoring, over reads of which half match nothing.
+//!
+//! Run with `cargo bench --bench query_strands`.
+
+extern crate bio;
+extern crate mtsv;
+extern crate rand;
+
+use bio::alphabets::dna::revcomp;
+use mtsv::index::{BinMode, Database, Gi, MGIndex, QueryParams, QueryStats, QueryStrand, TaxId};
+use rand::{Rng, XorShiftRng};
+use std::time::{Duration, Instant};
+
+const NUM_READS: usize = 100_000;
+const READ_LEN: usize = 100;
+
+fn random_seq<R: Rng>(rng: &mut R, len: usize) -> Vec<u8> {
+    (0..len).map(|_| *rng.choose(b"ACGT").unwrap()).collect()
+}
+
+fn seconds(d: Duration) -> f64 {
+    d.as_secs() as f64 + d.subsec_nanos() as f64 / 1e9
+}
+
+fn main() {
+    let mut rng = XorShiftRng::new_unseeded();
+
+    let mut db = Database::new();
+    let mut references = Vec::new();
+    for taxid in 0..100u32 {
+        let reference = random_seq(&mut rng, 10_000);
+        db.insert(TaxId(taxid as u64), vec![(Gi(taxid), reference.clone())]);
+        references.push(reference);
+    }
+
+    // half the reads come from the references (on either strand), half match nothing
+    let reads = (0..NUM_READS)
+        .map(|i| if i % 2 == 0 {
+            let reference = rng.choose(&references).unwrap();
+            let start = rng.gen_range(0, reference.len() - READ_LEN);
+            let read = reference[start..start + READ_LEN].to_vec();
+            if rng.gen() { revcomp(&read) } else { read }
+        } else {
+            random_seq(&mut rng, READ_LEN)
+        })
+        .collect::<Vec<_>>();
+
+    let index = MGIndex::new(db, 64, 32, BinMode::PerGi);
+    let fmindex = index.fm_index();
+    let params = QueryParams::default();
+
+    for &eager in &[true, false] {
+        let mut stats = QueryStats::default();
+        let mut num_hits = 0;
+
+        let start = Instant::now();
+        for read in &reads {
+            let rev_comp_read = revcomp(read);
+            for strand in &[QueryStrand::new(read), QueryStrand::new(&rev_comp_read)] {
+                if eager {
+                    strand.profile();
+                }
+                num_hits += index.matching_tax_ids_for(&fmindex, strand, &params, &mut stats).len();
+            }
+        }
+        let elapsed = seconds(start.elapsed());
+
+        println!("{} profiles: {:.3}s ({:.1} reads/s, {} hits)",
+                 if eager { "eager" } else { "lazy " },
+                 elapsed,
+                 NUM_READS as f64 / elapsed,
+                 num_hits);
+    }
+}
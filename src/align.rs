@@ -2,6 +2,69 @@
 
 use std::cmp::min;
 
+/// Whether two nucleotides match when aligning a read, where an N (in either sequence) never
+/// matches anything, including another N.
+pub fn nucleotides_match(a: u8, b: u8) -> bool {
+    a == b && a != b'N'
+}
+
+/// Like `nucleotides_match`, but an N in the read (the first nucleotide) matches anything. Ns
+/// in the reference still never match.
+pub fn nucleotides_match_free_n(a: u8, b: u8) -> bool {
+    a == b'N' || nucleotides_match(a, b)
+}
+
+/// The bases an IUPAC nucleotide code stands for, a bit for each of `ACGT`, or 0 for anything
+/// which isn't one. `N` stands for all four.
+pub(crate) fn iupac_bases(code: u8) -> u8 {
+    match code {
+        b'A' => 0b0001,
+        b'C' => 0b0010,
+        b'G' => 0b0100,
+        b'T' => 0b1000,
+        b'R' => 0b0101,
+        b'Y' => 0b1010,
+        b'S' => 0b0110,
+        b'W' => 0b1001,
+        b'K' => 0b1100,
+        b'M' => 0b0011,
+        b'B' => 0b1110,
+        b'D' => 0b1101,
+        b'H' => 0b1011,
+        b'V' => 0b0111,
+        b'N' => 0b1111,
+        _ => 0,
+    }
+}
+
+/// Whether a byte is one of the IUPAC codes for two or three bases, such as `R` for `A` or `G`.
+/// `N` isn't, since it's any base.
+pub fn is_ambiguity_code(code: u8) -> bool {
+    let bases = iupac_bases(code).count_ones();
+    bases == 2 || bases == 3
+}
+
+/// Like `nucleotides_match`, but IUPAC ambiguity codes match any of their bases, and each other
+/// if they share one, e.g. an `R` (`A` or `G`) matches an `A` or an `S` (`C` or `G`).
+pub fn nucleotides_match_iupac(a: u8, b: u8) -> bool {
+    a != b'N' && b != b'N' && iupac_bases(a) & iupac_bases(b) != 0
+}
+
+/// Like `nucleotides_match_iupac`, but an N in the read (the first nucleotide) matches anything.
+pub fn nucleotides_match_iupac_free_n(a: u8, b: u8) -> bool {
+    a == b'N' || nucleotides_match_iupac(a, b)
+}
+
+/// Like `nucleotides_match`, for protein residues, where an X never matches anything.
+pub fn residues_match(a: u8, b: u8) -> bool {
+    a == b && a != b'X'
+}
+
+/// Like `residues_match`, but an X in the read (the first residue) matches anything.
+pub fn residues_match_free_x(a: u8, b: u8) -> bool {
+    a == b'X' || residues_match(a, b)
+}
+
 /// An Aligner owns a buffer of data, and uses that to calculate the minimum edit distance with
 /// which one sequence can be aligned against the other.
 pub struct Aligner {
@@ -19,13 +82,95 @@ impl Aligner {
     ///
     /// Based on
     /// <https://www.cs.jhu.edu/~langmea/resources/lecture_notes/variations_on_edit_dist.pdf.>
+    pub fn min_edit_distance(&mut self, p: &[u8], t: &[u8]) -> u32 {
+        self.min_edit_distance_with(p, t, |a, b| a == b)
+    }
+
+    /// Like `min_edit_distance`, but two characters only match if `eq` returns true for them
+    /// (needle character first), e.g. `nucleotides_match`.
+    pub fn min_edit_distance_with<F>(&mut self, p: &[u8], t: &[u8], eq: F) -> u32
+        where F: Fn(u8, u8) -> bool
+    {
+        self.fill(p, t, eq);
+
+        // get the minimum value in the last row
+        let dp_size = (p.len() + 1) * (t.len() + 1);
+        let last_row = &self.buffer[(dp_size - (t.len() + 1))..dp_size];
+        last_row.iter().copied().min().unwrap()
+    }
+
+    /// Find the minimum edit distance with which a needle can be aligned to a substring of
+    /// haystack, where up to `max_clip_start` bases at the start of the needle may hang off the
+    /// start of the haystack, or up to `max_clip_end` bases at its end may hang off the end of the
+    /// haystack. Clipped bases aren't counted as edits.
+    ///
+    /// Characters match if `eq` returns true for them, as in `min_edit_distance_with`. Returns the
+    /// edit distance and the number of bases clipped from the start and end of the needle. Only
+    /// one end is ever clipped, and ties go to the smallest clip.
+    pub fn min_edit_distance_clipped<F>(&mut self,
+                                        p: &[u8],
+                                        t: &[u8],
+                                        max_clip_start: usize,
+                                        max_clip_end: usize,
+                                        eq: F)
+                                        -> (u32, usize, usize)
+        where F: Fn(u8, u8) -> bool
+    {
+        let (end_edits, end_clip) =
+            self.min_edit_distance_suffix_clipped(p, t, max_clip_end, &eq);
+        if max_clip_start == 0 {
+            return (end_edits, 0, end_clip);
+        }
+
+        // clipping the start is clipping the end of both sequences reversed
+        let p_rev = p.iter().rev().cloned().collect::<Vec<_>>();
+        let t_rev = t.iter().rev().cloned().collect::<Vec<_>>();
+        let (start_edits, start_clip) =
+            self.min_edit_distance_suffix_clipped(&p_rev, &t_rev, max_clip_start, &eq);
+
+        if (start_edits, start_clip) < (end_edits, end_clip) {
+            (start_edits, start_clip, 0)
+        } else {
+            (end_edits, 0, end_clip)
+        }
+    }
+
+    /// The minimum edit distance when up to `max_clip` bases at the end of the needle may hang off
+    /// the end of the haystack, along with the number of bases clipped.
+    fn min_edit_distance_suffix_clipped<F>(&mut self,
+                                           p: &[u8],
+                                           t: &[u8],
+                                           max_clip: usize,
+                                           eq: F)
+                                           -> (u32, usize)
+        where F: Fn(u8, u8) -> bool
+    {
+        let mut best = (self.min_edit_distance_with(p, t, eq), 0);
+
+        // a clipped alignment has to end with the last base of the haystack, which is the last
+        // column of each row
+        let row_mult = t.len() + 1;
+        for clip in 1..(min(max_clip, p.len()) + 1) {
+            let row = p.len() - clip;
+            let edits = self.buffer[row * row_mult + t.len()];
+            if edits < best.0 {
+                best = (edits, clip);
+            }
+        }
+
+        best
+    }
+
+    /// Fill the dynamic programming matrix for aligning a needle against any substring of a
+    /// haystack, where characters match if `eq` returns true for them.
     ///
     /// # Safety
     ///
     /// This method makes liberal use of `Vec::get_unchecked_mut`. All accesses are within bounds,
     /// but pay *very close* attention if modifying the indexing logic here.
-
-    pub fn min_edit_distance(&mut self, p: &[u8], t: &[u8]) -> u32 {
+    fn fill<F>(&mut self, p: &[u8], t: &[u8], eq: F)
+        where F: Fn(u8, u8) -> bool
+    {
         let dp_size = (p.len() + 1) * (t.len() + 1);
         let row_mult = t.len() + 1;
 
@@ -58,7 +203,7 @@ impl Aligner {
                     let haystack_char = *t.get_unchecked(col - 1);
 
                     // do the characters at this cell match? if not, potentially add 1 to edit dist
-                    let delta = if needle_char != haystack_char { 1 } else { 0 };
+                    let delta = if eq(needle_char, haystack_char) { 0 } else { 1 };
 
                     // determine score weights for insertion, deletion, substitution
                     let diag = ((row - 1) * row_mult) + (col - 1);
@@ -78,16 +223,18 @@ impl Aligner {
 
             }
         }
-
-        // get the minimum value in the last row
-        let last_row = &d[(dp_size - (t.len() + 1))..dp_size];
-        last_row.iter().map(|s| *s).min().unwrap()
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::Aligner;
+    use rand::{Rng, XorShiftRng};
+    use super::{is_ambiguity_code, nucleotides_match, nucleotides_match_free_n,
+                nucleotides_match_iupac, nucleotides_match_iupac_free_n, Aligner};
+
+    fn exact(a: u8, b: u8) -> bool {
+        a == b
+    }
 
     fn check_test(needle: &[u8], haystack: &[u8], expected_edits: u32) {
         let mut aligner = Aligner::new();
@@ -160,4 +307,107 @@ mod test {
 
         check_test(needle, haystack, 3);
     }
+
+    #[test]
+    fn clipped_end() {
+        let mut aligner = Aligner::new();
+        let needle = b"ACTCCANTTAGCTTTTTTTT";
+        let haystack = b"ACGACTAGTTATAAAAATTCNACTCCANTTAGC";
+
+        // the last 8 bases hang off the end of the haystack
+        assert_eq!(aligner.min_edit_distance_clipped(needle, haystack, 0, 10, exact), (0, 0, 8));
+        // only some of them can be clipped, the rest are edits
+        assert_eq!(aligner.min_edit_distance_clipped(needle, haystack, 0, 5, exact), (3, 0, 5));
+        assert_eq!(aligner.min_edit_distance_clipped(needle, haystack, 0, 0, exact),
+                   (aligner.min_edit_distance(needle, haystack), 0, 0));
+    }
+
+    #[test]
+    fn clipped_start() {
+        let mut aligner = Aligner::new();
+        let needle = b"TTTTTTACGACTAGTTATAA";
+        let haystack = b"ACGACTAGTTATAAAAATTCNACTCCANTTAGC";
+
+        assert_eq!(aligner.min_edit_distance_clipped(needle, haystack, 6, 6, exact), (0, 6, 0));
+        assert_eq!(aligner.min_edit_distance_clipped(needle, haystack, 0, 6, exact), (6, 0, 0));
+    }
+
+    #[test]
+    fn clipping_unneeded() {
+        let mut aligner = Aligner::new();
+        let needle = b"GTTATAAAAATTC";
+        let haystack = b"ACGACTAGTTATAAAAATTCNACTCCANTTAGC";
+
+        assert_eq!(aligner.min_edit_distance_clipped(needle, haystack, 5, 5, exact), (0, 0, 0));
+    }
+
+    #[test]
+    fn n_never_matches() {
+        let mut aligner = Aligner::new();
+
+        assert_eq!(aligner.min_edit_distance(b"ANNA", b"ANNA"), 0);
+        assert_eq!(aligner.min_edit_distance_with(b"ANNA", b"ANNA", nucleotides_match), 2);
+        assert_eq!(aligner.min_edit_distance_with(b"ACGT", b"ANGT", nucleotides_match), 1);
+        assert_eq!(aligner.min_edit_distance_with(b"ANGT", b"ACGT", nucleotides_match), 1);
+        assert_eq!(aligner.min_edit_distance_with(b"NNN", b"", nucleotides_match), 3);
+
+        // free Ns in the read match anything, but Ns in the reference still don't
+        assert_eq!(aligner.min_edit_distance_with(b"ANGT", b"ACGT", nucleotides_match_free_n), 0);
+        assert_eq!(aligner.min_edit_distance_with(b"ANNA", b"ANNA", nucleotides_match_free_n), 0);
+        assert_eq!(aligner.min_edit_distance_with(b"ACGT", b"ANGT", nucleotides_match_free_n), 1);
+    }
+
+    #[test]
+    fn ambiguity_codes_match_their_bases() {
+        let mut aligner = Aligner::new();
+
+        assert_eq!(aligner.min_edit_distance_with(b"ACRT", b"ACAT", nucleotides_match_iupac), 0);
+        assert_eq!(aligner.min_edit_distance_with(b"ACRT", b"ACGT", nucleotides_match_iupac), 0);
+        assert_eq!(aligner.min_edit_distance_with(b"ACRT", b"ACTT", nucleotides_match_iupac), 1);
+        assert_eq!(aligner.min_edit_distance_with(b"ACST", b"ACRT", nucleotides_match_iupac), 0);
+        assert_eq!(aligner.min_edit_distance_with(b"ACRT", b"ACYT", nucleotides_match_iupac), 1);
+        assert_eq!(aligner.min_edit_distance_with(b"ACRT", b"ACAT", nucleotides_match), 1);
+
+        // Ns still never match unless they're free
+        assert_eq!(aligner.min_edit_distance_with(b"ANRT", b"ACAT", nucleotides_match_iupac), 1);
+        assert_eq!(aligner.min_edit_distance_with(b"ACRT", b"ACNT", nucleotides_match_iupac), 1);
+        assert_eq!(aligner.min_edit_distance_with(b"ANRT",
+                                                  b"ACAT",
+                                                  nucleotides_match_iupac_free_n),
+                   0);
+
+        assert!(is_ambiguity_code(b'R') && is_ambiguity_code(b'V'));
+        assert!(!is_ambiguity_code(b'A') && !is_ambiguity_code(b'N') && !is_ambiguity_code(b'r'));
+    }
+
+    #[test]
+    fn n_predicate_matches_wildcard_copy() {
+        // the binner used to compare a copy of the read with its Ns replaced by '.', which
+        // nothing in a reference can match
+        let mut rng = XorShiftRng::new_unseeded();
+        let mut aligner = Aligner::new();
+
+        for _ in 0..200 {
+            let mut random_seq = |len| {
+                (0..len).map(|_| *rng.choose(b"ACGTN").unwrap()).collect::<Vec<u8>>()
+            };
+            let needle = random_seq(30);
+            let haystack = random_seq(45);
+            let wildcard = needle.iter()
+                .map(|&b| if b == b'N' { b'.' } else { b })
+                .collect::<Vec<u8>>();
+
+            let expected = aligner.min_edit_distance(&wildcard, &haystack);
+            assert_eq!(aligner.min_edit_distance_with(&needle, &haystack, nucleotides_match),
+                       expected);
+
+            let expected = aligner.min_edit_distance_clipped(&wildcard, &haystack, 5, 5, exact);
+            assert_eq!(aligner.min_edit_distance_clipped(&needle,
+                                                         &haystack,
+                                                         5,
+                                                         5,
+                                                         nucleotides_match),
+                       expected);
+        }
+    }
 }
@@ -7,34 +7,62 @@ extern crate mtsv;
 
 use clap::{App, Arg};
 
-use mtsv::binner;
+use mtsv::binner::{self, BinnerConfig, ReadFormat};
+use mtsv::QueryParams;
+use mtsv::index::AmbiguityPolicy;
+use mtsv::formats;
+use mtsv::io::{FindingsVersion, TaxidMap};
+use mtsv::memory;
+use mtsv::prep::DustParams;
+use mtsv::run::RunContext;
 use mtsv::util;
 
+/// Exit with a usage error, as clap does for values it can't parse itself.
+fn invalid_value(message: &str) -> ! {
+    clap::Error::with_description(message, clap::ErrorKind::InvalidValue).exit()
+}
+
 fn main() {
+    if formats::format_versions_requested() {
+        println!("{}", formats::format_versions());
+        return;
+    }
+
 
     let args = App::new("mtsv")
         .version(env!("CARGO_PKG_VERSION"))
         .author(env!("CARGO_PKG_AUTHORS"))
         .about("Metagenomics binning tool.")
         .arg(Arg::with_name("FASTA")
-            .short("fa")
+            .short("f")
             .long("fasta")
-            .help("Path to FASTA reads.")
+            .help("Path(s) to FASTA reads. Several files are read one after another (see \
+            --interleave-inputs) and their results written to one file, or to one file each \
+            with --results-dir.")
             .takes_value(true)
+            .multiple(true)
             .required_unless("FASTQ")
             .conflicts_with("FASTQ"))
         .arg(Arg::with_name("FASTQ")
-            .short("fq")
             .long("fastq")
-            .help("Path to FASTQ reads.")
+            .help("Path(s) to FASTQ reads, as for --fasta. FASTA and FASTQ reads can't be mixed \
+            in one run.")
             .takes_value(true)
+            .multiple(true)
             .required_unless("FASTA")
             .conflicts_with("FASTA"))
+        .arg(Arg::with_name("INTERLEAVE_INPUTS")
+            .long("interleave-inputs")
+            .help("With several reads files, take a read from each in turn rather than reading \
+            them one after another, so that every file's results fill in from the start of the \
+            run."))
         .arg(Arg::with_name("INDEX")
             .short("i")
             .long("index")
-            .help("Path to MG-index file.")
+            .help("Path(s) to MG-index files. Reads are binned against each index in turn and the \
+            results merged into one file.")
             .takes_value(true)
+            .multiple(true)
             .required(true))
         .arg(Arg::with_name("VERBOSE")
             .short("v")
@@ -44,18 +72,55 @@ fn main() {
             .long("results")
             .takes_value(true)
             .help("Path to write results file."))
+        .arg(Arg::with_name("RESULTS_DIR")
+            .long("results-dir")
+            .takes_value(true)
+            .value_name("DIR")
+            .conflicts_with_all(&["RESULTS_PATH", "NO_COMBINED"])
+            .help("Instead of one results file, write each reads file's results to \
+            DIR/NAME.results and its run summary to DIR/NAME.summary.json, where NAME is the reads \
+            file's name without its extension, and list them in DIR/manifest.json."))
         .arg(Arg::with_name("NUM_THREADS")
             .short("t")
             .long("threads")
             .takes_value(true)
             .help("Number of worker threads to spawn.")
             .default_value("4"))
+        .arg(Arg::with_name("MEMORY_LIMIT_GB")
+            .long("memory-limit-gb")
+            .takes_value(true)
+            .value_name("GB")
+            .help("Keep the run under GB gigabytes: refuse to start if the index files are bigger, \
+            queue fewer reads if memory is short, and stop taking reads until those already taken \
+            are written whenever resident memory passes 90% of the limit. See the README for \
+            what isn't covered."))
         .arg(Arg::with_name("EDIT_TOLERANCE")
             .short("e")
             .long("edit-rate")
             .takes_value(true)
             .help("The maximum proportion of edits allowed for alignment.")
             .default_value("0.13"))
+        .arg(Arg::with_name("FREE_N")
+            .long("free-n")
+            .help("Let Ns in a read match any reference base without counting as edits, e.g. to \
+            find reads which are exact apart from their Ns with --edit-rate 0. Seeds with Ns \
+            are still skipped (see --max-seed-ns)."))
+        .arg(Arg::with_name("MAX_FREE_N")
+            .long("max-free-n")
+            .takes_value(true)
+            .requires("FREE_N")
+            .help("With --free-n, reads with more than MAX_FREE_N Ns are aligned as usual, \
+            where every N is a mismatch. Defaults to 5."))
+        .arg(Arg::with_name("AMBIGUITY")
+            .long("ambiguity")
+            .takes_value(true)
+            .possible_values(&["n", "expand", "reject"])
+            .help("How to treat IUPAC ambiguity codes (R, Y, S, W, K, M, B, D, H, V) in reads \
+            and references: 'n' treats them as N, 'expand' aligns them as any of their bases, \
+            e.g. an R in a read matches an A or G in the reference (and an A in a read matches \
+            an R kept in an index built with mtsv-build --ambiguity expand), and 'reject' skips \
+            reads with them. Seeds are searched for with them as N either way.")
+            .default_value("n"))
         .arg(Arg::with_name("SEED_SIZE")
             .long("seed-size")
             .takes_value(true)
@@ -71,6 +136,12 @@ fn main() {
             .takes_value(true)
             .help("Set the minimum percentage of seeds required to perform an alignment.")
             .default_value("0.015"))
+        .arg(Arg::with_name("MIN_SEEDS_ABSOLUTE")
+            .long("min-seeds-absolute")
+            .takes_value(true)
+            .help("Set the minimum number of seeds required to perform an alignment, however \
+            few seeds a read has. The larger of this and --min-seed applies.")
+            .default_value("1"))
         .arg(Arg::with_name("MAX_HITS")
             .long("max-hits")
             .takes_value(true)
@@ -79,9 +150,232 @@ fn main() {
         .arg(Arg::with_name("TUNE_MAX_HITS")
             .long("tune-max-hits")
             .takes_value(true)
-            .help("Each time the number of seed hits is greater than TUNE_MAX_HITS \
-            but less than MAX_HITS, the seed interval will be doubled to reduce the number of seed hits and reduce runtime.")
-            .default_value("200"))
+            .help("Each time a seed has more than TUNE_MAX_HITS hits (but no more than \
+            MAX_HITS), the interval to the next seed is doubled for the rest of the read, so \
+            repetitive reads search fewer seeds and take less time. Must not be more than \
+            MAX_HITS. Defaults to MAX_HITS / 100 (200 with the default MAX_HITS)."))
+        .arg(Arg::with_name("MAX_HITS_PER_BIN")
+            .long("max-hits-per-bin")
+            .takes_value(true)
+            .help("Record at most MAX_HITS_PER_BIN seed hits in any one reference sequence for each \
+            read, so highly repetitive references don't crowd out the others.")
+            .default_value("1000"))
+        .arg(Arg::with_name("USE_PARTIAL_SEEDS")
+            .long("use-partial-seeds")
+            .takes_value(true)
+            .value_name("MINLEN")
+            .help("Use seeds which only partially match the index, from the start of the matching \
+            suffix, if at least MINLEN bases of the seed matched. By default partial matches are \
+            skipped."))
+        .arg(Arg::with_name("MAX_SEED_NS")
+            .long("max-seed-ns")
+            .takes_value(true)
+            .help("Skip seeds with more than MAX_SEED_NS Ns rather than searching for them. Ns \
+            never match when aligning, so seeds of them only find runs of Ns in the references.")
+            .default_value("0"))
+        .arg(Arg::with_name("N_SEEDS")
+            .long("n-seeds")
+            .help("Keep the hits of seeds which land in runs of Ns in the references, such as \
+            scaffold gaps, and align the reads against them. By default they're dropped, since \
+            Ns never match when aligning, and the number dropped is logged at debug level. Only \
+            seeds with Ns can land in the references' Ns, so this only matters with \
+            --max-seed-ns above 0."))
+        .arg(Arg::with_name("MAX_SEED_HITS_PER_READ")
+            .long("max-seed-hits-per-read")
+            .takes_value(true)
+            .help("Record at most MAX_SEED_HITS_PER_READ seed hits for each strand of a read. \
+            Once a seed's hits would go over this, it and the rest of the read's seeds are \
+            skipped, bounding memory use for reads from highly repetitive references.")
+            .default_value("1000000"))
+        .arg(Arg::with_name("FLUSH_EVERY")
+            .long("flush-every")
+            .takes_value(true)
+            .help("Flush the results file after every FLUSH_EVERY reads. By default results are \
+            only flushed when the write buffer fills and at the end of the run."))
+        .arg(Arg::with_name("SAMPLE_NAME")
+            .long("sample-name")
+            .takes_value(true)
+            .help("Name of the sample the reads came from, recorded in the results file header."))
+        .arg(Arg::with_name("FSYNC_ON_CLOSE")
+            .long("fsync-on-close")
+            .help("Sync the results file to disk before exiting."))
+        .arg(Arg::with_name("CHECKPOINT_EVERY")
+            .long("checkpoint-every")
+            .takes_value(true)
+            .value_name("N")
+            .help("Flush the results file and write a JSON checkpoint with the progress so far \
+            to RESULTS.checkpoint after every N reads."))
+        .arg(Arg::with_name("FSYNC_CHECKPOINTS")
+            .long("fsync-checkpoints")
+            .requires("CHECKPOINT_EVERY")
+            .help("Sync each checkpoint to disk before it replaces the previous one."))
+        .arg(Arg::with_name("MAX_READ_LENGTH")
+            .long("max-read-length")
+            .takes_value(true)
+            .default_value("100000")
+            .help("Skip reads longer than this, warning with their read ID. Reads over 1,000,000 \
+            bases are always skipped."))
+        .arg(Arg::with_name("MAX_N_FRACTION")
+            .long("max-n-fraction")
+            .takes_value(true)
+            .value_name("F")
+            .help("Skip reads where more than this proportion of bases are N (including any \
+            non-ACGT bases). By default no reads are skipped."))
+        .arg(Arg::with_name("FILTER_LOW_COMPLEXITY")
+            .long("filter-low-complexity")
+            .help("Skip reads with low-complexity regions (e.g. poly-A tails or simple repeats), \
+            as scored by DUST."))
+        .arg(Arg::with_name("MASK_LOW_COMPLEXITY_READS")
+            .long("mask-low-complexity-reads")
+            .conflicts_with("FILTER_LOW_COMPLEXITY")
+            .help("Replace the low-complexity regions of reads with N instead of skipping them. \
+            Masked bases count towards --max-n-fraction."))
+        .arg(Arg::with_name("ALLOW_OVERHANG")
+            .long("allow-overhang")
+            .takes_value(true)
+            .value_name("FRAC")
+            .help("Allow up to this proportion of a read (between 0 and 1) to hang off either end \
+            of a reference sequence. Overhanging bases are clipped instead of counted as edits."))
+        .arg(Arg::with_name("MIN_CONFIDENCE")
+            .long("min-confidence")
+            .takes_value(true)
+            .help("Drop hits with a confidence score (between 0 and 1, combining the edit \
+            distance, seed support and the number of taxids the read hit) below this."))
+        .arg(Arg::with_name("NO_PRESCREEN")
+            .long("no-prescreen")
+            .help("Don't use the index's prescreen (from mtsv-build --build-prescreen) to skip \
+            reads with no seeds in the index. The results are the same either way."))
+        .arg(Arg::with_name("NO_VERIFY")
+            .long("no-verify")
+            .help("Don't check the indices against the checksums they were written with as \
+            they're loaded. This saves a little time, but a corrupt index may then give wrong \
+            results rather than an error."))
+        .arg(Arg::with_name("SKIP_REVERSE_IF_SATURATED")
+            .long("skip-reverse-if-saturated")
+            .takes_value(true)
+            .value_name("HITS")
+            .help("Don't query the reverse strand of reads with at least HITS hits on the forward \
+            strand. This saves time on reads which match many taxids, but CHANGES THE RESULTS: \
+            taxids such reads only match on the reverse strand are missed. Off by default."))
+        .arg(Arg::with_name("REQUIRE_PRESCREEN")
+            .long("require-prescreen")
+            .conflicts_with_all(&["NO_PRESCREEN", "USE_PARTIAL_SEEDS"])
+            .help("Fail before binning if an index was built without --build-prescreen, or its \
+            prescreen can't be used, rather than querying every read."))
+        .arg(Arg::with_name("ESCAPE_IDS")
+            .long("escape-ids")
+            .help("Percent-encode read IDs in the results (e.g. ':' as %3A), so that read IDs \
+            starting with '#' or whitespace, or containing line breaks, can be written. \
+            Without this, such reads stop the run with an error."))
+        .arg(Arg::with_name("CONTROL_CHARS")
+            .long("read-id-control-chars")
+            .takes_value(true)
+            .possible_values(&["replace", "reject"])
+            .default_value("replace")
+            .help("What to do with control characters, such as line breaks, in read IDs: \
+            replace each with '_' (with a warning, and a count in the run summary), or stop \
+            with an error."))
+        .arg(Arg::with_name("DUPLICATE_IDS")
+            .long("duplicate-ids")
+            .takes_value(true)
+            .possible_values(&["suffix", "merge", "error"])
+            .default_value("suffix")
+            .help("What to do with a read whose ID was already used by an earlier read: write \
+            it as READ_ID#2 (then #3, ...), merge the hits of all the reads with the ID into \
+            one line at the end of the results, or stop with an error. The run summary counts \
+            the duplicates. merge can't be used with --no-combined."))
+        .arg(Arg::with_name("STRICT_READS")
+            .long("strict-reads")
+            .help("Stop with an error at FASTQ records whose quality line is a different length \
+            to their sequence. Without this, such records are skipped with a warning."))
+        .arg(Arg::with_name("FINDINGS_FORMAT")
+            .long("findings-format")
+            .alias("output-format")
+            .takes_value(true)
+            .possible_values(&["legacy", "v1", "v2", "binary"])
+            .default_value("v1")
+            .help("Format of the results file. v2 starts with a schema line and records each \
+            hit's seed counts and clipping as well as its edit distance, with escaped read IDs. \
+            legacy writes only the taxids of each read's hits, without any header lines, for \
+            tools which predate edit distances (see mtsv-convert). binary records the same as \
+            v2 in a much smaller file, which mtsv's tools read transparently."))
+        .arg(Arg::with_name("EMIT_ACCESSIONS")
+            .long("emit-accessions")
+            .help("Add an accession field to each hit in v2 results, with the accession of the \
+            reference the read aligned to. The index must have been built from accession \
+            headers (mtsv-build --header-format accession)."))
+        .arg(Arg::with_name("MAX_LINE_TAXIDS")
+            .long("max-line-taxids")
+            .takes_value(true)
+            .value_name("N")
+            .help("Write at most N taxids on a read's line, keeping those with the fewest edits, \
+            and end the line with a ...+COUNT marker for the rest. Only for v1 and v2 results."))
+        .arg(Arg::with_name("SLOW_READ_LOG")
+            .long("slow-read-log")
+            .takes_value(true)
+            .value_name("PATH")
+            .help("Write a TSV row to PATH for each read whose query takes at least \
+            --slow-read-threshold-ms, with its length, time, seeds searched, candidates aligned, \
+            hits and whether its reverse strand was skipped (see --skip-reverse-if-saturated)."))
+        .arg(Arg::with_name("SLOW_READ_THRESHOLD_MS")
+            .long("slow-read-threshold-ms")
+            .takes_value(true)
+            .value_name("T")
+            .default_value("1000")
+            .help("Queries taking at least this many milliseconds are written to the slow read \
+            log."))
+        .arg(Arg::with_name("EDIT_HISTOGRAM")
+            .long("edit-histogram")
+            .takes_value(true)
+            .value_name("PATH")
+            .help("Write a TSV histogram to PATH at the end of the run, with the number of reads \
+            whose best hit had each edit distance and the number of hits with each edit \
+            distance, for choosing --edit-rate."))
+        .arg(Arg::with_name("SEED")
+            .long("seed")
+            .takes_value(true)
+            .help("Seed for all randomness in the run. A random seed is chosen and logged if \
+            this isn't given, so that any run can be reproduced."))
+        .arg(Arg::with_name("TAXID_MAP")
+            .long("taxid-map")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("Relabel the taxids in the results using FILE, with an old and a new taxid on \
+            each line (optionally gzipped). Taxids which aren't in FILE are left as they are."))
+        .arg(Arg::with_name("SHARD_OUTPUT_BY_TAXID")
+            .long("shard-output-by-taxid")
+            .takes_value(true)
+            .value_name("DIR")
+            .help("Also write the hits for each taxid to DIR/taxid_<id>.txt, one READ_ID=EDIT \
+            line per read."))
+        .arg(Arg::with_name("NO_COMBINED")
+            .long("no-combined")
+            .requires("SHARD_OUTPUT_BY_TAXID")
+            .help("Only write the per-taxid shards, not the combined results file."))
+        .arg(Arg::with_name("EXCLUSION_INDEX")
+            .long("exclusion-index")
+            .takes_value(true)
+            .value_name("PATH")
+            .help("Path to an MG-index of sequences to deplete, such as the host genome. Reads \
+            with a hit in it (within --exclusion-edit-rate) are skipped rather than binned. It's \
+            held in memory alongside the main index."))
+        .arg(Arg::with_name("EXCLUSION_EDIT_RATE")
+            .long("exclusion-edit-rate")
+            .takes_value(true)
+            .default_value("0.05")
+            .help("The maximum proportion of edits allowed for a hit in the exclusion index."))
+        .arg(Arg::with_name("EXCLUDED_READS")
+            .long("excluded-reads")
+            .takes_value(true)
+            .value_name("PATH")
+            .requires("EXCLUSION_INDEX")
+            .help("Write the reads skipped for matching the exclusion index to PATH as FASTA."))
+        .arg(Arg::with_name("REJECTS")
+            .long("rejects")
+            .takes_value(true)
+            .value_name("PATH")
+            .help("Write skipped reads to PATH as FASTA, with the reason they were skipped."))
+        .arg(formats::format_versions_arg())
         .get_matches();
 
 
@@ -99,32 +393,34 @@ fn main() {
 
     let exit_code = {
         let results_path = args.value_of("RESULTS_PATH");
-        let fastq_path = args.value_of("FASTQ");
-        let fasta_path = args.value_of("FASTA");
-        let index_path = args.value_of("INDEX").unwrap();
+        let fastq_paths = args.values_of("FASTQ");
+        let fasta_paths = args.values_of("FASTA");
+        let index_paths = args.values_of("INDEX").unwrap().collect::<Vec<_>>();
 
-        let input_path;
-        let input_type;
+        let input_paths;
+        let input_format;
 
-        if !fasta_path.is_none() {
-            input_path = fasta_path.unwrap();
-            input_type = "FASTA";
+        if !fasta_paths.is_none() {
+            input_paths = fasta_paths.unwrap().collect::<Vec<_>>();
+            input_format = ReadFormat::Fasta;
         } else {
-            input_path = fastq_path.unwrap();
-            input_type = "FASTQ";
+            input_paths = fastq_paths.unwrap().collect::<Vec<_>>();
+            input_format = ReadFormat::Fastq;
         }
 
         let num_threads = match args.value_of("NUM_THREADS") {
-            Some(s) => s.parse::<usize>().expect("Invalid number entered for number of threads!"),
+            Some(s) => s.parse::<usize>()
+                .unwrap_or_else(|_| invalid_value("Invalid number entered for number of threads!")),
             None => unreachable!(),
         };
 
         let edit_tolerance = match args.value_of("EDIT_TOLERANCE") {
             Some(s) => {
-                let edit = s.parse::<f64>().expect("Invalid edit proportion entered!");
+                let edit = s.parse::<f64>()
+                    .unwrap_or_else(|_| invalid_value("Invalid edit proportion entered!"));
                 info!("Max Edit Tolerance Proportion: {}", edit);
                 if edit < 0.0 || edit > 1.0 {
-                    panic!("Edit tolerance proportion must be between 0 and 1, inclusive");
+                    invalid_value("Edit tolerance proportion must be between 0 and 1, inclusive");
                 }
                 edit
             }
@@ -133,7 +429,8 @@ fn main() {
 
         let seed_size = match args.value_of("SEED_SIZE") {
             Some(s) => {
-                let seed_size = s.parse::<usize>().expect("Invalid seed size entered!");
+                let seed_size = s.parse::<usize>()
+                    .unwrap_or_else(|_| invalid_value("Invalid seed size entered!"));
                 info!("Seed size: {}", seed_size);
                 if seed_size < 16 {
                     warn!("Seed size may be small enough that it causes performance issues.");
@@ -148,7 +445,8 @@ fn main() {
 
         let seed_gap = match args.value_of("SEED_INTERVAL") {
             Some(s) => {
-                let seed_gap = s.parse::<usize>().expect("Invalid seed interval entered!");
+                let seed_gap = s.parse::<usize>()
+                    .unwrap_or_else(|_| invalid_value("Invalid seed interval entered!"));
                 info!("Seed Interval: {}", seed_gap);
                 if seed_gap < 2 {
                     warn!("Seed interval may be small enough that it causes performance issues.");
@@ -163,19 +461,34 @@ fn main() {
 
         let min_seeds = match args.value_of("MIN_SEED") {
             Some(s) => {
-                let min_seeds = s.parse::<f64>().expect("Invalid min seeds entered!");
+                let min_seeds = s.parse::<f64>()
+                    .unwrap_or_else(|_| invalid_value("Invalid min seeds entered!"));
                 info!("Min Seeds: {}", min_seeds);
                 if min_seeds <= 0.0 || min_seeds > 1.0 {
-                    panic!("Min seed percent must be between 0 and 1");
+                    invalid_value("Min seed percent must be between 0 and 1");
                 }
                 min_seeds
             },
             None => panic!("Missing parameter: min-seeds"),
         };
 
+        let min_seeds_absolute = match args.value_of("MIN_SEEDS_ABSOLUTE") {
+            Some(s) => {
+                let min_seeds_absolute = s.parse::<usize>()
+                    .unwrap_or_else(|_| invalid_value("Invalid absolute min seeds entered!"));
+                info!("Min Seeds (absolute): {}", min_seeds_absolute);
+                if min_seeds_absolute == 0 {
+                    invalid_value("Absolute min seeds must be at least 1");
+                }
+                min_seeds_absolute
+            },
+            None => panic!("Missing parameter: min-seeds-absolute"),
+        };
+
         let max_hits = match args.value_of("MAX_HITS") {
             Some(s) => {
-                let max_hits = s.parse::<usize>().expect("Invalid cutoff for max hits!");
+                let max_hits = s.parse::<usize>()
+                    .unwrap_or_else(|_| invalid_value("Invalid cutoff for max hits!"));
                 info!("Max Hits: {}", max_hits);
                 if max_hits > 100000 {
                     warn!("Max hits may be large enough to cause performance issues.");
@@ -188,60 +501,337 @@ fn main() {
             None => panic!("Missing parameter: max-hits"),
         };
         let tune_max_hits = match args.value_of("TUNE_MAX_HITS") {
+            Some(s) => s.parse::<usize>()
+                .unwrap_or_else(|_| invalid_value("Invalid cutoff for tune max hits!")),
+            None => QueryParams::default_tune_max_hits(max_hits),
+        };
+        info!("Tune Max Hits: {}", tune_max_hits);
+        let max_hits_per_bin = match args.value_of("MAX_HITS_PER_BIN") {
             Some(s) => {
-                let tune_max_hits = s.parse::<usize>().expect("Invalid cutoff for max hits!");
-                info!("Tune Max Hits: {}", tune_max_hits);
-                tune_max_hits
+                let max_hits_per_bin = s.parse::<usize>()
+                    .unwrap_or_else(|_| invalid_value("Invalid cutoff for max hits per bin!"));
+                info!("Max Hits Per Bin: {}", max_hits_per_bin);
+                if max_hits_per_bin == 0 {
+                    invalid_value("Max hits per bin must be at least 1");
+                }
+                max_hits_per_bin
             },
-            None => panic!("Missing parameter: tune-max-hits"),
+            None => panic!("Missing parameter: max-hits-per-bin"),
         };
-        
 
-        if results_path.is_none() {
-            error!("No results path provided!");
-            3
+        let min_partial_seed = args.value_of("USE_PARTIAL_SEEDS").map(|s| {
+            let min_len = s.parse::<usize>()
+                .unwrap_or_else(|_| invalid_value("Invalid minimum partial seed length!"));
+            info!("Min Partial Seed Length: {}", min_len);
+            if min_len == 0 || min_len >= seed_size {
+                invalid_value("Minimum partial seed length must be between 1 and the seed size");
+            }
+            min_len
+        });
+
+        let max_seed_ns = args.value_of("MAX_SEED_NS")
+            .unwrap()
+            .parse::<usize>()
+            .unwrap_or_else(|_| invalid_value("Invalid maximum number of Ns in a seed!"));
+        info!("Max Seed Ns: {}", max_seed_ns);
+
+        let skip_n_seed_hits = !args.is_present("N_SEEDS");
+        info!("Skip Seed Hits In Ns: {}", skip_n_seed_hits);
+
+        let max_seed_hits_per_read = args.value_of("MAX_SEED_HITS_PER_READ")
+            .unwrap()
+            .parse::<usize>()
+            .unwrap_or_else(|_| invalid_value("Invalid cutoff for max seed hits per read!"));
+        info!("Max Seed Hits Per Read: {}", max_seed_hits_per_read);
+        if max_seed_hits_per_read == 0 {
+            invalid_value("Max seed hits per read must be at least 1");
+        }
+
+        let memory_limit = args.value_of("MEMORY_LIMIT_GB").map(|s| {
+            let gb = s.parse::<f64>()
+                .unwrap_or_else(|_| invalid_value("Invalid memory limit entered!"));
+            info!("Memory Limit: {} GB", gb);
+            if !(gb > 0.0) {
+                invalid_value("Memory limit must be greater than 0");
+            }
+            memory::gb_to_bytes(gb)
+        });
+
+        let skip_reverse_after = args.value_of("SKIP_REVERSE_IF_SATURATED").map(|s| {
+            let min_hits = s.parse::<usize>()
+                .unwrap_or_else(|_| invalid_value("Invalid number of forward hits!"));
+            info!("Skip Reverse Strand After: {} hits", min_hits);
+            if min_hits == 0 {
+                invalid_value("Forward hits to skip the reverse strand must be at least 1");
+            }
+            min_hits
+        });
+
+        let max_free_ns = if args.is_present("FREE_N") {
+            let max_free_ns = args.value_of("MAX_FREE_N")
+                .map_or(Ok(5), |s| s.parse::<usize>())
+                .unwrap_or_else(|_| invalid_value("Invalid maximum number of free Ns!"));
+            info!("Max Free Ns: {}", max_free_ns);
+            Some(max_free_ns)
         } else {
-            let results_path = results_path.unwrap();
-            if input_type == "FASTA" {
-                match binner::get_fasta_and_write_matching_bin_ids(
-                                                         input_path,
-                                                         index_path,
-                                                         results_path,
-                                                         num_threads,
-                                                         edit_tolerance,
-                                                         seed_size,
-                                                         seed_gap,
-                                                         min_seeds,
-                                                         max_hits,
-                                                         tune_max_hits) {
-                    Ok(_) => 0,
-                    Err(why) => {
-                        error!("Error running query: {}", why);
-                        2
-                        
-                    },
+            None
+        };
+
+        let max_overhang = args.value_of("ALLOW_OVERHANG").map(|s| {
+            let max_overhang = s.parse::<f64>()
+                .unwrap_or_else(|_| invalid_value("Invalid overhang proportion entered!"));
+            info!("Allow Overhang: {}", max_overhang);
+            if max_overhang <= 0.0 || max_overhang >= 1.0 {
+                invalid_value("Overhang proportion must be between 0 and 1, exclusive");
+            }
+            max_overhang
+        });
+
+        let ambiguity = args.value_of("AMBIGUITY")
+            .unwrap()
+            .parse::<AmbiguityPolicy>()
+            .unwrap_or_else(|_| invalid_value("Invalid ambiguity policy entered!"));
+        info!("Ambiguity Codes: {}", ambiguity.name());
+
+        let params = QueryParams {
+            edit_freq: edit_tolerance,
+            seed_length: seed_size,
+            seed_gap,
+            min_seeds_percent: min_seeds,
+            min_seeds_absolute,
+            max_hits,
+            tune_max_hits,
+            max_hits_per_bin,
+            min_partial_seed,
+            max_overhang,
+            max_seed_ns,
+            max_seed_hits_per_read,
+            max_free_ns,
+            ambiguity,
+            skip_n_seed_hits,
+            ..QueryParams::default()
+        };
+        if let Err(why) = params.validate() {
+            invalid_value(&why.to_string());
+        }
+
+        let flush_every = args.value_of("FLUSH_EVERY").map(|s| {
+            let flush_every = s.parse::<usize>()
+                .unwrap_or_else(|_| invalid_value("Invalid number of reads between flushes!"));
+            info!("Flush Every: {} reads", flush_every);
+            if flush_every == 0 {
+                invalid_value("Flush interval must be at least 1 read");
+            }
+            flush_every
+        });
+
+        let sample_name = args.value_of("SAMPLE_NAME").map(|s| {
+            info!("Sample Name: {}", s);
+            if s.is_empty() || s.contains(|c: char| c.is_whitespace() || c == ':') {
+                invalid_value("Sample name must be non-empty without whitespace or ':'");
+            }
+            s.to_string()
+        });
+
+        let findings_version = args.value_of("FINDINGS_FORMAT")
+            .unwrap()
+            .parse::<FindingsVersion>()
+            .unwrap_or_else(|_| invalid_value("Invalid findings format!"));
+        if findings_version == FindingsVersion::Binary && args.is_present("ESCAPE_IDS") {
+            invalid_value("Binary results store read IDs as they are, so they can't be escaped");
+        }
+        if findings_version == FindingsVersion::Legacy {
+            if args.is_present("ESCAPE_IDS") {
+                invalid_value("Legacy results can't have escaped read IDs");
+            }
+            if sample_name.is_some() {
+                warn!("Legacy results have no header, so the sample name won't be recorded.");
+            }
+        }
+
+        let max_line_taxids = args.value_of("MAX_LINE_TAXIDS").map(|s| {
+            let max_line_taxids = s.parse::<usize>()
+                .unwrap_or_else(|_| invalid_value("Invalid max line taxids entered!"));
+            info!("Max Line Taxids: {}", max_line_taxids);
+            if max_line_taxids == 0 {
+                invalid_value("Max line taxids must be at least 1");
+            }
+            if findings_version == FindingsVersion::Legacy ||
+               findings_version == FindingsVersion::Binary {
+                invalid_value("Only v1 and v2 results can have capped lines");
+            }
+            max_line_taxids
+        });
+
+        let checkpoint_every = args.value_of("CHECKPOINT_EVERY").map(|s| {
+            let checkpoint_every = s.parse::<usize>()
+                .unwrap_or_else(|_| invalid_value("Invalid number of reads between checkpoints!"));
+            info!("Checkpoint Every: {} reads", checkpoint_every);
+            if checkpoint_every == 0 {
+                invalid_value("Checkpoint interval must be at least 1 read");
+            }
+            checkpoint_every
+        });
+
+        let max_read_length = match args.value_of("MAX_READ_LENGTH") {
+            Some(s) => {
+                let max_read_length = s.parse::<usize>()
+                    .unwrap_or_else(|_| invalid_value("Invalid max read length entered!"));
+                info!("Max Read Length: {}", max_read_length);
+                if max_read_length == 0 {
+                    invalid_value("Max read length must be at least 1");
+                } else if max_read_length > binner::READ_LENGTH_LIMIT {
+                    warn!("Max read length is above the limit of {}, which will be used instead.",
+                          binner::READ_LENGTH_LIMIT);
                 }
-            } else {
+                max_read_length
+            },
+            None => unreachable!(),
+        };
 
-                match binner::get_fastq_and_write_matching_bin_ids(
-                                                        input_path,
-                                                        index_path,
-                                                        results_path,
-                                                        num_threads,
-                                                        edit_tolerance,
-                                                        seed_size,
-                                                        seed_gap,
-                                                        min_seeds,
-                                                        max_hits,
-                                                        tune_max_hits) {
-                    Ok(_) => 0,
-                    Err(why) => {
-                    error!("Error running query: {}", why);
-                    2
-
-                    },
+        let max_n_fraction = args.value_of("MAX_N_FRACTION").map(|s| {
+            let max_n_fraction = s.parse::<f64>()
+                .unwrap_or_else(|_| invalid_value("Invalid max N fraction entered!"));
+            info!("Max N Fraction: {}", max_n_fraction);
+            if max_n_fraction < 0.0 || max_n_fraction > 1.0 {
+                invalid_value("Max N fraction must be between 0 and 1, inclusive");
+            }
+            max_n_fraction
+        });
+
+        let min_confidence = args.value_of("MIN_CONFIDENCE").map(|s| {
+            let min_confidence = s.parse::<f32>()
+                .unwrap_or_else(|_| invalid_value("Invalid min confidence entered!"));
+            info!("Min Confidence: {}", min_confidence);
+            if min_confidence < 0.0 || min_confidence > 1.0 {
+                invalid_value("Min confidence must be between 0 and 1, inclusive");
+            }
+            min_confidence
+        });
+
+        let slow_read_log = args.value_of("SLOW_READ_LOG").map(|s| {
+            info!("Slow Read Log: {}", s);
+            s.to_string()
+        });
+
+        let slow_read_threshold_ms = match args.value_of("SLOW_READ_THRESHOLD_MS") {
+            Some(s) => s.parse::<u64>()
+                .unwrap_or_else(|_| invalid_value("Invalid slow read threshold entered!")),
+            None => unreachable!(),
+        };
+
+        let exclusion_edit_rate = match args.value_of("EXCLUSION_EDIT_RATE") {
+            Some(s) => {
+                let rate = s.parse::<f64>()
+                    .unwrap_or_else(|_| invalid_value("Invalid exclusion edit rate entered!"));
+                if rate < 0.0 || rate > 1.0 {
+                    invalid_value("Exclusion edit rate must be between 0 and 1, inclusive");
                 }
+                rate
+            },
+            None => unreachable!(),
+        };
+        if let Some(path) = args.value_of("EXCLUSION_INDEX") {
+            info!("Exclusion Index: {} (edit rate {})", path, exclusion_edit_rate);
+        }
+
+        let seed = args.value_of("SEED")
+            .map(|s| s.parse::<u64>().unwrap_or_else(|_| invalid_value("Invalid seed entered!")));
+
+        let taxid_map = args.value_of("TAXID_MAP").map(|path| {
+            match TaxidMap::from_file(path) {
+                Ok(map) => {
+                    info!("Taxid Map: {} taxids from {}", map.len(), path);
+                    map
+                },
+                Err(why) => {
+                    error!("Unable to read taxid map: {}", why);
+                    std::process::exit(2);
+                },
             }
+        });
+
+        let config = BinnerConfig {
+            flush_every,
+            fsync_on_close: args.is_present("FSYNC_ON_CLOSE"),
+            sample_name,
+            max_read_length: Some(max_read_length),
+            max_n_fraction,
+            low_complexity: if args.is_present("FILTER_LOW_COMPLEXITY") ||
+                               args.is_present("MASK_LOW_COMPLEXITY_READS") {
+                Some(DustParams::default())
+            } else {
+                None
+            },
+            mask_low_complexity: args.is_present("MASK_LOW_COMPLEXITY_READS"),
+            rejects_path: args.value_of("REJECTS").map(|s| s.to_string()),
+            exclusion_index: args.value_of("EXCLUSION_INDEX").map(|s| s.to_string()),
+            exclusion_edit_rate,
+            excluded_reads_path: args.value_of("EXCLUDED_READS").map(|s| s.to_string()),
+            min_confidence,
+            checkpoint_every,
+            fsync_checkpoints: args.is_present("FSYNC_CHECKPOINTS"),
+            taxid_map,
+            shard_dir: args.value_of("SHARD_OUTPUT_BY_TAXID").map(|s| s.to_string()),
+            no_combined: args.is_present("NO_COMBINED"),
+            control_chars: args.value_of("CONTROL_CHARS")
+                .unwrap()
+                .parse()
+                .unwrap_or_else(|_| invalid_value("Invalid control character policy!")),
+            escape_ids: args.is_present("ESCAPE_IDS"),
+            duplicate_ids: args.value_of("DUPLICATE_IDS")
+                .unwrap()
+                .parse()
+                .unwrap_or_else(|_| invalid_value("Invalid duplicate read ID policy!")),
+            strict_reads: args.is_present("STRICT_READS"),
+            findings_version,
+            max_line_taxids,
+            run: RunContext::new(seed),
+            no_prescreen: args.is_present("NO_PRESCREEN"),
+            skip_reverse_after,
+            require_prescreen: args.is_present("REQUIRE_PRESCREEN"),
+            slow_read_log,
+            slow_read_threshold_ms,
+            interleave_inputs: args.is_present("INTERLEAVE_INPUTS"),
+            edit_histogram: args.value_of("EDIT_HISTOGRAM").map(|s| s.to_string()),
+            memory_limit,
+            emit_accessions: args.is_present("EMIT_ACCESSIONS"),
+            no_verify: args.is_present("NO_VERIFY"),
+        };
+        
+
+        let result = match (results_path, args.value_of("RESULTS_DIR")) {
+            (Some(results_path), _) => {
+                Some(binner::bin_inputs(&input_paths,
+                                        input_format,
+                                        &index_paths,
+                                        results_path,
+                                        num_threads,
+                                        &params,
+                                        &config))
+            },
+            (None, Some(results_dir)) => {
+                Some(binner::bin_inputs_to_dir(&input_paths,
+                                               input_format,
+                                               &index_paths,
+                                               results_dir,
+                                               num_threads,
+                                               &params,
+                                               &config))
+            },
+            (None, None) => None,
+        };
+
+        match result {
+            None => {
+                error!("No results path provided!");
+                3
+            },
+            Some(Ok(_)) => 0,
+            Some(Err(why)) => {
+                error!("Error running query: {}", why);
+                2
+            },
         }
 
     };
@@ -1,18 +1,31 @@
 #[macro_use]
 extern crate log;
 
-extern crate bio;
 extern crate clap;
 extern crate mtsv;
 
 
-use bio::io::fasta;
 use clap::{App, Arg};
-use std::path::Path;
 use mtsv::builder;
+use mtsv::builder::BuildOptions;
+use mtsv::index;
+use mtsv::index::{Alphabet, AmbiguityPolicy, BinMode};
+use mtsv::MtsvResult;
+use mtsv::MGIndex;
+use mtsv::TaxId;
+use mtsv::formats;
+use mtsv::io::{COMPRESSION_LEVELS, DEFAULT_COMPRESSION_LEVEL};
+use mtsv::prescreen::PrescreenParams;
+use mtsv::run::RunContext;
 use mtsv::util;
+use mtsv::util::HeaderFormat;
 
 fn main() {
+    if formats::format_versions_requested() {
+        println!("{}", formats::format_versions());
+        return;
+    }
+
 
     let args = App::new("mtsv-build")
         .version(env!("CARGO_PKG_VERSION"))
@@ -21,28 +34,276 @@ fn main() {
         .arg(Arg::with_name("FASTA")
             .short("f")
             .long("fasta")
-            .help("Path to FASTA database file.")
+            .help("Path(s) to FASTA database files, which may be gzipped, or - to read standard \
+                   input. Several files are read one after another, giving the same index as \
+                   their concatenation.")
+            .takes_value(true)
+            .multiple(true)
+            .required_unless_one(&["CONVERT", "REMOVE_TAXIDS", "MANIFEST"]))
+        .arg(Arg::with_name("MANIFEST")
+            .long("manifest")
             .takes_value(true)
-            .required(true))
+            .value_name("FILE")
+            .conflicts_with_all(&["FASTA", "CONVERT", "REMOVE_TAXIDS", "ACCESSION_MAP",
+                                  "ACC2TAXID"])
+            .help("Build from the FASTA files listed in FILE instead of --fasta, one per line \
+                   with its taxid and optionally the GI to number its references from, \
+                   separated by tabs: path<TAB>taxid[<TAB>starting_gi]. Every reference in a \
+                   file gets its taxid, and GIs are numbered on from the previous file's when \
+                   there's no starting GI. Relative paths are relative to FILE's directory. The \
+                   headers aren't parsed, so --header-format doesn't apply, and \
+                   --keep-descriptions keeps them whole."))
         .arg(Arg::with_name("INDEX")
             .short("i")
             .long("index")
             .help("Absolute path to mtsv index file.")
             .takes_value(true)
-            .required(true))
+            .required_unless("DRY_RUN"))
+        .arg(Arg::with_name("DRY_RUN")
+            .long("dry-run")
+            .conflicts_with_all(&["MANIFEST", "CONVERT", "REMOVE_TAXIDS", "RESUME", "VALIDATE"])
+            .help("Instead of building the index, read the references as the build would, and \
+                   print the number of taxa, references and bases it would have, the references \
+                   and bases of each taxid, and every reference which would fail the build, \
+                   with why. Only the references' lengths are kept, so this takes little memory. \
+                   Exits with status 1 if any reference would fail the build."))
         .arg(Arg::with_name("VERBOSE")
             .short("v")
             .help("Include this flag to trigger debug-level logging."))
+        .arg(Arg::with_name("PROGRESS_INTERVAL")
+            .long("progress-interval")
+            .takes_value(true)
+            .value_name("SECONDS")
+            .help("Log how far the build has got every SECONDS seconds while reading the \
+                   references and building the index, with the time taken so far and about how \
+                   long each step has left. 0 turns progress off.")
+            .default_value("60"))
         .arg(Arg::with_name("SA_SAMPLE_RATE")
             .long("sa-sample")
             .takes_value(true)
-            .help("Suffix array sampling rate. If sampling rate is k, every k-th entry will be kept.")
+            .help("Suffix array sampling rate. If sampling rate is k, every k-th entry \
+            will be kept. Must be at least 1, values above 1024 make queries very slow.")
             .default_value("32"))
         .arg(Arg::with_name("FM_SAMPLE_INTERVAL")
             .long("sample-interval")
             .takes_value(true)
-            .help("BWT occurance sampling rate. If sample interval is k, every k-th entry will be kept.")
+            .help("BWT occurance sampling rate. If sample interval is k, every k-th entry \
+            will be kept. Must be at least 1, values above 1024 make queries very slow.")
             .default_value("64"))
+        .arg(Arg::with_name("HEADER_FORMAT")
+            .long("header-format")
+            .takes_value(true)
+            .possible_values(&["gi-taxid", "taxid", "accession"])
+            .help("Format of the FASTA headers. With \"taxid\" each header is just the taxid, and \
+                   sequential GIs are assigned to the records. With \"accession\" each header \
+                   starts with an accession such as NZ_CP012345.1, whose taxid is looked up in \
+                   --accession-map or --acc2taxid, and the accessions are kept in the index for \
+                   mtsv-binner --emit-accessions.")
+            .default_value("gi-taxid"))
+        .arg(Arg::with_name("ALPHABET")
+            .long("alphabet")
+            .takes_value(true)
+            .possible_values(&["dna", "dna4", "protein"])
+            .help("Alphabet of the references. Protein references are indexed over the 20 amino \
+                   acids and X, which anything else becomes, and mtsv-binner queries protein \
+                   reads against them on the forward strand only. Protein indices can't be \
+                   written with --mmap-format or built with --external-sa. DNA4 indices are \
+                   over ACGT alone, for a smaller occurrence table, so the build fails if any \
+                   reference has an N once it's converted, and reads' seeds with Ns are never \
+                   found. DNA4 indices can't be written with --mmap-format, or have taxa's \
+                   references spaced with Ns by --spacer-length.")
+            .default_value("dna"))
+        .arg(Arg::with_name("RESPECT_SOFTMASK")
+            .long("respect-softmask")
+            .conflicts_with_all(&["CONVERT", "REMOVE_TAXIDS"])
+            .help("Don't seed in the references' lowercase (soft-masked) bases, such as repeats \
+                   masked by RepeatMasker, so reads aren't drawn to every copy of a repeat. \
+                   Reads still align across masked bases from seeds outside them. Without this \
+                   lowercase bases are treated like uppercase ones."))
+        .arg(Arg::with_name("MASK_LOW_COMPLEXITY")
+            .long("mask-low-complexity")
+            .takes_value(true)
+            .min_values(0)
+            .max_values(1)
+            .value_name("LEVEL")
+            .conflicts_with_all(&["CONVERT", "REMOVE_TAXIDS"])
+            .help("Don't seed in the low-complexity regions of the references (poly-A runs, \
+                   simple repeats and so on), the 64-base windows whose DUST score is above \
+                   LEVEL in tenths, as for dustmasker -level, 20 if not given. Reads still \
+                   align across them, as with --respect-softmask. The number of bases masked \
+                   is logged. DNA only."))
+        .arg(Arg::with_name("AMBIGUITY")
+            .long("ambiguity")
+            .takes_value(true)
+            .possible_values(&["n", "expand", "reject"])
+            .conflicts_with_all(&["CONVERT", "REMOVE_TAXIDS"])
+            .help("How to treat IUPAC ambiguity codes (R, Y, S, W, K, M, B, D, H, V) in DNA \
+                   references: 'n' replaces them with N, 'expand' keeps them so reads align \
+                   against them as any of their bases, and 'reject' fails the build on a \
+                   reference with one. Seeds never match them either way. Defaults to 'n'."))
+        .arg(Arg::with_name("STRICT_REFERENCES")
+            .long("strict-references")
+            .conflicts_with_all(&["CONVERT", "REMOVE_TAXIDS"])
+            .help("Stop with an error at references with no sequence, or only whitespace. \
+                   Without this, such references are skipped with a warning."))
+        .arg(Arg::with_name("KEEP_DESCRIPTIONS")
+            .long("keep-descriptions")
+            .conflicts_with_all(&["MMAP_FORMAT", "CONVERT", "REMOVE_TAXIDS"])
+            .help("Keep each reference's FASTA description, the rest of its header line after \
+                   the ID, in the index, for mtsv-reference to write back out. Descriptions \
+                   make the index bigger, and the memory-mapped layout can't keep them."))
+        .arg(Arg::with_name("BIN_MODE")
+            .long("bin-mode")
+            .takes_value(true)
+            .possible_values(&["gi", "taxon"])
+            .conflicts_with_all(&["CONVERT", "REMOVE_TAXIDS"])
+            .help("How to divide the references into bins: 'gi' gives each reference a bin of \
+                   its own, and 'taxon' joins all of a taxid's references into one bin, \
+                   separated by --spacer-length Ns. Taxa with many contigs then take far fewer \
+                   bins, which makes the index smaller and binning faster, but hits can't say \
+                   which reference they're on, so their GI is 0, and the index keeps no \
+                   accessions or descriptions. Defaults to 'gi'."))
+        .arg(Arg::with_name("SPACER_LENGTH")
+            .long("spacer-length")
+            .takes_value(true)
+            .value_name("BASES")
+            .requires("BIN_MODE")
+            .help("Number of Ns between a taxid's references with --bin-mode taxon, 1000 if not \
+                   given. It should be at least as long as the longest read to be binned, so no \
+                   read aligns across two references."))
+        .arg(Arg::with_name("ACCESSION_MAP")
+            .long("accession-map")
+            .takes_value(true)
+            .value_name("FILE")
+            .conflicts_with("ACC2TAXID")
+            .help("File of accessions and their taxids, two whitespace-separated columns per \
+                   line, for --header-format accession. Lines starting with '#' are ignored. \
+                   Accession headers need this or --acc2taxid."))
+        .arg(Arg::with_name("ACC2TAXID")
+            .long("acc2taxid")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("One of NCBI's accession2taxid files, such as nucl_gb.accession2taxid(.gz), \
+                   to look up the taxids of --header-format accession headers in, by either \
+                   the accession or accession.version column. The FASTA files are read twice, \
+                   first for their accessions, so that only the entries needed are kept from \
+                   the file rather than all of it."))
+        .arg(Arg::with_name("NUM_THREADS")
+            .short("t")
+            .long("threads")
+            .takes_value(true)
+            .help("Number of threads to build the BWT, occurrence table and suffix array \
+                   samples on. The suffix array itself is built on one.")
+            .default_value("1"))
+        .arg(Arg::with_name("EXTERNAL_SA")
+            .long("external-sa")
+            .takes_value(true)
+            .value_name("DIR")
+            .conflicts_with("CONVERT")
+            .help("Sort the suffix array into a temporary file in DIR, a batch at a time, rather \
+                   than in memory. This is slower, but the build takes far less memory, and the \
+                   index is the same."))
+        .arg(Arg::with_name("SHARD_SIZE")
+            .long("shard-size")
+            .takes_value(true)
+            .value_name("BASES")
+            .conflicts_with("CONVERT")
+            .help("Cut the references into shards of about BASES bases, in the order they're \
+                   read, and write each one as an index of its own, INDEX.0, INDEX.1 and so on, \
+                   with a list of the shards and the taxids in each one in INDEX.shards.json. \
+                   Shards are only cut between references with different GIs. mtsv-binner can \
+                   bin against all of the shards at once."))
+        .arg(Arg::with_name("RESUME")
+            .long("resume")
+            .conflicts_with_all(&["CONVERT", "REMOVE_TAXIDS", "SHARD_SIZE"])
+            .help("Save the build's progress to INDEX.ckpt once the references are read and \
+                   again once the suffix array is built, and carry on from a checkpoint already \
+                   there rather than starting over. Run the same command again to resume a \
+                   build which died. The checkpoint is removed once the index is written."))
+        .arg(Arg::with_name("BUILD_PRESCREEN")
+            .long("build-prescreen")
+            .help("Also build a bloom filter of the index's k-mers, stored next to the index as \
+                   INDEX.prescreen, which mtsv-binner uses to skip reads with no seeds in the \
+                   index."))
+        .arg(Arg::with_name("PRESCREEN_KMER_SIZE")
+            .long("prescreen-kmer-size")
+            .takes_value(true)
+            .help("K-mer size of the prescreen, which must match the binner's --seed-size for \
+                   the prescreen to be used.")
+            .default_value("18"))
+        .arg(Arg::with_name("PRESCREEN_FPR")
+            .long("prescreen-fpr")
+            .takes_value(true)
+            .help("Target false positive rate of the prescreen. Lower rates skip more reads but \
+                   use more memory.")
+            .default_value("0.01"))
+        .arg(Arg::with_name("PRESCREEN_MAX_MB")
+            .long("prescreen-max-mb")
+            .takes_value(true)
+            .requires("BUILD_PRESCREEN")
+            .help("Limit the prescreen to this many megabytes, raising its false positive rate \
+                   if needed."))
+        .arg(Arg::with_name("COMPRESS_INDEX")
+            .long("compress-index")
+            .takes_value(true)
+            .min_values(0)
+            .max_values(1)
+            .value_name("LEVEL")
+            .help("Write the index zstd-compressed, at LEVEL from 1 (fastest) to 19 (smallest), \
+                   3 if not given. Compressed indices are smaller on disk but slower to load; \
+                   mtsv-binner and the other tools read either kind. An index whose name ends \
+                   in .zst is compressed even without this."))
+        .arg(Arg::with_name("MMAP_FORMAT")
+            .long("mmap-format")
+            .conflicts_with_all(&["COMPRESS_INDEX", "VALIDATE"])
+            .help("Write the index in the memory-mapped layout, which mtsv-binner, \
+                   mtsv-reference and mtsv-run map rather than deserialize. Mapped indices open \
+                   in moments, and binners on the same machine share the memory they use."))
+        .arg(Arg::with_name("CONVERT")
+            .long("convert")
+            .takes_value(true)
+            .value_name("INDEX")
+            .requires("MMAP_FORMAT")
+            .conflicts_with_all(&["FASTA", "MANIFEST", "BUILD_PRESCREEN"])
+            .help("Instead of building from --fasta, convert the existing index INDEX to the \
+                   memory-mapped layout, written to --index. The index is copied as it's read, \
+                   so it's never held in memory."))
+        .arg(Arg::with_name("REMOVE_TAXIDS")
+            .long("remove-taxids")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .use_delimiter(true)
+            .value_name("TAXIDS")
+            .conflicts_with_all(&["FASTA", "MANIFEST", "CONVERT", "SHARD_SIZE", "EXTERNAL_SA"])
+            .help("Instead of building from --fasta, remove the references of these \
+                   comma-separated taxids from the existing index --index and rebuild it in \
+                   place, with the sampling rates it was built with. May be repeated. The old \
+                   index is only replaced once the new one is written."))
+        .arg(Arg::with_name("VALIDATE")
+            .long("validate")
+            .alias("self-test")
+            .help("After writing the index, reload it and query a read from each of a random \
+                   sample of its references, failing the build if any reference isn't found. An \
+                   index which fails, or can't be reloaded, is moved to INDEX.invalid."))
+        .arg(Arg::with_name("VALIDATE_SAMPLES")
+            .long("validate-samples")
+            .alias("self-test-samples")
+            .takes_value(true)
+            .help("Number of references to query in the validation.")
+            .default_value("100"))
+        .arg(Arg::with_name("VALIDATE_READ_LENGTH")
+            .long("validate-read-length")
+            .alias("self-test-read-length")
+            .takes_value(true)
+            .help("Length of the reads queried in the validation.")
+            .default_value("100"))
+        .arg(Arg::with_name("SEED")
+            .long("seed")
+            .takes_value(true)
+            .help("Seed for choosing the validation's reads. A random seed is chosen and logged \
+                   if this isn't given."))
+        .arg(formats::format_versions_arg())
         .get_matches();
 
 
@@ -52,9 +313,27 @@ fn main() {
     } else {
         log::LogLevelFilter::Info
     });
+    util::set_progress_interval(args.value_of("PROGRESS_INTERVAL")
+        .unwrap()
+        .parse::<u64>()
+        .expect("Invalid progress interval entered!"));
 
-    let fasta_path = args.value_of("FASTA").unwrap();
-    let index_path = args.value_of("INDEX").unwrap();
+    if let Some(source) = args.value_of("CONVERT") {
+        info!("Converting {} to a mapped index...", source);
+        match index::convert_to_mapped(source, args.value_of("INDEX").unwrap()) {
+            Ok(()) => info!("Done writing mapped index!"),
+            Err(why) => {
+                error!("Error converting index: {}", why);
+                std::process::exit(1);
+            },
+        }
+        return;
+    }
+    let fasta_paths = args.values_of("FASTA").map(|paths| paths.collect::<Vec<_>>());
+    let removed_taxids = args.values_of("REMOVE_TAXIDS").map(|taxids| {
+        taxids.map(|s| s.trim().parse::<TaxId>().expect("Invalid taxid entered!"))
+            .collect::<Vec<_>>()
+    });
 
     let exit_code = {
 
@@ -68,22 +347,237 @@ fn main() {
             None => unreachable!(),
         };
 
-        debug!("Opening FASTA database file...");
-        let records = fasta::Reader::from_file(Path::new(fasta_path))
-            .expect("Unable to open FASTA database for parsing.")
-            .records();
+        let header_format = match args.value_of("HEADER_FORMAT") {
+            Some(s) => s.parse::<HeaderFormat>().expect("Invalid header format entered!"),
+            None => unreachable!(),
+        };
 
-        match builder::build_and_write_index(records, index_path, fm_index_interval, sa_interval) {
-            Ok(_) => {
-                info!("Done building and writing index!");
-                0
-            },
-            Err(why) => {
-                error!("Error building index: {}", why);
-                1
-            },
+        let alphabet = match args.value_of("ALPHABET") {
+            Some(s) => s.parse::<Alphabet>().expect("Invalid alphabet entered!"),
+            None => unreachable!(),
+        };
+
+        let ambiguity = args.value_of("AMBIGUITY")
+            .map_or(AmbiguityPolicy::N,
+                    |s| s.parse::<AmbiguityPolicy>().expect("Invalid ambiguity policy entered!"));
+
+        let spacer_len = args.value_of("SPACER_LENGTH")
+            .map_or(1000, |s| s.parse::<usize>().expect("Invalid spacer length entered!"));
+        let bin_mode = match args.value_of("BIN_MODE") {
+            Some("taxon") => BinMode::PerTaxon { spacer_len },
+            _ => BinMode::PerGi,
+        };
+
+        let mask_low_complexity = if args.is_present("MASK_LOW_COMPLEXITY") {
+            Some(args.value_of("MASK_LOW_COMPLEXITY")
+                .unwrap_or("20")
+                .parse::<u32>()
+                .expect("Invalid low-complexity level entered!"))
+        } else {
+            None
+        };
+
+        let num_threads = match args.value_of("NUM_THREADS") {
+            Some(s) => s.parse::<usize>().expect("Invalid number entered for number of threads!"),
+            None => unreachable!(),
+        };
+        if num_threads == 0 {
+            panic!("Number of threads must be at least 1");
+        }
+
+        let prescreen = if args.is_present("BUILD_PRESCREEN") {
+            let kmer_size = args.value_of("PRESCREEN_KMER_SIZE")
+                .unwrap()
+                .parse::<usize>()
+                .expect("Invalid prescreen k-mer size entered!");
+            let false_positive_rate = args.value_of("PRESCREEN_FPR")
+                .unwrap()
+                .parse::<f64>()
+                .expect("Invalid prescreen false positive rate entered!");
+            if false_positive_rate <= 0.0 || false_positive_rate >= 1.0 {
+                panic!("Prescreen false positive rate must be between 0 and 1, exclusive");
+            }
+            let max_bytes = args.value_of("PRESCREEN_MAX_MB").map(|s| {
+                s.parse::<usize>().expect("Invalid prescreen size limit entered!") * 1024 * 1024
+            });
+
+            Some(PrescreenParams { kmer_size, false_positive_rate, max_bytes })
+        } else {
+            None
+        };
+
+        let compression = if args.is_present("COMPRESS_INDEX") {
+            let level = args.value_of("COMPRESS_INDEX")
+                .map_or(DEFAULT_COMPRESSION_LEVEL,
+                        |s| s.parse::<u32>().expect("Invalid index compression level entered!"));
+            if !COMPRESSION_LEVELS.contains(&level) {
+                panic!("Index compression level must be from {} to {}",
+                       COMPRESSION_LEVELS.start(),
+                       COMPRESSION_LEVELS.end());
+            }
+            Some(level)
+        } else {
+            None
+        };
+
+        let validation = if args.is_present("VALIDATE") {
+            let samples = args.value_of("VALIDATE_SAMPLES")
+                .unwrap()
+                .parse::<usize>()
+                .expect("Invalid number of validation samples entered!");
+            let read_len = args.value_of("VALIDATE_READ_LENGTH")
+                .unwrap()
+                .parse::<usize>()
+                .expect("Invalid validation read length entered!");
+            let seed = args.value_of("SEED")
+                .map(|s| s.parse::<u64>().expect("Invalid seed entered!"));
+            Some((samples, read_len, RunContext::new(seed)))
+        } else {
+            None
+        };
+
+        let options = BuildOptions {
+            sample_interval: fm_index_interval,
+            suffix_sample: sa_interval,
+            header_format,
+            accession_map: args.value_of("ACCESSION_MAP").map(|s| s.to_string()),
+            acc2taxid: args.value_of("ACC2TAXID").map(|s| s.to_string()),
+            prescreen,
+            compression,
+            threads: num_threads,
+            mapped: args.is_present("MMAP_FORMAT"),
+            external_sa: args.value_of("EXTERNAL_SA").map(|s| s.to_string()),
+            shard_size: args.value_of("SHARD_SIZE")
+                .map(|s| s.parse::<usize>().expect("Invalid shard size entered!")),
+            alphabet,
+            respect_softmask: args.is_present("RESPECT_SOFTMASK"),
+            mask_low_complexity,
+            ambiguity,
+            strict_references: args.is_present("STRICT_REFERENCES"),
+            keep_descriptions: args.is_present("KEEP_DESCRIPTIONS"),
+            resume: args.is_present("RESUME"),
+            bin_mode,
+        };
+        if args.is_present("DRY_RUN") {
+            dry_run(&fasta_paths.unwrap(), &options)
+        } else {
+            let index_path = args.value_of("INDEX").unwrap();
+            let built = match (removed_taxids, args.value_of("MANIFEST")) {
+                (Some(ref taxids), _) => {
+                    builder::remove_taxa_and_rewrite_index(index_path, taxids, &options)
+                },
+                (None, Some(manifest)) => {
+                    builder::build_and_write_index_from_manifest(manifest, index_path, &options)
+                },
+                (None, None) => {
+                    builder::build_and_write_index_from_files(&fasta_paths.unwrap(),
+                                                              index_path,
+                                                              &options)
+                },
+            };
+            match built {
+                Ok(_) => {
+                    info!("Done building and writing index!");
+                    match validation {
+                        Some((samples, read_len, run)) => {
+                            match validate_all(index_path,
+                                               options.shard_size.is_some(),
+                                               samples,
+                                               read_len,
+                                               &run) {
+                                Ok(true) => 0,
+                                Ok(false) => 1,
+                                Err(why) => {
+                                    error!("Error validating index: {}", why);
+                                    1
+                                },
+                            }
+                        },
+                        None => 0,
+                    }
+                },
+                Err(why) => {
+                    error!("Error building index: {}", why);
+                    1
+                },
+            }
         }
     };
 
     std::process::exit(exit_code);
 }
+
+/// Survey the references at `paths` as a build with `options` would read them (see
+/// `builder::survey_references_from_files`), and print what the index would hold: its totals, the
+/// references and bases of each taxid, and the references which would fail the build. Returns the
+/// exit code, 1 if any reference would fail the build.
+fn dry_run(paths: &[&str], options: &BuildOptions) -> i32 {
+    let survey = match builder::survey_references_from_files(paths, options) {
+        Ok(survey) => survey,
+        Err(why) => {
+            error!("Error reading references: {}", why);
+            return 1;
+        },
+    };
+
+    println!("taxa\t{}", survey.taxa.len());
+    println!("references\t{}", survey.references());
+    println!("bases\t{}", survey.bases());
+    println!("skipped\t{}", survey.skipped.len());
+    println!("problems\t{}", survey.problems.len());
+    println!();
+    println!("taxid\treferences\tbases");
+    for (tax_id, counts) in &survey.taxa {
+        println!("{}\t{}\t{}", tax_id.0, counts.references, counts.bases);
+    }
+    if !survey.problems.is_empty() {
+        println!();
+        println!("record\tid\tproblem");
+        for problem in &survey.problems {
+            println!("{}\t{}\t{}", problem.record, problem.id, problem.why);
+        }
+    }
+
+    if survey.problems.is_empty() {
+        info!("Every reference can be built");
+        0
+    } else {
+        error!("{} references would fail the build", survey.problems.len());
+        1
+    }
+}
+
+/// Validate the index, or each of its shards if it was sharded, moving any which can't be loaded
+/// or fail aside (see `builder::mark_invalid_index`). Returns whether they all passed.
+fn validate_all(index_path: &str,
+                sharded: bool,
+                samples: usize,
+                read_len: usize,
+                run: &RunContext)
+                -> MtsvResult<bool> {
+    let paths = if sharded {
+        let manifest =
+            builder::ShardManifest::from_file(&builder::shard_manifest_path(index_path))?;
+        manifest.index_paths().into_iter().map(String::from).collect()
+    } else {
+        vec![index_path.to_string()]
+    };
+
+    let mut passed = true;
+    for path in &paths {
+        // the index was just written, so failing to load it means it's broken
+        let valid = match MGIndex::from_file(path) {
+            Ok(index) => builder::validate_index(&index, samples, read_len, run)?,
+            Err(why) => {
+                error!("Unable to load index {}: {}", path, why);
+                false
+            },
+        };
+        if !valid {
+            let invalid_path = builder::mark_invalid_index(path)?;
+            error!("Moved invalid index {} to {}", path, invalid_path);
+            passed = false;
+        }
+    }
+    Ok(passed)
+}
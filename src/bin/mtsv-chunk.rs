@@ -1,4 +1,4 @@
-//! Split a FASTA reference database file into chunks well-suited for vedro's index generation.
+//! Split a FASTA reference database file into chunks well-suited for mtsv's index generation.
 
 #[macro_use]
 extern crate log;
@@ -11,10 +11,16 @@ use bio::io::fasta;
 use clap::{App, Arg};
 use std::path::Path;
 use mtsv::chunk::write_db_chunks;
+use mtsv::formats;
 use mtsv::io::parse_fasta_db;
 use mtsv::util;
 
 fn main() {
+    if formats::format_versions_requested() {
+        println!("{}", formats::format_versions());
+        return;
+    }
+
     let args = App::new("mtsv-chunk")
         .version(env!("CARGO_PKG_VERSION"))
         .author(env!("CARGO_PKG_AUTHORS"))
@@ -41,6 +47,7 @@ fn main() {
         .arg(Arg::with_name("VERBOSE")
             .short("v")
             .help("Include this flag to trigger debug-level logging."))
+        .arg(formats::format_versions_arg())
         .get_matches();
 
 
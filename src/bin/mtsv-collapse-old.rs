@@ -10,9 +10,15 @@ use std::fs::File;
 use std::io::{BufReader, BufWriter};
 
 use mtsv::collapse::collapse_files;
+use mtsv::formats;
 use mtsv::util;
 
 fn main() {
+    if formats::format_versions_requested() {
+        println!("{}", formats::format_versions());
+        return;
+    }
+
     let args = App::new("mtsv-collapse")
         .version(env!("CARGO_PKG_VERSION"))
         .author(env!("CARGO_PKG_AUTHORS"))
@@ -32,6 +38,7 @@ fn main() {
         .arg(Arg::with_name("VERBOSE")
             .short("v")
             .help("Include this flag to trigger debug-level logging."))
+        .arg(formats::format_versions_arg())
         .get_matches();
 
 
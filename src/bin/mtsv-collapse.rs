@@ -10,9 +10,15 @@ use std::fs::File;
 use std::io::{BufReader, BufWriter};
 
 use mtsv::collapse::collapse_edit_files;
+use mtsv::formats;
 use mtsv::util;
 
 fn main() {
+    if formats::format_versions_requested() {
+        println!("{}", formats::format_versions());
+        return;
+    }
+
     let args = App::new("mtsv-collapse")
         .version(env!("CARGO_PKG_VERSION"))
         .author(env!("CARGO_PKG_AUTHORS"))
@@ -29,9 +35,21 @@ fn main() {
             .takes_value(true)
             .multiple(true)
             .required(true))
+        .arg(Arg::with_name("ALLOW_MIXED_SAMPLES")
+            .long("allow-mixed-samples")
+            .help("Allow merging results from different samples, prefixing each read ID with its \
+            sample name."))
+        .arg(Arg::with_name("MAX_LINE_TAXIDS")
+            .long("max-line-taxids")
+            .takes_value(true)
+            .value_name("N")
+            .help("Write at most N taxids on a read's line, keeping those with the fewest edits, \
+            and end the line with a ...+COUNT marker for the rest. Lines which were capped in \
+            any input file keep a marker."))
         .arg(Arg::with_name("VERBOSE")
             .short("v")
             .help("Include this flag to trigger debug-level logging."))
+        .arg(formats::format_versions_arg())
         .get_matches();
 
 
@@ -43,6 +61,11 @@ fn main() {
     });
 
     let outpath = args.value_of("OUTPUT").unwrap();
+    let max_line_taxids = args.value_of("MAX_LINE_TAXIDS")
+        .map(|s| s.parse::<usize>().expect("Invalid max line taxids entered!"));
+    if max_line_taxids == Some(0) {
+        panic!("Max line taxids must be at least 1");
+    }
     let files = args.values_of("FILES").unwrap().collect::<Vec<_>>();
 
     let mut infiles = Vec::new();
@@ -58,7 +81,10 @@ fn main() {
         infiles.push(rdr);
     }
 
-    match collapse_edit_files(&mut infiles, &mut outfile) {
+    match collapse_edit_files(&mut infiles,
+                              &mut outfile,
+                              args.is_present("ALLOW_MIXED_SAMPLES"),
+                              max_line_taxids) {
         Ok(()) => {
             info!("Successfully collapsed files. Output available in {}",
                   outpath)
@@ -0,0 +1,104 @@
+#[macro_use]
+extern crate log;
+
+extern crate clap;
+extern crate mtsv;
+
+use clap::{App, Arg};
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+
+use mtsv::convert::convert_findings;
+use mtsv::MtsvResult;
+use mtsv::formats;
+use mtsv::io::{open_maybe_gz, FindingsVersion};
+use mtsv::util;
+
+fn main() {
+    if formats::format_versions_requested() {
+        println!("{}", formats::format_versions());
+        return;
+    }
+
+    let args = App::new("mtsv-convert")
+        .version(env!("CARGO_PKG_VERSION"))
+        .author(env!("CARGO_PKG_AUTHORS"))
+        .about("Convert mtsv results between the legacy taxid-only format, the edit distance \
+                formats and the binary format.")
+        .arg(Arg::with_name("INPUT")
+            .index(1)
+            .help("Path to the mtsv results file to convert (optionally gzipped).")
+            .takes_value(true)
+            .required(true))
+        .arg(Arg::with_name("OUTPUT")
+            .short("o")
+            .long("output")
+            .takes_value(true)
+            .help("Path to write the converted results to. Written to stdout if omitted."))
+        .arg(Arg::with_name("FROM")
+            .long("from")
+            .takes_value(true)
+            .possible_values(&["legacy", "findings"])
+            .default_value("findings")
+            .help("Ignored: the input's format (legacy, v1, v2 or binary) is recognized from \
+                   the file. Kept for existing scripts."))
+        .arg(Arg::with_name("TO")
+            .long("to")
+            .takes_value(true)
+            .required(true)
+            .possible_values(&["legacy", "v1", "v2", "binary"])
+            .help("Format to write."))
+        .arg(Arg::with_name("DEFAULT_EDIT")
+            .long("default-edit")
+            .takes_value(true)
+            .default_value("0")
+            .help("Edit distance to give hits read from legacy results, which don't record \
+                   one."))
+        .arg(Arg::with_name("VERBOSE")
+            .short("v")
+            .help("Include this flag to trigger debug-level logging."))
+        .arg(formats::format_versions_arg())
+        .get_matches();
+
+    // setup logger
+    util::init_logging(if args.is_present("VERBOSE") {
+        log::LogLevelFilter::Debug
+    } else {
+        log::LogLevelFilter::Info
+    });
+
+    let to = args.value_of("TO").unwrap().parse().expect("Invalid output format!");
+    let default_edit = args.value_of("DEFAULT_EDIT")
+        .unwrap()
+        .parse::<u32>()
+        .expect("Invalid default edit distance!");
+
+    match run(args.value_of("INPUT").unwrap(),
+              args.value_of("OUTPUT"),
+              to,
+              default_edit) {
+        Ok(()) => (),
+        Err(why) => {
+            error!("Problem converting results: {}", why);
+            std::process::exit(2);
+        },
+    }
+}
+
+fn run(input_path: &str,
+       output_path: Option<&str>,
+       to: FindingsVersion,
+       default_edit: u32)
+       -> MtsvResult<()> {
+    let mut writer: Box<dyn Write> = match output_path {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(io::stdout()),
+    };
+
+    let reads = convert_findings(open_maybe_gz(input_path)?, &mut writer, to, default_edit)?;
+    writer.flush()?;
+
+    info!("Converted results for {} reads", reads);
+    Ok(())
+}
@@ -0,0 +1,122 @@
+#[macro_use]
+extern crate log;
+
+extern crate clap;
+extern crate mtsv;
+
+use clap::{App, Arg};
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+
+use mtsv::coverage::{compute_coverage, load_reference_lengths, write_gi_coverage,
+                     write_taxid_coverage};
+use mtsv::MtsvResult;
+use mtsv::formats;
+use mtsv::io::open_maybe_gz;
+use mtsv::util;
+
+fn main() {
+    if formats::format_versions_requested() {
+        println!("{}", formats::format_versions());
+        return;
+    }
+
+    let args = App::new("mtsv-coverage")
+        .version(env!("CARGO_PKG_VERSION"))
+        .author(env!("CARGO_PKG_AUTHORS"))
+        .about("Report the breadth and depth of coverage of each reference from detailed hits.")
+        .arg(Arg::with_name("HITS")
+            .index(1)
+            .help("Path to a TSV of detailed hits (optionally gzipped), with a read ID, GI, \
+            0-based offset within the reference and aligned length on each line, grouped by GI.")
+            .takes_value(true)
+            .required(true))
+        .arg(Arg::with_name("REFERENCE_LENGTHS")
+            .long("reference-lengths")
+            .takes_value(true)
+            .required(true)
+            .multiple(true)
+            .number_of_values(1)
+            .help("Path to a table of reference lengths from mtsv-inspect --reference-lengths. \
+            May be repeated for the chunks of a database."))
+        .arg(Arg::with_name("BIN_SIZE")
+            .long("bin-size")
+            .takes_value(true)
+            .default_value("1000")
+            .help("Number of reference bases in each coverage bin."))
+        .arg(Arg::with_name("OUTPUT")
+            .short("o")
+            .long("output")
+            .takes_value(true)
+            .help("Path to write the per-GI report to. Written to stdout if omitted."))
+        .arg(Arg::with_name("TAXID_OUTPUT")
+            .long("taxid-output")
+            .takes_value(true)
+            .help("Path to write a per-taxid report to."))
+        .arg(Arg::with_name("VERBOSE")
+            .short("v")
+            .help("Include this flag to trigger debug-level logging."))
+        .arg(formats::format_versions_arg())
+        .get_matches();
+
+    // setup logger
+    util::init_logging(if args.is_present("VERBOSE") {
+        log::LogLevelFilter::Debug
+    } else {
+        log::LogLevelFilter::Info
+    });
+
+    let bin_size = args.value_of("BIN_SIZE")
+        .unwrap()
+        .parse::<usize>()
+        .expect("Invalid coverage bin size entered!");
+
+    match run(args.value_of("HITS").unwrap(),
+              &args.values_of("REFERENCE_LENGTHS").unwrap().collect::<Vec<_>>(),
+              bin_size,
+              args.value_of("OUTPUT"),
+              args.value_of("TAXID_OUTPUT")) {
+        Ok(()) => (),
+        Err(why) => {
+            error!("Problem computing coverage: {}", why);
+            std::process::exit(2);
+        },
+    }
+}
+
+fn run(hits_path: &str,
+       lengths_paths: &[&str],
+       bin_size: usize,
+       output_path: Option<&str>,
+       taxid_output_path: Option<&str>)
+       -> MtsvResult<()> {
+    let mut lengths = load_reference_lengths(lengths_paths[0])?;
+    for path in &lengths_paths[1..] {
+        lengths.extend(load_reference_lengths(path)?);
+    }
+    info!("Loaded lengths for {} references", lengths.len());
+
+    let (gis, taxids, missing) = compute_coverage(open_maybe_gz(hits_path)?, &lengths, bin_size)?;
+    if !missing.is_empty() {
+        warn!("{} GIs with hits aren't in the reference lengths and were left out: {}",
+              missing.len(),
+              missing.iter().map(|g| g.0.to_string()).collect::<Vec<_>>().join(","));
+    }
+    info!("Computed coverage for {} references of {} taxids", gis.len(), taxids.len());
+
+    let mut writer: Box<dyn Write> = match output_path {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(io::stdout()),
+    };
+    write_gi_coverage(&gis, &mut writer)?;
+    writer.flush()?;
+
+    if let Some(path) = taxid_output_path {
+        let mut writer = BufWriter::new(File::create(path)?);
+        write_taxid_coverage(&taxids, &mut writer)?;
+        writer.flush()?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,105 @@
+#[macro_use]
+extern crate log;
+
+extern crate clap;
+extern crate mtsv;
+extern crate serde_json;
+
+use clap::{App, Arg};
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+
+use mtsv::MtsvResult;
+use mtsv::evaluate::{evaluate, load_truth, write_misclassified};
+use mtsv::formats;
+use mtsv::io::open_maybe_gz;
+use mtsv::util;
+
+fn main() {
+    if formats::format_versions_requested() {
+        println!("{}", formats::format_versions());
+        return;
+    }
+
+    let args = App::new("mtsv-evaluate")
+        .version(env!("CARGO_PKG_VERSION"))
+        .author(env!("CARGO_PKG_AUTHORS"))
+        .about("Score mtsv results against the known source taxid of each read.")
+        .arg(Arg::with_name("RESULTS")
+            .index(1)
+            .help("Path to the mtsv results file to evaluate (optionally gzipped).")
+            .takes_value(true)
+            .required(true))
+        .arg(Arg::with_name("TRUTH")
+            .long("truth")
+            .takes_value(true)
+            .required(true)
+            .help("Path to a table with a read ID and the taxid it came from on each line."))
+        .arg(Arg::with_name("SUMMARY")
+            .long("summary")
+            .takes_value(true)
+            .help("Path to write the summary metrics to as JSON. Written to stdout if omitted."))
+        .arg(Arg::with_name("MISCLASSIFIED")
+            .long("misclassified")
+            .takes_value(true)
+            .help("Path to write a TSV of the reads which weren't assigned to only their true \
+            taxid."))
+        .arg(Arg::with_name("VERBOSE")
+            .short("v")
+            .help("Include this flag to trigger debug-level logging."))
+        .arg(formats::format_versions_arg())
+        .get_matches();
+
+    // setup logger
+    util::init_logging(if args.is_present("VERBOSE") {
+        log::LogLevelFilter::Debug
+    } else {
+        log::LogLevelFilter::Info
+    });
+
+    match run(args.value_of("RESULTS").unwrap(),
+              args.value_of("TRUTH").unwrap(),
+              args.value_of("SUMMARY"),
+              args.value_of("MISCLASSIFIED")) {
+        Ok(()) => (),
+        Err(why) => {
+            error!("Problem evaluating results: {}", why);
+            std::process::exit(2);
+        },
+    }
+}
+
+fn run(results_path: &str,
+       truth_path: &str,
+       summary_path: Option<&str>,
+       misclassified_path: Option<&str>)
+       -> MtsvResult<()> {
+    let truth = load_truth(truth_path)?;
+    info!("Loaded {} labelled reads from {}", truth.len(), truth_path);
+
+    let (summary, reads) = evaluate(open_maybe_gz(results_path)?, &truth)?;
+    info!("Recall: {:.4}, precision: {:.4}, F1: {:.4}",
+          summary.recall,
+          summary.precision,
+          summary.f1);
+    if summary.unlabelled > 0 {
+        warn!("{} reads in the results aren't in the truth table", summary.unlabelled);
+    }
+
+    let mut writer: Box<dyn Write> = match summary_path {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(io::stdout()),
+    };
+    serde_json::to_writer_pretty(&mut writer, &summary)?;
+    writeln!(writer)?;
+    writer.flush()?;
+
+    if let Some(path) = misclassified_path {
+        let mut writer = BufWriter::new(File::create(path)?);
+        write_misclassified(&reads, &mut writer)?;
+        writer.flush()?;
+    }
+
+    Ok(())
+}
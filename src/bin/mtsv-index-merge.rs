@@ -0,0 +1,192 @@
+#[macro_use]
+extern crate log;
+
+extern crate clap;
+extern crate mtsv;
+
+
+use clap::{App, Arg};
+use mtsv::builder;
+use mtsv::builder::BuildOptions;
+use mtsv::formats;
+use mtsv::io::{COMPRESSION_LEVELS, DEFAULT_COMPRESSION_LEVEL};
+use mtsv::prescreen::PrescreenParams;
+use mtsv::util;
+
+fn main() {
+    if formats::format_versions_requested() {
+        println!("{}", formats::format_versions());
+        return;
+    }
+
+
+    let args = App::new("mtsv-index-merge")
+        .version(env!("CARGO_PKG_VERSION"))
+        .author(env!("CARGO_PKG_AUTHORS"))
+        .about("Combine several mtsv indices into one holding all of their references.")
+        .arg(Arg::with_name("INPUT")
+            .short("m")
+            .long("merge")
+            .help("Paths to the indices to merge, which may be compressed but not in the \
+                   memory-mapped layout. No GI may be in more than one of them. The merged \
+                   index is the same as one built by mtsv-build from all of their references.")
+            .takes_value(true)
+            .multiple(true)
+            .required(true))
+        .arg(Arg::with_name("INDEX")
+            .short("i")
+            .long("index")
+            .help("Path to write the merged index to.")
+            .takes_value(true)
+            .required(true))
+        .arg(Arg::with_name("VERBOSE")
+            .short("v")
+            .help("Include this flag to trigger debug-level logging."))
+        .arg(Arg::with_name("PROGRESS_INTERVAL")
+            .long("progress-interval")
+            .takes_value(true)
+            .value_name("SECONDS")
+            .help("Log how far the build has got every SECONDS seconds while reading the \
+                   references and building the index, with the time taken so far and about how \
+                   long each step has left. 0 turns progress off.")
+            .default_value("60"))
+        .arg(Arg::with_name("SA_SAMPLE_RATE")
+            .long("sa-sample")
+            .takes_value(true)
+            .help("Suffix array sampling rate of the merged index. If sampling rate is k, every \
+            k-th entry will be kept. Must be at least 1, values above 1024 make queries very \
+            slow.")
+            .default_value("32"))
+        .arg(Arg::with_name("FM_SAMPLE_INTERVAL")
+            .long("sample-interval")
+            .takes_value(true)
+            .help("BWT occurance sampling rate of the merged index. If sample interval is k, \
+            every k-th entry will be kept. Must be at least 1, values above 1024 make queries \
+            very slow.")
+            .default_value("64"))
+        .arg(Arg::with_name("NUM_THREADS")
+            .short("t")
+            .long("threads")
+            .takes_value(true)
+            .help("Number of threads to build the BWT, occurrence table and suffix array \
+                   samples on, as for mtsv-build.")
+            .default_value("1"))
+        .arg(Arg::with_name("EXTERNAL_SA")
+            .long("external-sa")
+            .takes_value(true)
+            .value_name("DIR")
+            .help("Sort the suffix array into a temporary file in DIR, a batch at a time, rather \
+                   than in memory, as for mtsv-build."))
+        .arg(Arg::with_name("BUILD_PRESCREEN")
+            .long("build-prescreen")
+            .help("Also build a bloom filter of the merged index's k-mers, stored next to it as \
+                   INDEX.prescreen, which mtsv-binner uses to skip reads with no seeds in the \
+                   index."))
+        .arg(Arg::with_name("PRESCREEN_KMER_SIZE")
+            .long("prescreen-kmer-size")
+            .takes_value(true)
+            .help("K-mer size of the prescreen, which must match the binner's --seed-size for \
+                   the prescreen to be used.")
+            .default_value("18"))
+        .arg(Arg::with_name("PRESCREEN_FPR")
+            .long("prescreen-fpr")
+            .takes_value(true)
+            .help("Target false positive rate of the prescreen. Lower rates skip more reads but \
+                   use more memory.")
+            .default_value("0.01"))
+        .arg(Arg::with_name("COMPRESS_INDEX")
+            .long("compress-index")
+            .takes_value(true)
+            .min_values(0)
+            .max_values(1)
+            .value_name("LEVEL")
+            .help("Write the merged index compressed, at LEVEL from 1 (fastest) to 19 \
+                   (smallest), 3 if not given. An index whose name ends in .zst is compressed \
+                   even without this."))
+        .arg(Arg::with_name("MMAP_FORMAT")
+            .long("mmap-format")
+            .conflicts_with("COMPRESS_INDEX")
+            .help("Write the merged index in the memory-mapped layout (see mtsv-build \
+                   --mmap-format)."))
+        .arg(formats::format_versions_arg())
+        .get_matches();
+
+
+    // setup logger
+    util::init_logging(if args.is_present("VERBOSE") {
+        log::LogLevelFilter::Debug
+    } else {
+        log::LogLevelFilter::Info
+    });
+    util::set_progress_interval(args.value_of("PROGRESS_INTERVAL")
+        .unwrap()
+        .parse::<u64>()
+        .expect("Invalid progress interval entered!"));
+
+    let input_paths = args.values_of("INPUT").unwrap().collect::<Vec<_>>();
+    let index_path = args.value_of("INDEX").unwrap();
+
+    let num_threads = args.value_of("NUM_THREADS")
+        .unwrap()
+        .parse::<usize>()
+        .expect("Invalid number entered for number of threads!");
+    if num_threads == 0 {
+        panic!("Number of threads must be at least 1");
+    }
+
+    let prescreen = if args.is_present("BUILD_PRESCREEN") {
+        let kmer_size = args.value_of("PRESCREEN_KMER_SIZE")
+            .unwrap()
+            .parse::<usize>()
+            .expect("Invalid prescreen k-mer size entered!");
+        let false_positive_rate = args.value_of("PRESCREEN_FPR")
+            .unwrap()
+            .parse::<f64>()
+            .expect("Invalid prescreen false positive rate entered!");
+        if false_positive_rate <= 0.0 || false_positive_rate >= 1.0 {
+            panic!("Prescreen false positive rate must be between 0 and 1, exclusive");
+        }
+        Some(PrescreenParams { kmer_size, false_positive_rate, max_bytes: None })
+    } else {
+        None
+    };
+
+    let compression = if args.is_present("COMPRESS_INDEX") {
+        let level = args.value_of("COMPRESS_INDEX")
+            .map_or(DEFAULT_COMPRESSION_LEVEL,
+                    |s| s.parse::<u32>().expect("Invalid index compression level entered!"));
+        if !COMPRESSION_LEVELS.contains(&level) {
+            panic!("Index compression level must be from {} to {}",
+                   COMPRESSION_LEVELS.start(),
+                   COMPRESSION_LEVELS.end());
+        }
+        Some(level)
+    } else {
+        None
+    };
+
+    let options = BuildOptions {
+        sample_interval: args.value_of("FM_SAMPLE_INTERVAL")
+            .unwrap()
+            .parse::<u32>()
+            .expect("Invalid index sample interval entered!"),
+        suffix_sample: args.value_of("SA_SAMPLE_RATE")
+            .unwrap()
+            .parse::<usize>()
+            .expect("Invalid suffix array sample interval entered!"),
+        prescreen,
+        compression,
+        threads: num_threads,
+        mapped: args.is_present("MMAP_FORMAT"),
+        external_sa: args.value_of("EXTERNAL_SA").map(|s| s.to_string()),
+        ..BuildOptions::default()
+    };
+
+    match builder::merge_and_write_indices(&input_paths, index_path, &options) {
+        Ok(()) => info!("Done writing merged index!"),
+        Err(why) => {
+            error!("Error merging indices: {}", why);
+            std::process::exit(1);
+        },
+    }
+}
@@ -0,0 +1,235 @@
+#[macro_use]
+extern crate log;
+
+extern crate clap;
+extern crate mtsv;
+
+
+use clap::{App, Arg};
+use mtsv::builder;
+use mtsv::builder::BuildOptions;
+use mtsv::formats;
+use mtsv::io::{load_id_list, COMPRESSION_LEVELS, DEFAULT_COMPRESSION_LEVEL};
+use mtsv::prescreen::PrescreenParams;
+use mtsv::util;
+use mtsv::TaxId;
+
+fn main() {
+    if formats::format_versions_requested() {
+        println!("{}", formats::format_versions());
+        return;
+    }
+
+
+    let args = App::new("mtsv-index-subset")
+        .version(env!("CARGO_PKG_VERSION"))
+        .author(env!("CARGO_PKG_AUTHORS"))
+        .about("Build a smaller mtsv index holding only some of the taxa of another.")
+        .arg(Arg::with_name("SOURCE")
+            .short("s")
+            .long("source")
+            .help("Path to the index to take the taxa's references from, which may be \
+                   compressed but not in the memory-mapped layout.")
+            .takes_value(true)
+            .required(true))
+        .arg(Arg::with_name("INDEX")
+            .short("i")
+            .long("index")
+            .help("Path to write the subset index to. It's the same index as one built by \
+                   mtsv-build from just the taxa's references.")
+            .takes_value(true)
+            .required(true))
+        .arg(Arg::with_name("TAXID_LIST")
+            .long("taxid")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .use_delimiter(true)
+            .required_unless("TAXID_FILE")
+            .help("Comma-separated taxids to keep. May be repeated, and combined with \
+            --taxid-file."))
+        .arg(Arg::with_name("TAXID_FILE")
+            .long("taxid-file")
+            .takes_value(true)
+            .help("File with one taxid per line to keep. Blank lines and lines starting with '#' \
+            are ignored."))
+        .arg(Arg::with_name("FAIL_ON_MISSING")
+            .long("fail-on-missing")
+            .help("Exit with status 1 if any of the taxids aren't in the source index. They're \
+            listed in a warning either way, and the subset index is still written."))
+        .arg(Arg::with_name("VERBOSE")
+            .short("v")
+            .help("Include this flag to trigger debug-level logging."))
+        .arg(Arg::with_name("PROGRESS_INTERVAL")
+            .long("progress-interval")
+            .takes_value(true)
+            .value_name("SECONDS")
+            .help("Log how far the build has got every SECONDS seconds while reading the \
+                   references and building the index, with the time taken so far and about how \
+                   long each step has left. 0 turns progress off.")
+            .default_value("60"))
+        .arg(Arg::with_name("SA_SAMPLE_RATE")
+            .long("sa-sample")
+            .takes_value(true)
+            .help("Suffix array sampling rate of the subset index. If sampling rate is k, every \
+            k-th entry will be kept. Must be at least 1, values above 1024 make queries very \
+            slow.")
+            .default_value("32"))
+        .arg(Arg::with_name("FM_SAMPLE_INTERVAL")
+            .long("sample-interval")
+            .takes_value(true)
+            .help("BWT occurance sampling rate of the subset index. If sample interval is k, \
+            every k-th entry will be kept. Must be at least 1, values above 1024 make queries \
+            very slow.")
+            .default_value("64"))
+        .arg(Arg::with_name("NUM_THREADS")
+            .short("t")
+            .long("threads")
+            .takes_value(true)
+            .help("Number of threads to build the BWT, occurrence table and suffix array \
+                   samples on, as for mtsv-build.")
+            .default_value("1"))
+        .arg(Arg::with_name("BUILD_PRESCREEN")
+            .long("build-prescreen")
+            .help("Also build a bloom filter of the subset index's k-mers, stored next to it as \
+                   INDEX.prescreen, which mtsv-binner uses to skip reads with no seeds in the \
+                   index."))
+        .arg(Arg::with_name("PRESCREEN_KMER_SIZE")
+            .long("prescreen-kmer-size")
+            .takes_value(true)
+            .help("K-mer size of the prescreen, which must match the binner's --seed-size for \
+                   the prescreen to be used.")
+            .default_value("18"))
+        .arg(Arg::with_name("PRESCREEN_FPR")
+            .long("prescreen-fpr")
+            .takes_value(true)
+            .help("Target false positive rate of the prescreen. Lower rates skip more reads but \
+                   use more memory.")
+            .default_value("0.01"))
+        .arg(Arg::with_name("COMPRESS_INDEX")
+            .long("compress-index")
+            .takes_value(true)
+            .min_values(0)
+            .max_values(1)
+            .value_name("LEVEL")
+            .help("Write the subset index compressed, at LEVEL from 1 (fastest) to 19 \
+                   (smallest), 3 if not given. An index whose name ends in .zst is compressed \
+                   even without this."))
+        .arg(Arg::with_name("MMAP_FORMAT")
+            .long("mmap-format")
+            .conflicts_with("COMPRESS_INDEX")
+            .help("Write the subset index in the memory-mapped layout (see mtsv-build \
+                   --mmap-format)."))
+        .arg(formats::format_versions_arg())
+        .get_matches();
+
+
+    // setup logger
+    util::init_logging(if args.is_present("VERBOSE") {
+        log::LogLevelFilter::Debug
+    } else {
+        log::LogLevelFilter::Info
+    });
+    util::set_progress_interval(args.value_of("PROGRESS_INTERVAL")
+        .unwrap()
+        .parse::<u64>()
+        .expect("Invalid progress interval entered!"));
+
+    let source_path = args.value_of("SOURCE").unwrap();
+    let index_path = args.value_of("INDEX").unwrap();
+
+    let mut taxids = match args.value_of("TAXID_FILE") {
+        Some(taxid_file) => match load_id_list::<TaxId, _>(taxid_file) {
+            Ok(ids) => ids,
+            Err(why) => {
+                error!("Unable to read taxid file: {}", why);
+                std::process::exit(2);
+            },
+        },
+        None => Default::default(),
+    };
+    if let Some(list) = args.values_of("TAXID_LIST") {
+        taxids.extend(list.map(|s| s.trim().parse::<TaxId>().expect("Invalid taxid entered!")));
+    }
+
+    let num_threads = args.value_of("NUM_THREADS")
+        .unwrap()
+        .parse::<usize>()
+        .expect("Invalid number entered for number of threads!");
+    if num_threads == 0 {
+        panic!("Number of threads must be at least 1");
+    }
+
+    let prescreen = if args.is_present("BUILD_PRESCREEN") {
+        let kmer_size = args.value_of("PRESCREEN_KMER_SIZE")
+            .unwrap()
+            .parse::<usize>()
+            .expect("Invalid prescreen k-mer size entered!");
+        let false_positive_rate = args.value_of("PRESCREEN_FPR")
+            .unwrap()
+            .parse::<f64>()
+            .expect("Invalid prescreen false positive rate entered!");
+        if false_positive_rate <= 0.0 || false_positive_rate >= 1.0 {
+            panic!("Prescreen false positive rate must be between 0 and 1, exclusive");
+        }
+        Some(PrescreenParams { kmer_size, false_positive_rate, max_bytes: None })
+    } else {
+        None
+    };
+
+    let compression = if args.is_present("COMPRESS_INDEX") {
+        let level = args.value_of("COMPRESS_INDEX")
+            .map_or(DEFAULT_COMPRESSION_LEVEL,
+                    |s| s.parse::<u32>().expect("Invalid index compression level entered!"));
+        if !COMPRESSION_LEVELS.contains(&level) {
+            panic!("Index compression level must be from {} to {}",
+                   COMPRESSION_LEVELS.start(),
+                   COMPRESSION_LEVELS.end());
+        }
+        Some(level)
+    } else {
+        None
+    };
+
+    let options = BuildOptions {
+        sample_interval: args.value_of("FM_SAMPLE_INTERVAL")
+            .unwrap()
+            .parse::<u32>()
+            .expect("Invalid index sample interval entered!"),
+        suffix_sample: args.value_of("SA_SAMPLE_RATE")
+            .unwrap()
+            .parse::<usize>()
+            .expect("Invalid suffix array sample interval entered!"),
+        prescreen,
+        compression,
+        threads: num_threads,
+        mapped: args.is_present("MMAP_FORMAT"),
+        ..BuildOptions::default()
+    };
+
+    let exit_code = match builder::subset_and_write_index(source_path,
+                                                          &taxids,
+                                                          index_path,
+                                                          &options) {
+        Ok(report) => {
+            info!("Kept {} of {} taxids, with {} reference sequences",
+                  report.taxa,
+                  taxids.len(),
+                  report.references);
+            if report.missing.is_empty() {
+                0
+            } else {
+                warn!("{} taxids aren't in {}: {}",
+                      report.missing.len(),
+                      source_path,
+                      report.missing.iter().map(|t| t.0.to_string()).collect::<Vec<_>>().join(","));
+                if args.is_present("FAIL_ON_MISSING") { 1 } else { 0 }
+            }
+        },
+        Err(why) => {
+            error!("Error building subset index: {}", why);
+            2
+        },
+    };
+    std::process::exit(exit_code);
+}
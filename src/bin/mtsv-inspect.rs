@@ -0,0 +1,283 @@
+#[macro_use]
+extern crate log;
+
+extern crate clap;
+extern crate serde_json;
+
+extern crate mtsv;
+
+use clap::{App, Arg, ArgMatches};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+
+use mtsv::coverage::write_reference_lengths;
+use mtsv::MtsvResult;
+use mtsv::MGIndex;
+use mtsv::builder;
+use mtsv::formats;
+use mtsv::index::IndexCapabilities;
+use mtsv::io::load_id_list;
+use mtsv::run::RunContext;
+use mtsv::summary::write_manifest;
+use mtsv::util;
+
+fn main() {
+    if formats::format_versions_requested() {
+        println!("{}", formats::format_versions());
+        return;
+    }
+
+    let args = App::new("mtsv-inspect")
+        .version(env!("CARGO_PKG_VERSION"))
+        .author(env!("CARGO_PKG_AUTHORS"))
+        .about("Inspect the contents of an MG-index.")
+        .arg(Arg::with_name("INDEX")
+            .short("i")
+            .long("index")
+            .help("Path to MG-index file.")
+            .takes_value(true)
+            .required(true))
+        .arg(Arg::with_name("PROBE")
+            .long("probe")
+            .help("Sequence to search the index for. May be repeated.")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1))
+        .arg(Arg::with_name("PROBE_FILE")
+            .long("probe-file")
+            .help("File with one sequence per line to search the index for. Blank lines and lines \
+            starting with '#' are ignored.")
+            .takes_value(true))
+        .arg(Arg::with_name("MAX_LOCATIONS")
+            .long("max-locations")
+            .help("Print at most MAX_LOCATIONS positions for each probe.")
+            .takes_value(true)
+            .default_value("10"))
+        .arg(Arg::with_name("MANIFEST")
+            .long("manifest")
+            .help("Write the number of reference sequences and bases for each taxid in the index \
+            to this file, for use with mtsv-summary.")
+            .takes_value(true))
+        .arg(Arg::with_name("REFERENCE_LENGTHS")
+            .long("reference-lengths")
+            .help("Write the taxid and length of each reference sequence (GI) in the index to \
+            this file, for use with mtsv-coverage.")
+            .takes_value(true))
+        .arg(Arg::with_name("JSON")
+            .long("json")
+            .conflicts_with_all(&["PROBE", "PROBE_FILE"])
+            .help("Print the index summary as a JSON object instead."))
+        .arg(Arg::with_name("VALIDATE")
+            .long("validate")
+            .help("Also query a read from each of a random sample of the index's references, as \
+                   mtsv-build --validate does, exiting with status 1 if any reference isn't \
+                   found. An index which fails is moved to INDEX.invalid."))
+        .arg(Arg::with_name("VALIDATE_SAMPLES")
+            .long("validate-samples")
+            .takes_value(true)
+            .help("Number of references to query in the validation.")
+            .default_value("100"))
+        .arg(Arg::with_name("VALIDATE_READ_LENGTH")
+            .long("validate-read-length")
+            .takes_value(true)
+            .help("Length of the reads queried in the validation.")
+            .default_value("100"))
+        .arg(Arg::with_name("SEED")
+            .long("seed")
+            .takes_value(true)
+            .requires("VALIDATE")
+            .help("Seed for choosing the validation's reads. A random seed is chosen and logged \
+                   if this isn't given."))
+        .arg(Arg::with_name("NO_VERIFY")
+            .long("no-verify")
+            .help("Don't check the index against the checksum it was written with as it's \
+                   loaded, so that a corrupt index can still be inspected."))
+        .arg(Arg::with_name("VERBOSE")
+            .short("v")
+            .help("Include this flag to trigger debug-level logging."))
+        .arg(formats::format_versions_arg())
+        .get_matches();
+
+    // setup logger
+    util::init_logging(if args.is_present("VERBOSE") {
+        log::LogLevelFilter::Debug
+    } else {
+        log::LogLevelFilter::Info
+    });
+
+    let exit_code = {
+        let index_path = args.value_of("INDEX").unwrap();
+
+        let max_locations = match args.value_of("MAX_LOCATIONS") {
+            Some(s) => s.parse::<usize>().expect("Invalid number entered for max locations!"),
+            None => unreachable!(),
+        };
+
+        let mut probes = match args.values_of("PROBE") {
+            Some(p) => p.map(|s| s.to_uppercase()).collect::<Vec<_>>(),
+            None => Vec::new(),
+        };
+
+        let probe_list = match args.value_of("PROBE_FILE") {
+            Some(probe_file) => load_id_list::<String, _>(probe_file).map(Some),
+            None => Ok(None),
+        };
+
+        match probe_list {
+            Err(why) => {
+                error!("Unable to read probe file: {}", why);
+                2
+            },
+            Ok(probe_list) => {
+                if let Some(list) = probe_list {
+                    probes.extend(list.into_iter().map(|s| s.to_uppercase()));
+                }
+
+                info!("Deserializing index: {}", index_path);
+                let index = if args.is_present("NO_VERIFY") {
+                    MGIndex::from_file_unverified(index_path)
+                } else {
+                    MGIndex::from_file(index_path)
+                };
+                match index.and_then(|index| {
+                    Ok((IndexCapabilities::of_file(index_path)?,
+                        fs::metadata(index_path)?.len(),
+                        index))
+                }) {
+                    Ok((capabilities, file_bytes, index)) => {
+                        if args.is_present("JSON") {
+                            println!("{:#}", summary_json(&index, capabilities, file_bytes));
+                        } else {
+                            print_summary(&index, capabilities, file_bytes);
+                            inspect(&index, &probes, max_locations);
+                        }
+                        let manifest = match args.value_of("MANIFEST") {
+                            Some(path) => write_index_manifest(&index, path),
+                            None => Ok(()),
+                        };
+                        let lengths = match args.value_of("REFERENCE_LENGTHS") {
+                            Some(path) => write_index_reference_lengths(&index, path),
+                            None => Ok(()),
+                        };
+                        match (manifest, lengths) {
+                            (Err(why), _) => {
+                                error!("Unable to write manifest: {}", why);
+                                2
+                            },
+                            (_, Err(why)) => {
+                                error!("Unable to write reference lengths: {}", why);
+                                2
+                            },
+                            _ if args.is_present("VALIDATE") => {
+                                match validate(&index, index_path, &args) {
+                                    Ok(true) => 0,
+                                    Ok(false) => 1,
+                                    Err(why) => {
+                                        error!("Error validating index: {}", why);
+                                        2
+                                    },
+                                }
+                            },
+                            _ => 0,
+                        }
+                    },
+                    Err(why) => {
+                        error!("Unable to read index: {}", why);
+                        2
+                    },
+                }
+            },
+        }
+    };
+
+    std::process::exit(exit_code);
+}
+
+/// Validate the index as the arguments say (see `builder::validate_index`), moving it aside if it
+/// fails. Returns whether it passed.
+fn validate(index: &MGIndex, index_path: &str, args: &ArgMatches) -> MtsvResult<bool> {
+    let samples = args.value_of("VALIDATE_SAMPLES")
+        .unwrap()
+        .parse::<usize>()
+        .expect("Invalid number of validation samples entered!");
+    let read_len = args.value_of("VALIDATE_READ_LENGTH")
+        .unwrap()
+        .parse::<usize>()
+        .expect("Invalid validation read length entered!");
+    let seed = args.value_of("SEED").map(|s| s.parse::<u64>().expect("Invalid seed entered!"));
+
+    let valid = builder::validate_index(index, samples, read_len, &RunContext::new(seed))?;
+    if !valid {
+        let invalid_path = builder::mark_invalid_index(index_path)?;
+        error!("Moved invalid index {} to {}", index_path, invalid_path);
+    }
+    Ok(valid)
+}
+
+/// Print the index summary, one tab-separated name and value per line.
+fn print_summary(index: &MGIndex, capabilities: IndexCapabilities, file_bytes: u64) {
+    let stats = index.stats();
+    println!("fingerprint\t{:016x}", index.fingerprint());
+    println!("references\t{}", stats.references);
+    let names = capabilities.names();
+    println!("capabilities\t{}",
+             if names.is_empty() { String::from("none") } else { names.join(",") });
+    println!("taxa\t{}", stats.taxa);
+    println!("bases\t{}", stats.bases);
+    println!("min_reference_length\t{}", stats.min_reference_length);
+    println!("max_reference_length\t{}", stats.max_reference_length);
+    println!("mean_reference_length\t{:.1}", stats.mean_reference_length);
+    println!("sample_interval\t{}", stats.sample_interval);
+    println!("suffix_sample\t{}", stats.suffix_sample);
+    println!("synthetic_gis\t{}", index.synthetic_gis());
+    println!("file_bytes\t{}", file_bytes);
+}
+
+/// The index summary as a JSON object: the fields of `IndexStats`, along with the fingerprint,
+/// capabilities, whether the GIs are synthetic and the size of the index file.
+fn summary_json(index: &MGIndex,
+                capabilities: IndexCapabilities,
+                file_bytes: u64)
+                -> serde_json::Value {
+    let mut summary = serde_json::to_value(index.stats()).expect("Index stats are valid JSON");
+    summary["fingerprint"] = serde_json::json!(format!("{:016x}", index.fingerprint()));
+    summary["capabilities"] = serde_json::json!(capabilities.names());
+    summary["synthetic_gis"] = serde_json::json!(index.synthetic_gis());
+    summary["file_bytes"] = serde_json::json!(file_bytes);
+    summary
+}
+
+/// Print the results for each probe sequence.
+fn inspect(index: &MGIndex, probes: &[String], max_locations: usize) {
+    let fmindex = index.fm_index();
+
+    // one line per probe with its count, followed by up to max_locations of GI, taxid and offset
+    for probe in probes {
+        let count = index.count_occurrences(&fmindex, probe.as_bytes());
+        println!("{}\t{}", probe, count);
+
+        if count > 0 && max_locations > 0 {
+            for (gi, tax_id, offset) in index.locate_occurrences(&fmindex, probe.as_bytes())
+                .into_iter()
+                .take(max_locations) {
+                println!("\t{}\t{}\t{}", gi.0, tax_id.0, offset);
+            }
+        }
+    }
+}
+
+/// Write the index's per-taxid composition to `path`.
+fn write_index_manifest(index: &MGIndex, path: &str) -> MtsvResult<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_manifest(&index.taxid_composition(), &mut writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Write the taxid and length of each of the index's reference sequences to `path`.
+fn write_index_reference_lengths(index: &MGIndex, path: &str) -> MtsvResult<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_reference_lengths(&index.reference_lengths(), &mut writer)?;
+    writer.flush()?;
+    Ok(())
+}
@@ -6,11 +6,17 @@ extern crate clap;
 extern crate rustc_serialize;
 extern crate mtsv;
 
+use mtsv::formats;
 use mtsv::prep::*;
 use mtsv::prep_config::*;
 use mtsv::util;
 
 fn main() {
+    if formats::format_versions_requested() {
+        println!("{}", formats::format_versions());
+        return;
+    }
+
     let args = prep_cli_app().get_matches();
 
     // setup logger
@@ -8,10 +8,18 @@ extern crate mtsv;
 use clap::{App, Arg};
 
 use mtsv::binner;
+use mtsv::TaxId;
+use mtsv::formats;
+use mtsv::io::load_id_list;
 use mtsv::util;
 
 
 fn main() {
+    if formats::format_versions_requested() {
+        println!("{}", formats::format_versions());
+        return;
+    }
+
     let args = App::new("mtsv")
         .version(env!("CARGO_PKG_VERSION"))
         .author(env!("CARGO_PKG_AUTHORS"))
@@ -26,16 +34,54 @@ fn main() {
             .short("r")
             .long("results")
             .takes_value(true)
-            .help("Output file path (FASTA)."))
+            .help("Output file path (FASTA). Each record's header gives the reference's GI and \
+            taxid, and where it is in the index, as gi|GI|taxid|TAXID start=START len=LEN, \
+            followed by accession=ACCESSION if the index has accessions."))
         .arg(Arg::with_name("TAXID")
             .index(1)
             .help("Extract reference sequences for taxid")
             .takes_value(true)
             .multiple(true)
-            .required(true))
+            .required_unless_one(&["TAXID_LIST", "TAXID_FILE", "GI", "ALL"]))
+        .arg(Arg::with_name("TAXID_LIST")
+            .long("taxid")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .use_delimiter(true)
+            .help("Comma-separated taxids to extract reference sequences for. May be repeated, \
+            and combined with the other taxids given."))
+        .arg(Arg::with_name("TAXID_FILE")
+            .long("taxid-file")
+            .takes_value(true)
+            .help("File with one taxid per line to extract reference sequences for. Blank lines \
+            and lines starting with '#' are ignored."))
+        .arg(Arg::with_name("GI")
+            .long("gi")
+            .takes_value(true)
+            .conflicts_with_all(&["TAXID", "TAXID_LIST", "TAXID_FILE"])
+            .help("Extract the one reference sequence with this GI instead. Fails if no reference \
+            in the index has it."))
+        .arg(Arg::with_name("ALL")
+            .long("all")
+            .conflicts_with_all(&["TAXID", "TAXID_LIST", "GI", "SPLIT", "FAIL_ON_MISSING"])
+            .help("Write every reference sequence in the index, or only those of the taxids in \
+            --taxid-file, as GI-TAXID records with their original descriptions, which mtsv-build \
+            can rebuild the index from."))
+        .arg(Arg::with_name("SPLIT")
+            .long("split")
+            .conflicts_with("GI")
+            .help("Write each taxid's reference sequences to its own file, TAXID.fasta, in the \
+            directory given by --results, rather than all of them to one file."))
+        .arg(Arg::with_name("FAIL_ON_MISSING")
+            .long("fail-on-missing")
+            .conflicts_with("GI")
+            .help("Exit with status 1 if any of the taxids have no reference sequences in the \
+            index. They're listed in a warning either way."))
         .arg(Arg::with_name("VERBOSE")
             .short("v")
             .help("Include this flag to trigger debug-level logging."))
+        .arg(formats::format_versions_arg())
         .get_matches();
 
 
@@ -50,17 +96,61 @@ fn main() {
     let exit_code = {
 
 
-        let tax_str = args.values_of("TAXID").unwrap().collect::<Vec<_>>();
-        let taxids: Vec<u32> = tax_str.iter().flat_map(|x| x.parse()).collect();
+        let mut taxids: Vec<u64> = match args.values_of("TAXID") {
+            Some(tax_str) => tax_str.flat_map(|x| x.parse()).collect(),
+            None => Vec::new(),
+        };
+
+        if let Some(list) = args.values_of("TAXID_LIST") {
+            taxids.extend(list.map(|s| s.trim().parse::<u64>().expect("Invalid taxid entered!")));
+        }
+
+        let taxid_list = match args.value_of("TAXID_FILE") {
+            Some(taxid_file) => load_id_list::<TaxId, _>(taxid_file).map(Some),
+            None => Ok(None),
+        };
+
+        let gi = args.value_of("GI").map(|s| s.parse::<u32>().expect("Invalid GI entered!"));
 
         let results_path = args.value_of("RESULTS_PATH");
         if results_path.is_none() {
             error!("No results path provided!");
             3
+        } else if let Some(gi) = gi {
+            match binner::get_reference_sequence_by_gi_from_index(
+                index_path, results_path.unwrap(), gi) {
+                    Ok(_) => 0,
+                    Err(why) => {
+                        error!("Error running: {}", why);
+                        2
+                    },
+                }
+        } else if let Err(why) = taxid_list {
+            error!("Unable to read taxid file: {}", why);
+            2
+        } else if args.is_present("ALL") {
+            let taxids = taxid_list.unwrap().map(|ids| ids.into_iter().collect());
+            match binner::dump_references(index_path, results_path.unwrap(), taxids.as_ref()) {
+                Ok(_) => 0,
+                Err(why) => {
+                    error!("Error running: {}", why);
+                    2
+                },
+            }
         } else {
+            if let Ok(Some(ids)) = taxid_list {
+                taxids.extend(ids.into_iter().map(|t| t.0));
+            }
+
             let results_path = results_path.unwrap();
-            match binner::get_reference_sequences_from_index(
-                index_path, results_path, taxids) {
+            match binner::extract_references(
+                index_path, results_path, &taxids, args.is_present("SPLIT")) {
+                    Ok(ref missing) if !missing.is_empty() &&
+                                       args.is_present("FAIL_ON_MISSING") => {
+                        error!("{} taxids have no reference sequences in the index",
+                               missing.len());
+                        1
+                    },
                     Ok(_) => 0,
                     Err(why) => {
                         error!("Error running: {}", why);
@@ -0,0 +1,235 @@
+#[macro_use]
+extern crate log;
+
+extern crate clap;
+extern crate mtsv;
+
+use clap::{App, Arg};
+use std::cmp;
+
+use mtsv::binner::{BinnerConfig, ReadFormat};
+use mtsv::builder::BuildOptions;
+use mtsv::formats;
+use mtsv::run::RunContext;
+use mtsv::util;
+use mtsv::util::HeaderFormat;
+use mtsv::workflow::{run_workflow, IndexSource};
+use mtsv::QueryParams;
+
+fn main() {
+    if formats::format_versions_requested() {
+        println!("{}", formats::format_versions());
+        return;
+    }
+
+    let args = App::new("mtsv-run")
+        .version(env!("CARGO_PKG_VERSION"))
+        .author(env!("CARGO_PKG_AUTHORS"))
+        .about("Build an index (or use a prebuilt one), bin reads against it and collapse the \
+                results in one go, recording every stage's parameters in OUTPUT_DIR/run.json.")
+        .arg(Arg::with_name("REFERENCE")
+            .long("reference")
+            .takes_value(true)
+            .help("Path to a FASTA database of references to build the index from, which may be \
+                   gzipped.")
+            .required_unless("INDEX")
+            .conflicts_with("INDEX"))
+        .arg(Arg::with_name("INDEX")
+            .short("i")
+            .long("index")
+            .takes_value(true)
+            .help("Path to a prebuilt MG-index, instead of building one with --reference."))
+        .arg(Arg::with_name("FASTA")
+            .short("f")
+            .long("fasta")
+            .help("Path(s) to FASTA reads, each binned to its own results file.")
+            .takes_value(true)
+            .multiple(true)
+            .required_unless("FASTQ")
+            .conflicts_with("FASTQ"))
+        .arg(Arg::with_name("FASTQ")
+            .long("fastq")
+            .help("Path(s) to FASTQ reads, as for --fasta.")
+            .takes_value(true)
+            .multiple(true))
+        .arg(Arg::with_name("OUTPUT_DIR")
+            .short("o")
+            .long("output-dir")
+            .takes_value(true)
+            .value_name("DIR")
+            .required(true)
+            .help("Directory to write the index, results, collapsed results and run.json to."))
+        .arg(Arg::with_name("NUM_THREADS")
+            .short("t")
+            .long("threads")
+            .takes_value(true)
+            .help("Number of worker threads to bin with and to build the index on, as for \
+            mtsv-build --threads.")
+            .default_value("4"))
+        .arg(Arg::with_name("SA_SAMPLE_RATE")
+            .long("sa-sample")
+            .takes_value(true)
+            .help("Suffix array sampling rate of the index built, as for mtsv-build.")
+            .default_value("32"))
+        .arg(Arg::with_name("FM_SAMPLE_INTERVAL")
+            .long("sample-interval")
+            .takes_value(true)
+            .help("BWT occurance sampling rate of the index built, as for mtsv-build.")
+            .default_value("64"))
+        .arg(Arg::with_name("HEADER_FORMAT")
+            .long("header-format")
+            .takes_value(true)
+            .possible_values(&["gi-taxid", "taxid"])
+            .help("Format of the references' FASTA headers, as for mtsv-build.")
+            .default_value("gi-taxid"))
+        .arg(Arg::with_name("EDIT_TOLERANCE")
+            .short("e")
+            .long("edit-rate")
+            .takes_value(true)
+            .help("The maximum proportion of edits allowed for alignment.")
+            .default_value("0.13"))
+        .arg(Arg::with_name("SEED_SIZE")
+            .long("seed-size")
+            .takes_value(true)
+            .help("Set seed size.")
+            .default_value("18"))
+        .arg(Arg::with_name("SEED_INTERVAL")
+            .long("seed-interval")
+            .takes_value(true)
+            .help("Set the interval between seeds used for initial exact match.")
+            .default_value("15"))
+        .arg(Arg::with_name("MIN_SEED")
+            .long("min-seed")
+            .takes_value(true)
+            .help("Set the minimum percentage of seeds required to perform an alignment.")
+            .default_value("0.015"))
+        .arg(Arg::with_name("MAX_HITS")
+            .long("max-hits")
+            .takes_value(true)
+            .help("Skip seeds with more than MAX_HITS hits.")
+            .default_value("20000"))
+        .arg(Arg::with_name("MAX_READ_LENGTH")
+            .long("max-read-length")
+            .takes_value(true)
+            .help("Skip reads longer than this many bases.")
+            .default_value("100000"))
+        .arg(Arg::with_name("SAMPLE_NAME")
+            .long("sample-name")
+            .takes_value(true)
+            .help("Name of the sample the reads came from, recorded in the results headers."))
+        .arg(Arg::with_name("SEED")
+            .long("seed")
+            .takes_value(true)
+            .help("Seed for all randomness in the run. A random seed is chosen and recorded if \
+            this isn't given, so that any run can be reproduced."))
+        .arg(Arg::with_name("VERBOSE")
+            .short("v")
+            .help("Include this flag to trigger debug-level logging."))
+        .arg(formats::format_versions_arg())
+        .get_matches();
+
+    // setup logger
+    util::init_logging(if args.is_present("VERBOSE") {
+        log::LogLevelFilter::Debug
+    } else {
+        log::LogLevelFilter::Info
+    });
+
+    let num_threads = args.value_of("NUM_THREADS")
+        .unwrap()
+        .parse::<usize>()
+        .expect("Invalid number entered for number of threads!");
+    if num_threads == 0 {
+        panic!("Number of threads must be at least 1");
+    }
+
+    let index = match args.value_of("REFERENCE") {
+        Some(reference) => {
+            let options = BuildOptions {
+                sample_interval: args.value_of("FM_SAMPLE_INTERVAL")
+                    .unwrap()
+                    .parse()
+                    .expect("Invalid occurance array sample interval entered!"),
+                suffix_sample: args.value_of("SA_SAMPLE_RATE")
+                    .unwrap()
+                    .parse()
+                    .expect("Invalid suffix array sample interval entered!"),
+                header_format: args.value_of("HEADER_FORMAT")
+                    .unwrap()
+                    .parse::<HeaderFormat>()
+                    .expect("Invalid header format entered!"),
+                threads: num_threads,
+                ..BuildOptions::default()
+            };
+            IndexSource::Build(reference.to_string(), options)
+        },
+        None => IndexSource::Prebuilt(args.value_of("INDEX").unwrap().to_string()),
+    };
+
+    let (input_paths, format) = match args.values_of("FASTA") {
+        Some(paths) => (paths.collect::<Vec<_>>(), ReadFormat::Fasta),
+        None => (args.values_of("FASTQ").unwrap().collect::<Vec<_>>(), ReadFormat::Fastq),
+    };
+
+    let max_hits = args.value_of("MAX_HITS")
+        .unwrap()
+        .parse::<usize>()
+        .expect("Invalid cutoff for max hits!");
+    let params = QueryParams {
+        edit_freq: args.value_of("EDIT_TOLERANCE")
+            .unwrap()
+            .parse()
+            .expect("Invalid edit tolerance entered!"),
+        seed_length: args.value_of("SEED_SIZE")
+            .unwrap()
+            .parse()
+            .expect("Invalid seed size entered!"),
+        seed_gap: args.value_of("SEED_INTERVAL")
+            .unwrap()
+            .parse()
+            .expect("Invalid seed interval entered!"),
+        min_seeds_percent: args.value_of("MIN_SEED")
+            .unwrap()
+            .parse()
+            .expect("Invalid minimum seed percentage entered!"),
+        max_hits,
+        tune_max_hits: cmp::max(max_hits / 100, 1),
+        ..QueryParams::default()
+    };
+    if let Err(why) = params.validate() {
+        panic!("{}", why);
+    }
+
+    let sample_name = args.value_of("SAMPLE_NAME").map(|s| {
+        if s.is_empty() || s.contains(|c: char| c.is_whitespace() || c == ':') {
+            panic!("Sample name must be non-empty without whitespace or ':'");
+        }
+        s.to_string()
+    });
+    let seed = args.value_of("SEED").map(|s| s.parse::<u64>().expect("Invalid seed entered!"));
+    let config = BinnerConfig {
+        sample_name,
+        max_read_length: Some(args.value_of("MAX_READ_LENGTH")
+            .unwrap()
+            .parse()
+            .expect("Invalid max read length entered!")),
+        run: RunContext::new(seed),
+        ..BinnerConfig::default()
+    };
+
+    let exit_code = match run_workflow(&index,
+                                       &input_paths,
+                                       format,
+                                       args.value_of("OUTPUT_DIR").unwrap(),
+                                       num_threads,
+                                       &params,
+                                       &config) {
+        Ok(_) => 0,
+        Err(why) => {
+            error!("Error running mtsv: {}", why);
+            2
+        },
+    };
+
+    std::process::exit(exit_code);
+}
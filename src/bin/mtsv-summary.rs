@@ -0,0 +1,101 @@
+#[macro_use]
+extern crate log;
+
+extern crate clap;
+extern crate mtsv;
+
+use clap::{App, Arg};
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+
+use mtsv::MtsvResult;
+use mtsv::formats;
+use mtsv::io::open_maybe_gz;
+use mtsv::summary::{load_manifest, reference_bias, write_bias_report};
+use mtsv::util;
+
+fn main() {
+    if formats::format_versions_requested() {
+        println!("{}", formats::format_versions());
+        return;
+    }
+
+    let args = App::new("mtsv-summary")
+        .version(env!("CARGO_PKG_VERSION"))
+        .author(env!("CARGO_PKG_AUTHORS"))
+        .about("Report the reads hitting each taxid, normalized by the taxid's reference size.")
+        .arg(Arg::with_name("RESULTS")
+            .index(1)
+            .help("Path to the mtsv results file to summarize (optionally gzipped).")
+            .takes_value(true)
+            .required(true))
+        .arg(Arg::with_name("MANIFEST")
+            .long("manifest")
+            .takes_value(true)
+            .required(true)
+            .multiple(true)
+            .number_of_values(1)
+            .help("Path to an index manifest from mtsv-inspect --manifest. May be repeated for \
+            the chunks of a database."))
+        .arg(Arg::with_name("OUTPUT")
+            .short("o")
+            .long("output")
+            .takes_value(true)
+            .help("Path to write the report to. Written to stdout if omitted."))
+        .arg(Arg::with_name("VERBOSE")
+            .short("v")
+            .help("Include this flag to trigger debug-level logging."))
+        .arg(formats::format_versions_arg())
+        .get_matches();
+
+    // setup logger
+    util::init_logging(if args.is_present("VERBOSE") {
+        log::LogLevelFilter::Debug
+    } else {
+        log::LogLevelFilter::Info
+    });
+
+    match run(args.value_of("RESULTS").unwrap(),
+              &args.values_of("MANIFEST").unwrap().collect::<Vec<_>>(),
+              args.value_of("OUTPUT")) {
+        Ok(()) => (),
+        Err(why) => {
+            error!("Problem summarizing results: {}", why);
+            std::process::exit(2);
+        },
+    }
+}
+
+fn run(results_path: &str, manifest_paths: &[&str], output_path: Option<&str>) -> MtsvResult<()> {
+    let mut manifest = load_manifest(manifest_paths[0])?;
+    for path in &manifest_paths[1..] {
+        for (tax_id, composition) in load_manifest(path)? {
+            let total = manifest.entry(tax_id).or_default();
+            total.sequences += composition.sequences;
+            total.bases += composition.bases;
+        }
+    }
+    info!("Loaded reference sizes for {} taxids", manifest.len());
+
+    let (rows, missing) = reference_bias(open_maybe_gz(results_path)?, &manifest)?;
+    if !missing.is_empty() {
+        warn!("{} taxids with hits aren't in the manifest and were left out: {}",
+              missing.len(),
+              missing.iter().map(|t| t.0.to_string()).collect::<Vec<_>>().join(","));
+    }
+
+    let size_driven = rows.iter().filter(|r| r.size_driven).count();
+    info!("{} of {} taxids have hit counts explained largely by reference size",
+          size_driven,
+          rows.len());
+
+    let mut writer: Box<dyn Write> = match output_path {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(io::stdout()),
+    };
+    write_bias_report(&rows, &mut writer)?;
+    writer.flush()?;
+
+    Ok(())
+}
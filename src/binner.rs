@@ -2,444 +2,3894 @@
 //! of queries along with writing results.
 
 use bio::alphabets::dna::revcomp;
+use chrono::Local;
 use bio::io::{fasta, fastq};
-use cue::pipeline;
-use bio::data_structures::fmindex::{FMIndex};
+use collapse::collapse_edit_files;
+use convert::convert_findings;
+use cue::{pipeline_with_capacity, QUEUE_PER_WORKER};
 
 use error::*;
-use index::{MGIndex, TaxId, Hit};
-use io::from_file;
-use std::collections::{BTreeSet, HashMap};
-use std::fs::File;
-use std::io::{BufWriter, Write};
-use std::path::Path;
-use std::process::exit;
+use index::{Alphabet, AmbiguityPolicy, IndexCapabilities, IndexSearcher, LoadedIndex, TaxId,
+            Hit, QueryParams, QueryStats, QueryStrand};
+use io::{check_fastq_record, check_read_id, escape_read_id, from_file, from_file_unverified,
+         open_findings, sanitize_read_id, ControlCharPolicy, FindingsField, FindingsVersion,
+         FindingsWriter, NameResolver, ResultsHeader, TaxidMap, V2_FIELDS};
+use memory::{check_index_sizes, queue_capacity, ProcessMemory, Throttle, ASSUMED_READ_LENGTH};
+use prep::{is_low_complexity, mask_low_complexity, DustParams};
+use prescreen::{prescreen_path, Prescreen};
+use run::RunContext;
+use score::retain_confident_hits;
+use serde::{Serialize, Deserialize};
+use serde_json;
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::cmp;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Instant;
 use stopwatch::Stopwatch;
+use util::reference_header;
+
+/// Reads longer than this are always skipped, whatever `BinnerConfig::max_read_length` is, since
+/// aligning them would need enormous candidate windows and alignment profiles.
+pub const READ_LENGTH_LIMIT: usize = 1_000_000;
+
+/// Options for a binning run, other than how each read is queried against the index (see
+/// `QueryParams`).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BinnerConfig {
+    /// Flush the results file after every N reads, if set. Otherwise results are only flushed
+    /// when the write buffer fills and at the end of the run.
+    pub flush_every: Option<usize>,
+    /// Sync the results file to disk before returning.
+    pub fsync_on_close: bool,
+    /// The sample name to record in the results header, if any.
+    pub sample_name: Option<String>,
+    /// Skip reads longer than this, if set. Reads longer than `READ_LENGTH_LIMIT` are skipped
+    /// regardless.
+    pub max_read_length: Option<usize>,
+    /// Skip reads whose proportion of N bases (after normalization) is greater than this.
+    pub max_n_fraction: Option<f64>,
+    /// The DUST parameters to find low-complexity reads with, if filtering them.
+    pub low_complexity: Option<DustParams>,
+    /// Mask the low-complexity windows of a read to N rather than skipping the read.
+    pub mask_low_complexity: bool,
+    /// Path to write skipped reads to as FASTA, with the reason in the description. Reads
+    /// skipped for matching the exclusion index aren't written here.
+    pub rejects_path: Option<String>,
+    /// Path to an index of sequences (e.g. the host's) to deplete, if set. Reads with a hit in
+    /// it are skipped rather than binned.
+    pub exclusion_index: Option<String>,
+    /// Maximum proportion of the read length allowed as edits for a hit in the exclusion index.
+    pub exclusion_edit_rate: f64,
+    /// Path to write reads skipped for matching the exclusion index to as FASTA, if set.
+    pub excluded_reads_path: Option<String>,
+    /// Drop hits whose confidence score (see `score::confidence`) is less than this.
+    pub min_confidence: Option<f32>,
+    /// Flush the results and write a `Checkpoint` next to them after every N reads, if set.
+    pub checkpoint_every: Option<usize>,
+    /// Sync each checkpoint to disk before it replaces the previous one.
+    pub fsync_checkpoints: bool,
+    /// Relabel the taxids of hits before they're written, if set.
+    pub taxid_map: Option<TaxidMap>,
+    /// Also write the hits for each taxid to `taxid_<id>.txt` in this directory, if set.
+    pub shard_dir: Option<String>,
+    /// Don't write the combined results file (only useful with `shard_dir`).
+    pub no_combined: bool,
+    /// What to do with control characters in read IDs. This applies to every file the IDs are
+    /// written to, before any escaping.
+    pub control_chars: ControlCharPolicy,
+    /// Escape read IDs in the results and shard files (see `io::escape_read_id`), so that any
+    /// read ID can be written.
+    pub escape_ids: bool,
+    /// What to do with reads whose ID was already used by an earlier read for the same results
+    /// file.
+    pub duplicate_ids: DuplicateIdPolicy,
+    /// Stop with an error at FASTQ records whose sequence and quality lengths differ, rather than
+    /// skipping them.
+    pub strict_reads: bool,
+    /// Format of the results file (see `io::FindingsVersion`).
+    pub findings_version: FindingsVersion,
+    /// Write at most this many taxids on a read's line, if set, with an overflow marker for the
+    /// rest (see `io::FindingsWriter::with_max_taxids`).
+    pub max_line_taxids: Option<usize>,
+    /// The run's random seed.
+    pub run: RunContext,
+    /// Don't use an index's prescreen, even if it has one.
+    pub no_prescreen: bool,
+    /// Don't query the reverse strand of reads with at least this many hits on the forward
+    /// strand, if set. This saves time on reads which match many taxids, but misses any taxids
+    /// they only match on the reverse strand.
+    pub skip_reverse_after: Option<usize>,
+    /// Fail before binning if any index doesn't have a prescreen which can be used, rather than
+    /// querying every read.
+    pub require_prescreen: bool,
+    /// Append a TSV row to this file for each read whose query takes at least
+    /// `slow_read_threshold_ms`, if set.
+    pub slow_read_log: Option<String>,
+    /// Queries taking at least this many milliseconds are counted as slow, and written to
+    /// `slow_read_log`. Only used if `slow_read_log` is set.
+    pub slow_read_threshold_ms: u64,
+    /// Write the run's `EditHistogram` to this file as TSV at the end of the run, if set.
+    pub edit_histogram: Option<String>,
+    /// With several reads files, take a read from each in turn rather than reading them one
+    /// after another, so that every file's results fill in from the start of the run.
+    pub interleave_inputs: bool,
+    /// Keep the run under this many bytes of memory, if set (see `memory`): refuse to start if
+    /// the index files are bigger, queue fewer reads if the memory left over is short, and stop
+    /// taking reads until those already taken are written while the process is near the limit.
+    pub memory_limit: Option<u64>,
+    /// Add the accession of each hit's reference to v2 results (see `io::FindingsField`), from
+    /// an index built with accessions.
+    pub emit_accessions: bool,
+    /// Don't check the indices and prescreens against their checksums as they're loaded (see
+    /// `MGIndex::from_file_unverified`), which saves hashing them.
+    pub no_verify: bool,
+}
 
-/// Execute metagenomic binning queries in parallel.
-///
-/// This function:
-///
-/// 1. Opens the FASTA file with query reads
-/// 2. Creates the results file to write to
-/// 3. Deserializes the metagenomic index into memory
-/// 4. In parallel queries for which taxonomic IDs have a match to the query read within the edit
-/// distance specified.
-/// 5. Writes those results to the output file as they become available.
-///
-/// `seed_size` controls how large initial exact matches should be.
+/// What to do with a read whose ID was already used by an earlier read for the same results file.
 ///
-/// `seed_gap` controls how far apart the seeds pulled from the query read should be.
-///
-/// `min_seeds` scales the minimum number of seeds calculated using q-gram lemma.
-///
-/// 'max_hits' is a cutoff for skipping seeds with more than max_hits hits.
-///
-///  
-/// TODO: Replace separate functions once FASTX is implemented, currently awaiting review on pull request #433
-pub fn get_fasta_and_write_matching_bin_ids(input_path: &str,
-                                            index_path: &str,
-                                            results_path: &str,
-                                            num_threads: usize,
-                                            edit_distance: f64,
-                                            seed_size: usize,
-                                            seed_gap: usize,
-                                            min_seeds: f64,
-                                            max_hits: usize,
-                                            tune_max_hits: usize)
-                                            -> MtsvResult<()> {
-
-    let mut fasta_reader = fasta::Reader::from_file(Path::new(input_path))?;
-    fasta_reader.records().next().unwrap()?;
-
-    info!("Test parse of FASTA record successful, reinitializing parser.");
-    fasta_reader = fasta::Reader::from_file(Path::new(input_path))?;
-    let output_file = File::create(Path::new(results_path))?;
-    info!("Deserializing candidate filter ...");
-    let filter = from_file::<MGIndex>(index_path)?;
-    let fmindex = FMIndex::new(
-        filter.suffix_array.bwt(),
-        filter.suffix_array.less(),
-        filter.suffix_array.occ());
-
-    let mut result_writer = BufWriter::new(output_file);
-    
-    info!("Beginning queries.");
+/// Duplicates are found in the order the reads are read, so the outcome doesn't depend on the
+/// number of threads. Only a hash of each ID is kept (see `ReadIds`).
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum DuplicateIdPolicy {
+    /// Write the second read with an ID as `READ_ID#2`, the third as `READ_ID#3`, and so on. A
+    /// suffixed ID which is itself taken gets another suffix.
+    #[default]
+    Suffix,
+    /// Write a single line for the ID, with the best hit for each taxid from any of its reads.
+    /// The merged lines are written at the end of the results file once binning has finished, so
+    /// this needs the combined results file.
+    Merge,
+    /// Stop with an error.
+    Error,
+}
 
-    let timer = Stopwatch::start_new();
+impl FromStr for DuplicateIdPolicy {
+    type Err = MtsvError;
+
+    fn from_str(s: &str) -> MtsvResult<Self> {
+        match s {
+            "suffix" => Ok(DuplicateIdPolicy::Suffix),
+            "merge" => Ok(DuplicateIdPolicy::Merge),
+            "error" => Ok(DuplicateIdPolicy::Error),
+            _ => {
+                Err(MtsvError::InvalidParameter(format!("unknown duplicate read ID policy {:?}",
+                                                        s)))
+            },
+        }
+    }
+}
 
+/// The read IDs seen for a results file, for applying a `DuplicateIdPolicy`.
+///
+/// Only a 64-bit hash of each ID is kept, plus the IDs which turned out to be duplicated, so
+/// memory doesn't grow with the length of the IDs. Two different IDs with the same hash would be
+/// taken for duplicates, but that's vanishingly unlikely at any realistic number of reads.
+#[derive(Debug, Default)]
+struct ReadIds {
+    seen: HashSet<u64>,
+    /// How many reads have had each duplicated ID.
+    duplicated: BTreeMap<String, usize>,
+}
 
-    pipeline("taxonomic binning",
-             num_threads,
-             fasta_reader.records(),
-             |record| {
+impl ReadIds {
+    /// Record a read's ID, returning the ID to write its results under and whether it was a
+    /// duplicate.
+    fn check(&mut self, id: String, policy: DuplicateIdPolicy) -> MtsvResult<(String, bool)> {
+        let mut hasher = DefaultHasher::new();
+        id.hash(&mut hasher);
+        if self.seen.insert(hasher.finish()) {
+            return Ok((id, false));
+        }
 
-        let record = match record {
-            Ok(r) => r,
-            Err(why) => {
-                error!("Unable to read from input file: {:?}", why);
-                exit(12);
+        let count = self.duplicated.entry(id.clone()).or_insert(1);
+        *count += 1;
+        match policy {
+            DuplicateIdPolicy::Suffix => {
+                let suffixed = format!("{}#{}", id, count);
+                Ok((self.check(suffixed, policy)?.0, true))
             },
-        };
-
+            DuplicateIdPolicy::Merge => Ok((id, true)),
+            DuplicateIdPolicy::Error => Err(MtsvError::DuplicateReadId(id)),
+        }
+    }
+}
 
-        // convert any lowercase items to uppercase (a <-> A isn't a SNP)
-        let seq_all_caps = record.seq()
-            .iter()
-            .map(|b| {
-                match *b {
-                    b'A' | b'a' => b'A',
-                    b'C' | b'c' => b'C',
-                    b'G' | b'g' => b'G',
-                    b'T' | b't' => b'T',
-                    b'N' | b'n' => b'N',
-                    _ => b'N',
-                }
-            })
-            .collect::<Vec<u8>>();
-        
-        
-
-        let hits = filter.matching_tax_ids(
-                                        &fmindex,
-                                        &seq_all_caps,
-                                        edit_distance,
-                                        seed_size,
-                                        seed_gap,
-                                        min_seeds,
-                                        max_hits,
-                                        tune_max_hits);
-
-
-        // get the reverse complement
-        let rev_comp_seq = revcomp(&seq_all_caps);
-        let rev_hits = filter.matching_tax_ids(
-                                        &fmindex,
-                                        &rev_comp_seq,
-                                        edit_distance,
-                                        seed_size,
-                                        seed_gap,
-                                        min_seeds,
-                                        max_hits,
-                                        tune_max_hits);
-
-        // unify the result sets
-
-        // let results = candidates.into_iter().chain(rev_comp_candidates.into_iter()).collect::<BTreeSet<_>>();
-        let edit_distances: Vec<Hit> = hits.into_iter().chain(rev_hits.into_iter()).collect();
-
-        (record.id().to_owned(), edit_distances)
-    },
-             |(header, edit_distances)| {
+/// Why a read was skipped rather than queried against the index.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum RejectReason {
+    /// The read was longer than the maximum read length.
+    TooLong,
+    /// The read had more than `BinnerConfig::max_n_fraction` N bases.
+    NContent,
+    /// Part of the read was low-complexity according to DUST.
+    LowComplexity,
+    /// The read had a hit in the exclusion index.
+    Excluded,
+    /// The read had a DNA ambiguity code, with `AmbiguityPolicy::Reject`.
+    Ambiguous,
+}
 
-        match write_edit_distances(&header, &edit_distances, &mut result_writer) {
-            Ok(_) => (),
-            Err(why) => {
-                error!("Error writing to result file ({})", why);
-                exit(11);
-            },
+impl RejectReason {
+    /// The name recorded for this reason in the rejects file.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            RejectReason::TooLong => "too_long",
+            RejectReason::NContent => "n_content",
+            RejectReason::LowComplexity => "low_complexity",
+            RejectReason::Excluded => "excluded",
+            RejectReason::Ambiguous => "ambiguous",
         }
-    });
+    }
+}
 
-    info!("All worker and result consumer threads terminated. Took {} seconds.",
-          timer.elapsed_ms() as f32 / 1000.0);
-    Ok(())
+/// Counters for a binning run.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct RunStats {
+    /// Number of reads processed, whether or not they were queried.
+    pub reads: usize,
+    /// Number of reads processed from each reads file, in the order they were given.
+    #[serde(default)]
+    pub input_reads: Vec<usize>,
+    /// Length of the longest read seen, whether or not it was queried.
+    pub longest_read: usize,
+    /// Number of reads skipped for being too long.
+    pub rejected_too_long: usize,
+    /// Number of reads skipped for their N content.
+    pub rejected_n_content: usize,
+    /// Number of reads skipped for being low-complexity.
+    pub rejected_low_complexity: usize,
+    /// Number of reads skipped for matching the exclusion index.
+    #[serde(default)]
+    pub rejected_excluded: usize,
+    /// Number of reads skipped for having ambiguity codes.
+    #[serde(default)]
+    pub rejected_ambiguous: usize,
+    /// Number of FASTQ records skipped because their sequence and quality lengths differ. These
+    /// aren't counted in `reads`.
+    pub rejected_mismatched_quality: usize,
+    /// Number of reads which had low-complexity windows masked before querying.
+    pub masked_low_complexity: usize,
+    /// Number of hits dropped for being below the minimum confidence.
+    pub hits_below_confidence: usize,
+    /// Number of hits written with their original taxid because it wasn't in the taxid map.
+    pub unmapped_taxid_hits: usize,
+    /// Number of taxid shard files written.
+    pub shard_files: usize,
+    /// Number of reads whose query took at least `BinnerConfig::slow_read_threshold_ms`.
+    pub slow_reads: usize,
+    /// Number of reads which reached `QueryParams::max_seed_hits_per_read` on either strand, so
+    /// some of their seeds weren't searched.
+    #[serde(default)]
+    pub seed_hit_limited_reads: usize,
+    /// Number of reads whose IDs had control characters replaced.
+    #[serde(default)]
+    pub sanitized_read_ids: usize,
+    /// Number of reads whose ID was already used by an earlier read (see
+    /// `BinnerConfig::duplicate_ids`).
+    #[serde(default)]
+    pub duplicate_read_ids: usize,
+    /// The edit distances of the hits written.
+    #[serde(default)]
+    pub edits: EditHistogram,
+    /// Counters for the queries against the index.
+    pub query: QueryStats,
 }
 
-/// Execute metagenomic binning queries in parallel.
-///
-/// This function:
-///
-/// 1. Opens the FASTQ file with query reads
-/// 2. Creates the results file to write to
-/// 3. Deserializes the metagenomic index into memory
-/// 4. In parallel queries for which taxonomic IDs have a match to the query read within the edit
-/// distance specified.
-/// 5. Writes those results to the output file as they become available.
-///
-/// `seed_size` controls how large initial exact matches should be.
-///
-/// `seed_gap` controls how far apart the seeds pulled from the query read should be.
-///
-/// `min_seeds` scales the minimum number of seeds calculated using q-gram lemma.
-///
-/// 'max_hits' is a cutoff for skipping seeds with more than max_hits hits.
-///
-///  
-/// TODO: Replace separate functions once FASTX is implemented, currently awaiting review on pull request #433   
-pub fn get_fastq_and_write_matching_bin_ids(input_path: &str,
-                                            index_path: &str,
-                                            results_path: &str,
-                                            num_threads: usize,
-                                            edit_distance: f64,
-                                            seed_size: usize,
-                                            seed_gap: usize,
-                                            min_seeds: f64,
-                                            max_hits: usize,
-                                            tune_max_hits: usize)
-                                            -> MtsvResult<()> {
+impl RunStats {
+    /// Add the counters from another set of stats to this one.
+    pub fn add(&mut self, other: &RunStats) {
+        self.reads += other.reads;
+        if self.input_reads.len() < other.input_reads.len() {
+            self.input_reads.resize(other.input_reads.len(), 0);
+        }
+        for (reads, other_reads) in self.input_reads.iter_mut().zip(&other.input_reads) {
+            *reads += *other_reads;
+        }
+        self.longest_read = cmp::max(self.longest_read, other.longest_read);
+        self.rejected_too_long += other.rejected_too_long;
+        self.rejected_n_content += other.rejected_n_content;
+        self.rejected_low_complexity += other.rejected_low_complexity;
+        self.rejected_excluded += other.rejected_excluded;
+        self.rejected_ambiguous += other.rejected_ambiguous;
+        self.rejected_mismatched_quality += other.rejected_mismatched_quality;
+        self.masked_low_complexity += other.masked_low_complexity;
+        self.hits_below_confidence += other.hits_below_confidence;
+        self.unmapped_taxid_hits += other.unmapped_taxid_hits;
+        self.shard_files += other.shard_files;
+        self.slow_reads += other.slow_reads;
+        self.seed_hit_limited_reads += other.seed_hit_limited_reads;
+        self.sanitized_read_ids += other.sanitized_read_ids;
+        self.duplicate_read_ids += other.duplicate_read_ids;
+        self.edits.add(&other.edits);
+        self.query.add(&other.query);
+    }
 
-    let mut fastq_reader = fastq::Reader::from_file(Path::new(input_path))?;
-    fastq_reader.records().next().unwrap()?;
-
-    info!("Test parse of FASTQ record successful, reinitializing parser.");
-    fastq_reader = fastq::Reader::from_file(Path::new(input_path))?;
-    let output_file = File::create(Path::new(results_path))?;
-    info!("Deserializing candidate filter ...");
-    let filter = from_file::<MGIndex>(index_path)?;
-    let fmindex = FMIndex::new(
-        filter.suffix_array.bwt(),
-        filter.suffix_array.less(),
-        filter.suffix_array.occ());
-
-    let mut result_writer = BufWriter::new(output_file);
-    
-    info!("Beginning queries.");
+    /// Add the counters from another pass over the same reads against another index. The reads
+    /// and the filters they went through are the same, so only the queries and hits are added.
+    fn add_pass(&mut self, other: &RunStats) {
+        self.hits_below_confidence += other.hits_below_confidence;
+        self.unmapped_taxid_hits += other.unmapped_taxid_hits;
+        self.slow_reads += other.slow_reads;
+        self.seed_hit_limited_reads += other.seed_hit_limited_reads;
+        self.edits.add(&other.edits);
+        self.query.add(&other.query);
+    }
 
-    let timer = Stopwatch::start_new();
+    fn reject(&mut self, reason: RejectReason) {
+        match reason {
+            RejectReason::TooLong => self.rejected_too_long += 1,
+            RejectReason::NContent => self.rejected_n_content += 1,
+            RejectReason::LowComplexity => self.rejected_low_complexity += 1,
+            RejectReason::Excluded => self.rejected_excluded += 1,
+            RejectReason::Ambiguous => self.rejected_ambiguous += 1,
+        }
+    }
+}
 
+/// Histograms of the edit distances in a run's results, for choosing the edit rate.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct EditHistogram {
+    /// Number of reads whose best hit had each edit distance.
+    pub best: Vec<usize>,
+    /// Number of hits with each edit distance.
+    pub hits: Vec<usize>,
+    /// Number of reads with no hits, including skipped reads.
+    pub no_hits: usize,
+}
+
+impl EditHistogram {
+    /// Count the hits written for one read.
+    pub fn add_read(&mut self, hits: &[Hit]) {
+        match hits.iter().map(|h| h.edit).min() {
+            Some(best) => increment_bin(&mut self.best, best as usize, 1),
+            None => self.no_hits += 1,
+        }
+        for hit in hits {
+            increment_bin(&mut self.hits, hit.edit as usize, 1);
+        }
+    }
 
-    pipeline("taxonomic binning",
-             num_threads,
-             fastq_reader.records(),
-             |record| {
+    /// Add the counts from another histogram to this one.
+    pub fn add(&mut self, other: &EditHistogram) {
+        for (edit, &count) in other.best.iter().enumerate() {
+            increment_bin(&mut self.best, edit, count);
+        }
+        for (edit, &count) in other.hits.iter().enumerate() {
+            increment_bin(&mut self.hits, edit, count);
+        }
+        self.no_hits += other.no_hits;
+    }
 
-        let record = match record {
-            Ok(r) => r,
-            Err(why) => {
-                error!("Unable to read from input file: {:?}", why);
-                exit(12);
-            },
-        };
+    /// Write an `edits`, `reads`, `hits` TSV row for every edit distance up to `max_edit`, or
+    /// the largest one counted if that's larger, after a header line.
+    pub fn write_tsv<W: Write>(&self, writer: &mut W, max_edit: usize) -> MtsvResult<()> {
+        let count = |bins: &[usize], edit: usize| bins.get(edit).cloned().unwrap_or(0);
+        let rows = cmp::max(max_edit + 1, cmp::max(self.best.len(), self.hits.len()));
 
+        writeln!(writer, "edits\treads\thits")?;
+        for edit in 0..rows {
+            writeln!(writer, "{}\t{}\t{}", edit, count(&self.best, edit), count(&self.hits, edit))?;
+        }
+        Ok(())
+    }
 
-        // convert any lowercase items to uppercase (a <-> A isn't a SNP)
-        let seq_all_caps = record.seq()
+    /// A sparkline of the reads with each best edit distance, from 0 to the largest one counted.
+    pub fn sparkline(&self) -> String {
+        const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+        let max = self.best.iter().cloned().max().unwrap_or(0);
+        self.best
             .iter()
-            .map(|b| {
-                match *b {
-                    b'A' | b'a' => b'A',
-                    b'C' | b'c' => b'C',
-                    b'G' | b'g' => b'G',
-                    b'T' | b't' => b'T',
-                    b'N' | b'n' => b'N',
-                    _ => b'N',
-                }
+            .map(|&count| match count {
+                0 => ' ',
+                _ => BARS[(count * BARS.len()).div_ceil(max) - 1],
             })
-            .collect::<Vec<u8>>();
-        
-        
-
-        let hits = filter.matching_tax_ids(
-                                        &fmindex,
-                                        &seq_all_caps,
-                                        edit_distance,
-                                        seed_size,
-                                        seed_gap,
-                                        min_seeds,
-                                        max_hits,
-                                        tune_max_hits);
-
-
-        // get the reverse complement
-        let rev_comp_seq = revcomp(&seq_all_caps);
-        let rev_hits = filter.matching_tax_ids(
-                                            &fmindex,
-                                            &rev_comp_seq,
-                                            edit_distance,
-                                            seed_size,
-                                            seed_gap,
-                                            min_seeds,
-                                            max_hits,
-                                            tune_max_hits);
-
-        // unify the result sets
-
-        // let results = candidates.into_iter().chain(rev_comp_candidates.into_iter()).collect::<BTreeSet<_>>();
-        let edit_distances: Vec<Hit> = hits.into_iter().chain(rev_hits.into_iter()).collect();
-
-        (record.id().to_owned(), edit_distances)
-    },
-             |(header, edit_distances)| {
-        // again, if we can't write to the results file, just report it and bail
+            .collect()
+    }
+}
 
-        match write_edit_distances(&header, &edit_distances, &mut result_writer) {
-            Ok(_) => (),
-            Err(why) => {
-                error!("Error writing to result file ({})", why);
-                exit(11);
-            },
+fn increment_bin(bins: &mut Vec<usize>, bin: usize, count: usize) {
+    if bins.len() <= bin {
+        bins.resize(bin + 1, 0);
+    }
+    bins[bin] += count;
+}
+
+/// Progress of a binning run, written as JSON to `checkpoint_path(results_path)`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// Number of reads processed, all of which have been flushed to the results file.
+    pub reads_processed: usize,
+    /// ID of the last read processed, if any.
+    pub last_read_id: Option<String>,
+    /// Whether the run had finished when the checkpoint was written.
+    pub finished: bool,
+    /// Whether each reads file, in the order they were given, had been read to the end with all
+    /// of its reads processed.
+    #[serde(default)]
+    pub inputs_finished: Vec<bool>,
+    /// Local time the checkpoint was written, in RFC 3339 format.
+    pub timestamp: String,
+    /// Counters for the run so far.
+    pub stats: RunStats,
+    /// The run's random seed, to reproduce it with `--seed`.
+    pub seed: u64,
+}
+
+impl Checkpoint {
+    fn new(stats: &RunStats,
+           last_read_id: &Option<String>,
+           inputs_finished: Vec<bool>,
+           finished: bool,
+           run: &RunContext)
+           -> Self {
+        Checkpoint {
+            reads_processed: stats.reads,
+            last_read_id: last_read_id.clone(),
+            finished,
+            inputs_finished,
+            timestamp: Local::now().to_rfc3339(),
+            stats: stats.clone(),
+            seed: run.seed,
         }
-    });
+    }
 
-    info!("All worker and result consumer threads terminated. Took {} seconds.",
-          timer.elapsed_ms() as f32 / 1000.0);
-    Ok(())
+    /// Replace the checkpoint at `path`, by writing to a temporary file and renaming it so that
+    /// readers never see a partial checkpoint.
+    pub fn write(&self, path: &str, fsync: bool) -> MtsvResult<()> {
+        let tmp_path = format!("{}.tmp", path);
+        {
+            let mut file = File::create(&tmp_path)?;
+            serde_json::to_writer_pretty(&mut file, self)?;
+            file.write_all(b"\n")?;
+            if fsync {
+                file.sync_all()?;
+            }
+        }
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    /// Read the checkpoint at `path`.
+    pub fn read(path: &str) -> MtsvResult<Self> {
+        Ok(serde_json::from_reader(BufReader::new(File::open(path)?))?)
+    }
 }
-    
 
+/// Where the checkpoints for a results file are written.
+pub fn checkpoint_path(results_path: &str) -> String {
+    format!("{}.checkpoint", results_path)
+}
 
+/// The result of processing a single read.
+enum ReadOutcome {
+    /// The read was queried, with these hits, taking this many milliseconds. The counters are
+    /// boxed to keep the outcomes passed between threads small.
+    Queried(String, Vec<Hit>, Box<RunStats>, f64),
+    /// The read was skipped, and here's the original sequence.
+    Rejected(String, Vec<u8>, RejectReason),
+}
 
+/// Header line of the slow read log (see `BinnerConfig::slow_read_log`).
+const SLOW_READ_LOG_HEADER: &str =
+    "read_id\tlength\tms\tseeds_searched\tcandidates_aligned\thits\treverse_skipped";
+
+/// Open the slow read log for appending, writing the header line if the file is new or empty.
+fn open_slow_read_log(path: &str) -> MtsvResult<BufWriter<File>> {
+    let file = OpenOptions::new().append(true).create(true).open(path)?;
+    let is_empty = file.metadata()?.len() == 0;
+    let mut writer = BufWriter::new(file);
+    if is_empty {
+        writeln!(writer, "{}", SLOW_READ_LOG_HEADER)?;
+    }
+    Ok(writer)
+}
 
-/// Write the results for a single query read to the Writer specified.
-///
-/// Writes in the format `READ_ID:TAX_ID1,TAX_ID2,...`. Read header/ID is first, followed by a
-/// colon (':'), followed by a comma-separated list of taxonomic IDs (positive integers).
-pub fn write_single_line<W: Write>(header: &str,
-                                   matches: &BTreeSet<TaxId>,
-                                   writer: &mut W)
-                                   -> MtsvResult<()> {
-    if matches.len() == 0 {
-        return Ok(());
+/// Buffered writer for binning results which flushes at a fixed cadence of reads.
+pub struct ResultWriter<W: Write> {
+    writer: FindingsWriter<BufWriter<W>>,
+    flush_every: Option<usize>,
+    reads_since_flush: usize,
+}
+
+impl<W: Write> ResultWriter<W> {
+    /// Wrap `inner`, flushing after every `flush_every` reads if set.
+    pub fn new(inner: W, flush_every: Option<usize>) -> Self {
+        ResultWriter {
+            writer: FindingsWriter::new(BufWriter::new(inner)),
+            flush_every,
+            reads_since_flush: 0,
+        }
     }
 
-    let mut result_line = String::from(header);
-    result_line.push(':');
+    /// Write at most `max_taxids` taxids on each line, if set (see
+    /// `FindingsWriter::with_max_taxids`).
+    pub fn with_max_taxids(mut self, max_taxids: Option<usize>) -> Self {
+        self.writer = self.writer.with_max_taxids(max_taxids);
+        self
+    }
 
-    let mut matches_peek = matches.iter().peekable();
-    for tax_id in matches {
-        let _ = matches_peek.next();
+    /// Write the names of the hits' references (see `FindingsWriter::with_names`).
+    pub fn with_names(mut self, names: Arc<NameResolver>) -> Self {
+        self.writer = self.writer.with_names(names);
+        self
+    }
 
-        result_line.push_str(&tax_id.0.to_string());
+    /// Write the provenance header, before any results. Results are written in the header's
+    /// format from then on, e.g. with escaped read IDs if it says they are.
+    pub fn write_header(&mut self, header: &ResultsHeader) -> MtsvResult<()> {
+        self.writer.write_header(header)
+    }
 
-        if let Some(_) = matches_peek.peek() {
-            result_line.push(',');
+    /// Write the results for a single read. Every read counts towards the flush cadence, even if
+    /// it had no hits and nothing was written.
+    pub fn write_hits(&mut self, header: &str, hits: &[Hit]) -> MtsvResult<()> {
+        self.writer.write_hits(header, hits)?;
+        self.reads_since_flush += 1;
+
+        if let Some(n) = self.flush_every {
+            if self.reads_since_flush >= n {
+                self.writer.get_mut().flush()?;
+                self.reads_since_flush = 0;
+            }
         }
+        Ok(())
+    }
+
+    /// Flush any buffered results, restarting the flush cadence.
+    pub fn flush(&mut self) -> MtsvResult<()> {
+        self.writer.get_mut().flush()?;
+        self.reads_since_flush = 0;
+        Ok(())
+    }
+
+    /// Flush any buffered results and return the underlying writer.
+    pub fn finish(self) -> MtsvResult<W> {
+        let mut writer = self.writer.into_inner();
+        writer.flush()?;
+        writer.into_inner().map_err(|e| MtsvError::from(e.into_error()))
     }
-    result_line.push('\n');
-    writer.write(result_line.as_bytes())?;
-    Ok(())
 }
 
-/// Get all reference sequences for given taxid from index
+/// Most shard files a `ShardWriter` keeps open at once.
+pub(crate) const MAX_OPEN_SHARDS: usize = 256;
+
+/// The "too many open files" error number on Linux and macOS.
+const EMFILE: i32 = 24;
+
+/// Writes each hit to a file for its taxid, as `READ_ID=EDIT` lines, optionally with escaped read
+/// IDs (see `io::escape_read_id`).
 ///
-/// Writes to fasta file with headers ID-TAXID
-pub fn get_reference_sequences_from_index(
-    index_path: &str,
-    results_path: &str,
-    taxids: Vec<u32>) -> MtsvResult<()> {
-     
-    let output_file = File::create(Path::new(results_path))?;
-
-    info!("Deserializing candidate filter: {}", index_path);
-    let filter = from_file::<MGIndex>(index_path)?;
-    let result_writer = BufWriter::new(output_file);
-    let mut writer = fasta::Writer::new(result_writer);
-    for taxid in taxids {
-        info!("Getting reference sequences for taxid: {}", taxid);
-        let seqs = filter.get_references(taxid);
-        let mut seq_id = 1;
-        for seq in seqs {
-            let name = format!("{}-{}", seq_id.to_string(), taxid.to_string());
-            writer.write(
-                &name,
-                None, seq.as_slice()).expect("Error writing record.");
-                    seq_id += 1
-            }
-        }
-    info!("Sequences written to file: {}", results_path);
-    Ok(())
+/// Only the most recently used shards are kept open, and the rest are reopened for appending when
+/// they're next needed, so a run hitting thousands of taxids doesn't run out of file handles.
+pub struct ShardWriter {
+    dir: PathBuf,
+    max_open: usize,
+    open: HashMap<TaxId, (BufWriter<File>, u64)>,
+    created: BTreeSet<TaxId>,
+    uses: u64,
+    escape_ids: bool,
+}
+
+impl ShardWriter {
+    /// Create a writer for shards in `dir`, creating the directory if needed.
+    pub fn new<P: AsRef<Path>>(dir: P, max_open: usize, escape_ids: bool) -> MtsvResult<Self> {
+        fs::create_dir_all(dir.as_ref())?;
+        Ok(ShardWriter {
+            dir: dir.as_ref().to_path_buf(),
+            max_open: cmp::max(max_open, 1),
+            open: HashMap::new(),
+            created: BTreeSet::new(),
+            uses: 0,
+            escape_ids,
+        })
     }
 
+    /// The path of the shard for `tax_id` within `dir`.
+    pub fn shard_path<P: AsRef<Path>>(dir: P, tax_id: TaxId) -> PathBuf {
+        dir.as_ref().join(format!("taxid_{}.txt", tax_id.0))
+    }
 
-/// Write the results for a single read to the Writer specified.
-///
-/// Writes in the format `READ_ID:TAX_ID1=EDIT,TAX_ID2=EDIT,...`. Read header/ID is first, followed by a
-/// colon (':'), followed by a comma-separated list of taxonomic IDs (positive integers) with their
-/// edit distances (positive integers) separated by equal sign ('=').
-pub fn write_edit_distances<W: Write>(header: &str,
-            hits: &Vec<Hit>,
-            writer: &mut W)
-            -> MtsvResult<()> {
-    if hits.len() == 0 {
-        return Ok(());
+    /// Write a read's hits to their shards, keeping the smallest edit distance for each taxid.
+    pub fn write_hits(&mut self, header: &str, hits: &[Hit]) -> MtsvResult<()> {
+        let mut best: BTreeMap<TaxId, u32> = BTreeMap::new();
+        for hit in hits {
+            let edit = best.entry(hit.tax_id).or_insert(hit.edit);
+            *edit = cmp::min(*edit, hit.edit);
+        }
+
+        let header = if self.escape_ids {
+            escape_read_id(header)
+        } else {
+            check_read_id(header)?;
+            header.into()
+        };
+        for (tax_id, edit) in best {
+            let writer = self.writer(tax_id)?;
+            writeln!(writer, "{}={}", header, edit)?;
+        }
+        Ok(())
     }
-    let mut hit_map:HashMap<TaxId, u32> = HashMap::new();
-    for hit in hits {
 
-        match hit_map.get(&hit.tax_id) {
-            // if taxid already exists in hashmap, only add if edit distance is smaller
-            Some(edit_distance) => {
-                if edit_distance > &hit.edit {
-                    hit_map.insert(hit.tax_id, hit.edit);
-                }
-            }
-            None => {
-                hit_map.insert(hit.tax_id, hit.edit);
+    fn writer(&mut self, tax_id: TaxId) -> MtsvResult<&mut BufWriter<File>> {
+        self.uses += 1;
+        if !self.open.contains_key(&tax_id) {
+            if self.open.len() >= self.max_open {
+                self.close_least_recent()?;
             }
+
+            let file = match self.open_shard(tax_id) {
+                Ok(f) => f,
+                // out of file handles despite the cap, so make room and try once more
+                Err(ref e) if e.raw_os_error() == Some(EMFILE) => {
+                    self.close_all()?;
+                    self.open_shard(tax_id)?
+                },
+                Err(e) => return Err(e.into()),
+            };
+            self.created.insert(tax_id);
+            self.open.insert(tax_id, (BufWriter::new(file), 0));
         }
+
+        let entry = self.open.get_mut(&tax_id).unwrap();
+        entry.1 = self.uses;
+        Ok(&mut entry.0)
     }
 
+    fn open_shard(&self, tax_id: TaxId) -> io::Result<File> {
+        // a shard is truncated the first time it's opened in a run and appended to afterwards
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .append(self.created.contains(&tax_id))
+            .truncate(!self.created.contains(&tax_id))
+            .open(ShardWriter::shard_path(&self.dir, tax_id))
+    }
 
-    let mut result_line = String::from(header);
-    result_line.push(':');
-    // iterate over hits and add to output string
+    fn close_least_recent(&mut self) -> MtsvResult<()> {
+        let oldest = self.open.iter().min_by_key(|&(_, &(_, used))| used).map(|(t, _)| *t);
+        if let Some(tax_id) = oldest {
+            let (mut writer, _) = self.open.remove(&tax_id).unwrap();
+            writer.flush()?;
+        }
+        Ok(())
+    }
 
-    let mut hits_peek = hit_map.iter().peekable();
-    for (taxid, edit) in hit_map.iter() {
-        let _ = hits_peek.next();
+    fn close_all(&mut self) -> MtsvResult<()> {
+        for (_, (mut writer, _)) in self.open.drain() {
+            writer.flush()?;
+        }
+        Ok(())
+    }
 
-        result_line.push_str(&taxid.0.to_string());
-        result_line.push('=');
-        result_line.push_str(&edit.to_string());
-        if let Some(_) = hits_peek.peek() {
-            result_line.push(',');
+    /// Flush all of the open shards.
+    pub fn flush(&mut self) -> MtsvResult<()> {
+        for &mut (ref mut writer, _) in self.open.values_mut() {
+            writer.flush()?;
         }
+        Ok(())
+    }
+
+    /// Flush and close all of the shards, returning the number of shard files written.
+    pub fn finish(mut self) -> MtsvResult<usize> {
+        self.close_all()?;
+        Ok(self.created.len())
     }
-    result_line.push('\n');
-    writer.write(result_line.as_bytes())?;
-    Ok(())
 }
 
+/// Split combined results files into shards in `dir`, returning the number of shard files.
+fn shard_results_files(results_paths: &[String], dir: &str, escape_ids: bool) -> MtsvResult<usize> {
+    let mut shards = ShardWriter::new(dir, MAX_OPEN_SHARDS, escape_ids)?;
+    for results_path in results_paths {
+        for finding in open_findings(results_path)? {
+            let finding = finding?;
+            shards.write_hits(&finding.read_id, &finding.hits)?;
+        }
+    }
+    shards.finish()
+}
 
-#[cfg(test)]
-mod test {
-    use ::index::TaxId;
-    use std::collections::BTreeSet;
-    use super::*;
+/// Where a pass over the reads files writes its results.
+struct ResultsPaths {
+    /// The results file for each reads file, in the order they were given, or a single results
+    /// file for all of them.
+    files: Vec<String>,
+    /// Where the pass's checkpoints are written.
+    checkpoint: String,
+}
 
-    fn test_write(header: &str, matches: &BTreeSet<TaxId>, expected: &str) {
-        let mut buf = Vec::new();
+impl ResultsPaths {
+    /// A single results file, with its checkpoints next to it.
+    fn single(results_path: &str) -> Self {
+        ResultsPaths {
+            files: vec![results_path.to_string()],
+            checkpoint: checkpoint_path(results_path),
+        }
+    }
 
-        write_single_line(header, matches, &mut buf).unwrap();
+    /// Temporary paths for the pass against the `i`th of several indices.
+    fn pass(&self, i: usize) -> Self {
+        ResultsPaths {
+            files: self.files.iter().map(|f| format!("{}.index{}.tmp", f, i)).collect(),
+            checkpoint: format!("{}.index{}.tmp", self.checkpoint, i),
+        }
+    }
 
-        let found = String::from_utf8(buf).unwrap();
+    /// Which results file the reads from the `input`th reads file go to.
+    fn file_for(&self, input: usize) -> usize {
+        if self.files.len() > 1 { input } else { 0 }
+    }
+}
 
-        assert_eq!(expected, &found);
+/// The sum of the stats for each reads file of a run.
+fn total_stats(input_stats: &[RunStats]) -> RunStats {
+    let mut total = RunStats::default();
+    for stats in input_stats {
+        total.add(stats);
     }
+    total
+}
 
+/// The file name a reads file's results are given in a results directory: the reads file's name
+/// without any `.gz` extension and then any FASTA or FASTQ extension.
+///
+/// Fails if two reads files would get the same name.
+pub fn input_results_names(input_paths: &[&str]) -> MtsvResult<Vec<String>> {
+    let mut names: Vec<String> = Vec::new();
+    for (i, input_path) in input_paths.iter().enumerate() {
+        let file_name = Path::new(input_path)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .ok_or_else(|| {
+                MtsvError::InvalidParameter(format!("{} isn't a reads file name", input_path))
+            })?;
+        let mut name = file_name.trim_end_matches(".gz");
+        for extension in &[".fasta", ".fastq", ".fna", ".fa", ".fq"] {
+            if name.len() > extension.len() && name.ends_with(extension) {
+                name = &name[..name.len() - extension.len()];
+                break;
+            }
+        }
 
-    #[test]
-    fn success_many() {
-        let header = "R1_1_0_0";
-        let mut matches = BTreeSet::new();
-        matches.insert(TaxId(12345));
-        matches.insert(TaxId(5678));
-        matches.insert(TaxId(0));
+        if let Some(j) = names.iter().position(|n| n == name) {
+            return Err(MtsvError::InvalidParameter(format!("reads files {} and {} would both \
+                                                            write results named {}",
+                                                           input_paths[j],
+                                                           input_paths[i],
+                                                           name)));
+        }
+        names.push(name.to_string());
+    }
+    Ok(names)
+}
 
-        let expected = "R1_1_0_0:0,5678,12345\n";
+/// Where a reads file's entry in a results directory's manifest points.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// The reads file, as it was given.
+    pub input: String,
+    /// The reads file's results, relative to the results directory.
+    pub results: String,
+    /// The reads file's `RunStats` as JSON, relative to the results directory.
+    pub summary: String,
+    /// Number of reads processed from the reads file.
+    pub reads: usize,
+}
 
-        test_write(header, &matches, expected);
-    }
+/// The contents of a results directory, written as JSON to `manifest.json` in it.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ResultsManifest {
+    /// The run's random seed, to reproduce it with `--seed`.
+    pub seed: u64,
+    /// An entry for each reads file, in the order they were given.
+    pub inputs: Vec<ManifestEntry>,
+}
 
-    #[test]
-    fn success_single_spaces() {
-        let header = "R1 1 0\t0";
-        let mut matches = BTreeSet::new();
-        matches.insert(TaxId(12345));
+/// The format of a query reads file.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ReadFormat {
+    /// FASTA reads.
+    Fasta,
+    /// FASTQ reads.
+    Fastq,
+}
 
-        let expected = "R1 1 0\t0:12345\n";
+/// How far through each of a run's reads files the reader has got.
+struct InputProgress {
+    /// Number of records taken from each file.
+    taken: Vec<AtomicUsize>,
+    /// Whether each file has been read to the end.
+    exhausted: Vec<AtomicBool>,
+}
 
-        test_write(header, &matches, expected);
+impl InputProgress {
+    fn new(num_inputs: usize) -> Self {
+        InputProgress {
+            taken: (0..num_inputs).map(|_| AtomicUsize::new(0)).collect(),
+            exhausted: (0..num_inputs).map(|_| AtomicBool::new(false)).collect(),
+        }
     }
 
-    #[test]
-    fn success_empty() {
-        let header = "R1_1_0_0";
-        let matches = BTreeSet::new();
+    /// Whether each file has been read to the end, and all of its reads processed, given the
+    /// number of reads processed from each.
+    fn finished(&self, processed: &[usize]) -> Vec<bool> {
+        self.exhausted
+            .iter()
+            .zip(&self.taken)
+            .zip(processed)
+            .map(|((exhausted, taken), &processed)| {
+                exhausted.load(Ordering::SeqCst) && taken.load(Ordering::SeqCst) == processed
+            })
+            .collect()
+    }
+}
 
-        let expected = "";
+/// Reads from several reads files, tagged with the index of the file they came from.
+///
+/// The files are read one after another, or with `interleave` a read is taken from each file
+/// in turn, skipping files which have run out.
+struct InputReads<'a, I> {
+    inputs: Vec<Option<I>>,
+    current: usize,
+    interleave: bool,
+    progress: &'a InputProgress,
+}
 
-        test_write(header, &matches, expected);
+impl<'a, I> InputReads<'a, I> {
+    fn new(inputs: Vec<I>, interleave: bool, progress: &'a InputProgress) -> Self {
+        InputReads {
+            inputs: inputs.into_iter().map(Some).collect(),
+            current: 0,
+            interleave,
+            progress,
+        }
+    }
+}
+
+impl<'a, I> Iterator for InputReads<'a, I>
+    where I: Iterator<Item = MtsvResult<(String, Vec<u8>)>>
+{
+    type Item = MtsvResult<(usize, String, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // every file before the current one has run out unless interleaving, which wraps around
+        for _ in 0..self.inputs.len() {
+            let i = self.current;
+            match self.inputs[i].as_mut().and_then(|input| input.next()) {
+                Some(record) => {
+                    self.progress.taken[i].fetch_add(1, Ordering::SeqCst);
+                    if self.interleave {
+                        self.current = (i + 1) % self.inputs.len();
+                    }
+                    return Some(record.map(|(id, seq)| (i, id, seq)));
+                },
+                None => {
+                    if self.inputs[i].take().is_some() {
+                        self.progress.exhausted[i].store(true, Ordering::SeqCst);
+                    }
+                    self.current = (i + 1) % self.inputs.len();
+                },
+            }
+        }
+        None
+    }
+}
+
+/// Execute metagenomic binning queries in parallel.
+///
+/// This function:
+///
+/// 1. Opens the FASTA file with query reads
+/// 2. Creates the results file to write to
+/// 3. Deserializes the metagenomic index into memory
+/// 4. In parallel queries for which taxonomic IDs have a match to the query read within the edit
+/// distance specified.
+/// 5. Writes those results to the output file as they become available.
+///
+/// `params` controls the seeding and alignment of each query, see `QueryParams`. `config` controls
+/// how results are written, see `BinnerConfig`.
+///
+///  
+/// TODO: Replace separate functions once FASTX is implemented, currently awaiting review on pull request #433
+pub fn get_fasta_and_write_matching_bin_ids(input_path: &str,
+                                            index_path: &str,
+                                            results_path: &str,
+                                            num_threads: usize,
+                                            params: &QueryParams,
+                                            config: &BinnerConfig)
+                                            -> MtsvResult<()> {
+    bin_reads(input_path, ReadFormat::Fasta, &[index_path], results_path, num_threads, params, config)
+}
+
+/// Execute metagenomic binning queries in parallel.
+///
+/// This function:
+///
+/// 1. Opens the FASTQ file with query reads
+/// 2. Creates the results file to write to
+/// 3. Deserializes the metagenomic index into memory
+/// 4. In parallel queries for which taxonomic IDs have a match to the query read within the edit
+/// distance specified.
+/// 5. Writes those results to the output file as they become available.
+///
+/// `params` controls the seeding and alignment of each query, see `QueryParams`. `config` controls
+/// how results are written, see `BinnerConfig`.
+///
+///  
+/// TODO: Replace separate functions once FASTX is implemented, currently awaiting review on pull request #433   
+pub fn get_fastq_and_write_matching_bin_ids(input_path: &str,
+                                            index_path: &str,
+                                            results_path: &str,
+                                            num_threads: usize,
+                                            params: &QueryParams,
+                                            config: &BinnerConfig)
+                                            -> MtsvResult<()> {
+    bin_reads(input_path, ReadFormat::Fastq, &[index_path], results_path, num_threads, params, config)
+}
+
+/// Execute metagenomic binning queries against one or more indices, writing a single results
+/// file.
+///
+/// Indices are loaded one at a time, so only one is ever in memory. With more than one index,
+/// each pass over the reads writes to a temporary file next to `results_path`, and those are
+/// then collapsed into the results file keeping the smallest edit distance for each taxid (see
+/// `collapse::collapse_edit_files`). Read order in the merged file is sorted by read ID.
+pub fn bin_reads(input_path: &str,
+                 format: ReadFormat,
+                 index_paths: &[&str],
+                 results_path: &str,
+                 num_threads: usize,
+                 params: &QueryParams,
+                 config: &BinnerConfig)
+                 -> MtsvResult<()> {
+    bin_inputs(&[input_path], format, index_paths, results_path, num_threads, params, config)
+}
+
+/// Execute metagenomic binning queries for the reads in several files of the same format, as
+/// `bin_reads` does for one, writing a single results file.
+///
+/// The files are read one after another, or a read at a time from each in turn with
+/// `BinnerConfig::interleave_inputs`. The number of reads processed from each file is logged,
+/// and recorded in any checkpoints along with which files have been finished.
+pub fn bin_inputs(input_paths: &[&str],
+                  format: ReadFormat,
+                  index_paths: &[&str],
+                  results_path: &str,
+                  num_threads: usize,
+                  params: &QueryParams,
+                  config: &BinnerConfig)
+                  -> MtsvResult<()> {
+    bin_to_results(input_paths,
+                   format,
+                   index_paths,
+                   &ResultsPaths::single(results_path),
+                   num_threads,
+                   params,
+                   config)?;
+    Ok(())
+}
+
+/// Execute metagenomic binning queries for the reads in several files of the same format, as
+/// `bin_inputs` does, but write each file's results to a separate file in `results_dir`.
+///
+/// Each reads file's results are written to `NAME.results`, where `NAME` comes from the reads
+/// file's name (see `input_results_names`), and its `RunStats` to `NAME.summary.json`. Both are
+/// listed in `manifest.json` (see `ResultsManifest`), and checkpoints are written to
+/// `binner.checkpoint`, all in `results_dir`.
+pub fn bin_inputs_to_dir(input_paths: &[&str],
+                         format: ReadFormat,
+                         index_paths: &[&str],
+                         results_dir: &str,
+                         num_threads: usize,
+                         params: &QueryParams,
+                         config: &BinnerConfig)
+                         -> MtsvResult<()> {
+    let names = input_results_names(input_paths)?;
+    fs::create_dir_all(results_dir)?;
+    let dir = Path::new(results_dir);
+    let dir_path = |file_name: &str| dir.join(file_name).to_string_lossy().into_owned();
+
+    let results = ResultsPaths {
+        files: names.iter().map(|name| dir_path(&format!("{}.results", name))).collect(),
+        checkpoint: dir_path("binner.checkpoint"),
+    };
+    let input_stats =
+        bin_to_results(input_paths, format, index_paths, &results, num_threads, params, config)?;
+
+    let mut manifest = ResultsManifest { seed: config.run.seed, inputs: Vec::new() };
+    for ((input_path, name), stats) in input_paths.iter().zip(&names).zip(&input_stats) {
+        let summary = format!("{}.summary.json", name);
+        let mut writer = BufWriter::new(File::create(dir.join(&summary))?);
+        serde_json::to_writer_pretty(&mut writer, stats)?;
+        writer.write_all(b"\n")?;
+        writer.flush()?;
+
+        manifest.inputs.push(ManifestEntry {
+            input: input_path.to_string(),
+            results: format!("{}.results", name),
+            summary,
+            reads: stats.reads,
+        });
+    }
+
+    let mut writer = BufWriter::new(File::create(dir.join("manifest.json"))?);
+    serde_json::to_writer_pretty(&mut writer, &manifest)?;
+    writer.write_all(b"\n")?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Bin the reads files against each index, writing to `results`, and return the stats for each
+/// reads file.
+fn bin_to_results(input_paths: &[&str],
+                  format: ReadFormat,
+                  index_paths: &[&str],
+                  results: &ResultsPaths,
+                  num_threads: usize,
+                  params: &QueryParams,
+                  config: &BinnerConfig)
+                  -> MtsvResult<Vec<RunStats>> {
+    info!("Random seed: {} (pass --seed {} to reproduce this run)",
+          config.run.seed,
+          config.run.seed);
+
+    if config.duplicate_ids == DuplicateIdPolicy::Merge && config.no_combined {
+        return Err(MtsvError::InvalidParameter(String::from("merging duplicate read IDs needs \
+                                                             the combined results file")));
+    }
+
+    if config.emit_accessions {
+        let problem = if config.findings_version != FindingsVersion::V2 {
+            Some("accessions are only written to v2 results")
+        } else if config.duplicate_ids == DuplicateIdPolicy::Merge {
+            Some("merged duplicate reads don't keep their accessions")
+        } else if index_paths.len() > 1 {
+            Some("accessions are only written when binning against a single index")
+        } else {
+            None
+        };
+        if let Some(problem) = problem {
+            return Err(MtsvError::InvalidParameter(problem.to_string()));
+        }
+    }
+
+    // fail before binning any reads rather than part way through the indices
+    for index_path in index_paths {
+        check_index_capabilities(index_path, config)?;
+    }
+
+    // every pass appends to the slow read log, so start from an empty one
+    if let Some(ref path) = config.slow_read_log {
+        File::create(path)?;
+    }
+
+    if index_paths.len() == 1 {
+        let (stats, input_stats) = bin_file(input_paths, format, index_paths[0], results,
+                                            num_threads, params, config)?;
+        log_run_summary(index_paths[0], input_paths, &stats);
+        write_edit_histogram(&stats, params, config)?;
+        return Ok(input_stats);
+    }
+
+    // each index gets its own temporary results files, removed once they've been merged
+    let passes = index_paths.iter()
+        .enumerate()
+        .map(|(i, index_path)| (*index_path, results.pass(i)))
+        .collect::<Vec<_>>();
+
+    let result = bin_and_collapse(input_paths,
+                                  format,
+                                  &passes,
+                                  results,
+                                  num_threads,
+                                  params,
+                                  config);
+
+    for (_, partial) in &passes {
+        for path in partial.files.iter().chain(Some(&partial.checkpoint)) {
+            if Path::new(path).exists() {
+                if let Err(why) = fs::remove_file(path) {
+                    warn!("Unable to remove temporary results file {}: {}", path, why);
+                }
+            }
+        }
+    }
+
+    result
+}
+
+/// Run each `(index path, partial results paths)` pass in turn, then collapse the partial
+/// results into `results`, returning the stats for each reads file.
+fn bin_and_collapse(input_paths: &[&str],
+                    format: ReadFormat,
+                    passes: &[(&str, ResultsPaths)],
+                    results: &ResultsPaths,
+                    num_threads: usize,
+                    params: &QueryParams,
+                    config: &BinnerConfig)
+                    -> MtsvResult<Vec<RunStats>> {
+    // the partial files are discarded after collapsing, so they don't need any durability
+    let mut partial_config = config.clone();
+    partial_config.flush_every = None;
+    partial_config.fsync_on_close = false;
+    // shards are split from the merged results instead
+    partial_config.shard_dir = None;
+    partial_config.no_combined = false;
+    // lines are capped once merged, so no taxids are left off before merging
+    partial_config.max_line_taxids = None;
+    // legacy results are converted once merged, since merging needs the edit distances
+    if config.findings_version == FindingsVersion::Legacy {
+        partial_config.findings_version = FindingsVersion::V1;
+    }
+
+    let mut input_stats = vec![RunStats::default(); input_paths.len()];
+    for (i, &(index_path, ref partial)) in passes.iter().enumerate() {
+        info!("Binning against index {} of {}: {}", i + 1, passes.len(), index_path);
+
+        // read filters don't depend on the index, so only record the rejects once
+        if i > 0 {
+            partial_config.rejects_path = None;
+            partial_config.excluded_reads_path = None;
+        }
+
+        let (stats, pass_stats) = bin_file(input_paths, format, index_path, partial,
+                                           num_threads, params, &partial_config)?;
+        log_run_summary(index_path, input_paths, &stats);
+        for (total, stats) in input_stats.iter_mut().zip(&pass_stats) {
+            if i == 0 {
+                total.add(stats);
+            } else {
+                total.add_pass(stats);
+            }
+        }
+    }
+
+    info!("Merging results from {} indices...", passes.len());
+    let mut output_files = Vec::new();
+    for (j, results_path) in results.files.iter().enumerate() {
+        let mut partial_files = Vec::new();
+        for (_, partial) in passes {
+            partial_files.push(BufReader::new(File::open(&partial.files[j])?));
+        }
+
+        let mut result_writer = BufWriter::new(File::create(Path::new(results_path))?);
+        // every partial file has the same sample name, so there's nothing to mix
+        collapse_edit_files(&mut partial_files, &mut result_writer, false,
+                            config.max_line_taxids)?;
+        output_files.push(result_writer.into_inner()
+            .map_err(|e| MtsvError::from(e.into_error()))?);
+    }
+
+    // each pass only knew the best hits against its own index
+    if results.files.len() > 1 {
+        for (stats, results_path) in input_stats.iter_mut().zip(&results.files) {
+            stats.edits = results_edit_histogram(results_path)?;
+        }
+    }
+    let mut total_stats = total_stats(&input_stats);
+    if results.files.len() == 1 {
+        total_stats.edits = results_edit_histogram(&results.files[0])?;
+    }
+    if let Some(ref dir) = config.shard_dir {
+        total_stats.shard_files = shard_results_files(&results.files, dir, config.escape_ids)?;
+    }
+    log_run_summary("all indices", input_paths, &total_stats);
+    write_edit_histogram(&total_stats, params, config)?;
+
+    for (results_path, output_file) in results.files.iter().zip(output_files) {
+        if config.no_combined {
+            fs::remove_file(results_path)?;
+        } else if config.findings_version == FindingsVersion::Legacy {
+            drop(output_file);
+            let legacy_path = format!("{}.legacy.tmp", results_path);
+            let mut legacy_writer = BufWriter::new(File::create(&legacy_path)?);
+            convert_findings(BufReader::new(File::open(results_path)?),
+                             &mut legacy_writer,
+                             FindingsVersion::Legacy,
+                             0)?;
+            let legacy_file = legacy_writer.into_inner()
+                .map_err(|e| MtsvError::from(e.into_error()))?;
+            if config.fsync_on_close {
+                legacy_file.sync_all()?;
+            }
+            fs::rename(&legacy_path, results_path)?;
+        } else if config.fsync_on_close {
+            output_file.sync_all()?;
+        }
+    }
+    Ok(input_stats)
+}
+
+/// Count the edit distances in a results file. Hits left off capped lines aren't counted.
+fn results_edit_histogram(results_path: &str) -> MtsvResult<EditHistogram> {
+    let mut histogram = EditHistogram::default();
+    for finding in open_findings(results_path)? {
+        histogram.add_read(&finding?.hits);
+    }
+    Ok(histogram)
+}
+
+/// Write the run's edit histogram to `BinnerConfig::edit_histogram`, if set, with rows up to the
+/// edit distance allowed for the longest read which could have been queried.
+fn write_edit_histogram(stats: &RunStats,
+                        params: &QueryParams,
+                        config: &BinnerConfig)
+                        -> MtsvResult<()> {
+    if let Some(ref path) = config.edit_histogram {
+        let longest_read = cmp::min(stats.longest_read, max_read_length(config));
+        let max_edit = (longest_read as f64 * params.edit_freq).ceil() as usize;
+        let mut writer = BufWriter::new(File::create(path)?);
+        stats.edits.write_tsv(&mut writer, max_edit)?;
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+/// Bin the reads files against a single index, returning the stats for the run and for each reads
+/// file.
+fn bin_file(input_paths: &[&str],
+            format: ReadFormat,
+            index_path: &str,
+            results: &ResultsPaths,
+            num_threads: usize,
+            params: &QueryParams,
+            config: &BinnerConfig)
+            -> MtsvResult<(RunStats, Vec<RunStats>)> {
+    match format {
+        ReadFormat::Fasta => {
+            let mut inputs = Vec::new();
+            for input_path in input_paths {
+                let mut fasta_reader = fasta::Reader::from_file(Path::new(input_path))?;
+                // an empty file is just an input without reads
+                match fasta_reader.records().next() {
+                    Some(record) => {
+                        record?;
+                        info!("Test parse of FASTA record successful, reinitializing parser.");
+                    },
+                    None => warn!("No reads in {}", input_path),
+                }
+                fasta_reader = fasta::Reader::from_file(Path::new(input_path))?;
+                inputs.push(fasta_reader.records().map(|record| match record {
+                    Ok(r) => Ok((r.id().to_owned(), r.seq().to_vec())),
+                    Err(why) => Err(MtsvError::from(why)),
+                }));
+            }
+
+            write_matching_bin_ids(inputs, index_path, results, num_threads, params, config)
+        },
+        ReadFormat::Fastq => {
+            let mismatched = input_paths.iter().map(|_| Cell::new(0)).collect::<Vec<_>>();
+            let mut inputs = Vec::new();
+            for (input_path, mismatched) in input_paths.iter().zip(&mismatched) {
+                let mut fastq_reader = fastq::Reader::from_file(Path::new(input_path))?;
+                // an empty file is just an input without reads
+                match fastq_reader.records().next() {
+                    Some(record) => {
+                        record?;
+                        info!("Test parse of FASTQ record successful, reinitializing parser.");
+                    },
+                    None => warn!("No reads in {}", input_path),
+                }
+                fastq_reader = fastq::Reader::from_file(Path::new(input_path))?;
+                inputs.push(fastq_reader.records().filter_map(move |record| match record {
+                    Ok(r) => {
+                        match check_fastq_record(&r) {
+                            Ok(()) => Some(Ok((r.id().to_owned(), r.seq().to_vec()))),
+                            Err(why) if config.strict_reads => Some(Err(why)),
+                            Err(why) => {
+                                warn!("Skipping read: {}", why);
+                                mismatched.set(mismatched.get() + 1);
+                                None
+                            },
+                        }
+                    },
+                    Err(why) => Some(Err(MtsvError::from(why))),
+                }));
+            }
+
+            let (mut stats, mut input_stats) =
+                write_matching_bin_ids(inputs, index_path, results, num_threads, params, config)?;
+            for (input_stats, mismatched) in input_stats.iter_mut().zip(&mismatched) {
+                input_stats.rejected_mismatched_quality = mismatched.get();
+                stats.rejected_mismatched_quality += mismatched.get();
+            }
+            Ok((stats, input_stats))
+        },
+    }
+}
+
+/// Query each `(id, sequence)` read from each of the inputs against the index and write the
+/// results, reading the inputs as `InputReads` does. Returns the stats for the run and for each
+/// input.
+///
+/// With a results file for each input, each read's results are written to the file for the input
+/// it came from. Everything else (shards, rejects, checkpoints, ...) is shared by all the inputs.
+///
+/// Workers share nothing but the read-only index, and every cap in `QueryParams` applies to a
+/// single read, so a read's hits don't depend on the number of threads, only the order reads are
+/// written in does. Any randomness added to a read's processing must come from
+/// `RunContext::read_rng` to keep it that way.
+///
+/// Stops reading input after the first read or write error, lets the pipeline drain, and then
+/// returns that error once the results file has been flushed.
+fn write_matching_bin_ids<I>(inputs: Vec<I>,
+                             index_path: &str,
+                             results: &ResultsPaths,
+                             num_threads: usize,
+                             params: &QueryParams,
+                             config: &BinnerConfig)
+                             -> MtsvResult<(RunStats, Vec<RunStats>)>
+    where I: Iterator<Item = MtsvResult<(String, Vec<u8>)>>
+{
+    let capacity = match config.memory_limit {
+        Some(limit) => {
+            let prescreen = prescreen_path(index_path);
+            let mut index_paths = vec![index_path];
+            if !config.no_prescreen && Path::new(&prescreen).exists() {
+                index_paths.push(&prescreen);
+            }
+            if let Some(ref path) = config.exclusion_index {
+                index_paths.push(path);
+            }
+            let index_bytes = check_index_sizes(&index_paths, limit)?;
+            let read_length = config.max_read_length
+                .map_or(ASSUMED_READ_LENGTH, |l| cmp::min(l, READ_LENGTH_LIMIT));
+            queue_capacity(limit, index_bytes, num_threads, read_length)
+        },
+        None => num_threads * QUEUE_PER_WORKER,
+    };
+    let process_memory = ProcessMemory;
+    let throttle = config.memory_limit.map(|limit| Throttle::new(&process_memory, limit));
+
+    info!("Loading candidate filter ...");
+    let filter = open_index(index_path, config)?;
+    if config.emit_accessions && !filter.has_accessions() {
+        return Err(MtsvError::InvalidParameter(format!("{} was built without accessions",
+                                                       index_path)));
+    }
+    let searcher = filter.searcher();
+    let alphabet = filter.alphabet();
+    let prescreen = load_prescreen(index_path, &filter, params, config)?;
+    let exclusion = match config.exclusion_index {
+        Some(ref path) => Some(load_exclusion_index(path, alphabet, config)?),
+        None => None,
+    };
+    let exclusion_searcher = exclusion.as_ref().map(LoadedIndex::searcher);
+    let exclusion_params = QueryParams { edit_freq: config.exclusion_edit_rate, ..*params };
+    let excluded = |seq: &[u8]| match exclusion_searcher {
+        Some(ref searcher) => {
+            !query_read(searcher, None, seq, &exclusion_params, None).0.is_empty()
+        },
+        None => false,
+    };
+
+    assert!(results.files.len() == 1 || results.files.len() == inputs.len());
+    let mut result_writers = Vec::new();
+    if !config.no_combined {
+        let mut header = ResultsHeader {
+            sample: config.sample_name.clone(),
+            escaped_ids: config.escape_ids,
+            version: config.findings_version,
+            fields: match config.findings_version {
+                FindingsVersion::Legacy | FindingsVersion::V1 => Vec::new(),
+                FindingsVersion::V2 | FindingsVersion::Binary => V2_FIELDS.to_vec(),
+            },
+        };
+        if config.emit_accessions {
+            header.fields.push(FindingsField::Accession);
+        }
+        let names = if config.emit_accessions {
+            Some(Arc::new(NameResolver::new(filter.bins(), filter.accessions())))
+        } else {
+            None
+        };
+        for results_path in &results.files {
+            let output_file = File::create(Path::new(results_path))?;
+            let mut writer = ResultWriter::new(output_file, config.flush_every)
+                .with_max_taxids(config.max_line_taxids);
+            if let Some(ref names) = names {
+                writer = writer.with_names(names.clone());
+            }
+            writer.write_header(&header)?;
+            result_writers.push(writer);
+        }
+    }
+    let mut shard_writer = match config.shard_dir {
+        Some(ref dir) => Some(ShardWriter::new(dir, MAX_OPEN_SHARDS, config.escape_ids)?),
+        None => None,
+    };
+    let mut rejects_writer = match config.rejects_path {
+        Some(ref p) => Some(fasta::Writer::new(BufWriter::new(File::create(Path::new(p))?))),
+        None => None,
+    };
+    let mut excluded_writer = match config.excluded_reads_path {
+        Some(ref p) => Some(fasta::Writer::new(BufWriter::new(File::create(Path::new(p))?))),
+        None => None,
+    };
+    let mut slow_read_writer = match config.slow_read_log {
+        Some(ref p) => Some(open_slow_read_log(p)?),
+        None => None,
+    };
+    let num_inputs = inputs.len();
+    let progress = InputProgress::new(num_inputs);
+    // duplicate IDs are found as the reads are taken, in the same order whatever the threads do
+    let mut read_ids = results.files.iter().map(|_| ReadIds::default()).collect::<Vec<_>>();
+    let mut duplicate_read_ids = vec![0; num_inputs];
+    let records = InputReads::new(inputs, config.interleave_inputs, &progress).map(|record| {
+        if let Some(ref throttle) = throttle {
+            throttle.take();
+        }
+        let (input, id, seq) = record?;
+        let (id, duplicate) = read_ids[results.file_for(input)].check(id, config.duplicate_ids)?;
+        if duplicate {
+            duplicate_read_ids[input] += 1;
+        }
+        Ok((input, id, seq))
+    });
+    let mut input_stats = vec![RunStats { input_reads: vec![0; num_inputs], ..RunStats::default() };
+                               num_inputs];
+    let mut reads = 0;
+    let mut last_read_id = None;
+    let mut first_error = None;
+    let failed = AtomicBool::new(false);
+    let no_hits = Vec::new();
+
+    info!("Beginning queries.");
+
+    let timer = Stopwatch::start_new();
+
+    pipeline_with_capacity("taxonomic binning",
+                           num_threads,
+                           capacity,
+                           records.take_while(|_| !failed.load(Ordering::SeqCst)),
+                           |record| {
+        let (input, header, seq) = match record {
+            Ok(r) => r,
+            Err(why) => {
+                failed.store(true, Ordering::SeqCst);
+                return Err(why);
+            },
+        };
+
+        let mut stats = RunStats::default();
+        match prepare_read(&seq, alphabet, params.ambiguity, config, &mut stats) {
+            Ok(ref normalized) if excluded(normalized) => {
+                Ok((input, ReadOutcome::Rejected(header, seq, RejectReason::Excluded)))
+            },
+            Ok(normalized) => {
+                let start = Instant::now();
+                let (mut edit_distances, query_stats) =
+                    query_read(&searcher,
+                               prescreen.as_ref(),
+                               &normalized,
+                               params,
+                               config.skip_reverse_after);
+                let query_ms = start.elapsed().as_secs_f64() * 1000.0;
+                stats.query = query_stats;
+                if query_stats.hit_limited_queries > 0 {
+                    stats.seed_hit_limited_reads = 1;
+                }
+                if config.slow_read_log.is_some() &&
+                   query_ms >= config.slow_read_threshold_ms as f64 {
+                    stats.slow_reads = 1;
+                }
+                if let Some(min_confidence) = config.min_confidence {
+                    stats.hits_below_confidence +=
+                        retain_confident_hits(&mut edit_distances, normalized.len(), params,
+                                              min_confidence);
+                }
+                Ok((input, ReadOutcome::Queried(header, edit_distances, Box::new(stats), query_ms)))
+            },
+            Err(reason) => Ok((input, ReadOutcome::Rejected(header, seq, reason))),
+        }
+    },
+                           |result| {
+        let flush = throttle.as_ref().is_some_and(|throttle| throttle.wrote());
+        // keep draining the pipeline after an error, but only report the first one
+        if first_error.is_some() {
+            return;
+        }
+
+        let write_result = result.and_then(|(input, outcome)| {
+            reads += 1;
+            let run_stats = &mut input_stats[input];
+            run_stats.reads += 1;
+            run_stats.input_reads[input] += 1;
+            let mut result_writer = result_writers.get_mut(results.file_for(input));
+            let header = match outcome {
+                ReadOutcome::Queried(header, mut edit_distances, stats, query_ms) => {
+                    let header = sanitize_header(header, config, run_stats)?;
+                    run_stats.add(&stats);
+                    if stats.slow_reads > 0 {
+                        if let Some(ref mut writer) = slow_read_writer {
+                            writeln!(writer,
+                                     "{}\t{}\t{:.3}\t{}\t{}\t{}\t{}",
+                                     header,
+                                     stats.longest_read,
+                                     query_ms,
+                                     stats.query.seeds_searched,
+                                     stats.query.candidates_aligned,
+                                     edit_distances.len(),
+                                     stats.query.reverse_strands_skipped)?;
+                        }
+                    }
+                    if let Some(ref taxid_map) = config.taxid_map {
+                        run_stats.unmapped_taxid_hits += taxid_map.remap_hits(&mut edit_distances);
+                    }
+                    run_stats.edits.add_read(&edit_distances);
+                    if let Some(ref mut writer) = result_writer {
+                        writer.write_hits(&header, &edit_distances)?;
+                    }
+                    if let Some(ref mut writer) = shard_writer {
+                        writer.write_hits(&header, &edit_distances)?;
+                    }
+                    header
+                },
+                ReadOutcome::Rejected(header, seq, reason) => {
+                    let header = sanitize_header(header, config, run_stats)?;
+                    run_stats.longest_read = cmp::max(run_stats.longest_read, seq.len());
+                    run_stats.reject(reason);
+                    if reason == RejectReason::TooLong {
+                        warn!("Skipping read {}: {} bases is longer than the maximum read length",
+                              header,
+                              seq.len());
+                    }
+                    if reason == RejectReason::Excluded {
+                        if let Some(ref mut writer) = excluded_writer {
+                            writer.write(&header, None, &seq)?;
+                        }
+                    } else if let Some(ref mut writer) = rejects_writer {
+                        writer.write(&header, Some(&format!("reason={}", reason.name())), &seq)?;
+                    }
+                    run_stats.edits.add_read(&no_hits);
+                    // still counts towards the flush cadence
+                    if let Some(ref mut writer) = result_writer {
+                        writer.write_hits(&header, &no_hits)?;
+                    }
+                    header
+                },
+            };
+            last_read_id = Some(header);
+
+            // checkpoints are written from here so they always agree with the results file
+            match config.checkpoint_every {
+                Some(n) if reads % n == 0 => {
+                    for writer in &mut result_writers {
+                        writer.flush()?;
+                    }
+                    if let Some(ref mut writer) = shard_writer {
+                        writer.flush()?;
+                    }
+                    let run_stats = total_stats(&input_stats);
+                    Checkpoint::new(&run_stats,
+                                    &last_read_id,
+                                    progress.finished(&run_stats.input_reads),
+                                    false,
+                                    &config.run)
+                        .write(&results.checkpoint, config.fsync_checkpoints)
+                },
+                _ => Ok(()),
+            }
+        });
+        // the reader is being held back to save memory, so don't keep results buffered either
+        let write_result = write_result.and_then(|()| {
+            if flush {
+                for writer in &mut result_writers {
+                    writer.flush()?;
+                }
+                if let Some(ref mut writer) = shard_writer {
+                    writer.flush()?;
+                }
+            }
+            Ok(())
+        });
+
+        if let Err(why) = write_result {
+            failed.store(true, Ordering::SeqCst);
+            first_error = Some(why);
+        }
+    });
+
+    info!("All worker and result consumer threads terminated. Took {} seconds.",
+          timer.elapsed_ms() as f32 / 1000.0);
+    match throttle.as_ref().map(Throttle::throttled) {
+        Some(throttled) if throttled > 0 => {
+            warn!("Paused reading to stay under the memory limit (times paused: {}).", throttled)
+        },
+        _ => (),
+    }
+
+    for (stats, &duplicates) in input_stats.iter_mut().zip(&duplicate_read_ids) {
+        stats.duplicate_read_ids = duplicates;
+    }
+    let mut run_stats = total_stats(&input_stats);
+    for writer in result_writers {
+        let output_file = writer.finish()?;
+        if config.fsync_on_close {
+            output_file.sync_all()?;
+        }
+    }
+    if let Some(writer) = shard_writer {
+        run_stats.shard_files = writer.finish()?;
+    }
+    let merge = config.duplicate_ids == DuplicateIdPolicy::Merge && first_error.is_none();
+    if merge && read_ids.iter().any(|ids| !ids.duplicated.is_empty()) {
+        for (results_path, ids) in results.files.iter().zip(&read_ids) {
+            let duplicated = ids.duplicated
+                .keys()
+                .map(|id| sanitize_read_id(id, config.control_chars).map(Cow::into_owned))
+                .collect::<MtsvResult<BTreeSet<_>>>()?;
+            merge_duplicate_reads(results_path, &duplicated, config)?;
+        }
+        if let Some(ref dir) = config.shard_dir {
+            run_stats.shard_files = shard_results_files(&results.files, dir, config.escape_ids)?;
+        }
+    }
+    if let Some(mut writer) = rejects_writer {
+        writer.flush()?;
+    }
+    if let Some(mut writer) = excluded_writer {
+        writer.flush()?;
+    }
+    if let Some(mut writer) = slow_read_writer {
+        writer.flush()?;
+    }
+
+    match first_error {
+        Some(why) => Err(why),
+        None => {
+            if config.checkpoint_every.is_some() {
+                Checkpoint::new(&run_stats,
+                                &last_read_id,
+                                progress.finished(&run_stats.input_reads),
+                                true,
+                                &config.run)
+                    .write(&results.checkpoint, config.fsync_checkpoints)?;
+            }
+            Ok((run_stats, input_stats))
+        },
+    }
+}
+
+/// Merge the lines of the reads with these IDs in a results file into a single line each,
+/// keeping the best hit for each taxid (see `DuplicateIdPolicy::Merge`). The merged lines are
+/// moved to the end of the file.
+fn merge_duplicate_reads(results_path: &str,
+                         read_ids: &BTreeSet<String>,
+                         config: &BinnerConfig)
+                         -> MtsvResult<()> {
+    let tmp_path = format!("{}.merge.tmp", results_path);
+    {
+        let mut findings = open_findings(results_path)?;
+        let mut writer = FindingsWriter::new(BufWriter::new(File::create(&tmp_path)?))
+            .with_max_taxids(config.max_line_taxids);
+        writer.write_header(findings.header())?;
+
+        let mut merged = BTreeMap::new();
+        while let Some(finding) = findings.next() {
+            let finding = finding?;
+            if read_ids.contains(&finding.read_id) {
+                let entry = merged.entry(finding.read_id).or_insert((Vec::new(), 0));
+                entry.0.extend(finding.hits);
+                entry.1 += findings.overflow();
+            } else {
+                writer.write_hits_with_overflow(&finding.read_id,
+                                                &finding.hits,
+                                                findings.overflow())?;
+            }
+        }
+        for (read_id, (hits, overflow)) in merged {
+            writer.write_hits_with_overflow(&read_id, &hits, overflow)?;
+        }
+
+        let file = writer.into_inner()
+            .into_inner()
+            .map_err(|e| MtsvError::from(e.into_error()))?;
+        if config.fsync_on_close {
+            file.sync_all()?;
+        }
+    }
+    fs::rename(&tmp_path, results_path)?;
+    Ok(())
+}
+
+/// Apply `BinnerConfig::control_chars` to a read ID, counting it if it's changed.
+fn sanitize_header(header: String,
+                   config: &BinnerConfig,
+                   stats: &mut RunStats)
+                   -> MtsvResult<String> {
+    match sanitize_read_id(&header, config.control_chars)? {
+        Cow::Borrowed(_) => Ok(header),
+        Cow::Owned(sanitized) => {
+            warn!("Replaced control characters in read ID {:?} with '_'", header);
+            stats.sanitized_read_ids += 1;
+            Ok(sanitized)
+        },
+    }
+}
+
+/// The length above which reads are skipped.
+fn max_read_length(config: &BinnerConfig) -> usize {
+    config.max_read_length.map_or(READ_LENGTH_LIMIT, |l| cmp::min(l, READ_LENGTH_LIMIT))
+}
+
+/// Normalize a query read to the index's alphabet, uppercase ACGTN for DNA (keeping ambiguity
+/// codes unless the policy is `AmbiguityPolicy::N`), and apply any read filters, or decide to skip
+/// it. The low-complexity filter only applies to DNA reads, and the N content filter counts Xs in
+/// protein reads.
+fn prepare_read(seq: &[u8],
+                alphabet: Alphabet,
+                ambiguity: AmbiguityPolicy,
+                config: &BinnerConfig,
+                stats: &mut RunStats)
+                -> Result<Vec<u8>, RejectReason> {
+    stats.longest_read = seq.len();
+    if seq.len() > max_read_length(config) {
+        return Err(RejectReason::TooLong);
+    }
+
+    // convert any lowercase items to uppercase (a <-> A isn't a SNP)
+    let mut seq_all_caps = seq.iter()
+        .map(|&b| alphabet.normalize_with(b, ambiguity))
+        .collect::<Vec<u8>>();
+    if ambiguity == AmbiguityPolicy::Reject &&
+       seq_all_caps.iter().any(|&b| alphabet.is_ambiguous(b)) {
+        return Err(RejectReason::Ambiguous);
+    }
+
+    // masking happens first, so masked windows count towards the N content
+    let dust = config.low_complexity.as_ref().filter(|_| alphabet.is_dna());
+    if let Some(dust) = dust {
+        if config.mask_low_complexity {
+            if mask_low_complexity(&mut seq_all_caps, dust) > 0 {
+                stats.masked_low_complexity += 1;
+            }
+        } else if is_low_complexity(&seq_all_caps, dust) {
+            return Err(RejectReason::LowComplexity);
+        }
+    }
+
+    if let Some(max_n_fraction) = config.max_n_fraction {
+        let wildcard = alphabet.wildcard();
+        let num_n = seq_all_caps.iter().filter(|&&b| b == wildcard).count();
+        if num_n as f64 / seq_all_caps.len() as f64 > max_n_fraction {
+            return Err(RejectReason::NContent);
+        }
+    }
+
+    Ok(seq_all_caps)
+}
+
+/// Load the prescreen stored alongside an index, if it has one which can be used for this run.
+fn load_prescreen(index_path: &str,
+                  index: &LoadedIndex,
+                  params: &QueryParams,
+                  config: &BinnerConfig)
+                  -> MtsvResult<Option<Prescreen>> {
+    let path = prescreen_path(index_path);
+    if config.no_prescreen {
+        return Ok(None);
+    }
+    check_index_capabilities(index_path, config)?;
+    if !Path::new(&path).exists() {
+        return Ok(None);
+    }
+
+    let prescreen = if config.no_verify {
+        from_file_unverified::<Prescreen>(&path)?
+    } else {
+        from_file::<Prescreen>(&path)?
+    };
+    let unusable = if prescreen.fingerprint() != index.fingerprint() {
+        Some(format!("Prescreen {} was built from a different index", path))
+    } else if prescreen.kmer_size() != params.seed_length {
+        Some(format!("Prescreen {} has {}-mers but the seed size is {}",
+                     path,
+                     prescreen.kmer_size(),
+                     params.seed_length))
+    } else {
+        None
+    };
+
+    if let Some(reason) = unusable {
+        if config.require_prescreen {
+            return Err(MtsvError::InvalidParameter(format!("{}; rebuild it or drop \
+                                                            --require-prescreen",
+                                                           reason)));
+        }
+        warn!("{}, not using it.", reason);
+        Ok(None)
+    } else if params.min_partial_seed.is_some() {
+        if config.require_prescreen {
+            return Err(MtsvError::InvalidParameter(String::from("the prescreen can't be used \
+                                                                 with partial seeds; drop \
+                                                                 --require-prescreen or \
+                                                                 --use-partial-seeds")));
+        }
+        info!("Partial seeds are enabled, not using prescreen {}.", path);
+        Ok(None)
+    } else {
+        info!("Using prescreen {}.", path);
+        Ok(Some(prescreen))
+    }
+}
+
+/// Check that the index at `index_path` has the optional parts `config`'s options depend on.
+fn check_index_capabilities(index_path: &str, config: &BinnerConfig) -> MtsvResult<()> {
+    let capabilities = IndexCapabilities::of_file(index_path)?;
+    if config.require_prescreen {
+        capabilities.require(IndexCapabilities::PRESCREEN, "--require-prescreen")?;
+    }
+    Ok(())
+}
+
+/// Open the index at `path`, checking its checksum unless `config` says not to.
+fn open_index(path: &str, config: &BinnerConfig) -> MtsvResult<LoadedIndex> {
+    if config.no_verify {
+        LoadedIndex::open_unverified(path)
+    } else {
+        LoadedIndex::open(path)
+    }
+}
+
+/// Load the exclusion index, logging its size since it's held in memory alongside the main index
+/// (unless it's mapped). Its alphabet must be that of the main index.
+fn load_exclusion_index(path: &str,
+                        alphabet: Alphabet,
+                        config: &BinnerConfig)
+                        -> MtsvResult<LoadedIndex> {
+    let index = open_index(path, config)?;
+    if index.alphabet() != alphabet {
+        return Err(MtsvError::InvalidParameter(format!("exclusion index {} is {}, but the \
+                                                        index is {}",
+                                                       path,
+                                                       index.alphabet().name(),
+                                                       alphabet.name())));
+    }
+    // the index is deserialized as it was serialized, so its file size is a fair estimate
+    info!("Loaded exclusion index {}: {} reference sequences, about {:.1} MB {}.",
+          path,
+          index.bin_count(),
+          fs::metadata(path)?.len() as f64 / (1024.0 * 1024.0),
+          match index {
+              LoadedIndex::Deserialized(_) => "in memory",
+              LoadedIndex::Mapped(_) => "mapped",
+          });
+    Ok(index)
+}
+
+/// Find the hits for one strand of a normalized query read, unless the prescreen shows it can't
+/// have any.
+fn query_strand(searcher: &IndexSearcher,
+                prescreen: Option<&Prescreen>,
+                strand: &QueryStrand,
+                params: &QueryParams,
+                stats: &mut QueryStats)
+                -> Vec<Hit> {
+    match prescreen {
+        Some(p) if !p.may_match(&strand.seed_sequence(), params) => {
+            stats.prescreened += 1;
+            Vec::new()
+        },
+        _ => searcher.matching_tax_ids_for(strand, params, stats),
+    }
+}
+
+/// Find the hits for a normalized query read on both strands, unless the forward strand has at
+/// least `skip_reverse_after` hits. Reads against protein indices only have the one strand.
+fn query_read(searcher: &IndexSearcher,
+              prescreen: Option<&Prescreen>,
+              seq: &[u8],
+              params: &QueryParams,
+              skip_reverse_after: Option<usize>)
+              -> (Vec<Hit>, QueryStats) {
+    let mut stats = QueryStats::default();
+
+    if searcher.alphabet() == Alphabet::Protein {
+        let forward = QueryStrand::with_alphabet(seq, Alphabet::Protein)
+            .with_ambiguity(params.ambiguity);
+        let hits = query_strand(searcher, prescreen, &forward, params, &mut stats);
+        return (hits, stats);
+    }
+
+    // get the reverse complement, and prepare both strands up front
+    let rev_comp_seq = revcomp(seq);
+    let forward = QueryStrand::new(seq).with_ambiguity(params.ambiguity);
+    let reverse = QueryStrand::new(&rev_comp_seq).with_ambiguity(params.ambiguity);
+
+    let hits = query_strand(searcher, prescreen, &forward, params, &mut stats);
+    if skip_reverse_after.is_some_and(|min_hits| hits.len() >= min_hits) {
+        stats.reverse_strands_skipped += 1;
+        return (hits, stats);
+    }
+    let rev_hits = query_strand(searcher, prescreen, &reverse, params, &mut stats);
+
+    // unify the result sets
+    let edit_distances: Vec<Hit> = hits.into_iter().chain(rev_hits).collect();
+
+    (edit_distances, stats)
+}
+
+/// Log the counters accumulated over a binning run of the reads in `input_paths` against
+/// `label`.
+fn log_run_summary(label: &str, input_paths: &[&str], run_stats: &RunStats) {
+    let stats = &run_stats.query;
+    info!("Run summary ({}): {} reads, longest {} bases.",
+          label,
+          run_stats.reads,
+          run_stats.longest_read);
+    if input_paths.len() > 1 {
+        for (input_path, reads) in input_paths.iter().zip(&run_stats.input_reads) {
+            info!("Run summary ({}): {} reads from {}.", label, reads, input_path);
+        }
+    }
+    info!("Run summary ({}): {} skipped for length, {} skipped for N content, {} skipped and {} \
+           masked for low complexity.",
+          label,
+          run_stats.rejected_too_long,
+          run_stats.rejected_n_content,
+          run_stats.rejected_low_complexity,
+          run_stats.masked_low_complexity);
+    if run_stats.rejected_excluded > 0 {
+        info!("Run summary ({}): {} skipped for matching the exclusion index.",
+              label,
+              run_stats.rejected_excluded);
+    }
+    if run_stats.rejected_ambiguous > 0 {
+        info!("Run summary ({}): {} skipped for ambiguity codes.",
+              label,
+              run_stats.rejected_ambiguous);
+    }
+    if run_stats.sanitized_read_ids > 0 {
+        warn!("Run summary ({}): {} read IDs had control characters replaced.",
+              label,
+              run_stats.sanitized_read_ids);
+    }
+    if run_stats.duplicate_read_ids > 0 {
+        warn!("Run summary ({}): {} reads had an ID already used by an earlier read.",
+              label,
+              run_stats.duplicate_read_ids);
+    }
+    if run_stats.rejected_mismatched_quality > 0 {
+        warn!("Run summary ({}): {} FASTQ records skipped for mismatched sequence and quality \
+               lengths.",
+              label,
+              run_stats.rejected_mismatched_quality);
+    }
+    info!("Run summary ({}): {} hits dropped below the minimum confidence, {} hits not in the \
+           taxid map.",
+          label,
+          run_stats.hits_below_confidence,
+          run_stats.unmapped_taxid_hits);
+    if run_stats.shard_files > 0 {
+        info!("Run summary ({}): {} taxid shard files written.",
+              label,
+              run_stats.shard_files);
+    }
+    info!("Run summary ({}): {} seeds searched, {} seed hits recorded.",
+          label,
+          stats.seeds_searched,
+          stats.seed_hits);
+    info!("Run summary ({}): {} bins reached the per-bin seed hit cap, {} seed hits dropped.",
+          label,
+          stats.capped_bins,
+          stats.seed_hits_capped);
+    info!("Run summary ({}): {} seeds only partially matched the index, {} of them used.",
+          label,
+          stats.partial_seeds,
+          stats.partial_seeds_used);
+    if stats.seeds_with_ns > 0 {
+        info!("Run summary ({}): {} seeds skipped for containing Ns.",
+              label,
+              stats.seeds_with_ns);
+    }
+    if stats.seed_hits_in_ns > 0 {
+        debug!("Run summary ({}): {} seed hits dropped for landing in the references' Ns.",
+               label,
+               stats.seed_hits_in_ns);
+    }
+    if run_stats.seed_hit_limited_reads > 0 {
+        warn!("Run summary ({}): {} reads reached the seed hit limit, {} of their seeds skipped.",
+              label,
+              run_stats.seed_hit_limited_reads,
+              stats.seeds_over_hit_limit);
+    }
+    if stats.reverse_strands_skipped > 0 {
+        info!("Run summary ({}): {} reads had enough forward hits to skip the reverse strand.",
+              label,
+              stats.reverse_strands_skipped);
+    }
+    if stats.prescreened > 0 {
+        info!("Run summary ({}): {} read strands skipped by the prescreen.",
+              label,
+              stats.prescreened);
+    }
+    if run_stats.slow_reads > 0 {
+        info!("Run summary ({}): {} slow reads written to the slow read log.",
+              label,
+              run_stats.slow_reads);
+    }
+    if !run_stats.edits.best.is_empty() {
+        info!("Run summary ({}): best edit distances 0-{} [{}], {} reads without hits.",
+              label,
+              run_stats.edits.best.len() - 1,
+              run_stats.edits.sparkline(),
+              run_stats.edits.no_hits);
+    }
+}
+
+/// Write the results for a single query read to the Writer specified.
+///
+/// Writes in the format `READ_ID:TAX_ID1,TAX_ID2,...`. Read header/ID is first, followed by a
+/// colon (':'), followed by a comma-separated list of taxonomic IDs (positive integers). Fails
+/// for read IDs which wouldn't be parsed back unchanged (see `io::check_read_id`).
+pub fn write_single_line<W: Write>(header: &str,
+                                   matches: &BTreeSet<TaxId>,
+                                   writer: &mut W)
+                                   -> MtsvResult<()> {
+    if matches.len() == 0 {
+        return Ok(());
+    }
+    check_read_id(header)?;
+
+    let mut result_line = String::from(header);
+    result_line.push(':');
+
+    let mut matches_peek = matches.iter().peekable();
+    for tax_id in matches {
+        let _ = matches_peek.next();
+
+        result_line.push_str(&tax_id.0.to_string());
+
+        if let Some(_) = matches_peek.peek() {
+            result_line.push(',');
+        }
+    }
+    result_line.push('\n');
+    writer.write(result_line.as_bytes())?;
+    Ok(())
+}
+
+/// Get all reference sequences for given taxid from index
+///
+/// Writes to fasta file with headers giving each reference's GI, labelled if it's synthetic,
+/// taxid and where it is in the index, and its accession and original description if the index
+/// has them (see `util::reference_header`)
+pub fn get_reference_sequences_from_index(
+    index_path: &str,
+    results_path: &str,
+    taxids: Vec<u64>) -> MtsvResult<()> {
+    extract_references(index_path, results_path, &taxids, false).map(|_| ())
+}
+
+/// Write the reference sequences of each of `taxids` from the index at `index_path`, loading it
+/// once and going over its references once, in index order. Each record's header gives the
+/// reference's GI, labelled if it's synthetic, taxid and where it is in the index, and its
+/// accession and original description if the index has them (see `util::reference_header`).
+///
+/// The sequences are written to the FASTA file `results_path`, or with `split`, to a file
+/// `TAXID.fasta` for each taxid in the directory `results_path`, which is created if need be.
+/// Returns the taxids with no references in the index, which are also logged, and which get no
+/// file of their own.
+pub fn extract_references(index_path: &str,
+                          results_path: &str,
+                          taxids: &[u64],
+                          split: bool)
+                          -> MtsvResult<Vec<u64>> {
+    let wanted = taxids.iter().cloned().collect::<HashSet<_>>();
+
+    info!("Loading candidate filter: {}", index_path);
+    let filter = LoadedIndex::open(index_path)?;
+    let synthetic_gis = filter.synthetic_gis();
+
+    if split {
+        fs::create_dir_all(results_path)?;
+    }
+    let create = |name: &Path| -> MtsvResult<_> {
+        Ok(fasta::Writer::new(BufWriter::new(File::create(name)?)))
+    };
+    let mut writer = if split { None } else { Some(create(Path::new(results_path))?) };
+    let mut written = HashMap::new();
+    // bins are in taxid order, so each taxid's split file is finished before the next is opened
+    for bin in filter.bins().filter(|bin| wanted.contains(&bin.tax_id.0)) {
+        let count = written.entry(bin.tax_id.0).or_insert(0);
+        if split && *count == 0 {
+            if let Some(mut previous) = writer.take() {
+                previous.flush()?;
+            }
+            let path = Path::new(results_path).join(format!("{}.fasta", bin.tax_id.0));
+            writer = Some(create(&path)?);
+        }
+        *count += 1;
+
+        let (id, desc) = reference_header(&bin,
+                                          synthetic_gis,
+                                          filter.accession(bin.gi),
+                                          filter.description(bin.gi));
+        let seq = filter.reference_bases(bin.start, bin.end);
+        writer.as_mut()
+            .expect("A file is open for every taxid's references")
+            .write(&id, Some(&desc), &seq)?;
+    }
+    if let Some(mut writer) = writer {
+        writer.flush()?;
+    }
+
+    let mut missing = Vec::new();
+    for &taxid in taxids {
+        match written.get(&taxid) {
+            Some(count) => info!("Wrote {} reference sequences for taxid: {}", count, taxid),
+            None if !missing.contains(&taxid) => missing.push(taxid),
+            None => (),
+        }
+    }
+    if !missing.is_empty() {
+        warn!("No reference sequences for {} taxids: {}",
+              missing.len(),
+              missing.iter().map(|t| t.to_string()).collect::<Vec<_>>().join(", "));
+    }
+    info!("Sequences written to: {}", results_path);
+    Ok(missing)
+}
+
+/// Write every reference sequence in the index at `index_path`, or with `taxids` only theirs, to
+/// the FASTA file `results_path` in index order, as `GI-TAXID` records which `mtsv-build` builds
+/// back into an equivalent index, with the same references, GIs and fingerprint. Each record keeps
+/// its original description if the index has them. The references are written one at a time, so
+/// the index is the only thing held in memory. Returns the number of references written.
+///
+/// The sequences are as the index holds them, capitalized and with other bases made its wildcard,
+/// and an index with a bin per taxid (see `BinMode::PerTaxon`) has a record per taxid, spacers and
+/// all. An index whose GIs were assigned at build time (see `MGIndex::synthetic_gis`) has just the
+/// taxid as each record's ID, to be built with `mtsv-build --header-format taxid`, which assigns
+/// them afresh, so that the GIs aren't taken for real ones.
+pub fn dump_references(index_path: &str,
+                       results_path: &str,
+                       taxids: Option<&HashSet<TaxId>>)
+                       -> MtsvResult<usize> {
+    info!("Loading candidate filter: {}", index_path);
+    let filter = LoadedIndex::open(index_path)?;
+
+    let mut writer = fasta::Writer::new(BufWriter::new(File::create(Path::new(results_path))?));
+    let synthetic_gis = filter.synthetic_gis();
+    let mut written = 0;
+    for bin in filter.bins().filter(|bin| taxids.is_none_or(|t| t.contains(&bin.tax_id))) {
+        let id = if synthetic_gis {
+            bin.tax_id.0.to_string()
+        } else {
+            format!("{}-{}", bin.gi.0, bin.tax_id.0)
+        };
+        writer.write(&id, filter.description(bin.gi), &filter.reference_bases(bin.start, bin.end))?;
+        written += 1;
+    }
+    writer.flush()?;
+    info!("Wrote {} reference sequences to: {}", written, results_path);
+    Ok(written)
+}
+
+/// Get the reference sequence with this GI from the index (see `MGIndex::get_reference_by_gi`).
+///
+/// Writes a FASTA file of the one record, with the same header as `extract_references` writes.
+/// Fails without writing anything if no reference has the GI.
+pub fn get_reference_sequence_by_gi_from_index(index_path: &str,
+                                               results_path: &str,
+                                               gi: u32)
+                                               -> MtsvResult<()> {
+    info!("Loading candidate filter: {}", index_path);
+    let filter = LoadedIndex::open(index_path)?;
+    let (bin, seq) = match (filter.bins().find(|bin| bin.gi.0 == gi),
+                            filter.get_reference_by_gi(gi)) {
+        (Some(bin), Some(seq)) => (bin, seq),
+        _ => return Err(MtsvError::MissingGi(index_path.to_string(), gi)),
+    };
+
+    let mut writer = fasta::Writer::new(BufWriter::new(File::create(Path::new(results_path))?));
+    let (id, desc) = reference_header(&bin,
+                                      filter.synthetic_gis(),
+                                      filter.accession(bin.gi),
+                                      filter.description(bin.gi));
+    writer.write(&id, Some(&desc), &seq)?;
+    writer.flush()?;
+    info!("Sequence written to file: {}", results_path);
+    Ok(())
+}
+
+
+/// Write the results for a single read to the Writer specified.
+///
+/// Writes in the format `READ_ID:TAX_ID1=EDIT,TAX_ID2=EDIT,...`. Read header/ID is first, followed by a
+/// colon (':'), followed by a comma-separated list of taxonomic IDs (positive integers) with their
+/// edit distances (positive integers) separated by equal sign ('='). Fails for read IDs which
+/// wouldn't be parsed back unchanged (see `io::check_read_id`).
+pub fn write_edit_distances<W: Write>(header: &str,
+            hits: &[Hit],
+            writer: &mut W)
+            -> MtsvResult<()> {
+    FindingsWriter::new(writer).write_hits(header, hits)
+}
+
+
+#[cfg(test)]
+mod test {
+    use ::index::{convert_to_mapped, random_database, BinMode, Database, Gi, MGIndex, TaxId};
+    use io::{parse_edit_distance_findings, parse_findings, write_to_file,
+             write_to_file_compressed};
+    use mktemp::Temp;
+    use rand::{Rng, XorShiftRng};
+    use std::collections::{BTreeMap, BTreeSet};
+    use std::str;
+    use super::*;
+    use util::{parse_read_header, parse_reference_header};
+
+    fn test_write(header: &str, matches: &BTreeSet<TaxId>, expected: &str) {
+        let mut buf = Vec::new();
+
+        write_single_line(header, matches, &mut buf).unwrap();
+
+        let found = String::from_utf8(buf).unwrap();
+
+        assert_eq!(expected, &found);
+    }
+
+
+    #[test]
+    fn success_many() {
+        let header = "R1_1_0_0";
+        let mut matches = BTreeSet::new();
+        matches.insert(TaxId(12345));
+        matches.insert(TaxId(5678));
+        matches.insert(TaxId(0));
+
+        let expected = "R1_1_0_0:0,5678,12345\n";
+
+        test_write(header, &matches, expected);
+    }
+
+    #[test]
+    fn success_single_spaces() {
+        let header = "R1 1 0\t0";
+        let mut matches = BTreeSet::new();
+        matches.insert(TaxId(12345));
+
+        let expected = "R1 1 0\t0:12345\n";
+
+        test_write(header, &matches, expected);
+    }
+
+    #[test]
+    fn success_empty() {
+        let header = "R1_1_0_0";
+        let matches = BTreeSet::new();
+
+        let expected = "";
+
+        test_write(header, &matches, expected);
+    }
+
+    /// Records how many bytes have reached it at each flush.
+    #[derive(Default)]
+    struct FlushCounter {
+        written: usize,
+        flushed_at: Vec<usize>,
+    }
+
+    impl Write for FlushCounter {
+        fn write(&mut self, buf: &[u8]) -> ::std::io::Result<usize> {
+            self.written += buf.len();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> ::std::io::Result<()> {
+            self.flushed_at.push(self.written);
+            Ok(())
+        }
+    }
+
+    fn write_reads(flush_every: Option<usize>, num_reads: usize) -> FlushCounter {
+        let hits = vec![hit(1, 0)];
+        let mut writer = ResultWriter::new(FlushCounter::default(), flush_every);
+        for i in 0..num_reads {
+            // reads without hits write nothing but still count towards the cadence
+            if i % 2 == 0 {
+                writer.write_hits("R1", &hits).unwrap();
+            } else {
+                writer.write_hits("R2", &Vec::new()).unwrap();
+            }
+        }
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn flush_every_n_reads() {
+        let counter = write_reads(Some(2), 7);
+
+        // "R1:1=0\n" is 7 bytes, written for reads 0, 2, 4 and 6
+        assert_eq!(counter.flushed_at, vec![7, 14, 21, 28]);
+        assert_eq!(counter.written, 28);
+    }
+
+    #[test]
+    fn no_flush_policy() {
+        let counter = write_reads(None, 7);
+
+        // only the final flush when the writer is torn down
+        assert_eq!(counter.flushed_at, vec![28]);
+        assert_eq!(counter.written, 28);
+    }
+
+    fn random_seq<R: Rng>(rng: &mut R, len: usize) -> Vec<u8> {
+        (0..len).map(|_| *rng.choose(b"ACGT").unwrap()).collect()
+    }
+
+    fn parse_results(path: &Path) -> BTreeMap<String, BTreeMap<TaxId, u32>> {
+        let reader = BufReader::new(File::open(path).unwrap());
+        parse_edit_distance_findings(reader)
+            .map(|r| {
+                let (read_id, hits) = r.unwrap();
+                (read_id, hits.into_iter().map(|h| (h.tax_id, h.edit)).collect())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn multiple_indices_match_combined_index() {
+        let mut rng = XorShiftRng::new_unseeded();
+
+        let mut first = Database::new();
+        let mut second = Database::new();
+        for taxid in 0..8 {
+            let db = if taxid < 4 { &mut first } else { &mut second };
+            db.insert(TaxId(taxid as u64), vec![(Gi(taxid), random_seq(&mut rng, 400))]);
+        }
+        // a taxid present in both indices, matching with different edit distances
+        let shared = random_seq(&mut rng, 400);
+        let mut diverged = shared.clone();
+        diverged[120] = if diverged[120] == b'A' { b'C' } else { b'A' };
+        first.insert(TaxId(100), vec![(Gi(100), shared)]);
+        second.insert(TaxId(100), vec![(Gi(101), diverged)]);
+
+        let mut combined = first.clone();
+        for (taxid, seqs) in second.clone() {
+            combined.entry(taxid).or_insert_with(Vec::new).extend(seqs);
+        }
+
+        let dir = Temp::new_dir().unwrap();
+        let dir = dir.to_path_buf();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+
+        let mut reads = String::new();
+        for (taxid, seqs) in &combined {
+            for &(gi, ref seq) in seqs {
+                reads.push_str(&format!(">read_{}_{}\n", taxid.0, gi.0));
+                reads.push_str(str::from_utf8(&seq[100..200]).unwrap());
+                reads.push('\n');
+            }
+        }
+        reads.push_str(">unmatched\n");
+        reads.push_str(str::from_utf8(&random_seq(&mut rng, 100)).unwrap());
+        reads.push('\n');
+        File::create(path("reads.fasta")).unwrap().write_all(reads.as_bytes()).unwrap();
+
+        write_to_file(&MGIndex::new(first, 16, 32, BinMode::PerGi), &path("first.index")).unwrap();
+        write_to_file(&MGIndex::new(second, 16, 32, BinMode::PerGi),
+                      &path("second.index"))
+            .unwrap();
+        write_to_file(&MGIndex::new(combined, 16, 32, BinMode::PerGi),
+                      &path("combined.index"))
+            .unwrap();
+
+        let params = QueryParams { seed_gap: 4, ..QueryParams::default() };
+        let config = BinnerConfig::default();
+        bin_reads(&path("reads.fasta"),
+                  ReadFormat::Fasta,
+                  &[&path("combined.index")],
+                  &path("combined.txt"),
+                  2,
+                  &params,
+                  &config)
+            .unwrap();
+        bin_reads(&path("reads.fasta"),
+                  ReadFormat::Fasta,
+                  &[&path("first.index"), &path("second.index")],
+                  &path("merged.txt"),
+                  2,
+                  &params,
+                  &config)
+            .unwrap();
+
+        let expected = parse_results(Path::new(&path("combined.txt")));
+        let merged = parse_results(Path::new(&path("merged.txt")));
+        assert_eq!(expected.len(), 10);
+        assert_eq!(expected["read_100_101"][&TaxId(100)], 0);
+        assert_eq!(merged, expected);
+
+        // legacy results have the same hits whether or not they were converted after merging
+        let legacy_config = BinnerConfig {
+            findings_version: FindingsVersion::Legacy,
+            ..BinnerConfig::default()
+        };
+        for (indices, results) in vec![(vec![path("combined.index")], "combined.legacy"),
+                                       (vec![path("first.index"), path("second.index")],
+                                        "merged.legacy")] {
+            bin_reads(&path("reads.fasta"),
+                      ReadFormat::Fasta,
+                      &indices.iter().map(|p| p.as_str()).collect::<Vec<_>>(),
+                      &path(results),
+                      2,
+                      &params,
+                      &legacy_config)
+                .unwrap();
+        }
+        let parse_legacy = |name: &str| {
+            parse_findings(BufReader::new(File::open(path(name)).unwrap()))
+                .map(|r| r.unwrap())
+                .collect::<BTreeMap<_, _>>()
+        };
+        let legacy = parse_legacy("combined.legacy");
+        assert_eq!(parse_legacy("merged.legacy"), legacy);
+        assert_eq!(legacy.len(), expected.len());
+        for (read_id, hits) in &expected {
+            assert_eq!(legacy[read_id], hits.keys().cloned().collect::<BTreeSet<_>>());
+        }
+
+        // only the inputs, indices and results are left behind
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 8);
+    }
+
+    fn prepare(seq: &[u8], config: &BinnerConfig) -> Result<Vec<u8>, RejectReason> {
+        prepare_read(seq, Alphabet::Dna, AmbiguityPolicy::N, config, &mut RunStats::default())
+    }
+
+    fn n_fraction_config(max_n_fraction: f64) -> BinnerConfig {
+        BinnerConfig { max_n_fraction: Some(max_n_fraction), ..BinnerConfig::default() }
+    }
+
+    #[test]
+    fn n_fraction_boundary() {
+        // 2 of 8 bases are N
+        let seq = b"ACGTNNac";
+
+        assert_eq!(prepare(seq, &n_fraction_config(0.25)), Ok(b"ACGTNNAC".to_vec()));
+        assert_eq!(prepare(seq, &n_fraction_config(0.24)), Err(RejectReason::NContent));
+        assert_eq!(prepare(seq, &BinnerConfig::default()), Ok(b"ACGTNNAC".to_vec()));
+    }
+
+    #[test]
+    fn n_fraction_counts_normalized_bases() {
+        // ambiguity codes and masked bases become N, pushing this read over the threshold
+        let seq = b"ACGTNRY.";
+
+        assert_eq!(prepare(seq, &n_fraction_config(0.25)), Err(RejectReason::NContent));
+        assert_eq!(prepare(seq, &n_fraction_config(0.5)), Ok(b"ACGTNNNN".to_vec()));
+    }
+
+    #[test]
+    fn ambiguity_policies() {
+        let seq = b"ACGTNry.";
+        let config = n_fraction_config(0.25);
+        let prepare_with = |ambiguity, stats: &mut RunStats| {
+            prepare_read(seq, Alphabet::Dna, ambiguity, &config, stats)
+        };
+
+        let mut stats = RunStats::default();
+        assert_eq!(prepare_with(AmbiguityPolicy::N, &mut stats), Err(RejectReason::NContent));
+        // kept codes don't count as Ns
+        assert_eq!(prepare_with(AmbiguityPolicy::Expand, &mut stats), Ok(b"ACGTNRYN".to_vec()));
+        assert_eq!(prepare_with(AmbiguityPolicy::Reject, &mut stats),
+                   Err(RejectReason::Ambiguous));
+        let unambiguous = prepare_read(b"acgtn", Alphabet::Dna, AmbiguityPolicy::Reject, &config,
+                                       &mut stats);
+        assert_eq!(unambiguous, Ok(b"ACGTN".to_vec()));
+
+        stats.reject(RejectReason::Ambiguous);
+        assert_eq!(stats.rejected_ambiguous, 1);
+        assert_eq!(RejectReason::Ambiguous.name(), "ambiguous");
+    }
+
+    #[test]
+    fn rna_reads_match_dna() {
+        let mut rng = XorShiftRng::new_unseeded();
+        let reference = random_seq(&mut rng, 400);
+        let mut db = Database::new();
+        db.insert(TaxId(1), vec![(Gi(1), reference.clone())]);
+        let index = MGIndex::new(db, 16, 32, BinMode::PerGi);
+
+        let rna = reference[100..200]
+            .iter()
+            .map(|&b| if b == b'T' { b'U' } else { b.to_ascii_lowercase() })
+            .collect::<Vec<_>>();
+        let normalized = prepare(&rna, &BinnerConfig::default()).unwrap();
+        assert_eq!(normalized, &reference[100..200]);
+
+        let params = QueryParams { seed_gap: 4, ..QueryParams::default() };
+        let (hits, _) = query_read(&index.searcher(), None, &normalized, &params, None);
+        assert_eq!(hits.iter().map(|h| (h.tax_id, h.edit)).collect::<Vec<_>>(), [(TaxId(1), 0)]);
+    }
+
+    #[test]
+    fn n_content_rejects_written() {
+        let mut rng = XorShiftRng::new_unseeded();
+        let reference = random_seq(&mut rng, 400);
+        let mut db = Database::new();
+        db.insert(TaxId(1), vec![(Gi(1), reference.clone())]);
+
+        let dir = Temp::new_dir().unwrap();
+        let dir = dir.to_path_buf();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+
+        let clean = reference[100..200].to_vec();
+        let mut masked = clean.clone();
+        for b in masked.iter_mut().take(30) {
+            *b = b'N';
+        }
+        let mut reads = String::new();
+        for &(name, ref seq) in &[("clean", &clean), ("masked", &masked)] {
+            reads.push_str(&format!("@{}\n{}\n+\n{}\n",
+                                    name,
+                                    str::from_utf8(seq).unwrap(),
+                                    "I".repeat(seq.len())));
+        }
+        File::create(path("reads.fastq")).unwrap().write_all(reads.as_bytes()).unwrap();
+        write_to_file(&MGIndex::new(db, 16, 32, BinMode::PerGi), &path("test.index")).unwrap();
+
+        let params = QueryParams { seed_gap: 4, ..QueryParams::default() };
+        let config = BinnerConfig { rejects_path: Some(path("rejects.fasta")),
+                                    ..n_fraction_config(0.2) };
+        let (stats, _) = bin_file(&[&path("reads.fastq")],
+                                  ReadFormat::Fastq,
+                                  &path("test.index"),
+                                  &ResultsPaths::single(&path("results.txt")),
+                                  2,
+                                  &params,
+                                  &config)
+            .unwrap();
+
+        assert_eq!(stats.reads, 2);
+        assert_eq!(stats.rejected_n_content, 1);
+
+        let results = parse_results(Path::new(&path("results.txt")));
+        assert_eq!(results.keys().collect::<Vec<_>>(), vec!["clean"]);
+
+        let rejects = fasta::Reader::from_file(path("rejects.fasta"))
+            .unwrap()
+            .records()
+            .map(|r| r.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(rejects.len(), 1);
+        assert_eq!(rejects[0].id(), "masked");
+        assert_eq!(rejects[0].desc(), Some("reason=n_content"));
+        assert_eq!(rejects[0].seq(), &masked[..]);
+    }
+
+    #[test]
+    fn mismatched_quality_lengths() {
+        let mut rng = XorShiftRng::new_unseeded();
+        let reference = random_seq(&mut rng, 400);
+        let mut db = Database::new();
+        db.insert(TaxId(1), vec![(Gi(1), reference.clone())]);
+
+        let dir = Temp::new_dir().unwrap();
+        let dir = dir.to_path_buf();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+
+        // the middle record's quality line was truncated
+        let read = str::from_utf8(&reference[100..200]).unwrap();
+        let reads = format!("@good1\n{0}\n+\n{1}\n@short\n{0}\n+\n{2}\n@good2\n{0}\n+\n{1}\n",
+                            read,
+                            "I".repeat(100),
+                            "I".repeat(40));
+        File::create(path("reads.fastq")).unwrap().write_all(reads.as_bytes()).unwrap();
+        write_to_file(&MGIndex::new(db, 16, 32, BinMode::PerGi), &path("test.index")).unwrap();
+
+        let params = QueryParams { seed_gap: 4, ..QueryParams::default() };
+        let bin = |config: &BinnerConfig| {
+            bin_file(&[&path("reads.fastq")],
+                     ReadFormat::Fastq,
+                     &path("test.index"),
+                     &ResultsPaths::single(&path("results.txt")),
+                     2,
+                     &params,
+                     config)
+        };
+
+        let (stats, _) = bin(&BinnerConfig::default()).unwrap();
+        assert_eq!(stats.reads, 2);
+        assert_eq!(stats.rejected_mismatched_quality, 1);
+        let results = parse_results(Path::new(&path("results.txt")));
+        assert_eq!(results.keys().collect::<Vec<_>>(), vec!["good1", "good2"]);
+
+        match bin(&BinnerConfig { strict_reads: true, ..BinnerConfig::default() }) {
+            Err(MtsvError::MismatchedQuality(ref id, 100, 40)) => assert_eq!(id, "short"),
+            other => panic!("expected a mismatched quality error, found {:?}", other),
+        }
+    }
+
+    fn dust_config(mask: bool) -> BinnerConfig {
+        BinnerConfig {
+            low_complexity: Some(DustParams::default()),
+            mask_low_complexity: mask,
+            ..BinnerConfig::default()
+        }
+    }
+
+    #[test]
+    fn low_complexity_filtered_or_masked() {
+        let mut seq = b"acgtTGCAGGATCCATGACTTAGCAAGTCGATCGGATAC".to_vec();
+        seq.extend(vec![b'A'; 40]);
+
+        assert_eq!(prepare(&seq, &dust_config(false)), Err(RejectReason::LowComplexity));
+
+        let mut stats = RunStats::default();
+        let masked = prepare_read(&seq, Alphabet::Dna, AmbiguityPolicy::N, &dust_config(true),
+                                  &mut stats)
+            .unwrap();
+        assert_eq!(stats.masked_low_complexity, 1);
+        assert!(masked.iter().all(|&b| b == b'N'));
+
+        // masking the whole read pushes it over any N content threshold
+        let config = BinnerConfig { max_n_fraction: Some(0.5), ..dust_config(true) };
+        assert_eq!(prepare(&seq, &config), Err(RejectReason::NContent));
+
+        let mut rng = XorShiftRng::new_unseeded();
+        let random = random_seq(&mut rng, 100);
+        assert_eq!(prepare(&random, &dust_config(false)), Ok(random.clone()));
+        let mut stats = RunStats::default();
+        assert_eq!(prepare_read(&random, Alphabet::Dna, AmbiguityPolicy::N, &dust_config(true),
+                                &mut stats),
+                   Ok(random.clone()));
+        assert_eq!(stats.masked_low_complexity, 0);
+    }
+
+    #[test]
+    fn low_complexity_counters() {
+        let mut rng = XorShiftRng::new_unseeded();
+        let reference = random_seq(&mut rng, 400);
+        let mut db = Database::new();
+        db.insert(TaxId(1), vec![(Gi(1), reference.clone())]);
+
+        let dir = Temp::new_dir().unwrap();
+        let dir = dir.to_path_buf();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+
+        let mut poly_a_tail = reference[100..170].to_vec();
+        poly_a_tail.extend(vec![b'A'; 30]);
+        let reads = format!(">clean\n{}\n>poly_a_tail\n{}\n>repeat\n{}\n",
+                            str::from_utf8(&reference[200..300]).unwrap(),
+                            str::from_utf8(&poly_a_tail).unwrap(),
+                            "AT".repeat(50));
+        File::create(path("reads.fasta")).unwrap().write_all(reads.as_bytes()).unwrap();
+        write_to_file(&MGIndex::new(db, 16, 32, BinMode::PerGi), &path("test.index")).unwrap();
+
+        let params = QueryParams { seed_gap: 4, ..QueryParams::default() };
+        let run = |config: &BinnerConfig| {
+            bin_file(&[&path("reads.fasta")],
+                     ReadFormat::Fasta,
+                     &path("test.index"),
+                     &ResultsPaths::single(&path("results.txt")),
+                     2,
+                     &params,
+                     config)
+                .unwrap()
+                .0
+        };
+
+        let filtered = run(&dust_config(false));
+        assert_eq!(filtered.reads, 3);
+        assert_eq!(filtered.rejected_low_complexity, 2);
+        assert_eq!(filtered.masked_low_complexity, 0);
+        assert!(parse_results(Path::new(&path("results.txt"))).contains_key("clean"));
+
+        let masked = run(&dust_config(true));
+        assert_eq!(masked.reads, 3);
+        assert_eq!(masked.rejected_low_complexity, 0);
+        assert_eq!(masked.masked_low_complexity, 2);
+        assert!(parse_results(Path::new(&path("results.txt"))).contains_key("clean"));
+    }
+
+    #[test]
+    fn min_confidence_drops_ambiguous_hits() {
+        let mut rng = XorShiftRng::new_unseeded();
+        let unique = random_seq(&mut rng, 400);
+        let shared = random_seq(&mut rng, 400);
+        let mut db = Database::new();
+        db.insert(TaxId(1), vec![(Gi(1), unique.clone()), (Gi(2), shared.clone())]);
+        db.insert(TaxId(2), vec![(Gi(3), shared.clone())]);
+
+        let dir = Temp::new_dir().unwrap();
+        let dir = dir.to_path_buf();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+
+        let reads = format!(">unique\n{}\n>shared\n{}\n",
+                            str::from_utf8(&unique[100..200]).unwrap(),
+                            str::from_utf8(&shared[100..200]).unwrap());
+        File::create(path("reads.fasta")).unwrap().write_all(reads.as_bytes()).unwrap();
+        write_to_file(&MGIndex::new(db, 16, 32, BinMode::PerGi), &path("test.index")).unwrap();
+
+        let params = QueryParams { seed_gap: 4, ..QueryParams::default() };
+        let run = |min_confidence| {
+            let config = BinnerConfig { min_confidence, ..BinnerConfig::default() };
+            let (stats, _) = bin_file(&[&path("reads.fasta")],
+                                      ReadFormat::Fasta,
+                                      &path("test.index"),
+                                      &ResultsPaths::single(&path("results.txt")),
+                                      2,
+                                      &params,
+                                      &config)
+                .unwrap();
+            (stats, parse_results(Path::new(&path("results.txt"))))
+        };
+
+        let (stats, results) = run(None);
+        assert_eq!(stats.hits_below_confidence, 0);
+        assert_eq!(results["shared"].len(), 2);
+
+        // the shared read's hits lose half of the multiplicity component
+        let (stats, results) = run(Some(0.9));
+        assert_eq!(stats.hits_below_confidence, 2);
+        assert_eq!(results.keys().collect::<Vec<_>>(), vec!["unique"]);
+    }
+
+    #[test]
+    fn checkpoint_matches_counters() {
+        let mut rng = XorShiftRng::new_unseeded();
+        let reference = random_seq(&mut rng, 400);
+        let mut db = Database::new();
+        db.insert(TaxId(1), vec![(Gi(1), reference.clone())]);
+
+        let dir = Temp::new_dir().unwrap();
+        let dir = dir.to_path_buf();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        write_to_file(&MGIndex::new(db, 16, 32, BinMode::PerGi), &path("test.index")).unwrap();
+
+        let reads = (0..5)
+            .map(|i| (format!("read_{}", i), reference[i * 50..i * 50 + 100].to_vec()))
+            .collect::<Vec<_>>();
+
+        let params = QueryParams { seed_gap: 4, ..QueryParams::default() };
+        let config = BinnerConfig {
+            checkpoint_every: Some(2),
+            run: RunContext::new(Some(7)),
+            ..BinnerConfig::default()
+        };
+
+        // a bounded run ends with a final checkpoint
+        let (stats, _) = write_matching_bin_ids(vec![reads.clone().into_iter().map(Ok)],
+                                                &path("test.index"),
+                                                &ResultsPaths::single(&path("results.txt")),
+                                                1,
+                                                &params,
+                                                &config)
+            .unwrap();
+        let checkpoint = Checkpoint::read(&checkpoint_path(&path("results.txt"))).unwrap();
+        assert_eq!(checkpoint.reads_processed, 5);
+        assert_eq!(checkpoint.last_read_id, Some("read_4".to_string()));
+        assert!(checkpoint.finished);
+        assert_eq!(checkpoint.stats, stats);
+        assert_eq!(checkpoint.seed, 7);
+        assert!(!Path::new(&format!("{}.tmp", checkpoint_path(&path("results.txt")))).exists());
+
+        // a run which fails part way through leaves the last periodic checkpoint behind, which
+        // agrees with what was flushed to the results file
+        let failing = reads.into_iter()
+            .take(3)
+            .map(Ok)
+            .chain(Some(Err(MtsvError::MissingHeader)));
+        assert!(write_matching_bin_ids(vec![failing],
+                                       &path("test.index"),
+                                       &ResultsPaths::single(&path("failed.txt")),
+                                       1,
+                                       &params,
+                                       &config)
+            .is_err());
+        let checkpoint = Checkpoint::read(&checkpoint_path(&path("failed.txt"))).unwrap();
+        assert_eq!(checkpoint.reads_processed, 2);
+        assert_eq!(checkpoint.stats.reads, 2);
+        assert_eq!(checkpoint.last_read_id, Some("read_1".to_string()));
+        assert!(!checkpoint.finished);
+        assert!(checkpoint.stats.query.seeds_searched > 0);
+        assert!(parse_results(Path::new(&path("failed.txt"))).contains_key("read_1"));
+    }
+
+    #[test]
+    fn control_characters_in_read_ids() {
+        let mut rng = XorShiftRng::new_unseeded();
+        let reference = random_seq(&mut rng, 400);
+        let mut db = Database::new();
+        db.insert(TaxId(1), vec![(Gi(1), reference.clone())]);
+
+        let dir = Temp::new_dir().unwrap();
+        let dir = dir.to_path_buf();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        write_to_file(&MGIndex::new(db, 16, 32, BinMode::PerGi), &path("test.index")).unwrap();
+
+        // the last read is skipped for its length, and written to the rejects with a sanitized ID
+        let reads = vec![("read_0 glued\ndescription".to_string(), reference[..100].to_vec()),
+                         ("read_1".to_string(), reference[100..200].to_vec()),
+                         ("read\r2".to_string(), reference[200..].to_vec())];
+        let params = QueryParams { seed_gap: 4, ..QueryParams::default() };
+        let config = BinnerConfig {
+            max_read_length: Some(150),
+            rejects_path: Some(path("rejects.fasta")),
+            ..BinnerConfig::default()
+        };
+
+        let (stats, _) = write_matching_bin_ids(vec![reads.clone().into_iter().map(Ok)],
+                                                &path("test.index"),
+                                                &ResultsPaths::single(&path("results.txt")),
+                                                2,
+                                                &params,
+                                                &config)
+            .unwrap();
+        assert_eq!(stats.sanitized_read_ids, 2);
+        let results = parse_results(Path::new(&path("results.txt")));
+        assert_eq!(results.keys().map(|k| k.as_str()).collect::<Vec<_>>(),
+                   vec!["read_0 glued_description", "read_1"]);
+        let rejects = fs::read_to_string(path("rejects.fasta")).unwrap();
+        assert!(rejects.starts_with(">read_2 reason=too_long\n"));
+
+        let rejecting = BinnerConfig { control_chars: ControlCharPolicy::Reject, ..config };
+        match write_matching_bin_ids(vec![reads.into_iter().map(Ok)],
+                                     &path("test.index"),
+                                     &ResultsPaths::single(&path("rejected.txt")),
+                                     2,
+                                     &params,
+                                     &rejecting) {
+            Err(MtsvError::InvalidReadId(_)) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn exclusion_index_depletes_reads() {
+        let mut rng = XorShiftRng::new_unseeded();
+        let host = random_seq(&mut rng, 600);
+        let microbe = random_seq(&mut rng, 600);
+
+        let dir = Temp::new_dir().unwrap();
+        let dir = dir.to_path_buf();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        let mut db = Database::new();
+        db.insert(TaxId(9606), vec![(Gi(1), host.clone())]);
+        let exclusion = db.clone();
+        db.insert(TaxId(2), vec![(Gi(2), microbe.clone())]);
+        write_to_file(&MGIndex::new(db, 16, 32, BinMode::PerGi), &path("main.index")).unwrap();
+        write_to_file(&MGIndex::new(exclusion, 16, 32, BinMode::PerGi),
+                      &path("host.index"))
+            .unwrap();
+
+        // host reads with up to 3 edits are still excluded, as is the reverse complement
+        let mut reads = String::new();
+        for i in 0..3 {
+            let mut seq = host[i * 150..i * 150 + 100].to_vec();
+            for j in 0..i {
+                seq[30 * j + 10] = if seq[30 * j + 10] == b'A' { b'C' } else { b'A' };
+            }
+            reads.push_str(&format!(">host_{}\n{}\n", i, str::from_utf8(&seq).unwrap()));
+        }
+        let host_rc = revcomp(&host[450..550]);
+        reads.push_str(&format!(">host_rc\n{}\n", str::from_utf8(&host_rc).unwrap()));
+        for i in 0..2 {
+            reads.push_str(&format!(">microbe_{}\n{}\n",
+                                    i,
+                                    str::from_utf8(&microbe[i * 200..i * 200 + 100]).unwrap()));
+        }
+        File::create(path("reads.fasta")).unwrap().write_all(reads.as_bytes()).unwrap();
+
+        let params = QueryParams { seed_gap: 4, ..QueryParams::default() };
+        let config = BinnerConfig {
+            exclusion_index: Some(path("host.index")),
+            exclusion_edit_rate: 0.05,
+            excluded_reads_path: Some(path("excluded.fasta")),
+            rejects_path: Some(path("rejects.fasta")),
+            ..BinnerConfig::default()
+        };
+        let (stats, _) = bin_file(&[&path("reads.fasta")],
+                                  ReadFormat::Fasta,
+                                  &path("main.index"),
+                                  &ResultsPaths::single(&path("results.txt")),
+                                  2,
+                                  &params,
+                                  &config)
+            .unwrap();
+
+        assert_eq!(stats.reads, 6);
+        assert_eq!(stats.rejected_excluded, 4);
+        let results = parse_results(Path::new(&path("results.txt")));
+        assert_eq!(results.keys().map(|k| k.as_str()).collect::<Vec<_>>(),
+                   vec!["microbe_0", "microbe_1"]);
+        assert!(results.values().all(|hits| hits.keys().collect::<Vec<_>>() == vec![&TaxId(2)]));
+
+        let mut excluded = fasta::Reader::from_file(path("excluded.fasta"))
+            .unwrap()
+            .records()
+            .map(|r| r.unwrap().id().to_string())
+            .collect::<Vec<_>>();
+        excluded.sort();
+        assert_eq!(excluded, vec!["host_0", "host_1", "host_2", "host_rc"]);
+        assert_eq!(fs::read_to_string(path("rejects.fasta")).unwrap(), "");
+
+        // mapped indices bin and exclude the same reads
+        convert_to_mapped(&path("main.index"), &path("main.mapped")).unwrap();
+        convert_to_mapped(&path("host.index"), &path("host.mapped")).unwrap();
+        let mapped = BinnerConfig { exclusion_index: Some(path("host.mapped")), ..config };
+        let (stats, _) = bin_file(&[&path("reads.fasta")],
+                                  ReadFormat::Fasta,
+                                  &path("main.mapped"),
+                                  &ResultsPaths::single(&path("mapped.txt")),
+                                  2,
+                                  &params,
+                                  &mapped)
+            .unwrap();
+        assert_eq!(stats.rejected_excluded, 4);
+        assert_eq!(parse_results(Path::new(&path("mapped.txt"))), results);
+
+        // without the exclusion index, the host reads are binned to the host
+        let (stats, _) = bin_file(&[&path("reads.fasta")],
+                                  ReadFormat::Fasta,
+                                  &path("main.index"),
+                                  &ResultsPaths::single(&path("results.txt")),
+                                  2,
+                                  &params,
+                                  &BinnerConfig::default())
+            .unwrap();
+        assert_eq!(stats.rejected_excluded, 0);
+        assert_eq!(parse_results(Path::new(&path("results.txt")))["host_2"].keys().next(),
+                   Some(&TaxId(9606)));
+    }
+
+    #[test]
+    fn edit_histogram_counts() {
+        let mut histogram = EditHistogram::default();
+        histogram.add_read(&[hit(1, 2), hit(2, 0), hit(3, 2)]);
+        histogram.add_read(&[hit(1, 3)]);
+        histogram.add_read(&[]);
+        assert_eq!(histogram.best, vec![1, 0, 0, 1]);
+        assert_eq!(histogram.hits, vec![1, 0, 2, 1]);
+        assert_eq!(histogram.no_hits, 1);
+
+        let mut other = EditHistogram::default();
+        for _ in 0..6 {
+            other.add_read(&[hit(1, 1)]);
+        }
+        histogram.add(&other);
+        assert_eq!(histogram.best, vec![1, 6, 0, 1]);
+        assert_eq!(histogram.sparkline(), "▂█ ▂");
+
+        let mut tsv = Vec::new();
+        histogram.write_tsv(&mut tsv, 4).unwrap();
+        assert_eq!(String::from_utf8(tsv).unwrap(),
+                   "edits\treads\thits\n0\t1\t1\n1\t6\t6\n2\t0\t2\n3\t1\t1\n4\t0\t0\n");
+    }
+
+    #[test]
+    fn edit_histogram_written() {
+        let mut rng = XorShiftRng::new_unseeded();
+        let reference = random_seq(&mut rng, 400);
+        let mut variant = reference[..200].to_vec();
+        variant[60] = if variant[60] == b'A' { b'C' } else { b'A' };
+
+        let dir = Temp::new_dir().unwrap();
+        let dir = dir.to_path_buf();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        let mut one = Database::new();
+        one.insert(TaxId(1), vec![(Gi(1), reference.clone())]);
+        let mut two = Database::new();
+        two.insert(TaxId(2), vec![(Gi(2), variant)]);
+        let mut combined = one.clone();
+        combined.extend(two.clone());
+        write_to_file(&MGIndex::new(one, 16, 32, BinMode::PerGi), &path("one.index")).unwrap();
+        write_to_file(&MGIndex::new(two, 16, 32, BinMode::PerGi), &path("two.index")).unwrap();
+        write_to_file(&MGIndex::new(combined, 16, 32, BinMode::PerGi),
+                      &path("combined.index"))
+            .unwrap();
+
+        // an exact read which is 1 edit from the variant, a read 2 edits from the reference, and
+        // a read which matches nothing
+        let mut edited = reference[200..300].to_vec();
+        for &i in &[20, 70] {
+            edited[i] = if edited[i] == b'A' { b'C' } else { b'A' };
+        }
+        let reads = vec![reference[50..150].to_vec(), edited, random_seq(&mut rng, 100)];
+        let mut fasta = String::new();
+        for (i, seq) in reads.iter().enumerate() {
+            fasta.push_str(&format!(">read_{}\n{}\n", i, str::from_utf8(seq).unwrap()));
+        }
+        File::create(path("reads.fasta")).unwrap().write_all(fasta.as_bytes()).unwrap();
+
+        let params = QueryParams { seed_gap: 4, edit_freq: 0.05, ..QueryParams::default() };
+        let expected = "edits\treads\thits\n0\t1\t1\n1\t0\t1\n2\t1\t1\n3\t0\t0\n4\t0\t0\n\
+                        5\t0\t0\n";
+        let config = BinnerConfig {
+            edit_histogram: Some(path("edits.tsv")),
+            ..BinnerConfig::default()
+        };
+        // against several indices, the histogram is of the merged results
+        for indices in &[vec![path("combined.index")], vec![path("one.index"), path("two.index")]] {
+            bin_reads(&path("reads.fasta"),
+                      ReadFormat::Fasta,
+                      &indices.iter().map(|p| p.as_str()).collect::<Vec<_>>(),
+                      &path("results.txt"),
+                      2,
+                      &params,
+                      &config)
+                .unwrap();
+            assert_eq!(fs::read_to_string(path("edits.tsv")).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn input_reads_order_and_progress() {
+        let input = |name: &str, n: usize| {
+            (0..n).map(|i| Ok((format!("{}{}", name, i), b"ACGT".to_vec()))).collect::<Vec<_>>()
+        };
+        let read = |interleave: bool, take: usize| {
+            let progress = InputProgress::new(3);
+            let ids = InputReads::new(vec![input("a", 3), input("b", 1), input("c", 4)]
+                                          .into_iter()
+                                          .map(|i| i.into_iter())
+                                          .collect(),
+                                      interleave,
+                                      &progress)
+                .take(take)
+                .map(|r| {
+                    let (i, id, _) = r.unwrap();
+                    format!("{}:{}", i, id)
+                })
+                .collect::<Vec<_>>();
+            (ids, progress.finished(&[3, 1, 4]))
+        };
+
+        assert_eq!(read(false, 10),
+                   (vec!["0:a0", "0:a1", "0:a2", "1:b0", "2:c0", "2:c1", "2:c2", "2:c3"]
+                        .into_iter()
+                        .map(String::from)
+                        .collect(),
+                    vec![true, true, true]));
+        assert_eq!(read(true, 10).0,
+                   vec!["0:a0", "1:b0", "2:c0", "0:a1", "2:c1", "0:a2", "2:c2", "2:c3"]);
+
+        // a file isn't finished until it's been read to the end and its reads processed
+        let (ids, finished) = read(true, 4);
+        assert_eq!(ids.len(), 4);
+        assert_eq!(finished, vec![false, false, false]);
+        let (_, finished) = read(true, 6);
+        assert_eq!(finished, vec![false, true, false]);
+        let progress = InputProgress::new(1);
+        assert_eq!(InputReads::new(vec![input("a", 2).into_iter()], false, &progress).count(), 2);
+        assert_eq!(progress.finished(&[1]), vec![false]);
+        assert_eq!(progress.finished(&[2]), vec![true]);
+    }
+
+    #[test]
+    fn interleaved_inputs_counted_per_file() {
+        let mut rng = XorShiftRng::new_unseeded();
+        let reference = random_seq(&mut rng, 400);
+        let mut db = Database::new();
+        db.insert(TaxId(1), vec![(Gi(1), reference.clone())]);
+
+        let dir = Temp::new_dir().unwrap();
+        let dir = dir.to_path_buf();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        write_to_file(&MGIndex::new(db, 16, 32, BinMode::PerGi), &path("test.index")).unwrap();
+
+        // the empty file counts as an input, and doesn't stop the others being read
+        for &(name, n) in &[("a", 2), ("b", 4), ("c", 0)] {
+            let mut reads = String::new();
+            for i in 0..n {
+                reads.push_str(&format!(">{}{}\n{}\n",
+                                        name,
+                                        i,
+                                        str::from_utf8(&reference[i * 50..i * 50 + 100])
+                                            .unwrap()));
+            }
+            File::create(path(&format!("{}.fasta", name)))
+                .unwrap()
+                .write_all(reads.as_bytes())
+                .unwrap();
+        }
+
+        let params = QueryParams { seed_gap: 4, ..QueryParams::default() };
+        let run = |interleave_inputs: bool| {
+            let config = BinnerConfig {
+                interleave_inputs,
+                checkpoint_every: Some(100),
+                ..BinnerConfig::default()
+            };
+            let (stats, _) = bin_file(&[&path("a.fasta"), &path("b.fasta"), &path("c.fasta")],
+                                      ReadFormat::Fasta,
+                                      &path("test.index"),
+                                      &ResultsPaths::single(&path("results.txt")),
+                                      1,
+                                      &params,
+                                      &config)
+                .unwrap();
+            assert_eq!(stats.reads, 6);
+            assert_eq!(stats.input_reads, vec![2, 4, 0]);
+            let checkpoint = Checkpoint::read(&checkpoint_path(&path("results.txt"))).unwrap();
+            assert_eq!(checkpoint.inputs_finished, vec![true, true, true]);
+
+            fs::read_to_string(path("results.txt"))
+                .unwrap()
+                .lines()
+                .map(|l| {
+                    assert!(l.ends_with(":1=0"));
+                    l.split(':').next().unwrap().to_string()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(run(false), vec!["a0", "a1", "b0", "b1", "b2", "b3"]);
+        assert_eq!(run(true), vec!["a0", "b0", "a1", "b1", "b2", "b3"]);
+    }
+
+    #[test]
+    fn results_dir_per_input() {
+        let mut rng = XorShiftRng::new_unseeded();
+        let references = [random_seq(&mut rng, 400), random_seq(&mut rng, 400)];
+        let mut first = Database::new();
+        first.insert(TaxId(1), vec![(Gi(1), references[0].clone())]);
+        let mut second = Database::new();
+        second.insert(TaxId(2), vec![(Gi(2), references[1].clone())]);
+        let mut combined = first.clone();
+        combined.extend(second.clone());
+
+        let dir = Temp::new_dir().unwrap();
+        let dir = dir.to_path_buf();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        write_to_file(&MGIndex::new(first, 16, 32, BinMode::PerGi), &path("first.index")).unwrap();
+        write_to_file(&MGIndex::new(second, 16, 32, BinMode::PerGi),
+                      &path("second.index"))
+            .unwrap();
+        write_to_file(&MGIndex::new(combined, 16, 32, BinMode::PerGi),
+                      &path("combined.index"))
+            .unwrap();
+
+        // both samples use the same read IDs, each for reads from a different reference
+        for (name, reference, n) in vec![("gut.fasta", &references[0], 2),
+                                         ("skin.fa", &references[1], 3)] {
+            let mut reads = String::new();
+            for i in 0..n {
+                reads.push_str(&format!(">r{}\n{}\n",
+                                        i,
+                                        str::from_utf8(&reference[i * 50..i * 50 + 100])
+                                            .unwrap()));
+            }
+            File::create(path(name)).unwrap().write_all(reads.as_bytes()).unwrap();
+        }
+
+        let params = QueryParams { seed_gap: 4, ..QueryParams::default() };
+        let config = BinnerConfig { interleave_inputs: true, ..BinnerConfig::default() };
+        for index_paths in &[vec![path("combined.index")],
+                             vec![path("first.index"), path("second.index")]] {
+            let results_dir = path("results");
+            bin_inputs_to_dir(&[&path("gut.fasta"), &path("skin.fa")],
+                              ReadFormat::Fasta,
+                              &index_paths.iter().map(|p| p.as_str()).collect::<Vec<_>>(),
+                              &results_dir,
+                              2,
+                              &params,
+                              &config)
+                .unwrap();
+
+            let results_path = |name: &str| dir.join("results").join(name);
+            let manifest: ResultsManifest =
+                serde_json::from_reader(File::open(results_path("manifest.json")).unwrap())
+                    .unwrap();
+            assert_eq!(manifest.seed, config.run.seed);
+            assert_eq!(manifest.inputs.len(), 2);
+
+            for (entry, &(tax_id, n)) in manifest.inputs.iter().zip(&[(1, 2), (2, 3)]) {
+                let results = parse_results(&results_path(&entry.results));
+                assert_eq!(results.len(), n);
+                for i in 0..n {
+                    assert_eq!(results[&format!("r{}", i)],
+                               vec![(TaxId(tax_id), 0)].into_iter().collect());
+                }
+
+                let stats: RunStats =
+                    serde_json::from_reader(File::open(results_path(&entry.summary)).unwrap())
+                        .unwrap();
+                assert_eq!(stats.reads, n);
+                assert_eq!(entry.reads, n);
+                assert_eq!(stats.edits.best, vec![n]);
+            }
+            assert_eq!(manifest.inputs[0].results, "gut.results");
+            assert_eq!(manifest.inputs[1].summary, "skin.summary.json");
+        }
+
+        match input_results_names(&["a/gut.fastq.gz", "b/gut.fq"]) {
+            Err(MtsvError::InvalidParameter(msg)) => assert!(msg.contains("named gut")),
+            other => panic!("expected a name collision, got {:?}", other),
+        }
+        assert_eq!(input_results_names(&["x.fa", "dir/x.fa.1", ".fq"]).unwrap(),
+                   vec!["x", "x.fa.1", ".fq"]);
+    }
+
+    #[test]
+    fn slow_read_log_rows() {
+        let mut rng = XorShiftRng::new_unseeded();
+        let reference = random_seq(&mut rng, 400);
+        let mut db = Database::new();
+        db.insert(TaxId(1), vec![(Gi(1), reference.clone())]);
+
+        let dir = Temp::new_dir().unwrap();
+        let dir = dir.to_path_buf();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        write_to_file(&MGIndex::new(db, 16, 32, BinMode::PerGi), &path("test.index")).unwrap();
+
+        let reads = vec![("matching".to_string(), reference[50..150].to_vec()),
+                         ("random".to_string(), random_seq(&mut rng, 80))];
+        let params = QueryParams { seed_gap: 4, ..QueryParams::default() };
+        let run = |threshold: u64| {
+            let config = BinnerConfig {
+                slow_read_log: Some(path("slow.tsv")),
+                slow_read_threshold_ms: threshold,
+                ..BinnerConfig::default()
+            };
+            fs::remove_file(path("slow.tsv")).ok();
+            write_matching_bin_ids(vec![reads.clone().into_iter().map(Ok)],
+                                   &path("test.index"),
+                                   &ResultsPaths::single(&path("results.txt")),
+                                   1,
+                                   &params,
+                                   &config)
+                .unwrap()
+                .0
+        };
+
+        // every read takes at least 0ms
+        let stats = run(0);
+        assert_eq!(stats.slow_reads, 2);
+        let log = fs::read_to_string(path("slow.tsv")).unwrap();
+        let rows = log.lines().map(|l| l.split('\t').collect::<Vec<_>>()).collect::<Vec<_>>();
+        assert_eq!(rows[0],
+                   vec!["read_id", "length", "ms", "seeds_searched", "candidates_aligned",
+                        "hits", "reverse_skipped"]);
+        assert_eq!(rows.len(), 3);
+        for row in &rows[1..] {
+            assert_eq!(row.len(), 7);
+            assert_eq!(row[6], "0");
+            assert!(row[2].parse::<f64>().unwrap() >= 0.0);
+            row[3].parse::<usize>().unwrap();
+        }
+        assert_eq!((rows[1][0], rows[1][1], rows[1][5]), ("matching", "100", "1"));
+        assert!(rows[1][3].parse::<usize>().unwrap() > 0);
+        assert!(rows[1][4].parse::<usize>().unwrap() >= 1);
+        assert_eq!((rows[2][0], rows[2][1], rows[2][4], rows[2][5]), ("random", "80", "0", "0"));
+
+        // nothing is that slow
+        let stats = run(1_000_000);
+        assert_eq!(stats.slow_reads, 0);
+        assert_eq!(fs::read_to_string(path("slow.tsv")).unwrap().lines().count(), 1);
+    }
+
+    #[test]
+    fn taxid_map_relabels_results() {
+        let mut rng = XorShiftRng::new_unseeded();
+        let shared = random_seq(&mut rng, 400);
+        let mut diverged = shared.clone();
+        diverged[150] = if diverged[150] == b'A' { b'C' } else { b'A' };
+        let unique = random_seq(&mut rng, 400);
+        let mut db = Database::new();
+        db.insert(TaxId(1), vec![(Gi(1), shared.clone())]);
+        db.insert(TaxId(2), vec![(Gi(2), diverged)]);
+        db.insert(TaxId(3), vec![(Gi(3), unique.clone())]);
+
+        let dir = Temp::new_dir().unwrap();
+        let dir = dir.to_path_buf();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+
+        let reads = format!(">shared\n{}\n>unique\n{}\n",
+                            str::from_utf8(&shared[100..200]).unwrap(),
+                            str::from_utf8(&unique[100..200]).unwrap());
+        File::create(path("reads.fasta")).unwrap().write_all(reads.as_bytes()).unwrap();
+        write_to_file(&MGIndex::new(db, 16, 32, BinMode::PerGi), &path("test.index")).unwrap();
+
+        // both surrogate taxids of the shared sequence map to the same real one
+        let taxid_map = TaxidMap::from_pairs(vec![(TaxId(1), TaxId(100)), (TaxId(2), TaxId(100))]);
+        let params = QueryParams { seed_gap: 4, ..QueryParams::default() };
+        let config = BinnerConfig { taxid_map: Some(taxid_map), ..BinnerConfig::default() };
+        let (stats, _) = bin_file(&[&path("reads.fasta")],
+                                  ReadFormat::Fasta,
+                                  &path("test.index"),
+                                  &ResultsPaths::single(&path("results.txt")),
+                                  2,
+                                  &params,
+                                  &config)
+            .unwrap();
+
+        assert_eq!(stats.unmapped_taxid_hits, 1);
+
+        let results = parse_results(Path::new(&path("results.txt")));
+        let expected_shared = vec![(TaxId(100), 0)].into_iter().collect::<BTreeMap<_, _>>();
+        let expected_unique = vec![(TaxId(3), 0)].into_iter().collect::<BTreeMap<_, _>>();
+        assert_eq!(results["shared"], expected_shared);
+        assert_eq!(results["unique"], expected_unique);
+    }
+
+    #[test]
+    fn max_read_length_boundary() {
+        let config = BinnerConfig { max_read_length: Some(10), ..BinnerConfig::default() };
+
+        assert_eq!(prepare(&[b'A'; 10], &config), Ok(vec![b'A'; 10]));
+        assert_eq!(prepare(&[b'A'; 11], &config), Err(RejectReason::TooLong));
+
+        // the hard limit applies even without a maximum, and caps larger maximums
+        let huge = vec![b'A'; READ_LENGTH_LIMIT + 1];
+        assert_eq!(prepare(&huge, &BinnerConfig::default()), Err(RejectReason::TooLong));
+        let config = BinnerConfig { max_read_length: Some(usize::MAX), ..BinnerConfig::default() };
+        assert_eq!(prepare(&huge, &config), Err(RejectReason::TooLong));
+    }
+
+    #[test]
+    fn long_records_skipped() {
+        let mut rng = XorShiftRng::new_unseeded();
+        let reference = random_seq(&mut rng, 400);
+        let mut db = Database::new();
+        db.insert(TaxId(1), vec![(Gi(1), reference.clone())]);
+
+        let dir = Temp::new_dir().unwrap();
+        let dir = dir.to_path_buf();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+
+        // a whole contig which ended up in the reads file
+        let contig = random_seq(&mut rng, 1_000_000);
+        let reads = format!(">read\n{}\n>contig\n{}\n",
+                            str::from_utf8(&reference[100..200]).unwrap(),
+                            str::from_utf8(&contig).unwrap());
+        File::create(path("reads.fasta")).unwrap().write_all(reads.as_bytes()).unwrap();
+        write_to_file(&MGIndex::new(db, 16, 32, BinMode::PerGi), &path("test.index")).unwrap();
+
+        let params = QueryParams { seed_gap: 4, ..QueryParams::default() };
+        let config = BinnerConfig { max_read_length: Some(100_000), ..BinnerConfig::default() };
+        let (stats, _) = bin_file(&[&path("reads.fasta")],
+                                  ReadFormat::Fasta,
+                                  &path("test.index"),
+                                  &ResultsPaths::single(&path("results.txt")),
+                                  2,
+                                  &params,
+                                  &config)
+            .unwrap();
+
+        assert_eq!(stats.reads, 2);
+        assert_eq!(stats.rejected_too_long, 1);
+        assert_eq!(stats.longest_read, 1_000_000);
+        // only the short read was queried, on both strands
+        assert!(stats.query.seeds_searched <= 2 * 22);
+
+        let results = parse_results(Path::new(&path("results.txt")));
+        assert_eq!(results.keys().collect::<Vec<_>>(), vec!["read"]);
+    }
+
+    #[test]
+    fn skip_reverse_after_forward_hits() {
+        let mut rng = XorShiftRng::new_unseeded();
+        let read = random_seq(&mut rng, 100);
+        // two taxids match the read on the forward strand, and one only on the reverse strand
+        let mut db = Database::new();
+        for (tax_id, sequence) in vec![(1, read.clone()), (2, read.clone()), (3, revcomp(&read))] {
+            let reference = [random_seq(&mut rng, 200), sequence, random_seq(&mut rng, 200)]
+                .concat();
+            db.insert(TaxId(tax_id as u64), vec![(Gi(tax_id), reference)]);
+        }
+        let index = MGIndex::new(db, 16, 32, BinMode::PerGi);
+        let searcher = index.searcher();
+
+        let params = QueryParams { seed_gap: 4, ..QueryParams::default() };
+        let run = |skip_reverse_after: Option<usize>| {
+            let (hits, stats) = query_read(&searcher, None, &read, &params, skip_reverse_after);
+            (hits.iter().map(|h| h.tax_id.0).collect::<BTreeSet<_>>(), stats)
+        };
+
+        let (all, all_stats) = run(None);
+        assert_eq!(all, vec![1, 2, 3].into_iter().collect());
+        assert_eq!(all_stats.reverse_strands_skipped, 0);
+
+        // with fewer forward hits than the threshold both strands are queried
+        assert_eq!(run(Some(3)).0, all);
+
+        // skipping the reverse strand halves the work, but misses the reverse-only taxid
+        let (forward, forward_stats) = run(Some(2));
+        assert_eq!(forward, vec![1, 2].into_iter().collect());
+        assert_eq!(forward_stats.reverse_strands_skipped, 1);
+        assert!(forward_stats.seeds_searched * 2 <= all_stats.seeds_searched);
+    }
+
+    #[test]
+    fn memory_limit() {
+        let mut rng = XorShiftRng::new_unseeded();
+        let reference = random_seq(&mut rng, 2_000);
+        let mut db = Database::new();
+        db.insert(TaxId(1), vec![(Gi(1), reference.clone())]);
+
+        let dir = Temp::new_dir().unwrap();
+        let dir = dir.to_path_buf();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        write_to_file(&MGIndex::new(db, 16, 32, BinMode::PerGi), &path("test.index")).unwrap();
+        let mut reads = String::new();
+        for i in 0..600 {
+            let start = rng.gen_range(0, 1_900);
+            reads.push_str(&format!(">read_{}\n{}\n",
+                                    i,
+                                    str::from_utf8(&reference[start..start + 100]).unwrap()));
+        }
+        fs::write(path("reads.fasta"), reads).unwrap();
+
+        let params = QueryParams { seed_gap: 4, ..QueryParams::default() };
+        let run = |results: &str, memory_limit: Option<u64>| {
+            bin_file(&[&path("reads.fasta")],
+                     ReadFormat::Fasta,
+                     &path("test.index"),
+                     &ResultsPaths::single(&path(results)),
+                     2,
+                     &params,
+                     &BinnerConfig { memory_limit, ..BinnerConfig::default() })
+        };
+        let sorted_lines = |name: &str| {
+            let mut lines = fs::read_to_string(path(name))
+                .unwrap()
+                .lines()
+                .map(str::to_string)
+                .collect::<Vec<_>>();
+            lines.sort();
+            lines
+        };
+
+        // an index bigger than the limit is refused before anything is written
+        let index_bytes = fs::metadata(path("test.index")).unwrap().len();
+        match run("refused.txt", Some(index_bytes - 1)) {
+            Err(MtsvError::IndexTooLarge(..)) => (),
+            other => panic!("expected IndexTooLarge, got {:?}", other.map(|r| r.0)),
+        }
+        assert!(!Path::new(&path("refused.txt")).exists());
+
+        // the test process is far above a limit this close to the index size, so reading is held
+        // back every few hundred reads, without changing the results
+        run("unlimited.txt", None).unwrap();
+        run("limited.txt", Some(index_bytes + 1024)).unwrap();
+        assert_eq!(sorted_lines("limited.txt"), sorted_lines("unlimited.txt"));
+        assert_eq!(sorted_lines("limited.txt").len(), 600);
+    }
+
+    #[test]
+    fn prescreen_keeps_results() {
+        use prescreen::PrescreenParams;
+
+        let mut rng = XorShiftRng::new_unseeded();
+        let mut db = Database::new();
+        let references = (1..4).map(|_| random_seq(&mut rng, 1_000)).collect::<Vec<_>>();
+        for (i, reference) in references.iter().enumerate() {
+            db.insert(TaxId(i as u64 + 1), vec![(Gi(i as u32 + 1), reference.clone())]);
+        }
+
+        let dir = Temp::new_dir().unwrap();
+        let dir = dir.to_path_buf();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+
+        // reads from the references with a few substitutions, on either strand, and reads which
+        // match nothing
+        let mut reads = String::new();
+        for i in 0..40 {
+            let mut seq = if i % 2 == 0 {
+                let reference = &references[i % 3];
+                let start = rng.gen_range(0, 900);
+                reference[start..start + 100].to_vec()
+            } else {
+                random_seq(&mut rng, 100)
+            };
+            for _ in 0..rng.gen_range(0, 6) {
+                seq[rng.gen_range(0, 100)] = *rng.choose(b"ACGTN").unwrap();
+            }
+            if i % 4 == 0 {
+                seq = revcomp(&seq);
+            }
+            reads.push_str(&format!(">read_{}\n{}\n", i, str::from_utf8(&seq).unwrap()));
+        }
+        File::create(path("reads.fasta")).unwrap().write_all(reads.as_bytes()).unwrap();
+
+        let index = MGIndex::new(db, 16, 32, BinMode::PerGi);
+        write_to_file(&Prescreen::new(&index, &PrescreenParams::default()),
+                      &prescreen_path(&path("test.index")))
+            .unwrap();
+        write_to_file(&index, &path("test.index")).unwrap();
+
+        let params = QueryParams { seed_gap: 4, ..QueryParams::default() };
+        let run = |results: &str, no_prescreen: bool| {
+            bin_file(&[&path("reads.fasta")],
+                     ReadFormat::Fasta,
+                     &path("test.index"),
+                     &ResultsPaths::single(&path(results)),
+                     1,
+                     &params,
+                     &BinnerConfig { no_prescreen, ..BinnerConfig::default() })
+                .unwrap()
+                .0
+        };
+        let screened = run("screened.txt", false);
+        let unscreened = run("unscreened.txt", true);
+
+        // at least the random reads were skipped on both strands, without changing the results
+        assert!(screened.query.prescreened >= 40);
+        assert_eq!(unscreened.query.prescreened, 0);
+
+        let read_file = |name: &str| fs::read(path(name)).unwrap();
+        assert!(parse_results(Path::new(&path("unscreened.txt"))).len() >= 20);
+        assert_eq!(read_file("screened.txt"), read_file("unscreened.txt"));
+
+        // a prescreen with a different seed size is ignored
+        let other_seeds = QueryParams { seed_length: 20, ..params };
+        let (stats, _) = bin_file(&[&path("reads.fasta")],
+                                  ReadFormat::Fasta,
+                                  &path("test.index"),
+                                  &ResultsPaths::single(&path("other.txt")),
+                                  1,
+                                  &other_seeds,
+                                  &BinnerConfig::default())
+            .unwrap();
+        assert_eq!(stats.query.prescreened, 0);
+    }
+
+    #[test]
+    fn index_capabilities_checked_up_front() {
+        use prescreen::PrescreenParams;
+
+        let dir = Temp::new_dir().unwrap();
+        let dir = dir.to_path_buf();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+
+        let mut rng = XorShiftRng::new_unseeded();
+        let reference = random_seq(&mut rng, 400);
+        let mut db = Database::new();
+        db.insert(TaxId(1), vec![(Gi(1), reference.clone())]);
+        let index = MGIndex::new(db, 16, 32, BinMode::PerGi);
+        File::create(path("reads.fasta"))
+            .unwrap()
+            .write_all(format!(">r1\n{}\n", str::from_utf8(&reference[..100]).unwrap()).as_bytes())
+            .unwrap();
+
+        // every combination of a prescreen and compression
+        for &(name, prescreen, compressed) in &[("plain", false, false),
+                                                ("screened", true, false),
+                                                ("compressed", false, true),
+                                                ("both", true, true)] {
+            let index_path = path(&format!("{}.index", name));
+            if compressed {
+                write_to_file_compressed(&index, &index_path, 1).unwrap();
+            } else {
+                write_to_file(&index, &index_path).unwrap();
+            }
+            if prescreen {
+                write_to_file(&Prescreen::new(&index, &PrescreenParams::default()),
+                              &prescreen_path(&index_path))
+                    .unwrap();
+            }
+
+            let capabilities = IndexCapabilities::of_file(&index_path).unwrap();
+            assert_eq!(capabilities.contains(IndexCapabilities::PRESCREEN), prescreen);
+            assert_eq!(capabilities.contains(IndexCapabilities::COMPRESSED), compressed);
+
+            for &require_prescreen in &[false, true] {
+                let results = path(&format!("{}_{}.txt", name, require_prescreen));
+                let config = BinnerConfig { require_prescreen, ..BinnerConfig::default() };
+                let result = bin_to_results(&[&path("reads.fasta")],
+                                            ReadFormat::Fasta,
+                                            &[&index_path],
+                                            &ResultsPaths::single(&results),
+                                            1,
+                                            &QueryParams::default(),
+                                            &config);
+                match result {
+                    Err(MtsvError::InvalidParameter(msg)) => {
+                        assert!(require_prescreen && !prescreen);
+                        assert_eq!(msg,
+                                   "index built without --build-prescreen; rebuild or drop \
+                                    --require-prescreen");
+                        // nothing was binned
+                        assert!(!Path::new(&results).exists());
+                    },
+                    Ok(_) => {
+                        assert!(prescreen || !require_prescreen);
+                        assert_eq!(parse_results(Path::new(&results)).len(), 1);
+                    },
+                    Err(other) => panic!("unexpected error: {}", other),
+                }
+            }
+        }
+
+        // a prescreen which can't be used for the run is an error too when it's required
+        let config = BinnerConfig { require_prescreen: true, ..BinnerConfig::default() };
+        let other_seeds = QueryParams { seed_length: 20, ..QueryParams::default() };
+        match bin_file(&[&path("reads.fasta")],
+                       ReadFormat::Fasta,
+                       &path("screened.index"),
+                       &ResultsPaths::single(&path("other_seeds.txt")),
+                       1,
+                       &other_seeds,
+                       &config) {
+            Err(MtsvError::InvalidParameter(msg)) => assert!(msg.contains("18-mers"), "{}", msg),
+            other => panic!("expected an invalid parameter error, got {:?}", other.map(|s| s.0)),
+        }
+    }
+
+    #[test]
+    fn results_independent_of_thread_count() {
+        let mut rng = XorShiftRng::new_unseeded();
+        let mut db = Database::new();
+        // a tandem repeat to saturate the per-bin cap, and a taxid with a sequence on each strand
+        let unit = random_seq(&mut rng, 30);
+        let repeat = unit.iter().cycle().take(900).cloned().collect::<Vec<_>>();
+        let shared = random_seq(&mut rng, 600);
+        db.insert(TaxId(1), vec![(Gi(1), repeat.clone())]);
+        db.insert(TaxId(2), vec![(Gi(2), shared.clone()), (Gi(3), revcomp(&shared))]);
+        db.insert(TaxId(3), vec![(Gi(4), shared[..300].to_vec())]);
+
+        let dir = Temp::new_dir().unwrap();
+        let dir = dir.to_path_buf();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+
+        let mut reads = String::new();
+        for i in 0..200 {
+            let source = if i % 3 == 0 { &repeat } else { &shared };
+            let start = rng.gen_range(0, source.len() - 100);
+            let mut seq = source[start..start + 100].to_vec();
+            for _ in 0..rng.gen_range(0, 8) {
+                seq[rng.gen_range(0, 100)] = *rng.choose(b"ACGT").unwrap();
+            }
+            reads.push_str(&format!(">read_{}\n{}\n", i, str::from_utf8(&seq).unwrap()));
+        }
+        File::create(path("reads.fasta")).unwrap().write_all(reads.as_bytes()).unwrap();
+        write_to_file(&MGIndex::new(db, 16, 32, BinMode::PerGi), &path("test.index")).unwrap();
+
+        let params = QueryParams {
+            seed_gap: 4,
+            max_hits_per_bin: 8,
+            max_overhang: Some(0.1),
+            ..QueryParams::default()
+        };
+        let config = BinnerConfig {
+            findings_version: FindingsVersion::V2,
+            min_confidence: Some(0.2),
+            ..BinnerConfig::default()
+        };
+        let run = |threads: usize| {
+            let results = path(&format!("threads{}.txt", threads));
+            let (stats, _) = bin_file(&[&path("reads.fasta")],
+                                      ReadFormat::Fasta,
+                                      &path("test.index"),
+                                      &ResultsPaths::single(&results),
+                                      threads,
+                                      &params,
+                                      &config)
+                .unwrap();
+            let hits = parse_edit_distance_findings(BufReader::new(File::open(results).unwrap()))
+                .map(|r| r.unwrap())
+                .collect::<BTreeMap<_, _>>();
+            (stats, hits)
+        };
+
+        let (single_stats, single) = run(1);
+        let (parallel_stats, parallel) = run(8);
+        assert!(single_stats.query.capped_bins > 0);
+        assert!(single.len() > 100);
+        assert_eq!(parallel_stats, single_stats);
+        assert_eq!(parallel, single);
+    }
+
+    fn hit(tax_id: u64, edit: u32) -> Hit {
+        Hit {
+            tax_id: TaxId(tax_id),
+            edit,
+            seeds: 0,
+            seeds_searched: 0,
+            clipped_start: 0,
+            clipped_end: 0,
+            gi: Gi(0),
+        }
+    }
+
+    #[test]
+    fn shard_writer_reopens_closed_shards() {
+        let dir = Temp::new_dir().unwrap();
+
+        // with one open file at a time, every other write has to reopen its shard
+        let mut shards = ShardWriter::new(dir.as_ref(), 1, false).unwrap();
+        shards.write_hits("R1", &[hit(1, 0), hit(2, 1)]).unwrap();
+        shards.write_hits("R2", &[hit(3, 2), hit(1, 3), hit(1, 1)]).unwrap();
+        shards.write_hits("R3", &[hit(2, 0)]).unwrap();
+        assert_eq!(shards.finish().unwrap(), 3);
+
+        let read = |tax_id| fs::read_to_string(ShardWriter::shard_path(dir.as_ref(), TaxId(tax_id)))
+            .unwrap();
+        assert_eq!(read(1), "R1=0\nR2=1\n");
+        assert_eq!(read(2), "R1=1\nR3=0\n");
+        assert_eq!(read(3), "R2=2\n");
+
+        // a new run replaces the old shards rather than appending to them
+        let mut shards = ShardWriter::new(dir.as_ref(), 1, false).unwrap();
+        shards.write_hits("R4", &[hit(1, 2)]).unwrap();
+        assert_eq!(shards.finish().unwrap(), 1);
+        assert_eq!(read(1), "R4=2\n");
+    }
+
+    fn read_shards(dir: &Path) -> BTreeMap<String, BTreeMap<TaxId, u32>> {
+        let mut reads = BTreeMap::new();
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            let name = path.file_stem().unwrap().to_str().unwrap().to_string();
+            let tax_id = TaxId(name.trim_start_matches("taxid_").parse().unwrap());
+            for line in fs::read_to_string(&path).unwrap().lines() {
+                let mut parts = line.rsplitn(2, '=');
+                let edit = parts.next().unwrap().parse().unwrap();
+                let read_id = parts.next().unwrap().to_string();
+                reads.entry(read_id).or_insert_with(BTreeMap::new).insert(tax_id, edit);
+            }
+        }
+        reads
+    }
+
+    #[test]
+    fn shards_reassemble_combined_results() {
+        let mut rng = XorShiftRng::new_unseeded();
+        let seqs = (0..3).map(|_| random_seq(&mut rng, 400)).collect::<Vec<_>>();
+        let mut first = Database::new();
+        let mut second = Database::new();
+        first.insert(TaxId(1), vec![(Gi(1), seqs[0].clone())]);
+        first.insert(TaxId(2), vec![(Gi(2), seqs[1].clone())]);
+        // taxid 3 also contains part of taxid 2's sequence
+        let mut chimera = seqs[2].clone();
+        chimera.extend_from_slice(&seqs[1][..200]);
+        second.insert(TaxId(3), vec![(Gi(3), chimera)]);
+        let mut combined = first.clone();
+        combined.extend(second.clone());
+
+        let dir = Temp::new_dir().unwrap();
+        let dir = dir.to_path_buf();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+
+        let mut reads = String::new();
+        for (i, seq) in seqs.iter().enumerate() {
+            for start in &[50, 150, 250] {
+                reads.push_str(&format!(">read_{}_{}\n{}\n",
+                                        i,
+                                        start,
+                                        str::from_utf8(&seq[*start..*start + 100]).unwrap()));
+            }
+        }
+        File::create(path("reads.fasta")).unwrap().write_all(reads.as_bytes()).unwrap();
+        write_to_file(&MGIndex::new(combined, 16, 32, BinMode::PerGi),
+                      &path("combined.index"))
+            .unwrap();
+        write_to_file(&MGIndex::new(first, 16, 32, BinMode::PerGi), &path("first.index")).unwrap();
+        write_to_file(&MGIndex::new(second, 16, 32, BinMode::PerGi),
+                      &path("second.index"))
+            .unwrap();
+
+        let params = QueryParams { seed_gap: 4, ..QueryParams::default() };
+        let run = |indices: &[&str], results: &str, shards: &str, no_combined| {
+            let config = BinnerConfig {
+                shard_dir: Some(path(shards)),
+                no_combined,
+                ..BinnerConfig::default()
+            };
+            bin_reads(&path("reads.fasta"),
+                      ReadFormat::Fasta,
+                      indices,
+                      &path(results),
+                      2,
+                      &params,
+                      &config)
+                .unwrap();
+        };
+
+        run(&[&path("combined.index")], "results.txt", "shards", false);
+        let expected = parse_results(Path::new(&path("results.txt")));
+        assert_eq!(expected.len(), 9);
+        assert_eq!(expected["read_1_50"].len(), 2);
+        assert_eq!(fs::read_dir(path("shards")).unwrap().count(), 3);
+        assert_eq!(read_shards(Path::new(&path("shards"))), expected);
+
+        run(&[&path("combined.index")], "unused.txt", "only_shards", true);
+        assert!(!Path::new(&path("unused.txt")).exists());
+        assert_eq!(read_shards(Path::new(&path("only_shards"))), expected);
+
+        run(&[&path("first.index"), &path("second.index")], "merged.txt", "merged_shards", true);
+        assert!(!Path::new(&path("merged.txt")).exists());
+        assert_eq!(read_shards(Path::new(&path("merged_shards"))), expected);
+    }
+
+    #[test]
+    fn extract_several_taxa() {
+        let mut rng = XorShiftRng::new_unseeded();
+        let mut db = Database::new();
+        db.insert(TaxId(1),
+                  vec![(Gi(1), random_seq(&mut rng, 50)), (Gi(2), random_seq(&mut rng, 60))]);
+        db.insert(TaxId(2), vec![(Gi(3), random_seq(&mut rng, 70))]);
+        db.insert(TaxId(3), vec![(Gi(4), random_seq(&mut rng, 80))]);
+        let index = MGIndex::new(db, 16, 32, BinMode::PerGi);
+
+        let dir = Temp::new_dir().unwrap();
+        let dir = dir.to_path_buf();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        write_to_file(&index, &path("test.index")).unwrap();
+        convert_to_mapped(&path("test.index"), &path("mapped.index")).unwrap();
+        let records = |p: &str| {
+            fasta::Reader::from_file(p)
+                .unwrap()
+                .records()
+                .map(|r| {
+                    let r = r.unwrap();
+                    let (bin, accession) = parse_reference_header(r.id(), r.desc()).unwrap();
+                    assert_eq!(accession, None);
+                    (bin, r.seq().to_vec())
+                })
+                .collect::<Vec<_>>()
+        };
+        // the headers give each reference's bin in the index
+        let named = |taxid: u64| index.get_references_annotated(taxid);
+
+        // in index order, each taxid once, with the missing ones returned
+        for index_name in &["test.index", "mapped.index"] {
+            let missing =
+                extract_references(&path(index_name), &path("all.fasta"), &[3, 7, 1, 3], false)
+                    .unwrap();
+            assert_eq!(missing, vec![7]);
+            assert_eq!(records(&path("all.fasta")), [named(1), named(3)].concat());
+        }
+
+        let missing =
+            extract_references(&path("test.index"), &path("split"), &[2, 1, 9], true).unwrap();
+        assert_eq!(missing, vec![9]);
+        assert_eq!(records(&path("split/1.fasta")), named(1));
+        assert_eq!(records(&path("split/2.fasta")), named(2));
+        assert!(!dir.join("split/9.fasta").exists());
+    }
+
+    #[test]
+    fn dumped_references_rebuild() {
+        use io::parse_fasta_db;
+
+        let mut index = MGIndex::new(random_database(4, 3, 50, 150), 16, 32, BinMode::PerGi);
+        let gi = index.bins().next().unwrap().gi;
+        index.set_descriptions(vec![(gi, String::from("first reference"))].into_iter().collect());
+        let dir = Temp::new_dir().unwrap();
+        let dir = dir.to_path_buf();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        write_to_file(&index, &path("test.index")).unwrap();
+
+        let dumped = dump_references(&path("test.index"), &path("all.fasta"), None).unwrap();
+        assert_eq!(dumped, index.bins().count());
+        let records = || fasta::Reader::from_file(path("all.fasta")).unwrap().records();
+        let first = records().next().unwrap().unwrap();
+        assert_eq!(first.desc(), Some("first reference"));
+
+        // the GIs, taxids and sequences all survive, so the index does
+        let rebuilt = MGIndex::new(parse_fasta_db(records()).unwrap(), 16, 32, BinMode::PerGi);
+        assert_eq!(rebuilt.fingerprint(), index.fingerprint());
+        assert_eq!(rebuilt.bins().collect::<Vec<_>>(), index.bins().collect::<Vec<_>>());
+        assert_eq!(rebuilt.reference_sequence(), index.reference_sequence());
+
+        // or just some taxa's
+        let tax_id = index.bins().last().unwrap().tax_id;
+        let wanted = vec![tax_id].into_iter().collect();
+        let dumped = dump_references(&path("test.index"), &path("some.fasta"), Some(&wanted))
+            .unwrap();
+        let tax_ids = fasta::Reader::from_file(path("some.fasta"))
+            .unwrap()
+            .records()
+            .map(|r| parse_read_header(r.unwrap().id()).unwrap().1)
+            .collect::<Vec<_>>();
+        assert_eq!(tax_ids, vec![tax_id; dumped]);
+        assert_eq!(dumped, index.bins_for(tax_id).len());
     }
 }
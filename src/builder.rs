@@ -3,38 +3,1230 @@
 use bio::io::fasta;
 
 use error::*;
-use index::MGIndex;
-use io::{parse_fasta_db, write_to_file};
-use std::io;
+use index::{Alphabet, AmbiguityPolicy, BinInfo, BinMode, ConcatenatedReferences, Gi, HeaderMap,
+            IndexMerge, MGIndex, QueryParams, QueryStats, References, SortedReferences, TaxId};
+use io::{parse_fasta_references, parse_manifest_references, survey_fasta_references,
+         write_to_file, write_to_file_compressed, AccessionMap, FastaFiles, FastaManifest,
+         ReferenceSurvey, STDIN_PATH};
+use prep::DustParams;
+use prescreen::{prescreen_path, Prescreen, PrescreenParams};
+use rand::Rng;
+use run::RunContext;
+use serde::{Serialize, Deserialize};
+use serde_json;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs::{self, File};
+use std::hash::Hasher;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::mem;
+use std::path::Path;
+use util::{Fnv64, HeaderFormat};
+
+/// Occ sampling intervals above this make every backward search step slow enough that binning
+/// crawls, since each occurrence lookup scans up to this many BWT entries.
+pub const MAX_PRACTICAL_SAMPLE_INTERVAL: u32 = 1024;
+
+/// Suffix array sampling rates above this make locating each seed hit slow enough that binning
+/// crawls, since each lookup walks up to this many LF-mapping steps.
+pub const MAX_PRACTICAL_SUFFIX_SAMPLE: usize = 1024;
+
+/// How to build an index, other than from which references and to where.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BuildOptions {
+    /// Sampling interval of the BWT occurrence array.
+    pub sample_interval: u32,
+    /// Sampling rate of the suffix array.
+    pub suffix_sample: usize,
+    /// Format of the references' FASTA headers.
+    pub header_format: HeaderFormat,
+    /// Path to the map of accessions to taxids for `HeaderFormat::Accession` headers (see
+    /// `io::AccessionMap::from_file`).
+    pub accession_map: Option<String>,
+    /// Path to one of NCBI's accession2taxid files to look up the taxids of
+    /// `HeaderFormat::Accession` headers in instead of an accession map (see
+    /// `io::AccessionMap::from_accession2taxid`). Only builds from files can use one (see
+    /// `build_and_write_index_from_files`), since their headers are read first to pick out the
+    /// entries needed.
+    #[serde(default)]
+    pub acc2taxid: Option<String>,
+    /// Also build a prescreen with these parameters, if set.
+    pub prescreen: Option<PrescreenParams>,
+    /// Write the index compressed at this level, if set.
+    pub compression: Option<u32>,
+    /// Number of threads to build the suffix array on.
+    pub threads: usize,
+    /// Write the index in the mapped layout (see `MGIndex::open_mmap`) rather than as bincode.
+    #[serde(default)]
+    pub mapped: bool,
+    /// Sort the suffix array into a temporary file in this directory rather than in memory, if
+    /// set (see `MGIndex::from_references`).
+    #[serde(default)]
+    pub external_sa: Option<String>,
+    /// Cut the references into shards of about this many bases, each written as its own index,
+    /// if set (see `shard_path`).
+    #[serde(default)]
+    pub shard_size: Option<usize>,
+    /// The alphabet of the references (see `MGIndex::with_alphabet`). Only DNA indices can be
+    /// written in the mapped layout, and protein indices can't have their suffix array sorted
+    /// externally.
+    #[serde(default)]
+    pub alphabet: Alphabet,
+    /// Exclude the references' lowercase (soft-masked) bases from seeding, while still aligning
+    /// across them (see `References::set_respect_softmask`).
+    #[serde(default)]
+    pub respect_softmask: bool,
+    /// Exclude the DNA references' low-complexity regions from seeding too, those with a DUST
+    /// score above this level in tenths (see `prep::DustParams`), if set.
+    #[serde(default)]
+    pub mask_low_complexity: Option<u32>,
+    /// How the DNA references' ambiguity codes are treated: replaced with N, kept so reads
+    /// align against them as their bases, or rejected, failing the build (see
+    /// `References::set_ambiguity`).
+    #[serde(default)]
+    pub ambiguity: AmbiguityPolicy,
+    /// Fail on references with no sequence rather than skipping them with a warning (see
+    /// `io::parse_fasta_references`).
+    #[serde(default)]
+    pub strict_references: bool,
+    /// Keep each reference's original FASTA description, the rest of its header line, in the
+    /// index for `mtsv-reference` to write back out (see `MGIndex::set_descriptions`). Mapped
+    /// indices can't keep them.
+    #[serde(default)]
+    pub keep_descriptions: bool,
+    /// Save the build's progress to `checkpoint_path` of the index path after each of its slow
+    /// stages, and carry on from a checkpoint left there by an earlier build rather than starting
+    /// over (see `Checkpoint`). Sharded builds can't be resumed.
+    #[serde(default)]
+    pub resume: bool,
+    /// How the references are divided into bins (see `References::set_bin_mode`). Indices with a
+    /// bin per taxid don't keep accessions or descriptions, since their bins have no GIs.
+    #[serde(default)]
+    pub bin_mode: BinMode,
+}
+
+impl Default for BuildOptions {
+    fn default() -> Self {
+        BuildOptions {
+            sample_interval: 64,
+            suffix_sample: 32,
+            header_format: HeaderFormat::GiTaxId,
+            accession_map: None,
+            acc2taxid: None,
+            prescreen: None,
+            compression: None,
+            threads: 1,
+            mapped: false,
+            external_sa: None,
+            shard_size: None,
+            alphabet: Alphabet::Dna,
+            respect_softmask: false,
+            mask_low_complexity: None,
+            ambiguity: AmbiguityPolicy::N,
+            strict_references: false,
+            keep_descriptions: false,
+            resume: false,
+            bin_mode: BinMode::PerGi,
+        }
+    }
+}
+
+impl BuildOptions {
+    /// No references yet, of the alphabet and soft-masking these options build.
+    fn references(&self) -> References {
+        let mut references = References::with_alphabet(self.alphabet);
+        references.set_respect_softmask(self.respect_softmask);
+        references.set_mask_low_complexity(self.mask_low_complexity
+            .map(|level| DustParams { level, ..DustParams::default() }));
+        references.set_ambiguity(self.ambiguity);
+        references.set_bin_mode(self.bin_mode);
+        references
+    }
+
+    /// The accession of a reference to keep in the index, if it has one and the index has a bin
+    /// for its GI.
+    fn kept_accession<'a>(&self, accession: Option<&'a str>) -> Option<&'a str> {
+        accession.filter(|_| self.bin_mode == BinMode::PerGi)
+    }
+
+    /// Fail on a reference with an ambiguity code if the options reject them.
+    fn check_ambiguity(&self, gi: Gi, seq: &[u8]) -> MtsvResult<()> {
+        let ambiguous = self.ambiguity == AmbiguityPolicy::Reject &&
+                        seq.iter().any(|&b| self.alphabet.is_ambiguous(b.to_ascii_uppercase()));
+        if ambiguous {
+            return Err(MtsvError::InvalidParameter(format!("GI {} has ambiguity codes, which \
+                                                            are rejected",
+                                                           gi.0)));
+        }
+        Ok(())
+    }
+
+    /// These options less those which don't change what a `Checkpoint` holds, so that a build
+    /// can be resumed on more threads, say, or written compressed.
+    fn checkpointed(&self) -> BuildOptions {
+        BuildOptions {
+            sample_interval: BuildOptions::default().sample_interval,
+            prescreen: None,
+            compression: None,
+            threads: 1,
+            mapped: false,
+            external_sa: None,
+            resume: false,
+            ..self.clone()
+        }
+    }
+}
+
+/// Starts a file written by `Checkpoint::write`, followed by the version of mtsv which wrote it
+/// (as a byte giving its length and then the string), the checkpoint's serialization and the
+/// FNV-1a hash of the serialization as a little-endian u64.
+const CHECKPOINT_MAGIC: &[u8] = b"\x89MTSVK\r\n";
+
+/// The path a build of an index at `index_path` saves its progress to: `index_path.ckpt`.
+pub fn checkpoint_path(index_path: &str) -> String {
+    format!("{}.ckpt", index_path)
+}
+
+/// How far a build saved in a `Checkpoint` got.
+#[derive(Serialize, Deserialize)]
+pub enum BuildStage {
+    /// The references are read and concatenated (see `References::concatenate`).
+    Concatenated(ConcatenatedReferences),
+    /// Their suffix array is built and sampled (see `ConcatenatedReferences::sort`).
+    Sorted(SortedReferences),
+}
+
+impl BuildStage {
+    /// What's done at this stage, for logging.
+    fn describe(&self) -> &'static str {
+        match *self {
+            BuildStage::Concatenated(_) => "with the references concatenated",
+            BuildStage::Sorted(_) => "with the suffix array built",
+        }
+    }
+}
+
+/// An index build part way through, with everything needed to finish it: what's been built of
+/// the index so far, the references' accessions and descriptions, and the options it was built
+/// with. Builds with `BuildOptions::resume` write one after each slow stage, so that one which
+/// dies can carry on from the last of them rather than starting over.
+#[derive(Serialize, Deserialize)]
+pub struct Checkpoint {
+    options: BuildOptions,
+    accessions: HeaderMap,
+    descriptions: HeaderMap,
+    stage: BuildStage,
+}
+
+impl Checkpoint {
+    /// The first stage of a build of `references` as `options` say.
+    fn new(references: References,
+           accessions: HeaderMap,
+           descriptions: HeaderMap,
+           options: &BuildOptions)
+           -> Self {
+        if options.mask_low_complexity.is_some() {
+            info!("Masked {} low-complexity bases of {}",
+                  references.low_complexity_bases(),
+                  references.bases());
+        }
+        Checkpoint {
+            options: options.checkpointed(),
+            accessions,
+            descriptions,
+            stage: BuildStage::Concatenated(references.concatenate()),
+        }
+    }
+
+    /// How far the build got.
+    pub fn stage(&self) -> &BuildStage {
+        &self.stage
+    }
+
+    /// Carry the build on to its next stage, building the suffix array of concatenated
+    /// references. Sorted references are the last stage there is to save.
+    pub fn advance(self, options: &BuildOptions) -> MtsvResult<Self> {
+        let stage = match self.stage {
+            BuildStage::Concatenated(references) => {
+                BuildStage::Sorted(references.sort(options.suffix_sample,
+                                                   options.threads,
+                                                   options.external_sa.as_deref())?)
+            },
+            sorted => sorted,
+        };
+        Ok(Checkpoint { stage, ..self })
+    }
+
+    /// Finish the build, as `options` say, into the index.
+    pub fn into_index(self, options: &BuildOptions) -> MtsvResult<MGIndex> {
+        let Checkpoint { options: built, accessions, descriptions, stage } =
+            self.advance(options)?;
+        let mut index = match stage {
+            BuildStage::Sorted(references) => {
+                references.into_index(options.sample_interval, options.threads)
+            },
+            BuildStage::Concatenated(_) => unreachable!("advance always sorts the references"),
+        };
+        index.set_synthetic_gis(built.header_format != HeaderFormat::GiTaxId);
+        index.set_accessions(accessions);
+        index.set_descriptions(descriptions);
+        Ok(index)
+    }
+
+    /// Read the checkpoint at `p`, failing with `MtsvError::InvalidCheckpoint` if it isn't one,
+    /// was written by another release of mtsv, or is truncated or corrupt.
+    pub fn from_file(p: &str) -> MtsvResult<Self> {
+        let invalid = |why: &str| MtsvError::InvalidCheckpoint(p.to_string(), why.to_string());
+        let mut reader = BufReader::new(File::open(p)?);
+
+        let mut magic = [0; 8];
+        let mut len = [0; 1];
+        if reader.read_exact(&mut magic).and_then(|_| reader.read_exact(&mut len)).is_err() ||
+           magic != CHECKPOINT_MAGIC {
+            return Err(invalid("it isn't a checkpoint"));
+        }
+        let mut built_by = vec![0; len[0] as usize];
+        reader.read_exact(&mut built_by).map_err(|_| invalid("it's truncated"))?;
+        if built_by != env!("CARGO_PKG_VERSION").as_bytes() {
+            return Err(invalid(&format!("it was written by mtsv {}, and only the release which \
+                                         wrote a checkpoint can resume from it",
+                                        String::from_utf8_lossy(&built_by))));
+        }
+
+        let mut checksummed = Checksummed { inner: reader, hash: Fnv64::default() };
+        let checkpoint: Checkpoint = bincode::deserialize_from(&mut checksummed)
+            .map_err(|_| invalid("it's truncated or corrupt"))?;
+        let mut checksum = [0; 8];
+        checksummed.inner.read_exact(&mut checksum).map_err(|_| invalid("it's truncated"))?;
+        if u64::from_le_bytes(checksum) != checksummed.hash.finish() {
+            return Err(invalid("its checksum doesn't match, so it's corrupt"));
+        }
+        Ok(checkpoint)
+    }
+
+    /// Write the checkpoint to `p`, replacing any checkpoint there only once it's complete, so
+    /// that a build which dies while writing one still has the last.
+    pub fn write(&self, p: &str) -> MtsvResult<()> {
+        info!("Saving checkpoint {} {}...", p, self.stage.describe());
+        let partial = format!("{}.partial", p);
+        let mut writer = BufWriter::new(File::create(&partial)?);
+        writer.write_all(CHECKPOINT_MAGIC)?;
+        let built_by = env!("CARGO_PKG_VERSION").as_bytes();
+        writer.write_all(&[built_by.len() as u8])?;
+        writer.write_all(built_by)?;
+
+        let mut checksummed = Checksummed { inner: writer, hash: Fnv64::default() };
+        bincode::serialize_into(&mut checksummed, self)?;
+        let checksum = checksummed.hash.finish();
+        let mut writer = checksummed.inner;
+        writer.write_all(&checksum.to_le_bytes())?;
+        writer.into_inner().map_err(|e| MtsvError::from(e.into_error()))?.sync_all()?;
+        fs::rename(&partial, p)?;
+        Ok(())
+    }
+}
+
+/// Hashes the bytes read from or written to `inner` as they pass through.
+struct Checksummed<T> {
+    inner: T,
+    hash: Fnv64,
+}
+
+impl<R: Read> Read for Checksummed<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hash.write(&buf[..n]);
+        Ok(n)
+    }
+}
+
+impl<W: Write> Write for Checksummed<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hash.write(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// The shards of an index built with `BuildOptions::shard_size`, written as JSON to
+/// `shard_manifest_path` of the index path.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ShardManifest {
+    /// An entry for each shard, in the order they were built.
+    pub shards: Vec<ShardEntry>,
+}
+
+/// One of the shards in a `ShardManifest`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ShardEntry {
+    /// Path to the shard's index.
+    pub index: String,
+    /// Number of references in the shard.
+    pub references: usize,
+    /// Number of bases in the shard's references.
+    pub bases: usize,
+    /// The taxids the shard's references belong to, in ascending order.
+    pub taxids: Vec<TaxId>,
+}
+
+impl ShardManifest {
+    /// Read the manifest at `p`.
+    pub fn from_file(p: &str) -> MtsvResult<Self> {
+        Ok(serde_json::from_reader(BufReader::new(File::open(p)?))?)
+    }
+
+    /// The paths of the shards' indices, in order.
+    pub fn index_paths(&self) -> Vec<&str> {
+        self.shards.iter().map(|shard| shard.index.as_str()).collect()
+    }
+}
+
+/// The path shard number `shard` of a sharded index at `index_path` is written to:
+/// `index_path.0`, `index_path.1` and so on.
+pub fn shard_path(index_path: &str, shard: usize) -> String {
+    format!("{}.{}", index_path, shard)
+}
+
+/// The path the manifest of a sharded index at `index_path` is written to.
+pub fn shard_manifest_path(index_path: &str) -> String {
+    format!("{}.shards.json", index_path)
+}
+
+/// Check the FM-index sampling parameters before spending time parsing and building.
+///
+/// Both must be at least 1 (rust-bio divides by them). rust-bio samples every k-th entry with a
+/// plain modulus, so neither needs to be a power of two. Values above the practical maxima are
+/// accepted with a warning, since they only trade speed for memory.
+fn validate_sampling(sample_interval: u32, suffix_sample: usize) -> MtsvResult<()> {
+    if sample_interval < 1 {
+        return Err(MtsvError::InvalidParameter(format!(
+            "occurrence sample interval must be at least 1 (practical range 1-{}), got {}",
+            MAX_PRACTICAL_SAMPLE_INTERVAL,
+            sample_interval)));
+    }
+
+    if suffix_sample < 1 {
+        return Err(MtsvError::InvalidParameter(format!(
+            "suffix array sample rate must be at least 1 (practical range 1-{}), got {}",
+            MAX_PRACTICAL_SUFFIX_SAMPLE,
+            suffix_sample)));
+    }
+
+    if sample_interval > MAX_PRACTICAL_SAMPLE_INTERVAL {
+        warn!("Occurrence sample interval {} is above {}, queries against this index will be \
+               very slow",
+              sample_interval,
+              MAX_PRACTICAL_SAMPLE_INTERVAL);
+    }
+
+    if suffix_sample > MAX_PRACTICAL_SUFFIX_SAMPLE {
+        warn!("Suffix array sample rate {} is above {}, queries against this index will be very \
+               slow",
+              suffix_sample,
+              MAX_PRACTICAL_SUFFIX_SAMPLE);
+    }
+
+    Ok(())
+}
 
 /// Build and write the metagenomic index to disk.
 ///
 /// The actual construction logic is in `mtsv::index::MGIndex`, this just handles the I/O and
-/// parsing.
+/// parsing. Indices built from `HeaderFormat::TaxId` or `HeaderFormat::Accession` headers are
+/// marked as having synthetic GIs, and the latter keep their accessions.
 pub fn build_and_write_index<R>(records: R,
                                 index_path: &str,
                                 sample_interval: u32,
-                                suffix_sample: usize,)
+                                suffix_sample: usize,
+                                header_format: HeaderFormat)
                                 -> MtsvResult<()>
     where R: Iterator<Item = io::Result<fasta::Record>>
 {
-    let taxon_map = parse_fasta_db(records)?;
+    build_and_write_index_with_prescreen(records,
+                                         index_path,
+                                         sample_interval,
+                                         suffix_sample,
+                                         header_format,
+                                         None,
+                                         None)
+}
+
+/// Like `build_and_write_index`, but also build a prescreen over the index's k-mers with the
+/// given parameters, if any, and write it to `prescreen::prescreen_path(index_path)`.
+///
+/// If `compression` gives a level from 1 to 19, the index is written compressed (see
+/// `io::write_to_file_compressed`). The prescreen is always written uncompressed.
+pub fn build_and_write_index_with_prescreen<R>(records: R,
+                                               index_path: &str,
+                                               sample_interval: u32,
+                                               suffix_sample: usize,
+                                               header_format: HeaderFormat,
+                                               prescreen: Option<&PrescreenParams>,
+                                               compression: Option<u32>)
+                                               -> MtsvResult<()>
+    where R: Iterator<Item = io::Result<fasta::Record>>
+{
+    let options = BuildOptions {
+        sample_interval,
+        suffix_sample,
+        header_format,
+        prescreen: prescreen.cloned(),
+        compression,
+        ..BuildOptions::default()
+    };
+    build_and_write_index_with_options(records, index_path, &options)
+}
+
+/// Build and write the metagenomic index to disk as `options` say, along with any prescreen (see
+/// `build_and_write_index_with_prescreen`). The suffix array is built on `options.threads`
+/// threads (see `MGIndex::with_build_threads`). Mapped indices can't be compressed.
+///
+/// With `options.shard_size`, the references are cut into shards instead, in the order they're
+/// read, each written as an index of its own to `shard_path(index_path, n)` along with a
+/// `ShardManifest` (see `build_and_write_shards`), and nothing is written to `index_path`.
+///
+/// With `options.resume`, the build is saved to `checkpoint_path(index_path)` after each of its
+/// slow stages, and if a valid checkpoint made with the same options is already there, the build
+/// carries on from it without reading `records` at all. The checkpoint is removed once the index
+/// is written.
+///
+/// `options.acc2taxid` can't be used here, since `records` can only be read once (see
+/// `build_and_write_index_from_files`).
+pub fn build_and_write_index_with_options<R>(records: R,
+                                             index_path: &str,
+                                             options: &BuildOptions)
+                                             -> MtsvResult<()>
+    where R: Iterator<Item = io::Result<fasta::Record>>
+{
+    build_and_write_index_from_records(records, index_path, options, || {
+        Err(MtsvError::InvalidParameter(String::from("accession2taxid files can only be used \
+                                                      to build from FASTA files")))
+    })
+}
+
+/// Build and write the index as `build_and_write_index_with_options` does, calling `accessions`
+/// for the accessions of the references in `records` if they're needed to load
+/// `options.acc2taxid`.
+fn build_and_write_index_from_records<R, F>(records: R,
+                                            index_path: &str,
+                                            options: &BuildOptions,
+                                            accessions: F)
+                                            -> MtsvResult<()>
+    where R: Iterator<Item = io::Result<fasta::Record>>,
+          F: FnOnce() -> MtsvResult<HashSet<String>>
+{
+    if let Some(checkpoint) = start_build(index_path, options)? {
+        return finish_build(checkpoint, index_path, options);
+    }
+
+    let accession_map = load_accession_map(options, accessions)?;
+    let parse = |add: &mut ReferenceAdder| {
+        parse_fasta_references(records,
+                               options.header_format,
+                               accession_map.as_ref(),
+                               options.strict_references,
+                               add)
+    };
+    build_and_write_parsed(parse, index_path, options)
+}
+
+/// The accession map `options` say to look up accession headers in, if the headers are
+/// accessions, calling `accessions` for the accessions to load from `options.acc2taxid`.
+fn load_accession_map<F>(options: &BuildOptions, accessions: F) -> MtsvResult<Option<AccessionMap>>
+    where F: FnOnce() -> MtsvResult<HashSet<String>>
+{
+    Ok(match (options.header_format, &options.accession_map, &options.acc2taxid) {
+        (HeaderFormat::Accession, Some(_), Some(_)) => {
+            return Err(MtsvError::InvalidParameter(String::from("only one of an accession map \
+                                                                 and an accession2taxid file \
+                                                                 can be used")));
+        },
+        (HeaderFormat::Accession, Some(path), None) => Some(AccessionMap::from_file(path)?),
+        (HeaderFormat::Accession, None, Some(path)) => {
+            Some(AccessionMap::from_accession2taxid(path, &accessions()?)?)
+        },
+        (_, map, acc2taxid) => {
+            if map.is_some() || acc2taxid.is_some() {
+                warn!("The accession map is only used for accession headers, ignoring it.");
+            }
+            None
+        },
+    })
+}
+
+/// Build and write the index from the FASTA files listed in the manifest at `manifest_path`, as
+/// `build_and_write_index_with_options` does, each file's references taking the taxid it's
+/// listed with (see `io::parse_manifest_references`). The references' headers aren't parsed, only
+/// kept whole as descriptions, so `options.header_format`, `options.accession_map` and
+/// `options.acc2taxid` are ignored.
+pub fn build_and_write_index_from_manifest(manifest_path: &str,
+                                           index_path: &str,
+                                           options: &BuildOptions)
+                                           -> MtsvResult<()> {
+    if let Some(checkpoint) = start_build(index_path, options)? {
+        return finish_build(checkpoint, index_path, options);
+    }
+
+    let manifest = FastaManifest::from_file(manifest_path)?;
+    info!("Reading the references of {} files listed in {}...",
+          manifest.len(),
+          manifest_path);
+    let parse = |add: &mut ReferenceAdder| {
+        parse_manifest_references(&manifest, options.strict_references, add)
+    };
+    build_and_write_parsed(parse, index_path, options)
+}
+
+/// Called with each reference as it's parsed, with its GI, taxid, accession and description if
+/// it has them, and bases (see `io::parse_fasta_references`).
+type ReferenceAdder<'a> = dyn FnMut(Gi, TaxId, Option<&str>, Option<&str>, &[u8]) -> MtsvResult<()>
+                          + 'a;
+
+/// Check the options of a build of `index_path` before any references are read, and return the
+/// checkpoint of an earlier build to carry on from if the options resume one.
+fn start_build(index_path: &str, options: &BuildOptions) -> MtsvResult<Option<Checkpoint>> {
+    validate_sampling(options.sample_interval, options.suffix_sample)?;
+    if options.mapped && options.compression.is_some() {
+        return Err(MtsvError::InvalidParameter(String::from("mapped indices can't be \
+                                                             compressed")));
+    }
+    // don't parse the whole database only to fail once it's built
+    if options.alphabet != Alphabet::Dna && options.mapped {
+        return Err(MtsvError::InvalidParameter(format!("{} indices can't be mapped",
+                                                       options.alphabet.name())));
+    }
+    if !options.alphabet.is_dna() && options.external_sa.is_some() {
+        return Err(MtsvError::InvalidParameter(format!("{} indices can't have their suffix \
+                                                        array sorted externally",
+                                                       options.alphabet.name())));
+    }
+    if !options.alphabet.is_dna() && options.mask_low_complexity.is_some() {
+        return Err(MtsvError::InvalidParameter(format!("low-complexity masking is for DNA, not \
+                                                        {} indices",
+                                                       options.alphabet.name())));
+    }
+    let spaced = matches!(options.bin_mode, BinMode::PerTaxon { spacer_len } if spacer_len > 0);
+    if options.alphabet == Alphabet::Dna4 && spaced {
+        return Err(MtsvError::InvalidParameter(String::from("dna4 indices can't space a taxon's \
+                                                             references with Ns; use a spacer \
+                                                             length of 0")));
+    }
+    if options.keep_descriptions && options.bin_mode != BinMode::PerGi {
+        return Err(MtsvError::InvalidParameter(String::from("indices with a bin per taxid can't \
+                                                             keep descriptions")));
+    }
+    if options.resume {
+        if options.shard_size.is_some() {
+            return Err(MtsvError::InvalidParameter(String::from("sharded builds can't be \
+                                                                 resumed")));
+        }
+        return resumable_checkpoint(index_path, options);
+    }
+    Ok(None)
+}
+
+/// Build and write the index of the references `parse` passes to the adder it's given, in shards
+/// if `options.shard_size` says so.
+fn build_and_write_parsed<P>(parse: P, index_path: &str, options: &BuildOptions) -> MtsvResult<()>
+    where P: FnOnce(&mut ReferenceAdder) -> MtsvResult<()>
+{
+    if let Some(shard_size) = options.shard_size {
+        return build_and_write_shards(parse, index_path, shard_size, options);
+    }
+
+    let checkpoint = read_references(parse, options)?;
+    if options.resume {
+        checkpoint.write(&checkpoint_path(index_path))?;
+    }
+    finish_build(checkpoint, index_path, options)
+}
+
+/// Parse the references and concatenate them: the first stage of a build.
+fn read_references<P>(parse: P, options: &BuildOptions) -> MtsvResult<Checkpoint>
+    where P: FnOnce(&mut ReferenceAdder) -> MtsvResult<()>
+{
+    // the references are concatenated as they're parsed, so they're only held once
+    let mut references = options.references();
+    let mut accessions = BTreeMap::new();
+    let mut descriptions = BTreeMap::new();
+    let mut add = |gi, tax_id, accession: Option<&str>, description: Option<&str>, seq: &[u8]| {
+        options.check_ambiguity(gi, seq)?;
+        references.push(gi, tax_id, seq);
+        if let Some(accession) = options.kept_accession(accession) {
+            accessions.insert(gi, accession.to_string());
+        }
+        if let Some(description) = description.filter(|_| options.keep_descriptions) {
+            descriptions.insert(gi, description.to_string());
+        }
+        Ok(())
+    };
+    parse(&mut add)?;
 
     info!("File parsed, building index...");
-    let index = MGIndex::new(taxon_map, sample_interval, suffix_sample);
+    Ok(Checkpoint::new(references, accessions, descriptions, options))
+}
+
+/// The checkpoint of an earlier build of `index_path` to carry on from, if there's a valid one.
+/// Invalid checkpoints are ignored with a warning and the build starts over, but one made with
+/// different options fails the build rather than throwing its progress away.
+fn resumable_checkpoint(index_path: &str,
+                        options: &BuildOptions)
+                        -> MtsvResult<Option<Checkpoint>> {
+    let path = checkpoint_path(index_path);
+    if !Path::new(&path).exists() {
+        info!("No checkpoint at {}, starting from the beginning", path);
+        return Ok(None);
+    }
+    match Checkpoint::from_file(&path) {
+        Ok(ref checkpoint) if checkpoint.options != options.checkpointed() => {
+            Err(MtsvError::InvalidCheckpoint(path,
+                                             String::from("it was made with different build \
+                                                           options, remove it to start over")))
+        },
+        Ok(checkpoint) => {
+            info!("Resuming from checkpoint {} {}", path, checkpoint.stage.describe());
+            Ok(Some(checkpoint))
+        },
+        Err(e @ MtsvError::InvalidCheckpoint(..)) => {
+            warn!("{}, starting from the beginning", e);
+            Ok(None)
+        },
+        Err(e) => Err(e),
+    }
+}
+
+/// Carry the build in `checkpoint` through its remaining stages and write the index to
+/// `index_path` as `options` say, along with any prescreen. With `options.resume`, the sorted
+/// references are saved to `checkpoint_path(index_path)` once they're built, and the checkpoint
+/// is removed once the index is written.
+fn finish_build(mut checkpoint: Checkpoint,
+                index_path: &str,
+                options: &BuildOptions)
+                -> MtsvResult<()> {
+    let path = checkpoint_path(index_path);
+    if options.resume {
+        if let BuildStage::Concatenated(_) = checkpoint.stage {
+            checkpoint = checkpoint.advance(options)?;
+            checkpoint.write(&path)?;
+        }
+    }
+    let index = checkpoint.into_index(options)?;
+    write_index(&index, index_path, options)?;
+    if options.resume {
+        fs::remove_file(&path)?;
+    }
+    Ok(())
+}
+
+/// Write `index` to `index_path` as `options` say, along with any prescreen.
+fn write_index(index: &MGIndex, index_path: &str, options: &BuildOptions) -> MtsvResult<()> {
+    if let Some(ref params) = options.prescreen {
+        info!("Building prescreen...");
+        let prescreen = Prescreen::new(index, params);
+        write_to_file(&prescreen, &prescreen_path(index_path))?;
+    }
+
+    match options.compression {
+        None if options.mapped => {
+            info!("Writing mapped index to file...");
+            index.write_mapped(index_path)?;
+        },
+        Some(level) => {
+            info!("Writing index to file (compression level {})...", level);
+            write_to_file_compressed(index, index_path, level)?;
+        },
+        None => {
+            info!("Writing index to file...");
+            write_to_file(index, index_path)?;
+        },
+    }
+
+    Ok(())
+}
+
+/// The references of the shard being filled by `build_and_write_shards`.
+struct Shard {
+    references: References,
+    accessions: HeaderMap,
+    descriptions: HeaderMap,
+    taxids: BTreeSet<TaxId>,
+    last_gi: Option<Gi>,
+}
+
+impl Shard {
+    /// An empty shard of references built as `options` say.
+    fn new(options: &BuildOptions) -> Self {
+        Shard {
+            references: options.references(),
+            accessions: BTreeMap::new(),
+            descriptions: BTreeMap::new(),
+            taxids: BTreeSet::new(),
+            last_gi: None,
+        }
+    }
+
+    /// Build the shard's index, write it to `index_path` and return its manifest entry.
+    fn write(self, index_path: &str, options: &BuildOptions) -> MtsvResult<ShardEntry> {
+        let entry = ShardEntry {
+            index: index_path.to_string(),
+            references: self.references.len(),
+            bases: self.references.bases(),
+            taxids: self.taxids.into_iter().collect(),
+        };
+        info!("Building shard {} ({} references, {} bases)...",
+              index_path,
+              entry.references,
+              entry.bases);
+        let checkpoint =
+            Checkpoint::new(self.references, self.accessions, self.descriptions, options);
+        finish_build(checkpoint, index_path, options)?;
+        Ok(entry)
+    }
+}
+
+/// Build and write the index in shards of about `shard_size` bases (see
+/// `build_and_write_index_with_options`).
+///
+/// A shard is written as soon as the next reference would take it over `shard_size`, so only one
+/// shard's references are held at a time. Shards are only cut between references, and never
+/// between records with the same GI, so a single reference longer than `shard_size` gets a
+/// shard to itself. A GI whose records aren't next to each other would have to be split, so it
+/// fails the build. An empty database is written as a single empty shard.
+fn build_and_write_shards<P>(parse: P,
+                             index_path: &str,
+                             shard_size: usize,
+                             options: &BuildOptions)
+                             -> MtsvResult<()>
+    where P: FnOnce(&mut ReferenceAdder) -> MtsvResult<()>
+{
+    if shard_size < 1 {
+        return Err(MtsvError::InvalidParameter(String::from("shards must be at least 1 base")));
+    }
+
+    let mut manifest = ShardManifest { shards: Vec::new() };
+    let mut shard = Shard::new(options);
+    let mut shard_of_gi = HashMap::new();
+    let mut add = |gi, tax_id, accession: Option<&str>, description: Option<&str>, seq: &[u8]| {
+        options.check_ambiguity(gi, seq)?;
+        if !shard.references.is_empty() && shard.last_gi != Some(gi) &&
+           shard.references.bases() + seq.len() > shard_size {
+            let path = shard_path(index_path, manifest.shards.len());
+            let full = mem::replace(&mut shard, Shard::new(options));
+            manifest.shards.push(full.write(&path, options)?);
+        }
+
+        let current = manifest.shards.len();
+        if let Some(earlier) = shard_of_gi.insert(gi, current) {
+            if earlier != current {
+                return Err(MtsvError::InvalidParameter(format!(
+                    "GI {} has records in shards {} and {}, a GI's records must be next to \
+                     each other to shard the database",
+                    gi.0,
+                    earlier,
+                    current)));
+            }
+        }
+
+        shard.references.push(gi, tax_id, seq);
+        if let Some(accession) = options.kept_accession(accession) {
+            shard.accessions.insert(gi, accession.to_string());
+        }
+        if let Some(description) = description.filter(|_| options.keep_descriptions) {
+            shard.descriptions.insert(gi, description.to_string());
+        }
+        shard.taxids.insert(tax_id);
+        shard.last_gi = Some(gi);
+        Ok(())
+    };
+    parse(&mut add)?;
+
+    if !shard.references.is_empty() || manifest.shards.is_empty() {
+        let path = shard_path(index_path, manifest.shards.len());
+        manifest.shards.push(shard.write(&path, options)?);
+    }
+
+    info!("Writing manifest of {} shards...", manifest.shards.len());
+    let mut writer = BufWriter::new(File::create(shard_manifest_path(index_path))?);
+    serde_json::to_writer_pretty(&mut writer, &manifest)?;
+    writer.write_all(b"\n")?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Build and write the index from the records of the FASTA files at `paths`, one file after
+/// another, as `build_and_write_index_with_options` does. The references keep the files' order, so
+/// the index is the same as one built from the files concatenated. Files may be gzipped, and a
+/// record which can't be read or parsed is reported with its file and number.
+///
+/// With `options.acc2taxid`, the files are read twice: once for the references' accessions, so
+/// that only their entries are kept from the accession2taxid file, and again to build the index.
+pub fn build_and_write_index_from_files<P: AsRef<str>>(paths: &[P],
+                                                       index_path: &str,
+                                                       options: &BuildOptions)
+                                                       -> MtsvResult<()> {
+    let mut records = FastaFiles::open(paths)?;
+    let accessions = || accessions_of_files(paths);
+    build_and_write_index_from_records(&mut records, index_path, options, accessions)
+        .map_err(|e| locate_record_error(&records, e))
+}
+
+/// Read the records of the FASTA files at `paths` as `build_and_write_index_from_files` would,
+/// with `options`, without building anything, and return what the index would hold and which
+/// records would fail the build (see `io::survey_fasta_references`). References with ambiguity
+/// codes fail if `options.ambiguity` rejects them. Only the sequences' lengths are kept, so this
+/// takes little memory whatever the size of the references.
+pub fn survey_references_from_files<P: AsRef<str>>(paths: &[P],
+                                                   options: &BuildOptions)
+                                                   -> MtsvResult<ReferenceSurvey> {
+    let accession_map = load_accession_map(options, || accessions_of_files(paths))?;
+    let mut records = FastaFiles::open(paths)?;
+    survey_fasta_references(&mut records,
+                            options.header_format,
+                            accession_map.as_ref(),
+                            options.strict_references,
+                            |gi, seq| options.check_ambiguity(gi, seq))
+        .map_err(|e| locate_record_error(&records, e))
+}
+
+/// The accessions of the records of the FASTA files at `paths`, read to pick the entries of an
+/// accession2taxid file to load, since it's too big to load whole.
+fn accessions_of_files<P: AsRef<str>>(paths: &[P]) -> MtsvResult<HashSet<String>> {
+    if paths.iter().any(|path| path.as_ref() == STDIN_PATH) {
+        return Err(MtsvError::InvalidParameter(String::from("accession2taxid files can't be \
+                                                             used with references from standard \
+                                                             input")));
+    }
+    info!("Reading reference accessions...");
+    let mut records = FastaFiles::open(paths)?;
+    let accessions = (&mut records)
+        .map(|record| record.map(|r| r.id().to_string()))
+        .collect::<io::Result<HashSet<_>>>();
+    accessions.map_err(|e| locate_record_error(&records, e.into()))
+}
+
+/// Name the file and record `records` stopped at in `e`, if it stopped part way through, which
+/// only happens if one of the records failed.
+fn locate_record_error(records: &FastaFiles, e: MtsvError) -> MtsvError {
+    match records.position() {
+        Some((path, record)) => {
+            MtsvError::InvalidFastaRecord(path.to_string(), record, Box::new(e))
+        },
+        None => e,
+    }
+}
+
+/// Merge the indices at `paths` into one holding all of their references, and write it to
+/// `index_path` as `options` say, along with any prescreen (see `MGIndex::merge`). The merged
+/// index is the same as one built from all of the inputs' references at once, with
+/// `options.sample_interval` and `options.suffix_sample`, which needn't match the inputs'. The
+/// inputs are loaded one at a time and dropped once their references are copied, so only the
+/// merged references and one input are held at once. Fails if the same GI is in more than one
+/// input. Inputs in the mapped layout can't be merged, and `options.header_format`,
+/// `options.accession_map` and `options.shard_size` are ignored.
+pub fn merge_and_write_indices<P: AsRef<str>>(paths: &[P],
+                                              index_path: &str,
+                                              options: &BuildOptions)
+                                              -> MtsvResult<()> {
+    validate_sampling(options.sample_interval, options.suffix_sample)?;
+    if options.mapped && options.compression.is_some() {
+        return Err(MtsvError::InvalidParameter(String::from("mapped indices can't be \
+                                                             compressed")));
+    }
+    if paths.is_empty() {
+        return Err(MtsvError::InvalidParameter(String::from("no indices to merge")));
+    }
+
+    let mut merge = IndexMerge::new();
+    for path in paths {
+        let path = path.as_ref();
+        info!("Loading {}...", path);
+        let index = MGIndex::from_file(path)?;
+        if index.sampling_params() != (options.sample_interval, options.suffix_sample) {
+            info!("{} was built with sample interval {} and suffix array sample rate {}, the \
+                   merged index will have {} and {}",
+                  path,
+                  index.sampling_params().0,
+                  index.sampling_params().1,
+                  options.sample_interval,
+                  options.suffix_sample);
+        }
+        merge.add(path, index)?;
+    }
+
+    info!("Building merged index of {} references...", merge.len());
+    let index = merge.build(options.sample_interval,
+                            options.suffix_sample,
+                            options.threads,
+                            options.external_sa.as_deref())?;
+    write_index(&index, index_path, options)
+}
+
+/// What `subset_and_write_index` kept of the taxa it was asked for.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SubsetReport {
+    /// Number of the taxids asked for which are in the subset index.
+    pub taxa: usize,
+    /// Number of reference sequences (GIs) in the subset index.
+    pub references: usize,
+    /// The taxids asked for which aren't in the source index.
+    pub missing: Vec<TaxId>,
+}
 
-    info!("Writing index to file...");
-    write_to_file(&index, index_path)?;
+/// Build an index from only the references of `tax_ids` in the index at `source_path`, and write
+/// it to `index_path` as `options` say, along with any prescreen (see `MGIndex::subset`). The
+/// subset index is the same as one built from just those references, with
+/// `options.sample_interval` and `options.suffix_sample`. Fails without writing anything if none
+/// of the taxids are in the source index. Sources in the mapped layout can't be read, and
+/// `options.header_format`, `options.accession_map`, `options.external_sa` and
+/// `options.shard_size` are ignored.
+pub fn subset_and_write_index(source_path: &str,
+                              tax_ids: &BTreeSet<TaxId>,
+                              index_path: &str,
+                              options: &BuildOptions)
+                              -> MtsvResult<SubsetReport> {
+    validate_sampling(options.sample_interval, options.suffix_sample)?;
+    if options.mapped && options.compression.is_some() {
+        return Err(MtsvError::InvalidParameter(String::from("mapped indices can't be \
+                                                             compressed")));
+    }
+
+    info!("Loading {}...", source_path);
+    let source = MGIndex::from_file(source_path)?;
+    let (kept, missing): (Vec<TaxId>, Vec<TaxId>) =
+        tax_ids.iter().partition(|&&tax_id| source.contains_tax_id(tax_id));
+    if kept.is_empty() {
+        return Err(MtsvError::InvalidParameter(format!("none of the {} taxids are in index {}",
+                                                       tax_ids.len(),
+                                                       source_path)));
+    }
+
+    info!("Building index of {} taxa...", kept.len());
+    let index = source.subset(tax_ids,
+                              options.sample_interval,
+                              options.suffix_sample,
+                              options.threads);
+    drop(source);
+    write_index(&index, index_path, options)?;
+
+    Ok(SubsetReport {
+        taxa: kept.len(),
+        references: index.bin_count(),
+        missing,
+    })
+}
 
+/// Rebuild the index at `index_path` without the references of `tax_ids`, keeping its sampling
+/// parameters, and write it back to `index_path` as `options` say, along with any prescreen (see
+/// `MGIndex::remove`). The new index is written next to the old one and then moved over it, so
+/// the old index is left as it was if anything fails. A prescreen built for the old index no
+/// longer matches it, so it's replaced if `options.prescreen` is set, and otherwise left for
+/// mtsv-binner to ignore. Indices in the mapped layout can't be read, and
+/// `options.sample_interval`, `options.suffix_sample`, `options.header_format`,
+/// `options.accession_map`, `options.external_sa` and `options.shard_size` are ignored.
+pub fn remove_taxa_and_rewrite_index(index_path: &str,
+                                     tax_ids: &[TaxId],
+                                     options: &BuildOptions)
+                                     -> MtsvResult<()> {
+    if options.mapped && options.compression.is_some() {
+        return Err(MtsvError::InvalidParameter(String::from("mapped indices can't be \
+                                                             compressed")));
+    }
+
+    info!("Loading {}...", index_path);
+    let index = MGIndex::from_file(index_path)?.remove_with_build_threads(tax_ids, options.threads);
+
+    let new_path = format!("{}.tmp", index_path);
+    write_index(&index, &new_path, options)?;
+    fs::rename(&new_path, index_path)?;
+    if options.prescreen.is_some() {
+        fs::rename(prescreen_path(&new_path), prescreen_path(index_path))?;
+    } else if Path::new(&prescreen_path(index_path)).exists() {
+        warn!("{} was built for the index before the taxa were removed, so it won't be used. \
+               Rebuild it with --build-prescreen.",
+              prescreen_path(index_path));
+    }
     Ok(())
 }
 
+/// The outcome of querying one reference's sequence against the index it was built into.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VerifySample {
+    /// The reference the query was taken from.
+    pub bin: BinInfo,
+    /// Offset of the query within the reference.
+    pub offset: usize,
+    /// The taxids of the hits found, which should include the reference's.
+    pub hits: Vec<TaxId>,
+}
+
+impl VerifySample {
+    /// Whether the reference's taxid was found.
+    pub fn passed(&self) -> bool {
+        self.hits.contains(&self.bin.tax_id)
+    }
+}
+
+/// The results of `verify_index`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VerifyReport {
+    /// Every reference queried, in index order.
+    pub samples: Vec<VerifySample>,
+    /// Number of sampled references which were skipped because they were shorter than a seed, or
+    /// no window of them was free of ambiguous bases, which never match.
+    pub skipped: usize,
+}
+
+impl VerifyReport {
+    /// The samples whose reference's taxid wasn't found.
+    pub fn failures(&self) -> impl Iterator<Item = &VerifySample> {
+        self.samples.iter().filter(|s| !s.passed())
+    }
+
+    /// Whether every sample found its reference's taxid.
+    pub fn passed(&self) -> bool {
+        self.failures().next().is_none()
+    }
+}
+
+/// Check that an index finds the references it was built from, by querying `read_len` bases from
+/// each of `samples` randomly chosen references (or all of them, if there are fewer) with no edits
+/// allowed, and checking the reference's taxid is among the hits.
+///
+/// This goes through the same FM index, bin lookup and alignment as binning, so it catches an
+/// index which is corrupt or was built wrongly. References shorter than `read_len` are queried in
+/// full. Seed hit limits are lifted so that repetitive references can still be found, and
+/// windows with ambiguous or soft-masked bases aren't queried.
+pub fn verify_index(index: &MGIndex,
+                    samples: usize,
+                    read_len: usize,
+                    run: &RunContext)
+                    -> MtsvResult<VerifyReport> {
+    let params = QueryParams {
+        edit_freq: 0.0,
+        max_hits: usize::MAX,
+        tune_max_hits: usize::MAX,
+        max_hits_per_bin: usize::MAX,
+        ..QueryParams::default()
+    };
+    if read_len < params.seed_length {
+        return Err(MtsvError::InvalidParameter(format!("validation reads must be at least {} \
+                                                        bases, got {}",
+                                                       params.seed_length,
+                                                       read_len)));
+    }
+
+    let wildcard = index.alphabet().wildcard();
+    let mut rng = run.rng("verify-index");
+    let mut bins = index.bins().collect::<Vec<_>>();
+    rng.shuffle(&mut bins);
+    bins.truncate(samples);
+    bins.sort_by_key(|b| b.start);
+
+    let fmindex = index.fm_index();
+
+    let mut report = VerifyReport::default();
+    for bin in bins {
+        let len = read_len.min(bin.end - bin.start);
+        if len < params.seed_length {
+            report.skipped += 1;
+            continue;
+        }
+
+        // try a few windows for one without ambiguous or masked bases
+        let sequence = index.reference_bases(bin.start, bin.end);
+        let last_offset = bin.end - bin.start - len;
+        let offset = (0..10)
+            .map(|_| rng.gen_range(0, last_offset + 1))
+            .find(|&o| {
+                sequence[o..o + len].iter().all(|&b| index.alphabet().normalize(b) != wildcard) &&
+                !index.overlaps_soft_mask(bin.start + o, bin.start + o + len)
+            });
+        let offset = match offset {
+            Some(o) => o,
+            None => {
+                report.skipped += 1;
+                continue;
+            },
+        };
+
+        let query = &sequence[offset..offset + len];
+        let hits = index.matching_tax_ids(&fmindex, query, &params, &mut QueryStats::default());
+        report.samples.push(VerifySample {
+            bin,
+            offset,
+            hits: hits.into_iter().map(|h| h.tax_id).collect(),
+        });
+    }
+
+    Ok(report)
+}
+
+/// Where `mark_invalid_index` moves an index which failed validation.
+pub(crate) fn invalid_index_path(index_path: &str) -> String {
+    format!("{}.invalid", index_path)
+}
+
+/// Move the index at `index_path` to `invalid_index_path(index_path)`, so that nothing picks it up
+/// as a working index, and return the new path.
+pub fn mark_invalid_index(index_path: &str) -> MtsvResult<String> {
+    let invalid_path = invalid_index_path(index_path);
+    fs::rename(index_path, &invalid_path)?;
+    Ok(invalid_path)
+}
+
+/// Check that an index finds a sample of its references (see `verify_index`), logging the
+/// outcome for each one. Returns whether they were all found.
+pub fn validate_index(index: &MGIndex,
+                      samples: usize,
+                      read_len: usize,
+                      run: &RunContext)
+                      -> MtsvResult<bool> {
+    info!("Validating index (seed {})...", run.seed);
+    let report = verify_index(index, samples, read_len, run)?;
+
+    for sample in &report.samples {
+        let outcome = if sample.passed() { "pass" } else { "FAIL" };
+        info!("Validation of gi {} (taxid {}) at offset {}: {}",
+              sample.bin.gi.0,
+              sample.bin.tax_id.0,
+              sample.offset,
+              outcome);
+    }
+    if report.skipped > 0 {
+        warn!("Validation skipped {} references too short or ambiguous to query",
+              report.skipped);
+    }
+
+    let failures = report.failures().count();
+    if failures > 0 {
+        error!("Validation failed: {} of {} references weren't found by the index",
+               failures,
+               report.samples.len());
+        Ok(false)
+    } else {
+        info!("Validation passed for {} references", report.samples.len());
+        Ok(true)
+    }
+}
+
 #[cfg(test)]
 mod test {
+    use binner::{dump_references, get_fasta_and_write_matching_bin_ids,
+                 get_reference_sequence_by_gi_from_index, get_reference_sequences_from_index,
+                 BinnerConfig};
     use bio::io::fasta::Reader;
+    use error::MtsvError;
+    use index::{MGIndex, QueryParams};
+    use io::FindingsVersion;
     use mktemp::Temp;
-    use std::io::Cursor;
-    use super::build_and_write_index;
+    use std::fs::File;
+    use std::io::{Cursor, Read, Write};
+    use std::iter;
+    use super::*;
+    use util::{parse_reference_header, HeaderFormat};
 
     #[test]
     fn success() {
@@ -53,7 +1245,7 @@ AAAACACATATTTTCAAATCTAGTAAATATTAAATCTACTCTTGACGATTGCACCAATGCTACGCGATATAGATATCCAC
         let outfile_str = outfile_path.to_str().unwrap();
 
 
-        build_and_write_index(records, outfile_str, 32, 64).unwrap();
+        build_and_write_index(records, outfile_str, 32, 64, HeaderFormat::GiTaxId).unwrap();
 
         assert!(outfile_path.exists());
         assert!(outfile_path.is_file());
@@ -63,6 +1255,452 @@ AAAACACATATTTTCAAATCTAGTAAATATTAAATCTACTCTTGACGATTGCACCAATGCTACGCGATATAGATATCCAC
         assert!(metadata.len() > reference.len() as u64);
     }
 
+    #[test]
+    fn reproducible_index_file() {
+        let records = [">124-456\nTTTCACCTAGTACATTAAATACACGACCTAATGTTTCGTCACCAACAGGTACACTAATTTC\n",
+                       ">908-678\nAAAACACATATTTTCAAATCTAGTAAATATTAAATCTACTCTTGACGATTGCACCAATGCT\n",
+                       ">123-456\nTGTCTTAATGATAAAAATTGTTACAAACAGTTTAACATATTTAGCTACCTATTTTGCATAT\n",
+                       ">909-678\nCATAACCATCATTGTTAGAAACAAAATTATTTCCATGATAACCCTCACTTAATATATTTCT\n"];
+        let reversed = records.iter().rev().cloned().collect::<Vec<_>>();
+
+        let dir = Temp::new_dir().unwrap();
+        let dir = dir.to_path_buf();
+        let mut index_bytes = Vec::new();
+        let mut fingerprints = Vec::new();
+
+        for (i, reference) in [records.concat(), reversed.concat()].iter().enumerate() {
+            let index_path = dir.join(format!("{}.index", i));
+            let index_path = index_path.to_str().unwrap();
+
+            let records = Reader::new(Cursor::new(reference.as_bytes())).records();
+            build_and_write_index(records, index_path, 32, 64, HeaderFormat::GiTaxId).unwrap();
+
+            let mut bytes = Vec::new();
+            File::open(index_path).unwrap().read_to_end(&mut bytes).unwrap();
+            index_bytes.push(bytes);
+            fingerprints.push(MGIndex::from_file(index_path).unwrap().fingerprint());
+        }
+
+        assert_eq!(fingerprints[0], fingerprints[1]);
+        assert!(index_bytes[0] == index_bytes[1]);
+    }
+
+    #[test]
+    fn gzipped_database() {
+        use flate2::write::GzEncoder;
+        use flate2::Compression;
+        use io::open_fasta;
+
+        let reference = ">124-456\nTTTCACCTAGTACATTAAATACACGACCTAATGTTTCGTCACCAACAGGTACACTAATTTC\n\
+                         >908-678\nAAAACACATATTTTCAAATCTAGTAAATATTAAATCTACTCTTGACGATTGCACCAATGCT\n";
+        let dir = Temp::new_dir().unwrap();
+        let dir = dir.to_path_buf();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+
+        File::create(path("db.fasta")).unwrap().write_all(reference.as_bytes()).unwrap();
+        // the extension doesn't matter, only the gzip magic bytes
+        let mut encoder = GzEncoder::new(File::create(path("db.fasta.dat")).unwrap(),
+                                         Compression::Default);
+        encoder.write_all(reference.as_bytes()).unwrap();
+        encoder.finish().unwrap();
+
+        for name in &["db.fasta", "db.fasta.dat"] {
+            let records = open_fasta(&path(name)).unwrap().records();
+            build_and_write_index(records,
+                                  &path(&format!("{}.index", name)),
+                                  32,
+                                  64,
+                                  HeaderFormat::GiTaxId)
+                .unwrap();
+        }
+        let read = |name: &str| {
+            let mut bytes = Vec::new();
+            File::open(path(name)).unwrap().read_to_end(&mut bytes).unwrap();
+            bytes
+        };
+        assert!(read("db.fasta.index") == read("db.fasta.dat.index"));
+    }
+
+    #[test]
+    fn multiple_databases() {
+        let files = [">124-456\nTTTCACCTAGTACATTAAATACACGACCTAATGTTTCGTCACCAACAGGTACACTAATTTC\n\
+                      >908-678\nAAAACACATATTTTCAAATCTAGTAAATATTAAATCTACTCTTGACGATTGCACCAATGCT\n",
+                     "",
+                     ">123-456\nTGTCTTAATGATAAAAATTGTTACAAACAGTTTAACATATTTAGCTACCTATTTTGCATAT\n"];
+        let dir = Temp::new_dir().unwrap();
+        let dir = dir.to_path_buf();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        let read = |name: &str| {
+            let mut bytes = Vec::new();
+            File::open(path(name)).unwrap().read_to_end(&mut bytes).unwrap();
+            bytes
+        };
+
+        let mut paths = (0..files.len()).map(|i| path(&format!("{}.fasta", i))).collect::<Vec<_>>();
+        let write_files = |headers: &dyn Fn(&str) -> String| {
+            for (path, contents) in paths.iter().zip(&files) {
+                File::create(path).unwrap().write_all(headers(contents).as_bytes()).unwrap();
+            }
+            File::create(path("all.fasta"))
+                .unwrap()
+                .write_all(headers(&files.concat()).as_bytes())
+                .unwrap();
+        };
+
+        // taxid headers check that GIs keep counting from one file to the next
+        for &format in &[HeaderFormat::GiTaxId, HeaderFormat::TaxId] {
+            if format == HeaderFormat::TaxId {
+                write_files(&|s| {
+                    s.replace(">124-", ">").replace(">908-", ">").replace(">123-", ">")
+                });
+            } else {
+                write_files(&|s| s.to_string());
+            }
+            let options = BuildOptions { header_format: format, ..BuildOptions::default() };
+            build_and_write_index_from_files(&paths, &path("split.index"), &options).unwrap();
+            build_and_write_index_from_files(&[path("all.fasta")], &path("all.index"), &options)
+                .unwrap();
+            assert!(read("split.index") == read("all.index"));
+        }
+
+        // errors say which file and record they're in
+        write_files(&|s| s.to_string());
+        File::create(&paths[2]).unwrap().write_all(b">1\nACGT\n>bad\nACGT\n").unwrap();
+        let options = BuildOptions::default();
+        match build_and_write_index_from_files(&paths, &path("bad.index"), &options) {
+            Err(e @ MtsvError::InvalidFastaRecord(..)) => {
+                assert!(e.to_string().contains(&format!("record 1 of {}", paths[2])), "{}", e)
+            },
+            other => panic!("expected a record error, got {:?}", other),
+        }
+        paths.push(path("missing.fasta"));
+        assert!(build_and_write_index_from_files(&paths, &path("bad.index"), &options).is_err());
+        match build_and_write_index_from_files(&["-", "-"], &path("bad.index"), &options) {
+            Err(MtsvError::InvalidParameter(msg)) => assert!(msg.contains("standard input")),
+            other => panic!("expected invalid parameter error, got {:?}", other),
+        }
+        assert!(!dir.join("bad.index").exists());
+    }
+
+    #[test]
+    fn merged_index_files() {
+        let files = [">124-456\nTTTCACCTAGTACATTAAATACACGACCTAATGTTTCGTCACCAACAGGTACACTAATTTC\n\
+                      >908-678\nAAAACACATATTTTCAAATCTAGTAAATATTAAATCTACTCTTGACGATTGCACCAATGCT\n",
+                     ">123-456\nTGTCTTAATGATAAAAATTGTTACAAACAGTTTAACATATTTAGCTACCTATTTTGCATAT\n\
+                      >909-12\nCATAACCATCATTGTTAGAAACAAAATTATTTCCATGATAACCCTCACTTAATATATTTCT\n"];
+        let dir = Temp::new_dir().unwrap();
+        let dir = dir.to_path_buf();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        let read = |name: &str| {
+            let mut bytes = Vec::new();
+            File::open(path(name)).unwrap().read_to_end(&mut bytes).unwrap();
+            bytes
+        };
+
+        // the inputs' sampling and compression don't carry over to the merged index
+        let input_options = [BuildOptions::default(),
+                             BuildOptions {
+                                 sample_interval: 8,
+                                 suffix_sample: 4,
+                                 compression: Some(1),
+                                 ..BuildOptions::default()
+                             }];
+        for (i, (contents, options)) in files.iter().zip(&input_options).enumerate() {
+            File::create(path(&format!("{}.fasta", i)))
+                .unwrap()
+                .write_all(contents.as_bytes())
+                .unwrap();
+            build_and_write_index_from_files(&[path(&format!("{}.fasta", i))],
+                                             &path(&format!("{}.index", i)),
+                                             options)
+                .unwrap();
+        }
+        File::create(path("all.fasta")).unwrap().write_all(files.concat().as_bytes()).unwrap();
+        let options = BuildOptions::default();
+        build_and_write_index_from_files(&[path("all.fasta")], &path("all.index"), &options)
+            .unwrap();
+
+        merge_and_write_indices(&[path("0.index"), path("1.index")],
+                                &path("merged.index"),
+                                &options)
+            .unwrap();
+        assert!(read("merged.index") == read("all.index"));
+
+        let inputs = [path("0.index"), path("all.index")];
+        match merge_and_write_indices(&inputs, &path("bad.index"), &options) {
+            Err(e @ MtsvError::DuplicateGi(..)) => {
+                assert_eq!(e.to_string(),
+                           format!("GI 124 is in both {} and {}", inputs[0], inputs[1]))
+            },
+            other => panic!("expected a duplicate GI error, got {:?}", other),
+        }
+        assert!(merge_and_write_indices::<&str>(&[], &path("bad.index"), &options).is_err());
+        assert!(!dir.join("bad.index").exists());
+    }
+
+    #[test]
+    fn sharded_index() {
+        let seqs = ["TTTCACCTAGTACATTAAATACACGACCTAATGTTTCGTCACCAACAGGTACACTAATTTC",
+                    "AAAACACATATTTTCAAATCTAGTAAATATTAAATCTACTCTTGACGATTGCACCAATGCT",
+                    "TGTCTTAATGATAAAAATTGTTACAAACAGTTTAACATATTTAGCTACCTATTTTGCATAT",
+                    "CATAACCATCATTGTTAGAAACAAAATTATTTCCATGATAACCCTCACTTAATATATTTCT",
+                    "GGTACACTAATTTCTTTGCCTGTATCTTTTACATCCATGCCTCTTTGGACACCATCAGTTG"];
+        // gi 908's two records stay together even though they go over the shard size
+        let headers = ["124-456", "908-678", "908-678", "123-456", "909-789"];
+        let reference = headers.iter()
+            .zip(&seqs)
+            .map(|(header, seq)| format!(">{}\n{}\n", header, seq))
+            .collect::<String>();
+
+        let dir = Temp::new_dir().unwrap();
+        let dir = dir.to_path_buf();
+        let index_path = dir.join("db.index").to_str().unwrap().to_string();
+        let options = BuildOptions { shard_size: Some(130), ..BuildOptions::default() };
+        let records = Reader::new(Cursor::new(reference.as_bytes())).records();
+        build_and_write_index_with_options(records, &index_path, &options).unwrap();
+
+        assert!(!dir.join("db.index").exists());
+        let manifest = ShardManifest::from_file(&shard_manifest_path(&index_path)).unwrap();
+        assert_eq!(manifest.index_paths(),
+                   vec![shard_path(&index_path, 0), shard_path(&index_path, 1)]);
+        let shards = manifest.shards.iter().map(|s| (s.references, s.bases)).collect::<Vec<_>>();
+        assert_eq!(shards, vec![(3, 183), (2, 122)]);
+        assert_eq!(manifest.shards[0].taxids, vec![TaxId(456), TaxId(678)]);
+        assert_eq!(manifest.shards[1].taxids, vec![TaxId(456), TaxId(789)]);
+
+        // each shard's bins find its own references
+        let expected = [vec![(124, seqs[0]), (908, seqs[1]), (908, seqs[2])],
+                        vec![(123, seqs[3]), (909, seqs[4])]];
+        for (path, expected) in manifest.index_paths().iter().zip(&expected) {
+            let index = MGIndex::from_file(path).unwrap();
+            let mut bins = index.bins()
+                .map(|bin| (bin.gi.0, index.reference_bases(bin.start, bin.end)))
+                .collect::<Vec<_>>();
+            bins.sort();
+            let mut expected = expected.iter()
+                .map(|&(gi, seq)| (gi, seq.as_bytes().to_vec()))
+                .collect::<Vec<_>>();
+            expected.sort();
+            assert_eq!(bins, expected);
+        }
+
+        // a GI's records can't be split between shards
+        let split = format!(">1-1\n{}\n>2-1\n{}\n>1-1\n{}\n", seqs[0], seqs[1], seqs[2]);
+        let records = Reader::new(Cursor::new(split.as_bytes())).records();
+        match build_and_write_index_with_options(records, &index_path, &options) {
+            Err(MtsvError::InvalidParameter(msg)) => assert!(msg.contains("GI 1"), "{}", msg),
+            other => panic!("expected invalid parameter error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn softmasked_index() {
+        let reference = ">1-1\nttcacctagtacattaaatacacgacctaatgtttcg\n\
+                         >2-2\naaaacacataTTTTCAAATCTAGTAAATATTAAATCTACTCTTGACGATTGC\n";
+        let dir = Temp::new_dir().unwrap();
+        let index_path = dir.to_path_buf().join("db.index").to_str().unwrap().to_string();
+        let build = |options: &BuildOptions| {
+            let records = Reader::new(Cursor::new(reference.as_bytes())).records();
+            build_and_write_index_with_options(records, &index_path, options).unwrap();
+            MGIndex::from_file(&index_path).unwrap()
+        };
+
+        assert_eq!(build(&BuildOptions::default()).soft_masked_bases(), 0);
+        let options = BuildOptions { respect_softmask: true, ..BuildOptions::default() };
+        let index = build(&options);
+        assert_eq!(index.soft_masked_bases(), 37 + 10);
+
+        // the masked reference can't be found, so validation leaves it out
+        let report = verify_index(&index, 2, 20, &RunContext::new(Some(7))).unwrap();
+        assert!(report.passed());
+        assert_eq!(report.skipped, 1);
+        assert_eq!(report.samples.iter().map(|s| s.bin.tax_id).collect::<Vec<_>>(),
+                   vec![TaxId(2)]);
+    }
+
+    #[test]
+    fn ambiguity_policies() {
+        let reference = ">1-1\nTTCACCTAGTACATTAAATACACGACCTAATGTTTCG\n\
+                         >2-2\nAAAACACATATTTTCAAATCTAGTAAATATTAAATCTACyCTTGACGATTGC\n";
+        let dir = Temp::new_dir().unwrap();
+        let index_path = dir.to_path_buf().join("db.index").to_str().unwrap().to_string();
+        let build = |ambiguity, shard_size| {
+            let records = Reader::new(Cursor::new(reference.as_bytes())).records();
+            let options = BuildOptions { ambiguity, shard_size, ..BuildOptions::default() };
+            build_and_write_index_with_options(records, &index_path, &options)
+        };
+
+        build(AmbiguityPolicy::N, None).unwrap();
+        assert_eq!(MGIndex::from_file(&index_path).unwrap().reference_bases(76, 77), b"N");
+        build(AmbiguityPolicy::Expand, None).unwrap();
+        let index = MGIndex::from_file(&index_path).unwrap();
+        assert_eq!(index.reference_bases(76, 77), b"Y");
+        // validation only samples windows without the code, which can't be found exactly
+        assert!(verify_index(&index, 2, 20, &RunContext::new(Some(7))).unwrap().passed());
+
+        for shard_size in [None, Some(40)] {
+            match build(AmbiguityPolicy::Reject, shard_size) {
+                Err(MtsvError::InvalidParameter(why)) => assert!(why.contains("GI 2")),
+                other => panic!("expected the ambiguity code to be rejected, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn resumed_build() {
+        let reference = ">1-562
+TGTCTTAATGATAAAAATTGTTACAAACAGTTTAACATATTTAGCTACCTATTTTGCATATAAAAAACATGCTTGCATACACTATGCAATAAAA
+>2-28901
+AAAACACATATTTTCAAATCTAGTAAATATTAAATCTACTCTTGACGATTGCACCAATGCTACGCGATATAGATATCCACTAAAAACATACG
+";
+        let dir = Temp::new_dir().unwrap();
+        let path = |name: &str| dir.to_path_buf().join(name).to_str().unwrap().to_string();
+        let records = || Reader::new(Cursor::new(reference.as_bytes())).records();
+        let unread = || {
+            iter::repeat_with(|| -> io::Result<fasta::Record> {
+                panic!("resumed builds don't read the references")
+            })
+        };
+
+        build_and_write_index_with_options(records(), &path("fresh.index"), &Default::default())
+            .unwrap();
+        let fresh = fs::read(path("fresh.index")).unwrap();
+
+        // builds stopped after either stage carry on to the same index, even on more threads
+        let options = BuildOptions { resume: true, ..BuildOptions::default() };
+        let index_path = path("resumed.index");
+        let checkpoint = checkpoint_path(&index_path);
+        let read = || {
+            let parse = |add: &mut ReferenceAdder| {
+                parse_fasta_references(records(), HeaderFormat::GiTaxId, None, false, add)
+            };
+            read_references(parse, &options).unwrap()
+        };
+        for &stages in &[1, 2] {
+            let mut stopped = read();
+            if stages == 2 {
+                stopped = stopped.advance(&options).unwrap();
+            }
+            stopped.write(&checkpoint).unwrap();
+            let resumed = BuildOptions { threads: 2, ..options.clone() };
+            build_and_write_index_with_options(unread(), &index_path, &resumed).unwrap();
+            assert!(fs::read(&index_path).unwrap() == fresh, "stopped after {}", stages);
+            assert!(!Path::new(&checkpoint).exists());
+        }
+
+        // corrupt checkpoints are ignored, and the build starts over
+        read().write(&checkpoint).unwrap();
+        let mut corrupt = fs::read(&checkpoint).unwrap();
+        let base = corrupt.len() - 20;
+        corrupt[base] ^= 1;
+        fs::write(&checkpoint, &corrupt).unwrap();
+        assert!(matches!(Checkpoint::from_file(&checkpoint),
+                         Err(MtsvError::InvalidCheckpoint(..))));
+        build_and_write_index_with_options(records(), &index_path, &options).unwrap();
+        assert!(fs::read(&index_path).unwrap() == fresh);
+        assert!(!Path::new(&checkpoint).exists());
+
+        // but one made with other options fails the build, and is kept
+        read().write(&checkpoint).unwrap();
+        let other = BuildOptions { suffix_sample: 16, ..options.clone() };
+        match build_and_write_index_with_options(unread(), &index_path, &other) {
+            Err(MtsvError::InvalidCheckpoint(_, why)) => {
+                assert!(why.contains("options"), "{}", why)
+            },
+            other => panic!("expected an invalid checkpoint error, got {:?}", other),
+        }
+        assert!(Path::new(&checkpoint).exists());
+    }
+
+    #[test]
+    fn compressed_index_file() {
+        use rand::XorShiftRng;
+
+        let mut rng = XorShiftRng::new_unseeded();
+        let mut reference = String::new();
+        for gi in 1..10 {
+            let seq = (0..2000).map(|_| *rng.choose(b"ACGT").unwrap() as char).collect::<String>();
+            reference.push_str(&format!(">{}-{}\n{}\n", gi, gi % 3, seq));
+        }
+
+        let dir = Temp::new_dir().unwrap();
+        let dir = dir.to_path_buf();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        let build = |index_path: &str, compression| {
+            let records = Reader::new(Cursor::new(reference.as_bytes())).records();
+            build_and_write_index_with_prescreen(records,
+                                                 index_path,
+                                                 32,
+                                                 64,
+                                                 HeaderFormat::GiTaxId,
+                                                 None,
+                                                 compression)
+        };
+
+        build(&path("plain.index"), None).unwrap();
+        build(&path("fast.index"), Some(1)).unwrap();
+        build(&path("best.index"), Some(19)).unwrap();
+        // compressed by the name alone
+        build(&path("named.index.zst"), None).unwrap();
+
+        let size = |name: &str| dir.join(name).metadata().unwrap().len();
+        assert!(size("fast.index") < size("plain.index"));
+        assert!(size("best.index") <= size("fast.index"));
+        assert!(size("named.index.zst") < size("plain.index"));
+
+        let run = RunContext::new(Some(3));
+        let plain = MGIndex::from_file(&path("plain.index")).unwrap();
+        let plain_report = verify_index(&plain, 9, 100, &run).unwrap();
+        for name in &["fast.index", "best.index", "named.index.zst"] {
+            let index = MGIndex::from_file(&path(name)).unwrap();
+            assert_eq!(index.fingerprint(), plain.fingerprint());
+            assert_eq!(verify_index(&index, 9, 100, &run).unwrap(), plain_report);
+        }
+
+        match build(&path("bad.index"), Some(20)) {
+            Err(MtsvError::InvalidParameter(msg)) => assert!(msg.contains("1 to 19"), "{}", msg),
+            other => panic!("expected invalid parameter error, got {:?}", other),
+        }
+
+        // mapped indices are written uncompressed
+        let mut options = BuildOptions { mapped: true, ..BuildOptions::default() };
+        let records = Reader::new(Cursor::new(reference.as_bytes())).records();
+        build_and_write_index_with_options(records, &path("mapped.index"), &options).unwrap();
+        assert!(::mapped::is_mapped_file(&path("mapped.index")).unwrap());
+        let mapped = MGIndex::open_mmap(&path("mapped.index")).unwrap();
+        assert_eq!(mapped.fingerprint(), plain.fingerprint());
+
+        options.compression = Some(6);
+        let records = Reader::new(Cursor::new(reference.as_bytes())).records();
+        match build_and_write_index_with_options(records, &path("bad.index"), &options) {
+            Err(MtsvError::InvalidParameter(msg)) => assert!(msg.contains("mapped")),
+            other => panic!("expected invalid parameter error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reject_zero_sampling() {
+        let reference = ">123-456\nTGTCTTAATGATAAAAATTGTTACAAACAGTTTAACATATTTAGCTACC\n";
+        let outfile = Temp::new_file().unwrap();
+        let outfile_str = outfile.to_path_buf().to_str().unwrap().to_string();
+
+        for &(sample_interval, suffix_sample) in &[(0, 64), (32, 0), (0, 0)] {
+            let records = Reader::new(Cursor::new(reference.as_bytes())).records();
+            match build_and_write_index(records,
+                                        &outfile_str,
+                                        sample_interval,
+                                        suffix_sample,
+                                        HeaderFormat::GiTaxId) {
+                Err(MtsvError::InvalidParameter(msg)) => assert!(msg.contains("at least 1")),
+                other => panic!("expected invalid parameter error, got {:?}", other),
+            }
+        }
+
+        // odd and very large values only trade speed for memory
+        let records = Reader::new(Cursor::new(reference.as_bytes())).records();
+        build_and_write_index(records, &outfile_str, 3, 2048, HeaderFormat::GiTaxId).unwrap();
+    }
+
     #[test]
     #[should_panic]
     fn fail_empty_header() {
@@ -77,6 +1715,461 @@ TTTCACCTAGTACATTAAATACACGACCTAATGTTTCGTCACCAACAGGTACACTAATTTCTTTGCCTGTATCTTTTACA
         let outfile_path = outfile.to_path_buf();
         let outfile_str = outfile_path.to_str().unwrap();
 
-        build_and_write_index(records, outfile_str, 32, 64).unwrap();
+        build_and_write_index(records, outfile_str, 32, 64, HeaderFormat::GiTaxId).unwrap();
+    }
+
+    #[test]
+    fn verify_built_index() {
+        use index::{Database, Gi};
+        use rand::XorShiftRng;
+
+        let mut rng = XorShiftRng::new_unseeded();
+        let mut random_seq = |len: usize| {
+            (0..len).map(|_| *rng.choose(b"ACGT").unwrap()).collect::<Vec<u8>>()
+        };
+        let mut db = Database::new();
+        for tax_id in 1..20 {
+            db.insert(TaxId(tax_id as u64), vec![(Gi(tax_id), random_seq(500))]);
+        }
+        // a tandem repeat, a reference with no window free of Ns, and one too short to seed
+        let unit = random_seq(20);
+        db.insert(TaxId(20), vec![(Gi(20), unit.iter().cycle().take(2000).cloned().collect())]);
+        let mut gappy = random_seq(300);
+        for i in (0..300).step_by(50) {
+            gappy[i] = b'N';
+        }
+        db.insert(TaxId(21), vec![(Gi(21), gappy), (Gi(22), random_seq(10))]);
+
+        let dir = Temp::new_dir().unwrap();
+        let index_path = dir.to_path_buf().join("test.index");
+        let index_path = index_path.to_str().unwrap();
+        write_to_file(&MGIndex::new(db, 16, 32, BinMode::PerGi), index_path).unwrap();
+        let index = MGIndex::from_file(index_path).unwrap();
+
+        let run = RunContext::new(Some(7));
+        let report = verify_index(&index, 100, 100, &run).unwrap();
+        assert!(report.passed());
+        assert_eq!(report.samples.len(), 20);
+        assert_eq!(report.skipped, 2);
+        assert!(report.samples.iter().any(|s| s.bin.tax_id == TaxId(20)));
+
+        // a sample of the references, reproducible from the seed
+        let sampled = verify_index(&index, 5, 100, &run).unwrap();
+        assert_eq!(sampled.samples.len() + sampled.skipped, 5);
+        assert_eq!(verify_index(&index, 5, 100, &run).unwrap(), sampled);
+
+        assert!(verify_index(&index, 5, 10, &run).is_err());
+    }
+
+    #[test]
+    fn verify_report_failures() {
+        let bin = |tax_id| {
+            BinInfo {
+                gi: ::index::Gi(tax_id),
+                tax_id: TaxId(tax_id as u64),
+                start: 0,
+                end: 100,
+            }
+        };
+        let mut report = VerifyReport::default();
+        report.samples.push(VerifySample {
+            bin: bin(1),
+            offset: 0,
+            hits: vec![TaxId(2), TaxId(1)],
+        });
+        assert!(report.passed());
+
+        report.samples.push(VerifySample { bin: bin(3), offset: 5, hits: vec![TaxId(2)] });
+        assert!(!report.passed());
+        assert_eq!(report.failures().map(|s| s.bin.tax_id).collect::<Vec<_>>(), vec![TaxId(3)]);
+    }
+
+    #[test]
+    fn taxid_only_headers() {
+        let reference = ">456
+TGTCTTAATGATAAAAATTGTTACAAACAGTTTAACATATTTAGCTACCTATTTTGCATATAAAAAACATGCTTGCATACACTATGCAATAAAAATTACAAATTTATATATGATACCACTATGCTTGCTTATCTCTATAGCGCCATTGATACACATTTTTAAATATCTATACTGCCGTTAGAATTTTATCATGTCTTA
+>456
+TTTCACCTAGTACATTAAATACACGACCTAATGTTTCGTCACCAACAGGTACACTAATTTCTTTGCCTGTATCTTTTACATCCATGCCTCTTTGGACACCATCAGTTGAATCCATCGCAATTGTACGAACAACGTCGTCACCTAATTGCAGCGCAACTTCTAATGTTAGTTGTATTGTACC
+>678
+AAAACACATATTTTCAAATCTAGTAAATATTAAATCTACTCTTGACGATTGCACCAATGCTACGCGATATAGATATCCACTAAAAACATACGTAATCATAACCATCATTGTTAGAAACAAAATTATTTCCATGATAACCCTCACTTAATATATTTCTAAAATTTTTCACTACGAATTAAGGCATAAAATAAATACAAAACTAATGCAATAACTACCAGTAATAAAACGATGAGCATTGCCATAACC";
+
+        let reads = ">read1
+AAATCTACTCTTGACGATTGCACCAATGCTACGCGATATAGATATCCACTAAAAACATACGTAATCATAACCATCATTGTTAGAAACAAA
+";
+
+        let dir = Temp::new_dir().unwrap();
+        let dir = dir.to_path_buf();
+        let index_path = dir.join("taxid.index");
+        let reads_path = dir.join("reads.fasta");
+        let results_path = dir.join("results.txt");
+        let references_path = dir.join("references.fasta");
+
+        // bare taxids aren't accepted unless asked for
+        let records = Reader::new(Cursor::new(reference.as_bytes())).records();
+        assert!(build_and_write_index(records,
+                                      index_path.to_str().unwrap(),
+                                      32,
+                                      64,
+                                      HeaderFormat::GiTaxId)
+            .is_err());
+
+        let records = Reader::new(Cursor::new(reference.as_bytes())).records();
+        build_and_write_index(records,
+                              index_path.to_str().unwrap(),
+                              32,
+                              64,
+                              HeaderFormat::TaxId)
+            .unwrap();
+
+        let index = MGIndex::from_file(index_path.to_str().unwrap()).unwrap();
+        assert!(index.synthetic_gis());
+        assert_eq!(index.get_references(456).len(), 2);
+
+        File::create(&reads_path).unwrap().write_all(reads.as_bytes()).unwrap();
+        get_fasta_and_write_matching_bin_ids(reads_path.to_str().unwrap(),
+                                             index_path.to_str().unwrap(),
+                                             results_path.to_str().unwrap(),
+                                             1,
+                                             &QueryParams::default(),
+                                             &BinnerConfig::default())
+            .unwrap();
+
+        let mut results = String::new();
+        File::open(&results_path).unwrap().read_to_string(&mut results).unwrap();
+        assert_eq!(results, "read1:678=0\n");
+
+        get_reference_sequences_from_index(index_path.to_str().unwrap(),
+                                           references_path.to_str().unwrap(),
+                                           vec![456])
+            .unwrap();
+
+        let extracted = Reader::from_file(&references_path)
+            .unwrap()
+            .records()
+            .map(|r| r.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(extracted.iter().map(|r| r.seq().to_vec()).collect::<Vec<_>>(),
+                   index.get_references(456));
+        // the GIs were made up, and the headers say so
+        assert_eq!(extracted.iter().map(|r| r.id()).collect::<Vec<_>>(),
+                   ["synthetic_gi|1|taxid|456", "synthetic_gi|2|taxid|456"]);
+        get_reference_sequence_by_gi_from_index(index_path.to_str().unwrap(),
+                                                references_path.to_str().unwrap(),
+                                                3)
+            .unwrap();
+        let extracted = Reader::from_file(&references_path).unwrap().records().next().unwrap();
+        assert_eq!(extracted.unwrap().id(), "synthetic_gi|3|taxid|678");
+
+        // a dump leaves them out, and builds back from the taxids alone
+        dump_references(index_path.to_str().unwrap(), references_path.to_str().unwrap(), None)
+            .unwrap();
+        let dumped = Reader::from_file(&references_path)
+            .unwrap()
+            .records()
+            .map(|r| r.unwrap().id().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(dumped, ["456", "456", "678"]);
+        let records = Reader::from_file(&references_path).unwrap().records();
+        let rebuilt_path = dir.join("rebuilt.index");
+        build_and_write_index(records, rebuilt_path.to_str().unwrap(), 32, 64, HeaderFormat::TaxId)
+            .unwrap();
+        let rebuilt = MGIndex::from_file(rebuilt_path.to_str().unwrap()).unwrap();
+        assert!(rebuilt.synthetic_gis());
+        assert_eq!(rebuilt.fingerprint(), index.fingerprint());
+    }
+
+    #[test]
+    fn accession_headers() {
+        let reference = ">NZ_CP000001.1 Escherichia coli strain A
+TGTCTTAATGATAAAAATTGTTACAAACAGTTTAACATATTTAGCTACCTATTTTGCATATAAAAAACATGCTTGCATACACTATGCAATAAAAATTACAAATTTATATATGATACCACTATGCTTGCTTATCTCTATAGCGCCATTGATACACATTTTTAAATATCTATACTGCCGTTAGAATTTTATCATGTCTTA
+>NZ_CP000002.1 Escherichia coli strain B
+TTTCACCTAGTACATTAAATACACGACCTAATGTTTCGTCACCAACAGGTACACTAATTTCTTTGCCTGTATCTTTTACATCCATGCCTCTTTGGACACCATCAGTTGAATCCATCGCAATTGTACGAACAACGTCGTCACCTAATTGCAGCGCAACTTCTAATGTTAGTTGTATTGTACC
+>NC_000003.1 Salmonella enterica
+AAAACACATATTTTCAAATCTAGTAAATATTAAATCTACTCTTGACGATTGCACCAATGCTACGCGATATAGATATCCACTAAAAACATACGTAATCATAACCATCATTGTTAGAAACAAAATTATTTCCATGATAACCCTCACTTAATATATTTCTAAAATTTTTCACTACGAATTAAGGCATAAAATAAATACAAAACTAATGCAATAACTACCAGTAATAAAACGATGAGCATTGCCATAACC";
+
+        let reads = ">read1
+AAATCTACTCTTGACGATTGCACCAATGCTACGCGATATAGATATCCACTAAAAACATACGTAATCATAACCATCATTGTTAGAAACAAA
+";
+
+        let dir = Temp::new_dir().unwrap();
+        let dir = dir.to_path_buf();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        File::create(path("accessions.tsv"))
+            .unwrap()
+            .write_all(b"NZ_CP000001.1\t562\nNZ_CP000002.1\t562\n")
+            .unwrap();
+
+        let build = |options: &BuildOptions| {
+            let records = Reader::new(Cursor::new(reference.as_bytes())).records();
+            build_and_write_index_with_options(records, &path("accession.index"), options)
+        };
+        let mut options = BuildOptions {
+            header_format: HeaderFormat::Accession,
+            accession_map: Some(path("accessions.tsv")),
+            ..BuildOptions::default()
+        };
+        match build(&options) {
+            Err(MtsvError::UnmappedAccession(accession)) => assert_eq!(accession, "NC_000003.1"),
+            other => panic!("expected an unmapped accession, found {:?}", other),
+        }
+        File::create(path("accessions.tsv"))
+            .unwrap()
+            .write_all(b"NZ_CP000001.1\t562\nNZ_CP000002.1\t562\nNC_000003.1\t28901\n")
+            .unwrap();
+        build(&options).unwrap();
+
+        let index = MGIndex::from_file(&path("accession.index")).unwrap();
+        assert!(index.synthetic_gis());
+        let accessions = index.bins()
+            .map(|bin| (bin.tax_id.0, index.accession(bin.gi).unwrap().to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(accessions,
+                   vec![(562, String::from("NZ_CP000001.1")),
+                        (562, String::from("NZ_CP000002.1")),
+                        (28901, String::from("NC_000003.1"))]);
+
+        // hits name the reference they aligned to
+        File::create(path("reads.fasta")).unwrap().write_all(reads.as_bytes()).unwrap();
+        let mut config = BinnerConfig {
+            findings_version: FindingsVersion::V2,
+            emit_accessions: true,
+            ..BinnerConfig::default()
+        };
+        get_fasta_and_write_matching_bin_ids(&path("reads.fasta"),
+                                             &path("accession.index"),
+                                             &path("results.txt"),
+                                             1,
+                                             &QueryParams::default(),
+                                             &config)
+            .unwrap();
+        let mut results = String::new();
+        File::open(path("results.txt")).unwrap().read_to_string(&mut results).unwrap();
+        let mut lines = results.lines();
+        assert!(lines.next().unwrap().ends_with(",clipped_end,accession"));
+        let line = lines.next().unwrap();
+        assert!(line.starts_with("read1:28901=0=") && line.ends_with("=NC_000003.1"), "{}", line);
+
+        config.findings_version = FindingsVersion::V1;
+        assert!(get_fasta_and_write_matching_bin_ids(&path("reads.fasta"),
+                                                     &path("accession.index"),
+                                                     &path("results.txt"),
+                                                     1,
+                                                     &QueryParams::default(),
+                                                     &config)
+            .is_err());
+
+        // extracted references are described by their accessions
+        get_reference_sequences_from_index(&path("accession.index"), &path("562.fasta"), vec![562])
+            .unwrap();
+        let extracted = Reader::from_file(path("562.fasta"))
+            .unwrap()
+            .records()
+            .map(|r| {
+                let r = r.unwrap();
+                parse_reference_header(r.id(), r.desc()).unwrap().1.unwrap()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(extracted, vec!["NZ_CP000001.1", "NZ_CP000002.1"]);
+
+        // or a single reference by its GI, which was assigned in input order
+        get_reference_sequence_by_gi_from_index(&path("accession.index"), &path("gi.fasta"), 3)
+            .unwrap();
+        let extracted = Reader::from_file(path("gi.fasta"))
+            .unwrap()
+            .records()
+            .map(|r| r.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(extracted.len(), 1);
+        let (bin, accession) = parse_reference_header(extracted[0].id(), extracted[0].desc())
+            .unwrap();
+        assert_eq!((bin.gi, bin.tax_id, accession.as_deref()),
+                   (Gi(3), TaxId(28901), Some("NC_000003.1")));
+        assert_eq!(bin.end - bin.start, extracted[0].seq().len());
+        assert!(extracted[0].seq().starts_with(b"AAAACACATATTTTC"));
+        match get_reference_sequence_by_gi_from_index(&path("accession.index"),
+                                                      &path("missing.fasta"),
+                                                      4) {
+            Err(MtsvError::MissingGi(_, 4)) => (),
+            other => panic!("expected a missing GI error, got {:?}", other),
+        }
+        assert!(!dir.join("missing.fasta").exists());
+
+        // indices without accessions can't write them
+        options.header_format = HeaderFormat::TaxId;
+        let records = Reader::new(Cursor::new(b">562\nACGTACGTACGTACGTACGTACGT\n".as_ref())).records();
+        build_and_write_index_with_options(records, &path("taxid.index"), &options).unwrap();
+        config.findings_version = FindingsVersion::V2;
+        assert!(get_fasta_and_write_matching_bin_ids(&path("reads.fasta"),
+                                                     &path("taxid.index"),
+                                                     &path("results.txt"),
+                                                     1,
+                                                     &QueryParams::default(),
+                                                     &config)
+            .is_err());
+    }
+
+    #[test]
+    fn kept_descriptions() {
+        let reference = ">1-562 Escherichia coli strain A, complete genome
+TGTCTTAATGATAAAAATTGTTACAAACAGTTTAACATATTTAGCTACCTATTTTGCATATAAAAAACATGCTTGCATACACTATGCAATAAAA
+>2-562
+TTTCACCTAGTACATTAAATACACGACCTAATGTTTCGTCACCAACAGGTACACTAATTTCTTTGCCTGTATCTTTTACATCCATGCCTCTTT
+>3-28901 Salmonella enterica len=5 description=odd
+AAAACACATATTTTCAAATCTAGTAAATATTAAATCTACTCTTGACGATTGCACCAATGCTACGCGATATAGATATCCACTAAAAACATACG";
+
+        let dir = Temp::new_dir().unwrap();
+        let dir = dir.to_path_buf();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        let extract = |index: &str| {
+            get_reference_sequences_from_index(&path(index), &path("562.fasta"), vec![562])
+                .unwrap();
+            get_reference_sequence_by_gi_from_index(&path(index), &path("gi.fasta"), 3).unwrap();
+            ["562.fasta", "gi.fasta"]
+                .iter()
+                .flat_map(|name| Reader::from_file(path(name)).unwrap().records())
+                .map(|r| {
+                    let r = r.unwrap();
+                    let (bin, _) = parse_reference_header(r.id(), r.desc()).unwrap();
+                    let desc = r.desc().unwrap().to_string();
+                    (bin.gi.0, desc.find(" description=").map(|i| desc[i + 13..].to_string()))
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let mut options = BuildOptions::default();
+        for &keep in &[false, true] {
+            options.keep_descriptions = keep;
+            let records = Reader::new(Cursor::new(reference.as_bytes())).records();
+            build_and_write_index_with_options(records, &path("described.index"), &options)
+                .unwrap();
+            let index = MGIndex::from_file(&path("described.index")).unwrap();
+            let expected = if keep {
+                vec![(1, Some(String::from("Escherichia coli strain A, complete genome"))),
+                     (2, None),
+                     (3, Some(String::from("Salmonella enterica len=5 description=odd")))]
+            } else {
+                vec![(1, None), (2, None), (3, None)]
+            };
+            assert_eq!(index.description(Gi(1)), expected[0].1.as_deref());
+            assert_eq!(extract("described.index"), expected);
+        }
+
+        // and so do shards, each of its own references
+        options.shard_size = Some(100);
+        let records = Reader::new(Cursor::new(reference.as_bytes())).records();
+        build_and_write_index_with_options(records, &path("sharded.index"), &options).unwrap();
+        let manifest = ShardManifest::from_file(&shard_manifest_path(&path("sharded.index")))
+            .unwrap();
+        let descriptions = manifest.index_paths()
+            .iter()
+            .map(|p| MGIndex::from_file(p).unwrap().descriptions().len())
+            .collect::<Vec<_>>();
+        assert_eq!(descriptions, vec![1, 0, 1]);
+    }
+
+    #[test]
+    fn manifest_build() {
+        use flate2::write::GzEncoder;
+        use flate2::Compression;
+        let dir = Temp::new_dir().unwrap();
+        let dir = dir.to_path_buf();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        File::create(path("ecoli.fasta"))
+            .unwrap()
+            .write_all(b">contig_1 Escherichia coli\nACGTTGCAAGCTTCAGGATCC\n\
+                         >contig_2\nTTGACCATGGAAGCTTAACG\n>contig_3\nGGATCCTTAAGCTTGCAACGT\n")
+            .unwrap();
+        fs::create_dir(path("assemblies")).unwrap();
+        File::create(path("assemblies/salmonella.fasta.gz"))
+            .unwrap()
+            .write_all(&{
+                let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+                gz.write_all(b">contig_1\nCCATGGTTAACCGGTTAACC\n>contig_2\nAAGCTTGGATCCAAGCTT\n")
+                    .unwrap();
+                gz.finish().unwrap()
+            })
+            .unwrap();
+        File::create(path("manifest.tsv"))
+            .unwrap()
+            .write_all(b"# path\ttaxid\tstarting_gi\necoli.fasta\t562\n\
+                         assemblies/salmonella.fasta.gz\t28901\t100\n")
+            .unwrap();
+
+        let options = BuildOptions { keep_descriptions: true, ..BuildOptions::default() };
+        let build = |manifest: &str| {
+            build_and_write_index_from_manifest(&path(manifest), &path("manifest.index"), &options)
+        };
+        build("manifest.tsv").unwrap();
+        let index = MGIndex::from_file(&path("manifest.index")).unwrap();
+        assert_eq!(index.get_references(562).len(), 3);
+        assert_eq!(index.get_references(28901).len(), 2);
+        assert_eq!(index.bins().map(|b| b.gi.0).collect::<Vec<_>>(), vec![1, 2, 3, 100, 101]);
+        assert_eq!(index.description(Gi(1)), Some("contig_1 Escherichia coli"));
+
+        // errors name the manifest line, and the file and record
+        File::create(path("broken.tsv"))
+            .unwrap()
+            .write_all(b"ecoli.fasta\t562\nmissing.fasta\t28901\n")
+            .unwrap();
+        let error = build("broken.tsv").unwrap_err().to_string();
+        assert!(error.contains("line 2 of manifest") && error.contains("missing.fasta"),
+                "{}",
+                error);
+        File::create(path("sentinel.fasta")).unwrap().write_all(b">a\nACGT\n>b\nAC$T\n").unwrap();
+        File::create(path("broken.tsv")).unwrap().write_all(b"sentinel.fasta\t562\n").unwrap();
+        match build("broken.tsv") {
+            Err(MtsvError::InvalidManifestEntry(_, 1, e)) => {
+                match *e {
+                    MtsvError::InvalidFastaRecord(ref file, 2, _) => {
+                        assert!(file.ends_with("sentinel.fasta"))
+                    },
+                    ref other => panic!("expected an invalid record, found {:?}", other),
+                }
+            },
+            other => panic!("expected an invalid manifest entry, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn taxon_bins() {
+        let reference = ">NZ_CP000001.1 Escherichia coli strain A
+TGTCTTAATGATAAAAATTGTTACAAACAGTTTAACATATTTAGCTACCTATTTTGCATATAAAAAACATGCTTGCATACACTATGCAATAAAA
+>NC_000003.1 Salmonella enterica
+AAAACACATATTTTCAAATCTAGTAAATATTAAATCTACTCTTGACGATTGCACCAATGCTACGCGATATAGATATCCACTAAAAACATACG
+>NZ_CP000002.1 Escherichia coli strain B
+TTTCACCTAGTACATTAAATACACGACCTAATGTTTCGTCACCAACAGGTACACTAATTTCTTTGCCTGTATCTTTTACATCCATGCCTCTTT";
+
+        let dir = Temp::new_dir().unwrap();
+        let dir = dir.to_path_buf();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        File::create(path("accessions.tsv"))
+            .unwrap()
+            .write_all(b"NZ_CP000001.1\t562\nNZ_CP000002.1\t562\nNC_000003.1\t28901\n")
+            .unwrap();
+        let build = |options: &BuildOptions| {
+            let records = Reader::new(Cursor::new(reference.as_bytes())).records();
+            build_and_write_index_with_options(records, &path("taxon.index"), options)
+        };
+
+        let mut options = BuildOptions {
+            header_format: HeaderFormat::Accession,
+            accession_map: Some(path("accessions.tsv")),
+            bin_mode: BinMode::PerTaxon { spacer_len: 20 },
+            keep_descriptions: true,
+            ..BuildOptions::default()
+        };
+        match build(&options) {
+            Err(MtsvError::InvalidParameter(_)) => {},
+            other => panic!("expected descriptions to be rejected, found {:?}", other),
+        }
+
+        // E. coli's two references take a single bin, and the index has no accessions for it
+        options.keep_descriptions = false;
+        build(&options).unwrap();
+        let index = MGIndex::from_file(&path("taxon.index")).unwrap();
+        let bins = index.bins().map(|b| (b.gi, b.tax_id, b.end - b.start)).collect::<Vec<_>>();
+        assert_eq!(bins, vec![(Gi(0), TaxId(562), 94 + 20 + 93), (Gi(0), TaxId(28901), 92)]);
+        assert!(!index.has_accessions());
+        assert_eq!(index.get_references(562)[0][94..114], [b'N'; 20]);
     }
 }
@@ -1,11 +1,12 @@
 //! Collapse multiple mtsv results/findings files into a single one.
 
-use binner::{write_single_line, write_edit_distances};
+use binner::write_single_line;
 use error::*;
-use io::{parse_findings, parse_edit_distance_findings};
-use std::collections::{BTreeMap, BTreeSet, HashMap};
+use io::{parse_findings, FindingsField, FindingsIter, FindingsVersion, FindingsWriter,
+         ResultsHeader, V2_FIELDS};
+use std::collections::{BTreeMap, BTreeSet};
 use std::io::{BufRead, Write};
-use index::{TaxId, Hit};
+use index::Hit;
 
 /// Given a list of mtsv results file paths, collapse into a single one.
 pub fn collapse_files<R, W>(files: &mut [R], write_to: &mut W) -> MtsvResult<()>
@@ -32,51 +33,109 @@ pub fn collapse_files<R, W>(files: &mut [R], write_to: &mut W) -> MtsvResult<()>
 }
 
 /// Given a list of mtsv edit distance result file paths, collapse into a single one.
-pub fn collapse_edit_files<R, W>(files: &mut [R], write_to: &mut W) -> MtsvResult<()>
+///
+/// Files from different samples (see `ResultsHeader`) are only merged if `allow_mixed_samples`
+/// is set, in which case each read ID is prefixed with its sample name as `SAMPLE/READ_ID`.
+/// Otherwise the merged file records the files' common sample name.
+///
+/// The files may be in any edit distance format (see `FindingsVersion`), but not the legacy
+/// format, whose hits have no edit distances to keep the smallest of. If they're all binary,
+/// so is the merged file, and otherwise if any of them is v2 or binary the merged file is v2.
+/// Either way it has the hit fields which all of the files have (v1 files only have taxids and
+/// edit distances). Otherwise the merged file is v1, and has escaped read IDs if any of the files
+/// does.
+///
+/// Each merged line has at most `max_taxids` taxids, if set (see `FindingsWriter`). A read whose
+/// line was capped in any of the files stays capped, with an overflow marker counting the taxids
+/// left off from all of them, which may include some that another file had.
+pub fn collapse_edit_files<R, W>(files: &mut [R],
+                                 write_to: &mut W,
+                                 allow_mixed_samples: bool,
+                                 max_taxids: Option<usize>)
+                                 -> MtsvResult<()>
     where R: BufRead,
           W: Write
 {
+    let mut readers = Vec::new();
+    let mut headers = Vec::new();
+    for file in files.iter_mut() {
+        let reader = FindingsIter::new(file)?;
+        if reader.header().version == FindingsVersion::Legacy {
+            return Err(MtsvError::InvalidFindings(String::from("legacy results have no edit \
+                                                                distances to collapse")));
+        }
+        headers.push(reader.header().clone());
+        readers.push(reader);
+    }
+    let samples = headers.iter().map(|h| h.sample.clone()).collect::<Vec<_>>();
+
+    let describe = |s: &Option<String>| s.clone().unwrap_or_else(|| String::from("unnamed"));
+    let mixed = match samples.iter().find(|&s| *s != samples[0]) {
+        Some(other) => {
+            if !allow_mixed_samples {
+                return Err(MtsvError::MixedSamples(describe(&samples[0]), describe(other)));
+            }
+            warn!("Results are from several samples, prefixing read IDs with the sample name.");
+            true
+        },
+        None => false,
+    };
+
+    let output_header = merged_header(&headers);
+
     let mut results = BTreeMap::new();
 
-    for ref mut r in files {
+    for (mut reader, sample) in readers.into_iter().zip(samples.iter()) {
 
-        for res in parse_edit_distance_findings(r) {
-            let (readid, hits) = (res)?;
-                results.entry(readid).or_insert(Vec::<Hit>::new()).extend(hits);
+        while let Some(res) = reader.next() {
+            let finding = res?;
+            let readid = match *sample {
+                Some(ref name) if mixed => format!("{}/{}", name, finding.read_id),
+                _ => finding.read_id,
+            };
+            let entry = results.entry(readid).or_insert((Vec::<Hit>::new(), 0));
+            entry.0.extend(finding.hits);
+            entry.1 += reader.overflow();
         }
     }
+
+    let mut writer = FindingsWriter::new(write_to).with_max_taxids(max_taxids);
+    writer.write_header(&ResultsHeader {
+        sample: if mixed { None } else { samples.into_iter().next().and_then(|s| s) },
+        ..output_header
+    })?;
+
     info!("All input files parsed and collapsed, writing to disk...");
-    for (header, hits) in results.iter() {
-        let mut hit_map:HashMap<TaxId, u32> = HashMap::new();
-        for hit in hits {
-            
-            match hit_map.get(&hit.tax_id) {
-                    // if taxid already exists in hashmap, only add if edit distance is smaller
-                    Some(edit_exists) => {
-                        if edit_exists > &hit.edit {
-                            hit_map.insert(hit.tax_id, hit.edit);
-                        }
-                    }
-                    None => {
-                        hit_map.insert(hit.tax_id, hit.edit);
-                    }
-            }
-                
-        }
-    
+    for (header, &(ref hits, overflow)) in results.iter() {
+        writer.write_hits_with_overflow(header, hits, overflow)?;
+    }
+    Ok(())
+}
 
-        let mut combined_hits = Vec::<Hit>::new();
-        for (key, value) in hit_map.into_iter() {
-            let hit = Hit {
-                tax_id: key,
-                edit: value
-            };
-            combined_hits.push(hit);
-        }
-        write_edit_distances(header, &combined_hits, write_to)?;
+/// The format for merging files with these headers (see `collapse_edit_files`), without a sample.
+fn merged_header(headers: &[ResultsHeader]) -> ResultsHeader {
+    if headers.iter().all(|h| h.version == FindingsVersion::V1) {
+        return ResultsHeader {
+            escaped_ids: headers.iter().any(|h| h.escaped_ids),
+            ..ResultsHeader::default()
+        };
+    }
 
+    let v1_fields = [FindingsField::TaxId, FindingsField::Edit];
+    let fields = V2_FIELDS.iter()
+        .filter(|&field| {
+            headers.iter().all(|h| match h.version {
+                FindingsVersion::V1 | FindingsVersion::Legacy => v1_fields.contains(field),
+                FindingsVersion::V2 | FindingsVersion::Binary => h.fields.contains(field),
+            })
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+    if headers.iter().all(|h| h.version == FindingsVersion::Binary) {
+        ResultsHeader::binary(&fields)
+    } else {
+        ResultsHeader::v2(&fields)
     }
-    Ok(()) 
 }
 
 
@@ -90,6 +149,8 @@ pub fn collapse_edit_files<R, W>(files: &mut [R], write_to: &mut W) -> MtsvResul
 
 #[cfg(test)]
 mod test {
+    use convert::convert_findings;
+    use io::{parse_edit_distance_findings, BINARY_MAGIC};
     use std::io::Cursor;
     use super::*;
 
@@ -122,4 +183,154 @@ c:2,3,4,5
 
         assert_eq!(expected, &buf_str);
     }
+
+    #[test]
+    fn same_sample_keeps_header() {
+        let a = "#sample=gut1\nr1:1=0\n";
+        let b = "#sample=gut1\nr1:1=2,2=1\n";
+
+        let mut buf = Vec::new();
+        let mut infiles = vec![Cursor::new(a), Cursor::new(b)];
+        collapse_edit_files(&mut infiles, &mut buf, false, None).unwrap();
+
+        let buf_str = String::from_utf8(buf).unwrap();
+        assert!(buf_str.starts_with("#sample=gut1\n"));
+        let mut lines = buf_str.lines().skip(1).collect::<Vec<_>>();
+        lines.sort();
+        assert!(lines == vec!["r1:1=0,2=1"] || lines == vec!["r1:2=1,1=0"]);
+    }
+
+    #[test]
+    fn mixed_samples_rejected() {
+        let a = "#sample=gut1\nr1:1=0\n";
+        let b = "#sample=gut2\nr1:1=2\n";
+
+        let mut buf = Vec::new();
+        let mut infiles = vec![Cursor::new(a), Cursor::new(b)];
+        match collapse_edit_files(&mut infiles, &mut buf, false, None) {
+            Err(MtsvError::MixedSamples(first, second)) => {
+                assert_eq!(first, "gut1");
+                assert_eq!(second, "gut2");
+            },
+            other => panic!("expected a mixed samples error, found {:?}", other),
+        }
+
+        // a file without a sample name conflicts with a named one too
+        let mut infiles = vec![Cursor::new(a), Cursor::new("r1:1=2\n")];
+        assert!(collapse_edit_files(&mut infiles, &mut buf, false, None).is_err());
+    }
+
+    #[test]
+    fn legacy_inputs_rejected() {
+        let mut buf = Vec::new();
+        let mut infiles = vec![Cursor::new("r1:1=0\n"), Cursor::new("r1:1,2\n")];
+        match collapse_edit_files(&mut infiles, &mut buf, false, None) {
+            Err(MtsvError::InvalidFindings(_)) => (),
+            other => panic!("expected an invalid findings error, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn mixed_samples_prefixed() {
+        let a = "#sample=gut1\nr1:1=0\n";
+        let b = "#sample=gut2\nr1:1=2\nr2:5=3\n";
+
+        let mut buf = Vec::new();
+        let mut infiles = vec![Cursor::new(a), Cursor::new(b)];
+        collapse_edit_files(&mut infiles, &mut buf, true, None).unwrap();
+
+        let expected = "gut1/r1:1=0
+gut2/r1:1=2
+gut2/r2:5=3
+";
+        assert_eq!(expected, String::from_utf8(buf).unwrap());
+    }
+
+    #[test]
+    fn escaped_ids_stay_escaped() {
+        // the same read, once written with an escaped ID and once without escaping
+        let a = "#escaped_ids=true\nA1%3A2:1=0\n%23r2:3=1\n";
+        let b = "A1:2:1=2,2=1\n";
+
+        let mut buf = Vec::new();
+        let mut infiles = vec![Cursor::new(a), Cursor::new(b)];
+        collapse_edit_files(&mut infiles, &mut buf, false, None).unwrap();
+
+        let merged = String::from_utf8(buf).unwrap();
+        let mut lines = merged.lines().collect::<Vec<_>>();
+        assert_eq!(lines.remove(0), "#escaped_ids=true");
+        assert_eq!(lines[0], "%23r2:3=1");
+        assert!(lines[1] == "A1%3A2:1=0,2=1" || lines[1] == "A1%3A2:2=1,1=0");
+
+        let parsed = parse_edit_distance_findings(Cursor::new(merged.as_bytes()))
+            .map(|r| r.unwrap().0)
+            .collect::<Vec<_>>();
+        assert_eq!(parsed, vec!["#r2", "A1:2"]);
+    }
+
+    #[test]
+    fn mixed_versions() {
+        let a = "#sample=gut1\nr1:1=3\n";
+        let b = "#mtsv-findings v2 fields=taxid,edit,seeds\n#sample=gut1\n\
+                 r1:1=2=4,2=0=5\nr%3A2:3=1=1\n";
+
+        let mut buf = Vec::new();
+        let mut infiles = vec![Cursor::new(a), Cursor::new(b)];
+        collapse_edit_files(&mut infiles, &mut buf, false, None).unwrap();
+
+        // only the fields both files have are kept
+        let expected = "#mtsv-findings v2 fields=taxid,edit
+#sample=gut1
+r1:1=2,2=0
+r%3A2:3=1
+";
+        assert_eq!(expected, String::from_utf8(buf).unwrap());
+    }
+
+    #[test]
+    fn capped_lines_stay_capped() {
+        let a = "r1:1=0,2=1,...+3\nr2:1=0\n";
+        let b = "r1:3=0,...+1\nr2:2=4,3=1,4=2\n";
+
+        let mut buf = Vec::new();
+        let mut infiles = vec![Cursor::new(a), Cursor::new(b)];
+        collapse_edit_files(&mut infiles, &mut buf, false, None).unwrap();
+        assert_eq!(String::from_utf8(buf).unwrap(), "r1:1=0,2=1,3=0,...+4\nr2:1=0,2=4,3=1,4=2\n");
+
+        // capping the merged lines adds to the overflow count
+        let mut buf = Vec::new();
+        let mut infiles = vec![Cursor::new(a), Cursor::new(b)];
+        collapse_edit_files(&mut infiles, &mut buf, false, Some(2)).unwrap();
+        assert_eq!(String::from_utf8(buf).unwrap(), "r1:1=0,3=0,...+5\nr2:1=0,3=1,...+2\n");
+    }
+
+    #[test]
+    fn binary_inputs() {
+        let binary = |text: &str| {
+            let mut converted = Vec::new();
+            convert_findings(Cursor::new(text), &mut converted, FindingsVersion::Binary, 0)
+                .unwrap();
+            converted
+        };
+        let a = binary("#mtsv-findings v2 fields=taxid,edit,seeds\nr1:1=3=4\n");
+        let b = binary("#mtsv-findings v2 fields=taxid,edit,seeds\nr1:1=2=1,2=0=5\nr:2:3=1=1\n");
+
+        // binary files merge into a binary file
+        let mut buf = Vec::new();
+        let mut infiles = vec![Cursor::new(&a[..]), Cursor::new(&b[..])];
+        collapse_edit_files(&mut infiles, &mut buf, false, None).unwrap();
+        assert!(buf.starts_with(BINARY_MAGIC));
+        let mut text = Vec::new();
+        convert_findings(Cursor::new(buf), &mut text, FindingsVersion::V2, 0).unwrap();
+        assert_eq!(String::from_utf8(text).unwrap(),
+                   "#mtsv-findings v2 fields=taxid,edit,seeds\nr1:1=2=1,2=0=5\nr%3A2:3=1=1\n");
+
+        // and into v2 along with a text file
+        let mut buf = Vec::new();
+        let mut infiles: Vec<Box<dyn BufRead>> = vec![Box::new(Cursor::new(a)),
+                                                      Box::new(Cursor::new("r1:1=1\n"))];
+        collapse_edit_files(&mut infiles, &mut buf, false, None).unwrap();
+        assert_eq!(String::from_utf8(buf).unwrap(),
+                   "#mtsv-findings v2 fields=taxid,edit\nr1:1=1\n");
+    }
 }
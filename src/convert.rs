@@ -0,0 +1,140 @@
+//! Convert results files between the legacy taxid-only format, the edit distance formats and the
+//! binary format.
+
+use error::*;
+use io::{FindingsField, FindingsIter, FindingsVersion, FindingsWriter, ResultsHeader};
+use std::io::{BufRead, Write};
+
+/// Convert a results file to the `to` format, returning the number of reads converted.
+///
+/// The input may be in any format, which is recognized from the file (see `FindingsIter`). Hits
+/// from the taxid-only legacy format get `default_edit` as their edit distance. The sample name
+/// and escaped read IDs are kept, except in legacy output, which has no header. v2 and binary
+/// output have the input's hit fields except accessions, which aren't read back, or just taxids
+/// and edit distances if the input is v1 or legacy. Reads whose lines were capped can't be
+/// converted to the legacy or binary formats, which have no overflow marker.
+pub fn convert_findings<R, W>(input: R,
+                              output: &mut W,
+                              to: FindingsVersion,
+                              default_edit: u32)
+                              -> MtsvResult<usize>
+    where R: BufRead,
+          W: Write
+{
+    let mut writer = FindingsWriter::new(output);
+    let mut reads = 0;
+
+    let mut findings = FindingsIter::new(input)?;
+    let header = findings.header().clone();
+    writer.write_header(&output_header(&header, to))?;
+
+    while let Some(finding) = findings.next() {
+        let mut finding = finding?;
+        if header.version == FindingsVersion::Legacy {
+            for hit in &mut finding.hits {
+                hit.edit = default_edit;
+            }
+        }
+        writer.write_hits_with_overflow(&finding.read_id, &finding.hits, findings.overflow())?;
+        reads += 1;
+    }
+
+    Ok(reads)
+}
+
+/// The header for converting a file with `header` to the `to` format.
+fn output_header(header: &ResultsHeader, to: FindingsVersion) -> ResultsHeader {
+    match to {
+        FindingsVersion::Legacy => {
+            ResultsHeader { version: FindingsVersion::Legacy, ..ResultsHeader::default() }
+        },
+        FindingsVersion::V1 => {
+            ResultsHeader {
+                sample: header.sample.clone(),
+                escaped_ids: header.escapes_ids(),
+                ..ResultsHeader::default()
+            }
+        },
+        FindingsVersion::V2 | FindingsVersion::Binary => {
+            let fields = match header.version {
+                FindingsVersion::V2 | FindingsVersion::Binary => {
+                    header.fields
+                        .iter()
+                        .filter(|f| {
+                            !matches!(**f, FindingsField::Accession | FindingsField::Other(_))
+                        })
+                        .cloned()
+                        .collect()
+                },
+                _ => vec![FindingsField::TaxId, FindingsField::Edit],
+            };
+            ResultsHeader {
+                sample: header.sample.clone(),
+                version: to,
+                ..ResultsHeader::v2(&fields)
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use super::*;
+
+    fn convert(input: &str, to: FindingsVersion) -> String {
+        let mut output = Vec::new();
+        convert_findings(Cursor::new(input), &mut output, to, 2).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn golden_files() {
+        let findings = "#sample=gut1\nr1:562=3,9606=0\nA1:2:7=1,7=0\n";
+        let legacy = "r1:562,9606\nA1:2:7\n";
+
+        assert_eq!(convert(findings, FindingsVersion::Legacy), legacy);
+        assert_eq!(convert(legacy, FindingsVersion::V1), "r1:562=2,9606=2\nA1:2:7=2\n");
+        assert_eq!(convert(findings, FindingsVersion::V2),
+                   "#mtsv-findings v2 fields=taxid,edit\n#sample=gut1\n\
+                    r1:562=3,9606=0\nA1%3A2:7=0\n");
+        // v2 input is recognized from its header
+        let v2 = "#mtsv-findings v2 fields=taxid,edit,seeds\nr%3A1:562=3=5\n";
+        assert_eq!(convert(v2, FindingsVersion::Legacy), "r:1:562\n");
+        assert_eq!(convert(v2, FindingsVersion::V1), "#escaped_ids=true\nr%3A1:562=3\n");
+    }
+
+    #[test]
+    fn binary_roundtrip() {
+        let v2 = "#mtsv-findings v2 fields=taxid,edit,seeds\n#sample=gut1\n\
+                  r%3A1:562=3=5,9606=0=7\nr2:7=1=1\n";
+        let mut binary = Vec::new();
+        let reads = convert_findings(Cursor::new(v2), &mut binary, FindingsVersion::Binary, 0)
+            .unwrap();
+        assert_eq!(reads, 2);
+
+        let mut text = Vec::new();
+        convert_findings(Cursor::new(binary), &mut text, FindingsVersion::V2, 0).unwrap();
+        assert_eq!(String::from_utf8(text).unwrap(), v2);
+    }
+
+    #[test]
+    fn legacy_roundtrip() {
+        let legacy = "r1:1,2,3\nr2:5\nr3:3,100\n";
+        let upgraded = convert(legacy, FindingsVersion::V2);
+        assert_eq!(convert(&upgraded, FindingsVersion::Legacy), legacy);
+
+        // IDs which legacy readers would misread can't be downgraded
+        let mut output = Vec::new();
+        assert!(convert_findings(Cursor::new("%23r1:1=0\n"),
+                                 &mut output,
+                                 FindingsVersion::Legacy,
+                                 0)
+            .is_ok());
+        assert!(convert_findings(Cursor::new("#escaped_ids=true\n%23r1:1=0\n"),
+                                 &mut output,
+                                 FindingsVersion::Legacy,
+                                 0)
+            .is_err());
+    }
+}
@@ -0,0 +1,405 @@
+//! Breadth and depth of coverage of each reference sequence from detailed hits, to tell organisms
+//! which are really present apart from piles of hits at a single conserved locus.
+
+use error::*;
+use index::{Gi, TaxId};
+use io::open_maybe_gz;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+/// A reference sequence's taxid and length.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ReferenceLength {
+    /// The taxid of the reference.
+    pub tax_id: TaxId,
+    /// Length of the reference in bases.
+    pub bases: usize,
+}
+
+/// The lengths of an index's reference sequences, by GI.
+pub type ReferenceLengths = BTreeMap<Gi, ReferenceLength>;
+
+/// Write reference lengths as a TSV with a `#gi taxid bases` header line.
+pub fn write_reference_lengths<W: Write>(lengths: &ReferenceLengths,
+                                         writer: &mut W)
+                                         -> MtsvResult<()> {
+    writeln!(writer, "#gi\ttaxid\tbases")?;
+    for (gi, length) in lengths {
+        writeln!(writer, "{}\t{}\t{}", gi.0, length.tax_id.0, length.bases)?;
+    }
+    Ok(())
+}
+
+/// Load reference lengths written by `write_reference_lengths`. Blank lines and lines starting
+/// with '#' are skipped, and gzipped files are decompressed transparently, so the tables of
+/// several index chunks can be concatenated.
+pub fn load_reference_lengths<P: AsRef<Path>>(p: P) -> MtsvResult<ReferenceLengths> {
+    let path = p.as_ref();
+    let mut lengths = ReferenceLengths::new();
+
+    for (i, line) in open_maybe_gz(path)?.lines().enumerate() {
+        let line = line?;
+        let entry = line.trim();
+        if entry.is_empty() || entry.starts_with('#') {
+            continue;
+        }
+
+        let invalid = || {
+            MtsvError::InvalidListEntry(path.display().to_string(), i + 1, entry.to_string())
+        };
+
+        let columns = entry.split_whitespace().collect::<Vec<_>>();
+        if columns.len() != 3 {
+            return Err(invalid());
+        }
+        match (columns[0].parse::<Gi>(), columns[1].parse::<TaxId>(), columns[2].parse::<usize>()) {
+            (Ok(gi), Ok(tax_id), Ok(bases)) => {
+                lengths.insert(gi, ReferenceLength { tax_id, bases });
+            },
+            _ => return Err(invalid()),
+        }
+    }
+
+    Ok(lengths)
+}
+
+/// The coverage of a single reference sequence.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GiCoverage {
+    /// The reference's GI.
+    pub gi: Gi,
+    /// The reference's taxid and length.
+    pub reference: ReferenceLength,
+    /// Number of hits on the reference.
+    pub hits: usize,
+    /// Number of coverage bins with at least one hit base.
+    pub bins_covered: usize,
+    /// Number of coverage bins the reference is split into.
+    pub bins: usize,
+    /// Total number of hit bases, counting overlapping hits once each.
+    pub hit_bases: u64,
+}
+
+impl GiCoverage {
+    /// Fraction of the reference's bins which have any hits.
+    pub fn breadth(&self) -> f64 {
+        ratio(self.bins_covered as f64, self.bins as f64)
+    }
+
+    /// Mean number of hits covering each base of the reference.
+    pub fn mean_depth(&self) -> f64 {
+        ratio(self.hit_bases as f64, self.reference.bases as f64)
+    }
+}
+
+/// The coverage of all of a taxid's reference sequences, including those without hits.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TaxidCoverage {
+    /// The taxid.
+    pub tax_id: TaxId,
+    /// Number of reference sequences the taxid has.
+    pub references: usize,
+    /// Number of the taxid's reference sequences with hits.
+    pub references_hit: usize,
+    /// Total length of the taxid's reference sequences.
+    pub bases: usize,
+    /// Number of hits on the taxid's reference sequences.
+    pub hits: usize,
+    /// Number of coverage bins with at least one hit base, over all of the references.
+    pub bins_covered: usize,
+    /// Number of coverage bins over all of the references.
+    pub bins: usize,
+    /// Total number of hit bases over all of the references.
+    pub hit_bases: u64,
+}
+
+impl TaxidCoverage {
+    /// Fraction of the taxid's bins which have any hits.
+    pub fn breadth(&self) -> f64 {
+        ratio(self.bins_covered as f64, self.bins as f64)
+    }
+
+    /// Mean number of hits covering each base of the taxid's references.
+    pub fn mean_depth(&self) -> f64 {
+        ratio(self.hit_bases as f64, self.bases as f64)
+    }
+}
+
+fn ratio(numerator: f64, denominator: f64) -> f64 {
+    if denominator > 0.0 { numerator / denominator } else { 0.0 }
+}
+
+/// Hit bases in each fixed-size bin of a single reference.
+struct CoverageVector {
+    gi: Gi,
+    reference: ReferenceLength,
+    bin_size: usize,
+    hits: usize,
+    depths: Vec<u64>,
+}
+
+impl CoverageVector {
+    fn new(gi: Gi, reference: ReferenceLength, bin_size: usize) -> Self {
+        CoverageVector {
+            gi,
+            reference,
+            bin_size,
+            hits: 0,
+            depths: vec![0; reference.bases.div_ceil(bin_size)],
+        }
+    }
+
+    /// Add a hit covering `length` bases from `offset`, clipped to the end of the reference.
+    fn add(&mut self, offset: usize, length: usize) {
+        self.hits += 1;
+        let end = offset.saturating_add(length).min(self.reference.bases);
+
+        let mut start = offset;
+        while start < end {
+            let bin = start / self.bin_size;
+            let bin_end = ((bin + 1) * self.bin_size).min(end);
+            self.depths[bin] += (bin_end - start) as u64;
+            start = bin_end;
+        }
+    }
+
+    fn finish(self) -> GiCoverage {
+        GiCoverage {
+            gi: self.gi,
+            reference: self.reference,
+            hits: self.hits,
+            bins_covered: self.depths.iter().filter(|&&d| d > 0).count(),
+            bins: self.depths.len(),
+            hit_bases: self.depths.iter().sum(),
+        }
+    }
+}
+
+/// A detailed hit: a read aligned to `length` bases from `offset` within a reference.
+fn parse_detailed_hit(line: &str) -> MtsvResult<(Gi, usize, usize)> {
+    let invalid = || MtsvError::InvalidFindings(format!("bad detailed hit: {:?}", line));
+
+    let columns = line.split('\t').collect::<Vec<_>>();
+    if columns.len() < 4 {
+        return Err(invalid());
+    }
+    match (columns[1].parse::<Gi>(), columns[2].parse::<usize>(), columns[3].parse::<usize>()) {
+        (Ok(gi), Ok(offset), Ok(length)) => Ok((gi, offset, length)),
+        _ => Err(invalid()),
+    }
+}
+
+/// Compute the coverage of each reference from detailed hits, with coverage counted in bins of
+/// `bin_size` bases.
+///
+/// Each line of `hits` is a `READ_ID GI OFFSET LENGTH` TSV row, with the 0-based offset of the
+/// hit within its reference, and any further columns (e.g. the strand) are ignored. Lines
+/// starting with '#' are skipped. The lines must be grouped by GI (e.g. with `sort -k2,2n`), so
+/// only one reference's coverage is held in memory at a time.
+///
+/// Returns a row for each reference with hits, in input order, and one for each taxid with hits,
+/// in taxid order, along with the GIs which had hits but aren't in `lengths`.
+pub fn compute_coverage<R: BufRead>(hits: R,
+                                    lengths: &ReferenceLengths,
+                                    bin_size: usize)
+                                    -> MtsvResult<(Vec<GiCoverage>,
+                                                   Vec<TaxidCoverage>,
+                                                   BTreeSet<Gi>)> {
+    if bin_size == 0 {
+        return Err(MtsvError::InvalidParameter(String::from("coverage bin size must be at least \
+                                                             1")));
+    }
+
+    let mut rows = Vec::new();
+    let mut seen = BTreeSet::new();
+    let mut missing = BTreeSet::new();
+    let mut current: Option<CoverageVector> = None;
+
+    for line in hits.lines() {
+        let line = line?;
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (gi, offset, length) = parse_detailed_hit(line)?;
+        if current.as_ref().map(|c| c.gi) != Some(gi) {
+            if !seen.insert(gi) {
+                return Err(MtsvError::InvalidFindings(format!("detailed hits aren't grouped \
+                                                               by GI, GI {} appears again",
+                                                              gi.0)));
+            }
+            if let Some(finished) = current.take() {
+                rows.push(finished.finish());
+            }
+            current = lengths.get(&gi).map(|&r| CoverageVector::new(gi, r, bin_size));
+        }
+
+        match current {
+            Some(ref mut coverage) => coverage.add(offset, length),
+            None => {
+                missing.insert(gi);
+            },
+        }
+    }
+    if let Some(finished) = current.take() {
+        rows.push(finished.finish());
+    }
+
+    let mut taxids = BTreeMap::new();
+    for row in &rows {
+        let taxid = taxids.entry(row.reference.tax_id).or_insert(TaxidCoverage {
+            tax_id: row.reference.tax_id,
+            references: 0,
+            references_hit: 0,
+            bases: 0,
+            hits: 0,
+            bins_covered: 0,
+            bins: 0,
+            hit_bases: 0,
+        });
+        taxid.hits += row.hits;
+        taxid.bins_covered += row.bins_covered;
+        taxid.hit_bases += row.hit_bases;
+    }
+
+    // the taxids' references without hits still count towards their size
+    for reference in lengths.values() {
+        if let Some(taxid) = taxids.get_mut(&reference.tax_id) {
+            taxid.references += 1;
+            taxid.bases += reference.bases;
+            taxid.bins += reference.bases.div_ceil(bin_size);
+        }
+    }
+    for row in &rows {
+        if let Some(taxid) = taxids.get_mut(&row.reference.tax_id) {
+            taxid.references_hit += 1;
+        }
+    }
+
+    Ok((rows, taxids.into_values().collect(), missing))
+}
+
+/// Write per-reference coverage as a TSV, one GI per line.
+pub fn write_gi_coverage<W: Write>(rows: &[GiCoverage], writer: &mut W) -> MtsvResult<()> {
+    writeln!(writer, "gi\ttaxid\tbases\thits\tbins_covered\tbins\tbreadth\tmean_depth")?;
+    for row in rows {
+        writeln!(writer,
+                 "{}\t{}\t{}\t{}\t{}\t{}\t{:.4}\t{:.4}",
+                 row.gi.0,
+                 row.reference.tax_id.0,
+                 row.reference.bases,
+                 row.hits,
+                 row.bins_covered,
+                 row.bins,
+                 row.breadth(),
+                 row.mean_depth())?;
+    }
+    Ok(())
+}
+
+/// Write per-taxid coverage as a TSV, one taxid per line.
+pub fn write_taxid_coverage<W: Write>(rows: &[TaxidCoverage], writer: &mut W) -> MtsvResult<()> {
+    writeln!(writer,
+             "taxid\treferences\treferences_hit\tbases\thits\tbins_covered\tbins\tbreadth\t\
+              mean_depth")?;
+    for row in rows {
+        writeln!(writer,
+                 "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{:.4}\t{:.4}",
+                 row.tax_id.0,
+                 row.references,
+                 row.references_hit,
+                 row.bases,
+                 row.hits,
+                 row.bins_covered,
+                 row.bins,
+                 row.breadth(),
+                 row.mean_depth())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use mktemp::Temp;
+    use std::fs;
+    use std::io::Cursor;
+    use std::str;
+    use super::*;
+
+    fn lengths() -> ReferenceLengths {
+        let mut lengths = ReferenceLengths::new();
+        lengths.insert(Gi(10), ReferenceLength { tax_id: TaxId(1), bases: 1_000 });
+        lengths.insert(Gi(11), ReferenceLength { tax_id: TaxId(1), bases: 1_000 });
+        lengths.insert(Gi(20), ReferenceLength { tax_id: TaxId(2), bases: 950 });
+        lengths
+    }
+
+    #[test]
+    fn lengths_roundtrip() {
+        let dir = Temp::new_dir().unwrap();
+        let path = dir.to_path_buf().join("lengths.tsv");
+        let mut buf = Vec::new();
+        write_reference_lengths(&lengths(), &mut buf).unwrap();
+        fs::write(&path, &buf).unwrap();
+        assert_eq!(load_reference_lengths(&path).unwrap(), lengths());
+
+        fs::write(&path, b"10\t1\n").unwrap();
+        assert!(load_reference_lengths(&path).is_err());
+    }
+
+    #[test]
+    fn known_fractions() {
+        // GI 10 is covered evenly across half of its bins, while every hit on GI 20 piles up at
+        // the same locus
+        let mut hits = String::from("#read_id\tgi\toffset\tlength\tstrand\n");
+        for i in 0..5 {
+            hits.push_str(&format!("r{}\t10\t{}\t100\t+\n", i, i * 200));
+        }
+        for i in 0..5 {
+            hits.push_str(&format!("p{}\t20\t500\t100\t-\n", i));
+        }
+        // straddling a bin boundary, running off the end of the reference, and an unknown GI
+        hits.push_str("s1\t20\t150\t100\t+\ns2\t20\t900\t100\t+\ns3\t99\t0\t100\t+\n");
+
+        let (gis, taxids, missing) = compute_coverage(Cursor::new(hits), &lengths(), 100)
+            .unwrap();
+
+        assert_eq!(gis.iter()
+                       .map(|g| (g.gi, g.hits, g.bins_covered, g.bins, g.hit_bases))
+                       .collect::<Vec<_>>(),
+                   vec![(Gi(10), 5, 5, 10, 500), (Gi(20), 7, 4, 10, 650)]);
+        assert_eq!(gis[0].breadth(), 0.5);
+        assert_eq!(gis[0].mean_depth(), 0.5);
+        assert_eq!(missing, vec![Gi(99)].into_iter().collect());
+
+        // GI 11 has no hits but still counts towards taxid 1's size
+        assert_eq!(taxids[0],
+                   TaxidCoverage {
+                       tax_id: TaxId(1),
+                       references: 2,
+                       references_hit: 1,
+                       bases: 2_000,
+                       hits: 5,
+                       bins_covered: 5,
+                       bins: 20,
+                       hit_bases: 500,
+                   });
+        assert_eq!(taxids[0].breadth(), 0.25);
+        assert_eq!(taxids[1].tax_id, TaxId(2));
+
+        let mut report = Vec::new();
+        write_gi_coverage(&gis, &mut report).unwrap();
+        assert_eq!(str::from_utf8(&report).unwrap().lines().nth(2).unwrap(),
+                   "20\t2\t950\t7\t4\t10\t0.4000\t0.6842");
+    }
+
+    #[test]
+    fn ungrouped_hits_rejected() {
+        let hits = "r1\t10\t0\t100\nr2\t20\t0\t100\nr3\t10\t100\t100\n";
+        assert!(compute_coverage(Cursor::new(hits), &lengths(), 100).is_err());
+        assert!(compute_coverage(Cursor::new("r1\t10\tx\t100\n"), &lengths(), 100).is_err());
+        assert!(compute_coverage(Cursor::new(""), &lengths(), 0).is_err());
+    }
+}
@@ -3,19 +3,43 @@ use std::fmt;
 use std::io;
 use std::str;
 use bincode;
+use serde_json;
 
 #[allow(missing_docs)]
 pub type MtsvResult<T> = Result<T, MtsvError>;
 
 #[allow(missing_docs)]
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum MtsvError {
     Io(io::Error),
     InvalidHeader(String),
+    BareTaxIdHeader(String),
     InvalidInteger(String),
+    InvalidListEntry(String, usize, String),
+    InvalidFastaRecord(String, usize, Box<MtsvError>),
+    InvalidManifestEntry(String, usize, Box<MtsvError>),
+    InvalidParameter(String),
+    InvalidReadId(String),
+    DuplicateReadId(String),
+    InvalidFindings(String),
+    IncompatibleIndex(String, String),
+    CorruptIndex(String, u64, u64),
+    MappedIndex(String),
+    InvalidCheckpoint(String, String),
+    IndexTooLarge(f64, f64),
+    MismatchedQuality(String, usize, usize),
     MissingFile(String),
     MissingHeader,
+    EmptyReference(String),
+    InvalidReferenceByte(String, u8, usize),
+    AmbiguousReference(String, usize),
+    MissingGi(String, u32),
+    DuplicateGi(u32, String, String),
+    UnmappedAccession(String),
+    MixedSamples(String, String),
     Serialize(bincode::Error),
+    Json(serde_json::Error),
     Utf8(str::Utf8Error),
     FastqReadError(String),
     AnyhowError(String),
@@ -29,10 +53,103 @@ impl fmt::Display for MtsvError {
             &MtsvError::InvalidHeader(ref h) => {
                 write!(f, "Incorrectly formatted FASTA header: {}", h)
             },
+            &MtsvError::BareTaxIdHeader(ref h) => {
+                write!(f,
+                       "FASTA header {} has no GI, use --header-format taxid for taxid-only headers",
+                       h)
+            },
             &MtsvError::InvalidInteger(ref s) => write!(f, "Unable to parse \"{}\" as integer", s),
+            &MtsvError::InvalidListEntry(ref p, line, ref s) => {
+                write!(f, "Unable to parse \"{}\" on line {} of {}", s, line, p)
+            },
+            &MtsvError::InvalidFastaRecord(ref p, record, ref e) => {
+                write!(f, "Problem with record {} of {}: {}", record, p, e)
+            },
+            &MtsvError::InvalidManifestEntry(ref p, line, ref e) => {
+                write!(f, "Problem with line {} of manifest {}: {}", line, p, e)
+            },
+            &MtsvError::InvalidParameter(ref s) => write!(f, "Invalid parameter: {}", s),
+            &MtsvError::InvalidReadId(ref s) => {
+                write!(f,
+                       "Read ID {:?} can't be written to results unambiguously, use --escape-ids",
+                       s)
+            },
+            &MtsvError::DuplicateReadId(ref s) => {
+                write!(f,
+                       "Read ID {:?} appears more than once, use --duplicate-ids suffix or merge",
+                       s)
+            },
+            &MtsvError::InvalidFindings(ref s) => write!(f, "Invalid results: {}", s),
+            &MtsvError::IndexTooLarge(size, limit) => {
+                write!(f,
+                       "The indices take {:.2} GB, more than the memory limit of {:.2} GB \
+                        (--memory-limit-gb)",
+                       size,
+                       limit)
+            },
+            &MtsvError::IncompatibleIndex(ref p, ref why) => {
+                write!(f, "Unable to read index {}: {}", p, why)
+            },
+            &MtsvError::CorruptIndex(ref p, expected, found) => {
+                write!(f,
+                       "Index {} is corrupt: its checksum is {:016x}, but its contents hash to \
+                        {:016x}",
+                       p,
+                       expected,
+                       found)
+            },
+            &MtsvError::MappedIndex(ref p) => {
+                write!(f,
+                       "{} is a memory-mapped index (see mtsv-build --mmap-format), which only \
+                        mtsv-binner, mtsv-reference and mtsv-run read",
+                       p)
+            },
+            &MtsvError::InvalidCheckpoint(ref p, ref why) => {
+                write!(f, "Unable to resume from checkpoint {}: {}", p, why)
+            },
+            &MtsvError::MismatchedQuality(ref id, bases, quals) => {
+                write!(f, "FASTQ record {} has {} bases but {} quality scores", id, bases, quals)
+            },
             &MtsvError::MissingFile(ref p) => write!(f, "Unable to find file {}", p),
             &MtsvError::MissingHeader => write!(f, "Empty header found in FASTA file"),
+            &MtsvError::EmptyReference(ref h) => {
+                write!(f, "Reference {} has no sequence", h)
+            },
+            &MtsvError::InvalidReferenceByte(ref h, b, base) => {
+                let byte = if b.is_ascii_graphic() {
+                    format!("'{}'", b as char)
+                } else {
+                    format!("byte 0x{:02X}", b)
+                };
+                write!(f,
+                       "Reference {} has {} at base {}, which can't be in a reference sequence",
+                       h,
+                       byte,
+                       base)
+            },
+            &MtsvError::AmbiguousReference(ref h, base) => {
+                write!(f,
+                       "Reference {} has an N at base {}, which a dna4 index can't hold; build \
+                        it with --alphabet dna, or without masking or ambiguity codes",
+                       h,
+                       base)
+            },
+            &MtsvError::MissingGi(ref p, gi) => write!(f, "GI {} isn't in index {}", gi, p),
+            &MtsvError::DuplicateGi(gi, ref a, ref b) => {
+                write!(f, "GI {} is in both {} and {}", gi, a, b)
+            },
+            &MtsvError::UnmappedAccession(ref a) => {
+                write!(f, "Reference accession {} isn't in the accession map", a)
+            },
+            &MtsvError::MixedSamples(ref a, ref b) => {
+                write!(f,
+                       "Results are from different samples ({} and {}), allow mixed samples to \
+                        prefix read IDs with their sample name",
+                       a,
+                       b)
+            },
             &MtsvError::Serialize(ref e) => write!(f, "Unable to serialize/deserialize item: {}", e),
+            &MtsvError::Json(ref e) => write!(f, "Unable to read/write JSON: {}", e),
             &MtsvError::Utf8(ref e) => write!(f, "Found invalid UTF8 input ({})", e),
             &MtsvError::FastqReadError(ref e) => write!(f, "Error reading FASTQ file: ({})", e),
             &MtsvError::AnyhowError(ref s) => write!(f, "Error: {}", s),
@@ -53,6 +170,12 @@ impl From<bincode::Error> for MtsvError {
     }
 }
 
+impl From<serde_json::Error> for MtsvError {
+    fn from(e: serde_json::Error) -> Self {
+        MtsvError::Json(e)
+    }
+}
+
 impl From<str::Utf8Error> for MtsvError {
     fn from(e: str::Utf8Error) -> Self {
         MtsvError::Utf8(e)
@@ -0,0 +1,375 @@
+//! Evaluate binner results against the known source taxid of each read, e.g. for simulated reads.
+
+use error::*;
+use index::TaxId;
+use io::{open_maybe_gz, FindingsIter, ReadFindings};
+use serde::{Serialize, Deserialize};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+/// Load a truth table with a read ID and the taxid it came from on each line, separated by tabs
+/// or spaces. Blank lines and lines starting with '#' are skipped, and gzipped files are
+/// decompressed transparently. Giving one read two different taxids is an error.
+pub fn load_truth<P: AsRef<Path>>(p: P) -> MtsvResult<HashMap<String, TaxId>> {
+    let path = p.as_ref();
+    let mut truth = HashMap::new();
+
+    for (i, line) in open_maybe_gz(path)?.lines().enumerate() {
+        let line = line?;
+        let entry = line.trim();
+        if entry.is_empty() || entry.starts_with('#') {
+            continue;
+        }
+
+        let invalid = || {
+            MtsvError::InvalidListEntry(path.display().to_string(), i + 1, entry.to_string())
+        };
+
+        let mut columns = entry.split_whitespace();
+        let (read_id, tax_id) = match (columns.next(), columns.next(), columns.next()) {
+            (Some(read_id), Some(tax_id), None) => {
+                match tax_id.parse::<TaxId>() {
+                    Ok(tax_id) => (read_id, tax_id),
+                    Err(_) => return Err(invalid()),
+                }
+            },
+            _ => return Err(invalid()),
+        };
+
+        match truth.insert(read_id.to_string(), tax_id) {
+            Some(prev) if prev != tax_id => return Err(invalid()),
+            Some(_) => {
+                warn!("Duplicate entry \"{}\" on line {} of {}", entry, i + 1, path.display())
+            },
+            None => (),
+        }
+    }
+
+    Ok(truth)
+}
+
+/// How the binner did on a single read with a known source.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReadEvaluation {
+    /// The read's ID.
+    pub read_id: String,
+    /// The taxid the read came from.
+    pub truth: TaxId,
+    /// The taxids the read was assigned to and their edit distances, in order of edit distance.
+    pub hits: Vec<(TaxId, u32)>,
+    /// Rank of the true taxid among the hits by edit distance, starting from 1, if it was hit.
+    /// Hits with the same edit distance share a rank.
+    pub rank: Option<usize>,
+}
+
+impl ReadEvaluation {
+    /// Proportion of the read's taxids which are correct: 1 / the number of taxids if the true
+    /// taxid was hit, otherwise 0. `None` if the read wasn't assigned to any taxid.
+    pub fn precision(&self) -> Option<f64> {
+        if self.hits.is_empty() {
+            None
+        } else if self.rank.is_some() {
+            Some(1.0 / self.hits.len() as f64)
+        } else {
+            Some(0.0)
+        }
+    }
+
+    /// Whether the true taxid was hit (1) or not (0).
+    pub fn recall(&self) -> f64 {
+        if self.rank.is_some() { 1.0 } else { 0.0 }
+    }
+
+    /// Why the read wasn't classified perfectly, or `None` if only its true taxid was hit.
+    pub fn error(&self) -> Option<&'static str> {
+        match (self.rank, self.hits.len()) {
+            (_, 0) => Some("unclassified"),
+            (None, _) => Some("missed"),
+            (Some(_), 1) => None,
+            (Some(_), _) => Some("ambiguous"),
+        }
+    }
+}
+
+/// Aggregate metrics over all of the reads in a truth table.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct EvaluationSummary {
+    /// Number of reads in the truth table.
+    pub reads: usize,
+    /// Reads assigned to at least one taxid.
+    pub classified: usize,
+    /// Reads whose true taxid was among their hits.
+    pub truth_hit: usize,
+    /// Reads whose true taxid had the best edit distance (possibly tied).
+    pub truth_best: usize,
+    /// Reads whose only hit was their true taxid.
+    pub exact: usize,
+    /// Reads in the results which aren't in the truth table, and weren't evaluated.
+    pub unlabelled: usize,
+    /// Mean per-read precision over the classified reads.
+    pub precision: f64,
+    /// Proportion of all reads whose true taxid was hit.
+    pub recall: f64,
+    /// Harmonic mean of `precision` and `recall`.
+    pub f1: f64,
+    /// Mean rank of the true taxid, over the reads where it was hit.
+    pub mean_truth_rank: f64,
+}
+
+impl EvaluationSummary {
+    /// Summarize a set of per-read evaluations.
+    pub fn new(reads: &[ReadEvaluation], unlabelled: usize) -> Self {
+        let mut summary = EvaluationSummary { reads: reads.len(), unlabelled, ..Self::default() };
+
+        let mut precision_sum = 0.0;
+        let mut rank_sum = 0;
+        for read in reads {
+            if let Some(precision) = read.precision() {
+                summary.classified += 1;
+                precision_sum += precision;
+            }
+            if let Some(rank) = read.rank {
+                summary.truth_hit += 1;
+                rank_sum += rank;
+                if rank == 1 {
+                    summary.truth_best += 1;
+                }
+            }
+            if read.error().is_none() {
+                summary.exact += 1;
+            }
+        }
+
+        let ratio = |a: f64, b: usize| if b == 0 { 0.0 } else { a / b as f64 };
+        summary.precision = ratio(precision_sum, summary.classified);
+        summary.recall = ratio(summary.truth_hit as f64, summary.reads);
+        summary.mean_truth_rank = ratio(rank_sum as f64, summary.truth_hit);
+        summary.f1 = if summary.precision + summary.recall == 0.0 {
+            0.0
+        } else {
+            2.0 * summary.precision * summary.recall / (summary.precision + summary.recall)
+        };
+
+        summary
+    }
+}
+
+/// Evaluate a results file against a truth table, returning the summary and an evaluation for
+/// each read in the truth table, sorted by read ID.
+pub fn evaluate<R: BufRead>(results: R,
+                            truth: &HashMap<String, TaxId>)
+                            -> MtsvResult<(EvaluationSummary, Vec<ReadEvaluation>)> {
+    // best edit distance for each taxid of each read, in case a read appears more than once
+    let mut found: HashMap<String, HashMap<TaxId, u32>> = HashMap::new();
+    let mut unlabelled = 0;
+
+    for finding in FindingsIter::new(results)? {
+        let ReadFindings { read_id, hits, .. } = finding?;
+        if !truth.contains_key(&read_id) {
+            unlabelled += 1;
+            continue;
+        }
+
+        let best = found.entry(read_id).or_default();
+        for hit in hits {
+            let edit = best.entry(hit.tax_id).or_insert(hit.edit);
+            *edit = (*edit).min(hit.edit);
+        }
+    }
+
+    let reads = truth.iter()
+        .collect::<BTreeMap<_, _>>()
+        .into_iter()
+        .map(|(read_id, &tax_id)| {
+            let mut hits = found.remove(read_id)
+                .map(|best| best.into_iter().collect::<Vec<_>>())
+                .unwrap_or_default();
+            hits.sort_by_key(|&(tax_id, edit)| (edit, tax_id));
+
+            let rank = hits.iter()
+                .find(|&&(t, _)| t == tax_id)
+                .map(|&(_, edit)| 1 + hits.iter().filter(|&&(_, e)| e < edit).count());
+
+            ReadEvaluation {
+                read_id: read_id.clone(),
+                truth: tax_id,
+                hits,
+                rank,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    Ok((EvaluationSummary::new(&reads, unlabelled), reads))
+}
+
+/// Write a TSV of the reads which weren't classified perfectly, with the read ID, true taxid,
+/// the kind of error, the true taxid's rank (`-` if it wasn't hit) and the hits.
+pub fn write_misclassified<W: Write>(reads: &[ReadEvaluation], writer: &mut W) -> MtsvResult<()> {
+    writeln!(writer, "read_id\ttruth\terror\trank\thits")?;
+
+    for read in reads {
+        let error = match read.error() {
+            Some(error) => error,
+            None => continue,
+        };
+
+        let rank = read.rank.map_or_else(|| "-".to_string(), |r| r.to_string());
+        let hits = read.hits
+            .iter()
+            .map(|&(tax_id, edit)| format!("{}={}", tax_id.0, edit))
+            .collect::<Vec<_>>()
+            .join(",");
+
+        writeln!(writer,
+                 "{}\t{}\t{}\t{}\t{}",
+                 read.read_id,
+                 read.truth.0,
+                 error,
+                 rank,
+                 hits)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use binner::{bin_reads, BinnerConfig, ReadFormat};
+    use bio::io::fasta;
+    use index::{BinMode, Database, Gi, MGIndex, QueryParams};
+    use io::write_to_file;
+    use mktemp::Temp;
+    use rand::{Rng, XorShiftRng};
+    use std::fs::File;
+    use std::io::{BufReader, Cursor};
+    use std::str;
+    use super::*;
+
+    fn truth(entries: &[(&str, u64)]) -> HashMap<String, TaxId> {
+        entries.iter().map(|&(r, t)| (r.to_string(), TaxId(t))).collect()
+    }
+
+    #[test]
+    fn hand_checked_metrics() {
+        let results = b"#sample=s1\n\
+                        exact:1=0\n\
+                        ambiguous:2=3,1=1,3=1\n\
+                        missed:2=0\n\
+                        extra:5=0\n";
+        let truth = truth(&[("exact", 1),
+                            ("ambiguous", 3),
+                            ("missed", 1),
+                            ("unclassified", 4)]);
+
+        let (summary, reads) = evaluate(Cursor::new(&results[..]), &truth).unwrap();
+
+        let ranks = reads.iter().map(|r| (r.read_id.as_str(), r.rank)).collect::<Vec<_>>();
+        assert_eq!(ranks,
+                   vec![("ambiguous", Some(1)),
+                        ("exact", Some(1)),
+                        ("missed", None),
+                        ("unclassified", None)]);
+        assert_eq!(reads[0].hits, vec![(TaxId(1), 1), (TaxId(3), 1), (TaxId(2), 3)]);
+
+        // precision: (1/3 + 1 + 0) / 3 classified reads, recall: 2 of 4 reads
+        let precision = (1.0 / 3.0 + 1.0) / 3.0;
+        assert_eq!(summary,
+                   EvaluationSummary {
+                       reads: 4,
+                       classified: 3,
+                       truth_hit: 2,
+                       truth_best: 2,
+                       exact: 1,
+                       unlabelled: 1,
+                       precision,
+                       recall: 0.5,
+                       f1: 2.0 * precision * 0.5 / (precision + 0.5),
+                       mean_truth_rank: 1.0,
+                   });
+
+        let mut tsv = Vec::new();
+        write_misclassified(&reads, &mut tsv).unwrap();
+        assert_eq!(str::from_utf8(&tsv).unwrap(),
+                   "read_id\ttruth\terror\trank\thits\n\
+                    ambiguous\t3\tambiguous\t1\t1=1,3=1,2=3\n\
+                    missed\t1\tmissed\t-\t2=0\n\
+                    unclassified\t4\tunclassified\t-\t\n");
+    }
+
+    #[test]
+    fn ranks_share_ties() {
+        let truth = truth(&[("r", 3)]);
+        let results = b"r:1=0,2=2,3=2,4=5\n";
+
+        let (summary, reads) = evaluate(Cursor::new(&results[..]), &truth).unwrap();
+        assert_eq!(reads[0].rank, Some(2));
+        assert_eq!(summary.truth_best, 0);
+        assert_eq!(summary.mean_truth_rank, 2.0);
+    }
+
+    #[test]
+    fn truth_table_conflict() {
+        let dir = Temp::new_dir().unwrap();
+        let path = dir.to_path_buf().join("truth.tsv");
+        ::std::fs::write(&path, b"# read\ttaxid\nr1\t1\nr1 1\n\nr2\t2\n").unwrap();
+        assert_eq!(load_truth(&path).unwrap(), truth(&[("r1", 1), ("r2", 2)]));
+
+        ::std::fs::write(&path, b"r1\t1\nr1\t2\n").unwrap();
+        assert!(load_truth(&path).is_err());
+    }
+
+    #[test]
+    fn labelled_binner_run() {
+        let mut rng = XorShiftRng::new_unseeded();
+        let mut random_seq =
+            |len| (0..len).map(|_| *rng.choose(b"ACGT").unwrap()).collect::<Vec<u8>>();
+
+        let ref1 = random_seq(500);
+        let ref2 = random_seq(500);
+        let mut db = Database::new();
+        db.insert(TaxId(1), vec![(Gi(1), ref1.clone())]);
+        db.insert(TaxId(2), vec![(Gi(2), ref2.clone())]);
+
+        let dir = Temp::new_dir().unwrap();
+        let dir = dir.to_path_buf();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        write_to_file(&MGIndex::new(db, 16, 32, BinMode::PerGi), &path("test.index")).unwrap();
+
+        // reads from each reference, one from neither, and one with the wrong label
+        let reads = vec![("from_1", ref1[50..150].to_vec()),
+                         ("from_2", ref2[200..300].to_vec()),
+                         ("from_neither", random_seq(100)),
+                         ("mislabelled", ref1[300..400].to_vec())];
+        {
+            let mut writer = fasta::Writer::to_file(path("reads.fasta")).unwrap();
+            for &(id, ref seq) in &reads {
+                writer.write(id, None, seq).unwrap();
+            }
+        }
+        let truth = truth(&[("from_1", 1), ("from_2", 2), ("from_neither", 1), ("mislabelled", 2)]);
+
+        bin_reads(&path("reads.fasta"),
+                  ReadFormat::Fasta,
+                  &[&path("test.index")],
+                  &path("results.txt"),
+                  1,
+                  &QueryParams { seed_gap: 4, ..QueryParams::default() },
+                  &BinnerConfig::default())
+            .unwrap();
+
+        let results = BufReader::new(File::open(path("results.txt")).unwrap());
+        let (summary, reads) = evaluate(results, &truth).unwrap();
+
+        let errors = reads.iter().map(|r| (r.read_id.as_str(), r.error())).collect::<Vec<_>>();
+        assert_eq!(errors,
+                   vec![("from_1", None),
+                        ("from_2", None),
+                        ("from_neither", Some("unclassified")),
+                        ("mislabelled", Some("missed"))]);
+        assert_eq!((summary.reads, summary.classified, summary.truth_hit, summary.exact),
+                   (4, 3, 2, 2));
+        assert_eq!(summary.precision, 2.0 / 3.0);
+        assert_eq!(summary.recall, 0.5);
+    }
+}
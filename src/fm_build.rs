@@ -0,0 +1,458 @@
+//! The BWT, occurrence table and sampled suffix array of an index build, spread over several
+//! threads.
+//!
+//! rust-bio builds each of these in one pass over every row of the suffix array. The rows split
+//! into contiguous ranges which threads can work on at the same time, and the results are put
+//! together in row order, so the tables are identical to rust-bio's whatever the number of
+//! threads. rust-bio has no constructors for tables built elsewhere, so they're handed to it
+//! through its serde implementations (see `assemble`), which costs a copy of the BWT and the
+//! tables once the suffix array itself has been freed.
+
+use bio::alphabets::Alphabet;
+use bio::data_structures::bwt::{Less, Occ, BWT};
+use bio::data_structures::suffix_array::SampledSuffixArray;
+use serde::de::value::{Error, MapDeserializer};
+use serde::de::{DeserializeSeed, Deserializer, IntoDeserializer, SeqAccess, Visitor};
+use serde::{forward_to_deserialize_any, Deserialize, Serialize};
+use std::cmp;
+use bincode;
+use std::io::{self, Read, Write};
+use std::thread;
+use std::vec;
+use util::Progress;
+
+/// The number of suffix array rows `build_from_suffix_array_reader` reads at a time, before
+/// rounding up to a multiple of the sampling rate.
+const SUFFIX_ARRAY_CHUNK_ROWS: usize = 1 << 22;
+
+/// The number of rows each thread works through between adding them to its `Progress`.
+const PROGRESS_ROWS: usize = 1 << 20;
+
+/// The sampled rows of an occurrence table, as `Occ` holds them: for each byte, how many times it
+/// occurs in the BWT up to and including every `k`th row.
+pub struct OccTable {
+    occ: Vec<Vec<usize>>,
+    k: u32,
+}
+
+/// The sampled rows of a suffix array, as `SampledSuffixArray` holds them.
+#[derive(Serialize, Deserialize)]
+pub struct SuffixSamples {
+    sample: Vec<usize>,
+    s: usize,
+    extra_rows: Vec<(usize, usize)>,
+    sentinel: u8,
+}
+
+/// The number of rows for each thread to work on, so that `n` rows are split between at most
+/// `threads` threads, each starting at a multiple of `align`.
+fn rows_per_thread(n: usize, threads: usize, align: usize) -> usize {
+    cmp::max(n.div_ceil(cmp::max(threads, 1)), 1).next_multiple_of(align)
+}
+
+/// The BWT of `text` from its suffix array `sa`, as rust-bio's `bwt` computes it.
+pub fn build_bwt(text: &[u8], sa: &[usize], threads: usize) -> BWT {
+    assert_eq!(text.len(), sa.len());
+    let mut bwt = vec![0; text.len()];
+    fill_bwt(text, sa, &mut bwt, threads);
+    bwt
+}
+
+/// Fill in the rows of the BWT of `text` whose suffix array rows are `sa`.
+fn fill_bwt(text: &[u8], sa: &[usize], bwt: &mut [u8], threads: usize) {
+    let n = text.len();
+    let chunk = rows_per_thread(sa.len(), threads, 1);
+
+    thread::scope(|scope| {
+        for (rows, positions) in bwt.chunks_mut(chunk).zip(sa.chunks(chunk)) {
+            scope.spawn(move || {
+                for (b, &p) in rows.iter_mut().zip(positions) {
+                    *b = if p > 0 { text[p - 1] } else { text[n - 1] };
+                }
+            });
+        }
+    });
+}
+
+/// The BWT and suffix array samples of `text`, as `build_bwt` and `sample_suffix_array` build
+/// them, from its suffix array as little-endian `u64`s read from `sa` (see
+/// `suffix_sort::external_suffix_array`). The suffix array is read a chunk of rows at a time, so
+/// it's never held in memory. The rows are added to `progress` as they're sampled.
+pub fn build_from_suffix_array_reader<R: Read>(text: &[u8],
+                                               mut sa: R,
+                                               s: usize,
+                                               threads: usize,
+                                               progress: &Progress)
+                                               -> io::Result<(BWT, SuffixSamples)> {
+    let n = text.len();
+    let mut bwt = vec![0; n];
+    let mut samples = SuffixSamples::new(text, s);
+    let chunk = SUFFIX_ARRAY_CHUNK_ROWS.next_multiple_of(s);
+    let mut bytes = vec![0; cmp::min(chunk, n) * 8];
+    let mut rows = Vec::with_capacity(cmp::min(chunk, n));
+
+    for first in (0..n).step_by(chunk) {
+        let len = cmp::min(chunk, n - first);
+        sa.read_exact(&mut bytes[..len * 8])?;
+        rows.clear();
+        rows.extend(bytes[..len * 8].chunks_exact(8).map(|b| {
+            let mut row = [0; 8];
+            row.copy_from_slice(b);
+            u64::from_le_bytes(row) as usize
+        }));
+
+        fill_bwt(text, &rows, &mut bwt[first..first + len], threads);
+        samples.add_rows(first, &rows, &bwt[first..first + len], threads, progress);
+    }
+    Ok((bwt, samples))
+}
+
+/// The occurrence table of `bwt`, sampled every `k` rows, as rust-bio's `Occ::new` computes it.
+///
+/// Each thread first counts the bytes in its rows, so that it knows the counts it starts from,
+/// and then fills in its own part of the table, adding the rows to `progress` as it goes.
+pub fn build_occ(bwt: &[u8],
+                 k: u32,
+                 alphabet: &Alphabet,
+                 threads: usize,
+                 progress: &Progress)
+                 -> OccTable {
+    let n = bwt.len();
+    let m = alphabet.max_symbol().expect("Expecting non-empty alphabet.") as usize + 1;
+    let mut alpha = alphabet.symbols.iter().collect::<Vec<usize>>();
+    // the sentinel is counted too
+    if (b'$' as usize) < m && !alphabet.is_word(b"$") {
+        alpha.push(b'$' as usize);
+    }
+
+    let k_rows = k as usize;
+    let chunk = rows_per_thread(n, threads, k_rows);
+    let counts = thread::scope(|scope| {
+        let handles = bwt.chunks(chunk)
+            .map(|rows| {
+                scope.spawn(move || {
+                    let mut counts = [0usize; 256];
+                    for &c in rows {
+                        counts[c as usize] += 1;
+                    }
+                    counts
+                })
+            })
+            .collect::<Vec<_>>();
+        handles.into_iter().map(|h| h.join().unwrap()).collect::<Vec<_>>()
+    });
+
+    let mut occ = vec![Vec::new(); m];
+    for &a in &alpha {
+        occ[a] = vec![0; n.div_ceil(k_rows)];
+    }
+    // each thread's part of each byte's samples
+    let mut parts = counts.iter().map(|_| Vec::new()).collect::<Vec<_>>();
+    for (a, samples) in occ.iter_mut().enumerate().filter(|&(a, _)| alpha.contains(&a)) {
+        for (part, samples) in parts.iter_mut().zip(samples.chunks_mut(chunk / k_rows)) {
+            part.push((a, samples));
+        }
+    }
+
+    thread::scope(|scope| {
+        let mut current = [0usize; 256];
+        for (t, (counts, mut part)) in counts.iter().zip(parts).enumerate() {
+            let rows = t * chunk..cmp::min((t + 1) * chunk, n);
+            scope.spawn(move || {
+                let mut pending = 0;
+                for (sample, i) in rows.step_by(k_rows).enumerate() {
+                    let end = cmp::min(i + k_rows, n);
+                    current[bwt[i] as usize] += 1;
+                    for &mut (a, ref mut samples) in &mut part {
+                        samples[sample] = current[a];
+                    }
+                    for &c in &bwt[i + 1..end] {
+                        current[c as usize] += 1;
+                    }
+                    pending += end - i;
+                    if pending >= PROGRESS_ROWS {
+                        progress.add(pending as u64);
+                        pending = 0;
+                    }
+                }
+                progress.add(pending as u64);
+            });
+            for (total, count) in current.iter_mut().zip(counts.iter()) {
+                *total += count;
+            }
+        }
+    });
+
+    OccTable { occ, k }
+}
+
+/// Sample every `s`th row of the suffix array `sa` of `text`, keeping every row whose BWT entry
+/// is the sentinel as well, as rust-bio's `SuffixArray::sample` does. The rows are added to
+/// `progress` as they're sampled.
+pub fn sample_suffix_array(text: &[u8],
+                           sa: &[usize],
+                           bwt: &[u8],
+                           s: usize,
+                           threads: usize,
+                           progress: &Progress)
+                           -> SuffixSamples {
+    let mut samples = SuffixSamples::new(text, s);
+    samples.add_rows(0, sa, bwt, threads, progress);
+    samples
+}
+
+impl SuffixSamples {
+    /// No samples yet of the suffix array of `text`.
+    fn new(text: &[u8], s: usize) -> Self {
+        SuffixSamples {
+            sample: vec![0; text.len().div_ceil(s)],
+            s,
+            extra_rows: Vec::new(),
+            sentinel: text[text.len() - 1],
+        }
+    }
+
+    /// Sample the suffix array rows `sa`, the first of which is row `first`, a multiple of `s`.
+    /// `bwt` holds the same rows of the BWT. Rows must be added in order.
+    fn add_rows(&mut self,
+                first: usize,
+                sa: &[usize],
+                bwt: &[u8],
+                threads: usize,
+                progress: &Progress) {
+        let s = self.s;
+        let sentinel = self.sentinel;
+        let n = sa.len();
+        let chunk = rows_per_thread(n, threads, s);
+        let samples = &mut self.sample[first / s..first / s + n.div_ceil(s)];
+
+        let extra_rows = thread::scope(|scope| {
+            let handles = samples.chunks_mut(chunk / s)
+                .enumerate()
+                .map(|(t, samples)| {
+                    scope.spawn(move || {
+                        let mut extra_rows = Vec::new();
+                        let mut samples = samples.iter_mut();
+                        let rows = t * chunk..cmp::min((t + 1) * chunk, n);
+                        for i in rows.clone() {
+                            if i % s == 0 {
+                                *samples.next().unwrap() = sa[i];
+                            } else if bwt[i] == sentinel {
+                                extra_rows.push((first + i, sa[i]));
+                            }
+                            if (i + 1 - rows.start) % PROGRESS_ROWS == 0 {
+                                progress.add(PROGRESS_ROWS as u64);
+                            }
+                        }
+                        progress.add((rows.len() % PROGRESS_ROWS) as u64);
+                        extra_rows
+                    })
+                })
+                .collect::<Vec<_>>();
+            handles.into_iter().flat_map(|h| h.join().unwrap()).collect::<Vec<_>>()
+        });
+        self.extra_rows.extend(extra_rows);
+    }
+}
+
+/// Put the tables together into rust-bio's sampled suffix array, as `SuffixArray::sample` would
+/// have returned it.
+pub fn assemble(bwt: BWT,
+                less: Less,
+                occ: OccTable,
+                samples: SuffixSamples)
+                -> SampledSuffixArray<BWT, Less, Occ> {
+    let fields = vec![Field::Bytes(bwt),
+                      Field::Words(less),
+                      Field::Occ(occ),
+                      Field::Words(samples.sample),
+                      Field::Word(samples.s),
+                      Field::Rows(samples.extra_rows),
+                      Field::Byte(samples.sentinel)];
+    SampledSuffixArray::deserialize(Fields(fields))
+        .expect("rust-bio's sampled suffix array has changed its fields")
+}
+
+/// The sampling interval of rust-bio's occurrence table, which it keeps to itself. It's the last
+/// field `Occ` serializes, so this serializes the table, keeping only the last four bytes, which
+/// is only worth doing once as an index is loaded.
+pub fn occ_sampling_interval(occ: &Occ) -> u32 {
+    let mut tail = Tail([0; 4]);
+    bincode::serialize_into(&mut tail, occ).expect("Serializing to memory can't fail");
+    u32::from_le_bytes(tail.0)
+}
+
+/// A writer which only keeps the last four bytes written to it.
+struct Tail([u8; 4]);
+
+impl Write for Tail {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = cmp::min(buf.len(), 4);
+        self.0.rotate_left(n);
+        self.0[4 - n..].copy_from_slice(&buf[buf.len() - n..]);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A field of one of rust-bio's structs, in the order its serde implementation expects them.
+enum Field {
+    Byte(u8),
+    Bytes(Vec<u8>),
+    Word(usize),
+    Words(Vec<usize>),
+    WordLists(Vec<Vec<usize>>),
+    Interval(u32),
+    Occ(OccTable),
+    Rows(Vec<(usize, usize)>),
+}
+
+/// Deserializes a struct from its fields, handing over their elements one at a time rather than
+/// going through a serialized copy of them.
+struct Fields(Vec<Field>);
+
+impl<'de> Deserializer<'de> for Fields {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_seq(FieldSeq(self.0.into_iter()))
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+        option unit unit_struct newtype_struct seq tuple tuple_struct map struct enum identifier
+        ignored_any
+    }
+}
+
+struct FieldSeq(vec::IntoIter<Field>);
+
+impl<'de> SeqAccess<'de> for FieldSeq {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self,
+                                                  seed: T)
+                                                  -> Result<Option<T::Value>, Error> {
+        let field = match self.0.next() {
+            Some(field) => field,
+            None => return Ok(None),
+        };
+        let value = match field {
+            Field::Byte(b) => seed.deserialize(b.into_deserializer()),
+            Field::Bytes(bytes) => seed.deserialize(bytes.into_deserializer()),
+            Field::Word(w) => seed.deserialize(w.into_deserializer()),
+            Field::Words(words) => seed.deserialize(words.into_deserializer()),
+            Field::WordLists(lists) => seed.deserialize(lists.into_deserializer()),
+            Field::Interval(k) => seed.deserialize(k.into_deserializer()),
+            Field::Occ(occ) => {
+                seed.deserialize(Fields(vec![Field::WordLists(occ.occ), Field::Interval(occ.k)]))
+            },
+            Field::Rows(rows) => seed.deserialize(MapDeserializer::new(rows.into_iter())),
+        };
+        value.map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.0.len())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bincode::{deserialize, serialize};
+    use bio::alphabets::dna;
+    use bio::data_structures::bwt::{bwt, less};
+    use bio::data_structures::fmindex::{BackwardSearchResult, FMIndex, FMIndexable};
+    use bio::data_structures::suffix_array::{suffix_array, SuffixArray};
+    use rand::{Rng, XorShiftRng};
+    use super::*;
+
+    #[test]
+    fn matches_rust_bio() {
+        let mut rng = XorShiftRng::new_unseeded();
+        let alphabet = dna::n_alphabet();
+        for &len in &[0, 1, 2, 31, 32, 33, 1000, 5000] {
+            let mut text = (0..len).map(|_| *rng.choose(b"ACGTN").unwrap()).collect::<Vec<_>>();
+            text.push(b'$');
+            let sa = suffix_array(&text);
+
+            for &(k, s) in &[(1, 1), (3, 7), (64, 32), (10_000, 10_000)] {
+                let expected_bwt = bwt(&text, &sa);
+                let expected_less = less(&expected_bwt, &alphabet);
+                let expected_occ = Occ::new(&expected_bwt, k, &alphabet);
+                assert_eq!(occ_sampling_interval(&expected_occ), k);
+                let expected = serialize(&sa.sample(&text,
+                                                    expected_bwt,
+                                                    expected_less,
+                                                    expected_occ,
+                                                    s))
+                    .unwrap();
+
+                for threads in 1..6 {
+                    let bwt = build_bwt(&text, &sa, threads);
+                    let less = less(&bwt, &alphabet);
+                    let progress = Progress::new("Building", "rows", None);
+                    let occ = build_occ(&bwt, k, &alphabet, threads, &progress);
+                    let samples = sample_suffix_array(&text, &sa, &bwt, s, threads, &progress);
+                    assert!(serialize(&assemble(bwt, less, occ, samples)).unwrap() == expected,
+                            "{} bases, k {}, s {} on {} threads",
+                            text.len(),
+                            k,
+                            s,
+                            threads);
+                    // every row is counted once by each of them
+                    assert_eq!(progress.done(), 2 * text.len() as u64);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn assembled_index_round_trips() {
+        // `assemble` depends on the order of rust-bio's private fields, so a rust-bio upgrade
+        // which changes them should fail here rather than in a deserialized index
+        let mut rng = XorShiftRng::new_unseeded();
+        let alphabet = dna::n_alphabet();
+        let mut text = (0..20_000).map(|_| *rng.choose(b"ACGTN").unwrap()).collect::<Vec<_>>();
+        text.push(b'$');
+        let sa = suffix_array(&text);
+        let (k, s) = (64, 32);
+
+        let expected_bwt = bwt(&text, &sa);
+        let expected_less = less(&expected_bwt, &alphabet);
+        let expected_occ = Occ::new(&expected_bwt, k, &alphabet);
+        let expected = sa.sample(&text, expected_bwt, expected_less, expected_occ, s);
+
+        let bwt = build_bwt(&text, &sa, 3);
+        let less = less(&bwt, &alphabet);
+        let progress = Progress::new("Building", "rows", None);
+        let occ = build_occ(&bwt, k, &alphabet, 3, &progress);
+        let samples = sample_suffix_array(&text, &sa, &bwt, s, 3, &progress);
+        let assembled: SampledSuffixArray<BWT, Less, Occ> =
+            deserialize(&serialize(&assemble(bwt, less, occ, samples)).unwrap()).unwrap();
+
+        assert_eq!(occ_sampling_interval(assembled.occ()), k);
+        assert_eq!(assembled.sampling_rate(), s);
+        let expected_fm = FMIndex::new(expected.bwt(), expected.less(), expected.occ());
+        let assembled_fm = FMIndex::new(assembled.bwt(), assembled.less(), assembled.occ());
+        for len in 1..12 {
+            for _ in 0..50 {
+                let start = rng.gen_range(0, text.len() - len);
+                let pattern = &text[start..start + len];
+                let result = assembled_fm.backward_search(pattern.iter());
+                assert_eq!(result, expected_fm.backward_search(pattern.iter()));
+                if let BackwardSearchResult::Complete(interval) = result {
+                    let mut positions = interval.occ(&assembled);
+                    let mut expected_positions = interval.occ(&expected);
+                    positions.sort();
+                    expected_positions.sort();
+                    assert_eq!(positions, expected_positions);
+                    assert!(positions.contains(&start));
+                }
+            }
+        }
+    }
+}
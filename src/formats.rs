@@ -0,0 +1,127 @@
+//! The versions of the index and results formats this build of mtsv reads and writes.
+//!
+//! The loaders and writers use these constants, and every binary prints them as JSON with
+//! `--format-versions`, so workflow wrappers can check what a binary handles without parsing its
+//! help text, and what's printed can't drift from what the code does.
+
+use clap::Arg;
+use index::IndexCapabilities;
+use serde_json;
+use std::env;
+
+/// The index format version `mtsv-build` writes, recorded at the start of the index file (see
+/// `io::Envelope`). Version 1 indices predate the version being recorded.
+pub const INDEX_FORMAT_VERSION: u32 = 10;
+
+/// The first index format version which records the references' accessions (see
+/// `MGIndex::from_file`).
+pub const ACCESSIONS_INDEX_FORMAT_VERSION: u32 = 3;
+
+/// The first index format version which records taxids as 64-bit integers (see
+/// `MGIndex::from_file`).
+pub const WIDE_TAXID_INDEX_FORMAT_VERSION: u32 = 4;
+
+/// The first index format version which records the version of mtsv which wrote it (see
+/// `io::Envelope`).
+pub const BUILT_BY_INDEX_FORMAT_VERSION: u32 = 5;
+
+/// The first index format version which packs the reference sequences two bits per base (see
+/// `packed::PackedSequence`).
+pub const PACKED_INDEX_FORMAT_VERSION: u32 = 6;
+
+/// The first index format version which records the index's alphabet, and holds protein
+/// references a byte per residue (see `index::Alphabet`).
+pub const ALPHABET_INDEX_FORMAT_VERSION: u32 = 7;
+
+/// The first index format version which records the references' soft-masked intervals (see
+/// `index::References::set_respect_softmask`).
+pub const SOFTMASK_INDEX_FORMAT_VERSION: u32 = 8;
+
+/// The first index format version which records the references' original FASTA descriptions, if
+/// they were kept (see `MGIndex::set_descriptions`).
+pub const DESCRIPTIONS_INDEX_FORMAT_VERSION: u32 = 9;
+
+/// The first index format version which ends with a checksum of the serialization, checked as
+/// it's read (see `io::deserialize_checked`).
+pub const CHECKSUM_INDEX_FORMAT_VERSION: u32 = 10;
+
+/// The version of the memory-mapped index layout `mtsv-build --mmap-format` writes, recorded after
+/// `mapped::MAPPED_MAGIC`. Mapped indices are only read at this version.
+pub const MAPPED_INDEX_FORMAT_VERSION: u32 = 1;
+
+/// The oldest index format version this build reads.
+pub const MIN_INDEX_FORMAT_VERSION: u32 = 1;
+
+/// The version of the text results format with a schema line, which the schema line names as
+/// `v2` (see `io::FindingsVersion`).
+pub const FINDINGS_SCHEMA_VERSION: u32 = 2;
+
+/// The version of the binary results format, written after `io::BINARY_MAGIC`.
+pub const BINARY_FINDINGS_VERSION: u64 = 1;
+
+/// The results formats this build reads and writes, by their `--findings-format` names.
+pub const FINDINGS_FORMATS: [&str; 4] = ["legacy", "v1", "v2", "binary"];
+
+/// The format versions as a JSON object, as `--format-versions` prints them:
+///
+/// * `mtsv`: the crate version.
+/// * `index`: the index format versions this build can `read`, and the one it will `write`, and
+///   the version of the `mapped` layout.
+/// * `findings`: the results formats this build can `read` and `write`, and the versions of the
+///   schema line and binary formats.
+/// * `features`: the optional parts of an index this build can use (see `IndexCapabilities`).
+pub fn format_versions() -> serde_json::Value {
+    serde_json::json!({
+        "mtsv": env!("CARGO_PKG_VERSION"),
+        "index": {
+            "read": (MIN_INDEX_FORMAT_VERSION..=INDEX_FORMAT_VERSION).collect::<Vec<_>>(),
+            "write": INDEX_FORMAT_VERSION,
+            "mapped": MAPPED_INDEX_FORMAT_VERSION,
+        },
+        "findings": {
+            "read": FINDINGS_FORMATS,
+            "write": FINDINGS_FORMATS,
+            "schema_version": FINDINGS_SCHEMA_VERSION,
+            "binary_version": BINARY_FINDINGS_VERSION,
+        },
+        "features": IndexCapabilities::ALL.names(),
+    })
+}
+
+/// The `--format-versions` flag, which every binary takes.
+pub fn format_versions_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("FORMAT_VERSIONS")
+        .long("format-versions")
+        .help("Print the versions of the index and results formats this build reads and writes \
+               as JSON, and exit.")
+}
+
+/// Whether `--format-versions` was given. Binaries check this before parsing the rest of the
+/// command line, so that it works without their required arguments.
+pub fn format_versions_requested() -> bool {
+    env::args().skip(1).any(|arg| arg == "--format-versions")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn json_keys() {
+        let versions = format_versions();
+        let keys = |value: &serde_json::Value| {
+            value.as_object().unwrap().keys().cloned().collect::<Vec<_>>()
+        };
+        assert_eq!(keys(&versions), ["features", "findings", "index", "mtsv"]);
+        assert_eq!(keys(&versions["index"]), ["mapped", "read", "write"]);
+        assert_eq!(keys(&versions["findings"]),
+                   ["binary_version", "read", "schema_version", "write"]);
+
+        assert_eq!(versions["index"]["write"], INDEX_FORMAT_VERSION);
+        assert_eq!(versions["index"]["read"].as_array().unwrap().last().unwrap(),
+                   INDEX_FORMAT_VERSION);
+        assert_eq!(versions["index"]["mapped"], MAPPED_INDEX_FORMAT_VERSION);
+        assert_eq!(versions["features"],
+                   serde_json::json!(["prescreen", "compressed", "mapped"]));
+    }
+}
@@ -1,37 +1,497 @@
 //! The core metagenomic index used for queries.
 
-use align::Aligner;
+use align::{is_ambiguity_code, nucleotides_match, nucleotides_match_free_n,
+            nucleotides_match_iupac, nucleotides_match_iupac_free_n, residues_match,
+            residues_match_free_x, Aligner};
 use bio::alphabets;
-use bio::data_structures::bwt::{bwt, less, Less, Occ, BWT};
+use bio::data_structures::bwt::{less, Less, Occ, BWT};
 use bio::data_structures::fmindex::{BackwardSearchResult, FMIndex, FMIndexable, Interval};
-use bio::data_structures::suffix_array::{suffix_array, SuffixArray, SampledSuffixArray};
-
+use bio::data_structures::suffix_array::{suffix_array, SampledSuffixArray};
+use coverage::{ReferenceLength, ReferenceLengths};
+use error::*;
+use bincode::{deserialize_from, serialize_into};
+use fm_build::{assemble, build_bwt, build_from_suffix_array_reader, build_occ,
+               occ_sampling_interval, sample_suffix_array, SuffixSamples};
+use formats::{ACCESSIONS_INDEX_FORMAT_VERSION, ALPHABET_INDEX_FORMAT_VERSION,
+              DESCRIPTIONS_INDEX_FORMAT_VERSION, INDEX_FORMAT_VERSION,
+              PACKED_INDEX_FORMAT_VERSION, SOFTMASK_INDEX_FORMAT_VERSION,
+              WIDE_TAXID_INDEX_FORMAT_VERSION};
+use io::{deserialize_checked, deserialize_versioned, open_versioned, Envelope};
+use prep::{low_complexity_regions, DustParams};
+use prescreen::prescreen_path;
 use serde::{Serialize, Deserialize};
 use itertools::Itertools;
-use ssw::{IDENT_W_PENALTY_NO_N_MATCH, Profile};
+use mapped::{is_mapped_file, MappedFile, MappedWriter};
+use packed::{IndexText, PackedSequence, ReferenceText};
+use score::ConfidenceWeights;
+use summary::Manifest;
+use ssw::{IDENT_W_PENALTY_NO_N_MATCH, IUPAC_IDENT_W_PENALTY, PROTEIN_IDENT_W_PENALTY,
+          PROTEIN_RESIDUES, Profile};
+use std::borrow::Cow;
+use std::cell::OnceCell;
 use std::cmp;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::convert::TryFrom;
 use std::fmt::{Debug};
-use std::hash::{Hash};
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{pipe, BufReader, BufWriter, Read, Write};
+use std::iter;
 use std::num::ParseIntError;
+use std::path::Path;
 use std::str;
+use std::thread;
 use std::u32;
+use suffix_sort::external_suffix_array;
+use util::{Fnv64, Progress};
 
 /// Tuple struct to ensure GI/accession numbers don't get accidentally handled as tax IDs.
 #[derive(Clone, Copy, Eq, PartialEq, PartialOrd, Ord, Hash, Serialize, Deserialize, Debug)]
-pub struct TaxId(pub u32);
+pub struct TaxId(pub u64);
 
 /// Tuple struct to ensure taxonomic IDs don't get accidentally handled as GI/accession numbers.
 #[derive(Clone, Copy, Eq, PartialEq, PartialOrd, Ord, Hash, Serialize, Deserialize, Debug)]
 pub struct Gi(pub u32);
 
+/// The residues an index's reference sequences, and the reads queried against it, are made of.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum Alphabet {
+    /// DNA5: `ACGT` and `N`. Reads are queried on both strands.
+    #[default]
+    Dna,
+    /// The 20 amino acids and `X` (see `ssw::PROTEIN_RESIDUES`). Reads are only queried as they
+    /// are, since proteins have no reverse complement.
+    Protein,
+    /// DNA4: just `ACGT`. The FM index is built over the four bases alone, which makes its
+    /// occurrence table a fifth smaller than DNA5's, so no reference may have an `N`, or anything
+    /// which becomes one (see `MGIndex::with_alphabet`). Reads are DNA, and may still have `N`s,
+    /// but seeds with them are never found.
+    Dna4,
+}
+
+impl Alphabet {
+    /// The alphabet's name, as `FromStr` parses it.
+    pub fn name(self) -> &'static str {
+        match self {
+            Alphabet::Dna => "dna",
+            Alphabet::Protein => "protein",
+            Alphabet::Dna4 => "dna4",
+        }
+    }
+
+    /// Whether the alphabet is DNA, with or without `N`, so that reads are DNA.
+    pub fn is_dna(self) -> bool {
+        self != Alphabet::Protein
+    }
+
+    /// The residue which stands in for any other: `N` for DNA and `X` for protein. It never
+    /// matches when aligning, unless it's free (see `QueryParams::max_free_ns`).
+    pub fn wildcard(self) -> u8 {
+        match self {
+            Alphabet::Dna | Alphabet::Dna4 => b'N',
+            Alphabet::Protein => b'X',
+        }
+    }
+
+    /// Convert a residue to the alphabet: lowercase residues are capitalized, RNA's `U` becomes
+    /// `T` for DNA, and anything else outside the alphabet becomes the wildcard.
+    pub fn normalize(self, residue: u8) -> u8 {
+        self.normalize_with(residue, AmbiguityPolicy::N)
+    }
+
+    /// Like `normalize`, but DNA's IUPAC ambiguity codes are kept (capitalized) unless `ambiguity`
+    /// is `AmbiguityPolicy::N`.
+    pub fn normalize_with(self, residue: u8, ambiguity: AmbiguityPolicy) -> u8 {
+        let residue = residue.to_ascii_uppercase();
+        let known = match self {
+            Alphabet::Dna | Alphabet::Dna4 if residue == b'U' => return b'T',
+            Alphabet::Dna | Alphabet::Dna4 => {
+                b"ACGTN".contains(&residue) ||
+                (ambiguity != AmbiguityPolicy::N && is_ambiguity_code(residue))
+            },
+            Alphabet::Protein => PROTEIN_RESIDUES.contains(&residue),
+        };
+        if known { residue } else { self.wildcard() }
+    }
+
+    /// Whether a normalized residue is a DNA ambiguity code, which only `AmbiguityPolicy::Expand`
+    /// and `AmbiguityPolicy::Reject` keep. Protein residues never are.
+    pub fn is_ambiguous(self, residue: u8) -> bool {
+        self.is_dna() && is_ambiguity_code(residue)
+    }
+
+    /// The symbols of the alphabet, for the FM index.
+    fn symbols(self) -> alphabets::Alphabet {
+        match self {
+            Alphabet::Dna => alphabets::dna::n_alphabet(),
+            Alphabet::Protein => alphabets::Alphabet::new(&PROTEIN_RESIDUES[..]),
+            Alphabet::Dna4 => alphabets::dna::alphabet(),
+        }
+    }
+
+    /// Whether a k-mer is non-empty and only uses `ACGTN`, or `ACGT` for DNA4, whose FM index
+    /// has no `N`, since the `Occ` lookups require symbols the FM index was built over.
+    fn is_searchable(self, kmer: &[u8]) -> bool {
+        let symbols: &[u8] = if self == Alphabet::Dna4 { b"ACGT" } else { b"ACGTN" };
+        !kmer.is_empty() && kmer.iter().all(|b| symbols.contains(b))
+    }
+
+    /// Whether a read's residue matches a reference's when aligning, with or without a free
+    /// wildcard in the read, and with DNA ambiguity codes matching their bases if `expand`.
+    fn matcher(self, free_wildcard: bool, expand: bool) -> fn(u8, u8) -> bool {
+        match (self.is_dna(), free_wildcard, expand) {
+            (true, false, false) => nucleotides_match,
+            (true, true, false) => nucleotides_match_free_n,
+            (true, false, true) => nucleotides_match_iupac,
+            (true, true, true) => nucleotides_match_iupac_free_n,
+            (false, false, _) => residues_match,
+            (false, true, _) => residues_match_free_x,
+        }
+    }
+}
+
+impl str::FromStr for Alphabet {
+    type Err = MtsvError;
+
+    fn from_str(s: &str) -> MtsvResult<Self> {
+        match s {
+            "dna" => Ok(Alphabet::Dna),
+            "protein" => Ok(Alphabet::Protein),
+            "dna4" => Ok(Alphabet::Dna4),
+            _ => Err(MtsvError::InvalidParameter(format!("unknown alphabet {:?}", s))),
+        }
+    }
+}
+
+/// How IUPAC ambiguity codes in DNA, such as `R` for `A` or `G`, are handled in references (see
+/// `References::set_ambiguity`) and reads (see `QueryParams::ambiguity`). Protein residues are
+/// unaffected.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum AmbiguityPolicy {
+    /// Ambiguity codes become `N`, which never matches.
+    #[default]
+    N,
+    /// Ambiguity codes are kept, and match any of their bases when aligning. Seeds still can't
+    /// match them, since the FM index only holds `ACGTN`, so they're searched for as `N`s.
+    Expand,
+    /// Ambiguity codes are an error in references, and reads with them are skipped.
+    Reject,
+}
+
+impl AmbiguityPolicy {
+    /// The policy's name, as `FromStr` parses it.
+    pub fn name(self) -> &'static str {
+        match self {
+            AmbiguityPolicy::N => "n",
+            AmbiguityPolicy::Expand => "expand",
+            AmbiguityPolicy::Reject => "reject",
+        }
+    }
+}
+
+impl str::FromStr for AmbiguityPolicy {
+    type Err = MtsvError;
+
+    fn from_str(s: &str) -> MtsvResult<Self> {
+        match s {
+            "n" => Ok(AmbiguityPolicy::N),
+            "expand" => Ok(AmbiguityPolicy::Expand),
+            "reject" => Ok(AmbiguityPolicy::Reject),
+            _ => Err(MtsvError::InvalidParameter(format!("unknown ambiguity policy {:?}", s))),
+        }
+    }
+}
+
+/// How an index's reference sequences are divided into bins (see `References::set_bin_mode`).
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum BinMode {
+    /// Each reference sequence is its own bin, with its GI.
+    #[default]
+    PerGi,
+    /// All of a taxid's reference sequences are joined into a single bin with GI 0, separated by
+    /// `spacer_len` wildcards. Taxa with thousands of contigs then take a single bin, but hits
+    /// can't say which of them a read aligned to.
+    ///
+    /// Reads can't seed in the spacers, and the wildcards never match when aligning, so as long
+    /// as the spacers are at least as long as the longest read, no read aligns across two
+    /// sequences.
+    PerTaxon {
+        /// The number of wildcards between a taxid's sequences.
+        spacer_len: usize,
+    },
+}
+
 
 /// Records a hit and the edit distance. 
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub struct Hit {
     /// The taxid of the hit (TaxId)
     pub tax_id: TaxId,
     /// Edit distance of the alignment (u32)
-    pub edit: u32
+    pub edit: u32,
+    /// Number of seeds which fell in the aligned region. Only recorded in v2 results files (see
+    /// `io::FindingsVersion`), so zero for hits read back from a v1 file.
+    pub seeds: u32,
+    /// Number of seeds searched on the strand the hit was found on. Also zero for hits read back
+    /// from a v1 results file.
+    pub seeds_searched: u32,
+    /// Number of bases at the start of the read which hung off the start of the reference, and
+    /// weren't aligned (see `QueryParams::max_overhang`).
+    pub clipped_start: u32,
+    /// Number of bases at the end of the read which hung off the end of the reference.
+    pub clipped_end: u32,
+    /// GI of the reference sequence the read was aligned to, which resolves to its accession in
+    /// indices built with one (see `MGIndex::accession`). Results files don't record it, so it's
+    /// zero for hits read back from them.
+    pub gi: Gi,
+}
+
+impl Hit {
+    /// Whether this hit should be kept over `other` as a read's hit to the same taxid.
+    ///
+    /// Fewer edits wins, then more seeds, then fewer clipped bases, then any other difference,
+    /// so the choice never depends on the order hits were found in (strand, index, or thread).
+    pub fn is_better_than(&self, other: &Hit) -> bool {
+        self.rank() < other.rank()
+    }
+
+    fn rank(&self) -> (u32, cmp::Reverse<u32>, u32, u32, cmp::Reverse<u32>, u64, u32) {
+        (self.edit,
+         cmp::Reverse(self.seeds),
+         self.clipped_start + self.clipped_end,
+         self.clipped_start,
+         cmp::Reverse(self.seeds_searched),
+         self.tax_id.0,
+         self.gi.0)
+    }
+}
+
+/// Tuning parameters for a single query against the index.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct QueryParams {
+    /// Maximum proportion of the read length allowed as edits.
+    pub edit_freq: f64,
+    /// Length of the exact-match seeds pulled from the read.
+    pub seed_length: usize,
+    /// Interval between the starts of successive seeds.
+    pub seed_gap: usize,
+    /// Proportion of searched seeds a candidate region needs before it is aligned.
+    pub min_seeds_percent: f64,
+    /// Number of seeds a candidate region needs before it is aligned, however few seeds were
+    /// searched. The larger of this and `min_seeds_percent` applies.
+    pub min_seeds_absolute: usize,
+    /// Seeds with more than this many hits in the index are skipped.
+    pub max_hits: usize,
+    /// Seeds with more than this many hits (but no more than `max_hits`) double the interval to
+    /// the next seed, and every seed after it, so repetitive reads search fewer seeds.
+    pub tune_max_hits: usize,
+    /// Maximum number of seed hits recorded for a single bin, beyond which further hits in that
+    /// bin are dropped.
+    pub max_hits_per_bin: usize,
+    /// Seeds of which only a suffix of at least this length is found in the index are used from
+    /// the start of that suffix. Otherwise partially matching seeds are skipped.
+    pub min_partial_seed: Option<usize>,
+    /// Weights of the components of a hit's confidence score, see `score::confidence`.
+    pub confidence_weights: ConfidenceWeights,
+    /// Proportion of the read which may hang off either end of a reference sequence, if any.
+    /// Overhanging bases are clipped rather than counted as edits, and the edit distance is
+    /// checked against the rest of the read.
+    pub max_overhang: Option<f64>,
+    /// Seeds with more than this many Ns (Xs for protein indices) are skipped without being
+    /// searched. Ns never count as matches when aligning, so seeds of them only find the
+    /// references' own runs of Ns.
+    pub max_seed_ns: usize,
+    /// Most seed hits to record for one query. Seeds with between `tune_max_hits` and `max_hits`
+    /// hits are still located in full, so a few of them can record millions of hits in
+    /// repetitive databases. Once the next seed's hits would go over this, it and the rest of the
+    /// query's seeds are skipped.
+    pub max_seed_hits_per_read: usize,
+    /// Let up to this many Ns in a read match any reference base without counting as edits, if
+    /// set, so that e.g. an edit rate of zero still finds reads which are exact apart from their
+    /// Ns. Reads with more Ns than this are aligned as usual, where every N is a mismatch. Seeds
+    /// are unaffected, see `max_seed_ns`. For protein indices this applies to Xs.
+    pub max_free_ns: Option<usize>,
+    /// How the reads' ambiguity codes are aligned: with `AmbiguityPolicy::Expand` they match any
+    /// of their bases, and the references' codes match reads' bases too. Otherwise they never
+    /// match, as reads normally have them replaced by N beforehand.
+    pub ambiguity: AmbiguityPolicy,
+    /// Drop the hits of seeds which land in the references' runs of Ns, such as scaffold gaps,
+    /// rather than turning them into candidates which are mostly Ns and never align. Ns only
+    /// match Ns in the FM index, so these are the seeds with an N in the part of them which was
+    /// found, which `max_seed_ns` lets through. For protein indices this applies to Xs.
+    pub skip_n_seed_hits: bool,
+}
+
+impl QueryParams {
+    /// Number of seeds a candidate region needs before it is aligned, when `n_seeds` seeds were
+    /// searched: the larger of `min_seeds_percent` of them (rounded down) and
+    /// `min_seeds_absolute`.
+    pub fn min_seeds(&self, n_seeds: usize) -> usize {
+        let scaled = (n_seeds as f64 * self.min_seeds_percent).floor() as usize;
+        cmp::max(scaled, self.min_seeds_absolute)
+    }
+
+    /// The `tune_max_hits` used when only `max_hits` is given: a hundredth of it, and at least 1.
+    pub fn default_tune_max_hits(max_hits: usize) -> usize {
+        cmp::max(max_hits / 100, 1)
+    }
+
+    /// Check that the seeds and hit limits can be used: seeds must be at least one base long and
+    /// at least one base apart, and `tune_max_hits` can't be more than `max_hits`, since seeds
+    /// with more hits than that are skipped before they can tune the interval.
+    pub fn validate(&self) -> MtsvResult<()> {
+        if self.seed_length == 0 {
+            return Err(MtsvError::InvalidParameter("seed size must be at least 1".to_string()));
+        }
+        if self.seed_gap == 0 {
+            return Err(MtsvError::InvalidParameter("seed interval must be at least 1"
+                .to_string()));
+        }
+        if self.tune_max_hits > self.max_hits {
+            return Err(MtsvError::InvalidParameter(format!("tune max hits ({}) must not be more \
+                                                            than max hits ({})",
+                                                           self.tune_max_hits,
+                                                           self.max_hits)));
+        }
+        Ok(())
+    }
+}
+
+impl Default for QueryParams {
+    fn default() -> Self {
+        QueryParams {
+            edit_freq: 0.13,
+            seed_length: 18,
+            seed_gap: 15,
+            min_seeds_percent: 0.015,
+            min_seeds_absolute: 1,
+            max_hits: 20000,
+            tune_max_hits: QueryParams::default_tune_max_hits(20000),
+            max_hits_per_bin: 1000,
+            min_partial_seed: None,
+            confidence_weights: ConfidenceWeights::default(),
+            max_overhang: None,
+            max_seed_ns: 0,
+            max_seed_hits_per_read: 1_000_000,
+            max_free_ns: None,
+            ambiguity: AmbiguityPolicy::N,
+            skip_n_seed_hits: true,
+        }
+    }
+}
+
+/// Counters describing the work performed by one or more queries.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct QueryStats {
+    /// Number of seeds which were searched for in the FM index.
+    pub seeds_searched: usize,
+    /// Number of seed hits recorded for coalescing into candidates.
+    pub seed_hits: usize,
+    /// Number of seed hits dropped because their bin had already reached `max_hits_per_bin`.
+    pub seed_hits_capped: usize,
+    /// Number of bins which reached `max_hits_per_bin`.
+    pub capped_bins: usize,
+    /// Number of seeds of which only a suffix was found in the FM index.
+    pub partial_seeds: usize,
+    /// Number of seeds skipped for having more than `QueryParams::max_seed_ns` Ns.
+    pub seeds_with_ns: usize,
+    /// Number of seed hits dropped for landing in the references' Ns, see
+    /// `QueryParams::skip_n_seed_hits`.
+    #[serde(default)]
+    pub seed_hits_in_ns: usize,
+    /// Number of seeds skipped because the query had reached
+    /// `QueryParams::max_seed_hits_per_read`.
+    pub seeds_over_hit_limit: usize,
+    /// Number of queries which reached `QueryParams::max_seed_hits_per_read`.
+    pub hit_limited_queries: usize,
+    /// Number of partially matching seeds used anyway, see `QueryParams::min_partial_seed`.
+    pub partial_seeds_used: usize,
+    /// Number of read strands which weren't queried because none of their seeds were in the
+    /// prescreen (see `prescreen::Prescreen`).
+    pub prescreened: usize,
+    /// Number of reverse strands which weren't queried because the forward strand had enough
+    /// hits (see `binner::BinnerConfig::skip_reverse_after`).
+    pub reverse_strands_skipped: usize,
+    /// Number of candidate regions whose edit distance to the query was computed.
+    pub candidates_aligned: usize,
+}
+
+impl QueryStats {
+    /// Add the counters from another set of stats to this one.
+    pub fn add(&mut self, other: &QueryStats) {
+        self.seeds_searched += other.seeds_searched;
+        self.seed_hits += other.seed_hits;
+        self.seed_hits_capped += other.seed_hits_capped;
+        self.capped_bins += other.capped_bins;
+        self.partial_seeds += other.partial_seeds;
+        self.seeds_with_ns += other.seeds_with_ns;
+        self.seed_hits_in_ns += other.seed_hits_in_ns;
+        self.seeds_over_hit_limit += other.seeds_over_hit_limit;
+        self.hit_limited_queries += other.hit_limited_queries;
+        self.partial_seeds_used += other.partial_seeds_used;
+        self.prescreened += other.prescreened;
+        self.reverse_strands_skipped += other.reverse_strands_skipped;
+        self.candidates_aligned += other.candidates_aligned;
+    }
+}
+
+/// One strand of a normalized query read, prepared for querying against one or more indices.
+///
+/// The alignment profile is only built once a candidate region needs scoring, so reads without
+/// any candidates never pay for it, and it's kept for any later queries of the same strand.
+pub struct QueryStrand<'a> {
+    sequence: &'a [u8],
+    alphabet: Alphabet,
+    ambiguity: AmbiguityPolicy,
+    profile: OnceCell<Profile<'a>>,
+}
+
+impl<'a> QueryStrand<'a> {
+    /// Prepare a DNA strand for querying.
+    pub fn new(sequence: &'a [u8]) -> Self {
+        QueryStrand::with_alphabet(sequence, Alphabet::Dna)
+    }
+
+    /// Prepare a strand of `alphabet` for querying, against indices of the same alphabet.
+    pub fn with_alphabet(sequence: &'a [u8], alphabet: Alphabet) -> Self {
+        QueryStrand {
+            sequence,
+            alphabet,
+            ambiguity: AmbiguityPolicy::N,
+            profile: OnceCell::new(),
+        }
+    }
+
+    /// Score the strand's ambiguity codes as `ambiguity` says, which should be that of the
+    /// `QueryParams` it's queried with: with `AmbiguityPolicy::Expand` they score as matching any
+    /// of their bases.
+    pub fn with_ambiguity(self, ambiguity: AmbiguityPolicy) -> Self {
+        QueryStrand { ambiguity, ..self }
+    }
+
+    /// The strand's sequence.
+    pub fn sequence(&self) -> &'a [u8] {
+        self.sequence
+    }
+
+    /// The strand's sequence as its seeds are searched for, with any ambiguity codes as Ns, since
+    /// the FM index only holds `ACGTN`.
+    pub fn seed_sequence(&self) -> Cow<'a, [u8]> {
+        if self.sequence.iter().any(|&b| self.alphabet.is_ambiguous(b)) {
+            Cow::Owned(self.sequence.iter().map(|&b| self.alphabet.normalize(b)).collect())
+        } else {
+            Cow::Borrowed(self.sequence)
+        }
+    }
+
+    /// The strand's Smith-Waterman profile, built on first use.
+    pub fn profile(&self) -> &Profile<'a> {
+        self.profile.get_or_init(|| match (self.alphabet.is_dna(), self.ambiguity) {
+            (true, AmbiguityPolicy::Expand) => Profile::iupac(self.sequence, &IUPAC_IDENT_W_PENALTY),
+            (true, _) => Profile::new(self.sequence, &IDENT_W_PENALTY_NO_N_MATCH),
+            (false, _) => Profile::protein(self.sequence, &PROTEIN_IDENT_W_PENALTY),
+        })
+    }
 }
 
 /// Metadata about a region of the index, corresponding to a single sequence/GI/accession in the
@@ -48,18 +508,156 @@ struct Bin {
     end: usize,
 }
 
+/// The region of an index's concatenated reference sequences holding a single reference sequence
+/// (see `MGIndex::bins`).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct BinInfo {
+    /// The reference sequence's GI.
+    pub gi: Gi,
+    /// The reference sequence's taxid.
+    pub tax_id: TaxId,
+    /// Absolute offset of the reference's first base in the concatenated sequences.
+    pub start: usize,
+    /// Absolute offset just past the reference's last base.
+    pub end: usize,
+}
+
+impl BinInfo {
+    fn from_bin(bin: &Bin) -> Self {
+        BinInfo {
+            gi: bin.gi,
+            tax_id: bin.tax_id,
+            start: bin.start,
+            end: bin.end,
+        }
+    }
+}
+
+/// Summary statistics of an index (see `MGIndex::stats`).
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct IndexStats {
+    /// Number of distinct taxids.
+    pub taxa: usize,
+    /// Number of reference sequences (bins).
+    pub references: usize,
+    /// Number of bases in the concatenated reference sequences.
+    pub bases: usize,
+    /// Length of the shortest reference sequence, 0 if there are none.
+    pub min_reference_length: usize,
+    /// Length of the longest reference sequence, 0 if there are none.
+    pub max_reference_length: usize,
+    /// Mean length of the reference sequences, 0 if there are none.
+    pub mean_reference_length: f64,
+    /// Sampling interval of the BWT occurrence array.
+    pub sample_interval: u32,
+    /// Sampling rate of the suffix array.
+    pub suffix_sample: usize,
+}
 
 /// Metagenomic index comprised of reference sequences concatenated together, an FM Index over the
 /// concatenated sequences, and the metadata Bins to allow mapping absolute sequence offsets back
 /// to GI/accession numbers and taxonomic IDs.
 #[derive(Serialize, Deserialize)]
 pub struct MGIndex {
-    /// Concatenated reference sequences
-    sequences: Sequence,
+    /// Concatenated reference sequences, packed two bits per base unless they're protein
+    sequences: IndexText,
     /// Meta data for individual reference sequences (Bin)
     bins: Vec<Bin>,
     /// Sampled suffix array used to build FM-index 
-    pub suffix_array: SampledSuffixArray<BWT, Less, Occ>,
+    suffix_array: SampledSuffixArray<BWT, Less, Occ>,
+    /// Whether the GIs were assigned while parsing rather than read from the FASTA headers
+    synthetic_gis: bool,
+    /// Hash over the ordered (GI, taxid, sequence) contents of the bins
+    fingerprint: u64,
+    /// The references' accessions by GI, for indices built from accession headers
+    accessions: HeaderMap,
+    /// The alphabet of the reference sequences
+    alphabet: Alphabet,
+    /// Sorted, non-overlapping [start, end) intervals of soft-masked bases, which aren't seeded
+    soft_masked: Vec<(usize, usize)>,
+    /// The references' original FASTA descriptions by GI, for indices built keeping them
+    descriptions: HeaderMap,
+    /// The sampling interval of the occurrence table, which rust-bio keeps to itself: set as the
+    /// index is built or loaded rather than written with it
+    #[serde(skip)]
+    sample_interval: u32,
+}
+
+/// The layout of `MGIndex` in index format versions from `formats::PACKED_INDEX_FORMAT_VERSION`
+/// up to `formats::DESCRIPTIONS_INDEX_FORMAT_VERSION`, up to its accessions. Before
+/// `formats::ALPHABET_INDEX_FORMAT_VERSION` its sequences were a `PackedSequence` and it was all
+/// DNA, from then on they were an `IndexText` and it was followed by the alphabet, and from
+/// `formats::SOFTMASK_INDEX_FORMAT_VERSION` on by the soft-masked intervals too.
+#[derive(Deserialize)]
+#[cfg_attr(test, derive(Serialize))]
+struct PackedIndex<S> {
+    sequences: S,
+    bins: Vec<Bin>,
+    suffix_array: SampledSuffixArray<BWT, Less, Occ>,
+    synthetic_gis: bool,
+    fingerprint: u64,
+    accessions: HeaderMap,
+}
+
+/// The layout of a `Bin` in index format versions before
+/// `formats::WIDE_TAXID_INDEX_FORMAT_VERSION`, with a 32-bit taxid.
+#[derive(Deserialize)]
+#[cfg_attr(test, derive(Serialize))]
+struct NarrowBin {
+    gi: Gi,
+    tax_id: u32,
+    start: usize,
+    end: usize,
+}
+
+/// The layout of `MGIndex` in index format versions before
+/// `formats::PACKED_INDEX_FORMAT_VERSION`, with a byte per base, up to its fingerprint. Before
+/// `formats::WIDE_TAXID_INDEX_FORMAT_VERSION` its bins were `NarrowBin`s. From
+/// `formats::ACCESSIONS_INDEX_FORMAT_VERSION` on it was followed by the accessions.
+#[derive(Deserialize)]
+#[cfg_attr(test, derive(Serialize))]
+struct UnpackedIndex<B> {
+    sequences: Sequence,
+    bins: Vec<B>,
+    suffix_array: SampledSuffixArray<BWT, Less, Occ>,
+    synthetic_gis: bool,
+    fingerprint: u64,
+}
+
+impl<S> PackedIndex<S> {
+    fn map_sequences<T, F: FnOnce(S) -> T>(self, f: F) -> PackedIndex<T> {
+        PackedIndex {
+            sequences: f(self.sequences),
+            bins: self.bins,
+            suffix_array: self.suffix_array,
+            synthetic_gis: self.synthetic_gis,
+            fingerprint: self.fingerprint,
+            accessions: self.accessions,
+        }
+    }
+}
+
+impl NarrowBin {
+    fn widen(self) -> Bin {
+        Bin {
+            gi: self.gi,
+            tax_id: TaxId(self.tax_id as u64),
+            start: self.start,
+            end: self.end,
+        }
+    }
+}
+
+impl UnpackedIndex<NarrowBin> {
+    fn widen(self) -> UnpackedIndex<Bin> {
+        UnpackedIndex {
+            sequences: self.sequences,
+            bins: self.bins.into_iter().map(NarrowBin::widen).collect(),
+            suffix_array: self.suffix_array,
+            synthetic_gis: self.synthetic_gis,
+            fingerprint: self.fingerprint,
+        }
+    }
 }
 
 // impl Debug for MGIndex {
@@ -76,7 +674,7 @@ impl str::FromStr for TaxId {
     type Err = ParseIntError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match u32::from_str(s) {
+        match u64::from_str(s) {
             Ok(n) => Ok(TaxId(n)),
             Err(why) => Err(why),
         }
@@ -100,6 +698,502 @@ pub type Sequence = Vec<u8>;
 /// Sequence Database
 pub type Database = BTreeMap<TaxId, Vec<(Gi, Sequence)>>;
 
+/// Text kept from the reference sequences' FASTA headers by GI, such as their accessions.
+pub type HeaderMap = BTreeMap<Gi, String>;
+
+/// Reference sequences concatenated as they're added, recording where each one is, to build an
+/// index from (see `MGIndex::from_references`) without holding every reference in a `Database`.
+#[derive(Default)]
+pub struct References {
+    sequences: Sequence,
+    bins: Vec<Bin>,
+    alphabet: Alphabet,
+    respect_softmask: bool,
+    /// The DUST parameters to mask low-complexity regions of DNA references with, if masking them
+    low_complexity: Option<DustParams>,
+    /// The number of bases in low-complexity regions
+    low_complexity_bases: usize,
+    /// [start, end) intervals of soft-masked bases, in the order they were added
+    soft_masked: Vec<(usize, usize)>,
+    ambiguity: AmbiguityPolicy,
+    bin_mode: BinMode,
+}
+
+impl References {
+    /// No DNA references yet.
+    pub fn new() -> Self {
+        References::default()
+    }
+
+    /// No references of `alphabet` yet.
+    pub fn with_alphabet(alphabet: Alphabet) -> Self {
+        References { alphabet, ..References::default() }
+    }
+
+    /// Add a reference sequence, converting it to the references' alphabet (see
+    /// `Alphabet::normalize_with`): for DNA, lowercase bases are capitalized, `U` becomes `T`,
+    /// and anything other than `ACGTN` becomes `N`, except for ambiguity codes if the references
+    /// keep them (see `set_ambiguity`).
+    ///
+    /// If the references respect soft-masking (see `set_respect_softmask`), runs of lowercase
+    /// bases are recorded as masked, and if they mask low-complexity regions (see
+    /// `set_mask_low_complexity`), so are those of DNA references.
+    pub fn push(&mut self, gi: Gi, tax_id: TaxId, sequence: &[u8]) {
+        let start = self.sequences.len();
+        let mut masked = Vec::new();
+        if self.respect_softmask {
+            let mut bases = sequence.iter().enumerate();
+            while let Some((first, _)) = bases.find(|&(_, b)| b.is_ascii_lowercase()) {
+                let end = bases.find(|&(_, b)| !b.is_ascii_lowercase())
+                    .map_or(sequence.len(), |(i, _)| i);
+                masked.push((first, end));
+            }
+        }
+        let (alphabet, ambiguity) = (self.alphabet, self.ambiguity);
+        let bases = sequence.iter().map(|&b| alphabet.normalize_with(b, ambiguity));
+        self.push_bases(gi, tax_id, bases);
+
+        if let Some(ref dust) = self.low_complexity.filter(|_| self.alphabet.is_dna()) {
+            let regions = low_complexity_regions(&self.sequences[start..], dust);
+            self.low_complexity_bases += regions.iter().map(|&(s, e)| e - s).sum::<usize>();
+            masked.extend(regions);
+        }
+        // the intervals are merged within the reference, but not with the previous one's
+        masked.sort_unstable();
+        let mut merged: Vec<(usize, usize)> = Vec::with_capacity(masked.len());
+        for (s, e) in masked {
+            match merged.last_mut() {
+                Some(last) if last.1 >= s => last.1 = cmp::max(last.1, e),
+                _ => merged.push((s, e)),
+            }
+        }
+        self.soft_masked.extend(merged.into_iter().map(|(s, e)| (start + s, start + e)));
+    }
+
+    /// Add a reference sequence from an index, whose bases are already converted, with the
+    /// intervals of it in `soft_masked` (relative to its start) masked, whether or not the
+    /// references respect soft-masking, as when rebuilding an index from another.
+    fn push_masked<I>(&mut self, gi: Gi, tax_id: TaxId, sequence: &[u8], soft_masked: I)
+        where I: IntoIterator<Item = (usize, usize)>
+    {
+        let start = self.sequences.len();
+        self.soft_masked.extend(soft_masked.into_iter().map(|(s, e)| (start + s, start + e)));
+        self.push_bases(gi, tax_id, sequence.iter().cloned());
+    }
+
+    /// Add a reference sequence's converted bases and bin.
+    fn push_bases<I: IntoIterator<Item = u8>>(&mut self, gi: Gi, tax_id: TaxId, bases: I) {
+        let start = self.sequences.len();
+        self.sequences.extend(bases);
+        self.bins.push(Bin {
+            gi,
+            tax_id,
+            start,
+            end: self.sequences.len(),
+        });
+    }
+
+    /// The number of references.
+    pub fn len(&self) -> usize {
+        self.bins.len()
+    }
+
+    /// Whether there are no references.
+    pub fn is_empty(&self) -> bool {
+        self.bins.is_empty()
+    }
+
+    /// The number of bases in all the references.
+    pub fn bases(&self) -> usize {
+        self.sequences.len()
+    }
+
+    /// The alphabet the references are converted to.
+    pub fn alphabet(&self) -> Alphabet {
+        self.alphabet
+    }
+
+    /// Whether references pushed from now on have their lowercase (soft-masked) bases excluded
+    /// from seeding. The index still aligns across them, since it keeps the bases themselves
+    /// (see `MGIndex::from_references`). Off by default, when lowercase is only capitalized.
+    pub fn set_respect_softmask(&mut self, respect: bool) {
+        self.respect_softmask = respect;
+    }
+
+    /// How references pushed from now on treat DNA ambiguity codes: `AmbiguityPolicy::N`, the
+    /// default, replaces them with N, and the other policies keep them. The FM index is built
+    /// with them as N either way, so seeds never match them (see `MGIndex::from_references`),
+    /// but an index built from references which keep them aligns reads against the codes
+    /// themselves. Rejecting references with them is up to the caller.
+    pub fn set_ambiguity(&mut self, ambiguity: AmbiguityPolicy) {
+        self.ambiguity = ambiguity;
+    }
+
+    /// Whether references pushed from now on have their low-complexity regions, as found by
+    /// `prep::low_complexity_regions` with these DUST parameters, excluded from seeding like
+    /// soft-masked bases, if set. Only DNA references are masked. None by default.
+    pub fn set_mask_low_complexity(&mut self, params: Option<DustParams>) {
+        self.low_complexity = params;
+    }
+
+    /// How the references are divided into bins when they're concatenated: `BinMode::PerGi`,
+    /// the default, or joined by taxid.
+    pub fn set_bin_mode(&mut self, bin_mode: BinMode) {
+        self.bin_mode = bin_mode;
+    }
+
+    /// The number of bases in the low-complexity regions of the references (see
+    /// `set_mask_low_complexity`), some of which may be soft-masked too.
+    pub fn low_complexity_bases(&self) -> usize {
+        self.low_complexity_bases
+    }
+
+    /// The number of soft-masked bases in the references, including low-complexity regions.
+    pub fn soft_masked_bases(&self) -> usize {
+        self.soft_masked.iter().map(|&(start, end)| end - start).sum()
+    }
+
+    /// The concatenated sequences, their bins and their soft-masked intervals in index order:
+    /// by taxid, then GI, then sequence. The order of a taxon's sequences follows the input file,
+    /// so this fixes it for a reproducible index. With `BinMode::PerTaxon`, each taxid's
+    /// sequences are then joined into one bin. References which were added in order and are
+    /// binned by GI aren't copied.
+    fn into_index_order(self) -> (Sequence, Vec<Bin>, Vec<(usize, usize)>) {
+        let References { sequences, mut bins, soft_masked, alphabet, bin_mode, .. } = self;
+        let key = |bin: &Bin| (bin.tax_id, bin.gi, &sequences[bin.start..bin.end]);
+        let in_order = bins.windows(2).all(|pair| key(&pair[0]) <= key(&pair[1]));
+        let spacer_len = match bin_mode {
+            BinMode::PerGi if in_order => return (sequences, bins, soft_masked),
+            BinMode::PerGi => None,
+            BinMode::PerTaxon { spacer_len } => Some(spacer_len),
+        };
+
+        if !in_order {
+            bins.sort_by(|a, b| key(a).cmp(&key(b)));
+        }
+        let mut ordered = Vec::with_capacity(sequences.len());
+        let mut ordered_bins: Vec<Bin> = Vec::with_capacity(bins.len());
+        let mut ordered_masks = Vec::with_capacity(soft_masked.len());
+        for bin in &bins {
+            match (ordered_bins.last(), spacer_len) {
+                (Some(last), Some(spacer_len)) if last.tax_id == bin.tax_id => {
+                    ordered.extend(iter::repeat_n(alphabet.wildcard(), spacer_len));
+                },
+                _ => {
+                    ordered_bins.push(Bin {
+                        gi: if spacer_len.is_some() { Gi(0) } else { bin.gi },
+                        tax_id: bin.tax_id,
+                        start: ordered.len(),
+                        end: ordered.len(),
+                    })
+                },
+            }
+            let start = ordered.len();
+            ordered.extend_from_slice(&sequences[bin.start..bin.end]);
+            // a bin's intervals are within it, and were added in order
+            let first = soft_masked.partition_point(|&(s, _)| s < bin.start);
+            let last = soft_masked.partition_point(|&(s, _)| s < bin.end);
+            ordered_masks.extend(soft_masked[first..last]
+                .iter()
+                .map(|&(s, e)| (s - bin.start + start, e - bin.start + start)));
+            if let Some(last) = ordered_bins.last_mut() {
+                last.end = ordered.len();
+            }
+        }
+        (ordered, ordered_bins, ordered_masks)
+    }
+
+    /// Concatenate the references in index order (see `into_index_order`), fingerprint them and
+    /// add the sentinel: the first stage of `MGIndex::from_references`.
+    pub fn concatenate(self) -> ConcatenatedReferences {
+        let alphabet = self.alphabet;
+        let (mut seq, bins, soft_masked) = self.into_index_order();
+        // an empty bin shares its start with the next one, which confuses the bin lookups
+        debug_assert!(bins.iter().all(|b| b.start < b.end),
+                      "References with no sequence can't be indexed");
+        // anything else would have been normalized to the wildcard
+        debug_assert!(!seq.contains(&b'$'), "Only the sentinel can be a '$'");
+        let fingerprint = build_fingerprint(&seq, &bins);
+        info!("Index fingerprint: {:016x}", fingerprint);
+
+        // suffix array requires a lexicographically smallest sentinel
+        seq.push(b'$');
+        seq.shrink_to_fit();
+
+        info!("All reference sequences concatenated and boundaries recorded.");
+        ConcatenatedReferences { seq, bins, soft_masked, alphabet, fingerprint }
+    }
+}
+
+/// References concatenated in index order, with the sentinel the suffix array needs: the first
+/// stage of `MGIndex::from_references`. Builds save it to resume from (see
+/// `builder::Checkpoint`).
+#[derive(Serialize, Deserialize)]
+pub struct ConcatenatedReferences {
+    seq: Sequence,
+    bins: Vec<Bin>,
+    soft_masked: Vec<(usize, usize)>,
+    alphabet: Alphabet,
+    fingerprint: u64,
+}
+
+impl ConcatenatedReferences {
+    /// The number of reference sequences.
+    pub fn len(&self) -> usize {
+        self.bins.len()
+    }
+
+    /// Whether there are no reference sequences.
+    pub fn is_empty(&self) -> bool {
+        self.bins.is_empty()
+    }
+
+    /// Build the suffix array of the references, keeping its BWT and every `suffix_sample`th
+    /// row: the second stage of `MGIndex::from_references`, and the slow one. The suffix array
+    /// itself is dropped once they're built.
+    pub fn sort(self,
+                suffix_sample: usize,
+                threads: usize,
+                external_sa: Option<&str>)
+                -> MtsvResult<SortedReferences> {
+        let alphabet = self.alphabet;
+        if !alphabet.is_dna() && external_sa.is_some() {
+            return Err(MtsvError::InvalidParameter(format!("the suffix array of a {} index \
+                                                            can't be sorted externally",
+                                                           alphabet.name())));
+        }
+
+        // the FM index is built over a copy with the masked bases and any ambiguity codes
+        // replaced by the wildcard, so seeds are never found in them, but the bases are kept to
+        // align against
+        let seq = &self.seq;
+        let ambiguous = seq.iter().filter(|&&b| alphabet.is_ambiguous(b)).count();
+        let masked_seq = if self.soft_masked.is_empty() && ambiguous == 0 {
+            None
+        } else {
+            info!("Excluding {} soft-masked bases and {} ambiguity codes from seeding...",
+                  self.soft_masked.iter().map(|&(start, end)| end - start).sum::<usize>(),
+                  ambiguous);
+            let mut masked_seq = seq.iter()
+                .map(|&b| if alphabet.is_ambiguous(b) { alphabet.wildcard() } else { b })
+                .collect::<Vec<_>>();
+            for &(start, end) in &self.soft_masked {
+                masked_seq[start..end].iter_mut().for_each(|b| *b = alphabet.wildcard());
+            }
+            Some(masked_seq)
+        };
+        let text = masked_seq.as_ref().unwrap_or(seq);
+        let n = text.len() as u64;
+        if alphabet == Alphabet::Dna4 {
+            // everything but the sentinel has to be one of the FM index's symbols
+            if let Some(at) = text[..text.len() - 1].iter().position(|&b| b == b'N') {
+                let bin = &self.bins[find_bin(&self.bins, at).expect("The text is all in bins")];
+                let header = format!("{}-{}", bin.gi.0, bin.tax_id.0);
+                return Err(MtsvError::AmbiguousReference(header, at - bin.start + 1));
+            }
+        }
+
+        let (bwt, samples) = match external_sa {
+            Some(dir) => {
+                info!("Building suffix array in {}...", dir);
+                // each batch of suffixes takes about as much memory as the text
+                let batch_len = cmp::max(text.len() / 8, 1 << 20);
+                let sa = external_suffix_array(text, dir, batch_len, threads)?;
+                info!("Suffix array constructed.");
+
+                info!("Constructing Burrows-Wheeler Transform and sampling suffix array at {}...",
+                      suffix_sample);
+                let progress =
+                    Progress::new("Building BWT and sampling suffix array", "rows", Some(n));
+                let built = build_from_suffix_array_reader(text,
+                                                           sa.reader()?,
+                                                           suffix_sample,
+                                                           threads,
+                                                           &progress)?;
+                progress.finish();
+                info!("BWT constructed.");
+                built
+            },
+            None => {
+                info!("Building suffix array...");
+                let sa = suffix_array(text);
+                info!("Suffix array constructed.");
+
+                info!("Constructing Burrows-Wheeler Transform...");
+                let bwt = build_bwt(text, &sa, threads);
+                info!("BWT constructed.");
+
+                info!("Sampling suffix array at {}", suffix_sample);
+                let progress = Progress::new("Sampling suffix array", "rows", Some(n));
+                let samples =
+                    sample_suffix_array(text, &sa, &bwt, suffix_sample, threads, &progress);
+                progress.finish();
+                (bwt, samples)
+            },
+        };
+        drop(masked_seq);
+
+        Ok(SortedReferences { references: self, bwt, samples })
+    }
+}
+
+/// Concatenated references with the BWT and suffix array samples of their suffix array: the
+/// second stage of `MGIndex::from_references`, which only needs the occurrence table built to
+/// finish. Builds save it to resume from (see `builder::Checkpoint`).
+#[derive(Serialize, Deserialize)]
+pub struct SortedReferences {
+    references: ConcatenatedReferences,
+    bwt: BWT,
+    samples: SuffixSamples,
+}
+
+impl SortedReferences {
+    /// The number of reference sequences.
+    pub fn len(&self) -> usize {
+        self.references.len()
+    }
+
+    /// Whether there are no reference sequences.
+    pub fn is_empty(&self) -> bool {
+        self.references.is_empty()
+    }
+
+    /// Finish the index, packing the sequences and sampling the occurrence table every
+    /// `sample_interval` rows: the last stage of `MGIndex::from_references`.
+    pub fn into_index(self, sample_interval: u32, threads: usize) -> MGIndex {
+        let SortedReferences { references, bwt, samples } = self;
+        let ConcatenatedReferences { seq, bins, soft_masked, alphabet, fingerprint } = references;
+
+        // nothing else needs the text, so pack it before building the rest
+        let sequences = match alphabet {
+            Alphabet::Dna | Alphabet::Dna4 => {
+                info!("Packing reference sequences...");
+                let packed = PackedSequence::new(&seq);
+                drop(seq);
+                info!("Reference sequences packed.");
+                IndexText::Packed(packed)
+            },
+            Alphabet::Protein => IndexText::Plain(seq),
+        };
+
+        let symbols = alphabet.symbols();
+        info!("Building occurrence table at {}", sample_interval);
+        let less = less(&bwt, &symbols);
+        let progress = Progress::new("Building occurrence table", "rows", Some(bwt.len() as u64));
+        let occ = build_occ(&bwt, sample_interval, &symbols, threads, &progress);
+        progress.finish();
+        info!("Occurrence table constructed.");
+
+        let sampled_suffix_array = assemble(bwt, less, occ, samples);
+        info!("Sampled suffix array constructed");
+
+        MGIndex {
+            sequences,
+            bins,
+            suffix_array: sampled_suffix_array,
+            synthetic_gis: false,
+            fingerprint,
+            accessions: BTreeMap::new(),
+            alphabet,
+            soft_masked,
+            descriptions: BTreeMap::new(),
+            sample_interval,
+        }
+    }
+}
+
+/// The references of several indices, gathered one index at a time to build a single index
+/// holding all of them (see `MGIndex::merge`). Each index's references are copied out of it and
+/// the index dropped before the next one is added, so only one input index is held at once.
+#[derive(Default)]
+pub struct IndexMerge {
+    references: References,
+    accessions: HeaderMap,
+    descriptions: HeaderMap,
+    /// The names of the indices added, in order
+    names: Vec<String>,
+    /// Which of the indices added each GI came from
+    sources: BTreeMap<Gi, usize>,
+    synthetic_gis: bool,
+    sampling_params: Option<(u32, usize)>,
+}
+
+impl IndexMerge {
+    /// No indices yet.
+    pub fn new() -> Self {
+        IndexMerge::default()
+    }
+
+    /// Add the references, accessions and descriptions of `index`, named `name` in errors. Fails
+    /// if one of its GIs came from an index added before, or its alphabet isn't that of the
+    /// first index.
+    pub fn add(&mut self, name: &str, index: MGIndex) -> MtsvResult<()> {
+        if self.names.is_empty() {
+            self.references = References::with_alphabet(index.alphabet);
+        } else if index.alphabet != self.references.alphabet() {
+            return Err(MtsvError::InvalidParameter(format!("{} is a {} index, but {} is {}",
+                                                           name,
+                                                           index.alphabet.name(),
+                                                           self.names[0],
+                                                           self.references.alphabet().name())));
+        }
+        if let Some(bin) = index.bins.iter().find(|bin| self.sources.contains_key(&bin.gi)) {
+            let source = &self.names[self.sources[&bin.gi]];
+            return Err(MtsvError::DuplicateGi(bin.gi.0, source.clone(), name.to_string()));
+        }
+
+        for bin in &index.bins {
+            self.references.push_masked(bin.gi,
+                                        bin.tax_id,
+                                        &index.reference_bases(bin.start, bin.end),
+                                        index.soft_masked_in(bin));
+            self.sources.insert(bin.gi, self.names.len());
+        }
+        self.names.push(name.to_string());
+        self.accessions.extend(index.accessions.iter().map(|(&gi, acc)| (gi, acc.clone())));
+        self.descriptions.extend(index.descriptions.iter().map(|(&gi, d)| (gi, d.clone())));
+        self.synthetic_gis |= index.synthetic_gis;
+        if self.sampling_params.is_none() {
+            self.sampling_params = Some(index.sampling_params());
+        }
+        Ok(())
+    }
+
+    /// The sampling interval and suffix array sampling rate of the first index added.
+    pub fn sampling_params(&self) -> Option<(u32, usize)> {
+        self.sampling_params
+    }
+
+    /// The number of references added.
+    pub fn len(&self) -> usize {
+        self.references.len()
+    }
+
+    /// Whether no references have been added.
+    pub fn is_empty(&self) -> bool {
+        self.references.is_empty()
+    }
+
+    /// Build the merged index, the same index as one built from all of the references'
+    /// sequences at once (see `MGIndex::from_references`).
+    pub fn build(self,
+                 sample_interval: u32,
+                 suffix_sample: usize,
+                 threads: usize,
+                 external_sa: Option<&str>)
+                 -> MtsvResult<MGIndex> {
+        let mut index = MGIndex::from_references(self.references,
+                                                 sample_interval,
+                                                 suffix_sample,
+                                                 threads,
+                                                 external_sa)?;
+        index.set_synthetic_gis(self.synthetic_gis);
+        index.set_accessions(self.accessions);
+        index.set_descriptions(self.descriptions);
+        Ok(index)
+    }
+}
+
 /// The location within the index where a seed exact match was found.
 #[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
 struct SeedHit {
@@ -110,10 +1204,13 @@ struct SeedHit {
 impl SeedHit {
     /// Find the candidate alignment region for this seed hit, based on the query offset, the
     /// length of the original read, the edit distance tolerance, and the current GI bounds.
-    pub fn candidate_indices(&self,
+    /// Candidates cut short by the GI bounds may still be used if no more than `max_overhang`
+    /// bases of the read would hang off the end.
+    fn candidate_indices(&self,
                              bin: &Bin,
                              read_len: usize,
-                             edit_distance: usize)
+                             edit_distance: usize,
+                             max_overhang: usize)
                              -> Option<(usize, usize)> {
         let site = self.reference_offset;
         let seed_offset = self.query_offset;
@@ -121,7 +1218,7 @@ impl SeedHit {
         // the start of any alignment candidate needs to allow for some insertions at the beginning
         // but can't be earlier than the start of the GI in which this seed hit
         let start_offset = seed_offset + edit_distance;
-        let cand_start = if site - start_offset < bin.start || start_offset > site {
+        let cand_start = if start_offset > site || site - start_offset < bin.start {
             bin.start
         } else {
             site - start_offset
@@ -140,7 +1237,7 @@ impl SeedHit {
         // or we'd align against something outside the bin
         // or the candidate would be too short anyway
         if cand_start > cand_end || cand_start < bin.start || cand_end > bin.end ||
-           cand_end - cand_start < read_len - edit_distance {
+           cand_end - cand_start + max_overhang < read_len - edit_distance {
             None
         } else {
             Some((cand_start, cand_end))
@@ -156,7 +1253,7 @@ struct ReferenceCandidate<'rf> {
     reference_end_excl: usize,
     bin: Bin,
     num_seeds: usize,
-    index: &'rf MGIndex,
+    sequences: &'rf dyn ReferenceText,
 }
 
 
@@ -165,29 +1262,30 @@ impl<'rf> ReferenceCandidate<'rf> {
     /// Initialize a reference candidate with its first seed hit.
     fn new(seed_hit: SeedHit,
            bin: Bin,
-           index: &'rf MGIndex,
+           sequences: &'rf dyn ReferenceText,
            read_len: usize,
-           edit_distance: usize)
+           edit_distance: usize,
+           max_overhang: usize)
            -> Option<Self> {
 
         let (ref_start, ref_end_excl) =
-            match seed_hit.candidate_indices(&bin, read_len, edit_distance) {
-                Some(r) => r,
-                None => return None,
-            };
+            seed_hit.candidate_indices(&bin, read_len, edit_distance, max_overhang)?;
 
         Some(ReferenceCandidate {
             reference_start: ref_start,
             reference_end_excl: ref_end_excl,
             bin: bin,
             num_seeds: 1,
-            index: index,
+            sequences,
         })
     }
 
-    /// Returns a reference to the underlying candidate reference sequence.
-    fn candidate_seq(&self) -> &'rf [u8] {
-        &self.index.sequences[self.reference_start..self.reference_end_excl]
+    /// Decodes the underlying candidate reference sequence onto the end of `scratch`, returning
+    /// where it starts and ends there.
+    fn candidate_seq(&self, scratch: &mut Vec<u8>) -> (usize, usize) {
+        let start = scratch.len();
+        self.sequences.bases_into(self.reference_start..self.reference_end_excl, scratch);
+        (start, scratch.len())
     }
 
     /// Attempts to merge another seed hit into this reference region. Succeeds if a candidate
@@ -197,11 +1295,12 @@ impl<'rf> ReferenceCandidate<'rf> {
                     seed_hit: SeedHit,
                     bin: &Bin,
                     read_len: usize,
-                    edit_distance: usize)
+                    edit_distance: usize,
+                    max_overhang: usize)
                     -> Result<(), ()> {
 
         let (ref_start, ref_end_excl) =
-            match seed_hit.candidate_indices(&bin, read_len, edit_distance) {
+            match seed_hit.candidate_indices(bin, read_len, edit_distance, max_overhang) {
                 Some(r) => r,
                 None => return Err(()),
             };
@@ -230,62 +1329,94 @@ impl<'rf> ReferenceCandidate<'rf> {
     }
 }
 
-impl MGIndex {
-    // TODO test this function
-    /// Identify all taxonomic IDs in this index which match against the query sequence within the
-    /// specified edit distance.
-    ///
-    /// Process:
-    ///
-    /// 1. Generate a series of "seeds" (gapped subsequences) from the query sequence. The size and
-    /// spacing of these are determined by the `seed_length` and `seed_gap` arguments.
-    /// 2. Using the FM Index, find all locations where these seeds are present.
-    /// 3. Coalesce all of the `SeedHit`s into combined `ReferenceCandidate`s representing all of
-    /// the regions of the reference sequences against which we'll align the query sequence.
-    /// 4. Sort all of the `ReferenceCandidate`s by the number of seeds present (we want to align
-    /// the most likely regions first, as that will enable us to skip more regions later).
-    /// 5. Use a SIMD-accelerated Smith-Waterman algorithm to align each reference candidate whose
-    /// corresponding taxonomic ID hasn't already been found. When the score is within a threshold,
-    /// perform a final edit-distance alignment, recording the taxonomic ID as "found" if it's
-    /// equal to or lesser than the `edit_distance` argument.
-    /// 6. Return the list of matching taxonomic IDs.
+/// Finds seeds in an index's concatenated sequences, whether the index was deserialized or
+/// mapped.
+trait SeedSearch {
+    /// The suffix array interval of `seed`, or of its longest suffix which occurs.
+    fn backward_search(&self, seed: &[u8]) -> BackwardSearchResult;
 
-    pub fn matching_tax_ids(&self,
-                            fmindex: &FMIndex<&BWT, &Less, &Occ>,
-                            sequence: &[u8],
-                            edit_freq: f64,
-                            seed_length: usize,
-                            seed_gap: usize,
-                            min_seeds_percent: f64,
-                            max_hits: usize,
-                            tune_max_hits: usize)
-                            -> Vec<Hit> {
+    /// The offsets into the concatenated sequences of the suffixes in `interval`.
+    fn locate(&self, interval: &Interval) -> Vec<usize>;
+}
 
-        // we need to later compare for edit distance where N's won't match against reference N's
-        let seq_no_n = sequence.iter()
-            .map(|b| {
-                match *b {
-                    b'N' => b'.',
-                    _ => *b,
-                }
-            })
-            .collect::<Vec<u8>>();
+/// The FM index and suffix array of a deserialized index.
+struct InMemorySearch<'a> {
+    fmindex: &'a FMIndex<&'a BWT, &'a Less, &'a Occ>,
+    suffix_array: &'a SampledSuffixArray<BWT, Less, Occ>,
+}
+
+impl<'a> SeedSearch for InMemorySearch<'a> {
+    fn backward_search(&self, seed: &[u8]) -> BackwardSearchResult {
+        self.fmindex.backward_search(seed.iter())
+    }
+
+    fn locate(&self, interval: &Interval) -> Vec<usize> {
+        interval.occ(self.suffix_array)
+    }
+}
+
+impl<M> SeedSearch for MappedFile<M> {
+    fn backward_search(&self, seed: &[u8]) -> BackwardSearchResult {
+        MappedFile::backward_search(self, seed)
+    }
+
+    fn locate(&self, interval: &Interval) -> Vec<usize> {
+        MappedFile::locate(self, interval)
+    }
+}
+
+/// The parts of an index a query reads, which are the same however the index was loaded.
+struct QueryView<'a, S: 'a> {
+    sequences: &'a dyn ReferenceText,
+    bins: &'a [Bin],
+    search: &'a S,
+    alphabet: Alphabet,
+}
+
+impl<'a, S: SeedSearch> QueryView<'a, S> {
+    /// Find the hits for a strand, as `MGIndex::matching_tax_ids` describes.
+    fn matching_tax_ids_for(&self,
+                            strand: &QueryStrand,
+                            params: &QueryParams,
+                            stats: &mut QueryStats)
+                            -> Vec<Hit> {
+        let sequence = strand.sequence();
 
         let seq_len = sequence.len() as f64;
-        let edit_distance = (seq_len * edit_freq).ceil() as usize;
+        let edit_distance = (seq_len * params.edit_freq).ceil() as usize;
+        let max_overhang = params.max_overhang.map_or(0, |f| (seq_len * f).floor() as usize);
+        let seed_length = params.seed_length;
+        let wildcard = self.alphabet.wildcard();
+        // a DNA4 index has no Ns to find, so seeds with any are never searched for
+        let max_seed_ns = if self.alphabet == Alphabet::Dna4 { 0 } else { params.max_seed_ns };
+
+        // a read shorter than a seed has no seeds to search for
+        if sequence.len() < seed_length {
+            return Vec::new();
+        }
 
+        let seed_sequence = strand.seed_sequence();
         let seeds = (0..(sequence.len() + 1 - seed_length)) // get all seed start indices
-            .step(seed_gap)                                 // skip over any in between seed gap
-            .map(|i| (i, &sequence[i..i + seed_length]));   // create a reference into the query
+            .step(params.seed_gap)                          // skip over any in between seed gap
+            .map(|i| (i, &seed_sequence[i..i + seed_length])); // a reference into the query
         
 
         // find all of the reference regions which we'll align against
-        let reference_candidates = {
-            let mut bin_locations = Vec::new();
+        let (reference_candidates, seeds_searched) = {
+            // most reads record a few hits per seed, so start with room for that and grow only
+            // as far as the per-read limit
+            let seed_count = (sequence.len() + 1).saturating_sub(seed_length)
+                .checked_div(params.seed_gap)
+                .map_or(0, |n| n + 1);
+            let mut bin_locations = Vec::with_capacity(cmp::min(seed_count * 4,
+                                                                params.max_seed_hits_per_read));
+            // number of seed hits recorded so far for each bin, by bin index
+            let mut bin_hit_counts: HashMap<usize, usize> = HashMap::new();
 
             let mut n_seeds = 0.0;
             let mut next_offset = 0;
-            let mut seed_interval = seed_gap;
+            let mut seed_interval = params.seed_gap;
+            let mut hit_limited = false;
             for (offset, seed) in seeds {
                 // if end of this seeds does not extend past end
                 // of last seed (due to seed expansion for high hit counts),
@@ -293,42 +1424,68 @@ impl MGIndex {
                 if offset < next_offset {
                     continue;
                 }
-                
-                // find everywhere this seed occurs in the reference database
-                let interval = fmindex.backward_search(seed.iter());
-                // there are a few seeds which are SO prevalent they'll blow up memory usage if we don't
-                // filter them out. in practice they have little impact on quality of results
-                // if this seed is greater than max_hits, just skip it
 
-                let mut interval_upper = 0;
-                let mut interval_lower = 0;
-                let positions = match interval {
-                    BackwardSearchResult::Complete(sai) => {
-                        interval_upper = sai.upper;
-                        interval_lower = sai.lower;
-                        sai
-                    }
-                    BackwardSearchResult::Partial(sai, _l) => { 
-                        sai
-                    }
-                    BackwardSearchResult::Absent => {
-                        Interval {
-                            upper: 0,
-                            lower: 0
+                if hit_limited {
+                    stats.seeds_over_hit_limit += 1;
+                    continue;
+                }
+
+                // skipped seeds aren't counted as searched, so they don't raise min_seeds
+                if seed.iter().filter(|&&b| b == wildcard).count() > max_seed_ns {
+                    stats.seeds_with_ns += 1;
+                    continue;
+                }
+
+                // find everywhere this seed occurs in the reference database
+                let interval = self.search.backward_search(seed);
+
+                // a partial result means only a suffix of the seed was found, and the interval
+                // is for that suffix
+                let (positions, query_offset) = match interval {
+                    BackwardSearchResult::Complete(sai) => (sai, offset),
+                    BackwardSearchResult::Partial(sai, matched_len) => {
+                        stats.partial_seeds += 1;
+                        match params.min_partial_seed {
+                            Some(min_len) if matched_len >= min_len => {
+                                stats.partial_seeds_used += 1;
+                                (sai, offset + seed_length - matched_len)
+                            },
+                            _ => continue,
                         }
                     }
+                    BackwardSearchResult::Absent => continue,
                 };
 
-                // If no interval is returned no seed hits were found                 
-                if (interval_upper == 0) && (interval_lower == 0) {
+                // If no interval is returned no seed hits were found
+                let n_hits = positions.upper - positions.lower;
+                if n_hits == 0 {
+                    continue;
+                }
+                // every hit of a seed found with an N is in a run of the references' Ns
+                if params.skip_n_seed_hits && seed[query_offset - offset..].contains(&wildcard) {
+                    stats.seed_hits_in_ns += n_hits;
+                    continue;
+                }
+                // there are a few seeds which are SO prevalent they'll blow up memory usage if we don't
+                // filter them out. in practice they have little impact on quality of results
+                // if this seed is greater than max_hits, just skip it
+                if n_hits > params.max_hits {
                     continue;
                 }
-                let n_hits = interval_upper - interval_lower;
-                // if too many seed hits were found, skip
-                if n_hits > max_hits {
+                if bin_locations.len() + n_hits > params.max_seed_hits_per_read {
+                    hit_limited = true;
+                    stats.hit_limited_queries += 1;
+                    stats.seeds_over_hit_limit += 1;
                     continue;
                 }
-                if n_hits > tune_max_hits{
+                if bin_locations.capacity() < bin_locations.len() + n_hits {
+                    // grow as a Vec would, but not past the limit
+                    let target = cmp::min(cmp::max(bin_locations.capacity() * 2,
+                                                   bin_locations.len() + n_hits),
+                                          params.max_seed_hits_per_read);
+                    bin_locations.reserve_exact(target - bin_locations.len());
+                }
+                if n_hits > params.tune_max_hits {
                     // each time n_Hits exceeds max hits,
                     // double the seed interval
                     seed_interval = seed_interval * 2;
@@ -336,32 +1493,49 @@ impl MGIndex {
 
                 }
 
-                // track a new SeedHit for each value in ther suffix array interval
-                bin_locations.extend(positions.occ(&self.suffix_array).iter().map(|i| {
-                    SeedHit {
-                        reference_offset: *i,
-                        query_offset: offset,
+                // track a new SeedHit for each value in ther suffix array interval, unless its bin
+                // is already saturated -- more seeds there add little beyond the num_seeds ranking
+                for reference_offset in self.search.locate(&positions) {
+                    if let Some(bin_idx) = find_bin(self.bins, reference_offset) {
+                        let count = bin_hit_counts.entry(bin_idx).or_insert(0);
+                        if *count >= params.max_hits_per_bin {
+                            stats.seed_hits_capped += 1;
+                            continue;
+                        }
+
+                        *count += 1;
+                        if *count == params.max_hits_per_bin {
+                            stats.capped_bins += 1;
+                        }
                     }
-                }));
+
+                    bin_locations.push(SeedHit {
+                        reference_offset,
+                        query_offset,
+                    });
+                }
 
                 n_seeds += 1.0;
                 }
 
-            // calculate min seeds given number of seeds and percent, force a minimum of 1 seed.       
-            let min_seeds = (n_seeds * min_seeds_percent).floor().max(1.0) as usize;
-       
+            stats.seeds_searched += n_seeds as usize;
+            stats.seed_hits += bin_locations.len();
+
+            let min_seeds = params.min_seeds(n_seeds as usize);
+
 
             // merge all of the seed hits into candidate regions we can align against
             let mut refs =
                 self.coalesce_seed_sites(&mut bin_locations,
                                          min_seeds,
                                          sequence.len(),
-                                         edit_distance);
+                                         edit_distance,
+                                         max_overhang);
 
             // sort in reverse by number of seeds -- check the most promising locations first
             refs.sort_by(|a, b| b.num_seeds.cmp(&a.num_seeds));
 
-            refs
+            (refs, n_seeds as u32)
         };
 
 
@@ -370,7 +1544,17 @@ impl MGIndex {
 
         let mut aligner = Aligner::new();
 
-        let profile = Profile::new(sequence, &IDENT_W_PENALTY_NO_N_MATCH);
+        // Ns (or Xs) in the read never match unless they're free, see `QueryParams::max_free_ns`
+        let read_ns = sequence.iter().filter(|&&b| b == wildcard).count();
+        let free_ns = match params.max_free_ns {
+            Some(max_free_ns) if read_ns <= max_free_ns => read_ns,
+            _ => 0,
+        };
+        let expand = params.ambiguity == AmbiguityPolicy::Expand;
+        let matcher = self.alphabet.matcher(free_ns > 0, expand);
+
+        // the candidate sequence, decoded from the index
+        let mut scratch = Vec::new();
         // let mut n_skip = 0;
         // let n_refs = reference_candidates.len();
         for candidate in reference_candidates {
@@ -383,24 +1567,63 @@ impl MGIndex {
             // see if there's a match in the search candidate
             // if there is, record the hit tax id and then advance to the next candidate
 
-            let cand_seq = candidate.candidate_seq();
+            // the read may only hang off the ends of the reference sequence
+            let clip_start = if candidate.reference_start == candidate.bin.start {
+                max_overhang
+            } else {
+                0
+            };
+            let clip_end = if candidate.reference_end_excl == candidate.bin.end {
+                max_overhang
+            } else {
+                0
+            };
+
+            scratch.clear();
+            let (start, end) = candidate.candidate_seq(&mut scratch);
+            let cand_seq = &scratch[start..end];
 
-            let score = profile.align_score(cand_seq, 1, 1);
+            // the profile is only built once there's a candidate to score
+            let score = strand.profile().align_score(cand_seq, 1, 1);
 
             // -1 for substitution, -1 for gap open, -1 for gap extend
             // means that we need to allow for a hit to the alignment score of up to 1.5x editdist
-            if score as usize >= sequence.len() - (edit_distance * 2) {
+            // overhanging bases can't score either, so allow for those too, and free Ns still
+            // score as mismatches
+            let min_score = sequence.len()
+                .saturating_sub(edit_distance * 2 + clip_start + clip_end + free_ns * 2);
+            if score as usize >= min_score {
+                stats.candidates_aligned += 1;
 
                 // the SW check is faster (w/ SIMD) than the min_edit_distance check, so if we're
                 // within an acceptable tolerance, now do the expensive check
-                let edits = aligner.min_edit_distance(&seq_no_n, cand_seq);
-                
-                if edits as usize <= edit_distance {
+                // Ns in the reference never match
+                let (edits, clipped_start, clipped_end) = if clip_start + clip_end == 0 {
+                    (aligner.min_edit_distance_with(sequence, cand_seq, matcher), 0, 0)
+                } else {
+                    aligner.min_edit_distance_clipped(sequence,
+                                                      cand_seq,
+                                                      clip_start,
+                                                      clip_end,
+                                                      matcher)
+                };
+
+                // clipped bases aren't aligned, so only the rest of the read counts towards the
+                // allowed edit distance
+                let aligned_len = seq_len - (clipped_start + clipped_end) as f64;
+                let allowed_edits = (aligned_len * params.edit_freq).ceil() as usize;
+
+                if edits as usize <= allowed_edits {
                     matches.push(candidate.bin.tax_id);
 
                     let hit = Hit {
                         tax_id: candidate.bin.tax_id,
-                        edit: edits
+                        edit: edits,
+                        seeds: candidate.num_seeds as u32,
+                        seeds_searched,
+                        clipped_start: clipped_start as u32,
+                        clipped_end: clipped_end as u32,
+                        gi: candidate.bin.gi,
                     };
                     
                     hits.push(hit);
@@ -417,8 +1640,9 @@ impl MGIndex {
                            seed_hits: &mut [SeedHit],
                            min_seeds: usize,
                            read_len: usize,
-                           edit_distance: usize)
-                           -> Vec<ReferenceCandidate> {
+                           edit_distance: usize,
+                           max_overhang: usize)
+                           -> Vec<ReferenceCandidate<'a>> {
     
         
         seed_hits.sort();
@@ -426,19 +1650,24 @@ impl MGIndex {
         let mut curr_cand: Option<ReferenceCandidate> = None;
         let mut candidates = Vec::new();
 
-        let mut bin_iter = self.bins.iter().peekable();
-        // if there are no bins we have bigger problems
-        let mut curr_bin = bin_iter.next().unwrap();
+        // seed hits are usually in a few bins far apart, so each new bin is found by binary
+        // search rather than walking the bins in between
+        let mut last_bin: Option<&Bin> = None;
 
         for &mut sh in seed_hits {
-
-            // if the site is ahead of the current bin, we need to advance the bin
-            while curr_bin.end <= sh.reference_offset {
-                curr_bin = bin_iter.next().unwrap();
-
-            }
+            let curr_bin = match last_bin {
+                Some(bin) if sh.reference_offset < bin.end => bin,
+                _ => {
+                    let bin = find_bin(self.bins, sh.reference_offset)
+                        .map(|i| &self.bins[i])
+                        .expect("Seed hits are always within a reference sequence");
+                    last_bin = Some(bin);
+                    bin
+                },
+            };
             if let Some(mut cand) = curr_cand {
-                if let Ok(()) = cand.add_seed_hit(sh, curr_bin, read_len, edit_distance) {
+                if let Ok(()) =
+                       cand.add_seed_hit(sh, curr_bin, read_len, edit_distance, max_overhang) {
                     curr_cand = Some(cand);
                     // last_cand = curr_cand;
                 } else {
@@ -450,10 +1679,20 @@ impl MGIndex {
                     }
                     // curr_cand = None;
                     // Save the current seedhit as new reference candidate
-                    curr_cand = ReferenceCandidate::new(sh, *curr_bin, self, read_len, edit_distance);
+                    curr_cand = ReferenceCandidate::new(sh,
+                                                        *curr_bin,
+                                                        self.sequences,
+                                                        read_len,
+                                                        edit_distance,
+                                                        max_overhang);
                 }
             } else {
-                curr_cand = ReferenceCandidate::new(sh, *curr_bin, self, read_len, edit_distance);
+                curr_cand = ReferenceCandidate::new(sh,
+                                                    *curr_bin,
+                                                    self.sequences,
+                                                    read_len,
+                                                    edit_distance,
+                                                    max_overhang);
             }
 
             
@@ -466,117 +1705,1148 @@ impl MGIndex {
         }
         candidates
     }
+}
+
+/// Find the index of the bin containing an absolute offset into the concatenated sequences.
+fn find_bin(bins: &[Bin], offset: usize) -> Option<usize> {
+    // bins are contiguous and sorted by start, so find the last bin starting at or before
+    let idx = match bins.binary_search_by(|b| b.start.cmp(&offset)) {
+        Ok(i) => i,
+        Err(0) => return None,
+        Err(i) => i - 1,
+    };
+
+    if offset < bins[idx].end {
+        Some(idx)
+    } else {
+        None
+    }
+}
+
+impl MGIndex {
+    // TODO test this function
+    /// Identify all taxonomic IDs in this index which match against the query sequence within the
+    /// specified edit distance.
+    ///
+    /// Process:
+    ///
+    /// 1. Generate a series of "seeds" (gapped subsequences) from the query sequence. The size and
+    ///    spacing of these are determined by the `seed_length` and `seed_gap` parameters.
+    /// 2. Using the FM Index, find all locations where these seeds are present. At most
+    ///    `max_hits_per_bin` locations are kept for any one bin.
+    /// 3. Coalesce all of the `SeedHit`s into combined `ReferenceCandidate`s representing all of
+    ///    the regions of the reference sequences against which we'll align the query sequence.
+    /// 4. Sort all of the `ReferenceCandidate`s by the number of seeds present (we want to align
+    ///    the most likely regions first, as that will enable us to skip more regions later).
+    /// 5. Use a SIMD-accelerated Smith-Waterman algorithm to align each reference candidate whose
+    ///    corresponding taxonomic ID hasn't already been found. When the score is within a
+    ///    threshold, perform a final edit-distance alignment, recording the taxonomic ID as
+    ///    "found" if it's equal to or lesser than the edit distance implied by `edit_freq`.
+    /// 6. Return the list of matching taxonomic IDs.
+    ///
+    /// Counters for the work performed are added to `stats`.
+    pub fn matching_tax_ids(&self,
+                            fmindex: &FMIndex<&BWT, &Less, &Occ>,
+                            sequence: &[u8],
+                            params: &QueryParams,
+                            stats: &mut QueryStats)
+                            -> Vec<Hit> {
+        let strand =
+            QueryStrand::with_alphabet(sequence, self.alphabet).with_ambiguity(params.ambiguity);
+        self.matching_tax_ids_for(fmindex, &strand, params, stats)
+    }
+
+    /// Like `matching_tax_ids`, for a prepared strand whose alignment profile can be reused across
+    /// queries.
+    pub fn matching_tax_ids_for(&self,
+                                fmindex: &FMIndex<&BWT, &Less, &Occ>,
+                                strand: &QueryStrand,
+                                params: &QueryParams,
+                                stats: &mut QueryStats)
+                                -> Vec<Hit> {
+        let search = InMemorySearch {
+            fmindex,
+            suffix_array: &self.suffix_array,
+        };
+        QueryView {
+                sequences: &self.sequences,
+                bins: &self.bins,
+                search: &search,
+                alphabet: self.alphabet,
+            }
+            .matching_tax_ids_for(strand, params, stats)
+    }
+
+    /// Number of reference sequences (bins) in the index.
+    pub fn bin_count(&self) -> usize {
+        self.bins.len()
+    }
+
+    /// The reference sequences in the index, in the order they're concatenated.
+    pub fn bins(&self) -> impl Iterator<Item = BinInfo> + '_ {
+        self.bins.iter().map(BinInfo::from_bin)
+    }
+
+    /// The distinct taxids of the index's reference sequences, in ascending order.
+    pub fn tax_ids(&self) -> Vec<TaxId> {
+        let mut tax_ids = self.bins.iter().map(|bin| bin.tax_id).collect::<Vec<_>>();
+        tax_ids.dedup();
+        tax_ids
+    }
+
+    /// The GI and length of each of the reference sequences with this taxid, in index order.
+    pub fn bins_for(&self, tax_id: TaxId) -> Vec<(Gi, usize)> {
+        bins_of(&self.bins, tax_id).iter().map(|bin| (bin.gi, bin.end - bin.start)).collect()
+    }
+
+    /// Whether the index has any reference sequences with this taxid.
+    pub fn contains_tax_id(&self, tax_id: TaxId) -> bool {
+        !bins_of(&self.bins, tax_id).is_empty()
+    }
+
+    /// Resolve an absolute offset into the concatenated sequences (e.g. from the suffix array)
+    /// to the GI and taxid of the reference sequence containing it, and the offset within that
+    /// sequence. None for the sentinel and anything past it.
+    pub fn resolve_offset(&self, offset: usize) -> Option<(Gi, TaxId, usize)> {
+        let bin = &self.bins[self.bin_index(offset)?];
+        Some((bin.gi, bin.tax_id, offset - bin.start))
+    }
+
+    /// Find the index of the bin containing an absolute offset into the concatenated sequences.
+    fn bin_index(&self, offset: usize) -> Option<usize> {
+        find_bin(&self.bins, offset)
+    }
 
     /// Construct a new MGIndex from a series of reference sequences, concatenating all reference
-    /// sequences and recording sequence boundaries and other metadata.
-    pub fn new(reference: Database, sample_interval: u32, suffix_sample: usize) -> Self {
+    /// sequences and recording sequence boundaries and other metadata, in bins as `bin_mode` says
+    /// (see `References::set_bin_mode`).
+    pub fn new(reference: Database,
+               sample_interval: u32,
+               suffix_sample: usize,
+               bin_mode: BinMode)
+               -> Self {
+        MGIndex::with_build_threads(reference, sample_interval, suffix_sample, 1, bin_mode)
+    }
+
+    /// Like `new`, but build the BWT, occurrence table and suffix array samples on `threads`
+    /// threads (see `fm_build`). The suffix array itself is built on one. The index is identical
+    /// whatever the number of threads.
+    pub fn with_build_threads(reference: Database,
+                              sample_interval: u32,
+                              suffix_sample: usize,
+                              threads: usize,
+                              bin_mode: BinMode)
+                              -> Self {
+        MGIndex::with_alphabet(reference,
+                               Alphabet::Dna,
+                               sample_interval,
+                               suffix_sample,
+                               threads,
+                               bin_mode)
+            .expect("A DNA index can hold any references")
+    }
+
+    /// Like `with_build_threads`, for reference sequences of `alphabet`, which they're converted
+    /// to (see `References::push`). Protein indices hold their references a byte per residue
+    /// rather than packed, and are queried on the forward strand only (see `QueryStrand`).
+    ///
+    /// Fails with `MtsvError::AmbiguousReference` if the alphabet is `Alphabet::Dna4` and a
+    /// reference has an `N` once it's converted, which DNA4's FM index can't hold.
+    pub fn with_alphabet(reference: Database,
+                         alphabet: Alphabet,
+                         sample_interval: u32,
+                         suffix_sample: usize,
+                         threads: usize,
+                         bin_mode: BinMode)
+                         -> MtsvResult<Self> {
         info!("Concatenating all reference sequences and recording boundaries...");
+        let mut references = References::with_alphabet(alphabet);
+        references.set_bin_mode(bin_mode);
+        for (tax_id, sequences) in reference {
+            for (gi, sequence) in sequences {
+                references.push(gi, tax_id, &sequence);
+            }
+        }
+        MGIndex::from_references(references, sample_interval, suffix_sample, threads, None)
+    }
 
-        // concatenate all of the sequences, recording a new bin for each sequence
-        let mut seq = Vec::new();
-        let mut bins = Vec::new();
-        for (tax_id, references) in reference {
-
-            for (gi, reference) in references {
-                let bin = Bin {
-                    gi: gi,
-                    tax_id: tax_id,
-                    start: seq.len(),
-                    end: seq.len() + reference.len(),
-                };
+    /// Build an index from references concatenated as they were read, as `with_build_threads`
+    /// does from a `Database`.
+    ///
+    /// If `external_sa` names a directory, the suffix array is sorted into a temporary file there
+    /// a batch at a time (see `suffix_sort::external_suffix_array`), and the BWT and suffix array
+    /// samples are built from it as it's read back, so it's never held in memory. That's slower,
+    /// but it takes about an eighth of the memory. The index is the same either way. Only DNA
+    /// suffix arrays can be sorted externally.
+    ///
+    /// Fails with `MtsvError::AmbiguousReference` if the references are `Alphabet::Dna4` and one
+    /// has an `N`, including soft-masked bases and ambiguity codes, which are `N` to the FM
+    /// index.
+    ///
+    /// The index is built in stages, `References::concatenate`, `ConcatenatedReferences::sort`
+    /// and `SortedReferences::into_index`, which builds can save and resume between.
+    pub fn from_references(references: References,
+                           sample_interval: u32,
+                           suffix_sample: usize,
+                           threads: usize,
+                           external_sa: Option<&str>)
+                           -> MtsvResult<Self> {
+        let sorted = references.concatenate().sort(suffix_sample, threads, external_sa)?;
+        Ok(sorted.into_index(sample_interval, threads))
+    }
 
-                seq.extend_from_slice(&reference);
-                bins.push(bin);
+    /// Combine several indices into one holding all of their references, with the sampling
+    /// parameters of the first, rebuilding the FM index over the concatenated sequences. The
+    /// result is the same index as one built from all of their references at once. Each index is
+    /// dropped once its references are copied. Fails if the same GI is in more than one index,
+    /// the indices' alphabets differ, or there are no indices.
+    pub fn merge(indices: Vec<MGIndex>) -> MtsvResult<MGIndex> {
+        let mut merge = IndexMerge::new();
+        for (i, index) in indices.into_iter().enumerate() {
+            merge.add(&format!("index {}", i + 1), index)?;
+        }
+        match merge.sampling_params() {
+            Some((sample_interval, suffix_sample)) => {
+                merge.build(sample_interval, suffix_sample, 1, None)
+            },
+            None => Err(MtsvError::InvalidParameter("no indices to merge".to_string())),
+        }
+    }
+
+    /// Build a new index from only the reference sequences of `tax_ids`, with their GIs,
+    /// accessions and descriptions, the same index as one built from just those references (see
+    /// `with_build_threads`). Taxids which aren't in this index are ignored.
+    pub fn subset(&self,
+                  tax_ids: &BTreeSet<TaxId>,
+                  sample_interval: u32,
+                  suffix_sample: usize,
+                  threads: usize)
+                  -> MGIndex {
+        self.rebuild(|bin| tax_ids.contains(&bin.tax_id), sample_interval, suffix_sample, threads)
+    }
+
+    /// Build a new index without the reference sequences of `tax_ids`, with this index's
+    /// sampling parameters, the same index as one built from the references left. The number of
+    /// references and bases removed is logged. Taxids which aren't in this index are ignored.
+    pub fn remove(&self, tax_ids: &[TaxId]) -> MGIndex {
+        self.remove_with_build_threads(tax_ids, 1)
+    }
+
+    /// Like `remove`, but build the new index's FM index on `threads` threads (see
+    /// `with_build_threads`).
+    pub fn remove_with_build_threads(&self, tax_ids: &[TaxId], threads: usize) -> MGIndex {
+        let removed = tax_ids.iter()
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .flat_map(|&tax_id| bins_of(&self.bins, tax_id))
+            .collect::<Vec<_>>();
+        info!("Removing {} references ({} bases) of {} taxa...",
+              removed.len(),
+              removed.iter().map(|bin| bin.end - bin.start).sum::<usize>(),
+              removed.iter().map(|bin| bin.tax_id).dedup().count());
+
+        let (sample_interval, suffix_sample) = self.sampling_params();
+        self.rebuild(|bin| !tax_ids.contains(&bin.tax_id), sample_interval, suffix_sample, threads)
+    }
+
+    /// Build a new index from the reference sequences of the bins `keep` is true for, with their
+    /// GIs, accessions and descriptions.
+    fn rebuild<F>(&self,
+                  keep: F,
+                  sample_interval: u32,
+                  suffix_sample: usize,
+                  threads: usize)
+                  -> MGIndex
+        where F: Fn(&Bin) -> bool
+    {
+        let mut references = References::with_alphabet(self.alphabet);
+        let mut accessions = BTreeMap::new();
+        let mut descriptions = BTreeMap::new();
+        for bin in self.bins.iter().filter(|bin| keep(bin)) {
+            references.push_masked(bin.gi,
+                                   bin.tax_id,
+                                   &self.reference_bases(bin.start, bin.end),
+                                   self.soft_masked_in(bin));
+            if let Some(accession) = self.accessions.get(&bin.gi) {
+                accessions.insert(bin.gi, accession.clone());
+            }
+            if let Some(description) = self.descriptions.get(&bin.gi) {
+                descriptions.insert(bin.gi, description.clone());
             }
         }
-        // info!("Concatenating all reference sequences and recording boundaries...");
-        // // Combine sequences from same taxids with a spacer
-        // let mut seq_map = HashMap::new();
-        // for (tax_id, references) in reference {
-        //     for (_gi, mut refseq) in references {
-        //         for _i in 1..10 {
-        //             refseq.push(b'N');
 
-        //         }
-        //         seq_map.entry(tax_id).or_insert(Sequence::new()).extend_from_slice(&refseq);
-        //     }
-        // }
-        
-        // // concatenate all of the sequences, recording a new bin for each sequence
-        // let mut seq = Vec::new();
-        // let mut bins = Vec::new();
-        // for (tax_id, reference) in seq_map {
-        //     let bin = Bin {
-        //         gi: Gi(0),
-        //         tax_id: tax_id,
-        //         start: seq.len(),
-        //         end: seq.len() + reference.len(),
-        //     };
-
-        //         seq.extend_from_slice(&reference);
-        //         bins.push(bin);
-            
-        // }
+        let mut index =
+            MGIndex::from_references(references, sample_interval, suffix_sample, threads, None)
+                .expect("Building an index in memory can't fail");
+        index.set_synthetic_gis(self.synthetic_gis);
+        index.set_accessions(accessions);
+        index.set_descriptions(descriptions);
+        index
+    }
+
+    /// Load an index written by `io::write_to_file` or `io::write_to_file_compressed` in any
+    /// format version this build reads. Indices from versions without accessions load without
+    /// any, 32-bit taxids from older versions are widened, and sequences from older versions are
+    /// packed, so use this rather than `io::from_file`, which only reads the current version.
+    /// Indices from versions without an alphabet are DNA, and those from versions without
+    /// soft-masking or descriptions have none.
+    ///
+    /// Indices from versions with a checksum are checked against it as they're read, failing
+    /// with `MtsvError::CorruptIndex` if they don't match.
+    pub fn from_file(p: &str) -> MtsvResult<MGIndex> {
+        MGIndex::read_file(p, true)
+    }
+
+    /// Load an index like `MGIndex::from_file`, without checking its checksum, which saves
+    /// hashing the index as it's read.
+    pub fn from_file_unverified(p: &str) -> MtsvResult<MGIndex> {
+        MGIndex::read_file(p, false)
+    }
+
+    fn read_file(p: &str, verify: bool) -> MtsvResult<MGIndex> {
+        let mut index = MGIndex::deserialize_file(p, verify)?;
+        index.sample_interval = occ_sampling_interval(index.suffix_array.occ());
+        Ok(index)
+    }
+
+    /// The index at `p`, as whichever format version it was written in lays it out.
+    fn deserialize_file(p: &str, verify: bool) -> MtsvResult<MGIndex> {
+        let (version, mut reader) = open_index(p)?;
+        if version >= DESCRIPTIONS_INDEX_FORMAT_VERSION {
+            return deserialize_checked(reader, p, version, verify);
+        }
+        if version >= PACKED_INDEX_FORMAT_VERSION {
+            let (index, alphabet) = if version >= ALPHABET_INDEX_FORMAT_VERSION {
+                let index: PackedIndex<IndexText> =
+                    deserialize_versioned(&mut reader, p, version)?;
+                (index, deserialize_versioned(&mut reader, p, version)?)
+            } else {
+                let index: PackedIndex<PackedSequence> =
+                    deserialize_versioned(&mut reader, p, version)?;
+                (index.map_sequences(IndexText::Packed), Alphabet::Dna)
+            };
+            let soft_masked = if version >= SOFTMASK_INDEX_FORMAT_VERSION {
+                deserialize_versioned(&mut reader, p, version)?
+            } else {
+                Vec::new()
+            };
+            return Ok(MGIndex {
+                sequences: index.sequences,
+                bins: index.bins,
+                suffix_array: index.suffix_array,
+                synthetic_gis: index.synthetic_gis,
+                fingerprint: index.fingerprint,
+                accessions: index.accessions,
+                alphabet,
+                soft_masked,
+                descriptions: BTreeMap::new(),
+                sample_interval: 0,
+            });
+        }
+
+        let index = if version >= WIDE_TAXID_INDEX_FORMAT_VERSION {
+            deserialize_versioned::<UnpackedIndex<Bin>, _>(&mut reader, p, version)?
+        } else {
+            deserialize_versioned::<UnpackedIndex<NarrowBin>, _>(&mut reader, p, version)?.widen()
+        };
+        // bincode writes a struct's fields one after another, so the accessions follow the rest
+        let accessions = if version >= ACCESSIONS_INDEX_FORMAT_VERSION {
+            deserialize_versioned(&mut reader, p, version)?
+        } else {
+            BTreeMap::new()
+        };
+        Ok(MGIndex {
+            sequences: IndexText::Packed(PackedSequence::new(&index.sequences)),
+            bins: index.bins,
+            suffix_array: index.suffix_array,
+            synthetic_gis: index.synthetic_gis,
+            fingerprint: index.fingerprint,
+            accessions,
+            alphabet: Alphabet::Dna,
+            soft_masked: Vec::new(),
+            descriptions: BTreeMap::new(),
+            sample_interval: 0,
+        })
+    }
+
+    /// Open an index written in the mapped layout by `write_mapped` or `convert_to_mapped`. Its
+    /// sequences and FM index are read from a memory map as queries need them, rather than
+    /// deserialized up front (see `mapped`).
+    pub fn open_mmap(p: &str) -> MtsvResult<MappedIndex> {
+        Ok(MappedIndex { file: MappedFile::open(p)? })
+    }
+
+    /// Write this index to `p` in the mapped layout, for `open_mmap`. Only DNA indices can be
+    /// mapped.
+    ///
+    /// The index is serialized on another thread and copied into the file as it's serialized, so
+    /// no second copy of it is held in memory.
+    pub fn write_mapped(&self, p: &str) -> MtsvResult<()> {
+        if self.alphabet != Alphabet::Dna {
+            return Err(unmappable(self.alphabet));
+        }
+        let (reader, writer) = pipe()?;
+        thread::scope(|scope| {
+            let serializer = scope.spawn(move || -> MtsvResult<()> {
+                let mut writer = BufWriter::with_capacity(1 << 20, writer);
+                serialize_into(&mut writer, self)?;
+                writer.flush()?;
+                Ok(())
+            });
+            let written = write_mapped_from(BufReader::with_capacity(1 << 20, reader),
+                                            INDEX_FORMAT_VERSION,
+                                            p);
+            // serializing only fails once nothing is reading it, so report why that stopped
+            let serialized = serializer.join().expect("Index serializer panicked");
+            written.and(serialized)
+        })
+    }
+
+    /// The FM-index over the concatenated sequences, for the searches which take one (see
+    /// `matching_tax_ids`).
+    pub fn fm_index(&self) -> FMIndex<&BWT, &Less, &Occ> {
+        FMIndex::new(self.suffix_array.bwt(), self.suffix_array.less(), self.suffix_array.occ())
+    }
+
+    /// Create a searcher for queries against this index.
+    pub fn searcher(&self) -> IndexSearcher<'_> {
+        IndexSearcher::Deserialized(self, self.fm_index())
+    }
+
+    /// The alphabet of the index's reference sequences.
+    pub fn alphabet(&self) -> Alphabet {
+        self.alphabet
+    }
+
+    /// The number of soft-masked bases, which the index doesn't seed in (see
+    /// `References::set_respect_softmask`).
+    pub fn soft_masked_bases(&self) -> usize {
+        self.soft_masked.iter().map(|&(start, end)| end - start).sum()
+    }
+
+    /// Whether any of the bases from absolute offset `start` up to `end` are soft-masked.
+    pub fn overlaps_soft_mask(&self, start: usize, end: usize) -> bool {
+        let i = self.soft_masked.partition_point(|&(_, e)| e <= start);
+        self.soft_masked.get(i).is_some_and(|&(s, _)| s < end)
+    }
+
+    /// The soft-masked intervals of `bin`, relative to its start.
+    fn soft_masked_in<'a>(&'a self, bin: &'a Bin) -> impl Iterator<Item = (usize, usize)> + 'a {
+        let first = self.soft_masked.partition_point(|&(s, _)| s < bin.start);
+        self.soft_masked[first..]
+            .iter()
+            .take_while(move |&&(s, _)| s < bin.end)
+            .map(move |&(s, e)| (s - bin.start, e - bin.start))
+    }
+
+    /// A hash of the reference sequences and their GIs and taxids, in index order. Indices built
+    /// from the same references have the same fingerprint regardless of the sampling parameters.
+    pub fn fingerprint(&self) -> u64 {
+        self.fingerprint
+    }
+
+    /// The sampling interval of the BWT occurrence array and the sampling rate of the suffix
+    /// array the index was built with.
+    pub fn sampling_params(&self) -> (u32, usize) {
+        (self.sample_interval, self.suffix_array.sampling_rate())
+    }
 
+    /// The number of taxa, references and bases in the index, the references' lengths and the
+    /// sampling parameters.
+    pub fn stats(&self) -> IndexStats {
+        let lengths = self.bins.iter().map(|bin| bin.end - bin.start);
+        let (sample_interval, suffix_sample) = self.sampling_params();
+        IndexStats {
+            taxa: self.bins.iter().map(|bin| bin.tax_id).collect::<BTreeSet<_>>().len(),
+            references: self.bins.len(),
+            bases: self.reference_len(),
+            min_reference_length: lengths.clone().min().unwrap_or(0),
+            max_reference_length: lengths.clone().max().unwrap_or(0),
+            mean_reference_length: if self.bins.is_empty() {
+                0.0
+            } else {
+                lengths.clone().sum::<usize>() as f64 / self.bins.len() as f64
+            },
+            sample_interval,
+            suffix_sample,
+        }
+    }
+
+    /// The concatenated reference sequences, without the suffix array sentinel, decoded from
+    /// the packed sequences the index holds.
+    pub fn reference_sequence(&self) -> Vec<u8> {
+        self.reference_bases(0, self.reference_len())
+    }
+
+    /// The number of bases in the concatenated reference sequences, without the suffix array
+    /// sentinel.
+    pub fn reference_len(&self) -> usize {
+        self.sequences.len() - 1
+    }
+
+    /// The concatenated reference sequences from `start` up to `end`, decoded from the packed
+    /// sequences the index holds.
+    pub fn reference_bases(&self, start: usize, end: usize) -> Vec<u8> {
+        let mut bases = Vec::with_capacity(end - start);
+        self.sequences.bases_into(start..end, &mut bases);
+        bases
+    }
+
+    /// The number of reference sequences and bases the index holds for each taxid.
+    pub fn taxid_composition(&self) -> Manifest {
+        let mut manifest = Manifest::new();
+        for bin in &self.bins {
+            let composition = manifest.entry(bin.tax_id).or_default();
+            composition.sequences += 1;
+            composition.bases += bin.end - bin.start;
+        }
+        manifest
+    }
+
+    /// The taxid and length of each reference sequence in the index, by GI.
+    pub fn reference_lengths(&self) -> ReferenceLengths {
+        self.bins
+            .iter()
+            .map(|bin| (bin.gi, ReferenceLength { tax_id: bin.tax_id, bases: bin.end - bin.start }))
+            .collect()
+    }
+
+    /// Count the exact occurrences of `kmer` in the concatenated reference sequences, including
+    /// any which span the boundary between two adjacent sequences. K-mers with bases other than
+    /// uppercase `ACGTN`, or `ACGT` in a DNA4 index, never occur.
+    pub fn count_occurrences(&self, fmindex: &FMIndex<&BWT, &Less, &Occ>, kmer: &[u8]) -> usize {
+        if !self.alphabet.is_searchable(kmer) {
+            return 0;
+        }
+
+        match fmindex.backward_search(kmer.iter()) {
+            BackwardSearchResult::Complete(sai) => sai.upper - sai.lower,
+            _ => 0,
+        }
+    }
+
+    /// Locate the exact occurrences of `kmer` which lie within a single reference sequence, as
+    /// the GI and taxid of that sequence and the offset into it. Sorted by taxid, GI and offset.
+    pub fn locate_occurrences(&self,
+                              fmindex: &FMIndex<&BWT, &Less, &Occ>,
+                              kmer: &[u8])
+                              -> Vec<(Gi, TaxId, usize)> {
+        if !self.alphabet.is_searchable(kmer) {
+            return Vec::new();
+        }
+
+        let positions = match fmindex.backward_search(kmer.iter()) {
+            BackwardSearchResult::Complete(sai) => sai.occ(&self.suffix_array),
+            _ => return Vec::new(),
+        };
+
+        let mut locations = positions.into_iter()
+            .filter_map(|pos| {
+                let bin = &self.bins[self.bin_index(pos)?];
+                if pos + kmer.len() <= bin.end {
+                    Some((bin.gi, bin.tax_id, pos - bin.start))
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+
+        locations.sort_by_key(|&(gi, tax_id, offset)| (tax_id, gi, offset));
+        locations
+    }
+
+    /// Whether this index was built from taxid-only headers, so its GIs are sequence numbers
+    /// assigned at build time rather than real identifiers.
+    pub fn synthetic_gis(&self) -> bool {
+        self.synthetic_gis
+    }
+
+    /// Record whether this index's GIs were assigned at build time.
+    pub fn set_synthetic_gis(&mut self, synthetic: bool) {
+        self.synthetic_gis = synthetic;
+    }
+
+    /// Whether the references' accessions were recorded when the index was built.
+    pub fn has_accessions(&self) -> bool {
+        !self.accessions.is_empty()
+    }
+
+    /// The accession of the reference sequence with this GI, if the index has accessions.
+    pub fn accession(&self, gi: Gi) -> Option<&str> {
+        self.accessions.get(&gi).map(String::as_str)
+    }
+
+    /// The references' accessions by GI, empty if the index has none.
+    pub fn accessions(&self) -> &HeaderMap {
+        &self.accessions
+    }
+
+    /// Record the references' accessions by GI, as parsed from their headers (see
+    /// `io::parse_fasta_db_with_accessions`).
+    pub fn set_accessions(&mut self, accessions: HeaderMap) {
+        self.accessions = accessions;
+    }
+
+    /// The original FASTA description of the reference sequence with this GI, if the index kept
+    /// descriptions and it had one.
+    pub fn description(&self, gi: Gi) -> Option<&str> {
+        self.descriptions.get(&gi).map(String::as_str)
+    }
+
+    /// The references' descriptions by GI, empty if the index didn't keep them.
+    pub fn descriptions(&self) -> &HeaderMap {
+        &self.descriptions
+    }
+
+    /// Record the references' original FASTA descriptions by GI, the rest of their header lines
+    /// (see `io::parse_fasta_references`), for `mtsv-reference` to write back out.
+    pub fn set_descriptions(&mut self, descriptions: HeaderMap) {
+        self.descriptions = descriptions;
+    }
+
+    /// Returns a vector of reference sequences for a given taxid using
+    /// bin offset slices.
+    pub fn get_references(&self,
+        taxid: u64) -> Vec<Sequence> {
+            references_of(&self.bins, &self.sequences, taxid)
+        }
+
+    /// Like `get_references`, but with each sequence's bin, giving its GI and where it is in the
+    /// concatenated sequences.
+    pub fn get_references_annotated(&self, taxid: u64) -> Vec<(BinInfo, Sequence)> {
+        annotated_references_of(&self.bins, &self.sequences, taxid)
+    }
+
+    /// The reference sequence with this GI, or the first in index order if several records had
+    /// it. None if no reference has it.
+    pub fn get_reference_by_gi(&self, gi: u32) -> Option<Sequence> {
+        reference_by_gi(&self.bins, &self.sequences, gi)
+    }
+}
+
+/// The first reference sequence with this GI, in index order.
+fn reference_by_gi<T>(bins: &[Bin], sequences: &T, gi: u32) -> Option<Sequence>
+    where T: ReferenceText + ?Sized
+{
+    let bin = bins.iter().find(|bin| bin.gi.0 == gi)?;
+    let mut seq = Vec::with_capacity(bin.end - bin.start);
+    sequences.bases_into(bin.start..bin.end, &mut seq);
+    Some(seq)
+}
+
+/// The reference sequences with this taxid, in index order.
+fn references_of<T>(bins: &[Bin], sequences: &T, taxid: u64) -> Vec<Sequence>
+    where T: ReferenceText + ?Sized
+{
+    annotated_references_of(bins, sequences, taxid).into_iter().map(|(_, seq)| seq).collect()
+}
+
+/// The reference sequences with this taxid and their bins, in index order.
+fn annotated_references_of<T>(bins: &[Bin],
+                              sequences: &T,
+                              taxid: u64)
+                              -> Vec<(BinInfo, Sequence)>
+    where T: ReferenceText + ?Sized
+{
+    let mut seqs = Vec::new();
+
+    for bin in bins_of(bins, TaxId(taxid)) {
+        let mut seq = Vec::with_capacity(bin.end - bin.start);
+        sequences.bases_into(bin.start..bin.end, &mut seq);
+        seqs.push((BinInfo::from_bin(bin), seq));
+    }
+    info!("Returning {} reference sequences for taxid: {}", seqs.len(), taxid);
+    seqs
+}
+
+/// The bins with this taxid. Bins are in taxid order, so they're found by binary search.
+fn bins_of(bins: &[Bin], tax_id: TaxId) -> &[Bin] {
+    let start = bins.partition_point(|bin| bin.tax_id < tax_id);
+    let end = start + bins[start..].partition_point(|bin| bin.tax_id == tax_id);
+    &bins[start..end]
+}
+
+/// Open the index file at `p` with `io::open_versioned`, after checking that a file without a
+/// header is a legacy index.
+fn open_index(p: &str) -> MtsvResult<(u32, Box<dyn Read>)> {
+    if Envelope::of_file(p)?.is_headerless() {
+        check_legacy_index(p)?;
+        info!("{} has no header, so it's read as a legacy (format 1) index", p);
+    }
+    open_versioned(p)
+}
+
+/// Check that the file at `p`, which has no header, starts like an index from before headers
+/// were written: with the length of the reference sequences, which must fit in the file, and
+/// then the sequences themselves.
+fn check_legacy_index(p: &str) -> MtsvResult<()> {
+    let file = File::open(p)?;
+    let file_len = file.metadata()?.len();
+    let mut start = Vec::with_capacity(72);
+    file.take(72).read_to_end(&mut start)?;
+
+    let looks_legacy = start.len() >= 8 && {
+        let mut len = [0; 8];
+        len.copy_from_slice(&start[..8]);
+        let len = u64::from_le_bytes(len);
+        let sequences = &start[8..cmp::min(start.len() as u64, 8 + len) as usize];
+        len > 0 && len <= file_len - 8 &&
+        sequences.iter().all(|b| b"ACGTN$".contains(b))
+    };
+    if looks_legacy {
+        Ok(())
+    } else {
+        Err(MtsvError::IncompatibleIndex(p.to_string(),
+                                         String::from("it has no mtsv index header, and doesn't \
+                                                       start like a legacy index from before \
+                                                       headers were written")))
+    }
+}
+
+/// Convert the index at `index_path`, in any format version this build reads, to the mapped
+/// layout at `mapped_path` (see `MGIndex::open_mmap`). The index is copied as it's read, so it's
+/// never held in memory, apart from its packed sequences, which are unpacked into the mapped
+/// layout.
+pub fn convert_to_mapped(index_path: &str, mapped_path: &str) -> MtsvResult<()> {
+    let (version, reader) = open_index(index_path)?;
+    write_mapped_from(reader, version, mapped_path)
+}
+
+/// Copy the serialization of an index in format `version` from `reader` to the mapped layout at
+/// `p`, following the field order of `MGIndex` (or `PackedIndex` or `UnpackedIndex` for older
+/// versions). Fails without writing anything for protein indices, whose text isn't packed.
+fn write_mapped_from<R: Read>(mut reader: R, version: u32, p: &str) -> MtsvResult<()> {
+    let packed = if version >= ALPHABET_INDEX_FORMAT_VERSION {
+        match deserialize_from(&mut reader)? {
+            IndexText::Packed(sequences) => Some(sequences),
+            IndexText::Plain(_) => return Err(unmappable(Alphabet::Protein)),
+        }
+    } else if version >= PACKED_INDEX_FORMAT_VERSION {
+        Some(deserialize_from::<_, PackedSequence>(&mut reader)?)
+    } else {
+        None
+    };
+
+    let mut writer = MappedWriter::create(p)?;
+    match packed {
+        Some(sequences) => {
+            writer.copy_text(&mut sequences.reader(), sequences.len() as u64)?;
+        },
+        None => {
+            let sequences_len: u64 = deserialize_from(&mut reader)?;
+            writer.copy_text(&mut reader, sequences_len)?;
+        },
+    }
+    let bins = if version >= WIDE_TAXID_INDEX_FORMAT_VERSION {
+        deserialize_from(&mut reader)?
+    } else {
+        let bins: Vec<NarrowBin> = deserialize_from(&mut reader)?;
+        bins.into_iter().map(NarrowBin::widen).collect()
+    };
+    writer.copy_suffix_array(&mut reader)?;
+    let synthetic_gis = deserialize_from(&mut reader)?;
+    let fingerprint = deserialize_from(&mut reader)?;
+    let accessions = if version >= ACCESSIONS_INDEX_FORMAT_VERSION {
+        deserialize_from(&mut reader)?
+    } else {
+        BTreeMap::new()
+    };
+
+    writer.finish(&MappedMetadata {
+        bins,
+        synthetic_gis,
+        fingerprint,
+        accessions,
+    })
+}
+
+/// The error for writing an index of `alphabet` in the mapped layout, which only holds DNA.
+fn unmappable(alphabet: Alphabet) -> MtsvError {
+    MtsvError::InvalidParameter(format!("{} indices can't be written in the mapped layout",
+                                        alphabet.name()))
+}
+
+/// What a mapped index stores besides its sequences and FM index.
+#[derive(Serialize, Deserialize)]
+struct MappedMetadata {
+    bins: Vec<Bin>,
+    synthetic_gis: bool,
+    fingerprint: u64,
+    accessions: HeaderMap,
+}
+
+/// An index in the mapped layout, opened with `MGIndex::open_mmap`. It answers queries the same
+/// way as the `MGIndex` it was written from, reading its sequences and FM index from a memory
+/// map, which processes on the same machine share.
+pub struct MappedIndex {
+    file: MappedFile<MappedMetadata>,
+}
+
+impl MappedIndex {
+    /// Identify all taxonomic IDs in this index which match against the query sequence within
+    /// the specified edit distance, as `MGIndex::matching_tax_ids` does.
+    pub fn matching_tax_ids(&self,
+                            sequence: &[u8],
+                            params: &QueryParams,
+                            stats: &mut QueryStats)
+                            -> Vec<Hit> {
+        let strand = QueryStrand::new(sequence).with_ambiguity(params.ambiguity);
+        self.matching_tax_ids_for(&strand, params, stats)
+    }
+
+    /// Like `matching_tax_ids`, for a prepared strand whose alignment profile can be reused across
+    /// queries.
+    pub fn matching_tax_ids_for(&self,
+                                strand: &QueryStrand,
+                                params: &QueryParams,
+                                stats: &mut QueryStats)
+                                -> Vec<Hit> {
+        QueryView {
+                sequences: &self.file.text(),
+                bins: &self.file.metadata().bins,
+                search: &self.file,
+                alphabet: Alphabet::Dna,
+            }
+            .matching_tax_ids_for(strand, params, stats)
+    }
+
+    /// Create a searcher for queries against this index.
+    pub fn searcher(&self) -> IndexSearcher<'_> {
+        IndexSearcher::Mapped(self)
+    }
+
+    /// Number of reference sequences (bins) in the index.
+    pub fn bin_count(&self) -> usize {
+        self.file.metadata().bins.len()
+    }
+
+    /// The reference sequences in the index, in the order they're concatenated.
+    pub fn bins(&self) -> impl Iterator<Item = BinInfo> + '_ {
+        self.file.metadata().bins.iter().map(BinInfo::from_bin)
+    }
+
+    /// The fingerprint of the index this was written from (see `MGIndex::fingerprint`).
+    pub fn fingerprint(&self) -> u64 {
+        self.file.metadata().fingerprint
+    }
+
+    /// The alphabet of the index's reference sequences, which is always DNA for mapped indices.
+    pub fn alphabet(&self) -> Alphabet {
+        Alphabet::Dna
+    }
+
+    /// The concatenated reference sequences, without the suffix array sentinel.
+    pub fn reference_sequence(&self) -> &[u8] {
+        let text = self.file.text();
+        &text[..text.len() - 1]
+    }
+
+    /// Whether this index's GIs were assigned at build time (see `MGIndex::synthetic_gis`).
+    pub fn synthetic_gis(&self) -> bool {
+        self.file.metadata().synthetic_gis
+    }
+
+    /// Whether the references' accessions were recorded when the index was built.
+    pub fn has_accessions(&self) -> bool {
+        !self.file.metadata().accessions.is_empty()
+    }
+
+    /// The accession of the reference sequence with this GI, if the index has accessions.
+    pub fn accession(&self, gi: Gi) -> Option<&str> {
+        self.file.metadata().accessions.get(&gi).map(String::as_str)
+    }
+
+    /// The references' accessions by GI, empty if the index has none.
+    pub fn accessions(&self) -> &HeaderMap {
+        &self.file.metadata().accessions
+    }
+
+    /// Returns a vector of reference sequences for a given taxid using bin offset slices.
+    pub fn get_references(&self, taxid: u64) -> Vec<Sequence> {
+        references_of(&self.file.metadata().bins, self.file.text(), taxid)
+    }
+
+    /// The reference sequences with this taxid and their bins (see
+    /// `MGIndex::get_references_annotated`).
+    pub fn get_references_annotated(&self, taxid: u64) -> Vec<(BinInfo, Sequence)> {
+        annotated_references_of(&self.file.metadata().bins, self.file.text(), taxid)
+    }
+
+    /// The reference sequence with this GI (see `MGIndex::get_reference_by_gi`).
+    pub fn get_reference_by_gi(&self, gi: u32) -> Option<Sequence> {
+        reference_by_gi(&self.file.metadata().bins, self.file.text(), gi)
+    }
+}
+
+/// An index opened for binning: deserialized, or mapped if it was written in the mapped layout.
+pub enum LoadedIndex {
+    /// An index read into memory by `MGIndex::from_file`.
+    Deserialized(MGIndex),
+    /// An index opened by `MGIndex::open_mmap`.
+    Mapped(MappedIndex),
+}
+
+impl LoadedIndex {
+    /// Open the index at `p`, mapping it if it's in the mapped layout and otherwise
+    /// deserializing it.
+    pub fn open(p: &str) -> MtsvResult<Self> {
+        LoadedIndex::open_checked(p, true)
+    }
 
+    /// Open the index at `p` like `LoadedIndex::open`, without checking the checksum of a
+    /// deserialized index (see `MGIndex::from_file_unverified`).
+    pub fn open_unverified(p: &str) -> MtsvResult<Self> {
+        LoadedIndex::open_checked(p, false)
+    }
+
+    fn open_checked(p: &str, verify: bool) -> MtsvResult<Self> {
+        if is_mapped_file(p)? {
+            Ok(LoadedIndex::Mapped(MGIndex::open_mmap(p)?))
+        } else {
+            Ok(LoadedIndex::Deserialized(MGIndex::read_file(p, verify)?))
+        }
+    }
+
+    /// Create a searcher for queries against this index.
+    pub fn searcher(&self) -> IndexSearcher<'_> {
+        match *self {
+            LoadedIndex::Deserialized(ref index) => index.searcher(),
+            LoadedIndex::Mapped(ref index) => index.searcher(),
+        }
+    }
+
+    /// The bins.
+    fn bin_slice(&self) -> &[Bin] {
+        match *self {
+            LoadedIndex::Deserialized(ref index) => &index.bins,
+            LoadedIndex::Mapped(ref index) => &index.file.metadata().bins,
+        }
+    }
+
+    /// Number of reference sequences (bins) in the index.
+    pub fn bin_count(&self) -> usize {
+        self.bin_slice().len()
+    }
+
+    /// The reference sequences in the index, in the order they're concatenated.
+    pub fn bins(&self) -> impl Iterator<Item = BinInfo> + '_ {
+        self.bin_slice().iter().map(BinInfo::from_bin)
+    }
+
+    /// The index's fingerprint (see `MGIndex::fingerprint`).
+    pub fn fingerprint(&self) -> u64 {
+        match *self {
+            LoadedIndex::Deserialized(ref index) => index.fingerprint(),
+            LoadedIndex::Mapped(ref index) => index.fingerprint(),
+        }
+    }
+
+    /// The alphabet of the index's reference sequences.
+    pub fn alphabet(&self) -> Alphabet {
+        match *self {
+            LoadedIndex::Deserialized(ref index) => index.alphabet(),
+            LoadedIndex::Mapped(ref index) => index.alphabet(),
+        }
+    }
+
+    /// Whether the index's GIs were assigned at build time (see `MGIndex::synthetic_gis`).
+    pub fn synthetic_gis(&self) -> bool {
+        match *self {
+            LoadedIndex::Deserialized(ref index) => index.synthetic_gis(),
+            LoadedIndex::Mapped(ref index) => index.synthetic_gis(),
+        }
+    }
+
+    /// The references' accessions by GI, empty if the index has none.
+    pub fn accessions(&self) -> &HeaderMap {
+        match *self {
+            LoadedIndex::Deserialized(ref index) => index.accessions(),
+            LoadedIndex::Mapped(ref index) => index.accessions(),
+        }
+    }
+
+    /// Whether the references' accessions were recorded when the index was built.
+    pub fn has_accessions(&self) -> bool {
+        !self.accessions().is_empty()
+    }
+
+    /// The accession of the reference sequence with this GI, if the index has accessions.
+    pub fn accession(&self, gi: Gi) -> Option<&str> {
+        self.accessions().get(&gi).map(String::as_str)
+    }
+
+    /// The original FASTA description of the reference sequence with this GI, if the index kept
+    /// descriptions (see `MGIndex::description`). The mapped layout doesn't keep them.
+    pub fn description(&self, gi: Gi) -> Option<&str> {
+        match *self {
+            LoadedIndex::Deserialized(ref index) => index.description(gi),
+            LoadedIndex::Mapped(_) => None,
+        }
+    }
+
+    /// Returns a vector of reference sequences for a given taxid using bin offset slices.
+    pub fn get_references(&self, taxid: u64) -> Vec<Sequence> {
+        match *self {
+            LoadedIndex::Deserialized(ref index) => index.get_references(taxid),
+            LoadedIndex::Mapped(ref index) => index.get_references(taxid),
+        }
+    }
+
+    /// The reference sequences with this taxid and their bins (see
+    /// `MGIndex::get_references_annotated`).
+    pub fn get_references_annotated(&self, taxid: u64) -> Vec<(BinInfo, Sequence)> {
+        match *self {
+            LoadedIndex::Deserialized(ref index) => index.get_references_annotated(taxid),
+            LoadedIndex::Mapped(ref index) => index.get_references_annotated(taxid),
+        }
+    }
+
+    /// The reference sequence with this GI (see `MGIndex::get_reference_by_gi`).
+    pub fn get_reference_by_gi(&self, gi: u32) -> Option<Sequence> {
+        match *self {
+            LoadedIndex::Deserialized(ref index) => index.get_reference_by_gi(gi),
+            LoadedIndex::Mapped(ref index) => index.get_reference_by_gi(gi),
+        }
+    }
+
+    /// The concatenated reference sequences from `start` up to `end` (see
+    /// `MGIndex::reference_bases`).
+    pub fn reference_bases(&self, start: usize, end: usize) -> Vec<u8> {
+        match *self {
+            LoadedIndex::Deserialized(ref index) => index.reference_bases(start, end),
+            LoadedIndex::Mapped(ref index) => index.reference_sequence()[start..end].to_vec(),
+        }
+    }
+}
+
+/// Runs queries against an index however it was loaded (see `MGIndex::searcher`).
+pub enum IndexSearcher<'a> {
+    /// A deserialized index, with an FM index over its suffix array.
+    Deserialized(&'a MGIndex, FMIndex<&'a BWT, &'a Less, &'a Occ>),
+    /// A mapped index.
+    Mapped(&'a MappedIndex),
+}
+
+impl<'a> IndexSearcher<'a> {
+    /// Find the hits for a prepared strand (see `MGIndex::matching_tax_ids`).
+    pub fn matching_tax_ids_for(&self,
+                                strand: &QueryStrand,
+                                params: &QueryParams,
+                                stats: &mut QueryStats)
+                                -> Vec<Hit> {
+        match *self {
+            IndexSearcher::Deserialized(index, ref fmindex) => {
+                index.matching_tax_ids_for(fmindex, strand, params, stats)
+            },
+            IndexSearcher::Mapped(index) => index.matching_tax_ids_for(strand, params, stats),
+        }
+    }
+
+    /// The alphabet of the index, which the strands queried against it must be of.
+    pub fn alphabet(&self) -> Alphabet {
+        match *self {
+            IndexSearcher::Deserialized(index, _) => index.alphabet(),
+            IndexSearcher::Mapped(index) => index.alphabet(),
+        }
+    }
+}
 
-        // convert whole reference sequence to DNA5 alphabet
-        for b in &mut seq {
-            match *b {
-                // skip capital N alphabet characters
-                b'A' | b'C' | b'G' | b'T' | b'N' => (),
-                b'a' => *b = b'A',
-                b'c' => *b = b'C',
-                b'g' => *b = b'G',
-                b't' => *b = b'T',
-                _ => *b = b'N',
+/// The optional parts an index was built with, which some options depend on.
+///
+/// These are read from the index file and the files next to it (see `IndexCapabilities::of_file`)
+/// without deserializing the index, so options can be checked before a run starts.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct IndexCapabilities(u32);
+
+impl IndexCapabilities {
+    /// A prescreen was built for the index (see `prescreen::Prescreen`).
+    pub const PRESCREEN: IndexCapabilities = IndexCapabilities(1);
+    /// The index file is compressed (see `io::write_to_file_compressed`).
+    pub const COMPRESSED: IndexCapabilities = IndexCapabilities(1 << 1);
+    /// The index file is in the mapped layout (see `MGIndex::open_mmap`).
+    pub const MAPPED: IndexCapabilities = IndexCapabilities(1 << 2);
+    /// Every capability.
+    pub const ALL: IndexCapabilities = IndexCapabilities(IndexCapabilities::PRESCREEN.0 |
+                                                         IndexCapabilities::COMPRESSED.0 |
+                                                         IndexCapabilities::MAPPED.0);
+
+    /// The capabilities of the index at `index_path`.
+    pub fn of_file(index_path: &str) -> MtsvResult<Self> {
+        let mut capabilities = IndexCapabilities::default();
+
+        if is_mapped_file(index_path)? {
+            capabilities.insert(IndexCapabilities::MAPPED);
+        } else {
+            let envelope = Envelope::of_file(index_path)?;
+            if envelope.is_headerless() {
+                check_legacy_index(index_path)?;
+            } else if envelope.is_compressed() {
+                capabilities.insert(IndexCapabilities::COMPRESSED);
             }
         }
-        // suffix array requires a lexicographically smallest sentinel
-        seq.push(b'$');
-        seq.shrink_to_fit();
-
-        info!("All reference sequences concatenated and boundaries recorded.");
-
-        let alphabet = alphabets::dna::n_alphabet();
+        if Path::new(&prescreen_path(index_path)).exists() {
+            capabilities.insert(IndexCapabilities::PRESCREEN);
+        }
+        Ok(capabilities)
+    }
 
-        info!("Building suffix array...");
-        let sa = suffix_array(&seq);
-        info!("Suffix array constructed.");
+    /// Whether all of `other`'s capabilities are in these.
+    pub fn contains(self, other: IndexCapabilities) -> bool {
+        self.0 & other.0 == other.0
+    }
 
-        info!("Constructing Burrows-Wheeler Transform...");
-        let bwt = bwt(&seq, &sa);
-        info!("BWT constructed.");
+    /// Add `other`'s capabilities to these.
+    pub fn insert(&mut self, other: IndexCapabilities) {
+        self.0 |= other.0;
+    }
 
-        let less = less(&bwt, &alphabet);
-        let occ = Occ::new(&bwt, sample_interval, &alphabet);
-        
-        info!("Sampling suffix array at {}", suffix_sample);
-        let sampled_suffix_array = sa.sample(&seq, bwt, less, occ, suffix_sample);
-        info!("Sampled suffix array constructed");
-        
-        MGIndex {
-            sequences: seq,
-            bins: bins,
-            suffix_array: sampled_suffix_array,
+    /// Check that the index has `capability`, which the option `option` needs, and otherwise fail
+    /// with an error naming the build option which adds it.
+    pub fn require(self, capability: IndexCapabilities, option: &str) -> MtsvResult<()> {
+        if self.contains(capability) {
+            return Ok(());
         }
+        let build_options = [(IndexCapabilities::PRESCREEN, "--build-prescreen"),
+                             (IndexCapabilities::COMPRESSED, "--compress-index"),
+                             (IndexCapabilities::MAPPED, "--mmap-format")];
+        let missing = build_options.iter()
+            .filter(|&&(c, _)| capability.contains(c) && !self.contains(c))
+            .map(|&(_, name)| name)
+            .collect::<Vec<_>>();
+        Err(MtsvError::InvalidParameter(format!("index built without {}; rebuild or drop {}",
+                                                missing.join(" and "),
+                                                option)))
     }
 
-    /// Returns a vector of reference sequences for a given taxid using
-    /// bin offset slices.
-    pub fn get_references(&self,
-        taxid: u32) -> Vec<Sequence> {
-            let mut seqs = Vec::new();
+    /// The names of the capabilities, as `mtsv-inspect` lists them.
+    pub fn names(self) -> Vec<&'static str> {
+        [(IndexCapabilities::PRESCREEN, "prescreen"),
+         (IndexCapabilities::COMPRESSED, "compressed"),
+         (IndexCapabilities::MAPPED, "mapped")]
+            .iter()
+            .filter(|&&(c, _)| self.contains(c))
+            .map(|&(_, name)| name)
+            .collect()
+    }
+}
 
-            for bin in &self.bins {
-                if bin.tax_id.0 == taxid {
-                    seqs.push(self.sequences[bin.start .. bin.end].to_vec());
-                }
-            }
-            info!("Returning {} reference sequences for taxid: {}", seqs.len(), taxid);
-            seqs
+/// Hash the ordered (GI, taxid, sequence hash) triples of every bin.
+fn build_fingerprint(sequences: &[u8], bins: &[Bin]) -> u64 {
+    let mut fingerprint = Fnv64::default();
+    for bin in bins {
+        let mut seq_hash = Fnv64::default();
+        seq_hash.write(&sequences[bin.start..bin.end]);
+
+        fingerprint.write(&bin.gi.0.to_le_bytes());
+        // taxids which fit in 32 bits are hashed as they were before taxids were widened, so
+        // the same references keep the same fingerprint
+        match u32::try_from(bin.tax_id.0) {
+            Ok(tax_id) => fingerprint.write(&tax_id.to_le_bytes()),
+            Err(_) => fingerprint.write(&bin.tax_id.0.to_le_bytes()),
         }
-
+        fingerprint.write(&seq_hash.finish().to_le_bytes());
+    }
+    fingerprint.finish()
 }
 
 // this needs to be outside the test module so that integration tests can use it
@@ -592,7 +2862,7 @@ pub fn random_database(num_taxa: u16,
     let mut to_ret = BTreeMap::new();
 
     for _ in 0..num_taxa {
-        let taxid = TaxId(rng.gen());
+        let taxid = TaxId(rng.gen::<u32>() as u64);
         let mut seqs = Vec::new();
 
         for _ in 0..num_gis {
@@ -644,7 +2914,7 @@ mod test {
         let edits = 3;
 
         let db = random_database(10, 10, 500, 501);
-        let index = MGIndex::new(db, 16, 32);
+        let index = MGIndex::new(db, 16, 32, BinMode::PerGi);
 
         let bin = index.bins
             .iter()
@@ -652,9 +2922,10 @@ mod test {
             .next()
             .unwrap();
 
-        let mut cand = ReferenceCandidate::new(seed_hit, *bin, &index, read_len, edits).unwrap();
+        let mut cand = ReferenceCandidate::new(seed_hit, *bin, &index.sequences, read_len, edits, 0)
+            .unwrap();
 
-        cand.add_seed_hit(seed_hit2, bin, read_len, edits).unwrap();
+        cand.add_seed_hit(seed_hit2, bin, read_len, edits, 0).unwrap();
     }
 
     #[test]
@@ -674,7 +2945,7 @@ mod test {
         let edits = 3;
 
         let db = random_database(10, 10, 150, 151);
-        let index = MGIndex::new(db, 16, 32);
+        let index = MGIndex::new(db, 16, 32, BinMode::PerGi);
 
         if let Some(bin) = index.bins
             .iter()
@@ -688,11 +2959,12 @@ mod test {
                 .next() {
                 if let Some(mut cand) = ReferenceCandidate::new(seed_hit,
                                                                 *bin,
-                                                                &index,
+                                                                &index.sequences,
                                                                 read_len,
-                                                                edits) {
+                                                                edits,
+                                                                0) {
                     // THIS is what should actually fail
-                    cand.add_seed_hit(seed_hit2, bin2, read_len, edits).unwrap();
+                    cand.add_seed_hit(seed_hit2, bin2, read_len, edits, 0).unwrap();
                 }
             }
         }
@@ -709,7 +2981,7 @@ mod test {
         let edits = 3;
 
         let db = random_database(100, 200, 500, 1_000);
-        let index = MGIndex::new(db, 16, 32);
+        let index = MGIndex::new(db, 16, 32, BinMode::PerGi);
 
         let bin = index.bins
             .iter()
@@ -717,31 +2989,36 @@ mod test {
             .next()
             .unwrap();
 
-        let mut cand = ReferenceCandidate::new(seed_hit, *bin, &index, read_len, edits).unwrap();
+        let mut cand = ReferenceCandidate::new(seed_hit, *bin, &index.sequences, read_len, edits, 0)
+            .unwrap();
 
-        let (expect_start, expect_end) = seed_hit.candidate_indices(bin, read_len, edits).unwrap();
+        let (expect_start, expect_end) =
+            seed_hit.candidate_indices(bin, read_len, edits, 0).unwrap();
 
-        let found_seq = cand.candidate_seq();
+        let mut scratch = vec![b'$'];
+        let (start, end) = cand.candidate_seq(&mut scratch);
+        let found_seq = &scratch[start..end];
 
         let found_ref_cand = ReferenceCandidate {
             reference_start: expect_start,
             reference_end_excl: expect_end,
             bin: *bin,
             num_seeds: 1,
-            index: &index,
+            sequences: &index.sequences,
         };
 
         assert_eq!(found_ref_cand.bin, cand.bin);
-        assert_eq!(found_seq, &index.sequences[expect_start..expect_end]);
+        assert_eq!(start, 1);
+        assert_eq!(found_seq, &index.reference_bases(expect_start, expect_end)[..]);
 
         let seed_hit2 = SeedHit {
             reference_offset: 115,
             query_offset: 3,
         };
 
-        cand.add_seed_hit(seed_hit2, bin, read_len, edits).unwrap();
+        cand.add_seed_hit(seed_hit2, bin, read_len, edits, 0).unwrap();
 
-        let (_, expect_end2) = seed_hit2.candidate_indices(bin, read_len, edits)
+        let (_, expect_end2) = seed_hit2.candidate_indices(bin, read_len, edits, 0)
             .unwrap();
 
         assert_eq!(expect_start, cand.reference_start);
@@ -765,12 +3042,33 @@ mod test {
             })
             .collect();
 
-        let uppercase = MGIndex::new(uppercase, 32, 64);
-        let lowercase = MGIndex::new(lowercase, 32, 64);
+        let uppercase = MGIndex::new(uppercase, 32, 64, BinMode::PerGi);
+        let lowercase = MGIndex::new(lowercase, 32, 64, BinMode::PerGi);
 
         assert_eq!(uppercase.sequences, lowercase.sequences);
     }
 
+    #[test]
+    fn construct_index_rna() {
+        let dna = random_database(10, 10, 150, 300);
+        let rna = dna.iter()
+            .map(|(&tax_id, seqs)| {
+                let seqs = seqs.iter()
+                    .map(|&(gi, ref seq)| {
+                        (gi, seq.iter().map(|&b| if b == b'T' { b'u' } else { b }).collect())
+                    })
+                    .collect::<Vec<_>>();
+                (tax_id, seqs)
+            })
+            .collect::<Database>();
+
+        let dna = MGIndex::new(dna, 32, 64, BinMode::PerGi);
+        let rna = MGIndex::new(rna, 32, 64, BinMode::PerGi);
+        assert_eq!(dna.sequences, rna.sequences);
+        assert_eq!(dna.fingerprint(), rna.fingerprint());
+        assert_eq!(Alphabet::Protein.normalize(b'U'), b'X');
+    }
+
     #[test]
     fn seed_hits_success() {
         let bin = Bin {
@@ -787,7 +3085,7 @@ mod test {
 
         let read_len = 50;
         let edits = 3;
-        let (cand_start, cand_end) = seed_hit.candidate_indices(&bin, read_len, edits).unwrap();
+        let (cand_start, cand_end) = seed_hit.candidate_indices(&bin, read_len, edits, 0).unwrap();
 
         assert!(cand_start < cand_end);
         assert!(cand_start >= bin.start);
@@ -808,7 +3106,7 @@ mod test {
 
         let read_len = 50;
         let edits = 3;
-        let (cand_start, cand_end) = seed_hit.candidate_indices(&bin, read_len, edits).unwrap();
+        let (cand_start, cand_end) = seed_hit.candidate_indices(&bin, read_len, edits, 0).unwrap();
 
         assert!(cand_start < cand_end);
         assert!(cand_start >= bin.start);
@@ -833,6 +3131,1384 @@ mod test {
 
         let read_len = 50;
         let edits = 3;
-        let _ = seed_hit.candidate_indices(&bin, read_len, edits).unwrap();
+        let _ = seed_hit.candidate_indices(&bin, read_len, edits, 0).unwrap();
+    }
+
+    fn query(index: &MGIndex, read: &[u8], params: &QueryParams) -> (Vec<Hit>, QueryStats) {
+        let fmindex = FMIndex::new(index.suffix_array.bwt(),
+                                   index.suffix_array.less(),
+                                   index.suffix_array.occ());
+        let mut stats = QueryStats::default();
+        let hits = index.matching_tax_ids(&fmindex, read, params, &mut stats);
+
+        (hits, stats)
+    }
+
+    fn tandem_repeat_database() -> Database {
+        let unit = b"ACGTTGCAAGCTTCAGGATCCATGCAAGTC";
+        let mut repeat = Vec::new();
+        for _ in 0..200 {
+            repeat.extend_from_slice(unit);
+        }
+
+        let mut db = random_database(5, 2, 500, 600);
+        db.insert(TaxId(1), vec![(Gi(1), repeat)]);
+        db
+    }
+
+    #[test]
+    fn threaded_build_matches() {
+        for &(taxa, gis, min, max) in &[(1, 1, 10, 20), (3, 2, 100, 200), (20, 5, 500, 3000)] {
+            let db = || random_database(taxa, gis, min, max);
+            let single = MGIndex::new(db(), 16, 32, BinMode::PerGi);
+            for threads in 2..5 {
+                let parallel = MGIndex::with_build_threads(db(), 16, 32, threads, BinMode::PerGi);
+                assert!(parallel.suffix_array.bwt() == single.suffix_array.bwt());
+                assert_eq!(parallel.fingerprint(), single.fingerprint());
+            }
+        }
+        let single = MGIndex::new(tandem_repeat_database(), 16, 32, BinMode::PerGi);
+        let parallel =
+            MGIndex::with_build_threads(tandem_repeat_database(), 16, 32, 3, BinMode::PerGi);
+        assert!(parallel.suffix_array.bwt() == single.suffix_array.bwt());
+    }
+
+    #[test]
+    fn threaded_tables_match() {
+        let index_bytes = |threads| {
+            let db = random_database(8, 3, 200, 2000);
+            let index = MGIndex::with_build_threads(db, 16, 8, threads, BinMode::PerGi);
+            ::bincode::serialize(&index).unwrap()
+        };
+        let single = index_bytes(1);
+        for &threads in &[2, 3, 8] {
+            assert!(index_bytes(threads) == single, "{} threads", threads);
+        }
+    }
+
+    #[test]
+    fn taxa_and_their_bins() {
+        let db = random_database(20, 3, 10, 200);
+        let index = MGIndex::new(db.clone(), 16, 32, BinMode::PerGi);
+
+        assert_eq!(index.tax_ids(), db.keys().cloned().collect::<Vec<_>>());
+        for (&tax_id, references) in &db {
+            assert!(index.contains_tax_id(tax_id));
+            let mut expected = references.iter()
+                .map(|&(gi, ref seq)| (gi, seq.len()))
+                .collect::<Vec<_>>();
+            let mut bins = index.bins_for(tax_id);
+            expected.sort();
+            bins.sort();
+            assert_eq!(bins, expected);
+        }
+
+        let missing = (0..).map(TaxId).find(|tax_id| !db.contains_key(tax_id)).unwrap();
+        assert!(!index.contains_tax_id(missing));
+        assert!(index.bins_for(missing).is_empty());
+        assert!(!index.contains_tax_id(TaxId(u64::MAX)));
+        assert!(MGIndex::new(Database::new(), 16, 32, BinMode::PerGi).tax_ids().is_empty());
+    }
+
+    #[test]
+    fn reference_by_gi() {
+        let db = random_database(5, 3, 10, 200);
+        let index = MGIndex::new(db.clone(), 16, 32, BinMode::PerGi);
+        for &(gi, ref seq) in db.values().flat_map(|refs| refs) {
+            assert_eq!(index.get_reference_by_gi(gi.0).as_ref(), Some(seq));
+        }
+
+        let gis = db.values().flat_map(|refs| refs).map(|&(gi, _)| gi.0).collect::<Vec<_>>();
+        let missing = (0..).find(|gi| !gis.contains(gi)).unwrap();
+        assert_eq!(index.get_reference_by_gi(missing), None);
+    }
+
+    #[test]
+    fn index_stats() {
+        let mut db = Database::new();
+        db.insert(TaxId(1), vec![(Gi(1), b"ACGTACGT".to_vec()), (Gi(2), b"ACG".to_vec())]);
+        db.insert(TaxId(2), vec![(Gi(3), b"TTTTT".to_vec())]);
+        let index = MGIndex::new(db, 4, 8, BinMode::PerGi);
+        assert_eq!(index.sampling_params(), (4, 8));
+
+        let stats = index.stats();
+        assert_eq!((stats.taxa, stats.references, stats.bases), (2, 3, 16));
+        assert_eq!((stats.min_reference_length, stats.max_reference_length), (3, 8));
+        assert!((stats.mean_reference_length - 16.0 / 3.0).abs() < 1e-9);
+
+        let empty = MGIndex::new(Database::new(), 64, 32, BinMode::PerGi).stats();
+        assert_eq!((empty.references, empty.min_reference_length, empty.sample_interval),
+                   (0, 0, 64));
+        assert_eq!(empty.mean_reference_length, 0.0);
+    }
+
+    #[test]
+    fn merged_indices() {
+        let db = random_database(6, 3, 200, 2000);
+        let mut expected = MGIndex::new(db.clone(), 16, 8, BinMode::PerGi);
+        let gis = expected.bins().map(|bin| bin.gi).collect::<Vec<_>>();
+        expected.set_accessions(vec![(gis[0], "A.1".to_string()), (gis[17], "B.1".to_string())]
+            .into_iter()
+            .collect());
+
+        // alternate taxa between the parts so they interleave in the merged index
+        let (mut first, mut second) = (Database::new(), Database::new());
+        for (i, (tax_id, sequences)) in db.into_iter().enumerate() {
+            let part = if i % 2 == 0 { &mut first } else { &mut second };
+            part.insert(tax_id, sequences);
+        }
+        let mut first = MGIndex::new(first, 16, 8, BinMode::PerGi);
+        first.set_accessions(vec![(gis[0], "A.1".to_string())].into_iter().collect());
+        // the first index's sampling parameters are the merged index's
+        let mut second = MGIndex::new(second, 4, 2, BinMode::PerGi);
+        second.set_accessions(vec![(gis[17], "B.1".to_string())].into_iter().collect());
+        second.set_synthetic_gis(true);
+        let second_bins = second.bins().collect::<Vec<_>>();
+
+        let merged = MGIndex::merge(vec![first, second]).unwrap();
+        assert!(merged.synthetic_gis());
+        expected.set_synthetic_gis(true);
+        assert!(::bincode::serialize(&merged).unwrap() == ::bincode::serialize(&expected).unwrap());
+
+        let mut again = Database::new();
+        again.insert(TaxId(1), vec![(second_bins[2].gi, b"ACGT".to_vec())]);
+        match MGIndex::merge(vec![merged, MGIndex::new(again, 16, 8, BinMode::PerGi)]) {
+            Err(e @ MtsvError::DuplicateGi(..)) => {
+                assert_eq!(e.to_string(),
+                           format!("GI {} is in both index 1 and index 2", second_bins[2].gi.0))
+            },
+            Err(e) => panic!("expected a duplicate GI error, got {}", e),
+            Ok(_) => panic!("expected a duplicate GI error"),
+        }
+        assert!(MGIndex::merge(Vec::new()).is_err());
+    }
+
+    #[test]
+    fn index_subset() {
+        let db = random_database(5, 2, 200, 2000);
+        let tax_ids = db.keys().cloned().collect::<Vec<_>>();
+        let mut index = MGIndex::new(db.clone(), 64, 32, BinMode::PerGi);
+        let gis = index.bins().map(|bin| bin.gi).collect::<Vec<_>>();
+        index.set_accessions(gis.iter().map(|&gi| (gi, format!("ACC{}.1", gi.0))).collect());
+        index.set_synthetic_gis(true);
+
+        let wanted = vec![tax_ids[1], tax_ids[3], TaxId(0)].into_iter().collect();
+        let subset = index.subset(&wanted, 16, 8, 2);
+
+        let kept = db.into_iter().filter(|&(tax_id, _)| wanted.contains(&tax_id)).collect();
+        let mut expected = MGIndex::new(kept, 16, 8, BinMode::PerGi);
+        expected.set_accessions(expected.bins()
+            .map(|bin| (bin.gi, format!("ACC{}.1", bin.gi.0)))
+            .collect());
+        expected.set_synthetic_gis(true);
+        assert_eq!(subset.tax_ids(), vec![tax_ids[1], tax_ids[3]]);
+        assert_eq!(subset.accessions().len(), 4);
+        assert!(::bincode::serialize(&subset).unwrap() == ::bincode::serialize(&expected).unwrap());
+    }
+
+    #[test]
+    fn remove_taxa() {
+        let db = random_database(5, 2, 200, 2000);
+        let tax_ids = db.keys().cloned().collect::<Vec<_>>();
+        let mut index = MGIndex::new(db.clone(), 16, 8, BinMode::PerGi);
+        let gis = index.bins().map(|bin| bin.gi).collect::<Vec<_>>();
+        index.set_accessions(gis.iter().map(|&gi| (gi, format!("ACC{}.1", gi.0))).collect());
+
+        // the sampling parameters are kept, and unknown taxids ignored
+        let removed = index.remove(&[tax_ids[0], tax_ids[3], TaxId(0), tax_ids[0]]);
+        let kept = db.into_iter()
+            .filter(|&(tax_id, _)| tax_id != tax_ids[0] && tax_id != tax_ids[3])
+            .collect();
+        let mut expected = MGIndex::new(kept, 16, 8, BinMode::PerGi);
+        expected.set_accessions(expected.bins()
+            .map(|bin| (bin.gi, format!("ACC{}.1", bin.gi.0)))
+            .collect());
+        assert_eq!(removed.tax_ids(), vec![tax_ids[1], tax_ids[2], tax_ids[4]]);
+        let serialized = ::bincode::serialize(&removed).unwrap();
+        assert!(serialized == ::bincode::serialize(&expected).unwrap());
+
+        let unchanged = index.remove(&[]);
+        assert!(::bincode::serialize(&unchanged).unwrap() == ::bincode::serialize(&index).unwrap());
+    }
+
+    #[test]
+    fn streamed_build_matches() {
+        use mktemp::Temp;
+        use std::fs;
+
+        let dir = Temp::new_dir().unwrap();
+        let dir_path = dir.to_path_buf();
+        for db in vec![random_database(8, 3, 200, 2000), tandem_repeat_database()] {
+            let expected =
+                ::bincode::serialize(&MGIndex::new(db.clone(), 16, 8, BinMode::PerGi)).unwrap();
+
+            // out of order and in lowercase, as a FASTA file might have them
+            let mut references = References::new();
+            for (&tax_id, sequences) in db.iter().rev() {
+                for &(gi, ref sequence) in sequences.iter().rev() {
+                    references.push(gi, tax_id, &sequence.to_ascii_lowercase());
+                }
+            }
+            let index = MGIndex::from_references(references,
+                                                 16,
+                                                 8,
+                                                 2,
+                                                 Some(dir_path.to_str().unwrap()))
+                .unwrap();
+            assert!(::bincode::serialize(&index).unwrap() == expected);
+        }
+        // the suffix array's temporary file is removed
+        assert_eq!(fs::read_dir(&dir_path).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn bin_index_boundaries() {
+        let index = MGIndex::new(random_database(3, 2, 50, 51), 16, 32, BinMode::PerGi);
+
+        assert_eq!(index.bin_index(0), Some(0));
+        assert_eq!(index.bin_index(49), Some(0));
+        assert_eq!(index.bin_index(50), Some(1));
+        assert_eq!(index.bin_index(299), Some(5));
+        // the sentinel isn't in any bin
+        assert_eq!(index.bin_index(300), None);
+    }
+
+    #[test]
+    fn resolve_offset_boundaries() {
+        let index = MGIndex::new(random_database(3, 2, 50, 51), 16, 32, BinMode::PerGi);
+        let bins = index.bins().collect::<Vec<_>>();
+        assert_eq!(index.bin_count(), 6);
+        assert_eq!(bins.len(), 6);
+        assert_eq!((bins[1].start, bins[1].end), (50, 100));
+
+        let second = (bins[1].gi, bins[1].tax_id);
+        assert_eq!(index.resolve_offset(50), Some((second.0, second.1, 0)));
+        assert_eq!(index.resolve_offset(99), Some((second.0, second.1, 49)));
+        // the end of one bin is the start of the next
+        assert_eq!(index.resolve_offset(100), Some((bins[2].gi, bins[2].tax_id, 0)));
+        assert_eq!(index.resolve_offset(299), Some((bins[5].gi, bins[5].tax_id, 49)));
+        // the sentinel and anything past it aren't in any reference
+        assert_eq!(index.resolve_offset(300), None);
+        assert_eq!(index.resolve_offset(usize::max_value()), None);
+    }
+
+    #[test]
+    fn absolute_seed_floor() {
+        use rand::{Rng, XorShiftRng};
+        let mut rng = XorShiftRng::new_unseeded();
+        let reference = (0..1000).map(|_| *rng.choose(b"ACGT").unwrap()).collect::<Vec<u8>>();
+        let mut db = Database::new();
+        db.insert(TaxId(1), vec![(Gi(1), reference.clone())]);
+        let index = MGIndex::new(db, 16, 32, BinMode::PerGi);
+
+        // edits every 15 bases from 30 leave only the 4 seeds within the first 30 bases intact
+        let mut read = reference[200..300].to_vec();
+        for i in (30..100).step(15) {
+            read[i] = if read[i] == b'A' { b'C' } else { b'A' };
+        }
+
+        let params = QueryParams { seed_gap: 4, ..QueryParams::default() };
+        assert_eq!(params.min_seeds(21), 1);
+        let (hits, stats) = query(&index, &read, &params);
+        assert_eq!(hits.len(), 1);
+        assert_eq!(stats.candidates_aligned, 1);
+
+        let floor = |min_seeds_absolute| QueryParams { min_seeds_absolute, ..params };
+        assert_eq!(query(&index, &read, &floor(4)).1.candidates_aligned, 1);
+        let (hits, stats) = query(&index, &read, &floor(5));
+        assert!(hits.is_empty());
+        assert_eq!(stats.candidates_aligned, 0);
+
+        // the larger of the scaled and absolute minimums applies
+        let scaled = QueryParams { min_seeds_percent: 0.2, ..params };
+        assert_eq!(scaled.min_seeds(21), 4);
+        assert_eq!(QueryParams { min_seeds_absolute: 5, ..scaled }.min_seeds(21), 5);
+        assert_eq!(QueryParams { min_seeds_absolute: 3, ..scaled }.min_seeds(21), 4);
+    }
+
+    #[test]
+    fn per_bin_hit_cap() {
+        let index = MGIndex::new(tandem_repeat_database(), 16, 32, BinMode::PerGi);
+        let read = index.get_references(1)[0][45..145].to_vec();
+
+        let uncapped = QueryParams {
+            seed_gap: 2,
+            max_hits_per_bin: usize::max_value(),
+            ..QueryParams::default()
+        };
+        let capped = QueryParams { max_hits_per_bin: 20, ..uncapped };
+
+        let (uncapped_hits, uncapped_stats) = query(&index, &read, &uncapped);
+        let (capped_hits, capped_stats) = query(&index, &read, &capped);
+
+        assert!(uncapped_hits.iter().any(|h| h.tax_id == TaxId(1) && h.edit == 0));
+        assert!(capped_hits.iter().any(|h| h.tax_id == TaxId(1) && h.edit == 0));
+
+        assert_eq!(uncapped_stats.capped_bins, 0);
+        assert_eq!(capped_stats.capped_bins, 1);
+        assert_eq!(capped_stats.seed_hits, 20);
+        assert_eq!(capped_stats.seed_hits + capped_stats.seed_hits_capped,
+                   uncapped_stats.seed_hits);
+        assert!(capped_stats.seed_hits * 100 < uncapped_stats.seed_hits);
+    }
+
+    #[test]
+    fn tune_max_hits_widens_seed_interval() {
+        let index = MGIndex::new(tandem_repeat_database(), 16, 32, BinMode::PerGi);
+        // every seed of the read occurs about 200 times in the repeat
+        let read = index.get_references(1)[0][45..145].to_vec();
+
+        let untuned = QueryParams { seed_gap: 2, tune_max_hits: 1000, ..QueryParams::default() };
+        let tuned = QueryParams { tune_max_hits: 50, ..untuned };
+
+        let (untuned_hits, untuned_stats) = query(&index, &read, &untuned);
+        let (tuned_hits, tuned_stats) = query(&index, &read, &tuned);
+
+        // only the forward strand is in the index; the interval doubles after every seed, giving
+        // seeds at offsets 0, 4, 12, 28 and 60 rather than every other offset
+        assert_eq!(untuned_stats.seeds_searched, 42);
+        assert_eq!(tuned_stats.seeds_searched, 5);
+        assert!(untuned_hits.iter().any(|h| h.tax_id == TaxId(1) && h.edit == 0));
+        assert!(tuned_hits.iter().any(|h| h.tax_id == TaxId(1) && h.edit == 0));
+
+        assert!(untuned.validate().is_ok());
+        assert!(QueryParams { tune_max_hits: untuned.max_hits, ..untuned }.validate().is_ok());
+        match (QueryParams { max_hits: 999, ..untuned }).validate() {
+            Err(MtsvError::InvalidParameter(msg)) => assert!(msg.contains("(1000)")),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn unusable_seeds() {
+        let index = MGIndex::new(tandem_repeat_database(), 16, 32, BinMode::PerGi);
+        let read = index.get_references(1)[0][45..62].to_vec();
+
+        // the read is a base shorter than a seed
+        let (hits, stats) = query(&index, &read, &QueryParams::default());
+        assert!(hits.is_empty());
+        assert_eq!(stats.seeds_searched, 0);
+
+        for params in &[QueryParams { seed_length: 0, ..QueryParams::default() },
+                        QueryParams { seed_gap: 0, ..QueryParams::default() }] {
+            match params.validate() {
+                Err(MtsvError::InvalidParameter(msg)) => assert!(msg.contains("at least 1")),
+                other => panic!("unexpected result: {:?}", other),
+            }
+        }
+        assert_eq!(QueryParams::default().tune_max_hits, 200);
+    }
+
+    #[test]
+    fn seed_hits_limited_per_read() {
+        let index = MGIndex::new(tandem_repeat_database(), 16, 32, BinMode::PerGi);
+        // every seed of the read occurs about 200 times in the repeat
+        let read = index.get_references(1)[0][45..145].to_vec();
+
+        let unlimited = QueryParams {
+            seed_gap: 2,
+            tune_max_hits: 1000,
+            max_hits_per_bin: usize::max_value(),
+            ..QueryParams::default()
+        };
+        let limited = QueryParams { max_seed_hits_per_read: 1000, ..unlimited };
+
+        let (unlimited_hits, unlimited_stats) = query(&index, &read, &unlimited);
+        let (limited_hits, limited_stats) = query(&index, &read, &limited);
+
+        assert_eq!(unlimited_stats.hit_limited_queries, 0);
+        assert_eq!(unlimited_stats.seeds_over_hit_limit, 0);
+        assert!(unlimited_stats.seed_hits > 8_000);
+
+        // recording stops short of the limit, and the rest of the seeds aren't searched
+        assert_eq!(limited_stats.hit_limited_queries, 1);
+        assert!(limited_stats.seed_hits <= 1000);
+        assert!(limited_stats.seed_hits > 800);
+        assert_eq!(limited_stats.seeds_searched + limited_stats.seeds_over_hit_limit,
+                   unlimited_stats.seeds_searched);
+
+        // the seeds which were searched are still enough to find the read's reference
+        assert!(unlimited_hits.iter().any(|h| h.tax_id == TaxId(1) && h.edit == 0));
+        assert!(limited_hits.iter().any(|h| h.tax_id == TaxId(1) && h.edit == 0));
+    }
+
+    #[test]
+    fn seeds_with_ns_skipped() {
+        use rand::{Rng, XorShiftRng};
+        let mut rng = XorShiftRng::new_unseeded();
+        let mut random_seq = |len: usize| {
+            (0..len).map(|_| *rng.choose(b"ACGT").unwrap()).collect::<Vec<u8>>()
+        };
+        let n_run = vec![b'N'; 24];
+
+        // two unrelated references which both contain the same run of Ns
+        let mut db = Database::new();
+        let first = [random_seq(300), n_run.clone(), random_seq(300)].concat();
+        let second = [random_seq(300), n_run.clone(), random_seq(300)].concat();
+        db.insert(TaxId(1), vec![(Gi(1), first.clone())]);
+        db.insert(TaxId(2), vec![(Gi(2), second)]);
+        let index = MGIndex::new(db, 16, 32, BinMode::PerGi);
+
+        // the run of Ns is within the edit distance of a read this long
+        let read = first[200..400].to_vec();
+
+        let params = QueryParams { seed_gap: 2, ..QueryParams::default() };
+        let (hits, stats) = query(&index, &read, &params);
+        assert_eq!(hits.iter().map(|h| h.tax_id).collect::<Vec<_>>(), vec![TaxId(1)]);
+        // the Ns are at offsets 100-123, so seeds from 84 to 122 overlap them, and every other
+        // seed hits the read's own reference once, leaving nothing to seed a candidate elsewhere
+        assert_eq!(stats.seeds_searched, 92 - 20);
+        assert_eq!(stats.seeds_with_ns, 20);
+        assert_eq!(stats.seed_hits, 92 - 20);
+
+        // searched verbatim, the seeds of only Ns hit all 7 positions of each reference's run
+        let verbatim = QueryParams { max_seed_ns: 18, skip_n_seed_hits: false, ..params };
+        let (hits, stats) = query(&index, &read, &verbatim);
+        assert_eq!(hits.iter().map(|h| h.tax_id).collect::<Vec<_>>(), vec![TaxId(1)]);
+        assert_eq!(stats.seeds_searched, 92);
+        assert_eq!(stats.seeds_with_ns, 0);
+        assert_eq!(stats.seed_hits, 92 - 4 + 4 * 7 * 2);
+        assert_eq!(stats.seed_hits_in_ns, 0);
+
+        // unless their hits in the runs are dropped, along with those of the seeds partly in them
+        let (hits, stats) = query(&index, &read, &QueryParams { max_seed_ns: 18, ..params });
+        assert_eq!(hits.iter().map(|h| h.tax_id).collect::<Vec<_>>(), vec![TaxId(1)]);
+        assert_eq!(stats.seeds_searched, 92 - 20);
+        assert_eq!(stats.seeds_with_ns, 0);
+        assert_eq!(stats.seed_hits, 92 - 20);
+        assert_eq!(stats.seed_hits_in_ns, 16 + 4 * 7 * 2);
+
+        // a read of only Ns would otherwise seed candidates in both runs, though it can never
+        // match them
+        let all_ns = vec![b'N'; 100];
+        let (hits, stats) = query(&index, &all_ns, &verbatim);
+        assert!(hits.is_empty());
+        assert_eq!(stats.seed_hits, 42 * 7 * 2);
+        assert_eq!(stats.seed_hits_in_ns, 0);
+        let (hits, stats) = query(&index, &all_ns, &QueryParams { max_seed_ns: 18, ..params });
+        assert!(hits.is_empty());
+        assert_eq!(stats.seed_hits, 0);
+        assert_eq!(stats.seed_hits_in_ns, 42 * 7 * 2);
+
+        // allowing a few Ns searches the seeds only just overlapping the run
+        let (_, stats) = query(&index, &read, &QueryParams { max_seed_ns: 4, ..params });
+        assert_eq!(stats.seeds_with_ns, 20 - 2 * 2);
+    }
+
+    #[test]
+    fn soft_masked_repeats_not_seeded() {
+        use io::write_to_file;
+        use mktemp::Temp;
+        use rand::{Rng, XorShiftRng};
+        let mut rng = XorShiftRng::new_unseeded();
+        let mut random_seq = |len: usize| {
+            (0..len).map(|_| *rng.choose(b"ACGT").unwrap()).collect::<Vec<u8>>()
+        };
+
+        // three unrelated references which all contain the same soft-masked repeat
+        let repeat = random_seq(200).to_ascii_lowercase();
+        let db = (1..4)
+            .map(|t| (TaxId(t), Gi(t as u32), [random_seq(300), repeat.clone(), random_seq(300)]
+                .concat()))
+            .collect::<Vec<_>>();
+        let build = |respect, reversed| {
+            let mut references = References::new();
+            references.set_respect_softmask(respect);
+            let mut refs = db.iter().collect::<Vec<_>>();
+            if reversed {
+                refs.reverse();
+            }
+            for &&(tax_id, gi, ref seq) in &refs {
+                references.push(gi, tax_id, seq);
+            }
+            MGIndex::from_references(references, 16, 32, 1, None).unwrap()
+        };
+        let plain = build(false, false);
+        let masked = build(true, true);
+        assert_eq!(masked.sequences, plain.sequences);
+        assert_eq!(masked.fingerprint(), plain.fingerprint());
+        assert_eq!(masked.soft_masked, vec![(300, 500), (1100, 1300), (1900, 2100)]);
+        assert_eq!(plain.soft_masked_bases(), 0);
+        assert!(masked.overlaps_soft_mask(250, 301));
+        assert!(!masked.overlaps_soft_mask(500, 1100));
+
+        // a read from the first reference running into the repeat only seeds before it, but
+        // still aligns across it
+        let read = db[0].2[200..400].to_ascii_uppercase();
+        let params = QueryParams { seed_gap: 2, edit_freq: 0.0, ..QueryParams::default() };
+        let (plain_hits, plain_stats) = query(&plain, &read, &params);
+        let (masked_hits, masked_stats) = query(&masked, &read, &params);
+        assert_eq!(plain_hits.iter().map(|h| h.tax_id).collect::<Vec<_>>(), vec![TaxId(1)]);
+        assert_eq!(masked_hits.iter().map(|h| h.tax_id).collect::<Vec<_>>(), vec![TaxId(1)]);
+        // only the 42 seeds before the repeat hit once masked, rather than another 8 running
+        // into it and 42 inside it, which hit all three copies
+        assert_eq!(masked_stats.seed_hits, 42);
+        assert!(plain_stats.seed_hits >= 42 + 8 + 42 * 3);
+
+        // the masked intervals are kept by rebuilt and saved indices
+        let subset = masked.subset(&[TaxId(1), TaxId(3)].iter().cloned().collect(), 16, 32, 1);
+        assert_eq!(subset.soft_masked, vec![(300, 500), (1100, 1300)]);
+        let dir = Temp::new_dir().unwrap();
+        let path = dir.to_path_buf().join("masked.index").to_str().unwrap().to_string();
+        write_to_file(&masked, &path).unwrap();
+        assert_eq!(MGIndex::from_file(&path).unwrap().soft_masked, masked.soft_masked);
+    }
+
+    #[test]
+    fn taxon_bins() {
+        use rand::{Rng, XorShiftRng};
+        let mut rng = XorShiftRng::new_unseeded();
+        let mut random_seq = |len: usize| {
+            (0..len).map(|_| *rng.choose(b"ACGT").unwrap()).collect::<Vec<u8>>()
+        };
+
+        // two contigs of taxid 1, the second starting with a soft-masked run, and one of taxid 2
+        let mut second = random_seq(300);
+        second[..50].make_ascii_lowercase();
+        let db = vec![(TaxId(1), Gi(1), random_seq(300)),
+                      (TaxId(1), Gi(2), second),
+                      (TaxId(2), Gi(3), random_seq(300))];
+        let build = |bin_mode, reversed| {
+            let mut references = References::new();
+            references.set_respect_softmask(true);
+            references.set_bin_mode(bin_mode);
+            let mut refs = db.iter().collect::<Vec<_>>();
+            if reversed {
+                refs.reverse();
+            }
+            for &&(tax_id, gi, ref seq) in &refs {
+                references.push(gi, tax_id, seq);
+            }
+            MGIndex::from_references(references, 16, 32, 1, None).unwrap()
+        };
+        let per_gi = build(BinMode::PerGi, false);
+        let spaced = build(BinMode::PerTaxon { spacer_len: 100 }, true);
+        let joined = build(BinMode::PerTaxon { spacer_len: 0 }, false);
+        assert_eq!(per_gi.bin_count(), 3);
+        assert_eq!(spaced.bins().map(|b| (b.gi, b.tax_id, b.start, b.end)).collect::<Vec<_>>(),
+                   vec![(Gi(0), TaxId(1), 0, 700), (Gi(0), TaxId(2), 700, 1000)]);
+        assert_eq!(spaced.reference_bases(300, 400), vec![b'N'; 100]);
+        assert_eq!(spaced.soft_masked, vec![(400, 450)]);
+        assert_eq!(joined.bins().map(|b| (b.start, b.end)).collect::<Vec<_>>(),
+                   vec![(0, 600), (600, 900)]);
+
+        // a read within a contig hits its taxid, with no GI
+        let params = QueryParams { seed_gap: 2, ..QueryParams::default() };
+        let inside = db[1].2[100..200].to_vec();
+        let (hits, _) = query(&spaced, &inside, &params);
+        assert_eq!(hits.iter().map(|h| (h.tax_id, h.gi)).collect::<Vec<_>>(),
+                   vec![(TaxId(1), Gi(0))]);
+
+        // a read spanning the end of one contig and the start of the next only aligns if
+        // there's no spacer between them
+        let spanning = [&db[0].2[250..], &db[1].2[..50]].concat().to_ascii_uppercase();
+        assert!(query(&spaced, &spanning, &params).0.is_empty());
+        assert!(query(&per_gi, &spanning, &params).0.is_empty());
+        let (hits, _) = query(&joined, &spanning, &params);
+        assert_eq!(hits.iter().map(|h| h.tax_id).collect::<Vec<_>>(), vec![TaxId(1)]);
+    }
+
+    #[test]
+    fn low_complexity_masked() {
+        use rand::{Rng, XorShiftRng};
+        let mut rng = XorShiftRng::new_unseeded();
+        let mut random_seq = |len: usize| {
+            (0..len).map(|_| *rng.choose(b"ACGT").unwrap()).collect::<Vec<u8>>()
+        };
+
+        // two references with a poly-A run, one of them soft-masked next to it
+        let first = [random_seq(300), vec![b'A'; 200], random_seq(300)].concat();
+        let second = [random_seq(300), b"acgtacgt".to_vec(), vec![b'A'; 150], random_seq(300)]
+            .concat();
+        let build = |dust| {
+            let mut references = References::new();
+            references.set_respect_softmask(true);
+            references.set_mask_low_complexity(dust);
+            references.push(Gi(1), TaxId(1), &first);
+            references.push(Gi(2), TaxId(2), &second);
+            let low_complexity = references.low_complexity_bases();
+            (MGIndex::from_references(references, 16, 32, 1, None).unwrap(), low_complexity)
+        };
+        let (plain, none) = build(None);
+        let (masked, low_complexity) = build(Some(DustParams::default()));
+        assert_eq!(none, 0);
+        assert_eq!(masked.sequences, plain.sequences);
+        assert_eq!(plain.soft_masked, vec![(1100, 1108)]);
+        // the poly-A runs and the windows running into them are masked, merged with the
+        // soft-masked bases in the second reference
+        assert_eq!(masked.soft_masked.len(), 2);
+        assert!(masked.soft_masked[0].0 <= 300 && masked.soft_masked[0].1 >= 500);
+        assert!(masked.soft_masked[1].0 <= 1100 && masked.soft_masked[1].1 >= 1258);
+        assert!(low_complexity >= 200 + 150);
+        assert!(masked.soft_masked_bases() >= low_complexity);
+
+        // a read from the homopolymer no longer has any seed hits, while one from the rest of
+        // the reference still finds it
+        let params = QueryParams { seed_gap: 2, ..QueryParams::default() };
+        let homopolymer = vec![b'A'; 100];
+        let (_, plain_stats) = query(&plain, &homopolymer, &params);
+        let (hits, stats) = query(&masked, &homopolymer, &params);
+        assert!(plain_stats.seed_hits > 0);
+        assert_eq!(stats.seed_hits, 0);
+        assert!(hits.is_empty());
+
+        let read = first[600..750].to_vec();
+        let (hits, stats) = query(&masked, &read, &params);
+        assert_eq!(hits.iter().map(|h| h.tax_id).collect::<Vec<_>>(), vec![TaxId(1)]);
+        assert!(stats.seed_hits > 0);
+    }
+
+    #[test]
+    fn free_ns_at_zero_edit_rate() {
+        use rand::{Rng, XorShiftRng};
+        let mut rng = XorShiftRng::new_unseeded();
+        let reference = (0..600).map(|_| *rng.choose(b"ACGT").unwrap()).collect::<Vec<u8>>();
+        let mut db = Database::new();
+        db.insert(TaxId(1), vec![(Gi(1), reference.clone())]);
+        let index = MGIndex::new(db, 16, 32, BinMode::PerGi);
+
+        // exact apart from two Ns
+        let mut read = reference[100..200].to_vec();
+        read[30] = b'N';
+        read[70] = b'N';
+
+        let exact = QueryParams { edit_freq: 0.0, seed_gap: 2, ..QueryParams::default() };
+        let (hits, _) = query(&index, &read, &exact);
+        assert!(hits.is_empty());
+
+        let (hits, _) = query(&index, &read, &QueryParams { max_free_ns: Some(2), ..exact });
+        assert_eq!(hits.iter().map(|h| (h.tax_id, h.edit)).collect::<Vec<_>>(),
+                   vec![(TaxId(1), 0)]);
+
+        // with more Ns than are free, every N counts
+        let (hits, _) = query(&index, &read, &QueryParams { max_free_ns: Some(1), ..exact });
+        assert!(hits.is_empty());
+        let (hits, _) = query(&index,
+                              &read,
+                              &QueryParams { max_free_ns: Some(1), edit_freq: 0.02, ..exact });
+        assert_eq!(hits.iter().map(|h| h.edit).collect::<Vec<_>>(), vec![2]);
+    }
+
+    #[test]
+    fn ambiguity_codes_expand() {
+        use rand::{Rng, XorShiftRng};
+        let mut rng = XorShiftRng::new_unseeded();
+        let mut reference = (0..600).map(|_| *rng.choose(b"ACGT").unwrap()).collect::<Vec<u8>>();
+        reference[150] = b'A';
+        let edits = |index: &MGIndex, read: &[u8], ambiguity| {
+            let params = QueryParams {
+                edit_freq: 0.02,
+                seed_gap: 2,
+                ambiguity,
+                ..QueryParams::default()
+            };
+            query(index, read, &params).0.iter().map(|h| h.edit).collect::<Vec<_>>()
+        };
+
+        // an R in the read over an A in the reference
+        let mut db = Database::new();
+        db.insert(TaxId(1), vec![(Gi(1), reference.clone())]);
+        let index = MGIndex::new(db, 16, 32, BinMode::PerGi);
+        let mut read = reference[100..200].to_vec();
+        read[50] = b'R';
+        let as_n = read.iter().map(|&b| Alphabet::Dna.normalize(b)).collect::<Vec<_>>();
+        assert_eq!(edits(&index, &read, AmbiguityPolicy::Expand), vec![0]);
+        assert_eq!(edits(&index, &as_n, AmbiguityPolicy::N), vec![1]);
+        // a code which doesn't include the reference's base is still an edit
+        read[50] = b'Y';
+        assert_eq!(edits(&index, &read, AmbiguityPolicy::Expand), vec![1]);
+
+        // an A in the read over an R kept in the reference, which isn't seeded
+        let mut ambiguous = reference.clone();
+        ambiguous[150] = b'r';
+        let build = |ambiguity| {
+            let mut references = References::new();
+            references.set_ambiguity(ambiguity);
+            references.push(Gi(1), TaxId(1), &ambiguous);
+            MGIndex::from_references(references, 16, 32, 1, None).unwrap()
+        };
+        let kept = build(AmbiguityPolicy::Expand);
+        let replaced = build(AmbiguityPolicy::N);
+        assert_eq!(kept.reference_bases(150, 151), b"R");
+        assert_eq!(replaced.reference_bases(150, 151), b"N");
+        assert_eq!(kept.suffix_array.bwt(), replaced.suffix_array.bwt());
+        let read = &reference[100..200];
+        assert_eq!(edits(&kept, read, AmbiguityPolicy::Expand), vec![0]);
+        assert_eq!(edits(&kept, read, AmbiguityPolicy::N), vec![1]);
+        assert_eq!(edits(&replaced, read, AmbiguityPolicy::Expand), vec![1]);
+    }
+
+    /// Mutate roughly `rate` of the bases in `seq` to a random base.
+    fn mutate<R: ::rand::Rng>(rng: &mut R, seq: &[u8], rate: f64) -> Vec<u8> {
+        seq.iter()
+            .map(|&b| if rng.gen::<f64>() < rate { *rng.choose(b"ACGT").unwrap() } else { b })
+            .collect()
+    }
+
+    #[test]
+    fn reproducible_across_input_order() {
+        use rand::{Rng, XorShiftRng};
+        let mut rng = XorShiftRng::new_unseeded();
+
+        let db = random_database(10, 5, 200, 300);
+        let shuffled = db.iter()
+            .map(|(&taxid, seqs)| {
+                let mut seqs = seqs.clone();
+                rng.shuffle(&mut seqs);
+                (taxid, seqs)
+            })
+            .collect::<Database>();
+        assert!(shuffled != db);
+
+        let first = MGIndex::new(db, 16, 32, BinMode::PerGi);
+        let second = MGIndex::new(shuffled, 16, 32, BinMode::PerGi);
+
+        assert_eq!(first.fingerprint(), second.fingerprint());
+        assert_eq!(first.sequences, second.sequences);
+        assert_eq!(first.bins, second.bins);
+
+        let params = QueryParams { seed_gap: 4, ..QueryParams::default() };
+        for bin in first.bins.iter().step(7) {
+            let read = first.reference_bases(bin.start, bin.start + 100);
+            assert_eq!(query(&first, &read, &params).0, query(&second, &read, &params).0);
+        }
+    }
+
+    #[test]
+    fn fingerprint_tracks_contents() {
+        let db = random_database(3, 2, 100, 200);
+        let mut renumbered = db.clone();
+        renumbered.values_mut().next().unwrap()[0].0 = Gi(7);
+        let mut edited = db.clone();
+        {
+            let base = &mut edited.values_mut().next().unwrap()[0].1[10];
+            *base = if *base == b'A' { b'C' } else { b'A' };
+        }
+
+        let index = MGIndex::new(db, 16, 32, BinMode::PerGi);
+        let resampled = MGIndex::new(random_database(3, 2, 100, 200), 32, 64, BinMode::PerGi);
+        assert_eq!(index.fingerprint(), resampled.fingerprint());
+
+        let fingerprint = |db| MGIndex::new(db, 16, 32, BinMode::PerGi).fingerprint();
+        assert!(index.fingerprint() != fingerprint(renumbered));
+        assert!(index.fingerprint() != fingerprint(edited));
+    }
+
+    #[test]
+    fn loads_older_indices() {
+        use bincode::serialize_into;
+        use io::{write_to_file, ENVELOPE_MAGIC};
+        use mktemp::Temp;
+        use std::fs::File;
+        use std::io::Write;
+
+        let dir = Temp::new_dir().unwrap();
+        let path = |name: &str| dir.to_path_buf().join(name).to_str().unwrap().to_string();
+
+        let mut index = MGIndex::new(random_database(3, 2, 100, 200), 16, 32, BinMode::PerGi);
+        index.set_accessions(vec![(Gi(1), String::from("NZ_CP012345.1"))].into_iter().collect());
+        write_to_file(&index, &path("current.index")).unwrap();
+        let current = MGIndex::from_file(&path("current.index")).unwrap();
+        assert_eq!(current.accession(Gi(1)), Some("NZ_CP012345.1"));
+        assert_eq!(current.accession(Gi(2)), None);
+
+        // indices written with 32-bit taxids, before and after accessions were recorded, with
+        // 64-bit taxids before the sequences were packed, packed before the alphabet was
+        // recorded, with the alphabet before soft-masking was recorded, and with soft-masking
+        // before descriptions were, and with descriptions before the checksum was written
+        let accessions = index.accessions.clone();
+        let with_alphabet = PackedIndex {
+            sequences: index.sequences.clone(),
+            bins: index.bins.clone(),
+            suffix_array: index.suffix_array.clone(),
+            synthetic_gis: index.synthetic_gis,
+            fingerprint: index.fingerprint,
+            accessions: accessions.clone(),
+        };
+        let packed = PackedIndex {
+            sequences: match index.sequences {
+                IndexText::Packed(ref sequences) => sequences.clone(),
+                IndexText::Plain(_) => unreachable!(),
+            },
+            bins: index.bins.clone(),
+            suffix_array: index.suffix_array.clone(),
+            synthetic_gis: index.synthetic_gis,
+            fingerprint: index.fingerprint,
+            accessions: accessions.clone(),
+        };
+        let unpacked = UnpackedIndex {
+            sequences: index.sequences.unpack(),
+            bins: index.bins.clone(),
+            suffix_array: index.suffix_array,
+            synthetic_gis: index.synthetic_gis,
+            fingerprint: index.fingerprint,
+        };
+        let old = UnpackedIndex {
+            sequences: unpacked.sequences.clone(),
+            bins: index.bins
+                .iter()
+                .map(|bin| {
+                    NarrowBin {
+                        gi: bin.gi,
+                        tax_id: bin.tax_id.0 as u32,
+                        start: bin.start,
+                        end: bin.end,
+                    }
+                })
+                .collect(),
+            suffix_array: unpacked.suffix_array.clone(),
+            synthetic_gis: index.synthetic_gis,
+            fingerprint: index.fingerprint,
+        };
+        for &version in &[1u32, 2, 3, 4, 5, 6, 7, 8, 9] {
+            // version 1 indices have no header at all
+            let name = format!("v{}.index", version);
+            let mut file = File::create(path(&name)).unwrap();
+            if version > 1 {
+                file.write_all(ENVELOPE_MAGIC).unwrap();
+                file.write_all(&version.to_le_bytes()).unwrap();
+                file.write_all(&[0]).unwrap();
+            }
+            if version >= 5 {
+                file.write_all(b"\x052.0.0").unwrap();
+            }
+            if version < 4 {
+                serialize_into(&mut file, &old).unwrap();
+            } else if version < 6 {
+                serialize_into(&mut file, &unpacked).unwrap();
+            } else if version < 7 {
+                serialize_into(&mut file, &packed).unwrap();
+            } else {
+                serialize_into(&mut file, &with_alphabet).unwrap();
+                serialize_into(&mut file, &Alphabet::Dna).unwrap();
+            }
+            if version >= 8 {
+                serialize_into(&mut file, &index.soft_masked).unwrap();
+            }
+            if version >= 9 {
+                serialize_into(&mut file, &index.descriptions).unwrap();
+            }
+            if version >= 3 && version < 6 {
+                serialize_into(&mut file, &accessions).unwrap();
+            }
+            drop(file);
+
+            let loaded = MGIndex::from_file(&path(&name)).unwrap();
+            assert_eq!(loaded.has_accessions(), version >= 3);
+            assert_eq!(loaded.fingerprint(), current.fingerprint());
+            assert_eq!(loaded.bins, current.bins);
+            assert_eq!(loaded.sequences, current.sequences);
+            assert_eq!(loaded.sampling_params(), current.sampling_params());
+
+            let mapped_name = format!("v{}.mapped", version);
+            convert_to_mapped(&path(&name), &path(&mapped_name)).unwrap();
+            let mapped = MGIndex::open_mmap(&path(&mapped_name)).unwrap();
+            assert_eq!(mapped.has_accessions(), version >= 3);
+            assert_eq!(mapped.fingerprint(), current.fingerprint());
+            assert_eq!(mapped.bins().collect::<Vec<_>>(), current.bins().collect::<Vec<_>>());
+            assert_eq!(mapped.reference_sequence(), current.reference_sequence());
+        }
+    }
+
+    #[test]
+    fn rejects_incompatible_indices() {
+        use io::write_to_file;
+        use mktemp::Temp;
+        use std::fs;
+
+        let dir = Temp::new_dir().unwrap();
+        let path = |name: &str| dir.to_path_buf().join(name).to_str().unwrap().to_string();
+        let incompatible = |name: &str, why: &str| {
+            for result in &[MGIndex::from_file(&path(name)).map(|_| ()),
+                            IndexCapabilities::of_file(&path(name)).map(|_| ())] {
+                match *result {
+                    Err(ref e @ MtsvError::IncompatibleIndex(..)) => {
+                        assert!(e.to_string().contains(why), "{}: {}", name, e)
+                    },
+                    ref other => panic!("{} wasn't rejected: {:?}", name, other),
+                }
+            }
+        };
+
+        write_to_file(&MGIndex::new(random_database(3, 2, 100, 200), 16, 32, BinMode::PerGi),
+                      &path("current.index"))
+            .unwrap();
+        let bytes = fs::read(path("current.index")).unwrap();
+
+        // files which were never indices, or are only the start of one
+        fs::write(path("reads.fasta"), b">read1\nACGTACGTACGTACGTACGTACGTACGT\n").unwrap();
+        incompatible("reads.fasta", "no mtsv index header");
+        fs::write(path("short"), b"ACGT").unwrap();
+        incompatible("short", "no mtsv index header");
+        fs::write(path("header.index"), &bytes[..12]).unwrap();
+        incompatible("header.index", "header is truncated");
+
+        // a truncated serialization is only found when it's read
+        fs::write(path("truncated.index"), &bytes[..bytes.len() / 2]).unwrap();
+        assert!(IndexCapabilities::of_file(&path("truncated.index")).is_ok());
+        match MGIndex::from_file(&path("truncated.index")) {
+            Err(e @ MtsvError::IncompatibleIndex(..)) => {
+                assert!(e.to_string().contains("truncated"))
+            },
+            other => panic!("truncated index wasn't rejected: {:?}", other.map(|_| ())),
+        }
+
+        // a format this build doesn't know, say from a later release
+        let mut newer = bytes.clone();
+        newer[8..12].copy_from_slice(&(INDEX_FORMAT_VERSION + 1).to_le_bytes());
+        fs::write(path("newer.index"), &newer).unwrap();
+        incompatible("newer.index",
+                     &format!("built with format {} by mtsv {}",
+                              INDEX_FORMAT_VERSION + 1,
+                              env!("CARGO_PKG_VERSION")));
+    }
+
+    #[test]
+    fn corrupt_indices() {
+        use io::{verify_checksum, write_to_file, write_to_file_compressed};
+        use mktemp::Temp;
+        use std::fs;
+
+        let dir = Temp::new_dir().unwrap();
+        let path = |name: &str| dir.to_path_buf().join(name).to_str().unwrap().to_string();
+        let corrupt = |name: &str| match MGIndex::from_file(&path(name)) {
+            Err(MtsvError::CorruptIndex(p, expected, found)) => {
+                assert_eq!(p, path(name));
+                assert!(expected != found);
+                (expected, found)
+            },
+            other => panic!("{} wasn't found to be corrupt: {:?}", name, other.map(|_| ())),
+        };
+
+        let index = MGIndex::new(random_database(3, 2, 100, 200), 16, 32, BinMode::PerGi);
+        write_to_file(&index, &path("current.index")).unwrap();
+        write_to_file_compressed(&index, &path("compressed.index"), 1).unwrap();
+        for name in &["current.index", "compressed.index"] {
+            assert_eq!(MGIndex::from_file(&path(name)).unwrap().bins, index.bins);
+            assert!(verify_checksum(&path(name)).is_ok());
+        }
+        let bytes = fs::read(path("current.index")).unwrap();
+
+        // a flipped byte in the serialization, whether or not it still parses (byte 19, after
+        // the header, is the sequences' variant, which becomes one that doesn't exist)
+        for &at in &[19, 40, bytes.len() / 2] {
+            let mut flipped = bytes.clone();
+            flipped[at] ^= 0x10;
+            fs::write(path("flipped.index"), &flipped).unwrap();
+            let (expected, _) = corrupt("flipped.index");
+            assert_eq!(expected.to_le_bytes(), bytes[bytes.len() - 8..]);
+            match verify_checksum(&path("flipped.index")) {
+                Err(MtsvError::CorruptIndex(..)) => (),
+                other => panic!("byte {} wasn't found to be corrupt: {:?}", at, other),
+            }
+        }
+
+        // or in the checksum itself, which only matters if it's checked
+        let mut flipped = bytes.clone();
+        *flipped.last_mut().unwrap() ^= 0x10;
+        fs::write(path("checksum.index"), &flipped).unwrap();
+        let (_, found) = corrupt("checksum.index");
+        assert_eq!(found.to_le_bytes(), bytes[bytes.len() - 8..]);
+        let unverified = MGIndex::from_file_unverified(&path("checksum.index")).unwrap();
+        assert_eq!(unverified.bins, index.bins);
+        assert_eq!(unverified.fingerprint(), index.fingerprint());
+
+        // a missing checksum is a truncated index
+        fs::write(path("unsummed.index"), &bytes[..bytes.len() - 8]).unwrap();
+        match MGIndex::from_file(&path("unsummed.index")) {
+            Err(e @ MtsvError::IncompatibleIndex(..)) => {
+                assert!(e.to_string().contains("truncated"))
+            },
+            other => panic!("index without a checksum wasn't rejected: {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn mapped_index_matches_deserialized() {
+        use io::{from_file, write_to_file_compressed};
+        use mktemp::Temp;
+        use rand::{Rng, XorShiftRng};
+        use std::fs;
+
+        let dir = Temp::new_dir().unwrap();
+        let path = |name: &str| dir.to_path_buf().join(name).to_str().unwrap().to_string();
+        let mut rng = XorShiftRng::new_unseeded();
+
+        for &(sample_interval, suffix_sample) in &[(16, 32), (1, 1), (100, 7)] {
+            let mut index = MGIndex::new(random_database(5, 4, 300, 600),
+                                         sample_interval,
+                                         suffix_sample,
+                                         BinMode::PerGi);
+            index.set_accessions(vec![(Gi(7), String::from("NC_000913.3"))].into_iter().collect());
+            index.write_mapped(&path("direct.mapped")).unwrap();
+            write_to_file_compressed(&index, &path("compressed.index"), 1).unwrap();
+            convert_to_mapped(&path("compressed.index"), &path("converted.mapped")).unwrap();
+            assert_eq!(fs::read(path("direct.mapped")).unwrap(),
+                       fs::read(path("converted.mapped")).unwrap());
+
+            let mapped = MGIndex::open_mmap(&path("direct.mapped")).unwrap();
+            assert_eq!(mapped.fingerprint(), index.fingerprint());
+            assert_eq!(mapped.accessions(), index.accessions());
+            assert_eq!(mapped.bins().collect::<Vec<_>>(), index.bins().collect::<Vec<_>>());
+            let tax_id = index.bins[2].tax_id.0;
+            assert_eq!(mapped.get_references(tax_id), index.get_references(tax_id));
+
+            // the same intervals and suffix array entries, including for partial matches
+            let fmindex = FMIndex::new(index.suffix_array.bwt(),
+                                       index.suffix_array.less(),
+                                       index.suffix_array.occ());
+            let sequence = index.reference_sequence();
+            for _ in 0..200 {
+                let start = rng.gen_range(0, sequence.len() - 30);
+                let mut kmer = sequence[start..start + rng.gen_range(1, 30)].to_vec();
+                if rng.gen() {
+                    let i = rng.gen_range(0, kmer.len());
+                    kmer[i] = *rng.choose(b"ACGTN").unwrap();
+                }
+                let expected = fmindex.backward_search(kmer.iter());
+                assert_eq!(mapped.file.backward_search(&kmer), expected);
+                let interval = match expected {
+                    BackwardSearchResult::Complete(i) | BackwardSearchResult::Partial(i, _) => i,
+                    BackwardSearchResult::Absent => continue,
+                };
+                assert_eq!(mapped.file.locate(&interval), interval.occ(&index.suffix_array));
+            }
+
+            // and the same hits
+            let params = QueryParams { seed_gap: 4, ..QueryParams::default() };
+            for _ in 0..50 {
+                let start = rng.gen_range(0, sequence.len() - 100);
+                let read = mutate(&mut rng, &sequence[start..start + 100], 0.05);
+                let mut expected_stats = QueryStats::default();
+                let mut stats = QueryStats::default();
+                let expected =
+                    index.matching_tax_ids(&fmindex, &read, &params, &mut expected_stats);
+                assert_eq!(mapped.matching_tax_ids(&read, &params, &mut stats), expected);
+                assert_eq!(stats, expected_stats);
+            }
+        }
+
+        // tools which only read bincode indices say what they were given
+        match from_file::<MGIndex>(&path("direct.mapped")) {
+            Err(MtsvError::MappedIndex(_)) => (),
+            Err(other) => panic!("expected a mapped index error, got {}", other),
+            Ok(_) => panic!("expected a mapped index error"),
+        }
+        let capabilities = IndexCapabilities::of_file(&path("direct.mapped")).unwrap();
+        assert_eq!(capabilities.names(), vec!["mapped"]);
+        match LoadedIndex::open(&path("direct.mapped")).unwrap() {
+            LoadedIndex::Mapped(_) => (),
+            LoadedIndex::Deserialized(_) => panic!("expected the index to be mapped"),
+        }
+
+        // a truncated file is refused rather than read past its end
+        let bytes = fs::read(path("direct.mapped")).unwrap();
+        fs::write(path("truncated.mapped"), &bytes[..bytes.len() / 2]).unwrap();
+        assert!(MGIndex::open_mmap(&path("truncated.mapped")).is_err());
+    }
+
+    #[test]
+    fn wide_taxids() {
+        use io::write_to_file;
+        use mktemp::Temp;
+        use rand::{Rng, XorShiftRng};
+
+        let mut rng = XorShiftRng::new_unseeded();
+        let mut random_seq = || {
+            (0..300).map(|_| *rng.choose(b"ACGT").unwrap()).collect::<Vec<u8>>()
+        };
+        // the same taxid as 5000000000 once truncated to 32 bits
+        let wide = TaxId(5_000_000_000);
+        let mut db = Database::new();
+        db.insert(wide, vec![(Gi(1), random_seq())]);
+        db.insert(TaxId(wide.0 - (1 << 32)), vec![(Gi(2), random_seq())]);
+        let index = MGIndex::new(db, 16, 32, BinMode::PerGi);
+
+        let dir = Temp::new_dir().unwrap();
+        let path = dir.to_path_buf().join("wide.index").to_str().unwrap().to_string();
+        write_to_file(&index, &path).unwrap();
+        let loaded = MGIndex::from_file(&path).unwrap();
+        assert_eq!(loaded.bins().map(|bin| bin.tax_id).collect::<Vec<_>>(),
+                   vec![TaxId(705_032_704), wide]);
+        assert_eq!(loaded.get_references(wide.0).len(), 1);
+        assert_eq!(loaded.fingerprint(), index.fingerprint());
+    }
+
+    #[test]
+    fn taxid_composition_counts_bins() {
+        use summary::TaxidComposition;
+
+        let mut db = Database::new();
+        db.insert(TaxId(562), vec![(Gi(10), b"GATTACAGGGCCCTTTGATTACA".to_vec()),
+                                   (Gi(11), b"CCCCCCCCCCCGATTACA".to_vec())]);
+        db.insert(TaxId(9606), vec![(Gi(20), b"TTTTTTTTTTGATTACATTTT".to_vec())]);
+        let index = MGIndex::new(db, 4, 2, BinMode::PerGi);
+
+        let composition = index.taxid_composition().into_iter().collect::<Vec<_>>();
+        assert_eq!(composition,
+                   vec![(TaxId(562), TaxidComposition { sequences: 2, bases: 41 }),
+                        (TaxId(9606), TaxidComposition { sequences: 1, bases: 21 })]);
+    }
+
+    #[test]
+    fn probe_known_kmers() {
+        let mut db = Database::new();
+        db.insert(TaxId(562), vec![(Gi(10), b"GATTACAGGGCCCTTTGATTACA".to_vec()),
+                                   (Gi(11), b"CCCCCCCCCCCGATTACA".to_vec())]);
+        db.insert(TaxId(9606), vec![(Gi(20), b"TTTTTTTTTTGATTACATTTT".to_vec())]);
+        let index = MGIndex::new(db, 4, 2, BinMode::PerGi);
+        let fmindex = FMIndex::new(index.suffix_array.bwt(),
+                                   index.suffix_array.less(),
+                                   index.suffix_array.occ());
+
+        assert_eq!(index.count_occurrences(&fmindex, b"GATTACA"), 4);
+        assert_eq!(index.locate_occurrences(&fmindex, b"GATTACA"),
+                   vec![(Gi(10), TaxId(562), 0),
+                        (Gi(10), TaxId(562), 16),
+                        (Gi(11), TaxId(562), 11),
+                        (Gi(20), TaxId(9606), 10)]);
+
+        assert_eq!(index.count_occurrences(&fmindex, b"AGGGCCC"), 1);
+        assert_eq!(index.locate_occurrences(&fmindex, b"AGGGCCC"), vec![(Gi(10), TaxId(562), 6)]);
+
+        assert_eq!(index.count_occurrences(&fmindex, b"GATTACAGATTACA"), 0);
+        assert_eq!(index.count_occurrences(&fmindex, b"GATXACA"), 0);
+        assert!(index.locate_occurrences(&fmindex, b"GATXACA").is_empty());
+        assert!(index.locate_occurrences(&fmindex, b"GATTACAGATTACA").is_empty());
+
+        // "ACACCC" only exists across the boundary between GIs 10 and 11
+        assert_eq!(index.count_occurrences(&fmindex, b"ACACCC"), 1);
+        assert!(index.locate_occurrences(&fmindex, b"ACACCC").is_empty());
+    }
+
+    #[test]
+    fn partial_seeds() {
+        use rand::{Rng, XorShiftRng};
+        let mut rng = XorShiftRng::new_unseeded();
+
+        let reference = (0..1_000).map(|_| *rng.choose(b"ACGT").unwrap()).collect::<Vec<u8>>();
+        let mut db = Database::new();
+        db.insert(TaxId(1), vec![(Gi(1), reference.clone())]);
+        let index = MGIndex::new(db, 16, 32, BinMode::PerGi);
+
+        // only the first seed has a mismatch, at its first base, so just its 17bp suffix matches
+        let mut read = reference[300..400].to_vec();
+        read[0] = if read[0] == b'A' { b'C' } else { b'A' };
+
+        let skipped = QueryParams { seed_gap: 18, ..QueryParams::default() };
+        let used = QueryParams { min_partial_seed: Some(12), ..skipped };
+        let too_short = QueryParams { min_partial_seed: Some(18), ..skipped };
+
+        let (skipped_hits, skipped_stats) = query(&index, &read, &skipped);
+        let (used_hits, used_stats) = query(&index, &read, &used);
+        let (_, too_short_stats) = query(&index, &read, &too_short);
+
+        assert_eq!(skipped_stats.partial_seeds, 1);
+        assert_eq!(skipped_stats.partial_seeds_used, 0);
+        assert_eq!(too_short_stats.partial_seeds_used, 0);
+        assert_eq!(too_short_stats.seed_hits, skipped_stats.seed_hits);
+
+        assert_eq!(used_stats.partial_seeds, 1);
+        assert_eq!(used_stats.partial_seeds_used, 1);
+        assert_eq!(used_stats.seed_hits, skipped_stats.seed_hits + 1);
+
+        // the partial seed's hit lines up with the rest, so the alignment is the same
+        let alignments = |hits: &[Hit]| hits.iter().map(|h| (h.tax_id, h.edit)).collect::<Vec<_>>();
+        assert_eq!(alignments(&skipped_hits), vec![(TaxId(1), 1)]);
+        assert_eq!(alignments(&used_hits), alignments(&skipped_hits));
+        assert_eq!(used_hits[0].seeds, skipped_hits[0].seeds + 1);
+    }
+
+    #[test]
+    fn overhanging_reads() {
+        use rand::{Rng, XorShiftRng};
+        let mut rng = XorShiftRng::new_unseeded();
+        let mut random_seq =
+            |len| (0..len).map(|_| *rng.choose(b"ACGT").unwrap()).collect::<Vec<u8>>();
+
+        let reference = random_seq(300);
+        let mut db = Database::new();
+        db.insert(TaxId(1), vec![(Gi(1), reference.clone())]);
+        let index = MGIndex::new(db, 16, 32, BinMode::PerGi);
+
+        // half of each read hangs off one end of the reference
+        let mut off_end = reference[250..].to_vec();
+        off_end.extend_from_slice(&random_seq(50));
+        let mut off_start = random_seq(50);
+        off_start.extend_from_slice(&reference[..50]);
+
+        let strict = QueryParams { seed_gap: 4, ..QueryParams::default() };
+        let overhang = QueryParams { max_overhang: Some(0.5), ..strict };
+
+        assert!(query(&index, &off_end, &strict).0.is_empty());
+        assert!(query(&index, &off_start, &strict).0.is_empty());
+
+        let clips = |hits: &[Hit]| {
+            hits.iter()
+                .map(|h| (h.tax_id, h.edit, h.clipped_start, h.clipped_end))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(clips(&query(&index, &off_end, &overhang).0), vec![(TaxId(1), 0, 0, 50)]);
+        assert_eq!(clips(&query(&index, &off_start, &overhang).0), vec![(TaxId(1), 0, 50, 0)]);
+
+        // a read in the middle of the reference still has to align end to end
+        let mut inside = reference[100..150].to_vec();
+        inside.extend_from_slice(&random_seq(50));
+        assert!(query(&index, &inside, &overhang).0.is_empty());
+    }
+
+    #[test]
+    fn strand_profile_reused() {
+        use rand::{Rng, XorShiftRng};
+        let mut rng = XorShiftRng::new_unseeded();
+        let mut random_seq =
+            |len| (0..len).map(|_| *rng.choose(b"ACGT").unwrap()).collect::<Vec<u8>>();
+
+        let reference = random_seq(400);
+        let mut db = Database::new();
+        db.insert(TaxId(1), vec![(Gi(1), reference.clone())]);
+        db.insert(TaxId(2), vec![(Gi(2), random_seq(400))]);
+        let index = MGIndex::new(db, 16, 32, BinMode::PerGi);
+        let fmindex = FMIndex::new(index.suffix_array.bwt(),
+                                   index.suffix_array.less(),
+                                   index.suffix_array.occ());
+        let params = QueryParams { seed_gap: 4, ..QueryParams::default() };
+
+        let read = reference[100..200].to_vec();
+        let strand = QueryStrand::new(&read);
+        let mut stats = QueryStats::default();
+        let first = index.matching_tax_ids_for(&fmindex, &strand, &params, &mut stats);
+        assert!(strand.profile.get().is_some());
+        let second = index.matching_tax_ids_for(&fmindex, &strand, &params, &mut stats);
+        assert_eq!(first, second);
+        assert_eq!(first, query(&index, &read, &params).0);
+        assert_eq!(first.len(), 1);
+
+        // no candidates, so nothing to score
+        let unrelated = random_seq(100);
+        let strand = QueryStrand::new(&unrelated);
+        assert!(index.matching_tax_ids_for(&fmindex, &strand, &params, &mut stats).is_empty());
+        assert!(strand.profile.get().is_none());
+    }
+
+    #[test]
+    fn protein_index() {
+        use io::write_to_file;
+        use mktemp::Temp;
+        use rand::{Rng, XorShiftRng};
+        let mut rng = XorShiftRng::new_unseeded();
+        let mut random_protein = |len| {
+            (0..len).map(|_| *rng.choose(&PROTEIN_RESIDUES[..20]).unwrap()).collect::<Vec<u8>>()
+        };
+
+        let reference = random_protein(400);
+        let mut unusual = random_protein(300);
+        unusual[..4].copy_from_slice(b"mkB*");
+        let mut db = Database::new();
+        db.insert(TaxId(1), vec![(Gi(1), reference.clone())]);
+        db.insert(TaxId(2), vec![(Gi(2), unusual)]);
+        let index =
+            MGIndex::with_alphabet(db, Alphabet::Protein, 16, 32, 1, BinMode::PerGi).unwrap();
+        assert_eq!(index.alphabet(), Alphabet::Protein);
+        assert_eq!(index.get_reference_by_gi(2).unwrap()[..4], b"MKXX"[..]);
+
+        let params = QueryParams { seed_gap: 4, edit_freq: 0.05, ..QueryParams::default() };
+        let read = reference[100..160].to_vec();
+        let hits = query(&index, &read, &params).0;
+        assert_eq!(hits.iter().map(|h| (h.tax_id, h.edit)).collect::<Vec<_>>(), [(TaxId(1), 0)]);
+        let mut substituted = read.clone();
+        substituted[20] = if read[20] == b'W' { b'C' } else { b'W' };
+        substituted[45] = b'X';
+        let hits = query(&index, &substituted, &params).0;
+        assert_eq!(hits.iter().map(|h| (h.tax_id, h.edit)).collect::<Vec<_>>(), [(TaxId(1), 2)]);
+
+        let dir = Temp::new_dir().unwrap();
+        let path = dir.to_path_buf().join("protein.index").to_str().unwrap().to_string();
+        write_to_file(&index, &path).unwrap();
+        let loaded = MGIndex::from_file(&path).unwrap();
+        assert_eq!(loaded.alphabet(), Alphabet::Protein);
+        assert!(matches!(loaded.sequences, IndexText::Plain(_)));
+        assert_eq!(loaded.sequences, index.sequences);
+        assert!(index.write_mapped(&format!("{}.mapped", path)).is_err());
+        assert!(convert_to_mapped(&path, &format!("{}.mapped", path)).is_err());
+
+        let subset = index.subset(&vec![TaxId(1)].into_iter().collect(), 16, 32, 1);
+        assert_eq!(subset.alphabet(), Alphabet::Protein);
+        assert_eq!(query(&subset, &read, &params).0.len(), 1);
+        let mut dna = Database::new();
+        dna.insert(TaxId(3), vec![(Gi(3), b"ACGTACGTACGTACGTACGT".to_vec())]);
+        assert!(MGIndex::merge(vec![index, MGIndex::new(dna, 16, 32, BinMode::PerGi)]).is_err());
+
+        assert_eq!("protein".parse::<Alphabet>().unwrap(), Alphabet::Protein);
+        assert!("rna".parse::<Alphabet>().is_err());
+    }
+
+    #[test]
+    fn dna4_index() {
+        use io::write_to_file;
+        use mktemp::Temp;
+        use rand::{Rng, XorShiftRng};
+        let mut rng = XorShiftRng::new_unseeded();
+        let mut random_seq = |len: usize| {
+            (0..len).map(|_| *rng.choose(b"ACGT").unwrap()).collect::<Vec<u8>>()
+        };
+
+        let first = random_seq(400);
+        let mut second = random_seq(300);
+        second[..4].copy_from_slice(b"acgu");
+        let mut db = Database::new();
+        db.insert(TaxId(1), vec![(Gi(1), first.clone())]);
+        db.insert(TaxId(2), vec![(Gi(2), second)]);
+        let index =
+            MGIndex::with_alphabet(db.clone(), Alphabet::Dna4, 16, 32, 1, BinMode::PerGi).unwrap();
+        assert_eq!(index.alphabet(), Alphabet::Dna4);
+        assert!(matches!(index.sequences, IndexText::Packed(_)));
+        assert_eq!(index.get_reference_by_gi(2).unwrap()[..4], b"ACGT"[..]);
+        let dna = MGIndex::new(db, 16, 32, BinMode::PerGi);
+
+        // reads with Ns still find their reference from the seeds without any
+        let params = QueryParams { seed_gap: 2, ..QueryParams::default() };
+        let read = first[100..200].to_vec();
+        let mut with_n = read.clone();
+        with_n[50] = b'N';
+        for read in [&read, &with_n] {
+            let hits = query(&index, read, &params).0;
+            assert_eq!(hits.iter().map(|h| h.tax_id).collect::<Vec<_>>(), vec![TaxId(1)]);
+            assert_eq!(hits, query(&dna, read, &params).0);
+        }
+        let (hits, stats) = query(&index, &with_n, &QueryParams { max_seed_ns: 1, ..params });
+        assert_eq!(hits.len(), 1);
+        assert!(stats.seeds_with_ns > 0);
+        assert_eq!(index.count_occurrences(&FMIndex::new(index.suffix_array.bwt(),
+                                                         index.suffix_array.less(),
+                                                         index.suffix_array.occ()),
+                                           b"NNNN"),
+                   0);
+
+        let dir = Temp::new_dir().unwrap();
+        let path = dir.to_path_buf().join("dna4.index").to_str().unwrap().to_string();
+        write_to_file(&index, &path).unwrap();
+        let loaded = MGIndex::from_file(&path).unwrap();
+        assert_eq!(loaded.alphabet(), Alphabet::Dna4);
+        assert_eq!(query(&loaded, &with_n, &params).0, query(&index, &with_n, &params).0);
+        assert!(index.write_mapped(&format!("{}.mapped", path)).is_err());
+        assert!(MGIndex::merge(vec![index, dna]).is_err());
+
+        // an N, or anything which becomes one, can't be indexed
+        for (i, base) in [(0, b'N'), (9, b'R'), (299, b'-')] {
+            let mut ambiguous = random_seq(300);
+            ambiguous[i] = base;
+            let mut db = Database::new();
+            db.insert(TaxId(1), vec![(Gi(1), first.clone())]);
+            db.insert(TaxId(2), vec![(Gi(2), ambiguous)]);
+            match MGIndex::with_alphabet(db, Alphabet::Dna4, 16, 32, 1, BinMode::PerGi) {
+                Err(MtsvError::AmbiguousReference(ref header, at)) => {
+                    assert_eq!((&header[..], at), ("2-2", i + 1))
+                },
+                other => panic!("expected an ambiguous reference, got {:?}", other.map(|_| ())),
+            }
+        }
+
+        assert_eq!("dna4".parse::<Alphabet>().unwrap(), Alphabet::Dna4);
     }
 }
@@ -1,54 +1,2360 @@
 //! Helper functions for serialization & deserialization.
 
 use serde::{Serialize};
-use bincode::{deserialize_from, serialize_into};
-use bio::io::fasta;
+use bincode::{self, deserialize_from, serialize_into};
+use binner::write_single_line;
+use bio::io::{fasta, fastq};
 use error::*;
-use index::{Database, TaxId, Hit};
-use std::collections::{BTreeMap, BTreeSet};
-use std::fs::File;
+use formats::{BINARY_FINDINGS_VERSION, BUILT_BY_INDEX_FORMAT_VERSION,
+              CHECKSUM_INDEX_FORMAT_VERSION, FINDINGS_SCHEMA_VERSION, INDEX_FORMAT_VERSION,
+              MIN_INDEX_FORMAT_VERSION};
+use flate2::read::MultiGzDecoder;
+use index::{BinInfo, Database, Gi, HeaderMap, TaxId, Hit};
+use mapped::MAPPED_MAGIC;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fmt::Write as FmtWrite;
+use std::fs::{self, File};
 use std::io;
-use std::io::{BufRead, BufReader, BufWriter};
-use std::path::Path;
-use util::parse_read_header;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::mem;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::str;
+use std::sync::Arc;
+use std::str::FromStr;
+use util::{parse_read_header, parse_taxid_header, HeaderFormat, Progress};
+use xxhash_rust::xxh3::Xxh3;
+use zstd;
+
+/// Starts a file written by `write_to_file` or `write_to_file_compressed`, followed by the
+/// format version as a little-endian u32, a byte naming the codec, from version 5 the version of
+/// mtsv which wrote the file (as a byte giving its length and then the string), the serialization
+/// and from version 10 its checksum (see `ChecksumWriter`). Plain bincode can't start with this,
+/// as the first eight bytes of an index would have to be an impossibly long length.
+pub const ENVELOPE_MAGIC: &[u8] = b"\x89MTSVE\r\n";
+
+/// Started compressed files before the format version was recorded, followed by the codec byte.
+/// Files without either magic are plain bincode from before then.
+pub const COMPRESSED_MAGIC: &[u8] = b"\x89MTSVZ\r\n";
+
+/// Codec byte for no compression.
+const CODEC_NONE: u8 = 0;
+
+/// Codec byte for zstd compression.
+const CODEC_ZSTD: u8 = 1;
+
+/// Starts every zstd frame, so a file compressed whole by the `zstd` tool, rather than written
+/// compressed, starts with it.
+const ZSTD_MAGIC: &[u8] = b"\x28\xb5\x2f\xfd";
+
+/// The zstd levels `write_to_file_compressed` takes, from fastest to smallest.
+pub const COMPRESSION_LEVELS: RangeInclusive<u32> = 1..=19;
+
+/// The level indices are compressed at if none is given.
+pub const DEFAULT_COMPRESSION_LEVEL: u32 = 3;
+
+/// The start of a file written by `write_to_file` or `write_to_file_compressed`, or the version
+/// 1 equivalent for files from before it was written.
+///
+/// Later format versions must keep the layout up to the version of mtsv, so that builds which
+/// can't read a file can still say which release wrote it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Envelope {
+    /// The format version of the file (see `formats::INDEX_FORMAT_VERSION`).
+    pub version: u32,
+    codec: u8,
+    /// The version of mtsv which wrote the file, if it was recorded.
+    pub built_by: Option<String>,
+    /// Whether the whole file, envelope and all, is zstd-compressed (see `open_unframed`).
+    framed: bool,
+}
+
+impl Envelope {
+    /// The envelope of the file at `p`, without reading the rest of the file.
+    pub fn of_file(p: &str) -> MtsvResult<Self> {
+        let (mut reader, framed) = open_unframed(p)?;
+        Ok(Envelope { framed, ..Envelope::read(&mut reader, p)? })
+    }
+
+    /// The envelope this build writes.
+    fn current(codec: u8) -> Self {
+        Envelope {
+            version: INDEX_FORMAT_VERSION,
+            codec,
+            built_by: Some(env!("CARGO_PKG_VERSION").to_string()),
+            framed: false,
+        }
+    }
+
+    /// Whether the serialization is compressed, by mtsv or the whole file by the `zstd` tool.
+    pub fn is_compressed(&self) -> bool {
+        self.codec != CODEC_NONE || self.framed
+    }
+
+    /// Whether the file has no envelope at all, so it's either plain bincode from before the
+    /// compressed magic was written, or not one of mtsv's files.
+    pub fn is_headerless(&self) -> bool {
+        // envelopes with either magic were only ever written with compressed or later files
+        self.version == 1 && self.codec == CODEC_NONE
+    }
+
+    /// Read and consume the envelope of the file at `p`, failing if this build can't read its
+    /// version, or it's a mapped index (see `mapped`), which has no envelope.
+    fn read<R: BufRead>(reader: &mut R, p: &str) -> MtsvResult<Self> {
+        let truncated = |e: io::Error| if e.kind() == io::ErrorKind::UnexpectedEof {
+            MtsvError::IncompatibleIndex(p.to_string(), String::from("its header is truncated"))
+        } else {
+            MtsvError::from(e)
+        };
+
+        let mut codec = [0; 1];
+        let envelope = if reader.fill_buf()?.starts_with(MAPPED_MAGIC) {
+            return Err(MtsvError::MappedIndex(p.to_string()));
+        } else if reader.fill_buf()?.starts_with(ENVELOPE_MAGIC) {
+            reader.consume(ENVELOPE_MAGIC.len());
+            let mut version = [0; 4];
+            reader.read_exact(&mut version).map_err(truncated)?;
+            reader.read_exact(&mut codec).map_err(truncated)?;
+            let version = u32::from_le_bytes(version);
+            let built_by = if version >= BUILT_BY_INDEX_FORMAT_VERSION {
+                let mut len = [0; 1];
+                reader.read_exact(&mut len).map_err(truncated)?;
+                let mut built_by = vec![0; len[0] as usize];
+                reader.read_exact(&mut built_by).map_err(truncated)?;
+                Some(String::from_utf8_lossy(&built_by).into_owned())
+            } else {
+                None
+            };
+            Envelope { version, codec: codec[0], built_by, framed: false }
+        } else if reader.fill_buf()?.starts_with(COMPRESSED_MAGIC) {
+            reader.consume(COMPRESSED_MAGIC.len());
+            reader.read_exact(&mut codec).map_err(truncated)?;
+            Envelope { version: 1, codec: codec[0], built_by: None, framed: false }
+        } else {
+            Envelope { version: 1, codec: CODEC_NONE, built_by: None, framed: false }
+        };
+
+        if envelope.version < MIN_INDEX_FORMAT_VERSION || envelope.version > INDEX_FORMAT_VERSION {
+            let built_by = match envelope.built_by {
+                Some(ref v) => format!(" by mtsv {}", v),
+                None => String::new(),
+            };
+            return Err(MtsvError::IncompatibleIndex(p.to_string(),
+                                                    format!("it was built with format {}{}, \
+                                                             but this build of mtsv ({}) reads \
+                                                             formats {} to {} (see \
+                                                             --format-versions)",
+                                                            envelope.version,
+                                                            built_by,
+                                                            env!("CARGO_PKG_VERSION"),
+                                                            MIN_INDEX_FORMAT_VERSION,
+                                                            INDEX_FORMAT_VERSION)));
+        }
+        Ok(envelope)
+    }
+
+    fn write<W: Write>(&self, writer: &mut W) -> MtsvResult<()> {
+        writer.write_all(ENVELOPE_MAGIC)?;
+        writer.write_all(&self.version.to_le_bytes())?;
+        writer.write_all(&[self.codec])?;
+        if self.version >= BUILT_BY_INDEX_FORMAT_VERSION {
+            let built_by = self.built_by.as_ref().map_or(&[][..], |v| v.as_bytes());
+            writer.write_all(&[built_by.len() as u8])?;
+            writer.write_all(built_by)?;
+        }
+        Ok(())
+    }
+}
 
 /// Parse an arbitrary `Decodable` type from a file path.
+///
+/// Files written by `write_to_file_compressed`, or compressed whole by the `zstd` tool, are
+/// decompressed as they're read, without holding the compressed file in memory. Fails if the
+/// file's format version is newer than this build reads (see `Envelope`).
+///
+/// Fails with `MtsvError::CorruptIndex` if the file has a checksum which its contents don't match.
 pub fn from_file<T>(p: &str) -> MtsvResult<T>
     where T: serde::de::DeserializeOwned
 {
+    let (version, reader) = open_versioned(p)?;
+    deserialize_checked(reader, p, version, true)
+}
+
+/// Parse an arbitrary `Decodable` type from a file path like `from_file`, without checking its
+/// checksum, which saves hashing the file as it's read.
+pub fn from_file_unverified<T>(p: &str) -> MtsvResult<T>
+    where T: serde::de::DeserializeOwned
+{
+    let (version, reader) = open_versioned(p)?;
+    deserialize_checked(reader, p, version, false)
+}
+
+/// Deserialize a `T` from the rest of the file at `p`, opened by `open_versioned`, like
+/// `deserialize_versioned`, and with `verify`, check it against the checksum which follows it
+/// from `formats::CHECKSUM_INDEX_FORMAT_VERSION` on.
+///
+/// If the file doesn't parse, other than by ending early, it's hashed again from the start, so
+/// that a corrupt file fails with `MtsvError::CorruptIndex` rather than whatever the corruption
+/// made of it.
+pub fn deserialize_checked<T, R>(reader: R, p: &str, version: u32, verify: bool) -> MtsvResult<T>
+    where T: serde::de::DeserializeOwned,
+          R: Read
+{
+    if version < CHECKSUM_INDEX_FORMAT_VERSION || !verify {
+        return deserialize_versioned(reader, p, version);
+    }
+
+    let mut reader = ChecksumReader::new(reader);
+    let t = deserialize_versioned(&mut reader, p, version).map_err(|e| match e {
+        MtsvError::IncompatibleIndex(_, ref why) if why == TRUNCATED => e,
+        _ => {
+            match verify_checksum(p) {
+                Err(corrupt @ MtsvError::CorruptIndex(..)) => corrupt,
+                _ => e,
+            }
+        },
+    })?;
+    reader.verify(p)?;
+    Ok(t)
+}
+
+/// Check the file at `p`, written by `write_to_file` or `write_to_file_compressed`, against its
+/// checksum, without deserializing it. Files from before checksums were written always pass.
+pub fn verify_checksum(p: &str) -> MtsvResult<()> {
+    let (version, mut reader) = open_versioned(p)?;
+    if version < CHECKSUM_INDEX_FORMAT_VERSION {
+        return Ok(());
+    }
+
+    // everything but the last few bytes read is serialization, and those are its checksum
+    let mut hasher = Xxh3::new();
+    let mut buf = vec![0; 1 << 16];
+    let mut tail = Vec::with_capacity(buf.len() + CHECKSUM_LEN);
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(MtsvError::from(e)),
+        };
+        tail.extend_from_slice(&buf[..n]);
+        if tail.len() > CHECKSUM_LEN {
+            let hashed = tail.len() - CHECKSUM_LEN;
+            hasher.update(&tail[..hashed]);
+            tail.drain(..hashed);
+        }
+    }
+    if tail.len() < CHECKSUM_LEN {
+        return Err(MtsvError::IncompatibleIndex(p.to_string(), String::from(TRUNCATED)));
+    }
+    check_digest(p, &tail, hasher.digest())
+}
+
+/// Why a file which ends before its serialization does can't be read.
+const TRUNCATED: &str = "it ends part way through, so it's truncated";
+
+/// The length of the checksum at the end of a file written by `write_to_file` or
+/// `write_to_file_compressed`.
+const CHECKSUM_LEN: usize = 8;
+
+/// Check the little-endian checksum `expected` read from the file at `p` against the hash of its
+/// serialization.
+fn check_digest(p: &str, expected: &[u8], found: u64) -> MtsvResult<()> {
+    let mut bytes = [0; CHECKSUM_LEN];
+    bytes.copy_from_slice(expected);
+    let expected = u64::from_le_bytes(bytes);
+    if expected == found {
+        Ok(())
+    } else {
+        Err(MtsvError::CorruptIndex(p.to_string(), expected, found))
+    }
+}
+
+/// Hashes everything written through it, so that the hash can be written after it as a checksum
+/// for `ChecksumReader` to check. The hash is the 64-bit XXH3 of the (uncompressed) serialization,
+/// which hashes far faster than the serialization can be read, and is written little-endian.
+struct ChecksumWriter<W: Write> {
+    inner: W,
+    hasher: Xxh3,
+}
+
+impl<W: Write> ChecksumWriter<W> {
+    fn new(inner: W) -> Self {
+        ChecksumWriter { inner, hasher: Xxh3::new() }
+    }
+
+    /// Write the checksum of everything written so far, and return the inner writer.
+    fn finish(mut self) -> MtsvResult<W> {
+        self.inner.write_all(&self.hasher.digest().to_le_bytes())?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Hashes everything read through it, to check against the checksum written by `ChecksumWriter`
+/// once the serialization has been read. It mustn't be buffered, or the hash would take in
+/// whatever the buffer read ahead.
+struct ChecksumReader<R: Read> {
+    inner: R,
+    hasher: Xxh3,
+}
+
+impl<R: Read> ChecksumReader<R> {
+    fn new(inner: R) -> Self {
+        ChecksumReader { inner, hasher: Xxh3::new() }
+    }
+
+    /// Read the checksum which follows what's been read so far from the file at `p`, and check
+    /// it against the hash of what's been read.
+    fn verify(mut self, p: &str) -> MtsvResult<()> {
+        let mut expected = [0; CHECKSUM_LEN];
+        self.inner.read_exact(&mut expected).map_err(|e| {
+            if e.kind() == io::ErrorKind::UnexpectedEof {
+                MtsvError::IncompatibleIndex(p.to_string(), String::from(TRUNCATED))
+            } else {
+                MtsvError::from(e)
+            }
+        })?;
+        check_digest(p, &expected, self.hasher.digest())
+    }
+}
+
+impl<R: Read> Read for ChecksumReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// Deserialize a `T` from (part of) the file at `p`, opened by `open_versioned`, failing with
+/// `MtsvError::IncompatibleIndex` if the file ends early or doesn't parse, rather than with
+/// bincode's own error.
+pub fn deserialize_versioned<T, R>(reader: R, p: &str, version: u32) -> MtsvResult<T>
+    where T: serde::de::DeserializeOwned,
+          R: Read
+{
+    deserialize_from(reader).map_err(|e| {
+        let why = match *e {
+            bincode::ErrorKind::Io(ref io) if io.kind() == io::ErrorKind::UnexpectedEof => {
+                String::from(TRUNCATED)
+            },
+            bincode::ErrorKind::Io(_) => return MtsvError::from(e),
+            _ => format!("it doesn't parse as format {} ({})", version, e),
+        };
+        MtsvError::IncompatibleIndex(p.to_string(), why)
+    })
+}
+
+/// Open a file written by `write_to_file` or `write_to_file_compressed`, returning its format
+/// version and a reader of the (decompressed) serialization, for types whose serialization
+/// differs between versions (see `MGIndex::from_file`).
+pub fn open_versioned(p: &str) -> MtsvResult<(u32, Box<dyn Read>)> {
+    let (mut reader, _) = open_unframed(p)?;
+
+    let envelope = Envelope::read(&mut reader, p)?;
+    match envelope.codec {
+        CODEC_NONE => Ok((envelope.version, Box::new(reader))),
+        CODEC_ZSTD => {
+            Ok((envelope.version, Box::new(BufReader::new(zstd::Decoder::with_buffer(reader)?))))
+        },
+        other => {
+            Err(MtsvError::from(io::Error::new(io::ErrorKind::InvalidData,
+                                               format!("unknown compression codec {} in {}",
+                                                       other,
+                                                       p))))
+        },
+    }
+}
+
+/// Open the file at `p` for buffered reading, decompressing it as it's read if it was compressed
+/// whole by the `zstd` tool, whatever it's named, and say whether it was.
+fn open_unframed(p: &str) -> MtsvResult<(Box<dyn BufRead>, bool)> {
+    let mut reader = BufReader::new(File::open(Path::new(p))?);
+    if reader.fill_buf()?.starts_with(ZSTD_MAGIC) {
+        Ok((Box::new(BufReader::new(zstd::Decoder::with_buffer(reader)?)), true))
+    } else {
+        Ok((Box::new(reader), false))
+    }
+}
+
+/// Write an arbitrary `Encodable` type to a file path, after an `Envelope` with the current
+/// format version and followed by a checksum of the serialization (see `ChecksumWriter`).
+///
+/// The file is written to `p.partial` and only renamed to `p` once it's complete and synced to
+/// disk, so a write which fails or is killed part way through never leaves a truncated file at
+/// `p`, or replaces what was there. The partial file is removed if the write fails.
+///
+/// Paths ending in `.zst` are written compressed at the default level (see
+/// `write_to_file_compressed`).
+pub fn write_to_file<T>(t: &T, p: &str) -> MtsvResult<()>
+    where T: Serialize
+{
+    if p.ends_with(".zst") {
+        return write_to_file_compressed(t, p, DEFAULT_COMPRESSION_LEVEL);
+    }
+    write_enveloped(t, p, CODEC_NONE, Ok, Ok)
+}
+
+/// Write an arbitrary `Encodable` type to a file path, zstd-compressed at `level`, from 1
+/// (fastest) to 19 (smallest), which `from_file` reads transparently.
+pub fn write_to_file_compressed<T>(t: &T, p: &str, level: u32) -> MtsvResult<()>
+    where T: Serialize
+{
+    if !COMPRESSION_LEVELS.contains(&level) {
+        return Err(MtsvError::InvalidParameter(format!("compression level must be from {} to \
+                                                        {}, got {}",
+                                                       COMPRESSION_LEVELS.start(),
+                                                       COMPRESSION_LEVELS.end(),
+                                                       level)));
+    }
+
+    write_enveloped(t,
+                    p,
+                    CODEC_ZSTD,
+                    |file| zstd::Encoder::new(file, level as i32),
+                    |encoder| encoder.finish())
+}
+
+/// Write `t` to the file at `p` after an envelope naming `codec`, through the writer `encode`
+/// makes of the file, which `finish` ends and hands the file back from. The file only appears at
+/// `p` once it's complete (see `write_to_file`).
+fn write_enveloped<T, E, F, G>(t: &T, p: &str, codec: u8, encode: F, finish: G) -> MtsvResult<()>
+    where T: Serialize,
+          E: Write,
+          F: FnOnce(BufWriter<File>) -> io::Result<E>,
+          G: FnOnce(E) -> io::Result<BufWriter<File>>
+{
+    let partial = format!("{}.partial", p);
+    let written = File::create(Path::new(&partial)).map_err(MtsvError::from).and_then(|file| {
+        let mut file = BufWriter::new(file);
+        Envelope::current(codec).write(&mut file)?;
+
+        let mut writer = BufWriter::new(ChecksumWriter::new(encode(file)?));
+        serialize_into(&mut writer, t)?;
+        let checksummed = writer.into_inner().map_err(|e| MtsvError::from(e.into_error()))?;
+        let file = finish(checksummed.finish()?)?;
+        file.into_inner().map_err(|e| MtsvError::from(e.into_error()))?.sync_all()?;
+        Ok(())
+    });
+
+    match written {
+        Ok(()) => Ok(fs::rename(&partial, p)?),
+        Err(e) => {
+            if let Err(why) = fs::remove_file(&partial) {
+                if why.kind() != io::ErrorKind::NotFound {
+                    warn!("Unable to remove partially written file {}: {}", partial, why);
+                }
+            }
+            Err(e)
+        },
+    }
+}
+
+/// Open a file for buffered reading, transparently decompressing it if it starts with the gzip
+/// magic bytes.
+pub fn open_maybe_gz<P: AsRef<Path>>(p: P) -> MtsvResult<Box<dyn BufRead>> {
+    maybe_gz(File::open(p)?)
+}
+
+/// Buffer `reader`, transparently decompressing it if it starts with the gzip magic bytes.
+fn maybe_gz<R: Read + 'static>(reader: R) -> MtsvResult<Box<dyn BufRead>> {
+    let mut reader = BufReader::new(reader);
+
+    let is_gz = reader.fill_buf()?.starts_with(&[0x1f, 0x8b]);
+    if is_gz {
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(reader)?)))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+/// The path which reads standard input where FASTA files are expected (see `open_fasta`).
+pub const STDIN_PATH: &str = "-";
+
+/// Open a FASTA file for parsing, decompressing it first if it's gzipped (see `open_maybe_gz`).
+/// The path `-` reads standard input instead.
+pub fn open_fasta<P: AsRef<Path>>(p: P) -> MtsvResult<fasta::Reader<Box<dyn BufRead>>> {
+    let reader = if p.as_ref() == Path::new(STDIN_PATH) {
+        maybe_gz(io::stdin().lock())?
+    } else {
+        open_maybe_gz(p)?
+    };
+    Ok(fasta::Reader::from_bufread(reader))
+}
+
+/// The records of several FASTA files, one file after another, each of which may be gzipped (see
+/// `open_fasta`). Each file is opened when its records are reached, and the file and record the
+/// iterator stopped at are kept, so that errors can say where they happened. Standard input can
+/// be one of the files, as `-`.
+pub struct FastaFiles {
+    paths: Vec<String>,
+    file: usize,
+    records: Option<fasta::Records<Box<dyn BufRead>>>,
+    record: usize,
+    finished: bool,
+}
+
+impl FastaFiles {
+    /// Iterate over the records of the files at `paths` in order, failing now if any of them
+    /// can't be opened, or standard input is given more than once.
+    pub fn open<P: AsRef<str>>(paths: &[P]) -> MtsvResult<Self> {
+        let paths = paths.iter().map(|p| p.as_ref().to_string()).collect::<Vec<_>>();
+        if paths.iter().filter(|p| *p == STDIN_PATH).count() > 1 {
+            return Err(MtsvError::InvalidParameter(String::from("standard input (-) can only be \
+                                                                 read once")));
+        }
+        for path in paths.iter().filter(|p| *p != STDIN_PATH) {
+            File::open(path).map_err(|e| {
+                MtsvError::from(io::Error::new(e.kind(), format!("{}: {}", path, e)))
+            })?;
+        }
+        let finished = paths.is_empty();
+        Ok(FastaFiles { paths, file: 0, records: None, record: 0, finished })
+    }
+
+    /// The path of the file (or "standard input") and the number (from 1) of the record within it
+    /// which was read last, or `None` if no record has been read yet or every file has been read
+    /// to the end.
+    pub fn position(&self) -> Option<(&str, usize)> {
+        if self.finished || self.record == 0 {
+            None
+        } else if self.paths[self.file] == STDIN_PATH {
+            Some(("standard input", self.record))
+        } else {
+            Some((&self.paths[self.file], self.record))
+        }
+    }
+}
+
+impl Iterator for FastaFiles {
+    type Item = io::Result<fasta::Record>;
+
+    fn next(&mut self) -> Option<io::Result<fasta::Record>> {
+        while !self.finished {
+            if self.records.is_none() {
+                match open_fasta(&self.paths[self.file]) {
+                    Ok(reader) => self.records = Some(reader.records()),
+                    Err(MtsvError::Io(e)) => return Some(Err(e)),
+                    Err(e) => return Some(Err(io::Error::other(e.to_string()))),
+                }
+            }
+
+            match self.records.as_mut().and_then(Iterator::next) {
+                Some(record) => {
+                    self.record += 1;
+                    return Some(record);
+                },
+                None if self.file + 1 < self.paths.len() => {
+                    self.file += 1;
+                    self.records = None;
+                    self.record = 0;
+                },
+                None => self.finished = true,
+            }
+        }
+        None
+    }
+}
+
+/// Load a list of IDs (taxids, read IDs, ...) with one entry per line.
+///
+/// Surrounding whitespace is trimmed, and blank lines or lines starting with '#' are skipped.
+/// Duplicate entries are logged and collapsed. Gzipped lists are decompressed transparently.
+pub fn load_id_list<T, P>(p: P) -> MtsvResult<BTreeSet<T>>
+    where T: FromStr + Ord,
+          P: AsRef<Path>
+{
+    let path = p.as_ref();
+    let mut ids = BTreeSet::new();
+
+    for (i, line) in open_maybe_gz(path)?.lines().enumerate() {
+        let line = line?;
+        let entry = line.trim();
+        if entry.is_empty() || entry.starts_with('#') {
+            continue;
+        }
+
+        let id = match entry.parse::<T>() {
+            Ok(id) => id,
+            Err(_) => {
+                return Err(MtsvError::InvalidListEntry(path.display().to_string(),
+                                                       i + 1,
+                                                       entry.to_string()))
+            },
+        };
+
+        if !ids.insert(id) {
+            warn!("Duplicate entry \"{}\" on line {} of {}", entry, i + 1, path.display());
+        }
+    }
+
+    Ok(ids)
+}
+
+/// A relabeling of taxids, e.g. from the surrogate taxids an index was built with to NCBI taxids.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TaxidMap {
+    map: HashMap<TaxId, TaxId>,
+}
+
+impl TaxidMap {
+    /// Load a map from a file with two tab- or space-separated columns per line, old and new
+    /// taxid. Blank lines and lines starting with '#' are skipped, and gzipped files are
+    /// decompressed transparently. Mapping one taxid to two different ones is an error.
+    pub fn from_file<P: AsRef<Path>>(p: P) -> MtsvResult<Self> {
+        let path = p.as_ref();
+        let mut map = HashMap::new();
+
+        for (i, line) in open_maybe_gz(path)?.lines().enumerate() {
+            let line = line?;
+            let entry = line.trim();
+            if entry.is_empty() || entry.starts_with('#') {
+                continue;
+            }
+
+            let invalid = || {
+                MtsvError::InvalidListEntry(path.display().to_string(), i + 1, entry.to_string())
+            };
+
+            let mut columns = entry.split_whitespace().map(|c| c.parse::<TaxId>());
+            let (old, new) = match (columns.next(), columns.next(), columns.next()) {
+                (Some(Ok(old)), Some(Ok(new)), None) => (old, new),
+                _ => return Err(invalid()),
+            };
+
+            match map.insert(old, new) {
+                Some(prev) if prev != new => return Err(invalid()),
+                Some(_) => {
+                    warn!("Duplicate entry \"{}\" on line {} of {}", entry, i + 1, path.display())
+                },
+                None => (),
+            }
+        }
+
+        Ok(TaxidMap { map })
+    }
+
+    /// Build a map from `(old, new)` pairs.
+    pub fn from_pairs<I: IntoIterator<Item = (TaxId, TaxId)>>(pairs: I) -> Self {
+        TaxidMap { map: pairs.into_iter().collect() }
+    }
+
+    /// Number of taxids which are relabeled.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Whether the map relabels no taxids.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// The new taxid for `tax_id`, if it's in the map.
+    pub fn get(&self, tax_id: TaxId) -> Option<TaxId> {
+        self.map.get(&tax_id).cloned()
+    }
+
+    /// Relabel a read's hits in place. Taxids which aren't in the map are left as they are, and
+    /// the number of hits which weren't relabeled is returned. If several hits end up with the
+    /// same taxid only the best of them is kept (see `Hit::is_better_than`), in the position of the
+    /// first of them.
+    pub fn remap_hits(&self, hits: &mut Vec<Hit>) -> usize {
+        let mut unmapped = 0;
+        let mut best: HashMap<TaxId, usize> = HashMap::new();
+        let mut remapped: Vec<Hit> = Vec::with_capacity(hits.len());
+
+        for mut hit in hits.drain(..) {
+            match self.get(hit.tax_id) {
+                Some(new) => hit.tax_id = new,
+                None => unmapped += 1,
+            }
+
+            match best.get(&hit.tax_id) {
+                Some(&i) => {
+                    if hit.is_better_than(&remapped[i]) {
+                        remapped[i] = hit;
+                    }
+                },
+                None => {
+                    best.insert(hit.tax_id, remapped.len());
+                    remapped.push(hit);
+                },
+            }
+        }
+
+        *hits = remapped;
+        unmapped
+    }
+}
+
+/// The taxids of reference accessions, for databases with `HeaderFormat::Accession` headers.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AccessionMap {
+    map: HashMap<String, TaxId>,
+}
+
+impl AccessionMap {
+    /// Load a map from a file with two tab- or space-separated columns per line, accession and
+    /// taxid, e.g. the `accession.version` and `taxid` columns of NCBI's accession2taxid files.
+    /// Blank lines and lines starting with '#' are skipped, and gzipped files are decompressed
+    /// transparently. Mapping one accession to two different taxids is an error.
+    pub fn from_file<P: AsRef<Path>>(p: P) -> MtsvResult<Self> {
+        let path = p.as_ref();
+        let mut map = HashMap::new();
+
+        for (i, line) in open_maybe_gz(path)?.lines().enumerate() {
+            let line = line?;
+            let entry = line.trim();
+            if entry.is_empty() || entry.starts_with('#') {
+                continue;
+            }
+
+            let invalid = || {
+                MtsvError::InvalidListEntry(path.display().to_string(), i + 1, entry.to_string())
+            };
+
+            let mut columns = entry.split_whitespace();
+            let (accession, tax_id) = match (columns.next(), columns.next(), columns.next()) {
+                (Some(accession), Some(tax_id), None) => {
+                    (accession, tax_id.parse::<TaxId>().map_err(|_| invalid())?)
+                },
+                _ => return Err(invalid()),
+            };
+
+            match map.insert(accession.to_string(), tax_id) {
+                Some(prev) if prev != tax_id => return Err(invalid()),
+                Some(_) => {
+                    warn!("Duplicate entry \"{}\" on line {} of {}", entry, i + 1, path.display())
+                },
+                None => (),
+            }
+        }
+
+        Ok(AccessionMap { map })
+    }
+
+    /// Load the taxids of the `wanted` accessions from one of NCBI's accession2taxid files, such
+    /// as `nucl_gb.accession2taxid`, with four tab-separated columns per line: accession,
+    /// accession.version, taxid and GI. An accession is found by either of its first two columns,
+    /// and kept under whichever of them is wanted. The file is streamed, and only the entries of
+    /// wanted accessions are kept, since there are hundreds of millions of them. Its header line,
+    /// blank lines and lines starting with '#' are skipped, and it may be gzipped.
+    ///
+    /// Wanted accessions missing from the file aren't an error here, but looking them up finds
+    /// nothing. As with `from_file`, mapping one accession to two different taxids is an error.
+    pub fn from_accession2taxid<P: AsRef<Path>>(p: P,
+                                                wanted: &HashSet<String>)
+                                                -> MtsvResult<Self> {
+        let path = p.as_ref();
+        let mut map = HashMap::new();
+        let progress = Progress::new("Reading accession2taxid entries", "lines", None);
+
+        let mut reader = open_maybe_gz(path)?;
+        let mut line = String::new();
+        let mut i = 0;
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                break;
+            }
+            i += 1;
+            progress.add(1);
+            let entry = line.trim();
+            if entry.is_empty() || entry.starts_with('#') ||
+               (i == 1 && entry.starts_with("accession\t")) {
+                continue;
+            }
+
+            let invalid = || {
+                MtsvError::InvalidListEntry(path.display().to_string(), i, entry.to_string())
+            };
+
+            let mut columns = entry.split('\t');
+            let (accession, versioned, tax_id) =
+                match (columns.next(), columns.next(), columns.next(), columns.next(),
+                       columns.next()) {
+                    (Some(accession), Some(versioned), Some(tax_id), Some(_), None) => {
+                        (accession, versioned, tax_id)
+                    },
+                    _ => return Err(invalid()),
+                };
+            if !wanted.contains(accession) && !wanted.contains(versioned) {
+                continue;
+            }
+            let tax_id = tax_id.parse::<TaxId>().map_err(|_| invalid())?;
+
+            for key in [accession, versioned].iter().filter(|key| wanted.contains(**key)) {
+                match map.insert(key.to_string(), tax_id) {
+                    Some(prev) if prev != tax_id => return Err(invalid()),
+                    _ => (),
+                }
+            }
+        }
+        progress.finish();
+        info!("Found the taxids of {} of {} accessions in {}",
+              map.len(),
+              wanted.len(),
+              path.display());
+
+        Ok(AccessionMap { map })
+    }
+
+    /// Build a map from `(accession, taxid)` pairs.
+    pub fn from_pairs<I: IntoIterator<Item = (String, TaxId)>>(pairs: I) -> Self {
+        AccessionMap { map: pairs.into_iter().collect() }
+    }
+
+    /// The taxid of `accession`, if it's in the map.
+    pub fn get(&self, accession: &str) -> Option<TaxId> {
+        self.map.get(accession).cloned()
+    }
+}
+
+/// Parse a FASTA database into a single map of all taxonomy IDs.
+pub fn parse_fasta_db<R>(records: R) -> MtsvResult<Database>
+    where R: Iterator<Item = io::Result<fasta::Record>>
+{
+    parse_fasta_db_with_format(records, HeaderFormat::GiTaxId)
+}
+
+/// Parse a FASTA database whose headers are in the given format into a single map of all
+/// taxonomy IDs.
+///
+/// For `HeaderFormat::TaxId` databases, GIs are assigned sequentially from 1 in input order.
+/// `HeaderFormat::Accession` databases need an accession map, see
+/// `parse_fasta_db_with_accessions`.
+pub fn parse_fasta_db_with_format<R>(records: R, format: HeaderFormat) -> MtsvResult<Database>
+    where R: Iterator<Item = io::Result<fasta::Record>>
+{
+    Ok(parse_fasta_db_headers(records, format, None)?.0)
+}
+
+/// Parse a FASTA database with `HeaderFormat::Accession` headers into a single map of all
+/// taxonomy IDs, looking up each accession's taxid in `accession_map`. GIs are assigned
+/// sequentially from 1 in input order, and each GI's accession is returned alongside.
+///
+/// Accessions are written into results files, so they can't contain `,`, `=` or `:`.
+pub fn parse_fasta_db_with_accessions<R>(records: R,
+                                         accession_map: &AccessionMap)
+                                         -> MtsvResult<(Database, HeaderMap)>
+    where R: Iterator<Item = io::Result<fasta::Record>>
+{
+    parse_fasta_db_headers(records, HeaderFormat::Accession, Some(accession_map))
+}
+
+fn parse_fasta_db_headers<R>(records: R,
+                             format: HeaderFormat,
+                             accession_map: Option<&AccessionMap>)
+                             -> MtsvResult<(Database, HeaderMap)>
+    where R: Iterator<Item = io::Result<fasta::Record>>
+{
+    let mut taxon_map = BTreeMap::new();
+    let mut accessions = BTreeMap::new();
+    parse_fasta_references(records, format, accession_map, false, |gi, tax_id, accession, _, seq| {
+        let sequences = taxon_map.entry(tax_id).or_insert_with(Vec::new);
+        sequences.push((gi, seq.to_vec()));
+        if let Some(accession) = accession {
+            accessions.insert(gi, accession.to_string());
+        }
+        Ok(())
+    })?;
+
+    Ok((taxon_map, accessions))
+}
+
+/// Parse the references of a FASTA database whose headers are in the given format, handing each
+/// one's GI, taxid, accession (for `HeaderFormat::Accession` headers), description (the rest of
+/// its header line, if any) and sequence to `add` as it's read, rather than collecting them into
+/// a `Database`. GIs are assigned and accessions looked up as `parse_fasta_db_with_format` and
+/// `parse_fasta_db_with_accessions` do. Parsing stops at the first error `add` returns.
+///
+/// Records with no sequence, or only whitespace, would be empty bins in an index, so they're
+/// skipped with a warning, or are an error if `strict`. Their GIs are still used up for
+/// `HeaderFormat::TaxId` and `HeaderFormat::Accession` headers, so the rest keep theirs.
+///
+/// Other bytes which aren't residues of the index's alphabet become its wildcard when they're
+/// added (see `Alphabet::normalize`), but a `$`, the suffix array's sentinel, or a byte which
+/// isn't ASCII means the file is malformed, so either fails with
+/// `MtsvError::InvalidReferenceByte`.
+///
+/// The number of bases read so far is logged as a `Progress`.
+pub fn parse_fasta_references<R, F>(records: R,
+                                    format: HeaderFormat,
+                                    accession_map: Option<&AccessionMap>,
+                                    strict: bool,
+                                    mut add: F)
+                                    -> MtsvResult<()>
+    where R: Iterator<Item = io::Result<fasta::Record>>,
+          F: FnMut(Gi, TaxId, Option<&str>, Option<&str>, &[u8]) -> MtsvResult<()>
+{
+    debug!("Parsing FASTA database file...");
+    let progress = Progress::new("Reading references", "bases", None);
+    for (i, record) in records.enumerate() {
+        let record = (record)?;
+        let (gi, tax_id, accession) = reference_ids(&record, i, format, accession_map)?;
+        if !check_reference(&record, strict)? {
+            continue;
+        }
+        add(gi, tax_id, accession, record.desc(), record.seq())?;
+        progress.add(record.seq().len() as u64);
+    }
+
+    progress.finish();
+    Ok(())
+}
+
+/// The GI, taxid and accession (for `HeaderFormat::Accession` headers) of `record`, record
+/// number `i` (from 0) of a FASTA database, as `parse_fasta_references` reads them.
+fn reference_ids<'a>(record: &'a fasta::Record,
+                     i: usize,
+                     format: HeaderFormat,
+                     accession_map: Option<&AccessionMap>)
+                     -> MtsvResult<(Gi, TaxId, Option<&'a str>)> {
+    match (format, accession_map) {
+        (HeaderFormat::GiTaxId, _) => {
+            let (gi, tax_id) = parse_read_header(record.id())?;
+            Ok((gi, tax_id, None))
+        },
+        (HeaderFormat::TaxId, _) => Ok((Gi(i as u32 + 1), parse_taxid_header(record.id())?, None)),
+        (HeaderFormat::Accession, Some(map)) => {
+            let accession = record.id();
+            if accession.is_empty() {
+                return Err(MtsvError::MissingHeader);
+            }
+            if accession.contains([',', '=', ':']) {
+                return Err(MtsvError::InvalidHeader(accession.to_string()));
+            }
+            let tax_id = map.get(accession)
+                .ok_or_else(|| MtsvError::UnmappedAccession(accession.to_string()))?;
+            Ok((Gi(i as u32 + 1), tax_id, Some(accession)))
+        },
+        (HeaderFormat::Accession, None) => Err(missing_accession_map()),
+    }
+}
+
+/// The error for parsing accession headers without an accession map.
+fn missing_accession_map() -> MtsvError {
+    MtsvError::InvalidParameter(String::from("accession headers need an accession map or \
+                                              accession2taxid file"))
+}
+
+/// The number of references and bases a taxid would have in an index, as
+/// `survey_fasta_references` counts them.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct TaxonCounts {
+    /// The number of references.
+    pub references: usize,
+    /// The number of bases across them.
+    pub bases: u64,
+}
 
-    let f = File::open(Path::new(p))?;
-    let mut reader = BufReader::new(f);
-    Ok(deserialize_from(&mut reader)?)
+/// A record which would fail a build, found by `survey_fasta_references`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ReferenceProblem {
+    /// The record's number in the database, from 1.
+    pub record: usize,
+    /// The record's ID, the first word of its header.
+    pub id: String,
+    /// Why it would fail the build.
+    pub why: String,
 }
 
-/// Write an arbitrary `Encodable` type to a file path.
-pub fn write_to_file<T>(t: &T, p: &str) -> MtsvResult<()>
-    where T: Serialize
-{
+/// What an index built from a FASTA database would hold, and which of its records would stop
+/// the build, found by `survey_fasta_references` without keeping any sequences.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReferenceSurvey {
+    /// The references and bases of each taxid.
+    pub taxa: BTreeMap<TaxId, TaxonCounts>,
+    /// The IDs of the records which would be skipped for having no sequence.
+    pub skipped: Vec<String>,
+    /// The records which would fail the build, in the order they're read.
+    pub problems: Vec<ReferenceProblem>,
+}
+
+impl ReferenceSurvey {
+    /// The number of references which would be indexed.
+    pub fn references(&self) -> usize {
+        self.taxa.values().map(|counts| counts.references).sum()
+    }
+
+    /// The number of bases which would be indexed.
+    pub fn bases(&self) -> u64 {
+        self.taxa.values().map(|counts| counts.bases).sum()
+    }
+}
+
+/// Read a FASTA database as `parse_fasta_references` does, but only count the references and
+/// bases of each taxid rather than handing the references on, so that it takes next to no memory
+/// whatever the size of the database. Rather than stopping at the first record which would fail
+/// a build, such as one with a malformed header or an accession missing from `accession_map`,
+/// every such record is collected with why it fails. `check` can fail the references which get
+/// that far for reasons of its own, given their GIs and sequences.
+///
+/// Only records which can't be read at all, and a missing accession map for
+/// `HeaderFormat::Accession` headers, are errors.
+pub fn survey_fasta_references<R, F>(records: R,
+                                     format: HeaderFormat,
+                                     accession_map: Option<&AccessionMap>,
+                                     strict: bool,
+                                     mut check: F)
+                                     -> MtsvResult<ReferenceSurvey>
+    where R: Iterator<Item = io::Result<fasta::Record>>,
+          F: FnMut(Gi, &[u8]) -> MtsvResult<()>
+{
+    if format == HeaderFormat::Accession && accession_map.is_none() {
+        return Err(missing_accession_map());
+    }
+
+    let progress = Progress::new("Surveying references", "bases", None);
+    let mut survey = ReferenceSurvey::default();
+    for (i, record) in records.enumerate() {
+        let record = (record)?;
+        let checked = reference_ids(&record, i, format, accession_map).and_then(|(gi, tax_id, _)| {
+            if !check_reference(&record, strict)? {
+                return Ok(None);
+            }
+            check(gi, record.seq())?;
+            Ok(Some(tax_id))
+        });
+        match checked {
+            Ok(Some(tax_id)) => {
+                let counts = survey.taxa.entry(tax_id).or_insert_with(TaxonCounts::default);
+                counts.references += 1;
+                counts.bases += record.seq().len() as u64;
+            },
+            Ok(None) => survey.skipped.push(record.id().to_string()),
+            Err(why) => {
+                survey.problems.push(ReferenceProblem {
+                    record: i + 1,
+                    id: record.id().to_string(),
+                    why: why.to_string(),
+                })
+            },
+        }
+        progress.add(record.seq().len() as u64);
+    }
+
+    progress.finish();
+    Ok(survey)
+}
+
+/// Whether a reference record should be added to an index, as `parse_fasta_references` says:
+/// false, with a warning, if it has no sequence and isn't `strict`, and an error if it's
+/// malformed.
+fn check_reference(record: &fasta::Record, strict: bool) -> MtsvResult<bool> {
+    if record.seq().iter().all(u8::is_ascii_whitespace) {
+        if strict {
+            return Err(MtsvError::EmptyReference(record.id().to_string()));
+        }
+        warn!("Skipping reference {}, which has no sequence", record.id());
+        return Ok(false);
+    }
+    if let Some(i) = record.seq().iter().position(|&b| b == b'$' || !b.is_ascii()) {
+        return Err(MtsvError::InvalidReferenceByte(record.id().to_string(),
+                                                   record.seq()[i],
+                                                   i + 1));
+    }
+    Ok(true)
+}
+
+/// A FASTA file listed in a `FastaManifest`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ManifestEntry {
+    /// The line of the manifest it's on, from 1.
+    pub line: usize,
+    /// The path of the file, relative paths being relative to the manifest's directory.
+    pub path: PathBuf,
+    /// The taxid of all of the file's references.
+    pub tax_id: TaxId,
+    /// The GI of the file's first reference, if the manifest gives one.
+    pub starting_gi: Option<Gi>,
+}
+
+/// A list of FASTA files whose references each belong to a single taxid, such as one assembly
+/// per organism, so that their headers needn't say (see `parse_manifest_references`).
+#[derive(Clone, Debug, PartialEq)]
+pub struct FastaManifest {
+    path: String,
+    entries: Vec<ManifestEntry>,
+}
+
+impl FastaManifest {
+    /// Load a manifest with a FASTA file's path and its taxid on each line, and optionally the
+    /// GI to number its references from, separated by tabs. Relative paths are relative to the
+    /// manifest's directory. Blank lines and lines starting with '#' are skipped.
+    pub fn from_file<P: AsRef<Path>>(p: P) -> MtsvResult<Self> {
+        let path = p.as_ref();
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        let mut entries = Vec::new();
+
+        for (i, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+            let line = line?;
+            let entry = line.trim();
+            if entry.is_empty() || entry.starts_with('#') {
+                continue;
+            }
+
+            let invalid = || {
+                MtsvError::InvalidListEntry(path.display().to_string(), i + 1, entry.to_string())
+            };
+
+            let mut columns = entry.split('\t');
+            let (fasta, tax_id, starting_gi) =
+                match (columns.next(), columns.next(), columns.next(), columns.next()) {
+                    (Some(fasta), Some(tax_id), starting_gi, None) if !fasta.is_empty() => {
+                        (fasta, tax_id, starting_gi)
+                    },
+                    _ => return Err(invalid()),
+                };
+            entries.push(ManifestEntry {
+                line: i + 1,
+                path: dir.join(fasta),
+                tax_id: tax_id.parse::<TaxId>().map_err(|_| invalid())?,
+                starting_gi: match starting_gi {
+                    Some(gi) => Some(gi.parse::<Gi>().map_err(|_| invalid())?),
+                    None => None,
+                },
+            });
+        }
+
+        Ok(FastaManifest { path: path.display().to_string(), entries })
+    }
+
+    /// The files listed, in order.
+    pub fn entries(&self) -> &[ManifestEntry] {
+        &self.entries
+    }
+
+    /// The number of files listed.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether no files are listed.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Parse the references of the FASTA files listed in `manifest`, one file after another, handing
+/// each one's GI, taxid and sequence to `add` as `parse_fasta_references` does. Each reference
+/// gets the taxid its file is listed with, and GIs are assigned sequentially, from a file's
+/// starting GI if it has one, and otherwise on from the previous file's, or 1. Headers aren't
+/// parsed, so there are no accessions, and the description is the whole header line.
+///
+/// Empty and malformed references are handled as `parse_fasta_references` handles them, and GIs
+/// are still used up by those skipped. Errors reading a file are
+/// `MtsvError::InvalidManifestEntry`, naming the manifest line and the file, and the record for
+/// errors reading one.
+pub fn parse_manifest_references<F>(manifest: &FastaManifest,
+                                    strict: bool,
+                                    mut add: F)
+                                    -> MtsvResult<()>
+    where F: FnMut(Gi, TaxId, Option<&str>, Option<&str>, &[u8]) -> MtsvResult<()>
+{
+    let progress = Progress::new("Reading references", "bases", None);
+    let mut next_gi = 1;
+    for entry in &manifest.entries {
+        let failed = |e| {
+            MtsvError::InvalidManifestEntry(manifest.path.clone(), entry.line, Box::new(e))
+        };
+        let path = entry.path.display().to_string();
+        if !entry.path.is_file() {
+            return Err(failed(MtsvError::MissingFile(path)));
+        }
+
+        let first_gi = entry.starting_gi.map_or(next_gi, |gi| gi.0);
+        let mut records = 0;
+        for record in open_fasta(&entry.path).map_err(failed)?.records() {
+            records += 1;
+            let in_record = |e: MtsvError| {
+                failed(MtsvError::InvalidFastaRecord(path.clone(), records as usize, Box::new(e)))
+            };
+            let record = record.map_err(|e| in_record(e.into()))?;
+            let gi = Gi(first_gi + records - 1);
+            if !check_reference(&record, strict).map_err(in_record)? {
+                continue;
+            }
+
+            let header = match record.desc() {
+                Some(desc) => format!("{} {}", record.id(), desc),
+                None => record.id().to_string(),
+            };
+            add(gi, entry.tax_id, None, Some(&header), record.seq())?;
+            progress.add(record.seq().len() as u64);
+        }
+        next_gi = first_gi + records;
+    }
+
+    progress.finish();
+    Ok(())
+}
+
+/// Parse the references of the FASTA files listed in `manifest` into a single map of all
+/// taxonomy IDs (see `parse_manifest_references`).
+pub fn parse_manifest_db(manifest: &FastaManifest) -> MtsvResult<Database> {
+    let mut taxon_map = BTreeMap::new();
+    parse_manifest_references(manifest, false, |gi, tax_id, _, _, seq| {
+        taxon_map.entry(tax_id).or_insert_with(Vec::new).push((gi, seq.to_vec()));
+        Ok(())
+    })?;
+    Ok(taxon_map)
+}
+
+/// Check that a FASTQ record has a quality score for every base, since corrupt files can have
+/// truncated quality lines which the parser accepts.
+pub fn check_fastq_record(record: &fastq::Record) -> MtsvResult<()> {
+    if record.seq().len() == record.qual().len() {
+        Ok(())
+    } else {
+        Err(MtsvError::MismatchedQuality(record.id().to_string(),
+                                         record.seq().len(),
+                                         record.qual().len()))
+    }
+}
+
+/// Version of the line format of a results file.
+///
+/// * legacy lines are `READ_ID:TAXID,TAXID,...`, without edit distances (see `parse_findings`).
+///   Legacy files have no header lines, since older readers don't skip them, so `FindingsReader`
+///   can't tell them apart from v1 files, but `FindingsIter` can.
+/// * v1 lines are `READ_ID:TAXID=EDIT,TAXID=EDIT,...`, split at the last colon, with read IDs
+///   written as they are unless the file declares them escaped.
+/// * v2 files start with a `#mtsv-findings v2 fields=...` schema line naming the fields of each
+///   hit. Lines are `READ_ID:HIT,HIT,...` with the values of each hit's fields joined by `=` in
+///   schema order, and read IDs are always escaped (see `escape_read_id`). Fields a reader
+///   doesn't know are skipped, so fields can be added without breaking older readers.
+///
+///   A v1 or v2 line whose hits were capped (see `FindingsWriter::with_max_taxids`) ends with an
+///   `...+N` marker after its last hit, recording that N more taxids were left off.
+/// * binary files are a stream of unsigned LEB128 varints, for runs whose text results would be
+///   too large. They start with `BINARY_MAGIC`, a format version, the sample name (as its length
+///   and UTF-8 bytes, empty if there isn't one), and the hit fields as in v2 (their count, then
+///   each name's length and bytes). Then each read is its ID's length and bytes as they are, its
+///   number of hits, and each hit's field values in schema order. Hits are sorted by taxid, and
+///   each taxid is written as the difference from the previous hit's.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum FindingsVersion {
+    /// The taxid-only `READ_ID:TAXID,...` format.
+    Legacy,
+    /// The original `READ_ID:TAXID=EDIT,...` format.
+    #[default]
+    V1,
+    /// The format with a schema line.
+    V2,
+    /// The binary format.
+    Binary,
+}
+
+impl FromStr for FindingsVersion {
+    type Err = MtsvError;
+
+    fn from_str(s: &str) -> MtsvResult<Self> {
+        match s {
+            "legacy" => Ok(FindingsVersion::Legacy),
+            "v1" => Ok(FindingsVersion::V1),
+            "v2" => Ok(FindingsVersion::V2),
+            "binary" => Ok(FindingsVersion::Binary),
+            _ => Err(MtsvError::InvalidFindings(format!("unsupported format version {:?}", s))),
+        }
+    }
+}
+
+/// A field of each hit in a v2 results file.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum FindingsField {
+    /// `taxid`, see `Hit::tax_id`. Every v2 file has this field.
+    TaxId,
+    /// `edit`, see `Hit::edit`.
+    Edit,
+    /// `seeds`, see `Hit::seeds`.
+    Seeds,
+    /// `seeds_searched`, see `Hit::seeds_searched`.
+    SeedsSearched,
+    /// `clipped_start`, see `Hit::clipped_start`.
+    ClippedStart,
+    /// `clipped_end`, see `Hit::clipped_end`.
+    ClippedEnd,
+    /// `accession`, the accession of the reference the hit was aligned to (see `Hit::gi`). Only
+    /// written by `mtsv-binner --emit-accessions`, and skipped when read back.
+    Accession,
+    /// A field from a newer writer, whose values are skipped.
+    Other(String),
+}
+
+/// The fields mtsv-binner writes to v2 results files.
+pub const V2_FIELDS: [FindingsField; 6] = [FindingsField::TaxId,
+                                           FindingsField::Edit,
+                                           FindingsField::Seeds,
+                                           FindingsField::SeedsSearched,
+                                           FindingsField::ClippedStart,
+                                           FindingsField::ClippedEnd];
+
+impl FindingsField {
+    /// The field's name in the schema line.
+    pub fn name(&self) -> &str {
+        match *self {
+            FindingsField::TaxId => "taxid",
+            FindingsField::Edit => "edit",
+            FindingsField::Seeds => "seeds",
+            FindingsField::SeedsSearched => "seeds_searched",
+            FindingsField::ClippedStart => "clipped_start",
+            FindingsField::ClippedEnd => "clipped_end",
+            FindingsField::Accession => "accession",
+            FindingsField::Other(ref name) => name,
+        }
+    }
+
+    /// The field with a name from a schema line.
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            "taxid" => FindingsField::TaxId,
+            "edit" => FindingsField::Edit,
+            "seeds" => FindingsField::Seeds,
+            "seeds_searched" => FindingsField::SeedsSearched,
+            "clipped_start" => FindingsField::ClippedStart,
+            "clipped_end" => FindingsField::ClippedEnd,
+            "accession" => FindingsField::Accession,
+            _ => FindingsField::Other(name.to_string()),
+        }
+    }
+
+    /// The field's value for a hit, or None for unknown fields and accessions.
+    fn value(&self, hit: &Hit) -> Option<u64> {
+        match *self {
+            FindingsField::TaxId => Some(hit.tax_id.0),
+            FindingsField::Edit => Some(hit.edit as u64),
+            FindingsField::Seeds => Some(hit.seeds as u64),
+            FindingsField::SeedsSearched => Some(hit.seeds_searched as u64),
+            FindingsField::ClippedStart => Some(hit.clipped_start as u64),
+            FindingsField::ClippedEnd => Some(hit.clipped_end as u64),
+            FindingsField::Accession | FindingsField::Other(_) => None,
+        }
+    }
+
+    /// Set the field's value for a hit, if it's a known field. Taxids take any 64-bit value, and
+    /// the other fields must fit in 32 bits.
+    fn set(&self, hit: &mut Hit, value: u64) -> MtsvResult<()> {
+        let narrow = || {
+            u32::try_from(value).map_err(|_| {
+                MtsvError::InvalidFindings(format!("hit value {} is too large", value))
+            })
+        };
+        match *self {
+            FindingsField::TaxId => hit.tax_id = TaxId(value),
+            FindingsField::Edit => hit.edit = narrow()?,
+            FindingsField::Seeds => hit.seeds = narrow()?,
+            FindingsField::SeedsSearched => hit.seeds_searched = narrow()?,
+            FindingsField::ClippedStart => hit.clipped_start = narrow()?,
+            FindingsField::ClippedEnd => hit.clipped_end = narrow()?,
+            FindingsField::Accession | FindingsField::Other(_) => (),
+        }
+        Ok(())
+    }
+}
+
+/// Provenance metadata at the top of a results file, written as `#key=value` lines, after the
+/// schema line of a v2 file.
+///
+/// Unknown keys are ignored when reading, so older readers can skip metadata they don't know.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ResultsHeader {
+    /// The name of the sample the reads came from.
+    pub sample: Option<String>,
+    /// Whether the read IDs in a v1 file are escaped (see `escape_read_id`). They always are in
+    /// v2 files.
+    pub escaped_ids: bool,
+    /// The line format of the file.
+    pub version: FindingsVersion,
+    /// The fields of each hit in a v2 or binary file, in order. Empty for v1 files.
+    pub fields: Vec<FindingsField>,
+}
+
+impl ResultsHeader {
+    /// A header for a v2 file with hits of the given fields.
+    pub fn v2(fields: &[FindingsField]) -> Self {
+        ResultsHeader {
+            version: FindingsVersion::V2,
+            fields: fields.to_vec(),
+            ..ResultsHeader::default()
+        }
+    }
+
+    /// A header for a binary file with hits of the given fields.
+    pub fn binary(fields: &[FindingsField]) -> Self {
+        ResultsHeader {
+            version: FindingsVersion::Binary,
+            fields: fields.to_vec(),
+            ..ResultsHeader::default()
+        }
+    }
+
+    /// Whether the read IDs in the file are escaped.
+    pub fn escapes_ids(&self) -> bool {
+        self.escaped_ids || self.version == FindingsVersion::V2
+    }
+
+    /// Write the header lines, if there's anything to record. Legacy files never have any, and
+    /// binary files always start with a binary header instead.
+    pub fn write<W: Write>(&self, writer: &mut W) -> MtsvResult<()> {
+        match self.version {
+            FindingsVersion::Legacy => return Ok(()),
+            FindingsVersion::Binary => return self.write_binary(writer),
+            _ => (),
+        }
+        if self.version == FindingsVersion::V2 {
+            let fields = self.fields.iter().map(|f| f.name()).collect::<Vec<_>>();
+            writeln!(writer,
+                     "{} v{} fields={}",
+                     SCHEMA_LINE_PREFIX,
+                     FINDINGS_SCHEMA_VERSION,
+                     fields.join(","))?;
+        }
+        if let Some(ref sample) = self.sample {
+            writeln!(writer, "#sample={}", sample)?;
+        }
+        if self.escaped_ids && self.version == FindingsVersion::V1 {
+            writeln!(writer, "{}", ESCAPED_IDS_LINE)?;
+        }
+        Ok(())
+    }
+
+    /// Read and consume any header lines, or the header of a binary file, from the start of a
+    /// results file, leaving the reader positioned at the first read's findings.
+    pub fn read<R: BufRead>(reader: &mut R) -> MtsvResult<Self> {
+        if reader.fill_buf()?.starts_with(BINARY_MAGIC) {
+            return ResultsHeader::read_binary(reader);
+        }
+
+        let mut header = ResultsHeader::default();
+
+        while reader.fill_buf()?.starts_with(b"#") {
+            let mut line = String::new();
+            reader.read_line(&mut line)?;
+            header.apply_line(&line)?;
+        }
+
+        Ok(header)
+    }
+
+    fn write_binary<W: Write>(&self, writer: &mut W) -> MtsvResult<()> {
+        let mut bytes = BINARY_MAGIC.to_vec();
+        write_varint(&mut bytes, BINARY_FINDINGS_VERSION);
+        write_binary_string(&mut bytes, self.sample.as_ref().map_or("", |s| s.as_str()));
+        write_varint(&mut bytes, self.fields.len() as u64);
+        for field in &self.fields {
+            write_binary_string(&mut bytes, field.name());
+        }
+        writer.write_all(&bytes)?;
+        Ok(())
+    }
+
+    fn read_binary<R: BufRead>(reader: &mut R) -> MtsvResult<Self> {
+        read_binary_bytes(reader, BINARY_MAGIC.len() as u64)?;
+        let version = read_binary_value(reader)?;
+        if version != BINARY_FINDINGS_VERSION {
+            return Err(MtsvError::InvalidFindings(format!("unsupported binary format version {}",
+                                                          version)));
+        }
+
+        let sample = read_binary_string(reader)?;
+        let mut fields = Vec::new();
+        for _ in 0..read_binary_value(reader)? {
+            fields.push(FindingsField::from_name(&read_binary_string(reader)?));
+        }
+        if !fields.contains(&FindingsField::TaxId) {
+            return Err(MtsvError::InvalidFindings("binary header has no taxid field".to_string()));
+        }
+
+        Ok(ResultsHeader {
+            sample: if sample.is_empty() { None } else { Some(sample) },
+            ..ResultsHeader::binary(&fields)
+        })
+    }
+
+    /// Update the header from one of its lines.
+    fn apply_line(&mut self, line: &str) -> MtsvResult<()> {
+        let line = line.trim();
+
+        if let Some(schema) = line.strip_prefix(SCHEMA_LINE_PREFIX) {
+            let mut tokens = schema.split_whitespace();
+            self.version = tokens.next().unwrap_or("").parse()?;
+            if self.version == FindingsVersion::Legacy || self.version == FindingsVersion::Binary {
+                return Err(MtsvError::InvalidFindings(format!("schema line for a format without \
+                                                               one: {}",
+                                                              line)));
+            }
+            self.fields = Vec::new();
+            for token in tokens {
+                if let Some(fields) = token.strip_prefix("fields=") {
+                    self.fields = fields
+                        .split(',')
+                        .map(FindingsField::from_name)
+                        .collect();
+                }
+            }
+
+            if self.version == FindingsVersion::V2 &&
+               !self.fields.contains(&FindingsField::TaxId) {
+                return Err(MtsvError::InvalidFindings(format!("schema has no taxid field: {}",
+                                                              line)));
+            }
+            return Ok(());
+        }
+
+        let mut kv = line[1..].splitn(2, '=');
+        match (kv.next(), kv.next()) {
+            (Some("sample"), Some(sample)) => self.sample = Some(sample.to_string()),
+            (Some("escaped_ids"), Some(escaped)) => self.escaped_ids = escaped == "true",
+            _ => debug!("Ignoring results header line: {}", line),
+        }
+        Ok(())
+    }
+}
+
+/// The start of the schema line of a v2 results file.
+const SCHEMA_LINE_PREFIX: &str = "#mtsv-findings";
+
+/// The `ResultsHeader` line declaring that a results file's read IDs are escaped.
+const ESCAPED_IDS_LINE: &str = "#escaped_ids=true";
+
+/// The start of the token after a read's last hit which records how many taxids were left off.
+const OVERFLOW_MARKER: &str = "...+";
+
+/// The bytes a binary results file starts with, which can't start a text results file.
+pub const BINARY_MAGIC: &[u8] = b"\x89MTSVF\r\n";
+
+/// Append a value to a binary results record as an unsigned LEB128 varint.
+fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+/// Append a string to a binary results record as its length and bytes.
+fn write_binary_string(bytes: &mut Vec<u8>, s: &str) {
+    write_varint(bytes, s.len() as u64);
+    bytes.extend_from_slice(s.as_bytes());
+}
+
+/// Read a varint from a binary results file, or None at the end of the file.
+fn read_varint<R: BufRead>(reader: &mut R) -> MtsvResult<Option<u64>> {
+    let mut value = 0;
+    for i in 0..10 {
+        let byte = match reader.fill_buf()?.first() {
+            Some(&b) => b,
+            None if i == 0 => return Ok(None),
+            None => return Err(truncated_binary()),
+        };
+        reader.consume(1);
+
+        value |= ((byte & 0x7f) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(Some(value));
+        }
+    }
+    Err(MtsvError::InvalidFindings("binary value is more than 64 bits".to_string()))
+}
+
+/// Read a varint from a binary results file, which mustn't end first.
+fn read_binary_value<R: BufRead>(reader: &mut R) -> MtsvResult<u64> {
+    read_varint(reader)?.ok_or_else(truncated_binary)
+}
+
+fn read_binary_bytes<R: BufRead>(reader: &mut R, len: u64) -> MtsvResult<Vec<u8>> {
+    let mut bytes = Vec::new();
+    reader.by_ref().take(len).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != len {
+        return Err(truncated_binary());
+    }
+    Ok(bytes)
+}
+
+fn read_binary_string<R: BufRead>(reader: &mut R) -> MtsvResult<String> {
+    let len = read_binary_value(reader)?;
+    String::from_utf8(read_binary_bytes(reader, len)?)
+        .map_err(|_| MtsvError::InvalidFindings("binary string isn't UTF-8".to_string()))
+}
+
+fn truncated_binary() -> MtsvError {
+    MtsvError::InvalidFindings("binary results end part way through a read".to_string())
+}
+
+/// Whether a character of a read ID is percent-encoded by `escape_read_id`.
+fn needs_escape(c: char) -> bool {
+    match c {
+        '%' | ':' | ',' | '=' | '#' => true,
+        _ => c.is_whitespace() || c.is_control(),
+    }
+}
+
+/// Escape a read ID for a results file by percent-encoding (e.g. `:` as `%3A`) the separators
+/// used in results lines, `%`, `#`, whitespace and control characters, byte by byte for
+/// characters outside ASCII. Any non-empty read ID can be written once escaped.
+pub fn escape_read_id(id: &str) -> Cow<'_, str> {
+    if !id.chars().any(needs_escape) {
+        return Cow::Borrowed(id);
+    }
+
+    let mut escaped = String::with_capacity(id.len() + 8);
+    for c in id.chars() {
+        if needs_escape(c) {
+            let mut utf8 = [0; 4];
+            for b in c.encode_utf8(&mut utf8).bytes() {
+                escaped.push_str(&format!("%{:02X}", b));
+            }
+        } else {
+            escaped.push(c);
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+/// Undo `escape_read_id`.
+pub fn unescape_read_id(id: &str) -> MtsvResult<String> {
+    let invalid = || MtsvError::InvalidReadId(id.to_string());
+
+    let bytes = id.as_bytes();
+    let mut unescaped = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = bytes.get(i + 1..i + 3).ok_or_else(invalid)?;
+            if !hex.iter().all(|b| b.is_ascii_hexdigit()) {
+                return Err(invalid());
+            }
+            let hex = str::from_utf8(hex)?;
+            unescaped.push(u8::from_str_radix(hex, 16).map_err(|_| invalid())?);
+            i += 3;
+        } else {
+            unescaped.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    String::from_utf8(unescaped).map_err(|_| invalid())
+}
+
+/// What to do with control characters, such as line breaks, in the read IDs of query reads.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ControlCharPolicy {
+    /// Replace each control character with `_`.
+    #[default]
+    Replace,
+    /// Stop with an error.
+    Reject,
+}
+
+impl FromStr for ControlCharPolicy {
+    type Err = MtsvError;
+
+    fn from_str(s: &str) -> MtsvResult<Self> {
+        match s {
+            "replace" => Ok(ControlCharPolicy::Replace),
+            "reject" => Ok(ControlCharPolicy::Reject),
+            _ => {
+                Err(MtsvError::InvalidParameter(format!("unknown control character policy {:?}",
+                                                        s)))
+            },
+        }
+    }
+}
+
+/// Apply `policy` to the control characters in a read ID, so that a line break in an ID can't
+/// split its results line in two. IDs without control characters are returned as they are.
+pub fn sanitize_read_id(id: &str, policy: ControlCharPolicy) -> MtsvResult<Cow<'_, str>> {
+    if !id.chars().any(char::is_control) {
+        return Ok(Cow::Borrowed(id));
+    }
+
+    match policy {
+        ControlCharPolicy::Replace => {
+            Ok(Cow::Owned(id.chars().map(|c| if c.is_control() { '_' } else { c }).collect()))
+        },
+        ControlCharPolicy::Reject => Err(MtsvError::InvalidReadId(id.to_string())),
+    }
+}
+
+/// Check that a read ID will be parsed back unchanged from a results line.
+///
+/// The parsers split each line at its last colon, so colons, commas and digits anywhere in a read
+/// ID are fine, but an empty ID, one starting with `#` (a header line) or whitespace (trimmed),
+/// or one containing a line break can't be written unescaped.
+pub fn check_read_id(id: &str) -> MtsvResult<()> {
+    if id.is_empty() || id.starts_with('#') || id.trim_start() != id ||
+       id.contains(['\n', '\r']) {
+        Err(MtsvError::InvalidReadId(id.to_string()))
+    } else {
+        Ok(())
+    }
+}
+
+/// The findings lines of a results file, paired with whether their read IDs are escaped, which
+/// is declared by a `ResultsHeader` line. Other header lines are skipped.
+fn findings_lines<'a, R: BufRead + 'a>
+    (s: R)
+     -> Box<dyn Iterator<Item = io::Result<(String, bool)>> + 'a> {
+    let mut escaped = false;
+    Box::new(s.lines().filter_map(move |line| {
+        match line {
+            Ok(l) => {
+                if l.starts_with('#') {
+                    if l.trim() == ESCAPED_IDS_LINE {
+                        escaped = true;
+                    }
+                    None
+                } else {
+                    Some(Ok((l, escaped)))
+                }
+            },
+            Err(e) => Some(Err(e)),
+        }
+    }))
+}
+
+/// Reads the findings of a v1, v2 or binary results file (see `FindingsVersion`), as each read's
+/// ID and hits.
+///
+/// Header lines are applied as they're reached, so the format of a text file may change part way
+/// through, e.g. in results files which were concatenated. Hit fields which aren't in the file
+/// are zero.
+pub struct FindingsReader<R: BufRead> {
+    reader: R,
+    header: ResultsHeader,
+    /// Whether the start of the file has been checked for a binary header.
+    started: bool,
+    pending: Option<String>,
+    overflow: usize,
+}
+
+impl<R: BufRead> FindingsReader<R> {
+    /// Read findings from `reader`, which is positioned at the start of a results file.
+    pub fn new(reader: R) -> Self {
+        FindingsReader {
+            reader,
+            header: ResultsHeader::default(),
+            started: false,
+            pending: None,
+            overflow: 0,
+        }
+    }
+
+    /// Read the header at the start of the file, if it hasn't been already, and return it.
+    pub fn read_header(&mut self) -> MtsvResult<&ResultsHeader> {
+        if !self.started {
+            self.started = true;
+            if self.reader.fill_buf()?.starts_with(BINARY_MAGIC) {
+                self.header = ResultsHeader::read(&mut self.reader)?;
+            }
+        }
+        if self.header.version == FindingsVersion::Binary {
+            return Ok(&self.header);
+        }
+
+        while self.pending.is_none() {
+            match self.next_line()? {
+                Some(line) => {
+                    if line.starts_with('#') {
+                        self.header.apply_line(&line)?;
+                    } else {
+                        self.pending = Some(line);
+                    }
+                },
+                None => break,
+            }
+        }
+        Ok(&self.header)
+    }
+
+    /// The header lines read so far.
+    pub fn header(&self) -> &ResultsHeader {
+        &self.header
+    }
+
+    /// Number of taxids left off the line of the last read returned, according to its overflow
+    /// marker (see `FindingsVersion`). Zero if its hits weren't capped.
+    pub fn overflow(&self) -> usize {
+        self.overflow
+    }
+
+    /// The next line of a text file, without its line ending.
+    fn next_line(&mut self) -> MtsvResult<Option<String>> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        if line.ends_with('\n') {
+            line.pop();
+            if line.ends_with('\r') {
+                line.pop();
+            }
+        }
+        Ok(Some(line))
+    }
+
+    /// The next read's findings in a binary file, if there are any left.
+    fn read_binary_record(&mut self) -> MtsvResult<Option<(String, Vec<Hit>)>> {
+        let id_len = match read_varint(&mut self.reader)? {
+            Some(len) => len,
+            None => return Ok(None),
+        };
+        let read_id = String::from_utf8(read_binary_bytes(&mut self.reader, id_len)?)
+            .map_err(|e| MtsvError::InvalidReadId(String::from_utf8_lossy(e.as_bytes())
+                .into_owned()))?;
+
+        let num_hits = read_binary_value(&mut self.reader)?;
+        let mut hits = Vec::new();
+        let mut tax_id = 0u64;
+        for _ in 0..num_hits {
+            let mut hit = Hit {
+                tax_id: TaxId(0),
+                edit: 0,
+                seeds: 0,
+                seeds_searched: 0,
+                clipped_start: 0,
+                clipped_end: 0,
+                gi: Gi(0),
+            };
+            for field in &self.header.fields {
+                let value = read_binary_value(&mut self.reader)?;
+                if *field == FindingsField::TaxId {
+                    // taxids are written as the difference from the previous hit's
+                    tax_id = tax_id.checked_add(value).ok_or_else(|| {
+                        MtsvError::InvalidFindings(format!("taxid overflows for read {}",
+                                                           read_id))
+                    })?;
+                    field.set(&mut hit, tax_id)?;
+                } else {
+                    field.set(&mut hit, value)?;
+                }
+            }
+            hits.push(hit);
+        }
+
+        Ok(Some((read_id, hits)))
+    }
+
+    /// Parse a findings line according to the current header, along with its overflow count.
+    fn parse_line(&self, line: &str) -> MtsvResult<(String, Vec<Hit>, usize)> {
+        let line = line.trim();
+        // split from the right in case someone put colons in the read ID
+        let mut halves = line.rsplitn(2, ':');
+
+        // the first split iteration will always return something, even if it's empty
+        let raw_hits = halves.next().unwrap();
+        let read_id = match halves.next() {
+            Some(r) if !r.is_empty() => r,
+            _ => return Err(MtsvError::InvalidHeader(line.to_string())),
+        };
+        let read_id = if self.header.escapes_ids() {
+            unescape_read_id(read_id)?
+        } else {
+            read_id.to_string()
+        };
+
+        let parse = |value: &str| {
+            value.parse::<u64>().map_err(|_| MtsvError::InvalidInteger(value.to_string()))
+        };
+
+        let mut raw_hits = raw_hits.split(',').collect::<Vec<_>>();
+        let mut overflow = 0;
+        if let Some(count) = raw_hits.last().and_then(|h| h.strip_prefix(OVERFLOW_MARKER)) {
+            overflow = count.parse::<usize>()
+                .map_err(|_| MtsvError::InvalidInteger(count.to_string()))?;
+            raw_hits.pop();
+        }
+
+        let mut hits = Vec::new();
+        for raw_hit in raw_hits {
+            let mut hit = Hit {
+                tax_id: TaxId(0),
+                edit: 0,
+                seeds: 0,
+                seeds_searched: 0,
+                clipped_start: 0,
+                clipped_end: 0,
+                gi: Gi(0),
+            };
+            let mut values = raw_hit.split('=');
+
+            match self.header.version {
+                // legacy files have no header to switch the reader to them, see `FindingsIter`
+                FindingsVersion::Legacy => {
+                    hit.tax_id = TaxId(parse(values.next().unwrap())?);
+                },
+                FindingsVersion::V1 => {
+                    hit.tax_id = TaxId(parse(values.next().unwrap())?);
+                    FindingsField::Edit.set(&mut hit, parse(values.next().unwrap_or(""))?)?;
+                },
+                FindingsVersion::V2 | FindingsVersion::Binary => {
+                    for field in &self.header.fields {
+                        let value = values.next().ok_or_else(|| {
+                            MtsvError::InvalidFindings(format!("hit {:?} is missing {}",
+                                                               raw_hit,
+                                                               field.name()))
+                        })?;
+                        if let FindingsField::Accession | FindingsField::Other(_) = *field {
+                            continue;
+                        }
+                        field.set(&mut hit, parse(value)?)?;
+                    }
+                },
+            }
+
+            if values.next().is_some() {
+                return Err(MtsvError::InvalidFindings(format!("hit {:?} has too many values",
+                                                              raw_hit)));
+            }
+            hits.push(hit);
+        }
+
+        Ok((read_id, hits, overflow))
+    }
+}
+
+impl<R: BufRead> Iterator for FindingsReader<R> {
+    type Item = MtsvResult<(String, Vec<Hit>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.started {
+            if let Err(e) = self.read_header() {
+                return Some(Err(e));
+            }
+        }
+        self.overflow = 0;
+        if self.header.version == FindingsVersion::Binary {
+            return self.read_binary_record().transpose();
+        }
+
+        loop {
+            let line = match self.pending.take() {
+                Some(line) => line,
+                None => {
+                    match self.next_line() {
+                        Ok(line) => line?,
+                        Err(e) => return Some(Err(e)),
+                    }
+                },
+            };
+
+            if line.starts_with('#') {
+                if let Err(e) = self.header.apply_line(&line) {
+                    return Some(Err(e));
+                }
+                continue;
+            }
+
+            return Some(self.parse_line(&line).map(|(read_id, hits, overflow)| {
+                self.overflow = overflow;
+                (read_id, hits)
+            }));
+        }
+    }
+}
+
+/// One read's findings from a results file, see `FindingsIter`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReadFindings {
+    /// The read's ID, unescaped if the file escapes them.
+    pub read_id: String,
+    /// The read's hits. Fields which aren't in the file are zero, so hits from legacy files have
+    /// an edit distance of zero.
+    pub hits: Vec<Hit>,
+    /// Whether the read's line was capped, leaving some taxids off (see `FindingsVersion`).
+    pub truncated: bool,
+}
+
+/// Iterates over the findings of a results file in any format (see `FindingsVersion`), as
+/// `FindingsReader` does, but telling legacy files from v1 files by their first findings line.
+pub struct FindingsIter<R: BufRead = Box<dyn BufRead>> {
+    reader: FindingsReader<R>,
+}
+
+impl<R: BufRead> FindingsIter<R> {
+    /// Read findings from `reader`, which is positioned at the start of a results file, reading
+    /// its header lines straight away.
+    pub fn new(reader: R) -> MtsvResult<Self> {
+        let mut reader = FindingsReader::new(reader);
+        reader.read_header()?;
+        // every v1 hit has an edit distance, and no legacy hit does
+        let legacy = reader.pending.as_ref().is_some_and(|line| {
+            let raw_hits = line.trim().rsplit(':').next().unwrap();
+            !raw_hits.is_empty() && !raw_hits.contains('=')
+        });
+        if reader.header.version == FindingsVersion::V1 && legacy {
+            reader.header.version = FindingsVersion::Legacy;
+        }
+        Ok(FindingsIter { reader })
+    }
+
+    /// The header lines read so far, whose version is `Legacy` for legacy files.
+    pub fn header(&self) -> &ResultsHeader {
+        self.reader.header()
+    }
+
+    /// Number of taxids left off the line of the last read returned, as
+    /// `FindingsReader::overflow`.
+    pub fn overflow(&self) -> usize {
+        self.reader.overflow()
+    }
+}
+
+impl<R: BufRead> Iterator for FindingsIter<R> {
+    type Item = MtsvResult<ReadFindings>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let finding = self.reader.next()?;
+        Some(finding.map(|(read_id, hits)| {
+            ReadFindings {
+                read_id,
+                hits,
+                truncated: self.reader.overflow() > 0,
+            }
+        }))
+    }
+}
+
+/// Open a results file in any format, which may be gzipped, and iterate over its findings (see
+/// `FindingsIter`).
+pub fn open_findings<P: AsRef<Path>>(path: P) -> MtsvResult<FindingsIter> {
+    FindingsIter::new(open_maybe_gz(path)?)
+}
+
+/// How many slots per named reference `NameResolver` will spend on a table indexed by GI before
+/// it falls back to hashing.
+const MAX_NAME_TABLE_SPREAD: usize = 4;
+
+/// The names of an index's reference sequences by GI, looked up for every hit written, so built
+/// once from the loaded index and shared by all of the writers (see `FindingsWriter::with_names`).
+///
+/// A reference is named by its accession if the index has one for it, and otherwise by its GI
+/// and taxid as in a `gi-taxid` header, e.g. `123-456`. GIs shared by references with different
+/// taxids, like those of taxon bins (see `BinMode::PerTaxon`), don't name a single reference, so
+/// they have no name. When the GIs are close together, as they are when they were assigned at
+/// build time, a name is found by indexing a table with the GI, and otherwise by hashing it. The
+/// names themselves are kept together in GI order rather than each in its own allocation.
+#[derive(Clone, Debug, Default)]
+pub struct NameResolver {
+    /// Every name, one after another.
+    text: String,
+    /// The GI of the first slot in `table`.
+    first: u32,
+    /// The start and end of each name in `text` by GI less `first`, with empty spans for GIs
+    /// without one, unless the GIs are too spread out.
+    table: Vec<(usize, usize)>,
+    /// The start and end of each name in `text` by GI, when the GIs are too spread out for
+    /// `table`.
+    hashed: HashMap<Gi, (usize, usize)>,
+}
+
+impl NameResolver {
+    /// Name the reference sequences in `bins` (see `MGIndex::bins`), by their entries in
+    /// `accessions` where they have one (see `MGIndex::accessions`).
+    pub fn new<I>(bins: I, accessions: &HeaderMap) -> Self
+        where I: IntoIterator<Item = BinInfo>
+    {
+        let mut tax_ids: BTreeMap<Gi, Option<TaxId>> = BTreeMap::new();
+        for bin in bins {
+            let tax_id = tax_ids.entry(bin.gi).or_insert(Some(bin.tax_id));
+            if *tax_id != Some(bin.tax_id) {
+                *tax_id = None;
+            }
+        }
+
+        // in GI order, from the map
+        let mut text = String::new();
+        let mut spans = Vec::with_capacity(tax_ids.len());
+        for (gi, tax_id) in tax_ids {
+            let start = text.len();
+            match (accessions.get(&gi), tax_id) {
+                (Some(accession), _) => text.push_str(accession),
+                (None, Some(tax_id)) => text.push_str(&format!("{}-{}", gi.0, tax_id.0)),
+                (None, None) => continue,
+            }
+            spans.push((gi, (start, text.len())));
+        }
+
+        let (first, last) = match (spans.first(), spans.last()) {
+            (Some(&(first, _)), Some(&(last, _))) => (first.0, last.0),
+            _ => return NameResolver::default(),
+        };
+        let spread = (last - first) as usize + 1;
+        if spread > spans.len().saturating_mul(MAX_NAME_TABLE_SPREAD) {
+            return NameResolver {
+                text,
+                hashed: spans.into_iter().collect(),
+                ..NameResolver::default()
+            };
+        }
+
+        let mut table = vec![(0, 0); spread];
+        for (gi, span) in spans {
+            table[(gi.0 - first) as usize] = span;
+        }
+        NameResolver { text, first, table, hashed: HashMap::new() }
+    }
+
+    /// The name of the reference sequence with this GI, if it has one.
+    pub fn name(&self, gi: Gi) -> Option<&str> {
+        let span = if self.table.is_empty() {
+            self.hashed.get(&gi)
+        } else {
+            gi.0.checked_sub(self.first).and_then(|slot| self.table.get(slot as usize))
+        };
+        match span {
+            Some(&(start, end)) if start < end => Some(&self.text[start..end]),
+            _ => None,
+        }
+    }
+}
+
+/// Writes the findings of each read in the format of a results header (see `FindingsVersion`).
+///
+/// Each taxid is written once per read, with its best hit (see `Hit::is_better_than`), and reads
+/// without any hits aren't written. Unescaped read IDs which couldn't be read back unchanged are
+/// an error (see `check_read_id`).
+pub struct FindingsWriter<W: Write> {
+    writer: W,
+    header: ResultsHeader,
+    max_taxids: Option<usize>,
+    names: Option<Arc<NameResolver>>,
+    /// The line being written, kept between reads so that it doesn't grow from nothing each time.
+    line: String,
+}
+
+impl<W: Write> FindingsWriter<W> {
+    /// Write v1 findings with unescaped read IDs to `writer`, without any header.
+    pub fn new(writer: W) -> Self {
+        FindingsWriter {
+            writer,
+            header: ResultsHeader::default(),
+            max_taxids: None,
+            names: None,
+            line: String::new(),
+        }
+    }
+
+    /// Write at most `max_taxids` taxids on each line, if set. A read with more only has its best
+    /// hits written (see `Hit::is_better_than`), followed by an overflow marker with the number
+    /// left off (see `FindingsVersion`). Only v1 and v2 lines can have a marker.
+    pub fn with_max_taxids(mut self, max_taxids: Option<usize>) -> Self {
+        self.max_taxids = max_taxids;
+        self
+    }
+
+    /// Write the names of the hits' references in the `accession` field, which a v2 header can
+    /// only have if these are set. References without a name get an empty one.
+    pub fn with_names(mut self, names: Arc<NameResolver>) -> Self {
+        self.names = Some(names);
+        self
+    }
+
+    /// Write the header lines, and write findings in its format from then on.
+    pub fn write_header(&mut self, header: &ResultsHeader) -> MtsvResult<()> {
+        if header.fields.contains(&FindingsField::Accession) {
+            if header.version != FindingsVersion::V2 {
+                return Err(MtsvError::InvalidFindings("only v2 findings can have accessions"
+                    .to_string()));
+            }
+            if self.names.is_none() {
+                return Err(MtsvError::InvalidFindings("can't write accessions without the \
+                                                       index's reference names"
+                    .to_string()));
+            }
+        }
+        let unknown = header.fields.iter().find(|f| matches!(**f, FindingsField::Other(_)));
+        if let Some(field) = unknown {
+            return Err(MtsvError::InvalidFindings(format!("can't write unknown field {}",
+                                                          field.name())));
+        }
+        let has_fields = header.version == FindingsVersion::V2 ||
+                         header.version == FindingsVersion::Binary;
+        if has_fields && !header.fields.contains(&FindingsField::TaxId) {
+            return Err(MtsvError::InvalidFindings("findings need a taxid field".to_string()));
+        }
+        let raw_ids = header.version == FindingsVersion::Legacy ||
+                      header.version == FindingsVersion::Binary;
+        if raw_ids && header.escaped_ids {
+            return Err(MtsvError::InvalidFindings("legacy and binary findings can't have escaped \
+                                                   read IDs"
+                .to_string()));
+        }
+        if raw_ids && self.max_taxids.is_some() {
+            return Err(MtsvError::InvalidFindings("legacy and binary findings can't have capped \
+                                                   lines"
+                .to_string()));
+        }
+
+        header.write(&mut self.writer)?;
+        self.header = header.clone();
+        Ok(())
+    }
+
+    /// Write the findings line for a read.
+    pub fn write_hits(&mut self, read_id: &str, hits: &[Hit]) -> MtsvResult<()> {
+        self.write_hits_with_overflow(read_id, hits, 0)
+    }
+
+    /// Write the findings line for a read which already had `overflow` taxids left off, e.g. when
+    /// its line was capped in an earlier results file. The line gets an overflow marker for
+    /// those and any the cap leaves off now.
+    pub fn write_hits_with_overflow(&mut self,
+                                    read_id: &str,
+                                    hits: &[Hit],
+                                    mut overflow: usize)
+                                    -> MtsvResult<()> {
+        if hits.is_empty() {
+            return Ok(());
+        }
+        let raw_ids = self.header.version == FindingsVersion::Legacy ||
+                      self.header.version == FindingsVersion::Binary;
+        if raw_ids && overflow > 0 {
+            return Err(MtsvError::InvalidFindings(format!("legacy and binary findings can't \
+                                                           record that read {} had {} taxids \
+                                                           left off",
+                                                          read_id,
+                                                          overflow)));
+        }
+        if self.header.version == FindingsVersion::Legacy {
+            let tax_ids = hits.iter().map(|h| h.tax_id).collect::<BTreeSet<_>>();
+            return write_single_line(read_id, &tax_ids, &mut self.writer);
+        }
+
+        let mut best: BTreeMap<TaxId, Hit> = BTreeMap::new();
+        for hit in hits {
+            let entry = best.entry(hit.tax_id).or_insert(*hit);
+            if hit.is_better_than(entry) {
+                *entry = *hit;
+            }
+        }
+
+        if self.header.version == FindingsVersion::Binary {
+            return self.write_binary_hits(read_id, &best);
+        }
+
+        match self.max_taxids {
+            Some(max) if best.len() > max => {
+                let mut ranked = best.values().cloned().collect::<Vec<_>>();
+                ranked.sort_by(|a, b| if a.is_better_than(b) {
+                    Ordering::Less
+                } else if b.is_better_than(a) {
+                    Ordering::Greater
+                } else {
+                    Ordering::Equal
+                });
+                overflow += ranked.len() - max;
+                best = ranked.into_iter().take(max).map(|h| (h.tax_id, h)).collect();
+            },
+            _ => (),
+        }
+
+        let read_id = if self.header.escapes_ids() {
+            if read_id.is_empty() {
+                return Err(MtsvError::InvalidReadId(read_id.to_string()));
+            }
+            escape_read_id(read_id)
+        } else {
+            check_read_id(read_id)?;
+            Cow::Borrowed(read_id)
+        };
+
+        let mut line = mem::take(&mut self.line);
+        line.clear();
+        line.push_str(&read_id);
+        line.push(':');
+        for (i, hit) in best.values().enumerate() {
+            if i > 0 {
+                line.push(',');
+            }
+            match self.header.version {
+                FindingsVersion::V1 | FindingsVersion::Legacy => {
+                    line.push_str(&format!("{}={}", hit.tax_id.0, hit.edit))
+                },
+                FindingsVersion::V2 | FindingsVersion::Binary => {
+                    for (j, field) in self.header.fields.iter().enumerate() {
+                        if j > 0 {
+                            line.push('=');
+                        }
+                        match *field {
+                            FindingsField::Accession => {
+                                let name = self.names.as_ref().and_then(|n| n.name(hit.gi));
+                                line.push_str(name.unwrap_or(""));
+                            },
+                            // straight into the line, without a string for each value
+                            _ => {
+                                let _ = FmtWrite::write_fmt(&mut line,
+                                                            format_args!("{}",
+                                                                         field.value(hit)
+                                                                             .unwrap_or(0)));
+                            },
+                        }
+                    }
+                },
+            }
+        }
+        if overflow > 0 {
+            line.push_str(&format!(",{}{}", OVERFLOW_MARKER, overflow));
+        }
+        line.push('\n');
 
-    let f = File::create(Path::new(p))?;
-    let mut writer = BufWriter::new(f);
-    Ok(serialize_into(&mut writer, t)?)
-}
+        let written = self.writer.write_all(line.as_bytes());
+        self.line = line;
+        Ok(written?)
+    }
 
-/// Parse a FASTA database into a single map of all taxonomy IDs.
-pub fn parse_fasta_db<R>(records: R) -> MtsvResult<Database>
-    where R: Iterator<Item = io::Result<fasta::Record>>
-{
-    let mut taxon_map = BTreeMap::new();
+    /// Write a read's record to a binary file, with its best hit for each taxid.
+    fn write_binary_hits(&mut self, read_id: &str, best: &BTreeMap<TaxId, Hit>) -> MtsvResult<()> {
+        // read IDs are written as they are, so anything but an empty one can be read back
+        if read_id.is_empty() {
+            return Err(MtsvError::InvalidReadId(read_id.to_string()));
+        }
 
-    debug!("Parsing FASTA database file...");
-    for record in records {
-        let record = (record)?;
+        let mut record = Vec::with_capacity(read_id.len() + 8 * best.len());
+        write_binary_string(&mut record, read_id);
+        write_varint(&mut record, best.len() as u64);
+        let mut last_tax_id = 0;
+        for hit in best.values() {
+            for field in &self.header.fields {
+                let value = match *field {
+                    FindingsField::TaxId => hit.tax_id.0 - last_tax_id,
+                    _ => field.value(hit).unwrap_or(0),
+                };
+                write_varint(&mut record, value);
+            }
+            last_tax_id = hit.tax_id.0;
+        }
 
-        let (gi, tax_id) = parse_read_header(record.id())?;
+        self.writer.write_all(&record)?;
+        Ok(())
+    }
 
-        let sequences = taxon_map.entry(tax_id).or_insert_with(|| vec![]);
-        sequences.push((gi, record.seq().to_vec()));
+    /// The underlying writer.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
     }
 
-    Ok(taxon_map)
+    /// Return the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
 }
 
 /// Return a lazy iterator which parses the findings of a mtsv-binner run.
@@ -64,8 +2370,8 @@ pub fn parse_findings<'a, R: BufRead + 'a>
     // TODO: replace with -> impl Trait when stabilized
 
     // the BufRead::lines function handles lazily splitting on lines for us
-    Box::new(s.lines().map(|l| {
-        l.map_err(|e| MtsvError::from(e)).and_then(|l| {
+    Box::new(findings_lines(s).map(|l| {
+        l.map_err(MtsvError::from).and_then(|(l, escaped)| {
             let l = l.trim();
             // split from the right in case someone put colons in the read ID
             let mut halves = l.rsplitn(2, ':');
@@ -89,7 +2395,7 @@ pub fn parse_findings<'a, R: BufRead + 'a>
             let read_id = match halves.next() {
                 Some(r) => {
                     if r.len() > 0 {
-                        r.to_string()
+                        if escaped { unescape_read_id(r)? } else { r.to_string() }
                     } else {
                         return Err(MtsvError::InvalidHeader(l.to_string()));
                     }
@@ -102,85 +2408,60 @@ pub fn parse_findings<'a, R: BufRead + 'a>
     }))
 }
 
-/// Return a lazy iterator which parses the findings of a mtsv-binner run.
-///
-/// The Option return type could indicate a few problems:
-///
-/// * There are an incorrect number of tokens after splitting on the colon separator
-/// * One of the tax IDs isn't a valid unsigned integer
-///
+/// Return a lazy iterator which parses the findings of a mtsv-binner run, in either format (see
+/// `FindingsReader`).
 pub fn parse_edit_distance_findings<'a, R: BufRead + 'a>
     (s: R)
      -> Box<dyn Iterator<Item = MtsvResult<(String, Vec::<Hit>)>> + 'a> {
-    // TODO: replace with -> impl Trait when stabilized
-
-    // the BufRead::lines function handles lazily splitting on lines for us
-    Box::new(s.lines().map(|l| {
-        l.map_err(|e| MtsvError::from(e)).and_then(|l| {
-            let l = l.trim();
-            // split from the right in case someone put colons in the read ID
-            let mut halves = l.rsplitn(2, ':');
+    Box::new(FindingsReader::new(s))
+}
 
-    
-            // the first split iteration will always return something, even if it's empty
-            let taxids = halves.next().unwrap().split(',');
+/// Collect the IDs of all reads with findings in one or more mtsv-binner results files.
+///
+/// Results files may be gzipped, and header lines are skipped.
+pub fn read_ids_from_findings<P: AsRef<Path>>(paths: &[P]) -> MtsvResult<HashSet<String>> {
+    read_ids_from_findings_with_counts(paths).map(|(ids, _)| ids)
+}
 
-            // create vec of hits 
-            let mut hits = Vec::<Hit>::new();
+/// Like `read_ids_from_findings`, but also return the number of findings lines in each file, in
+/// the same order as `paths`. A read which appears in several files is counted in each of them.
+pub fn read_ids_from_findings_with_counts<P: AsRef<Path>>
+    (paths: &[P])
+     -> MtsvResult<(HashSet<String>, Vec<usize>)> {
+    let mut ids = HashSet::new();
+    let mut counts = Vec::with_capacity(paths.len());
+
+    for path in paths {
+        let mut count = 0;
+        for finding in open_findings(path)? {
+            ids.insert(finding?.read_id);
+            count += 1;
+        }
 
-            // parse each taxid (comma separated), returning None if it fails
-            for taxid_raw in taxids {
-                let mut res = taxid_raw.split('=');
-                let tax = match res.next().unwrap().parse::<TaxId>(){
-                        Ok(id) => id,
-                        Err(_) => return Err(MtsvError::InvalidInteger("".to_string())),
-                    };
-
-                let edit = match res.next().unwrap().parse::<u32>(){
-                    Ok(ed) => ed,
-                    Err(_) => return Err(MtsvError::InvalidInteger("".to_string())),
-                    };
-
-
-                // append this hit
-                let hit = Hit {
-                        tax_id: tax,
-                        edit: edit
-                    };
-                hits.push(hit);
-            }
-    
-            // since we're parsing from the right of each line, the read ID is the second token
-            let read_id = match halves.next() {
-                Some(r) => {
-                    if r.len() > 0 {
-                        r.to_string()
-                    } else {
-                        return Err(MtsvError::InvalidHeader(l.to_string()));
-                    }
-                },
-                None => return Err(MtsvError::InvalidHeader(l.to_string())),
-            };
+        debug!("Found {} reads in {}", count, path.as_ref().display());
+        counts.push(count);
+    }
 
-            Ok((read_id, hits))
-        })
-    }))
+    Ok((ids, counts))
 }
 
-
 #[cfg(test)]
 mod test {
 
-    use ::binner::write_single_line;
+    use ::binner::{write_edit_distances, write_single_line};
     use ::index::TaxId;
 
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
     use mktemp::Temp;
 
     use rand::{Rng, XorShiftRng};
     use std::collections::{BTreeMap, BTreeSet};
-    use std::io::{BufReader, Cursor};
+    use std::fs;
+    use std::io::{BufReader, Cursor, Write};
     use std::iter::FromIterator;
     use super::*;
+    use zstd;
 
     fn roundtrip(findings: Vec<(String, BTreeSet<TaxId>)>) {
 
@@ -304,6 +2585,870 @@ asldkfj:3,4,5,6")
         }
     }
 
+    fn write_list(contents: &[u8], gzip: bool) -> Temp {
+        let path = Temp::new_file().unwrap();
+        let file = File::create(path.as_ref()).unwrap();
+        if gzip {
+            let mut encoder = GzEncoder::new(file, Compression::Default);
+            encoder.write_all(contents).unwrap();
+            encoder.finish().unwrap();
+        } else {
+            BufWriter::new(file).write_all(contents).unwrap();
+        }
+        path
+    }
+
+    #[test]
+    fn id_list_comments_and_blanks() {
+        let list = write_list(b"# candidate taxa\n\n  562\n\t9606 \n#1234\n\n", false);
+
+        let ids = load_id_list::<TaxId, _>(list.as_ref()).unwrap();
+
+        assert_eq!(ids, BTreeSet::from_iter(vec![TaxId(562), TaxId(9606)]));
+    }
+
+    #[test]
+    fn id_list_duplicates() {
+        let list = write_list(b"562\n9606\n562\n", false);
+
+        let ids = load_id_list::<TaxId, _>(list.as_ref()).unwrap();
+
+        assert_eq!(ids, BTreeSet::from_iter(vec![TaxId(562), TaxId(9606)]));
+    }
+
+    #[test]
+    fn id_list_crlf() {
+        let list = write_list(b"read_1\r\nread_2\r\n# skip\r\n", false);
+
+        let ids = load_id_list::<String, _>(list.as_ref()).unwrap();
+
+        assert_eq!(ids,
+                   BTreeSet::from_iter(vec![String::from("read_1"), String::from("read_2")]));
+    }
+
+    #[test]
+    fn id_list_gzip() {
+        let list = write_list(b"# gzipped\n562\n9606\n", true);
+
+        let ids = load_id_list::<TaxId, _>(list.as_ref()).unwrap();
+
+        assert_eq!(ids, BTreeSet::from_iter(vec![TaxId(562), TaxId(9606)]));
+    }
+
+    #[test]
+    fn id_list_bad_entry_line_number() {
+        let list = write_list(b"# header\n562\n\nE. coli\n", false);
+
+        match load_id_list::<TaxId, _>(list.as_ref()) {
+            Err(MtsvError::InvalidListEntry(_, line, entry)) => {
+                assert_eq!(line, 4);
+                assert_eq!(entry, "E. coli");
+            },
+            other => panic!("expected an invalid entry error, found {:?}", other),
+        }
+    }
+
+    fn hit(tax_id: u64, edit: u32) -> Hit {
+        Hit {
+            tax_id: TaxId(tax_id),
+            edit,
+            seeds: 0,
+            seeds_searched: 0,
+            clipped_start: 0,
+            clipped_end: 0,
+            gi: Gi(0),
+        }
+    }
+
+    fn bin(gi: u32, tax_id: u64) -> BinInfo {
+        BinInfo { gi: Gi(gi), tax_id: TaxId(tax_id), start: 0, end: 0 }
+    }
+
+    #[test]
+    fn read_ids_from_several_files() {
+        let first = write_list(b"#sample=s1\nr1:5=0,7=2\r\nr2:5=1\r\n", true);
+        let second = write_list(b"r2:7=0\nr:3:9=4\n", false);
+
+        let (ids, counts) = read_ids_from_findings_with_counts(&[&first, &second]).unwrap();
+
+        let expected = ["r1", "r2", "r:3"].iter().map(|r| r.to_string()).collect::<HashSet<_>>();
+        assert_eq!(ids, expected);
+        assert_eq!(counts, vec![2, 2]);
+        assert_eq!(read_ids_from_findings(&[&first, &second]).unwrap(), expected);
+        assert!(read_ids_from_findings::<&Path>(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn read_ids_from_bad_file() {
+        let list = write_list(b"r1:5=0\n:7=2\n", false);
+        assert!(read_ids_from_findings(&[&list]).is_err());
+    }
+
+    #[test]
+    fn taxid_map_file() {
+        let list = write_list(b"# surrogate\tncbi\n1\t562\n2 9606\n\n1\t562\n", true);
+
+        let map = TaxidMap::from_file(list.as_ref()).unwrap();
+
+        let expected = TaxidMap::from_pairs(vec![(TaxId(1), TaxId(562)), (TaxId(2), TaxId(9606))]);
+        assert_eq!(map, expected);
+    }
+
+    #[test]
+    fn accession_map_file() {
+        let list = write_list(b"# accession\ttaxid\nNZ_CP012345.1\t562\nNC_000913.3 511145\n\n",
+                              true);
+
+        let map = AccessionMap::from_file(list.as_ref()).unwrap();
+
+        let expected = AccessionMap::from_pairs(vec![(String::from("NZ_CP012345.1"), TaxId(562)),
+                                                     (String::from("NC_000913.3"), TaxId(511145))]);
+        assert_eq!(map, expected);
+        assert_eq!(map.get("NC_000913"), None);
+
+        for contents in [&b"NZ_CP012345.1\t562\nNZ_CP012345.1\t563\n"[..], b"NZ_CP012345.1\n"] {
+            let list = write_list(contents, false);
+            assert!(AccessionMap::from_file(list.as_ref()).is_err());
+        }
+    }
+
+    #[test]
+    fn fasta_manifest() {
+        let dir = Temp::new_dir().unwrap();
+        let dir = dir.to_path_buf();
+        fs::write(dir.join("a.fasta"), b">x\nACGT\n>y\n\n>z\nGGCC\n").unwrap();
+        fs::write(dir.join("b.fasta"), b">w\nTTAA\n").unwrap();
+        let manifest_path = dir.join("manifest.tsv");
+        fs::write(&manifest_path, b"a.fasta\t10\n\n# more\nb.fasta\t20\na.fasta\t30\t500\n")
+            .unwrap();
+
+        let manifest = FastaManifest::from_file(&manifest_path).unwrap();
+        assert_eq!(manifest.entries().iter().map(|e| e.line).collect::<Vec<_>>(), vec![1, 4, 5]);
+        assert_eq!(manifest.entries()[2].path, dir.join("a.fasta"));
+
+        // the empty reference is skipped, but still takes a GI
+        let database = parse_manifest_db(&manifest).unwrap();
+        let expected = BTreeMap::from_iter(vec![(TaxId(10),
+                                                 vec![(Gi(1), b"ACGT".to_vec()),
+                                                      (Gi(3), b"GGCC".to_vec())]),
+                                                (TaxId(20), vec![(Gi(4), b"TTAA".to_vec())]),
+                                                (TaxId(30),
+                                                 vec![(Gi(500), b"ACGT".to_vec()),
+                                                      (Gi(502), b"GGCC".to_vec())])]);
+        assert_eq!(database, expected);
+
+        for contents in [&b"a.fasta\n"[..], b"a.fasta\tten\n", b"a.fasta\t10\t1\t2\n"] {
+            fs::write(&manifest_path, contents).unwrap();
+            assert!(FastaManifest::from_file(&manifest_path).is_err());
+        }
+    }
+
+    #[test]
+    fn accession2taxid_file() {
+        let list = write_list(b"accession\taccession.version\ttaxid\tgi\n\
+                                NZ_CP012345\tNZ_CP012345.1\t562\t1234\n\
+                                NC_000913\tNC_000913.3\t511145\t5678\n\
+                                NC_000001\tNC_000001.1\t9606\t42\n",
+                              true);
+        let wanted = ["NZ_CP012345.1", "NC_000913", "NC_999999.1"]
+            .iter()
+            .map(|a| a.to_string())
+            .collect::<HashSet<_>>();
+
+        // either column matches, and only wanted accessions are kept
+        let map = AccessionMap::from_accession2taxid(list.as_ref(), &wanted).unwrap();
+        let expected = AccessionMap::from_pairs(vec![(String::from("NZ_CP012345.1"), TaxId(562)),
+                                                     (String::from("NC_000913"), TaxId(511145))]);
+        assert_eq!(map, expected);
+
+        for contents in [&b"NC_000913\tNC_000913.3\t562\t1\nNC_000913\tNC_000913.3\t563\t1\n"[..],
+                         b"NZ_CP012345.1\t562\n"] {
+            let list = write_list(contents, false);
+            assert!(AccessionMap::from_accession2taxid(list.as_ref(), &wanted).is_err());
+        }
+    }
+
+    #[test]
+    fn empty_references_skipped() {
+        let fasta = b">1-10\nACGT\n>2-20\n>3-20\n  \n>4-30\nGGCC\n";
+        let records = || fasta::Reader::new(&fasta[..]).records();
+
+        let database = parse_fasta_db(records()).unwrap();
+        let expected = BTreeMap::from_iter(vec![(TaxId(10), vec![(Gi(1), b"ACGT".to_vec())]),
+                                                (TaxId(30), vec![(Gi(4), b"GGCC".to_vec())])]);
+        assert_eq!(database, expected);
+
+        // taxid headers still number the rest as if the empty ones were there
+        let taxid_fasta = b">10\nACGT\n>20\n>30\nGGCC\n";
+        let database = parse_fasta_db_with_format(fasta::Reader::new(&taxid_fasta[..]).records(),
+                                                  HeaderFormat::TaxId)
+            .unwrap();
+        assert_eq!(database[&TaxId(30)][0].0, Gi(3));
+
+        let mut added = 0;
+        let count = |_, _, _: Option<&str>, _: Option<&str>, _: &[u8]| {
+            added += 1;
+            Ok(())
+        };
+        let strict = parse_fasta_references(records(), HeaderFormat::GiTaxId, None, true, count);
+        match strict {
+            Err(MtsvError::EmptyReference(header)) => assert_eq!(header, "2-20"),
+            other => panic!("expected an empty reference error, found {:?}", other),
+        }
+        assert_eq!(added, 1);
+    }
+
+    #[test]
+    fn malformed_references_rejected() {
+        let malformed = [(&b">1-10\nACGT\n>2-20\nAC$GT\n"[..], "2-20", b'$', 3),
+                         (&b">1-10\nAC\xC3\xA9GT\n"[..], "1-10", 0xC3, 3)];
+        for &(fasta, header, byte, base) in &malformed {
+            match parse_fasta_db(fasta::Reader::new(fasta).records()) {
+                Err(MtsvError::InvalidReferenceByte(h, b, i)) => {
+                    assert_eq!((h.as_str(), b, i), (header, byte, base))
+                },
+                other => panic!("expected an invalid reference byte error, found {:?}", other),
+            }
+        }
+        let e = MtsvError::InvalidReferenceByte(String::from("2-20"), b'$', 3);
+        assert_eq!(e.to_string(),
+                   "Reference 2-20 has '$' at base 3, which can't be in a reference sequence");
+        let e = MtsvError::InvalidReferenceByte(String::from("1-10"), 0xC3, 3);
+        assert!(e.to_string().contains("has byte 0xC3 at base 3"));
+
+        // other bytes are left for the index to make wildcards
+        let database = parse_fasta_db(fasta::Reader::new(&b">1-10\nAC#GT\n"[..]).records());
+        assert_eq!(database.unwrap()[&TaxId(10)][0].1, b"AC#GT".to_vec());
+    }
+
+    #[test]
+    fn surveyed_references() {
+        let fasta = b">1-10\nACGT\n>2-10\nACGTAC\n>bad\nACGT\n>3-20\n>4-20\nAC$GT\n>5-30\nGG\n";
+        let records = || fasta::Reader::new(&fasta[..]).records();
+        let survey = survey_fasta_references(records(), HeaderFormat::GiTaxId, None, false, |_, _| {
+                Ok(())
+            })
+            .unwrap();
+
+        let counts = |references, bases| TaxonCounts { references, bases };
+        assert_eq!(survey.taxa,
+                   BTreeMap::from_iter(vec![(TaxId(10), counts(2, 10)),
+                                            (TaxId(30), counts(1, 2))]));
+        assert_eq!((survey.references(), survey.bases()), (3, 12));
+        assert_eq!(survey.skipped, ["3-20"]);
+        let problems = survey.problems
+            .iter()
+            .map(|problem| (problem.record, problem.id.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(problems, [(3, "bad"), (5, "4-20")]);
+        assert_eq!(survey.problems[0].why,
+                   MtsvError::InvalidInteger(String::from("bad")).to_string());
+
+        // strict surveys fail empty references, and the check can fail any which get that far
+        let strict = survey_fasta_references(records(), HeaderFormat::GiTaxId, None, true, |gi, _| {
+                if gi == Gi(5) {
+                    Err(MtsvError::InvalidParameter(String::from("no")))
+                } else {
+                    Ok(())
+                }
+            })
+            .unwrap();
+        assert!(strict.skipped.is_empty());
+        assert_eq!(strict.problems.iter().map(|problem| problem.record).collect::<Vec<_>>(),
+                   [3, 4, 5, 6]);
+        assert_eq!(strict.taxa.keys().collect::<Vec<_>>(), [&TaxId(10)]);
+
+        // accessions missing from the map are problems, but a missing map is an error
+        let accessions = b">NC_1.1\nACGT\n>NC_2.1\nACGT\n";
+        let map = AccessionMap::from_pairs(vec![(String::from("NC_1.1"), TaxId(562))]);
+        let survey = survey_fasta_references(fasta::Reader::new(&accessions[..]).records(),
+                                             HeaderFormat::Accession,
+                                             Some(&map),
+                                             false,
+                                             |_, _| Ok(()))
+            .unwrap();
+        assert_eq!(survey.taxa[&TaxId(562)], counts(1, 4));
+        assert_eq!(survey.problems[0].why,
+                   MtsvError::UnmappedAccession(String::from("NC_2.1")).to_string());
+        assert!(survey_fasta_references(fasta::Reader::new(&accessions[..]).records(),
+                                        HeaderFormat::Accession,
+                                        None,
+                                        false,
+                                        |_, _| Ok(()))
+            .is_err());
+    }
+
+    #[test]
+    fn taxid_map_conflict() {
+        let list = write_list(b"1\t562\n1\t563\n", false);
+
+        match TaxidMap::from_file(list.as_ref()) {
+            Err(MtsvError::InvalidListEntry(_, line, entry)) => {
+                assert_eq!(line, 2);
+                assert_eq!(entry, "1\t563");
+            },
+            other => panic!("expected an invalid entry error, found {:?}", other),
+        }
+
+        let list = write_list(b"1\t562\t9606\n", false);
+        assert!(TaxidMap::from_file(list.as_ref()).is_err());
+    }
+
+    #[test]
+    fn taxid_map_merge_collision() {
+        let map = TaxidMap::from_pairs(vec![(TaxId(1), TaxId(100)),
+                                            (TaxId(2), TaxId(100)),
+                                            (TaxId(3), TaxId(300))]);
+
+        // 1 and 2 both become 100, keeping the smaller edit distance
+        let mut hits = vec![hit(1, 3), hit(7, 2), hit(2, 1), hit(3, 0)];
+        let unmapped = map.remap_hits(&mut hits);
+
+        assert_eq!(unmapped, 1);
+        assert_eq!(hits, vec![hit(100, 1), hit(7, 2), hit(300, 0)]);
+    }
+
+    #[test]
+    fn results_header_roundtrip() {
+        let mut buf = Vec::new();
+        let header = ResultsHeader {
+            sample: Some(String::from("gut1")),
+            escaped_ids: true,
+            ..ResultsHeader::default()
+        };
+        header.write(&mut buf).unwrap();
+        buf.extend_from_slice(b"#future=ignored\nr1:5=0,7=2\n");
+
+        let mut reader = Cursor::new(buf);
+        assert_eq!(ResultsHeader::read(&mut reader).unwrap(), header);
+
+        let hits = parse_edit_distance_findings(reader).map(|r| r.unwrap()).collect::<Vec<_>>();
+        assert_eq!(hits,
+                   vec![(String::from("r1"),
+                         vec![hit(5, 0), hit(7, 2)])]);
+    }
+
+    #[test]
+    fn parsers_skip_header() {
+        let results = "#sample=gut1\nr1:5,7\n";
+
+        let found = parse_findings(results.as_bytes()).map(|r| r.unwrap()).collect::<Vec<_>>();
+
+        assert_eq!(found,
+                   vec![(String::from("r1"), BTreeSet::from_iter(vec![TaxId(5), TaxId(7)]))]);
+    }
+
+    /// Write a read's hits with an escaped or unescaped read ID, and parse the line back.
+    fn roundtrip_id(id: &str, tax_ids: &[u64], escape: bool) -> MtsvResult<(String, Vec<Hit>)> {
+        let hits = tax_ids.iter().map(|&t| hit(t, (t % 7) as u32)).collect::<Vec<_>>();
+        let mut buf = Vec::new();
+        if escape {
+            ResultsHeader { escaped_ids: true, ..ResultsHeader::default() }.write(&mut buf)?;
+            write_edit_distances(&escape_read_id(id), &hits, &mut buf)?;
+        } else {
+            write_edit_distances(id, &hits, &mut buf)?;
+        }
+        parse_edit_distance_findings(Cursor::new(buf)).next().unwrap()
+    }
+
+    #[test]
+    fn adversarial_read_ids() {
+        // the parsers split at the last colon, so none of these are ambiguous
+        let fine = ["A00123:45:HXXX:1:1101:1000:2000", "r:123,456", "r:7=0", "trailing:", "::",
+                    "12,34", "r=5", "50%", "r#1", "tab\tinside", "trailing space "];
+        // but these would be skipped as headers, trimmed or split across lines
+        let unwritable = ["#hash", " leading", "line\nbreak", "carriage\rreturn", ""];
+
+        for id in fine.iter() {
+            assert_eq!(roundtrip_id(id, &[1, 20], false).unwrap().0, *id);
+            assert_eq!(roundtrip_id(id, &[1, 20], true).unwrap().0, *id);
+        }
+        for id in unwritable.iter() {
+            match roundtrip_id(id, &[1], false) {
+                Err(MtsvError::InvalidReadId(ref found)) => assert_eq!(found, id),
+                other => panic!("{:?} was written: {:?}", id, other),
+            }
+            if !id.is_empty() {
+                assert_eq!(roundtrip_id(id, &[1], true).unwrap().0, *id);
+            }
+        }
+
+        let mut buf = Vec::new();
+        assert!(write_single_line("#hash", &BTreeSet::from_iter(vec![TaxId(1)]), &mut buf)
+            .is_err());
+        assert!(buf.is_empty());
+
+        assert_eq!(escape_read_id("A:1,2=3 #%"), "A%3A1%2C2%3D3%20%23%25");
+        assert_eq!(escape_read_id("plain"), Cow::Borrowed("plain"));
+        assert!(unescape_read_id("bad%2").is_err());
+        assert!(unescape_read_id("bad%zz").is_err());
+        assert!(unescape_read_id("bad%ff").is_err());
+    }
+
+    #[test]
+    fn sanitized_read_ids() {
+        let glued = "read1 sample=gut\nACGT\r";
+        let sanitized = sanitize_read_id(glued, ControlCharPolicy::Replace).unwrap();
+        assert_eq!(sanitized, "read1 sample=gut_ACGT_");
+        assert_eq!(roundtrip_id(&sanitized, &[1, 2], false).unwrap().0, sanitized);
+        assert_eq!(sanitize_read_id("a\tb\x7f", ControlCharPolicy::Replace).unwrap(), "a_b_");
+        assert_eq!(sanitize_read_id("plain:1", ControlCharPolicy::Reject).unwrap(),
+                   Cow::Borrowed("plain:1"));
+        match sanitize_read_id(glued, ControlCharPolicy::Reject) {
+            Err(MtsvError::InvalidReadId(ref found)) => assert_eq!(found, glued),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        assert_eq!("reject".parse::<ControlCharPolicy>().unwrap(), ControlCharPolicy::Reject);
+        assert_eq!(ControlCharPolicy::default(), ControlCharPolicy::Replace);
+        assert!("strip".parse::<ControlCharPolicy>().is_err());
+    }
+
+    quickcheck! {
+        fn escaped_read_ids_roundtrip(id: String, tax_ids: Vec<u64>) -> bool {
+            if id.is_empty() || tax_ids.is_empty() {
+                return true;
+            }
+
+            let escaped = roundtrip_id(&id, &tax_ids, true).unwrap();
+            // only IDs which pass the check can be written unescaped, and they round trip too
+            let unescaped = match check_read_id(&id) {
+                Ok(()) => roundtrip_id(&id, &tax_ids, false).unwrap().0 == id,
+                Err(_) => roundtrip_id(&id, &tax_ids, false).is_err(),
+            };
+
+            escaped.0 == id && escaped.1.len() == tax_ids.iter().collect::<BTreeSet<_>>().len() &&
+            unescaped
+        }
+    }
+
+    #[test]
+    fn v2_schema() {
+        // fields in any order, with a field from a newer writer
+        let results = "#mtsv-findings v2 fields=edit,taxid,future\n#sample=gut1\n\
+                       r%3A1:3=5=x,0=9=y\n";
+        let mut reader = FindingsReader::new(results.as_bytes());
+        assert_eq!(reader.read_header().unwrap().fields,
+                   vec![FindingsField::Edit,
+                        FindingsField::TaxId,
+                        FindingsField::Other(String::from("future"))]);
+        assert_eq!(reader.header().sample, Some(String::from("gut1")));
+        assert_eq!(reader.map(|r| r.unwrap()).collect::<Vec<_>>(),
+                   vec![(String::from("r:1"), vec![hit(5, 3), hit(9, 0)])]);
+
+        let parse = |results: &str| {
+            parse_edit_distance_findings(results.as_bytes()).collect::<MtsvResult<Vec<_>>>()
+        };
+        assert!(parse("#mtsv-findings v3 fields=taxid\nr1:1\n").is_err());
+        assert!(parse("#mtsv-findings v2 fields=edit\nr1:1\n").is_err());
+        assert!(parse("#mtsv-findings v2 fields=taxid,edit\nr1:1\n").is_err());
+        assert!(parse("#mtsv-findings v2 fields=taxid,edit\nr1:1=0=0\n").is_err());
+        assert!(parse("r1:1\n").is_err());
+
+        let mut writer = FindingsWriter::new(Vec::new());
+        assert!(writer.write_header(&ResultsHeader::v2(&[FindingsField::TaxId,
+                                                      FindingsField::from_name("future")]))
+            .is_err());
+        assert!(writer.write_header(&ResultsHeader::v2(&[FindingsField::Edit])).is_err());
+    }
+
+    #[test]
+    fn accession_field() {
+        let fields = [FindingsField::TaxId, FindingsField::Edit, FindingsField::Accession];
+        let accessions = vec![(Gi(1), String::from("NZ_CP012345.1")),
+                              (Gi(2), String::from("NC_000913.3"))]
+            .into_iter()
+            .collect::<BTreeMap<_, _>>();
+        let names = Arc::new(NameResolver::new(vec![bin(1, 9606), bin(2, 562), bin(3, 561)],
+                                               &accessions));
+        let hits = vec![Hit { gi: Gi(2), ..hit(562, 1) },
+                        Hit { gi: Gi(1), ..hit(9606, 0) },
+                        Hit { gi: Gi(3), ..hit(561, 2) }];
+
+        let mut writer = FindingsWriter::new(Vec::new()).with_names(names.clone());
+        writer.write_header(&ResultsHeader::v2(&fields)).unwrap();
+        writer.write_hits("r1", &hits).unwrap();
+        // a reference without an accession is named by its GI and taxid
+        writer.write_hits("r2", &[Hit { gi: Gi(3), ..hit(561, 2) }]).unwrap();
+        let results = String::from_utf8(writer.into_inner()).unwrap();
+        assert_eq!(results,
+                   "#mtsv-findings v2 fields=taxid,edit,accession\n\
+                    r1:561=2=3-561,562=1=NC_000913.3,9606=0=NZ_CP012345.1\n\
+                    r2:561=2=3-561\n");
+
+        // accessions are skipped when read back
+        let mut reader = FindingsReader::new(results.as_bytes());
+        assert_eq!(reader.read_header().unwrap().fields, fields.to_vec());
+        assert_eq!(reader.next().unwrap().unwrap(),
+                   (String::from("r1"), vec![hit(561, 2), hit(562, 1), hit(9606, 0)]));
+
+        // only v2 files with the index's names can have them
+        let mut writer = FindingsWriter::new(Vec::new());
+        assert!(writer.write_header(&ResultsHeader::v2(&fields)).is_err());
+        let mut writer = FindingsWriter::new(Vec::new()).with_names(names);
+        assert!(writer.write_header(&ResultsHeader::binary(&fields)).is_err());
+    }
+
+    #[test]
+    fn name_resolver() {
+        let accessions = vec![(Gi(2), String::from("NC_000913.3"))]
+            .into_iter()
+            .collect::<BTreeMap<_, _>>();
+
+        // assigned GIs are looked up in a table, and GIs from headers may be too spread out for one
+        for &spread in &[1, 1_000_000] {
+            let bins = (1..6).map(|i| bin(i * spread, 100 + i as u64)).collect::<Vec<_>>();
+            let accessions = accessions.iter()
+                .map(|(gi, accession)| (Gi(gi.0 * spread), accession.clone()))
+                .collect();
+            let names = NameResolver::new(bins, &accessions);
+            assert_eq!(names.table.is_empty(), spread > 1);
+            // stored accessions, and names made up of the GI and taxid for the rest
+            assert_eq!(names.name(Gi(2 * spread)), Some("NC_000913.3"));
+            assert_eq!(names.name(Gi(spread)), Some(&*format!("{}-101", spread)));
+            assert_eq!(names.name(Gi(5 * spread)), Some(&*format!("{}-105", 5 * spread)));
+            // and nothing for GIs which aren't in the index
+            assert_eq!(names.name(Gi(0)), None);
+            assert_eq!(names.name(Gi(6 * spread)), None);
+            assert_eq!(names.name(Gi(u32::max_value())), None);
+        }
+
+        // a GI shared by several taxa, like those of taxon bins, doesn't name a reference
+        let names = NameResolver::new(vec![bin(0, 1), bin(0, 2), bin(7, 3), bin(7, 3)],
+                                      &BTreeMap::new());
+        assert_eq!(names.name(Gi(0)), None);
+        assert_eq!(names.name(Gi(7)), Some("7-3"));
+
+        let empty = NameResolver::new(vec![], &BTreeMap::new());
+        assert_eq!(empty.name(Gi(1)), None);
+    }
+
+    #[test]
+    fn overflow_marker() {
+        let hits = [(5, 2), (1, 3), (9, 0), (7, 2), (3, 1)]
+            .iter()
+            .map(|&(tax_id, edit)| {
+                Hit {
+                    tax_id: TaxId(tax_id),
+                    edit,
+                    seeds: tax_id as u32,
+                    seeds_searched: 10,
+                    clipped_start: 0,
+                    clipped_end: 0,
+                    gi: Gi(0),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let mut writer = FindingsWriter::new(Vec::new()).with_max_taxids(Some(3));
+        writer.write_hits("r1", &hits).unwrap();
+        writer.write_hits("r2", &hits[..3]).unwrap();
+        writer.write_hits_with_overflow("r3", &hits[..1], 4).unwrap();
+        let written = String::from_utf8(writer.into_inner()).unwrap();
+        // ties in edit distance go to the hit with more seeds
+        assert_eq!(written, "r1:3=1,7=2,9=0,...+2\nr2:1=3,5=2,9=0\nr3:5=2,...+4\n");
+
+        let mut reader = FindingsReader::new(written.as_bytes());
+        let mut overflows = Vec::new();
+        while let Some(finding) = reader.next() {
+            let (read_id, hits) = finding.unwrap();
+            overflows.push((read_id, hits.len(), reader.overflow()));
+        }
+        assert_eq!(overflows,
+                   vec![("r1".to_string(), 3, 2), ("r2".to_string(), 3, 0),
+                        ("r3".to_string(), 1, 4)]);
+
+        // v2 lines have the marker too, but legacy and binary lines can't
+        let mut writer = FindingsWriter::new(Vec::new()).with_max_taxids(Some(1));
+        writer.write_header(&ResultsHeader::v2(&[FindingsField::TaxId])).unwrap();
+        writer.write_hits("r1", &hits).unwrap();
+        assert_eq!(writer.into_inner(), b"#mtsv-findings v2 fields=taxid\nr1:9,...+4\n");
+        for header in &[ResultsHeader { version: FindingsVersion::Legacy,
+                                        ..ResultsHeader::default() },
+                        ResultsHeader::binary(&V2_FIELDS)] {
+            let mut writer = FindingsWriter::new(Vec::new()).with_max_taxids(Some(1));
+            assert!(writer.write_header(header).is_err());
+            let mut writer = FindingsWriter::new(Vec::new());
+            writer.write_header(header).unwrap();
+            assert!(writer.write_hits_with_overflow("r1", &hits, 1).is_err());
+        }
+
+        assert!(parse_edit_distance_findings("r1:1=0,...+x\n".as_bytes()).next().unwrap().is_err());
+    }
+
+    #[test]
+    fn binary_findings() {
+        let mut rng = XorShiftRng::new_unseeded();
+        let mut reads = Vec::new();
+        for i in 0..1000 {
+            let mut hits = BTreeMap::new();
+            for _ in 0..rng.gen_range(1, 10) {
+                let tax_id = rng.gen_range(1_000, 2_000_000);
+                hits.insert(tax_id, Hit {
+                    tax_id: TaxId(tax_id),
+                    edit: rng.gen_range(0, 20),
+                    seeds: rng.gen_range(1, 30),
+                    seeds_searched: 30,
+                    clipped_start: 0,
+                    clipped_end: rng.gen_range(0, 3),
+                    gi: Gi(0),
+                });
+            }
+            let id = format!("A00123:45:HXXXXXXXX:1:1101:{}:{}", 1000 + i, 2000 + i * 7);
+            reads.push((id, hits.into_values().collect::<Vec<_>>()));
+        }
+
+        let write = |header: ResultsHeader| {
+            let mut writer = FindingsWriter::new(Vec::new());
+            writer.write_header(&ResultsHeader { sample: Some("gut1".to_string()), ..header })
+                .unwrap();
+            for &(ref id, ref hits) in &reads {
+                writer.write_hits(id, hits).unwrap();
+            }
+            writer.into_inner()
+        };
+        let text = write(ResultsHeader::v2(&V2_FIELDS));
+        let binary = write(ResultsHeader::binary(&V2_FIELDS));
+        assert!(binary.starts_with(BINARY_MAGIC));
+        // read IDs aren't escaped, and hits are varints with taxids as deltas rather than text
+        assert!(binary.len() * 3 < text.len() * 2,
+                "{} binary vs {} text",
+                binary.len(),
+                text.len());
+
+        let mut reader = FindingsReader::new(Cursor::new(&binary));
+        assert_eq!(reader.read_header().unwrap(),
+                   &ResultsHeader { sample: Some("gut1".to_string()),
+                                    ..ResultsHeader::binary(&V2_FIELDS) });
+        assert_eq!(reader.collect::<MtsvResult<Vec<_>>>().unwrap(), reads);
+
+        // a file cut off part way through a read is an error rather than a shorter file
+        let truncated = parse_edit_distance_findings(Cursor::new(&binary[..binary.len() - 3]))
+            .collect::<MtsvResult<Vec<_>>>();
+        assert!(truncated.is_err());
+
+        // fields a reader doesn't know are skipped
+        let mut writer = FindingsWriter::new(Vec::new());
+        writer.write_header(&ResultsHeader::binary(&[FindingsField::TaxId, FindingsField::Edit]))
+            .unwrap();
+        writer.write_hits("r1", &reads[0].1).unwrap();
+        let mut renamed = writer.into_inner();
+        let pos = renamed.windows(4).position(|w| w == b"edit").unwrap();
+        renamed[pos..pos + 4].copy_from_slice(b"next");
+        let parsed = parse_edit_distance_findings(Cursor::new(renamed)).next().unwrap().unwrap();
+        assert_eq!(parsed.1.iter().map(|h| (h.tax_id, h.edit)).collect::<Vec<_>>(),
+                   reads[0].1.iter().map(|h| (h.tax_id, 0)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn open_findings_any_format() {
+        let hits = vec![Hit { seeds: 4, ..hit(562, 3) }, hit(9606, 0)];
+        let write = |header: ResultsHeader, max_taxids: Option<usize>| {
+            let mut writer = FindingsWriter::new(Vec::new()).with_max_taxids(max_taxids);
+            writer.write_header(&ResultsHeader { sample: Some("gut1".to_string()), ..header })
+                .unwrap();
+            writer.write_hits("r:1", &hits[1..]).unwrap();
+            writer.write_hits("r2", &hits).unwrap();
+            writer.into_inner()
+        };
+        let files = vec![(b"r:1:9606\nr2:562,9606\n".to_vec(), FindingsVersion::Legacy),
+                         (write(ResultsHeader::default(), Some(1)), FindingsVersion::V1),
+                         (write(ResultsHeader::v2(&V2_FIELDS), Some(1)), FindingsVersion::V2),
+                         (write(ResultsHeader::binary(&V2_FIELDS), None),
+                          FindingsVersion::Binary)];
+
+        for (contents, version) in files {
+            for &gzip in &[false, true] {
+                let path = write_list(&contents, gzip);
+                let mut findings = open_findings(&path).unwrap();
+                assert_eq!(findings.header().version, version);
+                let reads = findings.by_ref().collect::<MtsvResult<Vec<_>>>().unwrap();
+                assert_eq!(reads.iter().map(|r| r.read_id.as_str()).collect::<Vec<_>>(),
+                           vec!["r:1", "r2"]);
+                assert_eq!(reads[0].hits, vec![hit(9606, 0)]);
+
+                let expected = match version {
+                    FindingsVersion::Legacy => (vec![hit(562, 0), hit(9606, 0)], false),
+                    FindingsVersion::V1 | FindingsVersion::V2 => (vec![hit(9606, 0)], true),
+                    _ => (hits.clone(), false),
+                };
+                assert_eq!((reads[1].hits.clone(), reads[1].truncated), expected);
+                assert!(!reads[0].truncated);
+            }
+        }
+
+        // a v1 file isn't mistaken for a legacy one by a read without hits
+        let findings = FindingsIter::new("r1:\nr2:5=0\n".as_bytes()).unwrap();
+        assert_eq!(findings.header().version, FindingsVersion::V1);
+    }
+
+    /// Fails every write once `left` more bytes have been written, like a disk filling up.
+    struct FailAfter<W: Write> {
+        inner: W,
+        left: usize,
+    }
+
+    impl<W: Write> Write for FailAfter<W> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.left == 0 {
+                return Err(io::Error::new(io::ErrorKind::Other, "no space left"));
+            }
+            let n = self.inner.write(&buf[..buf.len().min(self.left)])?;
+            self.left -= n;
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.inner.flush()
+        }
+    }
+
+    #[test]
+    fn interrupted_writes() {
+        let dir = Temp::new_dir().unwrap();
+        let path = |name: &str| dir.to_path_buf().join(name).to_str().unwrap().to_string();
+        let exists = |name: &str| Path::new(&path(name)).exists();
+        let value = (0..100_000u64).collect::<Vec<_>>();
+        let write_failing = |name: &str, at: usize| {
+            write_enveloped(&value,
+                            &path(name),
+                            CODEC_NONE,
+                            |file| Ok(FailAfter { inner: file, left: at }),
+                            |failing| Ok(failing.inner))
+        };
+
+        // whatever was there before is left as it was
+        write_to_file(&vec![1u64, 2, 3], &path("kept")).unwrap();
+        let before = fs::read(path("kept")).unwrap();
+        for &at in &[0, 100, 400_000] {
+            assert!(write_failing("kept", at).is_err());
+            assert_eq!(fs::read(path("kept")).unwrap(), before);
+            assert!(!exists("kept.partial"));
+        }
+
+        // and nothing is left where there was nothing
+        assert!(write_failing("new", 400_000).is_err());
+        assert!(!exists("new"));
+        assert!(!exists("new.partial"));
+
+        // until a write succeeds
+        write_to_file(&value, &path("kept")).unwrap();
+        assert_eq!(from_file::<Vec<u64>>(&path("kept")).unwrap(), value);
+        assert!(!exists("kept.partial"));
+    }
+
+    #[test]
+    fn envelope_versions() {
+        let dir = Temp::new_dir().unwrap();
+        let path = |name: &str| dir.to_path_buf().join(name).to_str().unwrap().to_string();
+        let value = vec![(1u32, String::from("a")), (2, String::from("b"))];
+        let read = |name: &str| from_file::<Vec<(u32, String)>>(&path(name));
+
+        write_to_file(&value, &path("current")).unwrap();
+        write_to_file_compressed(&value, &path("compressed"), 19).unwrap();
+        // written zstd-compressed by its name alone
+        write_to_file(&value, &path("named.zst")).unwrap();
+        // or compressed whole by the zstd tool, whatever it's named
+        let whole = zstd::stream::encode_all(File::open(path("current")).unwrap(), 3).unwrap();
+        fs::write(path("framed"), whole).unwrap();
+        for &(name, compressed) in &[("current", false),
+                                     ("compressed", true),
+                                     ("named.zst", true),
+                                     ("framed", true)] {
+            let envelope = Envelope::of_file(&path(name)).unwrap();
+            assert_eq!(envelope.version, INDEX_FORMAT_VERSION);
+            assert_eq!(envelope.is_compressed(), compressed);
+            assert!(!envelope.is_headerless());
+            assert_eq!(envelope.built_by.as_ref().unwrap(), env!("CARGO_PKG_VERSION"));
+            assert_eq!(read(name).unwrap(), value);
+        }
+
+        // files from before the version was recorded are version 1
+        serialize_into(File::create(path("plain")).unwrap(), &value).unwrap();
+        let mut old_compressed = File::create(path("old_compressed")).unwrap();
+        old_compressed.write_all(COMPRESSED_MAGIC).unwrap();
+        old_compressed.write_all(&[CODEC_ZSTD]).unwrap();
+        let mut encoder = zstd::Encoder::new(old_compressed, 3).unwrap();
+        serialize_into(&mut encoder, &value).unwrap();
+        encoder.finish().unwrap();
+        for &(name, headerless) in &[("plain", true), ("old_compressed", false)] {
+            let envelope = Envelope::of_file(&path(name)).unwrap();
+            assert_eq!(envelope.version, 1);
+            assert_eq!(envelope.is_headerless(), headerless);
+            assert_eq!(envelope.built_by, None);
+            assert_eq!(read(name).unwrap(), value);
+        }
+
+        // a newer version than this build reads names the release which wrote it
+        let mut newer = File::create(path("newer")).unwrap();
+        let envelope = Envelope {
+            version: INDEX_FORMAT_VERSION + 1,
+            codec: CODEC_NONE,
+            built_by: Some(String::from("9.9.9")),
+            framed: false,
+        };
+        envelope.write(&mut newer).unwrap();
+        serialize_into(&mut newer, &value).unwrap();
+        match read("newer") {
+            Err(e @ MtsvError::IncompatibleIndex(..)) => {
+                let message = e.to_string();
+                assert!(message.contains(&format!("built with format {} by mtsv 9.9.9",
+                                                  INDEX_FORMAT_VERSION + 1)),
+                        "{}",
+                        message);
+                assert!(message.contains("--format-versions"));
+            },
+            other => panic!("newer version wasn't rejected: {:?}", other),
+        }
+
+        // truncated headers and serializations are reported as such
+        let current = fs::read(path("current")).unwrap();
+        for &(len, why) in &[(ENVELOPE_MAGIC.len() + 2, "header is truncated"),
+                             (current.len() - 1, "truncated")] {
+            fs::write(path("truncated"), &current[..len]).unwrap();
+            match read("truncated") {
+                Err(e @ MtsvError::IncompatibleIndex(..)) => {
+                    assert!(e.to_string().contains(why), "{}", e)
+                },
+                other => panic!("truncated file wasn't rejected: {:?}", other),
+            }
+        }
+    }
+
+    quickcheck! {
+        fn findings_roundtrip(id: String, raw_hits: Vec<(u64, u32, u32)>) -> bool {
+            if id.is_empty() || raw_hits.is_empty() {
+                return true;
+            }
+
+            // the writer keeps one hit per taxid, so only generate one
+            let mut hits = BTreeMap::new();
+            for &(tax_id, edit, seeds) in &raw_hits {
+                hits.entry(tax_id).or_insert(Hit {
+                    tax_id: TaxId(tax_id),
+                    edit,
+                    seeds,
+                    seeds_searched: seeds.wrapping_add(edit),
+                    clipped_start: edit % 5,
+                    clipped_end: seeds % 3,
+                    gi: Gi(0),
+                });
+            }
+            let hits = hits.into_iter().map(|(_, h)| h).collect::<Vec<_>>();
+
+            [ResultsHeader::v2(&V2_FIELDS), ResultsHeader::binary(&V2_FIELDS)].iter().all(|h| {
+                let mut writer = FindingsWriter::new(Vec::new());
+                writer.write_header(h).unwrap();
+                writer.write_hits(&id, &hits).unwrap();
+
+                let parsed = parse_edit_distance_findings(Cursor::new(writer.into_inner()))
+                    .collect::<MtsvResult<Vec<_>>>()
+                    .unwrap();
+                parsed == vec![(id.clone(), hits.clone())]
+            })
+        }
+    }
+
     quickcheck! {
         fn io_helpers(map: BTreeMap<String, String>) -> bool {
             let outfile = Temp::new_file().unwrap();
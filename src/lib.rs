@@ -1,20 +1,24 @@
-//! `vedro` is an umbrella project for several metagenomic binning tools.
+//! `mtsv` is an umbrella project for several metagenomic binning tools.
 //!
-//! Currently:
+//! Among them:
 //!
-//! * `vedro-readprep`: a quality-control and deduplication tool which converts multiple FASTQ
+//! * `mtsv-readprep`: a quality-control and deduplication tool which converts multiple FASTQ
 //! files to a single FASTA file
-//! * `vedro-binner`: a metagenomic binning tool that is sensitive to SNPs and other edits
-//! * `vedro-build`: an index-construction tool to provide fast-lookup data structures for the
+//! * `mtsv-binner`: a metagenomic binning tool that is sensitive to SNPs and other edits
+//! * `mtsv-build`: an index-construction tool to provide fast-lookup data structures for the
 //! binner
-//! * `vedro-collapse`: a simple tool to combine the output of several runs of the binner
-//! * `vedro-tree-build`: an index-construction tool for NCBI taxonomic tree dumps -- these are
-//! used in deciding which results are "informative"
-//! * `vedro-inform`: takes findings from the binner/collapser, and decides whether they point to
-//! the presence of a particular species/family/genus based on a user-specified level of precision
+//! * `mtsv-collapse`: a simple tool to combine the output of several runs of the binner
+//! * `mtsv-run`: builds an index, bins reads against it and collapses the results in one go
 //!
-//! All of these CLI tools depend on functionality in `vedro` -- they are defined as CLI-param
+//! The others inspect, convert and summarize indices and results.
+//!
+//! All of these CLI tools depend on functionality in `mtsv` -- they are defined as CLI-param
 //! parsers which then call into library functionality.
+//!
+//! The types most code needs (the index, its IDs and query parameters, hits and errors) are
+//! re-exported here, and code outside this crate should import them from the crate root rather
+//! than from the module that happens to define them. Enums which are expected to grow are
+//! `#[non_exhaustive]`, so matches on them need a wildcard arm.
 
 #![warn(missing_docs)]
 
@@ -28,11 +32,17 @@ extern crate chrono;
 extern crate clap;
 extern crate cue;
 extern crate env_logger;
+extern crate flate2;
 extern crate itertools;
+extern crate memmap2;
+extern crate rand;
 extern crate rustc_serialize;
 extern crate ssw;
 extern crate stopwatch;
 extern crate serde;
+extern crate serde_json;
+extern crate xxhash_rust;
+extern crate zstd;
 
 #[cfg(test)]
 extern crate mktemp;
@@ -41,17 +51,31 @@ extern crate mktemp;
 #[macro_use]
 extern crate quickcheck;
 
-#[cfg(test)]
-extern crate rand;
-
 pub mod align;
 pub mod binner;
 pub mod builder;
 pub mod chunk;
 pub mod collapse;
+pub mod convert;
+pub mod coverage;
 pub mod error;
+pub mod evaluate;
+pub mod fm_build;
+pub mod formats;
 pub mod index;
 pub mod io;
+pub mod mapped;
+pub mod memory;
+pub mod packed;
 pub mod prep;
 pub mod prep_config;
+pub mod prescreen;
+pub mod run;
+pub mod score;
+pub mod suffix_sort;
+pub mod summary;
 pub mod util;
+pub mod workflow;
+
+pub use error::{MtsvError, MtsvResult};
+pub use index::{BinMode, Database, Gi, HeaderMap, Hit, MGIndex, QueryParams, TaxId};
@@ -0,0 +1,384 @@
+//! The memory-mapped index layout (`mtsv-build --mmap-format`).
+//!
+//! A bincode index has to be deserialized into memory before it can be queried, which takes a
+//! long time for large databases and as much memory as the index. The mapped layout instead
+//! stores the text (the concatenated reference sequences) and the FM index's BWT, occurrence
+//! samples and suffix array samples as flat arrays which are read straight from a memory map.
+//! Opening one is quick, only the pages a query touches are read, and processes on the same
+//! machine share the pages the kernel caches.
+//!
+//! A mapped file is laid out as:
+//!
+//! * `MAPPED_MAGIC`, the format version (see `formats::MAPPED_INDEX_FORMAT_VERSION`) as a
+//!   little-endian u32, four bytes of padding, and the offset of the trailer as a little-endian
+//!   u64.
+//! * The sections, each starting at a multiple of eight bytes: the text, the BWT, each symbol's
+//!   occurrence samples and the suffix array samples. Samples are little-endian u64s.
+//! * The trailer, the bincode serialization of the rest of the FM index, which is small, and the
+//!   writer's metadata.
+//!
+//! The sections are copied from a bincode serialization as it's read (see `MappedWriter`), so
+//! converting an index never holds it in memory.
+
+use bincode::{deserialize, deserialize_from, serialize_into};
+use bio::data_structures::fmindex::{BackwardSearchResult, Interval};
+use error::*;
+use formats::MAPPED_INDEX_FORMAT_VERSION;
+use memmap2::Mmap;
+use serde::de::DeserializeOwned;
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Starts a file in the mapped layout. It shares its first bytes with `io::ENVELOPE_MAGIC`, so
+/// tools which only read bincode indices recognize it and can say so.
+pub const MAPPED_MAGIC: &[u8] = b"\x89MTSVM\r\n";
+
+/// Length of the magic, version, padding and trailer offset.
+const PREAMBLE_LEN: u64 = 24;
+
+/// Sections start at multiples of this many bytes.
+const SECTION_ALIGNMENT: u64 = 8;
+
+/// A run of bytes in a mapped file.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+struct Section {
+    offset: u64,
+    len: u64,
+}
+
+impl Section {
+    /// Number of u64s in the section.
+    fn words(&self) -> u64 {
+        self.len / 8
+    }
+}
+
+/// The parts of rust-bio's `SampledSuffixArray` and its `Occ`, with the big ones as sections.
+#[derive(Serialize, Deserialize)]
+struct SuffixArraySections {
+    bwt: Section,
+    less: Vec<usize>,
+    /// Occurrence samples by symbol, empty for symbols which aren't in the alphabet.
+    occ: Vec<Section>,
+    /// Occurrence sampling interval.
+    k: u32,
+    sample: Section,
+    /// Suffix array sampling rate.
+    s: usize,
+    /// Suffix array entries of rows whose BWT symbol is the sentinel.
+    extra_rows: Vec<(usize, usize)>,
+    sentinel: u8,
+}
+
+/// The trailer of a mapped file.
+#[derive(Serialize, Deserialize)]
+struct Layout<M> {
+    text: Section,
+    suffix_array: SuffixArraySections,
+    metadata: M,
+}
+
+/// Whether the file at `p` is in the mapped layout.
+pub fn is_mapped_file(p: &str) -> MtsvResult<bool> {
+    let mut start = Vec::with_capacity(MAPPED_MAGIC.len());
+    File::open(Path::new(p))?.take(MAPPED_MAGIC.len() as u64).read_to_end(&mut start)?;
+    Ok(start == MAPPED_MAGIC)
+}
+
+fn invalid_data(message: String) -> MtsvError {
+    MtsvError::from(io::Error::new(io::ErrorKind::InvalidData, message))
+}
+
+/// Writes a file in the mapped layout, copying the text and the suffix array from a bincode
+/// serialization of them.
+pub struct MappedWriter {
+    writer: BufWriter<File>,
+    position: u64,
+    text: Option<Section>,
+    suffix_array: Option<SuffixArraySections>,
+}
+
+impl MappedWriter {
+    /// Create the file at `p`, and write its preamble.
+    pub fn create(p: &str) -> MtsvResult<Self> {
+        let mut writer = BufWriter::with_capacity(1 << 20, File::create(Path::new(p))?);
+        writer.write_all(MAPPED_MAGIC)?;
+        writer.write_all(&MAPPED_INDEX_FORMAT_VERSION.to_le_bytes())?;
+        writer.write_all(&[0; 4])?;
+        // the trailer offset is filled in by `finish`
+        writer.write_all(&[0; 8])?;
+        Ok(MappedWriter {
+            writer,
+            position: PREAMBLE_LEN,
+            text: None,
+            suffix_array: None,
+        })
+    }
+
+    /// Copy `len` bytes of text from `reader`, which is left just after them.
+    pub fn copy_text<R: Read>(&mut self, reader: &mut R, len: u64) -> MtsvResult<()> {
+        self.text = Some(self.copy_section(reader, len)?);
+        Ok(())
+    }
+
+    /// Copy the bincode serialization of a rust-bio `SampledSuffixArray<BWT, Less, Occ>` from
+    /// `reader`, which is left just after it.
+    ///
+    /// This follows rust-bio's field order, and bincode writes a `usize` as a little-endian u64
+    /// and a `Vec` as its length followed by its elements, so the arrays are copied as they are.
+    pub fn copy_suffix_array<R: Read>(&mut self, reader: &mut R) -> MtsvResult<()> {
+        let bwt_len: u64 = deserialize_from(&mut *reader)?;
+        let bwt = self.copy_section(reader, bwt_len)?;
+        let less = deserialize_from(&mut *reader)?;
+
+        let symbols: u64 = deserialize_from(&mut *reader)?;
+        let mut occ = Vec::new();
+        for _ in 0..symbols {
+            let samples: u64 = deserialize_from(&mut *reader)?;
+            occ.push(self.copy_section(reader, samples * 8)?);
+        }
+        let k = deserialize_from(&mut *reader)?;
+
+        let samples: u64 = deserialize_from(&mut *reader)?;
+        let sample = self.copy_section(reader, samples * 8)?;
+        let s = deserialize_from(&mut *reader)?;
+        // bincode writes a map as a sequence of pairs
+        let mut extra_rows: Vec<(usize, usize)> = deserialize_from(&mut *reader)?;
+        extra_rows.sort();
+        let sentinel = deserialize_from(&mut *reader)?;
+
+        self.suffix_array = Some(SuffixArraySections {
+            bwt,
+            less,
+            occ,
+            k,
+            sample,
+            s,
+            extra_rows,
+            sentinel,
+        });
+        Ok(())
+    }
+
+    /// Write the trailer with `metadata`, and point the preamble at it.
+    pub fn finish<M: Serialize>(mut self, metadata: &M) -> MtsvResult<()> {
+        let layout = Layout {
+            text: self.text
+                .ok_or_else(|| invalid_data(String::from("mapped index has no text")))?,
+            suffix_array: self.suffix_array
+                .ok_or_else(|| invalid_data(String::from("mapped index has no suffix array")))?,
+            metadata,
+        };
+        serialize_into(&mut self.writer, &layout)?;
+
+        let mut file = self.writer.into_inner().map_err(|e| MtsvError::from(e.into_error()))?;
+        file.seek(SeekFrom::Start(PREAMBLE_LEN - 8))?;
+        file.write_all(&self.position.to_le_bytes())?;
+        file.flush()?;
+        Ok(())
+    }
+
+    /// Copy `len` bytes from `reader` into a new section.
+    fn copy_section<R: Read>(&mut self, reader: &mut R, len: u64) -> MtsvResult<Section> {
+        let padding = (SECTION_ALIGNMENT - self.position % SECTION_ALIGNMENT) % SECTION_ALIGNMENT;
+        self.writer.write_all(&vec![0; padding as usize])?;
+        self.position += padding;
+
+        let copied = io::copy(&mut (&mut *reader).take(len), &mut self.writer)?;
+        if copied != len {
+            return Err(MtsvError::from(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                                      "index ended in the middle of an array")));
+        }
+        let section = Section { offset: self.position, len };
+        self.position += len;
+        Ok(section)
+    }
+}
+
+/// A file in the mapped layout, whose text and FM index are read from a memory map, with the
+/// metadata `M` its writer stored in it.
+pub struct MappedFile<M> {
+    map: Mmap,
+    layout: Layout<M>,
+    extra_rows: HashMap<usize, usize>,
+}
+
+impl<M: DeserializeOwned> MappedFile<M> {
+    /// Map the file at `p`, checking that its sections are all inside it.
+    ///
+    /// The file mustn't be changed while it's mapped, as the indices mtsv-build writes never are.
+    pub fn open(p: &str) -> MtsvResult<Self> {
+        let file = File::open(Path::new(p))?;
+        // the map is only read, and index files are written once and then left alone
+        let map = unsafe { Mmap::map(&file)? };
+
+        if map.len() < PREAMBLE_LEN as usize || !map.starts_with(MAPPED_MAGIC) {
+            return Err(invalid_data(format!("{} isn't a mapped index", p)));
+        }
+        let word = |at: usize, len: usize| {
+            map[at..at + len].iter().rev().fold(0u64, |w, &b| w << 8 | b as u64)
+        };
+        let version = word(MAPPED_MAGIC.len(), 4) as u32;
+        if version != MAPPED_INDEX_FORMAT_VERSION {
+            return Err(invalid_data(format!("{} has mapped index format version {}, but this \
+                                             build of mtsv reads version {}",
+                                            p,
+                                            version,
+                                            MAPPED_INDEX_FORMAT_VERSION)));
+        }
+        let trailer = word(PREAMBLE_LEN as usize - 8, 8);
+        if trailer < PREAMBLE_LEN || trailer > map.len() as u64 {
+            return Err(invalid_data(format!("{} is truncated", p)));
+        }
+        let layout: Layout<M> = deserialize(&map[trailer as usize..])?;
+
+        check_layout(&layout, trailer)
+            .map_err(|problem| invalid_data(format!("{} is corrupt: {}", p, problem)))?;
+        let extra_rows = layout.suffix_array.extra_rows.iter().cloned().collect();
+        Ok(MappedFile { map, layout, extra_rows })
+    }
+}
+
+/// Check that every section of `layout` ends before `trailer`, and that the sample arrays are
+/// as long as the BWT needs, so that lookups can't go past them.
+fn check_layout<M>(layout: &Layout<M>, trailer: u64) -> Result<(), String> {
+    let sa = &layout.suffix_array;
+    let n = sa.bwt.len;
+    if n == 0 || sa.k == 0 || sa.s == 0 {
+        return Err(String::from("empty BWT or zero sampling interval"));
+    }
+
+    let mut sections = vec![layout.text, sa.bwt, sa.sample];
+    sections.extend(&sa.occ);
+    for section in &sections {
+        match section.offset.checked_add(section.len) {
+            Some(end) if end <= trailer => (),
+            _ => return Err(format!("a section runs past the trailer at {}", trailer)),
+        }
+    }
+
+    if sa.sample.words() < (n - 1) / sa.s as u64 + 1 {
+        return Err(String::from("too few suffix array samples"));
+    }
+    for section in &sa.occ {
+        if section.len > 0 && section.words() < (n - 1) / sa.k as u64 + 1 {
+            return Err(String::from("too few occurrence samples"));
+        }
+    }
+    Ok(())
+}
+
+impl<M> MappedFile<M> {
+    /// The metadata stored with the file.
+    pub fn metadata(&self) -> &M {
+        &self.layout.metadata
+    }
+
+    /// The text the FM index was built over.
+    pub fn text(&self) -> &[u8] {
+        self.section(self.layout.text)
+    }
+
+    fn section(&self, section: Section) -> &[u8] {
+        &self.map[section.offset as usize..(section.offset + section.len) as usize]
+    }
+
+    /// The `i`th u64 of `section`.
+    fn word(&self, section: Section, i: usize) -> usize {
+        let start = section.offset as usize + i * 8;
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&self.map[start..start + 8]);
+        u64::from_le_bytes(bytes) as usize
+    }
+
+    fn bwt(&self) -> &[u8] {
+        self.section(self.layout.suffix_array.bwt)
+    }
+
+    /// The number of symbols in the BWT smaller than `a`, or `None` if `a` isn't in the alphabet.
+    fn less(&self, a: u8) -> Option<usize> {
+        let sa = &self.layout.suffix_array;
+        match sa.occ.get(a as usize) {
+            Some(section) if section.len > 0 => sa.less.get(a as usize).cloned(),
+            _ => None,
+        }
+    }
+
+    /// The number of occurrences of `a` in the BWT up to and including `r`, as rust-bio's
+    /// `Occ::get` counts them.
+    fn occ(&self, r: usize, a: u8) -> usize {
+        let sa = &self.layout.suffix_array;
+        let k = sa.k as usize;
+        let checkpoint = r / k;
+        let counted = self.bwt()[checkpoint * k + 1..r + 1].iter().filter(|&&b| b == a).count();
+        self.word(sa.occ[a as usize], checkpoint) + counted
+    }
+
+    /// Find the suffix array interval of `pattern`, or of its longest suffix which occurs in the
+    /// text, as rust-bio's `FMIndexable::backward_search` does.
+    pub fn backward_search(&self, pattern: &[u8]) -> BackwardSearchResult {
+        let (mut l, mut r) = (0, self.bwt().len() - 1);
+        let (mut pl, mut pr) = (l, r);
+        let mut matched_len = 0;
+        let mut complete = true;
+
+        for &a in pattern.iter().rev() {
+            pl = l;
+            pr = r;
+            let less = match self.less(a) {
+                Some(less) => less,
+                None => {
+                    complete = false;
+                    break;
+                },
+            };
+            l = less + if l > 0 { self.occ(l - 1, a) } else { 0 };
+            let end = less + self.occ(r, a);
+            if end == 0 || l > end - 1 {
+                complete = false;
+                break;
+            }
+            r = end - 1;
+            matched_len += 1;
+        }
+
+        if matched_len == 0 {
+            BackwardSearchResult::Absent
+        } else if complete {
+            BackwardSearchResult::Complete(Interval { lower: l, upper: r + 1 })
+        } else {
+            BackwardSearchResult::Partial(Interval { lower: pl, upper: pr + 1 }, matched_len)
+        }
+    }
+
+    /// The suffix array entry of `row`, walking back through the BWT to the nearest sampled row
+    /// as rust-bio's `SampledSuffixArray` does.
+    pub fn suffix(&self, row: usize) -> usize {
+        let sa = &self.layout.suffix_array;
+        let bwt = self.bwt();
+        let mut pos = row;
+        let mut offset = 0;
+        loop {
+            if pos.is_multiple_of(sa.s) {
+                return self.word(sa.sample, pos / sa.s) + offset;
+            }
+
+            let c = bwt[pos];
+            if c == sa.sentinel {
+                return self.extra_rows[&pos] + offset;
+            }
+
+            pos = sa.less[c as usize] + self.occ(pos - 1, c);
+            offset += 1;
+        }
+    }
+
+    /// The text offsets of the suffixes in `interval`.
+    pub fn locate(&self, interval: &Interval) -> Vec<usize> {
+        (interval.lower..interval.upper).map(|row| self.suffix(row)).collect()
+    }
+}
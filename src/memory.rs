@@ -0,0 +1,253 @@
+//! Keeping a binning run under a memory limit (`mtsv-binner --memory-limit-gb`).
+//!
+//! The indices are most of a run's memory and can't shrink, so a run whose indices are already
+//! bigger than the limit is refused before they're loaded. What's left over after the indices
+//! sizes the queue of reads waiting for a worker. While the process's resident memory is above a
+//! soft watermark, reads are only taken once every read taken before them has been written, and
+//! the writers are flushed, so buffered reads and hits stop growing.
+
+use cue::QUEUE_PER_WORKER;
+use error::*;
+use std::cmp;
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+/// The soft watermark, as a percentage of the limit.
+pub(crate) const WATERMARK_PERCENT: u64 = 90;
+
+/// How many reads are taken between checks of the process's memory.
+pub(crate) const CHECK_EVERY: usize = 256;
+
+/// The length assumed for queued reads when there's no maximum read length.
+pub const ASSUMED_READ_LENGTH: usize = 10_000;
+
+/// The read queue may use this fraction (1/N) of the memory left over after the indices. The rest
+/// is for the reads being queried, their hits waiting to be written, and the writers' buffers.
+const QUEUE_SHARE: u64 = 16;
+
+/// Bytes assumed for a queued read besides its sequence: the ID and the allocations' overhead.
+const READ_OVERHEAD_BYTES: u64 = 256;
+
+/// Convert a limit in gigabytes (GiB) to bytes.
+pub fn gb_to_bytes(gb: f64) -> u64 {
+    (gb * (1u64 << 30) as f64) as u64
+}
+
+fn bytes_to_gb(bytes: u64) -> f64 {
+    bytes as f64 / (1u64 << 30) as f64
+}
+
+/// Something which can tell how much memory the process is using.
+pub trait MemoryReporter: Sync {
+    /// The process's resident memory in bytes, or `None` if it can't be found.
+    fn resident_bytes(&self) -> Option<u64>;
+}
+
+/// Reports the process's resident memory from `/proc/self/status`. Where that isn't available,
+/// nothing is reported, and the watermark is never crossed.
+pub struct ProcessMemory;
+
+impl MemoryReporter for ProcessMemory {
+    fn resident_bytes(&self) -> Option<u64> {
+        let status = BufReader::new(fs::File::open("/proc/self/status").ok()?);
+        status.lines()
+            .map_while(Result::ok)
+            .find_map(|line| {
+                line.strip_prefix("VmRSS:")
+                    .and_then(|kb| kb.trim().trim_end_matches("kB").trim().parse::<u64>().ok())
+            })
+            .map(|kb| kb * 1024)
+    }
+}
+
+/// Check that the index files fit in the limit, returning their total size in bytes. Indices are
+/// at least as big in memory as on disk (compressed ones are bigger).
+pub fn check_index_sizes(index_paths: &[&str], limit: u64) -> MtsvResult<u64> {
+    let mut total = 0;
+    for path in index_paths {
+        total += fs::metadata(path)?.len();
+    }
+    if total > limit {
+        return Err(MtsvError::IndexTooLarge(bytes_to_gb(total), bytes_to_gb(limit)));
+    }
+    Ok(total)
+}
+
+/// The number of reads to queue for `num_workers` workers, given the limit and the size of the
+/// indices. This is the usual `num_workers * cue::QUEUE_PER_WORKER` if that fits in the queue's
+/// share of the memory left over after the indices, and otherwise as many reads of
+/// `read_length` as fit, but at least one per worker.
+pub fn queue_capacity(limit: u64,
+                      index_bytes: u64,
+                      num_workers: usize,
+                      read_length: usize)
+                      -> usize {
+    // each read is held both as read and normalized
+    let read_bytes = 2 * read_length as u64 + READ_OVERHEAD_BYTES;
+    let fits = limit.saturating_sub(index_bytes) / QUEUE_SHARE / read_bytes;
+    let fits = cmp::min(fits, usize::MAX as u64) as usize;
+    cmp::max(num_workers, cmp::min(fits, num_workers * QUEUE_PER_WORKER))
+}
+
+/// Holds back the reader while the process is above the soft watermark.
+///
+/// The reader calls `take` before taking each read, and the writer calls `wrote` after each
+/// result it's given, including errors, so that the reads taken and written can be compared.
+pub struct Throttle<'a> {
+    reporter: &'a dyn MemoryReporter,
+    watermark: u64,
+    taken: AtomicUsize,
+    written: AtomicUsize,
+    flush: AtomicBool,
+    throttled: AtomicUsize,
+}
+
+impl<'a> Throttle<'a> {
+    /// A throttle with its watermark at `WATERMARK_PERCENT` of `limit` bytes.
+    pub fn new(reporter: &'a dyn MemoryReporter, limit: u64) -> Self {
+        Throttle {
+            reporter,
+            watermark: limit / 100 * WATERMARK_PERCENT,
+            taken: AtomicUsize::new(0),
+            written: AtomicUsize::new(0),
+            flush: AtomicBool::new(false),
+            throttled: AtomicUsize::new(0),
+        }
+    }
+
+    /// Called before taking a read. Every `CHECK_EVERY` reads, if the process is above the
+    /// watermark, asks the writer to flush and waits until every read taken so far is written.
+    pub fn take(&self) {
+        let taken = self.taken.fetch_add(1, Ordering::SeqCst);
+        if !taken.is_multiple_of(CHECK_EVERY) {
+            return;
+        }
+        match self.reporter.resident_bytes() {
+            Some(bytes) if bytes > self.watermark => {
+                debug!("{} MB resident is above the memory watermark, waiting for {} reads to \
+                        be written",
+                       bytes >> 20,
+                       taken - self.written.load(Ordering::SeqCst));
+                self.throttled.fetch_add(1, Ordering::SeqCst);
+                self.flush.store(true, Ordering::SeqCst);
+                while self.written.load(Ordering::SeqCst) < taken {
+                    thread::sleep(Duration::from_millis(1));
+                }
+            },
+            _ => (),
+        }
+    }
+
+    /// Called after each result is written. Returns whether the writers should be flushed.
+    pub fn wrote(&self) -> bool {
+        self.written.fetch_add(1, Ordering::SeqCst);
+        self.flush.swap(false, Ordering::SeqCst)
+    }
+
+    /// The number of times the reader was held back.
+    pub fn throttled(&self) -> usize {
+        self.throttled.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mktemp::Temp;
+    use std::sync::atomic::AtomicU64;
+    use super::*;
+
+    struct FakeMemory(AtomicU64);
+
+    impl MemoryReporter for FakeMemory {
+        fn resident_bytes(&self) -> Option<u64> {
+            Some(self.0.load(Ordering::SeqCst))
+        }
+    }
+
+    #[test]
+    fn queue_sizing() {
+        let gb = gb_to_bytes(1.0);
+        // plenty of room: the usual queue
+        assert_eq!(queue_capacity(8 * gb, gb, 4, 150), 4 * QUEUE_PER_WORKER);
+        // 16 MiB for the queue, at 2 * 1M + 256 bytes per read
+        assert_eq!(queue_capacity(gb + (256 << 20), gb, 4, 1_000_000), 8);
+        // no room at all still queues a read per worker
+        assert_eq!(queue_capacity(gb, 2 * gb, 4, 150), 4);
+        assert_eq!(queue_capacity(gb, gb, 1, ASSUMED_READ_LENGTH), 1);
+    }
+
+    #[test]
+    fn index_size_limit() {
+        let dir = Temp::new_dir().unwrap();
+        let path = dir.to_path_buf().join("big.index");
+        fs::write(&path, vec![0; 2048]).unwrap();
+        let path = path.to_str().unwrap();
+
+        assert_eq!(check_index_sizes(&[path], 4096).unwrap(), 2048);
+        assert!(check_index_sizes(&[path, path], 4096).is_ok());
+        match check_index_sizes(&[path, path], 4095) {
+            Err(MtsvError::IndexTooLarge(..)) => (),
+            other => panic!("expected IndexTooLarge, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn process_memory() {
+        if cfg!(target_os = "linux") {
+            assert!(ProcessMemory.resident_bytes().unwrap() > 0);
+        }
+    }
+
+    #[test]
+    fn throttle_below_watermark() {
+        let memory = FakeMemory(AtomicU64::new(899));
+        let throttle = Throttle::new(&memory, 1000);
+        for _ in 0..CHECK_EVERY * 3 {
+            throttle.take();
+        }
+        assert!(!throttle.wrote());
+        assert_eq!(throttle.throttled(), 0);
+    }
+
+    #[test]
+    fn throttle_above_watermark() {
+        let memory = FakeMemory(AtomicU64::new(901));
+        let throttle = Throttle::new(&memory, 1000);
+        // the first check has nothing to wait for
+        for _ in 0..CHECK_EVERY {
+            throttle.take();
+        }
+        assert_eq!(throttle.throttled(), 1);
+
+        let taken = AtomicBool::new(false);
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                throttle.take();
+                taken.store(true, Ordering::SeqCst);
+            });
+            while throttle.throttled() < 2 {
+                thread::sleep(Duration::from_millis(1));
+            }
+            // the writers are asked to flush
+            assert!(throttle.wrote());
+            for _ in 0..CHECK_EVERY - 2 {
+                assert!(!throttle.wrote());
+            }
+            thread::sleep(Duration::from_millis(20));
+            assert!(!taken.load(Ordering::SeqCst));
+            // writing the last read taken lets the reader carry on
+            throttle.wrote();
+        });
+        assert!(taken.load(Ordering::SeqCst));
+
+        // back under the watermark, nothing waits
+        memory.0.store(0, Ordering::SeqCst);
+        for _ in 0..CHECK_EVERY {
+            throttle.take();
+        }
+        assert_eq!(throttle.throttled(), 2);
+    }
+}
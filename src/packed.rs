@@ -0,0 +1,245 @@
+//! Reference sequences packed two bits per base, as indices hold them.
+//!
+//! An index's concatenated references are almost all `ACGT`, so those bases are packed four to a
+//! byte, and anything else (runs of `N`, and the suffix array sentinel) is kept as a list of runs
+//! over the packed bases. Queries decode the few bases they align against into a buffer of their
+//! own (see `ReferenceText`).
+//!
+//! Protein references wouldn't pack, so they're kept a byte per residue (see `IndexText`).
+
+use serde::{Serialize, Deserialize};
+use std::cmp;
+use std::io::{self, Read};
+use std::ops::Range;
+
+/// The bases packed into each word.
+const BASES_PER_WORD: usize = 32;
+
+/// The bases `ACGT`, by their two-bit code.
+const CODES: &[u8; 4] = b"ACGT";
+
+/// A run of a byte other than `ACGT`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+struct Run {
+    start: usize,
+    end: usize,
+    base: u8,
+}
+
+/// A sequence packed two bits per base, with runs of any other bytes kept alongside.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct PackedSequence {
+    len: usize,
+    /// Two bits per base, starting from the least significant bits of each word. Bases in runs
+    /// are packed as `A`.
+    words: Vec<u64>,
+    /// The runs of other bytes, in order.
+    runs: Vec<Run>,
+}
+
+impl PackedSequence {
+    /// Pack `sequence`.
+    pub fn new(sequence: &[u8]) -> Self {
+        let mut words = vec![0; sequence.len().div_ceil(BASES_PER_WORD)];
+        let mut runs: Vec<Run> = Vec::new();
+        for (i, &base) in sequence.iter().enumerate() {
+            let code = match base {
+                b'A' => 0,
+                b'C' => 1,
+                b'G' => 2,
+                b'T' => 3,
+                _ => {
+                    match runs.last_mut() {
+                        Some(run) if run.end == i && run.base == base => run.end += 1,
+                        _ => {
+                            runs.push(Run {
+                                start: i,
+                                end: i + 1,
+                                base,
+                            })
+                        },
+                    }
+                    0
+                },
+            };
+            words[i / BASES_PER_WORD] |= code << (2 * (i % BASES_PER_WORD));
+        }
+        runs.shrink_to_fit();
+
+        PackedSequence {
+            len: sequence.len(),
+            words,
+            runs,
+        }
+    }
+
+    /// The number of bases.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether there are no bases.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Decode the bases from `start` into `out`, which must fit within the sequence.
+    pub fn decode(&self, start: usize, out: &mut [u8]) {
+        let end = start + out.len();
+        assert!(end <= self.len, "Decoding past the end of a packed sequence");
+
+        for (i, base) in (start..end).zip(out.iter_mut()) {
+            let code = self.words[i / BASES_PER_WORD] >> (2 * (i % BASES_PER_WORD)) & 3;
+            *base = CODES[code as usize];
+        }
+
+        let first = self.runs.partition_point(|run| run.end <= start);
+        for run in self.runs[first..].iter().take_while(|run| run.start < end) {
+            let from = cmp::max(run.start, start) - start;
+            let to = cmp::min(run.end, end) - start;
+            for base in &mut out[from..to] {
+                *base = run.base;
+            }
+        }
+    }
+
+    /// Decode the whole sequence.
+    pub fn unpack(&self) -> Vec<u8> {
+        let mut sequence = Vec::new();
+        self.bases_into(0..self.len, &mut sequence);
+        sequence
+    }
+
+    /// A reader of the decoded sequence, which is decoded as it's read.
+    pub fn reader(&self) -> Unpacker<'_> {
+        Unpacker {
+            packed: self,
+            position: 0,
+        }
+    }
+}
+
+/// Reads a packed sequence's bases (see `PackedSequence::reader`).
+pub struct Unpacker<'a> {
+    packed: &'a PackedSequence,
+    position: usize,
+}
+
+impl<'a> Read for Unpacker<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = cmp::min(buf.len(), self.packed.len - self.position);
+        self.packed.decode(self.position, &mut buf[..n]);
+        self.position += n;
+        Ok(n)
+    }
+}
+
+/// An index's concatenated reference sequences: packed for DNA indices, and a byte per residue
+/// for protein indices, which are almost never `ACGT`.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum IndexText {
+    /// DNA sequences, packed two bits per base.
+    Packed(PackedSequence),
+    /// Protein sequences, a byte per residue.
+    Plain(Vec<u8>),
+}
+
+impl IndexText {
+    /// The number of bases or residues.
+    pub fn len(&self) -> usize {
+        match *self {
+            IndexText::Packed(ref packed) => packed.len(),
+            IndexText::Plain(ref residues) => residues.len(),
+        }
+    }
+
+    /// Whether there are no bases or residues.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Decode the whole text.
+    pub fn unpack(&self) -> Vec<u8> {
+        match *self {
+            IndexText::Packed(ref packed) => packed.unpack(),
+            IndexText::Plain(ref residues) => residues.clone(),
+        }
+    }
+}
+
+/// Concatenated reference sequences which queries copy the bases they align against out of,
+/// whether they're packed or a byte per base.
+pub trait ReferenceText {
+    /// Append the bases in `range` to `out`.
+    fn bases_into(&self, range: Range<usize>, out: &mut Vec<u8>);
+}
+
+impl ReferenceText for [u8] {
+    fn bases_into(&self, range: Range<usize>, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self[range]);
+    }
+}
+
+impl<T: ReferenceText + ?Sized> ReferenceText for &T {
+    fn bases_into(&self, range: Range<usize>, out: &mut Vec<u8>) {
+        (**self).bases_into(range, out);
+    }
+}
+
+impl ReferenceText for PackedSequence {
+    fn bases_into(&self, range: Range<usize>, out: &mut Vec<u8>) {
+        let start = out.len();
+        out.resize(start + range.len(), 0);
+        self.decode(range.start, &mut out[start..]);
+    }
+}
+
+impl ReferenceText for IndexText {
+    fn bases_into(&self, range: Range<usize>, out: &mut Vec<u8>) {
+        match *self {
+            IndexText::Packed(ref packed) => packed.bases_into(range, out),
+            IndexText::Plain(ref residues) => residues[..].bases_into(range, out),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    quickcheck! {
+        fn unpacks_what_was_packed(bases: Vec<u8>, start: usize, len: usize) -> bool {
+            // mostly ACGT, with runs of other bytes
+            let sequence = bases.iter()
+                .map(|&b| if b % 8 < 4 { CODES[b as usize % 4] } else { b"NN$x"[b as usize % 4] })
+                .collect::<Vec<_>>();
+            let packed = PackedSequence::new(&sequence);
+
+            let start = if sequence.is_empty() { 0 } else { start % sequence.len() };
+            let end = cmp::min(start + len % 100, sequence.len());
+            let mut range = vec![b'?'];
+            packed.bases_into(start..end, &mut range);
+
+            let mut read = Vec::new();
+            packed.reader().read_to_end(&mut read).unwrap();
+
+            packed.len() == sequence.len() && packed.unpack() == sequence &&
+            range[1..] == sequence[start..end] && read == sequence
+        }
+    }
+
+    #[test]
+    fn packs_runs() {
+        let packed = PackedSequence::new(b"ACGTNNNNACGTNAC$");
+        assert_eq!(packed.words.len(), 1);
+        assert_eq!(packed.runs,
+                   vec![Run { start: 4, end: 8, base: b'N' },
+                        Run { start: 12, end: 13, base: b'N' },
+                        Run { start: 15, end: 16, base: b'$' }]);
+
+        let mut out = [0; 6];
+        packed.decode(6, &mut out);
+        assert_eq!(&out, b"NNACGT");
+        assert!(PackedSequence::new(b"").is_empty());
+    }
+}
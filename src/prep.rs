@@ -5,6 +5,7 @@ use bio::io::fastq::Reader;
 use cue::pipeline;
 
 use error::MtsvResult;
+use io::check_fastq_record;
 use itertools::Itertools;
 use prep_config::{PrepConfig, TrimType};
 use std::collections::{HashMap, HashSet};
@@ -15,6 +16,7 @@ use std::process::exit;
 /// Execute QC processes on a given configuration, in parallel as much as possible.
 pub fn run_prep(config: &PrepConfig) -> MtsvResult<()> {
     let mut processed: HashMap<Vec<u8>, Vec<usize>> = HashMap::new();
+    let mut mismatched = 0;
     let mut writer = BufWriter::new(File::create(&config.outfile)?);
 
     // chain together all of the FASTQ iterators
@@ -41,12 +43,30 @@ pub fn run_prep(config: &PrepConfig) -> MtsvResult<()> {
             },
         };
 
+        // the QC processes index into the qualities by position
+        if let Err(why) = check_fastq_record(&r) {
+            if config.strict_reads {
+                error!("Unable to read FASTQ file ({}): {}", config.infiles[i].0.display(), why);
+                exit(13);
+            }
+            warn!("Skipping read in {}: {}", config.infiles[i].0.display(), why);
+            return (i, None);
+        }
+
         // get the subsequence(s) from the QC processes
         let subseqs = process_read(r.seq(), r.qual(), i, config);
 
-        (i, subseqs)
+        (i, Some(subseqs))
     },
              |(i, s)| {
+        let s = match s {
+            Some(s) => s,
+            None => {
+                mismatched += 1;
+                return;
+            },
+        };
+
         // deduplicate subsequences, keeping count of which file they came from
         for r in s {
             let counts = processed.entry(r)
@@ -59,6 +79,11 @@ pub fn run_prep(config: &PrepConfig) -> MtsvResult<()> {
         }
     });
 
+    if mismatched > 0 {
+        warn!("Skipped {} FASTQ records with mismatched sequence and quality lengths",
+              mismatched);
+    }
+
     let mut processed = processed.into_iter().collect::<Vec<_>>();
     processed.sort();
 
@@ -84,6 +109,7 @@ pub fn run_prep(config: &PrepConfig) -> MtsvResult<()> {
 /// into multiple subsequences if enabled
 /// * Filter out any low-quality subsequences if enabled (depending on minimum quality and # of low
 /// quality bases tolerated)
+/// * Filter out any low-complexity subsequences if enabled
 pub fn process_read(seq: &[u8],
                     qual: &[u8],
                     file_index: usize,
@@ -125,6 +151,10 @@ pub fn process_read(seq: &[u8],
             },
             _ => true,
         })
+        .filter(|&(s, _)| match config.low_complexity {
+            Some(ref params) => !is_low_complexity(s, params),
+            None => true,
+        })
         .map(|(s, _)| Vec::from(s))
         .collect::<Vec<_>>()
 }
@@ -234,6 +264,111 @@ pub fn is_high_enough_quality(quality: &[u8], min_quality: u8, tolerance: usize)
     quality.iter().filter(|&&q| q < min_quality).count() <= tolerance
 }
 
+/// Parameters for the DUST low-complexity score.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct DustParams {
+    /// The number of bases scored at a time. Windows overlap by half their length.
+    pub window: usize,
+    /// The score above which a window is low-complexity, in tenths (as with `dustmasker -level`).
+    pub level: u32,
+}
+
+impl Default for DustParams {
+    fn default() -> Self {
+        DustParams {
+            window: 64,
+            level: 20,
+        }
+    }
+}
+
+/// The DUST score of a window, in tenths.
+///
+/// Each of the 64 possible triplets that occurs `c` times contributes `c * (c - 1) / 2`, and the
+/// total is divided by one less than the number of triplets in the window, so a single repeated
+/// base scores roughly half the window length and random sequence scores close to zero. Triplets
+/// containing an N aren't counted.
+pub fn dust_score(window: &[u8]) -> u32 {
+    if window.len() < 4 {
+        return 0;
+    }
+
+    let mut counts = [0u32; 64];
+    for triplet in window.windows(3) {
+        let mut code = 0;
+        let mut valid = true;
+        for b in triplet {
+            code = code << 2 |
+                   match *b {
+                b'A' | b'a' => 0,
+                b'C' | b'c' => 1,
+                b'G' | b'g' => 2,
+                b'T' | b't' => 3,
+                _ => {
+                    valid = false;
+                    0
+                },
+            };
+        }
+
+        if valid {
+            counts[code] += 1;
+        }
+    }
+
+    let sum = counts.iter().map(|&c| c * c.saturating_sub(1) / 2).sum::<u32>();
+    sum * 10 / (window.len() as u32 - 3)
+}
+
+/// Find the regions of a sequence made up of low-complexity windows, as sorted, non-overlapping
+/// half-open ranges. Sequences shorter than the window are scored as a single window.
+pub fn low_complexity_regions(seq: &[u8], params: &DustParams) -> Vec<(usize, usize)> {
+    let window = params.window.min(seq.len());
+    if window == 0 {
+        return Vec::new();
+    }
+
+    // windows overlap by half, with the last one flush against the end of the sequence
+    let step = (window / 2).max(1);
+    let mut starts = (0..seq.len() - window + 1).step_by(step).collect::<Vec<_>>();
+    if starts.last() != Some(&(seq.len() - window)) {
+        starts.push(seq.len() - window);
+    }
+
+    let mut regions: Vec<(usize, usize)> = Vec::new();
+    for start in starts {
+        let end = start + window;
+        if dust_score(&seq[start..end]) <= params.level {
+            continue;
+        }
+
+        match regions.last_mut() {
+            Some(last) if last.1 >= start => last.1 = end,
+            _ => regions.push((start, end)),
+        }
+    }
+
+    regions
+}
+
+/// Whether any window of a sequence is low-complexity.
+pub fn is_low_complexity(seq: &[u8], params: &DustParams) -> bool {
+    !low_complexity_regions(seq, params).is_empty()
+}
+
+/// Replace the low-complexity regions of a sequence with N, returning the number of bases masked.
+pub fn mask_low_complexity(seq: &mut [u8], params: &DustParams) -> usize {
+    let mut masked = 0;
+    for (start, end) in low_complexity_regions(seq, params) {
+        for b in &mut seq[start..end] {
+            *b = b'N';
+        }
+        masked += end - start;
+    }
+
+    masked
+}
+
 #[cfg(test)]
 mod tests {
     // use error::MtsvResult;
@@ -559,4 +694,71 @@ mod tests {
         assert_eq!(result_sequence, b"DDD");
         assert_eq!(result_quality, [33, 33, 33]);
     }
+
+    fn pseudo_random_seq(len: usize) -> Vec<u8> {
+        // a small LCG so the example doesn't depend on the rand crate's output
+        let mut state = 12345u32;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                b"ACGT"[(state >> 16) as usize % 4]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn dust_poly_a() {
+        let seq = vec![b'A'; 64];
+
+        // 62 copies of AAA: 62 * 61 / 2 / 61
+        assert_eq!(dust_score(&seq), 310);
+        assert!(is_low_complexity(&seq, &DustParams::default()));
+    }
+
+    #[test]
+    fn dust_dinucleotide_repeat() {
+        let seq = b"AT".repeat(32);
+
+        // 31 copies each of ATA and TAT: 2 * 31 * 30 / 2 / 61
+        assert_eq!(dust_score(&seq), 152);
+        assert!(is_low_complexity(&seq, &DustParams::default()));
+    }
+
+    #[test]
+    fn dust_random_sequence() {
+        let seq = pseudo_random_seq(150);
+
+        assert!(dust_score(&seq[..64]) < 20);
+        assert!(!is_low_complexity(&seq, &DustParams::default()));
+    }
+
+    #[test]
+    fn dust_ignores_n() {
+        assert_eq!(dust_score(&vec![b'N'; 64]), 0);
+    }
+
+    #[test]
+    fn dust_mask_poly_a_tail() {
+        let mut seq = pseudo_random_seq(128);
+        for b in &mut seq[96..] {
+            *b = b'A';
+        }
+
+        let regions = low_complexity_regions(&seq, &DustParams::default());
+        assert_eq!(regions, vec![(64, 128)]);
+
+        let masked = mask_low_complexity(&mut seq, &DustParams::default());
+        assert_eq!(masked, 64);
+        assert!(seq[64..].iter().all(|&b| b == b'N'));
+        assert!(seq[..64].iter().all(|&b| b != b'N'));
+    }
+
+    #[test]
+    fn dust_short_read() {
+        let params = DustParams::default();
+
+        assert!(is_low_complexity(&vec![b'A'; 20], &params));
+        assert!(!is_low_complexity(b"ACGTTGCA", &params));
+        assert!(!is_low_complexity(b"", &params));
+    }
 }
@@ -4,6 +4,9 @@ use bio::io::fastq::Reader;
 use clap::{App, Arg, ArgGroup, ArgMatches};
 
 use error::MtsvResult;
+use formats::format_versions_arg;
+use io::check_fastq_record;
+use prep::DustParams;
 use std::cmp::min;
 use std::path::{Path, PathBuf};
 use bio::io::fastq::FastqRead;
@@ -17,6 +20,8 @@ pub struct PrepConfig {
     pub min_quality: Option<u8>,
     /// The number of low-quality bases to tolerate, if filtering by quality.
     pub quality_threshold: Option<usize>,
+    /// The DUST parameters to drop low-complexity sequences with, if filtering them.
+    pub low_complexity: Option<DustParams>,
     /// The adapters to check for at the beginning of sequences, if filtering adapters.
     // pub adapters: Option<HashSet<Vec<u8>>>,
     // /// The number of adapter-matching bases to ignore at the beginning of a sequence, if filtering
@@ -28,6 +33,9 @@ pub struct PrepConfig {
     pub infiles: Vec<(PathBuf, FastqMetadata)>,
     /// The path to write the FASTA results file to.
     pub outfile: PathBuf,
+    /// Stop with an error at records whose sequence and quality lengths differ, rather than
+    /// skipping them.
+    pub strict_reads: bool,
 }
 
 /// Which type of length-homogenization (trimming) to use on the reads in a particular file.
@@ -79,7 +87,9 @@ pub struct FastqMetadata {
 
 /// Run an initial parse of a file, determining it's read length, the number of reads contained,
 /// and a best guess at what quality score encoding is used.
-pub fn read_fastq_metadata(p: &Path) -> MtsvResult<FastqMetadata> {
+///
+/// Records whose sequence and quality lengths differ are left out, or are an error if `strict`.
+pub fn read_fastq_metadata(p: &Path, strict: bool) -> MtsvResult<FastqMetadata> {
     let rdr = Reader::from_file(p)?;
 
     let mut count = 0;
@@ -88,12 +98,17 @@ pub fn read_fastq_metadata(p: &Path) -> MtsvResult<FastqMetadata> {
 
     for record in rdr.records() {
         let record = (record)?;
+        match check_fastq_record(&record) {
+            Ok(()) => (),
+            Err(why) if strict => return Err(why),
+            Err(_) => continue,
+        }
 
         count += 1;
         read_len = min(read_len, record.seq().len());
-        let curr_min_qual = record.qual().iter().min().expect("invalid quality string found");
-
-        min_quality = min(min_quality, *curr_min_qual);
+        if let Some(&curr_min_qual) = record.qual().iter().min() {
+            min_quality = min(min_quality, curr_min_qual);
+        }
     }
 
     let encoding = match min_quality {
@@ -205,7 +220,7 @@ pub fn parse_config(args: &ArgMatches) -> MtsvResult<PrepConfig> {
     for p in args.values_of("FASTQ").unwrap() {
         info!("Parsing {}...", p);
         let p = PathBuf::from(p);
-        let md = read_fastq_metadata(&p)?;
+        let md = read_fastq_metadata(&p, args.is_present("STRICT_READS"))?;
 
         infiles.push((p, md));
     }
@@ -230,11 +245,17 @@ pub fn parse_config(args: &ArgMatches) -> MtsvResult<PrepConfig> {
         trim: trim,
         min_quality: quality_min,
         quality_threshold: quality_threshold,
+        low_complexity: if args.is_present("FILTER_LOW_COMPLEXITY") {
+            Some(DustParams::default())
+        } else {
+            None
+        },
         // adapters: adapters,
         // adapter_tolerance: adapter_tolerance,
         num_threads: num_threads,
         infiles: infiles,
         outfile: outfile,
+        strict_reads: args.is_present("STRICT_READS"),
     })
 }
 
@@ -290,6 +311,14 @@ pub fn prep_cli_app() -> App<'static, 'static> {
             .help("Maximum number of bases below minimum quality to tolerate per read.")
             .takes_value(true)
             .requires("QUALITY_MIN"))
+        .arg(Arg::with_name("FILTER_LOW_COMPLEXITY")
+            .long("filter-low-complexity")
+            .help("Discard low-complexity sequences (e.g. poly-A or simple repeats), as scored by \
+                   DUST after trimming."))
+        .arg(Arg::with_name("STRICT_READS")
+            .long("strict-reads")
+            .help("Stop with an error at FASTQ records whose quality line is a different length \
+                   to their sequence. Without this, such records are skipped with a warning."))
         .arg(Arg::with_name("FASTA")
             .short("o")
             .long("out")
@@ -303,6 +332,7 @@ pub fn prep_cli_app() -> App<'static, 'static> {
             .required(true)
             .display_order(1000)
             .validator(validate_fastq_file))
+        .arg(format_versions_arg())
 }
 
 /// Check to make sure we can parse a FASTQ record out of a given path.
@@ -317,6 +347,8 @@ fn validate_fastq_file(s: String) -> Result<(), String> {
 #[cfg(test)]
 mod test {
 
+    use mktemp::Temp;
+    use std::fs;
     use std::path::{PathBuf};
     use super::*;
 
@@ -331,9 +363,11 @@ mod test {
             num_threads: 4,
             min_quality: None,
             quality_threshold: None,
+            low_complexity: None,
             // adapter_tolerance: None,
             // adapters: None,
             outfile: PathBuf::from("/dev/null"),
+            strict_reads: false,
             infiles: vec![
                 (PathBuf::from("tests/prep/sample1.fastq"),
                  FastqMetadata {
@@ -369,7 +403,9 @@ mod test {
             num_threads: 4,
             min_quality: None,
             quality_threshold: None,
+            low_complexity: None,
             outfile: PathBuf::from("/dev/null"),
+            strict_reads: false,
             infiles: vec![
                 (PathBuf::from("tests/prep/sample1.fastq"),
                  FastqMetadata {
@@ -394,6 +430,18 @@ mod test {
         assert_eq!(parsed, expected);
     }
 
+    #[test]
+    fn metadata_skips_mismatched_qualities() {
+        let file = Temp::new_file().unwrap();
+        let path = file.to_path_buf();
+        fs::write(&path, "@a\nACGTACGT\n+\nIIIIIIII\n@b\nACGT\n+\nII\n@c\nACGTAC\n+\n######\n")
+            .unwrap();
+
+        let md = read_fastq_metadata(&path, false).unwrap();
+        assert_eq!((md.num_reads, md.read_len), (2, 6));
+        assert!(read_fastq_metadata(&path, true).is_err());
+    }
+
     // #[test]
     // fn test_subadapters() {
     //     let expected = vec!["AAG",
@@ -0,0 +1,264 @@
+//! A bloom filter over an index's k-mers, to skip querying reads which can't have any seed hits.
+
+use index::{MGIndex, QueryParams};
+use itertools::Itertools;
+use serde::{Serialize, Deserialize};
+use std::cmp;
+use std::f64::consts::LN_2;
+use std::hash::Hasher;
+use util::Fnv64;
+
+/// The number of k-mers whose bases are decoded from the index at a time while building a
+/// prescreen.
+const DECODE_CHUNK: usize = 1 << 20;
+
+/// Settings for building a prescreen.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PrescreenParams {
+    /// Length of the k-mers in the filter, which must match the binner's seed size for the
+    /// prescreen to be used.
+    pub kmer_size: usize,
+    /// Target false positive rate.
+    pub false_positive_rate: f64,
+    /// Upper limit on the size of the filter, which raises the false positive rate if it's hit.
+    pub max_bytes: Option<usize>,
+}
+
+impl Default for PrescreenParams {
+    fn default() -> Self {
+        PrescreenParams {
+            kmer_size: QueryParams::default().seed_length,
+            false_positive_rate: 0.01,
+            max_bytes: None,
+        }
+    }
+}
+
+/// Path a prescreen is stored at for the index at `index_path`.
+pub fn prescreen_path(index_path: &str) -> String {
+    format!("{}.prescreen", index_path)
+}
+
+/// A bloom filter of every k-mer in an index's concatenated reference sequences.
+///
+/// A k-mer which is in the index is always found in the filter, so a read none of whose seeds are
+/// in the filter can't have any seed hits, and querying it would find nothing. False positives
+/// only cost the time to query the read.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Prescreen {
+    kmer_size: usize,
+    num_hashes: u32,
+    bits: Vec<u64>,
+    /// Fingerprint of the index the filter was built from.
+    fingerprint: u64,
+}
+
+impl Prescreen {
+    /// Build a prescreen for an index.
+    pub fn new(index: &MGIndex, params: &PrescreenParams) -> Self {
+        let len = index.reference_len();
+        let num_kmers = (len + 1).saturating_sub(params.kmer_size);
+
+        let mut prescreen = Prescreen::with_capacity(num_kmers, params, index.fingerprint());
+        // decode the sequences a chunk at a time, overlapping so every k-mer is in one chunk
+        let alphabet = index.alphabet();
+        for start in (0..num_kmers).step_by(DECODE_CHUNK) {
+            let end = cmp::min(start + DECODE_CHUNK + params.kmer_size - 1, len);
+            let mut bases = index.reference_bases(start, end);
+            // the k-mers are those of the FM index, which has any ambiguity codes as Ns, as
+            // seeds are searched for (see `QueryStrand::seed_sequence`)
+            if bases.iter().any(|&b| alphabet.is_ambiguous(b)) {
+                bases.iter_mut().for_each(|b| *b = alphabet.normalize(*b));
+            }
+            for kmer in bases.windows(params.kmer_size) {
+                prescreen.insert(kmer);
+            }
+        }
+
+        info!("Prescreen built: {} k-mers, {} bytes, {} hashes, {:.4} expected false positive rate",
+              num_kmers,
+              prescreen.bits.len() * 8,
+              prescreen.num_hashes,
+              prescreen.false_positive_rate(num_kmers));
+        prescreen
+    }
+
+    /// An empty filter sized for `num_kmers` k-mers.
+    fn with_capacity(num_kmers: usize, params: &PrescreenParams, fingerprint: u64) -> Self {
+        let n = num_kmers.max(1) as f64;
+        let mut num_bits = (-n * params.false_positive_rate.ln() / (LN_2 * LN_2)).ceil() as usize;
+        if let Some(max_bytes) = params.max_bytes {
+            num_bits = num_bits.min(max_bytes * 8);
+        }
+        let num_words = num_bits.div_ceil(64).max(1);
+
+        // the optimal number of hashes for the number of bits we ended up with
+        let num_hashes = ((num_words * 64) as f64 / n * LN_2).round().clamp(1.0, 32.0) as u32;
+
+        Prescreen {
+            kmer_size: params.kmer_size,
+            num_hashes,
+            bits: vec![0; num_words],
+            fingerprint,
+        }
+    }
+
+    /// Length of the k-mers in the filter.
+    pub fn kmer_size(&self) -> usize {
+        self.kmer_size
+    }
+
+    /// Fingerprint of the index the filter was built from (see `MGIndex::fingerprint`).
+    pub fn fingerprint(&self) -> u64 {
+        self.fingerprint
+    }
+
+    /// The expected false positive rate once `num_kmers` distinct k-mers have been inserted.
+    pub fn false_positive_rate(&self, num_kmers: usize) -> f64 {
+        let num_bits = (self.bits.len() * 64) as f64;
+        let k = self.num_hashes as f64;
+        (1.0 - (-k * num_kmers as f64 / num_bits).exp()).powf(k)
+    }
+
+    /// The bit positions for a k-mer, using double hashing.
+    fn positions(&self, kmer: &[u8]) -> BitPositions {
+        let mut hasher = Fnv64::default();
+        hasher.write(kmer);
+        let hash = hasher.finish();
+
+        BitPositions {
+            hash,
+            // an odd step can't cycle back to the first bit early
+            step: (hash.rotate_left(32) ^ 0x9e37_79b9_7f4a_7c15) | 1,
+            num_bits: (self.bits.len() * 64) as u64,
+            remaining: self.num_hashes,
+        }
+    }
+
+    fn insert(&mut self, kmer: &[u8]) {
+        for bit in self.positions(kmer) {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+    }
+
+    /// Whether `kmer` may be in the index. Always true for k-mers which are.
+    pub fn contains(&self, kmer: &[u8]) -> bool {
+        kmer.len() == self.kmer_size &&
+        self.positions(kmer).all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    /// Whether querying one strand of a read with `params` could find any hits, i.e. whether any
+    /// of the seeds `MGIndex::matching_tax_ids` would search may be in the index.
+    ///
+    /// Always true if the query could use partial seeds, or the seeds aren't this filter's k-mers.
+    pub fn may_match(&self, seq: &[u8], params: &QueryParams) -> bool {
+        if params.min_partial_seed.is_some() || params.seed_length != self.kmer_size ||
+           seq.len() < self.kmer_size {
+            return true;
+        }
+
+        (0..(seq.len() + 1 - self.kmer_size))
+            .step(params.seed_gap)
+            .map(|i| &seq[i..i + self.kmer_size])
+            .filter(|seed| seed.iter().filter(|&&b| b == b'N').count() <= params.max_seed_ns)
+            .any(|seed| self.contains(seed))
+    }
+}
+
+/// Iterator over the bits a k-mer sets in a `Prescreen`.
+struct BitPositions {
+    hash: u64,
+    step: u64,
+    num_bits: u64,
+    remaining: u32,
+}
+
+impl Iterator for BitPositions {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        self.remaining -= 1;
+        let bit = self.hash % self.num_bits;
+        self.hash = self.hash.wrapping_add(self.step);
+        Some(bit as usize)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use index::{BinMode, Database, Gi, TaxId};
+    use rand::{Rng, XorShiftRng};
+    use super::*;
+
+    fn random_seq(rng: &mut XorShiftRng, len: usize) -> Vec<u8> {
+        (0..len).map(|_| *rng.choose(b"ACGT").unwrap()).collect()
+    }
+
+    #[test]
+    fn no_false_negatives() {
+        let mut rng = XorShiftRng::new_unseeded();
+        let reference = random_seq(&mut rng, 2_000);
+        let mut db = Database::new();
+        db.insert(TaxId(1), vec![(Gi(1), reference.clone())]);
+        let index = MGIndex::new(db, 16, 32, BinMode::PerGi);
+
+        let prescreen = Prescreen::new(&index, &PrescreenParams::default());
+        assert_eq!(prescreen.fingerprint(), index.fingerprint());
+        for kmer in reference.windows(18) {
+            assert!(prescreen.contains(kmer));
+        }
+
+        // roughly the target false positive rate for k-mers which aren't in the index
+        let false_positives = (0..10_000)
+            .filter(|_| prescreen.contains(&random_seq(&mut rng, 18)))
+            .count();
+        assert!(false_positives < 300, "{} false positives", false_positives);
+    }
+
+    #[test]
+    fn size_limit() {
+        let mut rng = XorShiftRng::new_unseeded();
+        let mut db = Database::new();
+        db.insert(TaxId(1), vec![(Gi(1), random_seq(&mut rng, 2_000))]);
+        let index = MGIndex::new(db, 16, 32, BinMode::PerGi);
+
+        let params = PrescreenParams { max_bytes: Some(64), ..PrescreenParams::default() };
+        let small = Prescreen::new(&index, &params);
+        assert_eq!(small.bits.len(), 8);
+        assert!(small.false_positive_rate(1_983) > 0.5);
+    }
+
+    #[test]
+    fn may_match_checks_query_seeds() {
+        let mut rng = XorShiftRng::new_unseeded();
+        let mut reference = random_seq(&mut rng, 500);
+        reference[105] = b'N';
+        let mut db = Database::new();
+        db.insert(TaxId(1), vec![(Gi(1), reference.clone())]);
+        let prescreen =
+            Prescreen::new(&MGIndex::new(db, 16, 32, BinMode::PerGi), &PrescreenParams::default());
+
+        // only the seed at offset 40 is in the index
+        let mut read = random_seq(&mut rng, 100);
+        read[40..58].copy_from_slice(&reference[100..118]);
+        // and the seeds overlapping it aren't either
+        read[39] = if reference[99] == b'A' { b'C' } else { b'A' };
+        read[58] = if reference[118] == b'A' { b'C' } else { b'A' };
+
+        let params = QueryParams { seed_gap: 4, max_seed_ns: 1, ..QueryParams::default() };
+        assert!(prescreen.may_match(&read, &params));
+        // that seed has an N, so by default it isn't searched
+        assert!(!prescreen.may_match(&read, &QueryParams { max_seed_ns: 0, ..params }));
+        // with a gap of 3, offset 40 isn't searched
+        assert!(!prescreen.may_match(&read, &QueryParams { seed_gap: 3, ..params }));
+        // the filter can't rule anything out for partial seeds or other seed sizes
+        assert!(prescreen.may_match(&read,
+                                    &QueryParams { min_partial_seed: Some(12), seed_gap: 3,
+                                                   ..params }));
+        assert!(prescreen.may_match(&read, &QueryParams { seed_length: 20, ..params }));
+    }
+}
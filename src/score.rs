@@ -0,0 +1,175 @@
+//! Confidence scores for individual hits.
+
+use index::{Hit, QueryParams};
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeSet;
+
+/// How much each component contributes to a hit's confidence score. Only the ratios between the
+/// weights matter.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ConfidenceWeights {
+    /// Weight of how few edits the alignment needed.
+    pub edit: f32,
+    /// Weight of the proportion of searched seeds supporting the hit.
+    pub seed_support: f32,
+    /// Weight of how few taxids the read hit.
+    pub multiplicity: f32,
+}
+
+impl Default for ConfidenceWeights {
+    fn default() -> Self {
+        ConfidenceWeights {
+            edit: 0.5,
+            seed_support: 0.25,
+            multiplicity: 0.25,
+        }
+    }
+}
+
+/// Score how confident we can be that a read came from a hit's taxid, between 0 and 1.
+///
+/// The score is the weighted mean (using `params.confidence_weights`) of three components, each
+/// between 0 and 1:
+///
+/// * edit: `1 - edit / max_edit`, where `max_edit = ceil(read_len * params.edit_freq)` is the
+///   largest edit distance a hit can have. A perfect alignment scores 1, and if no edits are
+///   allowed every hit scores 1.
+/// * seed support: `seeds / seeds_searched`, the proportion of the seeds searched on the hit's
+///   strand which fell in the aligned region, capped at 1. Zero if no seeds were searched (e.g.
+///   for hits read back from a results file).
+/// * multiplicity: `1 / total_taxids_for_read`, so a read which only hit one taxid scores 1.
+///
+/// The formula is pinned by the tests below -- changing it changes which hits pass
+/// `--min-confidence`, so it should only change deliberately.
+pub fn confidence(hit: &Hit,
+                  read_len: usize,
+                  total_taxids_for_read: usize,
+                  params: &QueryParams)
+                  -> f32 {
+    let max_edit = (read_len as f64 * params.edit_freq).ceil() as u32;
+    let edit_score = if max_edit == 0 {
+        1.0
+    } else {
+        1.0 - (hit.edit.min(max_edit) as f32 / max_edit as f32)
+    };
+
+    let seed_score = if hit.seeds_searched == 0 {
+        0.0
+    } else {
+        (hit.seeds as f32 / hit.seeds_searched as f32).min(1.0)
+    };
+
+    let multiplicity_score = 1.0 / total_taxids_for_read.max(1) as f32;
+
+    let weights = &params.confidence_weights;
+    let total_weight = weights.edit + weights.seed_support + weights.multiplicity;
+    if total_weight <= 0.0 {
+        return 0.0;
+    }
+
+    (weights.edit * edit_score + weights.seed_support * seed_score +
+     weights.multiplicity * multiplicity_score) / total_weight
+}
+
+/// Drop the hits for a read whose confidence is below `min_confidence`, returning the number
+/// dropped. The multiplicity is the number of distinct taxids among all of the read's hits,
+/// counted before any are dropped.
+pub fn retain_confident_hits(hits: &mut Vec<Hit>,
+                             read_len: usize,
+                             params: &QueryParams,
+                             min_confidence: f32)
+                             -> usize {
+    let total_taxids = hits.iter().map(|h| h.tax_id).collect::<BTreeSet<_>>().len();
+
+    let before = hits.len();
+    hits.retain(|h| confidence(h, read_len, total_taxids, params) >= min_confidence);
+    before - hits.len()
+}
+
+#[cfg(test)]
+mod test {
+    use index::{Gi, TaxId};
+    use super::*;
+
+    fn hit(tax_id: u64, edit: u32, seeds: u32, seeds_searched: u32) -> Hit {
+        Hit {
+            tax_id: TaxId(tax_id),
+            edit,
+            seeds,
+            seeds_searched,
+            clipped_start: 0,
+            clipped_end: 0,
+            gi: Gi(0),
+        }
+    }
+
+    fn assert_close(found: f32, expected: f32) {
+        assert!((found - expected).abs() < 1e-6,
+                "expected {}, found {}",
+                expected,
+                found);
+    }
+
+    #[test]
+    fn perfect_unique_hit() {
+        let params = QueryParams::default();
+
+        assert_close(confidence(&hit(1, 0, 6, 6), 100, 1, &params), 1.0);
+    }
+
+    #[test]
+    fn pinned_values() {
+        let params = QueryParams::default();
+
+        // max edit is ceil(100 * 0.13) = 13
+        // 0.5 * (1 - 4/13) + 0.25 * 3/6 + 0.25 * 1/2
+        assert_close(confidence(&hit(1, 4, 3, 6), 100, 2, &params), 0.59615386);
+        // 0.5 * (1 - 13/13) + 0.25 * 1/12 + 0.25 * 1/5
+        assert_close(confidence(&hit(1, 13, 1, 12), 100, 5, &params), 0.070833333);
+        // edits beyond the maximum don't go negative, and seed support is capped
+        // 0.5 * 0 + 0.25 * 1 + 0.25 * 1
+        assert_close(confidence(&hit(1, 20, 8, 6), 100, 1, &params), 0.5);
+        // max edit is ceil(150 * 0.13) = 20
+        // 0.5 * (1 - 2/20) + 0.25 * 0 + 0.25 * 1/3
+        assert_close(confidence(&hit(1, 2, 0, 0), 150, 3, &params), 0.53333336);
+    }
+
+    #[test]
+    fn custom_weights() {
+        let params = QueryParams {
+            confidence_weights: ConfidenceWeights {
+                edit: 0.0,
+                seed_support: 1.0,
+                multiplicity: 3.0,
+            },
+            ..QueryParams::default()
+        };
+
+        // (1 * 3/6 + 3 * 1/2) / 4
+        assert_close(confidence(&hit(1, 4, 3, 6), 100, 2, &params), 0.5);
+
+        let no_weights = QueryParams {
+            confidence_weights: ConfidenceWeights {
+                edit: 0.0,
+                seed_support: 0.0,
+                multiplicity: 0.0,
+            },
+            ..QueryParams::default()
+        };
+        assert_close(confidence(&hit(1, 0, 6, 6), 100, 1, &no_weights), 0.0);
+    }
+
+    #[test]
+    fn retain_uses_distinct_taxids() {
+        let params = QueryParams::default();
+
+        // both strands hit taxid 1, so the read only hit two distinct taxids
+        let mut hits = vec![hit(1, 0, 6, 6), hit(1, 10, 1, 6), hit(2, 2, 4, 6)];
+        let dropped = retain_confident_hits(&mut hits, 100, &params, 0.5);
+
+        // 0.5 * 3/13 + 0.25 * 1/6 + 0.25 * 1/2 = 0.282 is dropped
+        // 0.5 * 11/13 + 0.25 * 4/6 + 0.25 * 1/2 = 0.715 is kept
+        assert_eq!(dropped, 1);
+        assert_eq!(hits, vec![hit(1, 0, 6, 6), hit(2, 2, 4, 6)]);
+    }
+}
@@ -0,0 +1,230 @@
+//! Suffix array construction for index builds too big to hold the suffix array in memory.
+//!
+//! rust-bio's `suffix_array` (SA-IS) builds the suffix array in memory, at 8 bytes per base, far
+//! more than the rest of a build. For references whose suffix array doesn't fit in memory,
+//! `external_suffix_array` sorts the suffixes a batch at a time into a file instead.
+
+use error::*;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use util::Progress;
+
+/// The number of leading bytes `external_suffix_array` buckets suffixes by.
+const BUCKET_PREFIX: u32 = 8;
+
+/// The number of distinct bytes in a bucket's prefix: the end of the text, and `$ACGNT`.
+const BUCKET_BASE: usize = 7;
+
+/// A suffix array in a temporary file, as little-endian `u64`s, which is removed when this is
+/// dropped (see `external_suffix_array`).
+pub struct SuffixArrayFile {
+    path: PathBuf,
+}
+
+impl SuffixArrayFile {
+    /// Read the suffix array from the start.
+    pub fn reader(&self) -> MtsvResult<BufReader<File>> {
+        Ok(BufReader::with_capacity(1 << 20, File::open(&self.path)?))
+    }
+}
+
+impl Drop for SuffixArrayFile {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!("Unable to remove temporary suffix array {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Build the suffix array of `text`, which must be `ACGTN` ending in a `$` sentinel, into a
+/// temporary file in `dir`, holding no more than about `batch_len` suffixes in memory at a time
+/// (besides any bucket of suffixes sharing their first 8 bytes which is bigger than that).
+///
+/// The suffixes are bucketed by their first 8 bytes, which puts the buckets in suffix array
+/// order. Each batch of consecutive buckets is collected in a pass over the text, each bucket is
+/// sorted by comparing its suffixes, with the buckets split between `threads` threads, and the
+/// batch is appended to the file. Every batch needs a pass over the text, and suffixes in long
+/// repeats take as long to compare as the repeats are, so this is much slower than building the
+/// suffix array in memory, but it takes little more memory than the text and one batch. The
+/// suffixes sorted so far are logged as a `Progress`.
+pub fn external_suffix_array(text: &[u8],
+                             dir: &str,
+                             batch_len: usize,
+                             threads: usize)
+                             -> MtsvResult<SuffixArrayFile> {
+    let mut counts = vec![0usize; BUCKET_BASE.pow(BUCKET_PREFIX)];
+    for_each_bucket(text, |_, bucket| counts[bucket] += 1);
+
+    let file = SuffixArrayFile {
+        path: Path::new(dir).join(format!("mtsv-{}.sa.tmp", process::id())),
+    };
+    let mut writer = BufWriter::with_capacity(1 << 20, File::create(&file.path)?);
+    let mut batch = Vec::new();
+    let mut offsets = Vec::new();
+    let mut sorted = 0;
+    let progress = Progress::new("Sorting suffixes", "suffixes", Some(text.len() as u64));
+    let mut lo = 0;
+    while lo < counts.len() {
+        // take buckets until the batch is full, but always at least one
+        let mut hi = lo + 1;
+        let mut len = counts[lo];
+        while hi < counts.len() && len + counts[hi] <= batch_len {
+            len += counts[hi];
+            hi += 1;
+        }
+        if len == 0 {
+            lo = hi;
+            continue;
+        }
+        debug!("Sorting suffixes {} to {} of {}...", sorted, sorted + len, text.len());
+
+        // place each suffix in its bucket, and then sort each bucket
+        offsets.clear();
+        offsets.extend(counts[lo..hi].iter().scan(0, |start, &count| {
+            *start += count;
+            Some(*start - count)
+        }));
+        batch.clear();
+        batch.resize(len, 0);
+        for_each_bucket(text, |i, bucket| if bucket >= lo && bucket < hi {
+            batch[offsets[bucket - lo]] = i;
+            offsets[bucket - lo] += 1;
+        });
+        sort_buckets(text, &mut batch, &counts[lo..hi], threads);
+
+        for &i in &batch {
+            writer.write_all(&(i as u64).to_le_bytes())?;
+        }
+        sorted += len;
+        progress.add(len as u64);
+        lo = hi;
+    }
+    progress.finish();
+    writer.flush()?;
+    Ok(file)
+}
+
+/// Call `f` with each suffix of `text` (from the last) and its bucket: its first
+/// `BUCKET_PREFIX` bytes as a number in base `BUCKET_BASE`, in the same order as the bytes.
+fn for_each_bucket<F: FnMut(usize, usize)>(text: &[u8], mut f: F) {
+    let top = BUCKET_BASE.pow(BUCKET_PREFIX - 1);
+    let mut bucket = 0;
+    for (i, &b) in text.iter().enumerate().rev() {
+        let rank = match b {
+            b'$' => 1,
+            b'A' => 2,
+            b'C' => 3,
+            b'G' => 4,
+            b'N' => 5,
+            b'T' => 6,
+            _ => panic!("Unexpected byte {} in an index's sequences", b),
+        };
+        bucket = rank * top + bucket / BUCKET_BASE;
+        f(i, bucket);
+    }
+}
+
+/// Sort each bucket of suffixes of `text` in `batch`, whose sizes are `counts`, splitting the
+/// buckets between `threads` threads.
+fn sort_buckets(text: &[u8], batch: &mut [usize], counts: &[usize], threads: usize) {
+    let share = batch.len().div_ceil(threads.max(1));
+    thread::scope(|scope| {
+        let mut rest = batch;
+        let mut counts = counts.iter().peekable();
+        while counts.peek().is_some() {
+            // the next few buckets, up to about a thread's share of the suffixes
+            let mut sizes = Vec::new();
+            let mut len = 0;
+            while let Some(&&count) = counts.peek() {
+                if len > 0 && len + count > share {
+                    break;
+                }
+                sizes.push(count);
+                len += count;
+                counts.next();
+            }
+            let (buckets, remaining) = rest.split_at_mut(len);
+            rest = remaining;
+
+            scope.spawn(move || {
+                let mut buckets = buckets;
+                for size in sizes {
+                    let (bucket, remaining) = buckets.split_at_mut(size);
+                    buckets = remaining;
+                    // suffixes sharing a bucket can't reach the sentinel within its prefix, so
+                    // only what follows the prefix needs comparing
+                    let skip = BUCKET_PREFIX as usize;
+                    if size > 1 {
+                        bucket.sort_unstable_by(|&a, &b| text[a + skip..].cmp(&text[b + skip..]));
+                    }
+                }
+            });
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use bio::data_structures::suffix_array::suffix_array;
+    use rand::{Rng, XorShiftRng};
+    use std::io::Read;
+    use mktemp::Temp;
+    use super::*;
+
+    fn check(mut text: Vec<u8>) {
+        text.push(b'$');
+        let expected = suffix_array(&text);
+
+        let dir = Temp::new_dir().unwrap();
+        let dir_path = dir.to_path_buf();
+        for &(batch_len, threads) in &[(text.len() / 10 + 1, 1), (100, 3), (text.len(), 2)] {
+            let file = external_suffix_array(&text, dir_path.to_str().unwrap(), batch_len, threads)
+                .unwrap();
+            let mut bytes = Vec::new();
+            file.reader().unwrap().read_to_end(&mut bytes).unwrap();
+            let sa = bytes.chunks(8)
+                .map(|b| {
+                    let mut row = [0; 8];
+                    row.copy_from_slice(b);
+                    u64::from_le_bytes(row) as usize
+                })
+                .collect::<Vec<_>>();
+            assert!(sa == expected, "{} bases in batches of {}", text.len(), batch_len);
+            drop(file);
+        }
+        assert_eq!(fs::read_dir(&dir_path).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn matches_single_threaded() {
+        let mut rng = XorShiftRng::new_unseeded();
+        for &len in &[0, 1, 2, 7, 8, 9, 100, 1000, 20_000] {
+            check((0..len).map(|_| *rng.choose(b"ACGTN").unwrap()).collect());
+        }
+    }
+
+    #[test]
+    fn matches_single_threaded_repeats() {
+        let mut rng = XorShiftRng::new_unseeded();
+        let random = |rng: &mut XorShiftRng, len| {
+            (0..len).map(|_| *rng.choose(b"ACGT").unwrap()).collect::<Vec<_>>()
+        };
+
+        check(vec![b'A'; 5000]);
+        check([random(&mut rng, 300), vec![b'N'; 2000], random(&mut rng, 300)].concat());
+        check(b"ACGTTGCAAGCTTCAGG".repeat(300));
+
+        // strains sharing most of their sequence
+        let genome = random(&mut rng, 3000);
+        let mut text = Vec::new();
+        for strain in 0..5 {
+            let mut copy = genome.clone();
+            copy[strain * 500] = b'N';
+            text.extend(copy);
+        }
+        check(text);
+    }
+}
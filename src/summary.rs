@@ -0,0 +1,239 @@
+//! Summaries of binner results, such as how much of each taxid's hit count is down to the size of
+//! its reference sequences.
+
+use error::*;
+use index::TaxId;
+use io::{open_maybe_gz, FindingsIter};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+/// How much reference sequence an index holds for a taxid.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct TaxidComposition {
+    /// Number of reference sequences.
+    pub sequences: usize,
+    /// Total length of the reference sequences.
+    pub bases: usize,
+}
+
+/// The composition of an index, by taxid.
+pub type Manifest = BTreeMap<TaxId, TaxidComposition>;
+
+/// Write a manifest as a TSV with a `#taxid sequences bases` header line.
+pub fn write_manifest<W: Write>(manifest: &Manifest, writer: &mut W) -> MtsvResult<()> {
+    writeln!(writer, "#taxid\tsequences\tbases")?;
+    for (tax_id, composition) in manifest {
+        writeln!(writer, "{}\t{}\t{}", tax_id.0, composition.sequences, composition.bases)?;
+    }
+    Ok(())
+}
+
+/// Load a manifest written by `write_manifest`. Blank lines and lines starting with '#' are
+/// skipped, and gzipped files are decompressed transparently. Entries for the same taxid (e.g.
+/// from the manifests of several index chunks) are added together.
+pub fn load_manifest<P: AsRef<Path>>(p: P) -> MtsvResult<Manifest> {
+    let path = p.as_ref();
+    let mut manifest = Manifest::new();
+
+    for (i, line) in open_maybe_gz(path)?.lines().enumerate() {
+        let line = line?;
+        let entry = line.trim();
+        if entry.is_empty() || entry.starts_with('#') {
+            continue;
+        }
+
+        let invalid = || {
+            MtsvError::InvalidListEntry(path.display().to_string(), i + 1, entry.to_string())
+        };
+
+        let columns = entry.split_whitespace().collect::<Vec<_>>();
+        if columns.len() != 3 {
+            return Err(invalid());
+        }
+        let (tax_id, sequences, bases) = match (columns[0].parse::<TaxId>(),
+                                                columns[1].parse::<usize>(),
+                                                columns[2].parse::<usize>()) {
+            (Ok(t), Ok(s), Ok(b)) => (t, s, b),
+            _ => return Err(invalid()),
+        };
+
+        let composition = manifest.entry(tax_id).or_default();
+        composition.sequences += sequences;
+        composition.bases += bases;
+    }
+
+    Ok(manifest)
+}
+
+/// A taxid's hits, normalized by the amount of reference sequence it has in the index.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TaxidBias {
+    /// The taxid.
+    pub tax_id: TaxId,
+    /// Number of reads which hit the taxid.
+    pub reads: usize,
+    /// The taxid's reference sequences.
+    pub composition: TaxidComposition,
+    /// Reads per megabase of reference sequence.
+    pub reads_per_mb: f64,
+    /// Whether the taxid has more reads than the median taxid, but no more reads per megabase,
+    /// i.e. its hit count is explained largely by the size of its references.
+    pub size_driven: bool,
+}
+
+/// Count the reads hitting each taxid in a results file, and normalize the counts by the
+/// taxids' reference sizes from `manifest`.
+///
+/// Returns a row for each taxid with hits and reference sequence, in decreasing order of reads,
+/// along with the taxids which had hits but aren't in the manifest.
+pub fn reference_bias<R: BufRead>(findings: R,
+                                  manifest: &Manifest)
+                                  -> MtsvResult<(Vec<TaxidBias>, BTreeSet<TaxId>)> {
+    let mut reads: BTreeMap<TaxId, usize> = BTreeMap::new();
+    for finding in FindingsIter::new(findings)? {
+        // a read may hit the same taxid on both strands
+        for tax_id in finding?.hits.iter().map(|h| h.tax_id).collect::<BTreeSet<_>>() {
+            *reads.entry(tax_id).or_insert(0) += 1;
+        }
+    }
+
+    let mut missing = BTreeSet::new();
+    let mut rows = Vec::new();
+    for (tax_id, reads) in reads {
+        match manifest.get(&tax_id) {
+            Some(composition) if composition.bases > 0 => {
+                rows.push(TaxidBias {
+                    tax_id,
+                    reads,
+                    composition: *composition,
+                    reads_per_mb: reads as f64 * 1_000_000.0 / composition.bases as f64,
+                    size_driven: false,
+                })
+            },
+            _ => {
+                missing.insert(tax_id);
+            },
+        }
+    }
+
+    let median_reads = median(rows.iter().map(|r| r.reads as f64).collect());
+    let median_rate = median(rows.iter().map(|r| r.reads_per_mb).collect());
+    for row in &mut rows {
+        row.size_driven = row.reads as f64 > median_reads && row.reads_per_mb <= median_rate;
+    }
+
+    rows.sort_by(|a, b| b.reads.cmp(&a.reads).then(a.tax_id.cmp(&b.tax_id)));
+    Ok((rows, missing))
+}
+
+/// The median of some values, averaging the middle two if there's an even number. Zero if there
+/// are no values.
+fn median(mut values: Vec<f64>) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+    let mid = values.len() / 2;
+    if values.len() % 2 == 1 {
+        values[mid]
+    } else {
+        (values[mid - 1] + values[mid]) / 2.0
+    }
+}
+
+/// Write a reference bias report as a TSV, one taxid per line.
+pub fn write_bias_report<W: Write>(rows: &[TaxidBias], writer: &mut W) -> MtsvResult<()> {
+    writeln!(writer, "taxid\treads\tsequences\tbases\treads_per_mb\tsize_driven")?;
+    for row in rows {
+        writeln!(writer,
+                 "{}\t{}\t{}\t{}\t{:.4}\t{}",
+                 row.tax_id.0,
+                 row.reads,
+                 row.composition.sequences,
+                 row.composition.bases,
+                 row.reads_per_mb,
+                 if row.size_driven { "yes" } else { "no" })?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use mktemp::Temp;
+    use std::fs;
+    use std::io::Cursor;
+    use std::str;
+    use super::*;
+
+    fn composition(sequences: usize, bases: usize) -> TaxidComposition {
+        TaxidComposition { sequences, bases }
+    }
+
+    #[test]
+    fn manifest_roundtrip() {
+        let mut manifest = Manifest::new();
+        manifest.insert(TaxId(1), composition(500, 2_000_000_000));
+        manifest.insert(TaxId(2), composition(1, 5_000_000));
+
+        let dir = Temp::new_dir().unwrap();
+        let path = dir.to_path_buf().join("manifest.tsv");
+        let mut buf = Vec::new();
+        write_manifest(&manifest, &mut buf).unwrap();
+        // a second chunk of the same database adds to the first
+        buf.extend_from_slice(b"\n2\t2\t1000000\n");
+        fs::write(&path, &buf).unwrap();
+
+        manifest.insert(TaxId(2), composition(3, 6_000_000));
+        assert_eq!(load_manifest(&path).unwrap(), manifest);
+
+        fs::write(&path, b"1\t2\n").unwrap();
+        assert!(load_manifest(&path).is_err());
+    }
+
+    #[test]
+    fn normalized_hits() {
+        let mut manifest = Manifest::new();
+        // a taxid with many genomes, one with a single genome, and one in between
+        manifest.insert(TaxId(1), composition(500, 2_000_000_000));
+        manifest.insert(TaxId(2), composition(1, 5_000_000));
+        manifest.insert(TaxId(3), composition(10, 50_000_000));
+
+        let findings = b"#sample=s1\n\
+                         r1:1=0,2=1\n\
+                         r2:1=0\n\
+                         r3:1=2,1=0\n\
+                         r4:1=1,3=0\n\
+                         r5:2=0\n\
+                         r6:9=0\n";
+
+        let (rows, missing) = reference_bias(Cursor::new(&findings[..]), &manifest).unwrap();
+
+        let values = rows.iter()
+            .map(|r| (r.tax_id, r.reads, r.reads_per_mb, r.size_driven))
+            .collect::<Vec<_>>();
+        // median reads is 2 and median rate is 0.02 per Mb, so only taxid 1 is size driven
+        assert_eq!(values,
+                   vec![(TaxId(1), 4, 0.002, true),
+                        (TaxId(2), 2, 0.4, false),
+                        (TaxId(3), 1, 0.02, false)]);
+        assert_eq!(missing, vec![TaxId(9)].into_iter().collect());
+
+        let mut report = Vec::new();
+        write_bias_report(&rows, &mut report).unwrap();
+        assert_eq!(str::from_utf8(&report).unwrap(),
+                   "taxid\treads\tsequences\tbases\treads_per_mb\tsize_driven\n\
+                    1\t4\t500\t2000000000\t0.0020\tyes\n\
+                    2\t2\t1\t5000000\t0.4000\tno\n\
+                    3\t1\t10\t50000000\t0.0200\tno\n");
+    }
+
+    #[test]
+    fn median_values() {
+        assert_eq!(median(vec![]), 0.0);
+        assert_eq!(median(vec![3.0, 1.0, 2.0]), 2.0);
+        assert_eq!(median(vec![4.0, 1.0, 2.0, 3.0]), 2.5);
+    }
+}
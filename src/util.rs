@@ -3,8 +3,13 @@
 use chrono::Local;
 use env_logger::LogBuilder;
 use error::*;
-use index::{Gi, TaxId};
+use index::{BinInfo, Gi, TaxId};
 use log::{LogLevelFilter, LogRecord};
+use serde::{Serialize, Deserialize};
+use std::hash::Hasher;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 
 /// Initialize the program-wide logger to write to stdout with timestamps.
 pub fn init_logging(level: LogLevelFilter) {
@@ -22,8 +27,168 @@ pub fn init_logging(level: LogLevelFilter) {
     let _ = builder.init();
 }
 
+/// Seconds between the log lines of each `Progress`, or 0 for none.
+static PROGRESS_INTERVAL_SECS: AtomicU64 = AtomicU64::new(60);
+
+/// Set how often every `Progress` in the program logs, every `secs` seconds, or never if 0.
+/// Progress is logged every minute otherwise.
+pub fn set_progress_interval(secs: u64) {
+    PROGRESS_INTERVAL_SECS.store(secs, Ordering::Relaxed);
+}
+
+/// Logs how far a long-running loop has got, at most once per `set_progress_interval`, with the
+/// time it's taken so far and, when the loop's length is known, about how long it has left.
+/// Threads working through the same loop share one, each adding what it's done every so often
+/// rather than every iteration, since adding checks the time.
+pub struct Progress {
+    what: String,
+    unit: &'static str,
+    total: Option<u64>,
+    started: Instant,
+    done: AtomicU64,
+    /// Milliseconds after `started` the progress was last logged
+    logged: AtomicU64,
+}
+
+impl Progress {
+    /// Start logging the progress of `what`, counted in `unit`s, out of `total` if it's known.
+    pub fn new(what: &str, unit: &'static str, total: Option<u64>) -> Self {
+        Progress {
+            what: what.to_string(),
+            unit,
+            total,
+            started: Instant::now(),
+            done: AtomicU64::new(0),
+            logged: AtomicU64::new(0),
+        }
+    }
+
+    /// Count another `n` units done, logging the progress if it hasn't been logged for an
+    /// interval.
+    pub fn add(&self, n: u64) {
+        let done = self.done.fetch_add(n, Ordering::Relaxed) + n;
+        let interval = PROGRESS_INTERVAL_SECS.load(Ordering::Relaxed) * 1000;
+        if interval == 0 {
+            return;
+        }
+        let elapsed = self.started.elapsed();
+        let now = elapsed.as_millis() as u64;
+        let logged = self.logged.load(Ordering::Relaxed);
+        // only one of the threads adding at once logs
+        if now - logged >= interval &&
+           self.logged.compare_exchange(logged, now, Ordering::Relaxed, Ordering::Relaxed).is_ok() {
+            info!("{}", self.message(done, elapsed));
+        }
+    }
+
+    /// Log how much was done in how long, once the loop has finished, unless progress isn't
+    /// logged.
+    pub fn finish(&self) {
+        if PROGRESS_INTERVAL_SECS.load(Ordering::Relaxed) > 0 {
+            info!("{}: finished {} {} in {}",
+                  self.what,
+                  self.done(),
+                  self.unit,
+                  format_duration(self.started.elapsed()));
+        }
+    }
+
+    /// The number of units done so far.
+    pub fn done(&self) -> u64 {
+        self.done.load(Ordering::Relaxed)
+    }
+
+    /// The line logged when `done` units are done after `elapsed`.
+    fn message(&self, done: u64, elapsed: Duration) -> String {
+        match self.total {
+            Some(total) if done > 0 && total > 0 => {
+                let fraction = done.min(total) as f64 / total as f64;
+                format!("{}: {} of {} {} ({:.1}%), {} elapsed, about {} left",
+                        self.what,
+                        done,
+                        total,
+                        self.unit,
+                        fraction * 100.0,
+                        format_duration(elapsed),
+                        format_duration(elapsed.mul_f64((1.0 - fraction) / fraction)))
+            },
+            _ => {
+                format!("{}: {} {}, {} elapsed",
+                        self.what,
+                        done,
+                        self.unit,
+                        format_duration(elapsed))
+            },
+        }
+    }
+}
+
+/// `d` as hours, minutes and seconds, such as `1:02:03`.
+fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+/// 64-bit FNV-1a hasher, used where a hash has to be stable across builds and platforms (unlike
+/// the std `DefaultHasher`).
+#[derive(Clone, Copy, Debug)]
+pub struct Fnv64(u64);
+
+impl Default for Fnv64 {
+    fn default() -> Self {
+        Fnv64(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for Fnv64 {
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= u64::from(*b);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// The layout of reference sequence headers in a FASTA database.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum HeaderFormat {
+    /// `GI-TAXID`, the default format expected by mtsv.
+    GiTaxId,
+    /// A bare `TAXID`, with GIs assigned sequentially when the database is parsed.
+    TaxId,
+    /// An `ACCESSION` such as `NZ_CP012345.1`, whose taxid is looked up in an accession map
+    /// (see `io::AccessionMap`). GIs are assigned sequentially as for `TaxId`, and the accessions
+    /// are kept in the index.
+    Accession,
+}
+
+impl FromStr for HeaderFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gi-taxid" => Ok(HeaderFormat::GiTaxId),
+            "taxid" => Ok(HeaderFormat::TaxId),
+            "accession" => Ok(HeaderFormat::Accession),
+            _ => {
+                Err(format!("Unknown header format \"{}\" (expected gi-taxid, taxid or accession)",
+                            s))
+            },
+        }
+    }
+}
+
 /// Parse a reference sequence's read header in the format expected by mtsv: `ACCESSION-TAXID`.
 pub fn parse_read_header(h: &str) -> MtsvResult<(Gi, TaxId)> {
+    // a lone integer is most likely a taxid-only header, which needs to be asked for explicitly
+    if !h.contains('-') && h.parse::<TaxId>().is_ok() {
+        return Err(MtsvError::BareTaxIdHeader(String::from(h)));
+    }
+
     let mut tokens = h.split('-');
 
     let gi = match tokens.next() {
@@ -54,18 +219,168 @@ pub fn parse_read_header(h: &str) -> MtsvResult<(Gi, TaxId)> {
     }
 }
 
+/// Parse a taxid-only reference sequence header.
+pub fn parse_taxid_header(h: &str) -> MtsvResult<TaxId> {
+    if h.is_empty() {
+        return Err(MtsvError::MissingHeader);
+    }
+
+    h.parse::<TaxId>().map_err(|_| MtsvError::InvalidInteger(h.to_owned()))
+}
+
+/// The FASTA ID and description `mtsv-reference` writes for a reference sequence extracted from
+/// an index: `gi|GI|taxid|TAXID start=START len=LEN`, where START is the offset of its first base
+/// in the index's concatenated sequences, followed by ` accession=ACCESSION` if the index has
+/// one, and then ` description=DESCRIPTION`, the rest of the line, if the index kept the
+/// reference's original description. If the GI was assigned by `mtsv-build` (see
+/// `MGIndex::synthetic_gis`) the ID starts `synthetic_gi|` instead, so it isn't mistaken for an
+/// NCBI GI. `parse_reference_header` reads them back.
+pub fn reference_header(bin: &BinInfo,
+                        synthetic_gi: bool,
+                        accession: Option<&str>,
+                        description: Option<&str>)
+                        -> (String, String) {
+    let label = if synthetic_gi { "synthetic_gi" } else { "gi" };
+    let id = format!("{}|{}|taxid|{}", label, bin.gi.0, bin.tax_id.0);
+    let mut desc = format!("start={} len={}", bin.start, bin.end - bin.start);
+    if let Some(accession) = accession {
+        desc.push_str(" accession=");
+        desc.push_str(accession);
+    }
+    if let Some(description) = description {
+        desc.push_str(" description=");
+        desc.push_str(description);
+    }
+    (id, desc)
+}
+
+/// Parse the FASTA ID and description of an extracted reference sequence (see
+/// `reference_header`) back into its bin and accession. Any original description is skipped.
+pub fn parse_reference_header(id: &str,
+                              desc: Option<&str>)
+                              -> MtsvResult<(BinInfo, Option<String>)> {
+    let invalid = || MtsvError::InvalidHeader(format!("{} {}", id, desc.unwrap_or("")));
+    // the original description is free text, so it's the rest of the line
+    let pairs = desc.map(|d| d.split_once(" description=").map_or(d, |(pairs, _)| pairs));
+    let integer = |s: &str| s.parse::<u64>().map_err(|_| MtsvError::InvalidInteger(s.to_owned()));
+
+    let fields = id.split('|').collect::<Vec<_>>();
+    let (gi, tax_id) = match fields[..] {
+        ["gi", gi, "taxid", tax_id] | ["synthetic_gi", gi, "taxid", tax_id] => {
+            (gi.parse::<Gi>().map_err(|_| MtsvError::InvalidInteger(gi.to_owned()))?,
+             TaxId(integer(tax_id)?))
+        },
+        _ => return Err(invalid()),
+    };
+
+    let (mut start, mut len, mut accession) = (None, None, None);
+    for field in pairs.unwrap_or("").split_whitespace() {
+        match field.split_once('=') {
+            Some(("start", value)) => start = Some(integer(value)? as usize),
+            Some(("len", value)) => len = Some(integer(value)? as usize),
+            Some(("accession", value)) => accession = Some(value.to_string()),
+            _ => return Err(invalid()),
+        }
+    }
+
+    match (start, len) {
+        (Some(start), Some(len)) => {
+            Ok((BinInfo { gi, tax_id, start, end: start + len }, accession))
+        },
+        _ => Err(invalid()),
+    }
+}
+
 #[cfg(test)]
 mod test {
+    use error::MtsvError;
     use index::{Gi, TaxId};
 
     use log::LogLevelFilter;
-    use super::{init_logging, parse_read_header};
+    use std::hash::Hasher;
+    use super::{init_logging, parse_read_header, parse_reference_header, parse_taxid_header,
+                reference_header, Fnv64, Progress};
+    use index::BinInfo;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn progress() {
+        // threads share the count
+        let progress = Progress::new("Building occurrence table", "rows", Some(4000));
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| (0..100).for_each(|_| progress.add(10)));
+            }
+        });
+        assert_eq!(progress.done(), 4000);
+
+        let elapsed = Duration::from_secs(3725);
+        assert_eq!(progress.message(1000, elapsed),
+                   "Building occurrence table: 1000 of 4000 rows (25.0%), 1:02:05 elapsed, about \
+                    3:06:15 left");
+        assert_eq!(progress.message(4000, elapsed),
+                   "Building occurrence table: 4000 of 4000 rows (100.0%), 1:02:05 elapsed, about \
+                    0:00:00 left");
+
+        // there's no estimate without a total, or before anything's done
+        let reading = Progress::new("Reading references", "bases", None);
+        assert_eq!(reading.message(123456, Duration::from_secs(59)),
+                   "Reading references: 123456 bases, 0:00:59 elapsed");
+        assert_eq!(progress.message(0, Duration::from_secs(1)),
+                   "Building occurrence table: 0 rows, 0:00:01 elapsed");
+    }
 
     #[test]
     fn lines_for_the_line_throne() {
         init_logging(LogLevelFilter::Debug);
     }
 
+    #[test]
+    fn fnv_known_values() {
+        let hash = |bytes: &[u8]| {
+            let mut h = Fnv64::default();
+            h.write(bytes);
+            h.finish()
+        };
+
+        assert_eq!(hash(b""), 0xcbf29ce484222325);
+        assert_eq!(hash(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(hash(b"foobar"), 0x85944171f73967e8);
+    }
+
+    #[test]
+    fn reference_headers() {
+        let bin = BinInfo { gi: Gi(12345), tax_id: TaxId(5_000_000_000), start: 100, end: 350 };
+        let (id, desc) = reference_header(&bin, false, None, None);
+        assert_eq!((id.as_str(), desc.as_str()),
+                   ("gi|12345|taxid|5000000000", "start=100 len=250"));
+        assert_eq!(parse_reference_header(&id, Some(&desc)).unwrap(), (bin, None));
+
+        // GIs assigned at build time are labelled as such
+        let (id, desc) = reference_header(&bin, true, None, None);
+        assert_eq!((id.as_str(), desc.as_str()),
+                   ("synthetic_gi|12345|taxid|5000000000", "start=100 len=250"));
+        assert_eq!(parse_reference_header(&id, Some(&desc)).unwrap(), (bin, None));
+
+        let (id, desc) = reference_header(&bin, false, Some("NZ_CP000001.1"), None);
+        assert_eq!(parse_reference_header(&id, Some(&desc)).unwrap(),
+                   (bin, Some(String::from("NZ_CP000001.1"))));
+
+        let (id, desc) = reference_header(&bin, false, None, Some("E. coli len=3 description=x"));
+        assert_eq!(desc, "start=100 len=250 description=E. coli len=3 description=x");
+        assert_eq!(parse_reference_header(&id, Some(&desc)).unwrap(), (bin, None));
+
+        for &(id, desc) in &[("12345-908", Some("start=0 len=1")),
+                             ("gi|12345|taxid|908", None),
+                             ("gi|12345|taxid|908", Some("start=0")),
+                             ("gi|12345|taxid|908", Some("start=0 len=1 strain=K12")),
+                             ("gi|x|taxid|908", Some("start=0 len=1")),
+                             ("fake_gi|12345|taxid|908", Some("start=0 len=1"))] {
+            assert!(parse_reference_header(id, desc).is_err(), "{} {:?}", id, desc);
+        }
+    }
+
     #[test]
     fn success() {
         let (found_gi, found_tax) = parse_read_header("12345-908").unwrap();
@@ -115,4 +430,19 @@ mod test {
     fn fail_non_numeric_taxid() {
         let _ = parse_read_header("123-abc").unwrap();
     }
+
+    #[test]
+    fn bare_taxid_suggests_header_format() {
+        match parse_read_header("562") {
+            Err(MtsvError::BareTaxIdHeader(h)) => assert_eq!(h, "562"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn taxid_header() {
+        assert_eq!(parse_taxid_header("562").unwrap(), TaxId(562));
+        assert!(parse_taxid_header("").is_err());
+        assert!(parse_taxid_header("12-562").is_err());
+    }
 }
@@ -0,0 +1,267 @@
+//! One-shot runs from a reference FASTA (or an index) to collapsed results, as `mtsv-run` does
+//! them, with the same library functions as `mtsv-build`, `mtsv-binner` and `mtsv-collapse`.
+//!
+//! A run's output directory holds:
+//!
+//! * `reference.index`: the index, if it was built from references.
+//! * `results/`: each reads file's results and run summary, and `manifest.json` listing them
+//!   (see `binner::bin_inputs_to_dir`).
+//! * `collapsed.results`: every reads file's results collapsed into one file.
+//! * `run.json`: the `RunRecord`, with the parameters of every stage and whether the run
+//!   finished.
+
+use binner::{bin_inputs_to_dir, BinnerConfig, ReadFormat, ResultsManifest};
+use builder::{build_and_write_index_from_files, shard_manifest_path, BuildOptions,
+              ShardManifest};
+use chrono::Local;
+use collapse::collapse_edit_files;
+use error::*;
+use index::QueryParams;
+use io::FindingsVersion;
+use serde::{Serialize, Deserialize};
+use serde_json;
+use std::fs;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// Where a run's index comes from.
+#[derive(Clone, Debug, PartialEq)]
+pub enum IndexSource {
+    /// Build an index from the references in this FASTA file.
+    Build(String, BuildOptions),
+    /// Use an index which was already built.
+    Prebuilt(String),
+}
+
+/// The stages of a run, in order.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stage {
+    /// Building the index.
+    Build,
+    /// Binning the reads files.
+    Bin,
+    /// Collapsing the reads files' results.
+    Collapse,
+}
+
+/// How far a run got.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RunStatus {
+    /// The run is going, or was killed without a chance to record anything else.
+    Running,
+    /// Every stage finished.
+    Complete,
+    /// A stage failed. Its outputs, and those of later stages, are missing or incomplete.
+    Failed,
+}
+
+/// The record of a run, written as JSON to `run.json` in its output directory when it starts,
+/// and again when it finishes or fails.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RunRecord {
+    /// The version of mtsv which did the run.
+    pub mtsv: String,
+    /// How far the run got.
+    pub status: RunStatus,
+    /// The stage which failed, if one did.
+    pub failed_stage: Option<Stage>,
+    /// Why that stage failed.
+    pub error: Option<String>,
+    /// Local time the run started, in RFC 3339 format.
+    pub started: String,
+    /// Local time the run finished or failed, in RFC 3339 format.
+    pub finished: Option<String>,
+    /// The reference FASTA the index was built from, if it was built by the run.
+    pub reference: Option<String>,
+    /// The options the index was built with, if it was built by the run.
+    pub build: Option<BuildOptions>,
+    /// The index the reads were binned against, or if it was built in shards (see
+    /// `BuildOptions::shard_size`), the path the shards are named after.
+    pub index: String,
+    /// The reads files, in the order they were given.
+    pub inputs: Vec<String>,
+    /// How each read was queried.
+    pub params: QueryParams,
+    /// The run's random seed, to reproduce it.
+    pub seed: u64,
+    /// The sample name recorded in the results, if any.
+    pub sample: Option<String>,
+    /// The results manifest, relative to the output directory.
+    pub results_manifest: String,
+    /// The collapsed results, relative to the output directory.
+    pub collapsed: String,
+}
+
+impl RunRecord {
+    /// Replace `run.json` in `dir`, by writing to a temporary file and renaming it so that
+    /// readers never see a partial record.
+    fn write(&self, dir: &Path) -> MtsvResult<()> {
+        let tmp_path = dir.join("run.json.tmp");
+        {
+            let mut writer = BufWriter::new(File::create(&tmp_path)?);
+            serde_json::to_writer_pretty(&mut writer, self)?;
+            writer.write_all(b"\n")?;
+            writer.flush()?;
+        }
+        fs::rename(&tmp_path, dir.join("run.json"))?;
+        Ok(())
+    }
+}
+
+/// Build or load the index, bin each reads file against it, and collapse their results, writing
+/// everything to `output_dir` (see the module documentation). Returns the run's record.
+///
+/// If a stage fails, its partial outputs are removed, except for the binning stage's results
+/// directory, which is left for inspection. Either way `run.json` records the stage which failed
+/// and why, and the error is returned.
+pub fn run_workflow(index: &IndexSource,
+                    input_paths: &[&str],
+                    format: ReadFormat,
+                    output_dir: &str,
+                    num_threads: usize,
+                    params: &QueryParams,
+                    config: &BinnerConfig)
+                    -> MtsvResult<RunRecord> {
+    // collapsing needs edit distances, so check before spending time on the other stages
+    if config.findings_version == FindingsVersion::Legacy || config.no_combined {
+        return Err(MtsvError::InvalidParameter(String::from("collapsing the results needs \
+                                                             combined results with edit \
+                                                             distances")));
+    }
+
+    let dir = Path::new(output_dir);
+    fs::create_dir_all(dir)?;
+    let dir_path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+
+    let index_path = index_path(index, dir);
+    let (reference, build) = match *index {
+        IndexSource::Build(ref reference, ref options) => {
+            (Some(reference.clone()), Some(options.clone()))
+        },
+        IndexSource::Prebuilt(_) => (None, None),
+    };
+    let mut record = RunRecord {
+        mtsv: env!("CARGO_PKG_VERSION").to_string(),
+        status: RunStatus::Running,
+        failed_stage: None,
+        error: None,
+        started: Local::now().to_rfc3339(),
+        finished: None,
+        reference,
+        build,
+        index: index_path.clone(),
+        inputs: input_paths.iter().map(|p| p.to_string()).collect(),
+        params: *params,
+        seed: config.run.seed,
+        sample: config.sample_name.clone(),
+        results_manifest: String::from("results/manifest.json"),
+        collapsed: String::from("collapsed.results"),
+    };
+    record.write(dir)?;
+
+    // don't leave collapsed results from an earlier run looking like this one's
+    let collapsed_path = dir_path(&record.collapsed);
+    if Path::new(&collapsed_path).exists() {
+        fs::remove_file(&collapsed_path)?;
+    }
+    let result = run_stages(index, input_paths, format, dir, num_threads, params, config);
+    record.finished = Some(Local::now().to_rfc3339());
+    match result {
+        Ok(()) => {
+            record.status = RunStatus::Complete;
+            record.write(dir)?;
+            info!("Run complete, see {}", dir_path("run.json"));
+            Ok(record)
+        },
+        Err((stage, why)) => {
+            // remove whatever the failed stage left half-written
+            let partial = match stage {
+                Stage::Build => Some(&index_path),
+                Stage::Bin => None,
+                Stage::Collapse => Some(&collapsed_path),
+            };
+            if let Some(path) = partial {
+                if Path::new(path).exists() {
+                    if let Err(e) = fs::remove_file(path) {
+                        warn!("Unable to remove partial output {}: {}", path, e);
+                    }
+                }
+            }
+            record.status = RunStatus::Failed;
+            record.failed_stage = Some(stage);
+            record.error = Some(why.to_string());
+            record.write(dir)?;
+            Err(why)
+        },
+    }
+}
+
+/// The index a run bins against: the one it builds in `dir`, or the one it was given.
+fn index_path(index: &IndexSource, dir: &Path) -> String {
+    match *index {
+        IndexSource::Build(..) => dir.join("reference.index").to_string_lossy().into_owned(),
+        IndexSource::Prebuilt(ref path) => path.clone(),
+    }
+}
+
+/// Run each stage in turn, returning the first one which fails and why.
+fn run_stages(index: &IndexSource,
+              input_paths: &[&str],
+              format: ReadFormat,
+              dir: &Path,
+              num_threads: usize,
+              params: &QueryParams,
+              config: &BinnerConfig)
+              -> Result<(), (Stage, MtsvError)> {
+    let index_path = index_path(index, dir);
+    let mut shards = None;
+    if let IndexSource::Build(ref reference, ref options) = *index {
+        info!("Building index from {}...", reference);
+        build_and_write_index_from_files(&[reference], &index_path, options)
+            .map_err(|why| (Stage::Build, why))?;
+        if options.shard_size.is_some() {
+            shards = Some(ShardManifest::from_file(&shard_manifest_path(&index_path))
+                .map_err(|why| (Stage::Build, why))?);
+        }
+    }
+    let index_paths = match shards {
+        Some(ref manifest) => manifest.index_paths(),
+        None => vec![index_path.as_str()],
+    };
+
+    info!("Binning {} reads files...", input_paths.len());
+    let results_dir = dir.join("results");
+    bin_inputs_to_dir(input_paths,
+                      format,
+                      &index_paths,
+                      &results_dir.to_string_lossy(),
+                      num_threads,
+                      params,
+                      config)
+        .map_err(|why| (Stage::Bin, why))?;
+
+    info!("Collapsing results...");
+    collapse_results(&results_dir, &dir.join("collapsed.results"), config)
+        .map_err(|why| (Stage::Collapse, why))
+}
+
+/// Collapse the results listed in the manifest in `results_dir` into `output`.
+fn collapse_results(results_dir: &Path, output: &Path, config: &BinnerConfig) -> MtsvResult<()> {
+    let manifest = File::open(results_dir.join("manifest.json"))?;
+    let manifest: ResultsManifest = serde_json::from_reader(BufReader::new(manifest))?;
+    let mut files = Vec::new();
+    for entry in &manifest.inputs {
+        files.push(BufReader::new(File::open(results_dir.join(&entry.results))?));
+    }
+
+    let mut writer = BufWriter::new(File::create(output)?);
+    collapse_edit_files(&mut files, &mut writer, false, config.max_line_taxids)?;
+    let file = writer.into_inner().map_err(|e| MtsvError::from(e.into_error()))?;
+    if config.fsync_on_close {
+        file.sync_all()?;
+    }
+    Ok(())
+}
@@ -1,6 +1,8 @@
 //! (Mostly) safe bindings to Mengyao Zhao's SIMD implementation of Smith-Waterman.
 //!
-//! Currently limited to processing DNA5 sequences.
+//! Processes DNA5 sequences (see `Profile::new`), DNA with IUPAC ambiguity codes (see
+//! `Profile::iupac`), or protein sequences over the 20 amino acids and `X` (see
+//! `Profile::protein`).
 
 #![warn(missing_docs)]
 
@@ -15,11 +17,79 @@ pub const IDENT_W_PENALTY_NO_N_MATCH: [i8; 25] =
      -1, -1, -1, 1, -1,
      -1, -1, -1, -1, 1];
 
+/// The IUPAC nucleotide codes, in the order of the rows and columns of an IUPAC scoring matrix:
+/// DNA5 in its usual order, then the ambiguity codes. Anything else is scored as `N`.
+pub const IUPAC_BASES: &[u8; 15] = b"ACGTNRYSWKMBDHV";
+
+/// Matrix scoring 1 for IUPAC codes which share a base, such as `R` (`A` or `G`) and `A`, and -1
+/// for anything else, in the order of `IUPAC_BASES`. `N` only scores 1 against itself, as in
+/// `IDENT_W_PENALTY_NO_N_MATCH`.
+pub const IUPAC_IDENT_W_PENALTY: [i8; 225] = iupac_matrix();
+
+/// The bases each of `IUPAC_BASES` stands for, a bit for each of `ACGT`.
+const IUPAC_BITS: [u8; 15] = [1, 2, 4, 8, 15, 5, 10, 6, 9, 12, 3, 14, 13, 11, 7];
+
+/// The index of each byte's code in `IUPAC_BASES`, that of `N` for anything else.
+const IUPAC_CODES: [i8; 256] = codes(IUPAC_BASES, 4);
+
+/// The residues of protein sequences, in the order of the rows and columns of a protein scoring
+/// matrix. Anything else is scored as `X`.
+pub const PROTEIN_RESIDUES: &[u8; 21] = b"ARNDCEQGHILKMFPSTWYVX";
+
+/// Identity matrix for matching protein residues, in the order of `PROTEIN_RESIDUES`.
+pub const PROTEIN_IDENT_W_PENALTY: [i8; 441] = identity_matrix();
+
+/// The index of each byte's residue in `PROTEIN_RESIDUES`, that of `X` for anything else.
+const PROTEIN_CODES: [i8; 256] = codes(PROTEIN_RESIDUES, PROTEIN_RESIDUES.len() - 1);
+
+/// A matrix scoring 1 for identical protein residues and -1 for anything else.
+const fn identity_matrix<const N: usize>() -> [i8; N] {
+    let size = PROTEIN_RESIDUES.len();
+    let mut matrix = [-1; N];
+    let mut i = 0;
+    while i < size {
+        matrix[i * size + i] = 1;
+        i += 1;
+    }
+    matrix
+}
+
+/// Build `IUPAC_IDENT_W_PENALTY`.
+const fn iupac_matrix() -> [i8; 225] {
+    let size = IUPAC_BASES.len();
+    let mut matrix = [-1; 225];
+    let mut i = 0;
+    while i < size {
+        let mut j = 0;
+        while j < size {
+            let n = IUPAC_BITS[i] == 15 || IUPAC_BITS[j] == 15;
+            if (n && i == j) || (!n && IUPAC_BITS[i] & IUPAC_BITS[j] != 0) {
+                matrix[i * size + j] = 1;
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+    matrix
+}
+
+/// The index of each byte in `residues`, `other` for anything not in it.
+const fn codes(residues: &[u8], other: usize) -> [i8; 256] {
+    let mut codes = [other as i8; 256];
+    let mut i = 0;
+    while i < residues.len() {
+        codes[residues[i] as usize] = i as i8;
+        i += 1;
+    }
+    codes
+}
+
 
 /// Query profile. Can be reused across alignments if aligning one sequence against many others.
 pub struct Profile<'read> {
     sequence: &'read [u8],
     _sequence_numeric: Vec<i8>,
+    to_numeric: fn(u8) -> i8,
     raw_profile: *const RawProfile,
 }
 
@@ -34,14 +104,36 @@ impl<'read> Drop for Profile<'read> {
 impl<'read> Profile<'read> {
     /// Create a new query profile for the given DNA5 read and scoring matrix.
     pub fn new(read: &'read [u8], matrix: &[i8; 25]) -> Profile<'read> {
+        Self::with_matrix(read, matrix, 5, dna_to_numeric)
+    }
+
+    /// Create a new query profile for the given DNA read, which may have IUPAC ambiguity codes,
+    /// and scoring matrix, whose rows and columns are in the order of `IUPAC_BASES`.
+    pub fn iupac(read: &'read [u8], matrix: &[i8; 225]) -> Profile<'read> {
+        Self::with_matrix(read, matrix, IUPAC_BASES.len() as i32, iupac_to_numeric)
+    }
+
+    /// Create a new query profile for the given protein read and scoring matrix, whose rows and
+    /// columns are in the order of `PROTEIN_RESIDUES`.
+    pub fn protein(read: &'read [u8], matrix: &[i8; 441]) -> Profile<'read> {
+        Self::with_matrix(read, matrix, PROTEIN_RESIDUES.len() as i32, protein_to_numeric)
+    }
+
+    /// Create a query profile for an `n` by `n` scoring matrix, indexed by `to_numeric` of each
+    /// residue.
+    fn with_matrix(read: &'read [u8],
+                   matrix: &[i8],
+                   n: i32,
+                   to_numeric: fn(u8) -> i8)
+                   -> Profile<'read> {
         assert!(read.len() > 0);
 
-        let read_num = Self::sequence_to_numeric(read);
+        let read_num = read.iter().map(|&b| to_numeric(b)).collect::<Vec<i8>>();
         let raw = unsafe {
             ssw_init(read_num.as_ptr(),
                      read_num.len() as i32,
                      matrix.as_ptr(),
-                     5,
+                     n,
                      2)
         };
 
@@ -51,6 +143,7 @@ impl<'read> Profile<'read> {
         Profile {
             sequence: read,
             _sequence_numeric: read_num,
+            to_numeric,
             raw_profile: raw,
         }
     }
@@ -62,7 +155,8 @@ impl<'read> Profile<'read> {
 
         assert!(reference.len() > 0);
 
-        let reference_numeric = Self::sequence_to_numeric(reference);
+        let reference_numeric =
+            reference.iter().map(|&b| (self.to_numeric)(b)).collect::<Vec<i8>>();
 
         let alignment = unsafe {
             ssw_align(self.raw_profile,
@@ -84,25 +178,29 @@ impl<'read> Profile<'read> {
             score
         }
     }
+}
 
-    /// Convert a DNA5 read sequence to 0-based indices in the matrix.
-    fn sequence_to_numeric(seq: &[u8]) -> Vec<i8> {
-        let mut converted = Vec::with_capacity(seq.len());
-
-        for &b in seq {
-            let num = match b {
-                b'A' => 0,
-                b'C' => 1,
-                b'G' => 2,
-                b'T' => 3,
-                _ => 4,
-            };
+/// Convert a DNA5 base to its 0-based index in the matrix.
+fn dna_to_numeric(b: u8) -> i8 {
+    match b {
+        b'A' => 0,
+        b'C' => 1,
+        b'G' => 2,
+        b'T' => 3,
+        _ => 4,
+    }
+}
 
-            converted.push(num);
-        }
+/// Convert an IUPAC nucleotide code to its 0-based index in the matrix, that of `N` for anything
+/// which isn't one of `IUPAC_BASES`.
+fn iupac_to_numeric(b: u8) -> i8 {
+    IUPAC_CODES[b as usize]
+}
 
-        converted
-    }
+/// Convert a protein residue to its 0-based index in the matrix, that of `X` for anything which
+/// isn't one of `PROTEIN_RESIDUES`.
+fn protein_to_numeric(b: u8) -> i8 {
+    PROTEIN_CODES[b as usize]
 }
 
 #[repr(C)]
@@ -196,5 +294,58 @@ mod test {
             // FIXME simd version is occasionally off by one
             diff <= 1
         }
+
+    }
+
+    #[test]
+    fn unknown_residues_score_as_x() {
+        assert_eq!(protein_to_numeric(b'A'), 0);
+        assert_eq!(protein_to_numeric(b'V'), 19);
+        assert_eq!(protein_to_numeric(b'X'), 20);
+        assert_eq!(protein_to_numeric(b'B'), 20);
+        assert_eq!(PROTEIN_IDENT_W_PENALTY.iter().filter(|&&s| s == 1).count(), 21);
+        assert_eq!(PROTEIN_IDENT_W_PENALTY[22], 1);
+    }
+
+    #[test]
+    fn iupac_codes_score_their_bases() {
+        let score = |a: u8, b: u8| {
+            let (a, b) = (iupac_to_numeric(a) as usize, iupac_to_numeric(b) as usize);
+            IUPAC_IDENT_W_PENALTY[a * IUPAC_BASES.len() + b]
+        };
+        assert_eq!(score(b'R', b'A'), 1);
+        assert_eq!(score(b'A', b'R'), 1);
+        assert_eq!(score(b'R', b'C'), -1);
+        assert_eq!(score(b'R', b'Y'), -1);
+        assert_eq!(score(b'B', b'S'), 1);
+        assert_eq!(score(b'N', b'A'), -1);
+        assert_eq!(score(b'N', b'N'), 1);
+        assert_eq!(iupac_to_numeric(b'.'), 4);
+
+        // DNA5 scores as it does with the DNA5 matrix
+        for &a in b"ACGTN" {
+            for &b in b"ACGTN" {
+                let dna5 = IDENT_W_PENALTY_NO_N_MATCH[dna_to_numeric(a) as usize * 5 +
+                                                      dna_to_numeric(b) as usize];
+                assert_eq!(score(a, b), dna5);
+            }
+        }
+
+        let read = b"ACGTTGCAAGCTAGCTAGGATCCGATCGATCGTAGCTAGCTAGCATCGAC";
+        let mut ambiguous = read.to_vec();
+        ambiguous[8] = b'R';
+        let profile = Profile::iupac(&ambiguous, &IUPAC_IDENT_W_PENALTY);
+        assert_eq!(profile.align_score(read, 1, 1), read.len() as u16);
+    }
+
+    #[test]
+    fn protein_identity_scores() {
+        let read = b"MKTAYIAKQRQISFVKSHFSRQLEERLGLIEVQAPILSRVGDGTQDNLSGAEKAVQVKVKALPDAQFEVVHSL";
+        let profile = Profile::protein(read, &PROTEIN_IDENT_W_PENALTY);
+        assert_eq!(profile.align_score(read, 1, 1), read.len() as u16);
+
+        let mut substituted = read.to_vec();
+        substituted[40] = b'W';
+        assert_eq!(profile.align_score(&substituted, 1, 1), read.len() as u16 - 2);
     }
 }
@@ -0,0 +1,41 @@
+//! Checks that mtsv-binner rejects bad arguments with a usage error before reading any input.
+
+extern crate mktemp;
+
+use mktemp::Temp;
+use std::process::Command;
+
+#[test]
+fn rejected_arguments() {
+    let dir = Temp::new_dir().unwrap();
+    let results = dir.to_path_buf().join("results.txt");
+
+    for &(args, message) in &[
+        (&["--edit-rate", "lots"][..], "Invalid edit proportion entered!"),
+        (&["--edit-rate", "1.5"][..], "must be between 0 and 1"),
+        (&["--max-hits-per-bin", "0"][..], "must be at least 1"),
+        (&["--seed-size", "0"][..], "seed size must be at least 1"),
+        (&["--seed-interval", "0"][..], "seed interval must be at least 1"),
+        (&["--max-hits", "100", "--tune-max-hits", "200"][..],
+         "tune max hits (200) must not be more than max hits (100)"),
+        (&["--findings-format", "binary", "--escape-ids"][..], "can't be escaped"),
+    ] {
+        // neither the reads nor the index exist, so only a rejected argument can end the run
+        // before the index is opened
+        let output = Command::new(env!("CARGO_BIN_EXE_mtsv-binner"))
+            .arg("--fasta")
+            .arg(dir.to_path_buf().join("reads.fasta"))
+            .arg("--index")
+            .arg(dir.to_path_buf().join("references.index"))
+            .arg("--results")
+            .arg(&results)
+            .args(args)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert_eq!(output.status.code(), Some(1), "{:?}: {}", args, stderr);
+        assert!(stderr.contains("error:") && stderr.contains(message), "{:?}: {}", args, stderr);
+        assert!(!stderr.contains("panicked"), "{:?}: {}", args, stderr);
+        assert!(!results.exists(), "{:?}", args);
+    }
+}
@@ -0,0 +1,1022 @@
+//! Runs the binaries end to end over the fixtures in `tests/fixtures/pipeline`, comparing their
+//! outputs against the golden files in `tests/fixtures/pipeline/golden`.
+//!
+//! After a deliberate change to an output, regenerate the golden files with
+//!
+//! ```text
+//! UPDATE_GOLDENS=1 cargo test --test integration
+//! ```
+//!
+//! and commit the differences along with the change.
+
+extern crate assert_cmd;
+extern crate serde_json;
+extern crate tempfile;
+
+use assert_cmd::Command;
+use serde_json::Value;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/pipeline").join(name)
+}
+
+/// Compare `actual` against the golden file `name`, or overwrite the golden file with it if
+/// `UPDATE_GOLDENS` is set.
+fn check_golden(name: &str, actual: &[u8]) {
+    let golden = fixture("golden").join(name);
+    if env::var_os("UPDATE_GOLDENS").is_some() {
+        fs::create_dir_all(golden.parent().unwrap()).unwrap();
+        fs::write(&golden, actual).unwrap();
+        return;
+    }
+
+    let expected = fs::read_to_string(&golden)
+        .unwrap_or_else(|e| panic!("can't read {}: {}", golden.display(), e));
+    // compared as text, so a failure shows a readable difference
+    assert_eq!(String::from_utf8_lossy(actual), expected, "{} differs from its golden file", name);
+}
+
+fn mtsv(bin: &str) -> Command {
+    Command::cargo_bin(bin).unwrap()
+}
+
+/// Build the fixture index in `dir`, returning its path.
+fn build_index(dir: &TempDir) -> PathBuf {
+    let index = dir.path().join("references.index");
+    mtsv("mtsv-build")
+        .arg("--fasta")
+        .arg(fixture("references.fasta"))
+        .arg("--index")
+        .arg(&index)
+        .assert()
+        .success();
+    index
+}
+
+fn read_json(path: &Path) -> Value {
+    serde_json::from_slice(&fs::read(path).unwrap()).unwrap()
+}
+
+/// Bin `reads` against `index`, with one thread unless `extra_args` say otherwise, so reads are
+/// written in input order.
+fn bin(index: &Path, reads: &[PathBuf], results: &Path, extra_args: &[&str]) -> Vec<u8> {
+    let threads: &[&str] = if extra_args.contains(&"--threads") {
+        &[]
+    } else {
+        &["--threads", "1"]
+    };
+    mtsv("mtsv-binner")
+        .arg("--fasta")
+        .args(reads)
+        .arg("--index")
+        .arg(index)
+        .arg("--results")
+        .arg(results)
+        .args(threads)
+        .args(extra_args)
+        .assert()
+        .success();
+    fs::read(results).unwrap()
+}
+
+#[test]
+fn binner_results() {
+    let dir = TempDir::new().unwrap();
+    let index = build_index(&dir);
+    let reads = [fixture("reads_1.fasta"), fixture("reads_2.fasta")];
+
+    let results = bin(&index, &reads, &dir.path().join("results.txt"), &[]);
+    check_golden("results.txt", &results);
+
+    let v2 = bin(&index, &reads, &dir.path().join("results.v2.txt"), &["--findings-format", "v2"]);
+    check_golden("results.v2.txt", &v2);
+
+    // with more threads reads are written as they finish, but their lines are the same
+    let threaded = bin(&index, &reads, &dir.path().join("threaded.txt"), &["--threads", "4"]);
+    let sorted_lines = |text: &[u8]| {
+        let mut lines = String::from_utf8_lossy(text)
+            .lines()
+            .map(String::from)
+            .collect::<Vec<_>>();
+        lines.sort();
+        lines
+    };
+    assert_eq!(sorted_lines(&threaded), sorted_lines(&results));
+}
+
+#[test]
+fn corrupt_index() {
+    let dir = TempDir::new().unwrap();
+    let index = build_index(&dir);
+    let reads = [fixture("reads_1.fasta"), fixture("reads_2.fasta")];
+
+    // flip a bit of the checksum at the end of the index
+    let mut bytes = fs::read(&index).unwrap();
+    *bytes.last_mut().unwrap() ^= 1;
+    fs::write(&index, &bytes).unwrap();
+    let output = mtsv("mtsv-binner")
+        .arg("--fasta")
+        .args(&reads)
+        .arg("--index")
+        .arg(&index)
+        .arg("--results")
+        .arg(dir.path().join("corrupt.txt"))
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("is corrupt"));
+
+    // which isn't noticed without checking it
+    let results = bin(&index, &reads, &dir.path().join("results.txt"), &["--no-verify"]);
+    check_golden("results.txt", &results);
+}
+
+#[test]
+fn zstd_index() {
+    let dir = TempDir::new().unwrap();
+    let plain = build_index(&dir);
+    let reads = [fixture("reads_1.fasta"), fixture("reads_2.fasta")];
+    let build = |index: &Path, extra: &[&str]| {
+        mtsv("mtsv-build")
+            .arg("--fasta")
+            .arg(fixture("references.fasta"))
+            .arg("--index")
+            .arg(index)
+            .args(extra)
+            .assert()
+            .success();
+    };
+
+    // compressed by the name alone, or by asking for it, the index bins the same
+    let named = dir.path().join("references.index.zst");
+    build(&named, &[]);
+    let flagged = dir.path().join("flagged.index");
+    build(&flagged, &["--compress-index", "19"]);
+    for index in &[&named, &flagged] {
+        assert!(fs::metadata(index).unwrap().len() < fs::metadata(&plain).unwrap().len());
+        let results = bin(index, &reads, &dir.path().join("results.txt"), &[]);
+        check_golden("results.txt", &results);
+    }
+
+    // zstd's levels stop at 19
+    mtsv("mtsv-build")
+        .arg("--fasta")
+        .arg(fixture("references.fasta"))
+        .arg("--index")
+        .arg(dir.path().join("bad.index"))
+        .args(["--compress-index", "20"])
+        .assert()
+        .failure();
+}
+
+#[test]
+fn chunked_runs_collapse() {
+    let dir = TempDir::new().unwrap();
+    let index = build_index(&dir);
+
+    // binning each half of the reads separately and collapsing gives the same as a single run
+    let first = dir.path().join("chunk_1.txt");
+    let second = dir.path().join("chunk_2.txt");
+    bin(&index, &[fixture("reads_1.fasta")], &first, &[]);
+    bin(&index, &[fixture("reads_2.fasta")], &second, &[]);
+
+    let collapsed = dir.path().join("collapsed.txt");
+    mtsv("mtsv-collapse")
+        .arg(&second)
+        .arg(&first)
+        .arg("--output")
+        .arg(&collapsed)
+        .assert()
+        .success();
+    check_golden("results.txt", &fs::read(collapsed).unwrap());
+}
+
+#[test]
+fn duplicate_read_ids() {
+    let dir = TempDir::new().unwrap();
+    let index = build_index(&dir);
+    // read_01 appears three times, and a read is already called read_01#2
+    let reads = [fixture("reads_duplicates.fasta")];
+
+    let suffixed = bin(&index, &reads, &dir.path().join("suffix.txt"), &[]);
+    check_golden("results_duplicates_suffix.txt", &suffixed);
+
+    let merged = bin(&index, &reads, &dir.path().join("merge.txt"), &["--duplicate-ids", "merge"]);
+    check_golden("results_duplicates_merge.txt", &merged);
+
+    let failed = mtsv("mtsv-binner")
+        .arg("--fasta")
+        .args(&reads)
+        .arg("--index")
+        .arg(&index)
+        .arg("--results")
+        .arg(dir.path().join("error.txt"))
+        .args(["--duplicate-ids", "error"])
+        .assert()
+        .failure();
+    let output = failed.get_output();
+    let log = String::from_utf8_lossy(&output.stdout) + String::from_utf8_lossy(&output.stderr);
+    assert!(log.contains("\"read_01\" appears more than once"), "unexpected output: {}", log);
+}
+
+#[test]
+fn reference_extraction() {
+    let dir = TempDir::new().unwrap();
+    let index = build_index(&dir);
+
+    let references = dir.path().join("references_562.fasta");
+    mtsv("mtsv-reference")
+        .arg("--index")
+        .arg(&index)
+        .arg("--results")
+        .arg(&references)
+        .arg("562")
+        .assert()
+        .success();
+    check_golden("references_562.fasta", &fs::read(references).unwrap());
+}
+
+#[test]
+fn reference_dump() {
+    let dir = TempDir::new().unwrap();
+    let index = build_index(&dir);
+
+    let dumped = dir.path().join("references.fasta");
+    mtsv("mtsv-reference")
+        .arg("--index")
+        .arg(&index)
+        .arg("--results")
+        .arg(&dumped)
+        .arg("--all")
+        .assert()
+        .success();
+    let original = fs::read_to_string(fixture("references.fasta")).unwrap();
+    let sequence = |fasta: &str| fasta.lines().filter(|l| !l.starts_with('>')).collect::<String>();
+    let headers = |fasta: &str| {
+        fasta.lines().filter(|l| l.starts_with('>')).map(String::from).collect::<Vec<_>>()
+    };
+    let dumped_fasta = fs::read_to_string(&dumped).unwrap();
+    assert_eq!(headers(&dumped_fasta), headers(&original));
+    assert_eq!(sequence(&dumped_fasta), sequence(&original).to_uppercase());
+
+    // which builds back into an index that bins the same
+    let rebuilt = dir.path().join("rebuilt.index");
+    mtsv("mtsv-build")
+        .arg("--fasta")
+        .arg(&dumped)
+        .arg("--index")
+        .arg(&rebuilt)
+        .assert()
+        .success();
+    let reads = [fixture("reads_1.fasta"), fixture("reads_2.fasta")];
+    let results = bin(&rebuilt, &reads, &dir.path().join("results.txt"), &[]);
+    check_golden("results.txt", &results);
+
+    // or only some taxa's
+    let taxids = dir.path().join("taxids.txt");
+    fs::write(&taxids, "9606\n1280\n").unwrap();
+    mtsv("mtsv-reference")
+        .arg("--index")
+        .arg(&index)
+        .arg("--results")
+        .arg(&dumped)
+        .arg("--all")
+        .arg("--taxid-file")
+        .arg(&taxids)
+        .assert()
+        .success();
+    assert_eq!(headers(&fs::read_to_string(&dumped).unwrap()), vec![">201-1280", ">301-9606"]);
+}
+
+#[test]
+fn one_shot_run() {
+    let dir = TempDir::new().unwrap();
+    let output = dir.path().join("run");
+    mtsv("mtsv-run")
+        .arg("--reference")
+        .arg(fixture("references.fasta"))
+        .arg("--fasta")
+        .arg(fixture("reads_1.fasta"))
+        .arg(fixture("reads_2.fasta"))
+        .arg("--output-dir")
+        .arg(&output)
+        .args(["--threads", "1", "--seed", "7"])
+        .assert()
+        .success();
+
+    let record = read_json(&output.join("run.json"));
+    assert_eq!(record["status"], "complete");
+    assert_eq!(record["seed"], 7);
+    assert_eq!(record["build"]["suffix_sample"], 32);
+    assert!(output.join("reference.index").exists());
+    assert!(output.join("results/manifest.json").exists());
+    check_golden("results.txt", &fs::read(output.join("collapsed.results")).unwrap());
+}
+
+#[test]
+fn one_shot_run_failure() {
+    let dir = TempDir::new().unwrap();
+    let output = dir.path().join("run");
+    mtsv("mtsv-run")
+        .arg("--reference")
+        .arg(fixture("references.fasta"))
+        .arg("--fasta")
+        .arg(dir.path().join("missing.fasta"))
+        .arg("--output-dir")
+        .arg(&output)
+        .args(["--threads", "1"])
+        .assert()
+        .code(2);
+
+    let record = read_json(&output.join("run.json"));
+    assert_eq!(record["status"], "failed");
+    assert_eq!(record["failed_stage"], "bin");
+    assert!(record["error"].is_string());
+    assert!(!output.join("collapsed.results").exists());
+}
+
+#[test]
+fn wide_taxids() {
+    let dir = TempDir::new().unwrap();
+    // the fixture references with accession headers, and 562 mapped to a taxid past 32 bits
+    let references = fs::read_to_string(fixture("references.fasta")).unwrap();
+    let mut map = String::new();
+    let mut acc2taxid =
+        String::from("accession\taccession.version\ttaxid\tgi\nOTHER1\tOTHER1.1\t9\t9\n");
+    let mut renamed = String::new();
+    for line in references.lines() {
+        match line.strip_prefix('>') {
+            Some(header) => {
+                let (gi, taxid) = header.split_once('-').unwrap();
+                let taxid = if taxid == "562" { "5000000000" } else { taxid };
+                map.push_str(&format!("REF{}.1\t{}\n", gi, taxid));
+                acc2taxid.push_str(&format!("REF{}\tREF{}.1\t{}\t{}\n", gi, gi, taxid, gi));
+                renamed.push_str(&format!(">REF{}.1\n", gi));
+            },
+            None => renamed.push_str(&format!("{}\n", line)),
+        }
+    }
+    fs::write(dir.path().join("references.fasta"), renamed).unwrap();
+    fs::write(dir.path().join("accessions.tsv"), map).unwrap();
+    fs::write(dir.path().join("nucl_gb.accession2taxid"), acc2taxid).unwrap();
+
+    let index = dir.path().join("wide.index");
+    mtsv("mtsv-build")
+        .arg("--fasta")
+        .arg(dir.path().join("references.fasta"))
+        .arg("--index")
+        .arg(&index)
+        .args(["--header-format", "accession", "--accession-map"])
+        .arg(dir.path().join("accessions.tsv"))
+        .assert()
+        .success();
+
+    // the same hits as the golden results, under the wide taxid
+    let reads = [fixture("reads_1.fasta"), fixture("reads_2.fasta")];
+    let results = bin(&index, &reads, &dir.path().join("results.txt"), &[]);
+    let hits = |text: &str, wide: bool| {
+        text.lines()
+            .map(|line| {
+                let (read, hits) = line.rsplit_once(':').unwrap();
+                let mut hits = hits.split(',')
+                    .map(|hit| match hit.strip_prefix("562=") {
+                        Some(edit) if wide => format!("5000000000={}", edit),
+                        _ => hit.to_string(),
+                    })
+                    .collect::<Vec<_>>();
+                hits.sort();
+                (read.to_string(), hits)
+            })
+            .collect::<Vec<_>>()
+    };
+    let golden = fs::read_to_string(fixture("golden/results.txt")).unwrap();
+    assert_eq!(hits(&String::from_utf8_lossy(&results), false), hits(&golden, true));
+
+    let extracted = dir.path().join("references_wide.fasta");
+    mtsv("mtsv-reference")
+        .arg("--index")
+        .arg(&index)
+        .arg("--results")
+        .arg(&extracted)
+        .arg("5000000000")
+        .assert()
+        .success();
+    let extracted = fs::read_to_string(extracted).unwrap();
+    // the GIs were assigned to the accessions, and are labelled as such
+    let first = ">synthetic_gi|1|taxid|5000000000 start=1800 len=900 accession=REF101.1\n";
+    assert!(extracted.starts_with(first), "{}", extracted);
+    let second = ">synthetic_gi|2|taxid|5000000000 start=2700 len=700 accession=REF102.1\n";
+    assert!(extracted.contains(second));
+
+    // an NCBI accession2taxid file maps them the same, but can't be used for standard input
+    let build = |fasta: &Path, index: &Path| {
+        mtsv("mtsv-build")
+            .arg("--fasta")
+            .arg(fasta)
+            .arg("--index")
+            .arg(index)
+            .args(["--header-format", "accession", "--acc2taxid"])
+            .arg(dir.path().join("nucl_gb.accession2taxid"))
+            .pipe_stdin(dir.path().join("references.fasta"))
+            .unwrap()
+            .assert()
+    };
+    let ncbi = dir.path().join("ncbi.index");
+    build(&dir.path().join("references.fasta"), &ncbi).success();
+    assert!(fs::read(&ncbi).unwrap() == fs::read(&index).unwrap());
+    build(Path::new("-"), &dir.path().join("stdin.index")).failure();
+}
+
+#[test]
+fn manifest_build() {
+    let dir = TempDir::new().unwrap();
+    let index = build_index(&dir);
+
+    // the fixture references split into a file per taxid, with headers which don't say which
+    let references = fs::read_to_string(fixture("references.fasta")).unwrap();
+    let mut files: Vec<(String, String, String)> = Vec::new();
+    for line in references.lines() {
+        match line.strip_prefix('>') {
+            Some(header) => {
+                let (gi, taxid) = header.split_once('-').unwrap();
+                if files.last().map_or(true, |file| file.1 != taxid) {
+                    files.push((gi.to_string(), taxid.to_string(), String::new()));
+                }
+                files.last_mut().unwrap().2.push_str(&format!(">contig_{}\n", gi));
+            },
+            None => files.last_mut().unwrap().2.push_str(&format!("{}\n", line)),
+        }
+    }
+    fs::create_dir(dir.path().join("assemblies")).unwrap();
+    let mut manifest = String::new();
+    for (gi, taxid, fasta) in &files {
+        fs::write(dir.path().join(format!("assemblies/{}.fasta", taxid)), fasta).unwrap();
+        manifest.push_str(&format!("assemblies/{}.fasta\t{}\t{}\n", taxid, taxid, gi));
+    }
+    fs::write(dir.path().join("manifest.tsv"), manifest).unwrap();
+
+    // with the GIs of the headers, the index is the same as one built from them
+    let listed = dir.path().join("listed.index");
+    mtsv("mtsv-build")
+        .arg("--manifest")
+        .arg(dir.path().join("manifest.tsv"))
+        .arg("--index")
+        .arg(&listed)
+        .assert()
+        .success();
+    assert!(fs::read(&listed).unwrap() == fs::read(&index).unwrap());
+
+    fs::write(dir.path().join("broken.tsv"), "assemblies/562.fasta\t562\nmissing.fasta\t9\n")
+        .unwrap();
+    let output = mtsv("mtsv-build")
+        .arg("--manifest")
+        .arg(dir.path().join("broken.tsv"))
+        .arg("--index")
+        .arg(dir.path().join("broken.index"))
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("line 2 of manifest") && stderr.contains("missing.fasta"),
+            "{}",
+            stderr);
+}
+
+#[test]
+fn references_from_stdin() {
+    let dir = TempDir::new().unwrap();
+    let index = build_index(&dir);
+
+    let piped = dir.path().join("piped.index");
+    mtsv("mtsv-build")
+        .args(["--fasta", "-", "--index"])
+        .arg(&piped)
+        .pipe_stdin(fixture("references.fasta"))
+        .unwrap()
+        .assert()
+        .success();
+    assert!(fs::read(&piped).unwrap() == fs::read(&index).unwrap());
+
+    // header formats apply as they do to files
+    let references = fs::read_to_string(fixture("references.fasta")).unwrap();
+    let taxid_headers = references.lines()
+        .map(|line| match line.strip_prefix('>') {
+            Some(header) => format!(">{}\n", header.split_once('-').unwrap().1),
+            None => format!("{}\n", line),
+        })
+        .collect::<String>();
+    fs::write(dir.path().join("taxids.fasta"), &taxid_headers).unwrap();
+    for (fasta, index) in [("taxids.fasta", "file.index"), ("-", "stdin.index")] {
+        mtsv("mtsv-build")
+            .current_dir(dir.path())
+            .args(["--fasta", fasta, "--index", index, "--header-format", "taxid"])
+            .write_stdin(taxid_headers.as_bytes())
+            .assert()
+            .success();
+    }
+    assert!(fs::read(dir.path().join("stdin.index")).unwrap() ==
+            fs::read(dir.path().join("file.index")).unwrap());
+    let output = mtsv("mtsv-inspect")
+        .arg("--index")
+        .arg(dir.path().join("file.index"))
+        .arg("--json")
+        .output()
+        .unwrap();
+    let summary: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(summary["synthetic_gis"], true);
+
+    let references = dir.path().join("references_562.fasta");
+    mtsv("mtsv-reference")
+        .arg("--index")
+        .arg(&piped)
+        .arg("--results")
+        .arg(&references)
+        .arg("562")
+        .assert()
+        .success();
+    check_golden("references_562.fasta", &fs::read(references).unwrap());
+}
+
+#[test]
+fn empty_references() {
+    let dir = TempDir::new().unwrap();
+    let index = build_index(&dir);
+
+    // references with no sequence are skipped, leaving the same index
+    let references = fs::read_to_string(fixture("references.fasta")).unwrap();
+    let with_empty = format!(">900-900\n{}>901-901\n   \n", references);
+    let fasta = dir.path().join("with_empty.fasta");
+    fs::write(&fasta, with_empty).unwrap();
+    let skipped = dir.path().join("skipped.index");
+    let output = mtsv("mtsv-build")
+        .arg("--fasta")
+        .arg(&fasta)
+        .arg("--index")
+        .arg(&skipped)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Skipping reference 900-900"));
+    assert!(fs::read(&skipped).unwrap() == fs::read(&index).unwrap());
+
+    let reads = [fixture("reads_1.fasta"), fixture("reads_2.fasta")];
+    let results = bin(&skipped, &reads, &dir.path().join("results.txt"), &[]);
+    check_golden("results.txt", &results);
+
+    mtsv("mtsv-build")
+        .arg("--fasta")
+        .arg(&fasta)
+        .arg("--index")
+        .arg(dir.path().join("strict.index"))
+        .arg("--strict-references")
+        .assert()
+        .failure();
+}
+
+#[test]
+fn dry_run() {
+    let dir = TempDir::new().unwrap();
+    let output = mtsv("mtsv-build")
+        .arg("--fasta")
+        .arg(fixture("references.fasta"))
+        .arg("--dry-run")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    check_golden("dry_run.txt", &output.stdout);
+
+    // every record which would fail the build is listed, not just the first
+    let references = fs::read_to_string(fixture("references.fasta")).unwrap();
+    let broken = format!(">bad\nACGT\n{}>900-900\nAC$GT\n>901-901\n\n", references);
+    let fasta = dir.path().join("broken.fasta");
+    fs::write(&fasta, broken).unwrap();
+    let output = mtsv("mtsv-build")
+        .arg("--fasta")
+        .arg(&fasta)
+        .arg("--dry-run")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("skipped\t1\nproblems\t2\n"));
+    assert!(stdout.contains("\tbad\tUnable to parse \"bad\" as integer\n"));
+    assert!(stdout.contains("\t900-900\tReference 900-900 has '$' at base 3"));
+}
+
+#[test]
+fn malformed_references() {
+    let dir = TempDir::new().unwrap();
+
+    // a stray '$' would be taken for the suffix array's sentinel
+    let references = fs::read_to_string(fixture("references.fasta")).unwrap();
+    let second = references.match_indices('>').nth(1).unwrap().0;
+    let malformed = format!("{}>900-900\nACGT$ACGT\n{}",
+                            &references[..second],
+                            &references[second..]);
+    let fasta = dir.path().join("malformed.fasta");
+    fs::write(&fasta, malformed).unwrap();
+    let index = dir.path().join("malformed.index");
+    let output = mtsv("mtsv-build")
+        .arg("--fasta")
+        .arg(&fasta)
+        .arg("--index")
+        .arg(&index)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Problem with record 2 of") &&
+            stderr.contains("Reference 900-900 has '$' at base 5"),
+            "{}",
+            stderr);
+    assert!(!index.exists());
+}
+
+#[test]
+fn resumed_build() {
+    let dir = TempDir::new().unwrap();
+    let index = build_index(&dir);
+
+    // a resumable build checkpoints as it goes, leaving the same index and no checkpoint
+    let resumed = dir.path().join("resumed.index");
+    let checkpoint = dir.path().join("resumed.index.ckpt");
+    let build = || {
+        mtsv("mtsv-build")
+            .arg("--fasta")
+            .arg(fixture("references.fasta"))
+            .arg("--index")
+            .arg(&resumed)
+            .arg("--resume")
+            .output()
+            .unwrap()
+    };
+    let output = build();
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("No checkpoint at"));
+    assert!(fs::read(&resumed).unwrap() == fs::read(&index).unwrap());
+    assert!(!checkpoint.exists());
+
+    // one which isn't a checkpoint is ignored, and the build starts over
+    fs::write(&checkpoint, b"not a checkpoint").unwrap();
+    let output = build();
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("it isn't a checkpoint"));
+    assert!(fs::read(&resumed).unwrap() == fs::read(&index).unwrap());
+    assert!(!checkpoint.exists());
+}
+
+#[test]
+fn kept_descriptions() {
+    let dir = TempDir::new().unwrap();
+    let index = build_index(&dir);
+
+    let references = fs::read_to_string(fixture("references.fasta")).unwrap();
+    let described = references.lines()
+        .map(|line| match line.strip_prefix('>') {
+            Some(header) => format!(">{} Reference {}, complete genome\n", header, header),
+            None => format!("{}\n", line),
+        })
+        .collect::<String>();
+    let fasta = dir.path().join("described.fasta");
+    fs::write(&fasta, described).unwrap();
+
+    // descriptions are dropped unless asked for, leaving the same index
+    let dropped = dir.path().join("dropped.index");
+    mtsv("mtsv-build").arg("--fasta").arg(&fasta).arg("--index").arg(&dropped).assert().success();
+    assert!(fs::read(&dropped).unwrap() == fs::read(&index).unwrap());
+
+    let kept = dir.path().join("kept.index");
+    mtsv("mtsv-build")
+        .arg("--fasta")
+        .arg(&fasta)
+        .arg("--index")
+        .arg(&kept)
+        .arg("--keep-descriptions")
+        .assert()
+        .success();
+    let reads = [fixture("reads_1.fasta"), fixture("reads_2.fasta")];
+    let results = bin(&kept, &reads, &dir.path().join("results.txt"), &[]);
+    check_golden("results.txt", &results);
+
+    // and written back out after the rest of the header
+    let extracted = dir.path().join("references_562.fasta");
+    mtsv("mtsv-reference")
+        .arg("--index")
+        .arg(&kept)
+        .arg("--results")
+        .arg(&extracted)
+        .arg("562")
+        .assert()
+        .success();
+    let extracted = fs::read_to_string(extracted).unwrap();
+    let headers = extracted.lines().filter(|line| line.starts_with('>')).collect::<Vec<_>>();
+    assert_eq!(headers,
+               vec![">gi|101|taxid|562 start=0 len=900 \
+                     description=Reference 101-562, complete genome",
+                    ">gi|102|taxid|562 start=900 len=700 \
+                     description=Reference 102-562, complete genome"]);
+
+    mtsv("mtsv-build")
+        .arg("--fasta")
+        .arg(&fasta)
+        .arg("--index")
+        .arg(dir.path().join("mapped.index"))
+        .args(["--keep-descriptions", "--mmap-format"])
+        .assert()
+        .failure();
+}
+
+#[test]
+fn taxon_bins() {
+    let dir = TempDir::new().unwrap();
+    let index = dir.path().join("taxon.index");
+    let build = |extra: &[&str]| {
+        mtsv("mtsv-build")
+            .arg("--fasta")
+            .arg(fixture("references.fasta"))
+            .arg("--index")
+            .arg(&index)
+            .args(["--bin-mode", "taxon", "--spacer-length", "200"])
+            .args(extra)
+            .assert()
+    };
+    build(&[]).success();
+
+    // 562's two references share a bin, and reads bin to the same taxa as with a bin per GI
+    let output = mtsv("mtsv-inspect").arg("--index").arg(&index).arg("--json").output().unwrap();
+    let summary: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(summary["references"], 3);
+    assert_eq!(summary["bases"], 900 + 200 + 700 + 900 + 900);
+    let reads = [fixture("reads_1.fasta"), fixture("reads_2.fasta")];
+    let results = bin(&index, &reads, &dir.path().join("results.txt"), &[]);
+    check_golden("results.txt", &results);
+
+    // the bins have no GIs to keep descriptions by
+    build(&["--keep-descriptions"]).failure();
+}
+
+#[test]
+fn index_summary() {
+    let dir = TempDir::new().unwrap();
+    let index = build_index(&dir);
+
+    let output = mtsv("mtsv-inspect").arg("--index").arg(&index).arg("--json").output().unwrap();
+    assert!(output.status.success());
+    let summary: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(summary["references"], 4);
+    assert_eq!(summary["taxa"], 3);
+    assert_eq!(summary["sample_interval"], 64);
+    assert_eq!(summary["suffix_sample"], 32);
+    assert_eq!(summary["file_bytes"], fs::metadata(&index).unwrap().len());
+    assert_eq!(summary["synthetic_gis"], false);
+
+    // the plain summary has the same values
+    let output = mtsv("mtsv-inspect").arg("--index").arg(&index).output().unwrap();
+    let plain = String::from_utf8(output.stdout).unwrap();
+    for name in &["references", "taxa", "bases", "max_reference_length", "synthetic_gis",
+                  "file_bytes"] {
+        assert!(plain.contains(&format!("{}\t{}\n", name, summary[name])), "{}", plain);
+    }
+}
+
+#[test]
+fn merged_indices() {
+    let dir = TempDir::new().unwrap();
+    let index = build_index(&dir);
+
+    // the first two references in one index, the rest in another
+    let references = fs::read_to_string(fixture("references.fasta")).unwrap();
+    let third = references.match_indices('>').nth(2).unwrap().0;
+    let parts = [&references[..third], &references[third..]];
+    for (i, part) in parts.iter().enumerate() {
+        let fasta = dir.path().join(format!("{}.fasta", i));
+        fs::write(&fasta, part).unwrap();
+        mtsv("mtsv-build")
+            .current_dir(dir.path())
+            .arg("--fasta")
+            .arg(&fasta)
+            .args(["--index", &format!("{}.index", i), "--sa-sample", "8"])
+            .assert()
+            .success();
+    }
+
+    mtsv("mtsv-index-merge")
+        .current_dir(dir.path())
+        .args(["--merge", "1.index", "0.index", "--index", "merged.index"])
+        .assert()
+        .success();
+    assert!(fs::read(dir.path().join("merged.index")).unwrap() == fs::read(&index).unwrap());
+
+    let output = mtsv("mtsv-index-merge")
+        .current_dir(dir.path())
+        .args(["--merge", "0.index", "merged.index", "--index", "bad.index"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("GI 101 is in both 0.index and merged.index"));
+    assert!(!dir.path().join("bad.index").exists());
+}
+
+#[test]
+fn index_subset() {
+    let dir = TempDir::new().unwrap();
+    let index = build_index(&dir);
+
+    // the same index as one built from just taxid 562's and 9606's references
+    let references = fs::read_to_string(fixture("references.fasta")).unwrap();
+    let kept = references.split_inclusive('\n')
+        .fold((String::new(), false), |(mut kept, keep), line| {
+            let keep = if line.starts_with('>') { !line.ends_with("-1280\n") } else { keep };
+            if keep {
+                kept.push_str(line);
+            }
+            (kept, keep)
+        })
+        .0;
+    fs::write(dir.path().join("kept.fasta"), kept).unwrap();
+    mtsv("mtsv-build")
+        .current_dir(dir.path())
+        .args(["--fasta", "kept.fasta", "--index", "kept.index"])
+        .assert()
+        .success();
+
+    let subset = |extra_args: &[&str]| {
+        mtsv("mtsv-index-subset")
+            .current_dir(dir.path())
+            .arg("--source")
+            .arg(&index)
+            .args(["--index", "subset.index", "--taxid", "562,9606"])
+            .args(extra_args)
+            .output()
+            .unwrap()
+    };
+    let output = subset(&[]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("Kept 2 of 2 taxids, with 3 reference sequences"));
+    assert!(fs::read(dir.path().join("subset.index")).unwrap() ==
+            fs::read(dir.path().join("kept.index")).unwrap());
+
+    let output = subset(&["--taxid", "4", "--fail-on-missing"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("1 taxids aren't in"));
+    assert!(fs::read(dir.path().join("subset.index")).unwrap() ==
+            fs::read(dir.path().join("kept.index")).unwrap());
+}
+
+#[test]
+fn removed_taxa() {
+    let dir = TempDir::new().unwrap();
+    let index = build_index(&dir);
+    let reads = [fixture("reads_1.fasta"), fixture("reads_2.fasta")];
+    let results = bin(&index, &reads, &dir.path().join("results.txt"), &[]);
+
+    mtsv("mtsv-build")
+        .arg("--index")
+        .arg(&index)
+        .args(["--remove-taxids", "1280"])
+        .assert()
+        .success();
+    let removed = bin(&index, &reads, &dir.path().join("removed.txt"), &[]);
+
+    // taxid 1280's hits are gone, and every other taxid's hits are the same
+    let expected = String::from_utf8_lossy(&results)
+        .lines()
+        .filter_map(|line| {
+            let (read, hits) = line.split_once(':').unwrap();
+            let hits = hits.split(',').filter(|hit| !hit.starts_with("1280=")).collect::<Vec<_>>();
+            if hits.is_empty() {
+                None
+            } else {
+                Some(format!("{}:{}\n", read, hits.join(",")))
+            }
+        })
+        .collect::<String>();
+    assert!(expected.len() < results.len());
+    assert_eq!(String::from_utf8_lossy(&removed), expected);
+}
+
+#[test]
+fn validated_index() {
+    let dir = TempDir::new().unwrap();
+    let index = dir.path().join("references.index");
+    mtsv("mtsv-build")
+        .arg("--fasta")
+        .arg(fixture("references.fasta"))
+        .arg("--index")
+        .arg(&index)
+        .args(["--validate", "--seed", "1"])
+        .assert()
+        .success();
+    let validate = || {
+        mtsv("mtsv-inspect")
+            .arg("--index")
+            .arg(&index)
+            .args(["--validate", "--seed", "1"])
+            .output()
+    };
+    assert!(validate().unwrap().status.success());
+
+    // garble some of the packed reference sequences, which no longer match the index's checksum,
+    // and without checking it still load, but no longer match the FM index
+    let mut bytes = fs::read(&index).unwrap();
+    for byte in &mut bytes[64..264] {
+        *byte ^= 0xff;
+    }
+    fs::write(&index, &bytes).unwrap();
+    let output = validate().unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("is corrupt"));
+    let output = mtsv("mtsv-inspect")
+        .arg("--index")
+        .arg(&index)
+        .args(["--validate", "--seed", "1", "--no-verify"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Validation failed"));
+    assert!(!index.exists());
+    assert!(fs::read(dir.path().join("references.index.invalid")).unwrap() == bytes);
+}
+
+#[test]
+fn protein_index() {
+    let dir = TempDir::new().unwrap();
+    let index = dir.path().join("proteins.index");
+    mtsv("mtsv-build")
+        .arg("--fasta")
+        .arg(fixture("protein_references.fasta"))
+        .arg("--index")
+        .arg(&index)
+        .args(["--alphabet", "protein", "--validate"])
+        .assert()
+        .success();
+
+    // protein reads are only queried on the forward strand, and the shared one hits both taxa
+    let results = bin(&index,
+                      &[fixture("protein_reads.fasta")],
+                      &dir.path().join("results.txt"),
+                      &["--seed-size", "12", "--edit-rate", "0.05"]);
+    check_golden("protein_results.txt", &results);
+
+    mtsv("mtsv-build")
+        .arg("--fasta")
+        .arg(fixture("protein_references.fasta"))
+        .arg("--index")
+        .arg(dir.path().join("proteins.mapped"))
+        .args(["--alphabet", "protein", "--mmap-format"])
+        .assert()
+        .failure();
+
+    // DUST only scores DNA
+    mtsv("mtsv-build")
+        .arg("--fasta")
+        .arg(fixture("protein_references.fasta"))
+        .arg("--index")
+        .arg(dir.path().join("proteins.dusted"))
+        .args(["--alphabet", "protein", "--mask-low-complexity"])
+        .assert()
+        .failure();
+}
+
+#[test]
+fn dna4_index() {
+    let dir = TempDir::new().unwrap();
+    let index = dir.path().join("references.dna4.index");
+    mtsv("mtsv-build")
+        .arg("--fasta")
+        .arg(fixture("references.fasta"))
+        .arg("--index")
+        .arg(&index)
+        .args(["--alphabet", "dna4"])
+        .assert()
+        .success();
+
+    // the references and reads are all ACGT, so the hits are those of a DNA index
+    let reads = [fixture("reads_1.fasta"), fixture("reads_2.fasta")];
+    let results = bin(&index, &reads, &dir.path().join("results.txt"), &[]);
+    check_golden("results.txt", &results);
+
+    let mut references = fs::read(fixture("references.fasta")).unwrap();
+    let at = references.iter().position(|&b| b == b'\n').unwrap() + 10;
+    references[at] = b'N';
+    let ambiguous = dir.path().join("ambiguous.fasta");
+    fs::write(&ambiguous, &references).unwrap();
+    let output = mtsv("mtsv-build")
+        .arg("--fasta")
+        .arg(&ambiguous)
+        .arg("--index")
+        .arg(dir.path().join("ambiguous.index"))
+        .args(["--alphabet", "dna4"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Reference 101-562 has an N at base 10"));
+    assert!(!dir.path().join("ambiguous.index").exists());
+}
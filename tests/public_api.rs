@@ -0,0 +1,53 @@
+//! Exercises everything re-exported at the crate root, so that removing or moving one of them
+//! fails the build rather than breaking code which embeds mtsv.
+
+extern crate mtsv;
+
+use mtsv::{BinMode, Database, Gi, HeaderMap, Hit, MGIndex, MtsvError, MtsvResult, QueryParams,
+           TaxId};
+
+fn reference() -> Vec<u8> {
+    // pseudo-random bases from a fixed linear congruential generator
+    let mut state = 12345u32;
+    (0..500)
+        .map(|_| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            b"ACGT"[(state >> 16) as usize % 4]
+        })
+        .collect()
+}
+
+fn query(index: &MGIndex, read: &[u8], params: &QueryParams) -> Vec<Hit> {
+    index.matching_tax_ids(&index.fm_index(), read, params, &mut Default::default())
+}
+
+fn check_params(params: &QueryParams) -> MtsvResult<usize> {
+    params.validate()?;
+    Ok(params.max_hits)
+}
+
+#[test]
+fn root_reexports() {
+    let reference = reference();
+    let mut db = Database::new();
+    db.insert(TaxId(562), vec![(Gi(7), reference.clone())]);
+    let index = MGIndex::new(db, 16, 32, BinMode::PerGi);
+    assert_eq!(index.bin_count(), 1);
+    assert_eq!(index.tax_ids(), vec![TaxId(562)]);
+    assert_eq!(index.bins_for(TaxId(562)), vec![(Gi(7), reference.len())]);
+    assert!(!index.contains_tax_id(TaxId(561)));
+    // built from gi-taxid headers, so there are no accessions
+    let accessions: &HeaderMap = index.accessions();
+    assert!(accessions.is_empty());
+
+    let params = QueryParams { seed_gap: 4, ..QueryParams::default() };
+    let hits = query(&index, &reference[100..200], &params);
+    assert!(hits.iter().any(|h: &Hit| h.tax_id == TaxId(562) && h.edit == 0));
+
+    assert_eq!(check_params(&params).unwrap(), params.max_hits);
+    match check_params(&QueryParams { tune_max_hits: params.max_hits + 1, ..params }) {
+        Err(MtsvError::InvalidParameter(_)) => (),
+        Err(other) => panic!("unexpected error: {}", other),
+        Ok(_) => panic!("inconsistent hit limits were accepted"),
+    }
+}